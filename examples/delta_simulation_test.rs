@@ -29,7 +29,7 @@ fn main() {
     
     // Initialize Alice
     let adapter_alice = Box::new(FilesystemAdapter::new("delta_sim_alice").unwrap());
-    let mut melda_alice = Melda::new(Arc::new(RwLock::new(adapter_alice))).unwrap();
+    let melda_alice = Melda::new(Arc::new(RwLock::new(adapter_alice))).unwrap();
     melda_alice.update(initial_json.as_object().unwrap().clone()).unwrap();
     melda_alice.commit(Some(json!({"op": "initial_state"}).as_object().unwrap().clone())).unwrap();
     
@@ -17,7 +17,7 @@ fn main() {
     
     // Initialize Alice with some data
     let adapter_alice = Box::new(FilesystemAdapter::new("transfer_alice").unwrap());
-    let mut melda_alice = Melda::new(Arc::new(RwLock::new(adapter_alice))).unwrap();
+    let melda_alice = Melda::new(Arc::new(RwLock::new(adapter_alice))).unwrap();
     
     // Initial state
     let initial_json = json!({
@@ -43,7 +43,7 @@ fn main() {
     copy_dir_recursively("transfer_alice", "transfer_bob").unwrap();
     
     let adapter_bob = Box::new(FilesystemAdapter::new("transfer_bob").unwrap());
-    let mut melda_bob = Melda::new(Arc::new(RwLock::new(adapter_bob))).unwrap();
+    let melda_bob = Melda::new(Arc::new(RwLock::new(adapter_bob))).unwrap();
     
     // Bob makes a change
     println!("\nStep 3: Bob adds item_4");
@@ -85,7 +85,7 @@ fn main() {
     println!("🔥 CRITICAL: What gets transferred when Alice melds with Bob?");
     
     // Count files before meld
-    let alice_files_before: Vec<_> = fs::read_dir("transfer_alice").unwrap()
+    let _alice_files_before: Vec<_> = fs::read_dir("transfer_alice").unwrap()
         .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
         .collect();
     
@@ -182,24 +182,22 @@ fn examine_storage(dir: &str) {
 
 fn examine_dir_recursive(dir: &str, delta_files: &mut Vec<(String, u64)>, pack_files: &mut Vec<(String, u64)>, total_size: &mut u64) {
     if let Ok(entries) = fs::read_dir(dir) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                let metadata = entry.metadata().unwrap();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let metadata = entry.metadata().unwrap();
+            
+            if metadata.is_file() {
+                let size = metadata.len();
+                *total_size += size;
+                let name = path.to_string_lossy().to_string();
                 
-                if metadata.is_file() {
-                    let size = metadata.len();
-                    *total_size += size;
-                    let name = path.to_string_lossy().to_string();
-                    
-                    if name.ends_with(".delta") {
-                        delta_files.push((name, size));
-                    } else if name.ends_with(".pack") {
-                        pack_files.push((name, size));
-                    }
-                } else if metadata.is_dir() {
-                    examine_dir_recursive(&path.to_string_lossy(), delta_files, pack_files, total_size);
+                if name.ends_with(".delta") {
+                    delta_files.push((name, size));
+                } else if name.ends_with(".pack") {
+                    pack_files.push((name, size));
                 }
+            } else if metadata.is_dir() {
+                examine_dir_recursive(&path.to_string_lossy(), delta_files, pack_files, total_size);
             }
         }
     }
@@ -212,7 +210,7 @@ fn calculate_storage_size(dir: &str) -> u64 {
 }
 
 fn copy_dir_recursively(source: &str, destination: &str) -> std::io::Result<()> {
-    fs::create_dir_all(&destination)?;
+    fs::create_dir_all(destination)?;
     for entry in fs::read_dir(source)? {
         let entry = entry?;
         let filetype = entry.file_type()?;
@@ -13,7 +13,8 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
-use anyhow::Result;
+use anyhow::{bail, Result};
+use std::ops::Deref;
 
 /// Initializes an adapter using the provided Url
 ///
@@ -21,30 +22,70 @@ use anyhow::Result;
 ///
 /// * `url` - An Url for the adapter (username and passwords can be encoded)
 ///
+/// The `+private` scheme modifier (for example `file+private+flate://`) hides the names of the
+/// stored objects using the secret found in the `MELDA_PRIVATE_KEY` environment variable.
+/// With the `mmap` feature, the `file+mmap://` scheme memory-maps the data packs.
+/// With the `remote` feature, the `melda://host:port/repository` scheme (`meldas://` for HTTPS)
+/// stores the objects in a repository hosted by a melda-server, using the token given as the
+/// password of the URL or found in the `MELDA_TOKEN` environment variable.
+/// With the `instrument` feature, the adapter is wrapped in an
+/// [crate::instrumentedadapter::InstrumentedAdapter].
+///
 /// # Example
 /// ```
 /// use melda::{melda::Melda, adapter::get_adapter};
 /// use std::sync::{Arc, Mutex, RwLock};
 /// use serde_json::{Map, Value,json};
 /// let adapter = get_adapter("memory+flate://").unwrap();
-/// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+/// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
 /// ```
 pub fn get_adapter(url: &str) -> Result<Box<dyn Adapter>> {
     let url = url::Url::parse(url).expect("invalid_url");
+    #[cfg(feature = "solid")]
     let username = if url.username().is_empty() {
         None
     } else {
         Some(url.username().to_string())
     };
+    #[cfg(any(feature = "solid", feature = "remote"))]
     let password = url.password().map(|s| s.to_string());
     let mut adapter: Option<Box<dyn Adapter>> = None;
     if url.scheme().starts_with("memory") {
         adapter = Some(Box::new(crate::memoryadapter::MemoryAdapter::new()));
     } else if url.scheme().starts_with("file") {
-        adapter = Some(Box::new(
-            crate::filesystemadapter::FilesystemAdapter::new(url.path())
-                .expect("cannot_initialize_adapter"),
-        ));
+        #[cfg(feature = "mmap")]
+        if url.scheme().contains("+mmap") {
+            adapter = Some(Box::new(
+                crate::filesystemadapter::FilesystemAdapter::new_mmap(url.path())
+                    .expect("cannot_initialize_adapter"),
+            ));
+        }
+        if adapter.is_none() {
+            adapter = Some(Box::new(
+                crate::filesystemadapter::FilesystemAdapter::new(url.path())
+                    .expect("cannot_initialize_adapter"),
+            ));
+        }
+    }
+    #[cfg(feature = "remote")]
+    if url.scheme().starts_with("melda") {
+        let scheme = match url.scheme().starts_with("meldas") {
+            true => "https",
+            false => "http",
+        };
+        let token = match password.clone() {
+            Some(token) => token,
+            None => std::env::var("MELDA_TOKEN").map_err(|_| anyhow::anyhow!("missing_token"))?,
+        };
+        let mut server = format!("{}://{}", scheme, url.host_str().unwrap_or("localhost"));
+        if let Some(port) = url.port() {
+            server += &format!(":{}", port);
+        }
+        adapter = Some(Box::new(crate::remoteadapter::RemoteAdapter::new(
+            &server,
+            url.path(),
+            &token,
+        )?));
     }
     #[cfg(feature = "solid")]
     if url.scheme().starts_with("solid") {
@@ -68,23 +109,38 @@ pub fn get_adapter(url: &str) -> Result<Box<dyn Adapter>> {
             crate::sqliteadapter::SqliteAdapter::new_in_memory(),
         ));
     }
-    match adapter {
-        Some(adapter) => {
-            if url.scheme().ends_with("+flate") {
-                return Ok(Box::new(crate::flate2adapter::Flate2Adapter::new(
-                    std::sync::Arc::new(std::sync::RwLock::new(adapter)),
-                )));
-            }
-            #[cfg(feature = "brotli")]
-            if url.scheme().ends_with("+brotli") {
-                return Ok(Box::new(crate::brotliadapter::BrotliAdapter::new(
-                    std::sync::Arc::new(std::sync::RwLock::new(adapter)),
-                )));
-            }
-            Ok(adapter)
+    if url.scheme().contains("+private") {
+        if let Some(inner) = adapter {
+            let secret = std::env::var("MELDA_PRIVATE_KEY")
+                .map_err(|_| anyhow::anyhow!("missing_private_key"))?;
+            adapter = Some(Box::new(crate::privateadapter::PrivateAdapter::new(
+                std::sync::Arc::new(std::sync::RwLock::new(inner)),
+                secret.as_bytes(),
+            )?));
         }
+    }
+    let mut adapter = match adapter {
+        Some(adapter) => adapter,
         None => anyhow::bail!("invalid_adapter_url"),
+    };
+    if url.scheme().ends_with("+flate") {
+        adapter = Box::new(crate::flate2adapter::Flate2Adapter::new(
+            std::sync::Arc::new(std::sync::RwLock::new(adapter)),
+        ));
+    }
+    #[cfg(feature = "brotli")]
+    if url.scheme().ends_with("+brotli") {
+        adapter = Box::new(crate::brotliadapter::BrotliAdapter::new(
+            std::sync::Arc::new(std::sync::RwLock::new(adapter)),
+        ));
     }
+    #[cfg(feature = "instrument")]
+    {
+        adapter = Box::new(crate::instrumentedadapter::InstrumentedAdapter::new(
+            std::sync::Arc::new(std::sync::RwLock::new(adapter)),
+        ));
+    }
+    Ok(adapter)
 }
 
 /// An adapter implements a storage backend for delta states
@@ -100,6 +156,29 @@ pub trait Adapter: Send + Sync {
     ///
     fn read_object(&self, key: &str, offset: usize, length: usize) -> Result<Vec<u8>>;
 
+    /// Reads an object or a sub-object like [Adapter::read_object], avoiding copies when the
+    /// backend supports it (for example by slicing a memory-mapped file)
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `offset` - The starting position of the sub-object in the associated data pack
+    /// * `length` - The length of the sub-object (in bytes) in the associated data pack
+    ///
+    fn read_object_bytes(&self, key: &str, offset: usize, length: usize) -> Result<ObjectBytes> {
+        Ok(ObjectBytes::Owned(self.read_object(key, offset, length)?))
+    }
+
+    /// Returns the size (in bytes) of an object. By default the object is read: backends
+    /// which can determine the size without reading the content should override this method
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn object_size(&self, key: &str) -> Result<usize> {
+        Ok(self.read_object(key, 0, 0)?.len())
+    }
+
     /// Writes an object to the storage
     ///
     /// # Arguments
@@ -108,10 +187,88 @@ pub trait Adapter: Send + Sync {
     /// * `data` - The content of the object
     fn write_object(&self, key: &str, data: &[u8]) -> Result<()>;
 
+    /// Writes an object to the storage, replacing the existing object (if any). By default the
+    /// existing object is deleted before the new content is written: backends which can
+    /// replace an object in place should override this method
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `data` - The content of the object
+    fn replace_object(&self, key: &str, data: &[u8]) -> Result<()> {
+        self.delete_object(key)?;
+        self.write_object(key, data)
+    }
+
+    /// Deletes an object from the storage (deleting a missing object is not an error). By
+    /// default objects cannot be deleted and an `unsupported_operation` error is returned:
+    /// maintenance operations such as [crate::melda::Melda::gc] require backends which
+    /// override this method
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn delete_object(&self, _key: &str) -> Result<()> {
+        bail!("unsupported_operation")
+    }
+
     /// Lists the keys of all objects whose key ends with ext. If ext is an empty string, all objects are returned.
     ///
     /// # Arguments
     ///
     /// * `ext` - The extension (last part of the string) of the requested objects    
     fn list_objects(&self, ext: &str) -> Result<Vec<String>>;
+
+    /// Lists, in lexicographic order, at most limit keys of the objects whose key ends with ext
+    /// and follows the given key (keys are compared without the extension). By default all
+    /// keys are listed and then filtered: backends which can enumerate keys in order should
+    /// override this method
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    /// * `after` - The key (without the extension) after which the listing starts
+    /// * `limit` - The maximum number of keys returned
+    fn list_objects_page(
+        &self,
+        ext: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        let mut keys: Vec<String> = self
+            .list_objects(ext)?
+            .into_iter()
+            .filter(|k| after.is_none_or(|after| k.as_str() > after))
+            .collect();
+        keys.sort();
+        keys.truncate(limit);
+        Ok(keys)
+    }
+}
+
+/// Content of an object read from the storage, possibly borrowed from a memory-mapped file
+pub enum ObjectBytes {
+    Owned(Vec<u8>),
+    #[cfg(feature = "mmap")]
+    Mapped {
+        map: std::sync::Arc<memmap2::Mmap>,
+        offset: usize,
+        length: usize,
+    },
+}
+
+impl Deref for ObjectBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            ObjectBytes::Owned(data) => data,
+            #[cfg(feature = "mmap")]
+            ObjectBytes::Mapped {
+                map,
+                offset,
+                length,
+            } => &map[*offset..*offset + *length],
+        }
+    }
 }
@@ -0,0 +1,176 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::adapter::Adapter;
+use crate::memoryadapter::MemoryAdapter;
+use anyhow::{anyhow, Result};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use tokio::runtime::Handle;
+
+/// Future returned by the methods of an [AsyncAdapter]
+pub type AdapterFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// An asynchronous adapter implements a storage backend for delta states whose operations do
+/// not block the caller (for example a network service accessed through an asynchronous
+/// client), see [crate::asyncmelda::AsyncMelda::open_with]. Blocking adapters can be used
+/// through a [BlockingAdapter]
+pub trait AsyncAdapter: Send + Sync {
+    /// Reads an object or a sub-object, see [Adapter::read_object]
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `offset` - The starting position of the sub-object in the associated data pack
+    /// * `length` - The length of the sub-object (in bytes) in the associated data pack
+    fn read_object<'a>(
+        &'a self,
+        key: &'a str,
+        offset: usize,
+        length: usize,
+    ) -> AdapterFuture<'a, Vec<u8>>;
+
+    /// Writes an object, see [Adapter::write_object]
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `data` - The content of the object
+    fn write_object<'a>(&'a self, key: &'a str, data: &'a [u8]) -> AdapterFuture<'a, ()>;
+
+    /// Deletes an object, see [Adapter::delete_object]
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn delete_object<'a>(&'a self, key: &'a str) -> AdapterFuture<'a, ()>;
+
+    /// Lists the keys of the objects whose key ends with ext, see [Adapter::list_objects]
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    fn list_objects<'a>(&'a self, ext: &'a str) -> AdapterFuture<'a, Vec<String>>;
+}
+
+/// Runs the operations of a blocking adapter on the blocking thread pool of the tokio runtime
+pub struct BlockingAdapter {
+    adapter: Arc<RwLock<Box<dyn Adapter>>>,
+}
+
+impl BlockingAdapter {
+    /// Wraps a blocking adapter
+    pub fn new(adapter: Arc<RwLock<Box<dyn Adapter>>>) -> Self {
+        BlockingAdapter { adapter }
+    }
+
+    // Runs an operation of the adapter on the blocking thread pool
+    fn run<'a, T, F>(&self, f: F) -> AdapterFuture<'a, T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn Adapter) -> Result<T> + Send + 'static,
+    {
+        let adapter = self.adapter.clone();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || f(adapter.read().unwrap().as_ref()))
+                .await
+                .map_err(|e| anyhow!("blocking_task_failed: {}", e))?
+        })
+    }
+}
+
+impl AsyncAdapter for BlockingAdapter {
+    fn read_object<'a>(
+        &'a self,
+        key: &'a str,
+        offset: usize,
+        length: usize,
+    ) -> AdapterFuture<'a, Vec<u8>> {
+        let key = key.to_string();
+        self.run(move |a| a.read_object(&key, offset, length))
+    }
+
+    fn write_object<'a>(&'a self, key: &'a str, data: &'a [u8]) -> AdapterFuture<'a, ()> {
+        let (key, data) = (key.to_string(), data.to_vec());
+        self.run(move |a| a.write_object(&key, &data))
+    }
+
+    fn delete_object<'a>(&'a self, key: &'a str) -> AdapterFuture<'a, ()> {
+        let key = key.to_string();
+        self.run(move |a| a.delete_object(&key))
+    }
+
+    fn list_objects<'a>(&'a self, ext: &'a str) -> AdapterFuture<'a, Vec<String>> {
+        let ext = ext.to_string();
+        self.run(move |a| a.list_objects(&ext))
+    }
+}
+
+// The memory adapter never blocks
+impl AsyncAdapter for MemoryAdapter {
+    fn read_object<'a>(
+        &'a self,
+        key: &'a str,
+        offset: usize,
+        length: usize,
+    ) -> AdapterFuture<'a, Vec<u8>> {
+        Box::pin(async move { Adapter::read_object(self, key, offset, length) })
+    }
+
+    fn write_object<'a>(&'a self, key: &'a str, data: &'a [u8]) -> AdapterFuture<'a, ()> {
+        Box::pin(async move { Adapter::write_object(self, key, data) })
+    }
+
+    fn delete_object<'a>(&'a self, key: &'a str) -> AdapterFuture<'a, ()> {
+        Box::pin(async move { Adapter::delete_object(self, key) })
+    }
+
+    fn list_objects<'a>(&'a self, ext: &'a str) -> AdapterFuture<'a, Vec<String>> {
+        Box::pin(async move { Adapter::list_objects(self, ext) })
+    }
+}
+
+// Exposes an asynchronous adapter as a blocking one, waiting for its operations on the given
+// runtime: it must only be used outside of the runtime (for example in blocking tasks)
+pub(crate) struct BridgedAdapter {
+    adapter: Arc<dyn AsyncAdapter>,
+    handle: Handle,
+}
+
+impl BridgedAdapter {
+    pub(crate) fn new(adapter: Arc<dyn AsyncAdapter>, handle: Handle) -> Self {
+        BridgedAdapter { adapter, handle }
+    }
+}
+
+impl Adapter for BridgedAdapter {
+    fn read_object(&self, key: &str, offset: usize, length: usize) -> Result<Vec<u8>> {
+        self.handle
+            .block_on(self.adapter.read_object(key, offset, length))
+    }
+
+    fn write_object(&self, key: &str, data: &[u8]) -> Result<()> {
+        self.handle.block_on(self.adapter.write_object(key, data))
+    }
+
+    fn delete_object(&self, key: &str) -> Result<()> {
+        self.handle.block_on(self.adapter.delete_object(key))
+    }
+
+    fn list_objects(&self, ext: &str) -> Result<Vec<String>> {
+        self.handle.block_on(self.adapter.list_objects(ext))
+    }
+}
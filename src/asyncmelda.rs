@@ -0,0 +1,160 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::adapter::Adapter;
+use crate::asyncadapter::{AsyncAdapter, BlockingAdapter, BridgedAdapter};
+use crate::builder::MeldaBuilder;
+use crate::melda::Melda;
+use anyhow::{anyhow, Result};
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+use std::sync::{Arc, RwLock};
+
+/// Asynchronous interface to a Melda data structure. Each operation is executed on the blocking
+/// thread pool of the tokio runtime, so that callers do not stall the asynchronous executor,
+/// and performs its I/O through an [AsyncAdapter]: either an asynchronous backend (see
+/// [AsyncMelda::open_with]) or a blocking adapter wrapped in a [BlockingAdapter]. Handles are
+/// cheap to clone and share the same state.
+///
+/// # Example
+/// ```
+/// use melda::{melda::Melda, asyncmelda::AsyncMelda};
+/// use serde_json::json;
+/// let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+/// runtime.block_on(async {
+///     let alice = AsyncMelda::open(Melda::builder().url("memory://")).await.unwrap();
+///     let bob = AsyncMelda::open(Melda::builder().url("memory://")).await.unwrap();
+///     alice.update(json!({ "key" : "value" }).as_object().unwrap().clone()).await.unwrap();
+///     alice.commit(None).await.unwrap();
+///     bob.meld(&alice).await.unwrap();
+///     bob.refresh().await.unwrap();
+///     let content = bob.read(None).await.unwrap();
+///     assert_eq!(content.get("key").unwrap(), "value");
+/// });
+/// ```
+#[derive(Clone)]
+pub struct AsyncMelda {
+    inner: Arc<Melda>,
+    adapter: Arc<dyn AsyncAdapter>,
+}
+
+impl AsyncMelda {
+    /// Wraps an existing Melda data structure
+    pub fn new(melda: Melda) -> Self {
+        AsyncMelda {
+            adapter: Arc::new(BlockingAdapter::new(melda.get_adapter())),
+            inner: Arc::new(melda),
+        }
+    }
+
+    /// Opens a Melda data structure configured by the given builder
+    pub async fn open(builder: MeldaBuilder) -> Result<Self> {
+        let melda = tokio::task::spawn_blocking(move || builder.open())
+            .await
+            .map_err(|e| anyhow!("blocking_task_failed: {}", e))??;
+        Ok(AsyncMelda::new(melda))
+    }
+
+    /// Opens a Melda data structure configured by the given builder, which persists the data
+    /// through the given asynchronous adapter (instead of the adapter of the builder). The
+    /// underlying data structure (see [AsyncMelda::inner]) waits for the operations of the
+    /// adapter on the current runtime, hence it must not be used from asynchronous tasks
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, asyncmelda::AsyncMelda, memoryadapter::MemoryAdapter};
+    /// use serde_json::json;
+    /// use std::sync::Arc;
+    /// let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    /// runtime.block_on(async {
+    ///     let adapter = Arc::new(MemoryAdapter::new());
+    ///     let alice = AsyncMelda::open_with(Melda::builder(), adapter.clone()).await.unwrap();
+    ///     alice.update(json!({ "key" : "value" }).as_object().unwrap().clone()).await.unwrap();
+    ///     alice.commit(None).await.unwrap();
+    ///     assert_eq!(alice.adapter().list_objects(".delta").await.unwrap().len(), 1);
+    ///     let bob = AsyncMelda::open_with(Melda::builder(), adapter).await.unwrap();
+    ///     assert_eq!(bob.read(None).await.unwrap().get("key").unwrap(), "value");
+    ///     // Blocking adapters are wrapped
+    ///     let carol = AsyncMelda::open(Melda::builder().url("memory://")).await.unwrap();
+    ///     carol.meld(&alice).await.unwrap();
+    ///     assert_eq!(carol.adapter().list_objects(".delta").await.unwrap().len(), 1);
+    /// });
+    /// ```
+    pub async fn open_with(builder: MeldaBuilder, adapter: Arc<dyn AsyncAdapter>) -> Result<Self> {
+        let bridged: Box<dyn Adapter> = Box::new(BridgedAdapter::new(
+            adapter.clone(),
+            tokio::runtime::Handle::current(),
+        ));
+        let builder = builder.adapter(Arc::new(RwLock::new(bridged)));
+        let melda = tokio::task::spawn_blocking(move || builder.open())
+            .await
+            .map_err(|e| anyhow!("blocking_task_failed: {}", e))??;
+        Ok(AsyncMelda {
+            inner: Arc::new(melda),
+            adapter,
+        })
+    }
+
+    /// Returns the adapter through which the data is persisted
+    pub fn adapter(&self) -> Arc<dyn AsyncAdapter> {
+        self.adapter.clone()
+    }
+
+    /// Returns the underlying (synchronous) data structure
+    pub fn inner(&self) -> Arc<Melda> {
+        self.inner.clone()
+    }
+
+    /// Updates the state, see [Melda::update]
+    pub async fn update(&self, obj: Map<String, Value>) -> Result<String> {
+        self.run(move |m| m.update(obj)).await
+    }
+
+    /// Commits the staged changes, see [Melda::commit]
+    pub async fn commit(
+        &self,
+        information: Option<Map<String, Value>>,
+    ) -> Result<Option<BTreeSet<String>>> {
+        self.run(move |m| m.commit(information)).await
+    }
+
+    /// Reads the state, see [Melda::read]
+    pub async fn read(&self, root: Option<String>) -> Result<Map<String, Value>> {
+        self.run(move |m| m.read(root.as_deref())).await
+    }
+
+    /// Melds the changes of another replica, see [Melda::meld]
+    pub async fn meld(&self, other: &AsyncMelda) -> Result<Vec<String>> {
+        let other = other.inner.clone();
+        self.run(move |m| m.meld(&other)).await
+    }
+
+    /// Reloads the blocks which have been added to the adapter, see [Melda::refresh]
+    pub async fn refresh(&self) -> Result<()> {
+        self.run(|m| m.refresh()).await
+    }
+
+    // Runs an operation on the blocking thread pool
+    async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Melda) -> Result<T> + Send + 'static,
+    {
+        let inner = self.inner.clone();
+        tokio::task::spawn_blocking(move || f(&inner))
+            .await
+            .map_err(|e| anyhow!("blocking_task_failed: {}", e))?
+    }
+}
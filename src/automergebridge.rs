@@ -0,0 +1,368 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//! Conversion between Melda repositories and [Automerge](https://automerge.org) documents, so
+//! that data can be migrated or bridged between the two CRDTs. Melda only tracks the state of
+//! JSON documents, hence Automerge text objects are imported as strings, counters and
+//! timestamps as numbers and byte arrays as hexadecimal strings.
+use crate::commitinfo::CommitInfo;
+use crate::constants::ID_FIELD;
+use crate::error::MeldaError;
+use crate::melda::{BlockFilter, BlockSummary, Causality, Melda};
+use anyhow::{anyhow, bail, Result};
+use automerge::hydrate;
+use automerge::transaction::{CommitOptions, Transactable};
+use automerge::{ActorId, AutoCommit, ChangeHash, ReadDoc, ScalarValue, ROOT};
+use serde_json::{Map, Number, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::convert::TryFrom;
+
+/// Field of the commit information holding the hash of the imported Automerge change
+const AUTOMERGE_CHANGE_FIELD: &str = "automerge_change";
+
+/// Imports the changes of an Automerge document (or of a concatenation of changes) into the
+/// replica, committing a block for each change in causal order. Each block records the state
+/// of the Automerge document once the change (and all the changes preceding it) is applied,
+/// which replaces the state of the replica. The commit information records the actor (as
+/// author), the message, the time and the hash of the change: changes imported earlier are
+/// skipped, so that the same document can be imported again after it evolved. The stage must
+/// be empty. Returns the committed blocks
+///
+/// # Arguments
+///
+/// * `melda` - The replica
+/// * `data` - The saved Automerge document
+///
+/// # Example
+/// ```
+/// use melda::{melda::Melda, automergebridge::import_changes};
+/// use automerge::{transaction::Transactable, AutoCommit, ObjType, ROOT};
+/// use serde_json::json;
+/// let mut doc = AutoCommit::new();
+/// doc.put(ROOT, "title", "Groceries").unwrap();
+/// let items = doc.put_object(ROOT, "items", ObjType::List).unwrap();
+/// doc.insert(&items, 0, "milk").unwrap();
+/// doc.commit();
+/// doc.insert(&items, 1, "bread").unwrap();
+/// doc.commit();
+/// let replica = Melda::new_from_url("memory://").unwrap();
+/// let blocks = import_changes(&replica, &doc.save()).unwrap();
+/// assert_eq!(blocks.len(), 2);
+/// assert_eq!(replica.read(None).unwrap(), *json!({ "_id" : "\u{221A}", "title" : "Groceries", "items" : [ "milk", "bread" ] }).as_object().unwrap());
+/// doc.put(ROOT, "title", "Shopping").unwrap();
+/// doc.commit();
+/// assert_eq!(import_changes(&replica, &doc.save()).unwrap().len(), 1);
+/// assert_eq!(replica.read(None).unwrap().get("title").unwrap(), "Shopping");
+/// ```
+pub fn import_changes(melda: &Melda, data: &[u8]) -> Result<Vec<String>> {
+    if melda.has_staging() {
+        bail!(MeldaError::StageNotEmpty);
+    }
+    let mut doc =
+        AutoCommit::load(data).map_err(|e| anyhow!("invalid_automerge_document: {}", e))?;
+    let imported = imported_changes(melda)?;
+    let changes: Vec<_> = doc
+        .get_changes(&[])
+        .into_iter()
+        .map(|c| {
+            (
+                c.hash(),
+                c.deps().to_vec(),
+                c.actor_id().to_hex_string(),
+                c.message().cloned(),
+                c.timestamp(),
+            )
+        })
+        .collect();
+    let mut heads = BTreeSet::new();
+    let mut blocks = vec![];
+    for (hash, deps, actor, message, time) in changes {
+        for dep in &deps {
+            heads.remove(dep);
+        }
+        heads.insert(hash);
+        if imported.contains(&hash.to_string()) {
+            continue;
+        }
+        let heads: Vec<ChangeHash> = heads.iter().copied().collect();
+        let state = doc.hydrate(ROOT, Some(&heads))?;
+        let object = match to_json(&state) {
+            Value::Object(object) => object,
+            _ => bail!("invalid_automerge_document: root_is_not_a_map"),
+        };
+        melda.update(object)?;
+        let mut info = CommitInfo::new()
+            .author(&actor)
+            .with(AUTOMERGE_CHANGE_FIELD, Value::from(hash.to_string()));
+        if let Some(message) = message {
+            info = info.message(&message);
+        }
+        info.timestamp = u64::try_from(time)
+            .ok()
+            .filter(|t| *t > 0)
+            .map(|t| t * 1000);
+        if let Some(anchors) = melda.commit_with(info)? {
+            blocks.extend(anchors);
+        }
+    }
+    Ok(blocks)
+}
+
+/// Exports the history of the replica as a saved Automerge document. Each applied block becomes
+/// a change depending on the changes of its parents (concurrent blocks become concurrent
+/// changes), which updates the document to the state at the block. Changes are made by the
+/// actor of the replica which committed the block (see [Melda::replica_id]) when possible, and
+/// carry the message and the time of the commit
+///
+/// # Arguments
+///
+/// * `melda` - The replica
+///
+/// # Example
+/// ```
+/// use melda::{melda::Melda, automergebridge::export_changes};
+/// use automerge::{AutoCommit, AutoSerde};
+/// use serde_json::json;
+/// let replica = Melda::new_from_url("memory://").unwrap();
+/// replica.update(json!({ "title" : "Groceries", "items" : [ "milk" ] }).as_object().unwrap().clone()).unwrap();
+/// replica.commit(Some(json!({ "message" : "First list" }).as_object().unwrap().clone())).unwrap();
+/// replica.update(json!({ "title" : "Groceries", "items" : [ "milk", "bread" ] }).as_object().unwrap().clone()).unwrap();
+/// replica.commit(None).unwrap();
+/// let mut doc = AutoCommit::load(&export_changes(&replica).unwrap()).unwrap();
+/// assert_eq!(serde_json::to_value(AutoSerde::from(&doc)).unwrap(), json!({ "title" : "Groceries", "items" : [ "milk", "bread" ] }));
+/// let changes = doc.get_changes(&[]);
+/// assert_eq!(changes.len(), 2);
+/// assert_eq!(changes[0].message().map(String::as_str), Some("First list"));
+/// assert_eq!(changes[1].deps(), &[changes[0].hash()]);
+/// ```
+pub fn export_changes(melda: &Melda) -> Result<Vec<u8>> {
+    let mut doc = AutoCommit::new();
+    let mut hashes = HashMap::<String, ChangeHash>::new();
+    let mut latest = HashMap::<String, String>::new();
+    for block in causal_order(melda)? {
+        let deps: Vec<ChangeHash> = block
+            .parents
+            .iter()
+            .filter_map(|p| hashes.get(p))
+            .copied()
+            .collect();
+        let mut fork = doc.fork_at(&deps)?;
+        fork.set_actor(actor_for(melda, &block, &mut latest)?);
+        // The identifier of the root object is not part of the exported state
+        let mut state = melda.state_until(&BTreeSet::from([block.id.clone()]))?;
+        if let Some(object) = state.as_object_mut() {
+            object.remove(ID_FIELD);
+        }
+        fork.update_object(ROOT, &to_hydrate(&state))
+            .map_err(|e| anyhow!("cannot_export_block: {}", e))?;
+        let options = || {
+            let mut options = CommitOptions::default();
+            if let Some(message) = &block.info.message {
+                options.set_message(message.clone());
+            }
+            if let Some(timestamp) = block.info.timestamp {
+                options.set_time((timestamp / 1000) as i64);
+            }
+            options
+        };
+        let hash = match fork.commit_with(options()) {
+            Some(hash) => hash,
+            None => fork.empty_change(options()),
+        };
+        doc.merge(&mut fork)?;
+        hashes.insert(block.id, hash);
+    }
+    Ok(doc.save())
+}
+
+// Returns the hashes of the Automerge changes imported into the replica
+fn imported_changes(melda: &Melda) -> Result<BTreeSet<String>> {
+    let blocks = melda.list_blocks(&BlockFilter::default(), None, usize::MAX)?;
+    Ok(blocks
+        .items
+        .into_iter()
+        .filter_map(|b| {
+            b.info
+                .extra
+                .get(AUTOMERGE_CHANGE_FIELD)
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        })
+        .collect())
+}
+
+// Returns the applied blocks ordered so that parents precede their children
+fn causal_order(melda: &Melda) -> Result<Vec<BlockSummary>> {
+    let blocks: HashMap<String, BlockSummary> = melda
+        .list_blocks(&BlockFilter::default(), None, usize::MAX)?
+        .items
+        .into_iter()
+        .filter(|b| b.applied)
+        .map(|b| (b.id.clone(), b))
+        .collect();
+    let mut generations = HashMap::<&str, usize>::new();
+    for id in blocks.keys() {
+        let mut stack = vec![id.as_str()];
+        while let Some(current) = stack.last().copied() {
+            let pending: Vec<&str> = blocks[current]
+                .parents
+                .iter()
+                .map(String::as_str)
+                .filter(|p| blocks.contains_key(*p) && !generations.contains_key(p))
+                .collect();
+            if pending.is_empty() {
+                let generation = blocks[current]
+                    .parents
+                    .iter()
+                    .filter_map(|p| generations.get(p.as_str()))
+                    .map(|g| g + 1)
+                    .max();
+                generations.insert(current, generation.unwrap_or(0));
+                stack.pop();
+            } else {
+                stack.extend(pending);
+            }
+        }
+    }
+    let mut order: Vec<(usize, &String)> = blocks
+        .keys()
+        .map(|id| (generations[id.as_str()], id))
+        .collect();
+    order.sort();
+    Ok(order
+        .into_iter()
+        .map(|(_, id)| blocks[id].clone())
+        .collect())
+}
+
+// Returns the actor making the change of a block: the replica which committed the block if its
+// previous change is known to the block (Automerge requires the changes of an actor to be
+// sequential), otherwise an actor derived from the block identifier
+fn actor_for(
+    melda: &Melda,
+    block: &BlockSummary,
+    latest: &mut HashMap<String, String>,
+) -> Result<ActorId> {
+    let replica = melda.get_block(&block.id)?.and_then(|b| b.replica);
+    if let Some(replica) = replica {
+        let sequential = match latest.get(&replica) {
+            Some(previous) => melda.compare_blocks(previous, &block.id)? == Causality::Before,
+            None => true,
+        };
+        if let (true, Ok(bytes)) = (sequential, hex::decode(&replica)) {
+            latest.insert(replica, block.id.clone());
+            return Ok(ActorId::from(bytes));
+        }
+    }
+    let mut bytes = hex::decode(&block.id).unwrap_or_else(|_| block.id.as_bytes().to_vec());
+    bytes.truncate(16);
+    Ok(ActorId::from(bytes))
+}
+
+// Converts an Automerge value to JSON
+fn to_json(value: &hydrate::Value) -> Value {
+    match value {
+        hydrate::Value::Map(map) => Value::from(
+            map.iter()
+                .map(|(k, v)| (k.clone(), to_json(&v.value)))
+                .collect::<Map<String, Value>>(),
+        ),
+        hydrate::Value::List(list) => {
+            Value::from(list.iter().map(|v| to_json(&v.value)).collect::<Vec<_>>())
+        }
+        hydrate::Value::Text(text) => Value::from(text.to_string()),
+        hydrate::Value::Scalar(scalar) => match scalar {
+            ScalarValue::Str(s) => Value::from(s.as_str()),
+            ScalarValue::Int(i) | ScalarValue::Timestamp(i) => Value::from(*i),
+            ScalarValue::Uint(u) => Value::from(*u),
+            ScalarValue::F64(f) => Number::from_f64(*f).map_or(Value::Null, Value::Number),
+            ScalarValue::Counter(c) => Value::from(i64::from(c)),
+            ScalarValue::Boolean(b) => Value::from(*b),
+            ScalarValue::Bytes(bytes) => Value::from(hex::encode(bytes)),
+            ScalarValue::Unknown { .. } | ScalarValue::Null => Value::Null,
+        },
+    }
+}
+
+// Converts JSON to an Automerge value (strings are converted to scalar values, since Melda
+// does not merge concurrent edits of strings)
+fn to_hydrate(value: &Value) -> hydrate::Value {
+    match value {
+        Value::Object(object) => hydrate::Value::Map(hydrate::Map::from(
+            object
+                .iter()
+                .map(|(k, v)| (k.clone(), to_hydrate(v)))
+                .collect::<HashMap<String, hydrate::Value>>(),
+        )),
+        Value::Array(array) => hydrate::Value::List(hydrate::List::from(
+            array.iter().map(to_hydrate).collect::<Vec<_>>(),
+        )),
+        Value::String(s) => hydrate::Value::Scalar(ScalarValue::Str(s.as_str().into())),
+        Value::Number(n) => hydrate::Value::Scalar(match (n.as_i64(), n.as_u64()) {
+            (Some(i), _) => ScalarValue::Int(i),
+            (None, Some(u)) => ScalarValue::Uint(u),
+            _ => ScalarValue::F64(n.as_f64().unwrap_or_default()),
+        }),
+        Value::Bool(b) => hydrate::Value::Scalar(ScalarValue::Boolean(*b)),
+        Value::Null => hydrate::Value::Scalar(ScalarValue::Null),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::ROOT_ID;
+    use serde_json::json;
+
+    #[test]
+    fn test_conversion() {
+        let value = json!({ "s" : "text", "i" : -3, "u" : u64::MAX, "f" : 1.5, "b" : true, "n" : null, "a" : [ 1, { "k" : [] } ] });
+        assert_eq!(to_json(&to_hydrate(&value)), value);
+    }
+
+    #[test]
+    fn test_round_trip_concurrent() {
+        let alice = Melda::new_from_url("memory://").unwrap();
+        alice
+            .update(json!({ "a" : 1 }).as_object().unwrap().clone())
+            .unwrap();
+        alice.commit(None).unwrap();
+        let bob = Melda::new_from_url("memory://").unwrap();
+        bob.meld(&alice).unwrap();
+        bob.refresh().unwrap();
+        alice
+            .update(json!({ "a" : 1, "b" : 2 }).as_object().unwrap().clone())
+            .unwrap();
+        alice.commit(None).unwrap();
+        bob.update(json!({ "a" : 1, "c" : 3 }).as_object().unwrap().clone())
+            .unwrap();
+        bob.commit(None).unwrap();
+        alice.meld(&bob).unwrap();
+        alice.refresh().unwrap();
+        let data = export_changes(&alice).unwrap();
+        let mut doc = AutoCommit::load(&data).unwrap();
+        assert_eq!(doc.get_changes(&[]).len(), 3);
+        assert_eq!(doc.get_heads().len(), 2);
+        let copy = Melda::new_from_url("memory://").unwrap();
+        assert_eq!(import_changes(&copy, &data).unwrap().len(), 3);
+        assert_eq!(
+            copy.read(None).unwrap(),
+            *json!({ "_id" : ROOT_ID, "a" : 1, "b" : 2, "c" : 3 })
+                .as_object()
+                .unwrap()
+        );
+        assert!(import_changes(&copy, &data).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,230 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//! Backup archives of whole repositories: a single tar archive compressed with zstd, holding
+//! every item of the repository (blocks, packs, indexes, attachments and metadata) along with a
+//! manifest recording the size and the digest of each item, which is verified on restore.
+//! Archives are a supported alternative to copying the directory of a repository.
+use crate::adapter::Adapter;
+use crate::constants::{DELTA_EXTENSION, PACK_EXTENSION};
+use crate::melda::Melda;
+use crate::utils::digest_bytes;
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+/// Version of the archive format
+const BACKUP_FORMAT_VERSION: u64 = 1;
+/// Name of the manifest entry (the last entry of the archive)
+const MANIFEST_ENTRY: &str = "manifest.json";
+/// Prefix of the entries holding the items
+const ITEM_PREFIX: &str = "items/";
+
+/// Writes a backup archive of the repository of the replica: all items written to the adapter
+/// are archived (staged changes are not). Returns the number of archived items
+///
+/// # Arguments
+///
+/// * `melda` - The replica
+/// * `path` - The path of the archive (replaced if it exists)
+///
+/// # Example
+/// ```
+/// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter, backup};
+/// use std::sync::{Arc, RwLock};
+/// use serde_json::json;
+/// let replica = Melda::new_from_url("memory://").unwrap();
+/// replica.update(json!({ "title" : "Backed up" }).as_object().unwrap().clone()).unwrap();
+/// replica.commit(None).unwrap();
+/// let archive = mktemp::Temp::new_file().unwrap();
+/// assert!(backup::backup(&replica, &archive).unwrap() > 0);
+/// let adapter: Arc<RwLock<Box<dyn Adapter>>> = Arc::new(RwLock::new(Box::new(MemoryAdapter::new())));
+/// backup::restore(&archive, adapter.clone()).unwrap();
+/// let restored = Melda::new(adapter).unwrap();
+/// assert_eq!(restored.read(None).unwrap(), replica.read(None).unwrap());
+/// assert_eq!(restored.replica_id().unwrap(), replica.replica_id().unwrap());
+/// ```
+pub fn backup(melda: &Melda, path: &Path) -> Result<usize> {
+    let adapter = melda.get_adapter();
+    let adapter = adapter.read().unwrap();
+    let encoder = zstd::Encoder::new(BufWriter::new(File::create(path)?), 0)?;
+    let mut archive = tar::Builder::new(encoder);
+    let mut items = Map::<String, Value>::new();
+    let mut keys = adapter.list_objects("")?;
+    keys.sort();
+    for key in keys {
+        let data = adapter.read_object(&key, 0, 0)?;
+        append(&mut archive, &(ITEM_PREFIX.to_string() + &key), &data)?;
+        items.insert(
+            key,
+            json!({ "size" : data.len(), "digest" : digest_bytes(&data) }),
+        );
+    }
+    let count = items.len();
+    let manifest = json!({ "version" : BACKUP_FORMAT_VERSION, "items" : items });
+    append(
+        &mut archive,
+        MANIFEST_ENTRY,
+        serde_json::to_string(&manifest)?.as_bytes(),
+    )?;
+    archive.into_inner()?.finish()?.into_inner()?.sync_all()?;
+    Ok(count)
+}
+
+/// Verifies a backup archive: every item recorded by the manifest must be archived, with the
+/// recorded size and digest, and no other item must be present. Returns the archived items and
+/// their size
+///
+/// # Arguments
+///
+/// * `path` - The path of the archive
+pub fn verify_backup(path: &Path) -> Result<BTreeMap<String, usize>> {
+    let mut found = BTreeMap::<String, (usize, String)>::new();
+    let mut manifest = None;
+    for_each_entry(path, |name, data| {
+        match name.strip_prefix(ITEM_PREFIX) {
+            Some(key) => {
+                found.insert(key.to_string(), (data.len(), digest_bytes(&data)));
+            }
+            None if name == MANIFEST_ENTRY => {
+                manifest = Some(
+                    serde_json::from_slice::<Value>(&data)
+                        .map_err(|_| anyhow!("invalid_backup_manifest"))?,
+                )
+            }
+            None => bail!("unexpected_backup_entry: {}", name),
+        }
+        Ok(())
+    })?;
+    let manifest = manifest.ok_or_else(|| anyhow!("missing_backup_manifest"))?;
+    match manifest.get("version").and_then(Value::as_u64) {
+        Some(BACKUP_FORMAT_VERSION) => {}
+        Some(version) => bail!("unsupported_backup_version: {}", version),
+        None => bail!("invalid_backup_manifest"),
+    }
+    let items = manifest
+        .get("items")
+        .and_then(Value::as_object)
+        .ok_or_else(|| anyhow!("invalid_backup_manifest"))?;
+    if let Some(key) = found.keys().find(|k| !items.contains_key(*k)) {
+        bail!("unexpected_backup_item: {}", key);
+    }
+    let mut sizes = BTreeMap::new();
+    for (key, entry) in items {
+        let (size, digest) = found
+            .remove(key)
+            .ok_or_else(|| anyhow!("missing_backup_item: {}", key))?;
+        if entry.get("size").and_then(Value::as_u64) != Some(size as u64)
+            || entry.get("digest").and_then(Value::as_str) != Some(digest.as_str())
+        {
+            bail!("corrupt_backup_item: {}", key);
+        }
+        sizes.insert(key.clone(), size);
+    }
+    Ok(sizes)
+}
+
+/// Restores a backup archive into the given adapter, once the archive has been verified (see
+/// [verify_backup]). The adapter must not hold any block nor pack (the metadata written when a
+/// replica is opened is replaced). The restored repository can then be opened, for example
+/// with [Melda::new]. Returns the number of restored items
+///
+/// # Arguments
+///
+/// * `path` - The path of the archive
+/// * `adapter` - The adapter
+pub fn restore(path: &Path, adapter: Arc<RwLock<Box<dyn Adapter>>>) -> Result<usize> {
+    let adapter = adapter.write().unwrap();
+    if !adapter.list_objects(DELTA_EXTENSION)?.is_empty()
+        || !adapter.list_objects(PACK_EXTENSION)?.is_empty()
+    {
+        bail!("repository_not_empty");
+    }
+    let count = verify_backup(path)?.len();
+    for_each_entry(path, |name, data| {
+        if let Some(key) = name.strip_prefix(ITEM_PREFIX) {
+            let _ = adapter.delete_object(key);
+            adapter.write_object(key, &data)?;
+        }
+        Ok(())
+    })?;
+    Ok(count)
+}
+
+/// Appends an entry to the archive
+fn append<W: std::io::Write>(archive: &mut tar::Builder<W>, name: &str, data: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    archive.append_data(&mut header, name, data)?;
+    Ok(())
+}
+
+/// Reads the entries of the archive, passing their name and content to the given function
+fn for_each_entry<F>(path: &Path, mut f: F) -> Result<()>
+where
+    F: FnMut(&str, Vec<u8>) -> Result<()>,
+{
+    let decoder = zstd::Decoder::new(BufReader::new(File::open(path)?))?;
+    let mut archive = tar::Archive::new(decoder);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = String::from_utf8(entry.path_bytes().into_owned())
+            .map_err(|_| anyhow!("invalid_backup_entry"))?;
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut data)?;
+        f(&name, data)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memoryadapter::MemoryAdapter;
+
+    #[test]
+    fn test_corrupt_backup() {
+        let replica = Melda::new_from_url("memory://").unwrap();
+        replica
+            .update(json!({ "key" : "value" }).as_object().unwrap().clone())
+            .unwrap();
+        replica.commit(None).unwrap();
+        let archive = mktemp::Temp::new_file().unwrap();
+        let count = backup(&replica, &archive).unwrap();
+        assert_eq!(verify_backup(&archive).unwrap().len(), count);
+        // Replace the content of an item (keeping its size)
+        let data = zstd::decode_all(File::open(&*archive).unwrap()).unwrap();
+        let position = data.windows(5).position(|w| w == b"value").unwrap();
+        let mut tampered = data.clone();
+        tampered[position..position + 5].copy_from_slice(b"VALUE");
+        std::fs::write(&*archive, zstd::encode_all(tampered.as_slice(), 0).unwrap()).unwrap();
+        let error = verify_backup(&archive).unwrap_err();
+        assert!(error.to_string().starts_with("corrupt_backup_item"));
+        let adapter: Arc<RwLock<Box<dyn Adapter>>> =
+            Arc::new(RwLock::new(Box::new(MemoryAdapter::new())));
+        assert!(restore(&archive, adapter.clone()).is_err());
+        assert!(adapter.read().unwrap().list_objects("").unwrap().is_empty());
+        // Restoring into a repository with blocks is refused
+        let data = zstd::encode_all(data.as_slice(), 0).unwrap();
+        std::fs::write(&*archive, data).unwrap();
+        assert!(restore(&archive, replica.get_adapter()).is_err());
+    }
+}
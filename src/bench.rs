@@ -0,0 +1,272 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::constants::{DELTA_EXTENSION, FLATTEN_SUFFIX, PACK_EXTENSION};
+use crate::melda::Melda;
+use crate::utils::is_array_descriptor;
+use anyhow::{bail, Result};
+use serde_json::{json, Map, Value};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Parameters of a synthetic workload. The document is a collection of items, some of which are
+/// shared by all replicas. Each replica commits a number of times: every commit appends an item
+/// owned by the replica and updates one of its items. A fraction of the commits (the conflict
+/// rate) also updates one of the shared items, producing conflicts once the replicas are melded
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workload {
+    /// Number of replicas
+    pub replicas: usize,
+    /// Number of commits of each replica
+    pub commits: usize,
+    /// Initial number of shared items (and of the items owned by each replica)
+    pub array_size: usize,
+    /// Fraction of the commits updating a shared item (between 0 and 1)
+    pub conflict_rate: f64,
+    /// Seed choosing the conflicting commits
+    pub seed: u64,
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Workload {
+            replicas: 2,
+            commits: 10,
+            array_size: 100,
+            conflict_rate: 0.1,
+            seed: 0,
+        }
+    }
+}
+
+impl Workload {
+    /// Returns true if the given commit of a replica updates a shared item (the choice only
+    /// depends on the seed)
+    pub fn is_conflicting(&self, replica: usize, commit: usize) -> bool {
+        let draw = splitmix64(self.seed ^ ((replica as u64) << 32) ^ commit as u64);
+        (draw as f64 / u64::MAX as f64) < self.conflict_rate
+    }
+
+    /// Returns the document committed by a replica with the given commit (each replica only
+    /// knows its own changes)
+    pub fn document(&self, replica: usize, commit: usize) -> Map<String, Value> {
+        let item = |id: String, value: Value| json!({ "_id" : id, "value" : value });
+        // Each commit updates the item whose index is the commit number (modulo the size)
+        let touches = |index: usize, c: &usize| self.array_size > 0 && c % self.array_size == index;
+        let shared = (0..self.array_size).map(|i| {
+            let value = (0..=commit)
+                .rev()
+                .find(|c| touches(i, c) && self.is_conflicting(replica, *c))
+                .map_or(Value::from(0), |c| {
+                    Value::from(format!("{}-{}", replica, c))
+                });
+            item(format!("shared_{}", i), value)
+        });
+        let owned = (0..self.array_size + commit + 1).map(|i| {
+            let value = (0..=commit).filter(|c| touches(i, c)).count();
+            item(format!("r{}_{}", replica, i), Value::from(value))
+        });
+        let items: Vec<Value> = shared.chain(owned).collect();
+        let mut document = Map::new();
+        document.insert("items".to_string() + FLATTEN_SUFFIX, Value::from(items));
+        document
+    }
+}
+
+/// Size of the data persisted by a replica
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StorageSize {
+    /// Number of delta blocks
+    pub blocks: usize,
+    /// Number of data packs
+    pub packs: usize,
+    /// Total number of bytes (including indexes and metadata)
+    pub bytes: usize,
+}
+
+/// Returns the size of the data persisted by a replica
+pub fn storage_size(replica: &Melda) -> Result<StorageSize> {
+    let adapter = replica.get_adapter();
+    let adapter = adapter.read().unwrap();
+    let mut size = StorageSize::default();
+    for key in adapter.list_objects("")? {
+        if key.ends_with(DELTA_EXTENSION) {
+            size.blocks += 1;
+        } else if key.ends_with(PACK_EXTENSION) {
+            size.packs += 1;
+        }
+        size.bytes += adapter.read_object(&key, 0, 0)?.len();
+    }
+    Ok(size)
+}
+
+/// Timings and sizes measured by a benchmark
+#[derive(Debug, Clone, Default)]
+pub struct BenchReport {
+    /// Time spent in each phase (in the order in which phases were first measured)
+    pub phases: Vec<(String, Duration)>,
+    /// Size of the data persisted by the replica holding the melded state
+    pub storage: StorageSize,
+    /// Number of objects in conflict in the melded state (the conflicts of the arrays, which
+    /// are merged automatically, are not counted)
+    pub conflicts: usize,
+}
+
+impl BenchReport {
+    /// Runs a function adding the elapsed time to the given phase
+    pub fn time<T, F: FnOnce() -> Result<T>>(&mut self, phase: &str, f: F) -> Result<T> {
+        let start = Instant::now();
+        let result = f();
+        let elapsed = start.elapsed();
+        match self.phases.iter_mut().find(|(name, _)| name == phase) {
+            Some((_, total)) => *total += elapsed,
+            None => self.phases.push((phase.to_string(), elapsed)),
+        }
+        result
+    }
+
+    /// Returns the time spent in the given phase
+    pub fn duration(&self, phase: &str) -> Option<Duration> {
+        self.phases
+            .iter()
+            .find(|(name, _)| name == phase)
+            .map(|(_, duration)| *duration)
+    }
+
+    /// Returns the report as a JSON object (durations are in seconds)
+    pub fn to_json(&self) -> Value {
+        let phases: Map<String, Value> = self
+            .phases
+            .iter()
+            .map(|(name, duration)| (name.clone(), Value::from(duration.as_secs_f64())))
+            .collect();
+        json!({
+            "phases" : phases,
+            "blocks" : self.storage.blocks,
+            "packs" : self.storage.packs,
+            "bytes" : self.storage.bytes,
+            "conflicts" : self.conflicts,
+        })
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, duration) in &self.phases {
+            writeln!(
+                f,
+                "{:<12}{:>12.3} ms",
+                name,
+                duration.as_secs_f64() * 1000.0
+            )?;
+        }
+        writeln!(f, "{:<12}{:>12}", "blocks", self.storage.blocks)?;
+        writeln!(f, "{:<12}{:>12}", "packs", self.storage.packs)?;
+        writeln!(f, "{:<12}{:>12}", "bytes", self.storage.bytes)?;
+        write!(f, "{:<12}{:>12}", "conflicts", self.conflicts)
+    }
+}
+
+/// Runs a workload on the replicas returned by the given function (invoked with the index of
+/// each replica, for example to open replicas with a specific adapter or builder options).
+/// Each replica commits its changes ("commit" phase), then all replicas are melded into the
+/// first one ("meld" phase), which loads the melded changes ("refresh" phase) and reads the
+/// resulting state ("read" phase)
+///
+/// # Example
+/// ```
+/// use melda::{bench::{run, Workload}, melda::Melda};
+/// let workload = Workload { replicas: 3, commits: 4, array_size: 8, conflict_rate: 0.5, seed: 1 };
+/// let report = run(&workload, |_| Melda::builder().url("memory://").open()).unwrap();
+/// assert!(report.duration("meld").is_some());
+/// assert_eq!(report.storage.blocks, 12);
+/// assert!(report.conflicts > 0);
+/// println!("{}", report);
+/// ```
+pub fn run<F: FnMut(usize) -> Result<Melda>>(
+    workload: &Workload,
+    mut open: F,
+) -> Result<BenchReport> {
+    if workload.replicas == 0 {
+        bail!("no_replicas");
+    }
+    let mut report = BenchReport::default();
+    let replicas = (0..workload.replicas)
+        .map(&mut open)
+        .collect::<Result<Vec<Melda>>>()?;
+    for (r, replica) in replicas.iter().enumerate() {
+        for c in 0..workload.commits {
+            let document = workload.document(r, c);
+            report.time("commit", || {
+                replica.update(document)?;
+                replica.commit(None)
+            })?;
+        }
+    }
+    let (first, others) = replicas.split_first().unwrap();
+    for other in others {
+        report.time("meld", || first.meld(other))?;
+    }
+    let first = replicas.into_iter().next().unwrap();
+    report.time("refresh", || first.refresh())?;
+    report.time("read", || first.read(None))?;
+    report.storage = storage_size(&first)?;
+    report.conflicts = first
+        .in_conflict()
+        .iter()
+        .filter(|uuid| !is_array_descriptor(uuid))
+        .count();
+    Ok(report)
+}
+
+// Mixes the bits of a seed (SplitMix64)
+pub(crate) fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bench::{run, Workload};
+    use crate::melda::Melda;
+
+    #[test]
+    fn test_workload() {
+        let workload = Workload {
+            conflict_rate: 0.0,
+            ..Workload::default()
+        };
+        assert!((0..workload.commits).all(|c| !workload.is_conflicting(0, c)));
+        let document = workload.document(1, 2);
+        assert_eq!(
+            document.get("items♭").unwrap().as_array().unwrap().len(),
+            203
+        );
+        assert_eq!(workload.document(1, 2), document);
+        let report = run(&workload, |_| Melda::new_from_url("memory://")).unwrap();
+        assert_eq!(report.conflicts, 0);
+        assert_eq!(report.storage.blocks, 20);
+        let phases: Vec<&str> = report.phases.iter().map(|(p, _)| p.as_str()).collect();
+        assert_eq!(phases, ["commit", "meld", "refresh", "read"]);
+        assert_eq!(report.to_json()["blocks"], 20);
+        let workload = Workload {
+            replicas: 0,
+            ..Workload::default()
+        };
+        assert!(run(&workload, |_| Melda::new_from_url("memory://")).is_err());
+    }
+}
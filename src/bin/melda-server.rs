@@ -0,0 +1,121 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//! Server hosting many Melda repositories
+use anyhow::{anyhow, bail, Result};
+use melda::capability::Capability;
+use melda::server::RepositoryServer;
+use std::path::Path;
+use std::time::Duration;
+
+const USAGE: &str =
+    "Usage: melda-server [-d <root>] [-c <seconds>] [-g <address>] [-w <address>] [-p <peer>] <command> [arguments]
+
+The secret used to sign the tokens is read from the MELDA_SERVER_SECRET environment variable.
+
+Options:
+  -d, --root <dir>           Directory containing the repositories (default: repositories)
+  -c, --compact <seconds>    Compacts the repositories which have not been written for the given time
+  -g, --grpc <address>       Also serves the gRPC interface on the given address (grpc feature)
+  -w, --websocket <address>  Also serves the sync channel on the given address (websocket feature)
+  -p, --peer <peer>          Identity of the peer bearing the issued token (to share presence)
+
+Commands:
+  serve [address] [threads]                 Serves requests (default: 127.0.0.1:8080 with 4 threads)
+  issue <repository> <r|rw> [seconds]       Issues a token for a repository (valid for the given time)";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = run(args) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(mut args: Vec<String>) -> Result<()> {
+    let mut root = "repositories".to_string();
+    let mut compaction = None;
+    let mut grpc = None;
+    let mut websocket = None;
+    let mut peer = None;
+    while args.len() > 1 && args[0].starts_with('-') {
+        let option = args.remove(0);
+        let value = args.remove(0);
+        match option.as_str() {
+            "-d" | "--root" => root = value,
+            "-c" | "--compact" => {
+                let seconds = value
+                    .parse()
+                    .map_err(|_| anyhow!("invalid_compaction_time"))?;
+                compaction = Some(Duration::from_secs(seconds));
+            }
+            "-g" | "--grpc" => grpc = Some(value),
+            "-w" | "--websocket" => websocket = Some(value),
+            "-p" | "--peer" => peer = Some(value),
+            _ => bail!("invalid_option\n\n{}", USAGE),
+        }
+    }
+    let secret = std::env::var("MELDA_SERVER_SECRET")
+        .map_err(|_| anyhow!("missing_server_secret\n\n{}", USAGE))?;
+    let mut server = RepositoryServer::new(Path::new(&root), secret.as_bytes())?;
+    if let Some(idle) = compaction {
+        server = server.with_compaction(idle);
+    }
+    if let Some(address) = grpc {
+        #[cfg(feature = "grpc")]
+        {
+            server = server.with_grpc(&address);
+        }
+        #[cfg(not(feature = "grpc"))]
+        bail!("grpc_not_supported: {}", address);
+    }
+    if let Some(address) = websocket {
+        #[cfg(feature = "websocket")]
+        {
+            server = server.with_websocket(&address);
+        }
+        #[cfg(not(feature = "websocket"))]
+        bail!("websocket_not_supported: {}", address);
+    }
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+    match args.as_slice() {
+        ["serve", rest @ ..] if rest.len() <= 2 => {
+            let address = rest.first().copied().unwrap_or("127.0.0.1:8080");
+            let threads = match rest.get(1) {
+                Some(t) => t.parse().map_err(|_| anyhow!("invalid_threads"))?,
+                None => 4,
+            };
+            println!("Serving repositories in {} on {}", root, address);
+            server.serve(address, threads)
+        }
+        ["issue", repository, access, lifetime @ ..] if lifetime.len() <= 1 => {
+            let mut capability = match *access {
+                "r" => Capability::read_only(),
+                "rw" => Capability::read_write(),
+                _ => bail!("invalid_access\n\n{}", USAGE),
+            };
+            if let Some(lifetime) = lifetime.first() {
+                let seconds = lifetime.parse().map_err(|_| anyhow!("invalid_lifetime"))?;
+                capability = capability.valid_for(Duration::from_secs(seconds));
+            }
+            if let Some(peer) = &peer {
+                capability = capability.with_peer(peer);
+            }
+            println!("{}", server.issue(repository, &capability)?);
+            Ok(())
+        }
+        _ => bail!("invalid_command\n\n{}", USAGE),
+    }
+}
@@ -0,0 +1,313 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//! Terminal UI to browse the history of a Melda repository and resolve conflicts
+use anyhow::{anyhow, Result};
+use melda::jsonpatch::PatchOp;
+use melda::melda::Melda;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Tabs, Wrap};
+use ratatui::Frame;
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, VecDeque};
+
+const HELP: &str =
+    "Tab: switch view | Up/Down: select | Left/Right: select revision | Enter: resolve | q: quit";
+
+#[derive(PartialEq)]
+enum View {
+    History,
+    Conflicts,
+}
+
+struct App {
+    replica: Melda,
+    view: View,
+    /// Blocks (most recent first) with a short description
+    blocks: Vec<(String, String)>,
+    blocks_state: ListState,
+    /// Objects in conflict
+    conflicts: Vec<String>,
+    conflicts_state: ListState,
+    /// Candidate revisions (the winner first) of the selected object
+    revisions: Vec<(String, Value)>,
+    revision: usize,
+    status: String,
+}
+
+impl App {
+    fn new(replica: Melda) -> Result<Self> {
+        let mut app = App {
+            replica,
+            view: View::History,
+            blocks: vec![],
+            blocks_state: ListState::default(),
+            conflicts: vec![],
+            conflicts_state: ListState::default(),
+            revisions: vec![],
+            revision: 0,
+            status: HELP.to_string(),
+        };
+        app.load()?;
+        Ok(app)
+    }
+
+    /// Loads the blocks reachable from the anchors and the objects in conflict
+    fn load(&mut self) -> Result<()> {
+        self.blocks.clear();
+        let mut visited = BTreeSet::new();
+        let mut queue: VecDeque<String> = self.replica.get_anchors().into_iter().collect();
+        while let Some(block_id) = queue.pop_front() {
+            if !visited.insert(block_id.clone()) {
+                continue;
+            }
+            if let Some(block) = self.replica.get_block(&block_id)? {
+                queue.extend(block.parents.iter().flatten().cloned());
+                let description = block
+                    .info
+                    .map(|i| Value::from(i).to_string())
+                    .unwrap_or_default();
+                self.blocks.push((block_id, description));
+            }
+        }
+        self.conflicts = self.replica.in_conflict().into_iter().collect();
+        select_first(&mut self.blocks_state, self.blocks.len());
+        select_first(&mut self.conflicts_state, self.conflicts.len());
+        self.load_revisions()
+    }
+
+    fn load_revisions(&mut self) -> Result<()> {
+        self.revisions.clear();
+        self.revision = 0;
+        if let Some(uuid) = self.selected_conflict() {
+            let winner = self.replica.get_winner(&uuid)?;
+            let mut candidates = vec![winner];
+            candidates.extend(self.replica.get_conflicting(&uuid)?);
+            for revision in candidates {
+                let value = Value::from(self.replica.get_value(&uuid, Some(&revision))?);
+                self.revisions.push((revision, value));
+            }
+        }
+        Ok(())
+    }
+
+    fn selected_conflict(&self) -> Option<String> {
+        self.conflicts_state
+            .selected()
+            .and_then(|i| self.conflicts.get(i).cloned())
+    }
+
+    /// Describes the selected block as JSON Patch operations
+    fn block_diff(&self) -> Vec<Line<'static>> {
+        let block_id = match self
+            .blocks_state
+            .selected()
+            .and_then(|i| self.blocks.get(i))
+        {
+            Some((block_id, _)) => block_id,
+            None => return vec![],
+        };
+        match self.replica.block_patch(block_id) {
+            Ok(patch) => patch
+                .into_iter()
+                .map(|op| match op {
+                    PatchOp::Add { path, value } => {
+                        Line::from(format!("+ {} {}", path, value)).green()
+                    }
+                    PatchOp::Remove { path } => Line::from(format!("- {}", path)).red(),
+                    PatchOp::Replace { path, value } => {
+                        Line::from(format!("~ {} {}", path, value)).yellow()
+                    }
+                    PatchOp::Move { from, path } => Line::from(format!("> {} -> {}", from, path)),
+                    PatchOp::Copy { from, path } => Line::from(format!("= {} -> {}", from, path)),
+                    PatchOp::Test { path, value } => Line::from(format!("? {} {}", path, value)),
+                })
+                .collect(),
+            Err(e) => vec![Line::from(format!("error: {}", e)).red()],
+        }
+    }
+
+    fn move_selection(&mut self, delta: isize) -> Result<()> {
+        let (state, len) = match self.view {
+            View::History => (&mut self.blocks_state, self.blocks.len()),
+            View::Conflicts => (&mut self.conflicts_state, self.conflicts.len()),
+        };
+        if len > 0 {
+            let current = state.selected().unwrap_or(0) as isize;
+            state.select(Some((current + delta).clamp(0, len as isize - 1) as usize));
+        }
+        if self.view == View::Conflicts {
+            self.load_revisions()?;
+        }
+        Ok(())
+    }
+
+    /// Resolves the selected conflict with the selected revision and commits the resolution
+    fn resolve(&mut self) -> Result<()> {
+        let uuid = self
+            .selected_conflict()
+            .ok_or_else(|| anyhow!("no_conflict_selected"))?;
+        let (revision, _) = self
+            .revisions
+            .get(self.revision)
+            .ok_or_else(|| anyhow!("no_revision_selected"))?;
+        self.replica.resolve_as(&uuid, revision)?;
+        let info = json!({ "message": format!("Resolve conflict on {}", uuid) });
+        self.replica
+            .commit(Some(info.as_object().unwrap().clone()))?;
+        self.status = format!("Resolved {} as {}", uuid, revision);
+        self.load()
+    }
+
+    fn handle_key(&mut self, key: KeyCode) -> Result<bool> {
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+            KeyCode::Tab => {
+                self.view = match self.view {
+                    View::History => View::Conflicts,
+                    View::Conflicts => View::History,
+                }
+            }
+            KeyCode::Up => self.move_selection(-1)?,
+            KeyCode::Down => self.move_selection(1)?,
+            KeyCode::Left => self.revision = self.revision.saturating_sub(1),
+            KeyCode::Right if self.revision + 1 < self.revisions.len() => self.revision += 1,
+            KeyCode::Enter if self.view == View::Conflicts => self.resolve()?,
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [tabs, main, status] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let selected = if self.view == View::History { 0 } else { 1 };
+        frame.render_widget(
+            Tabs::new(vec!["History", "Conflicts"]).select(selected),
+            tabs,
+        );
+        let [list, detail] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(main);
+        match self.view {
+            View::History => {
+                let items: Vec<ListItem> = self
+                    .blocks
+                    .iter()
+                    .map(|(id, description)| ListItem::new(format!("{:.12} {}", id, description)))
+                    .collect();
+                let title = format!("Blocks ({})", items.len());
+                frame.render_stateful_widget(
+                    highlighted(items, title),
+                    list,
+                    &mut self.blocks_state,
+                );
+                let diff = Paragraph::new(self.block_diff())
+                    .block(Block::bordered().title("Changes"))
+                    .wrap(Wrap { trim: false });
+                frame.render_widget(diff, detail);
+            }
+            View::Conflicts => {
+                let items: Vec<ListItem> = self
+                    .conflicts
+                    .iter()
+                    .map(|c| ListItem::new(c.as_str()))
+                    .collect();
+                let title = format!("Conflicts ({})", items.len());
+                frame.render_stateful_widget(
+                    highlighted(items, title),
+                    list,
+                    &mut self.conflicts_state,
+                );
+                let lines: Vec<Line> = self
+                    .revisions
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(i, (revision, value))| {
+                        let label = if i == 0 { "winner" } else { "conflicting" };
+                        let header = Line::from(format!("{} {}", label, revision));
+                        let header = if i == self.revision {
+                            header.reversed()
+                        } else {
+                            header.bold()
+                        };
+                        vec![header, Line::from(value.to_string()), Line::from("")]
+                    })
+                    .collect();
+                let revisions = Paragraph::new(lines)
+                    .block(Block::bordered().title("Revisions"))
+                    .wrap(Wrap { trim: false });
+                frame.render_widget(revisions, detail);
+            }
+        }
+        frame.render_widget(Paragraph::new(self.status.as_str()), status);
+    }
+}
+
+fn highlighted(items: Vec<ListItem>, title: String) -> List {
+    List::new(items)
+        .block(Block::bordered().title(title))
+        .highlight_style(Style::new().reversed())
+}
+
+fn select_first(state: &mut ListState, len: usize) {
+    match state.selected() {
+        Some(i) if i < len => {}
+        _ => state.select(if len > 0 { Some(0) } else { None }),
+    }
+}
+
+fn main() {
+    let url = match std::env::args().nth(1) {
+        Some(url) => url,
+        None => {
+            eprintln!("Usage: melda-tui <url>");
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = run(&url) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(url: &str) -> Result<()> {
+    let mut app = App::new(Melda::new_from_url(url)?)?;
+    let mut terminal = ratatui::init();
+    let result = (|| -> Result<()> {
+        loop {
+            terminal.draw(|frame| app.draw(frame))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                match app.handle_key(key.code) {
+                    Ok(true) => {}
+                    Ok(false) => return Ok(()),
+                    Err(e) => app.status = format!("error: {}", e),
+                }
+            }
+        }
+    })();
+    ratatui::restore();
+    result
+}
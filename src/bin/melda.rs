@@ -0,0 +1,774 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//! Command line interface to Melda repositories
+use anyhow::{anyhow, bail, Result};
+use melda::filetree::{checkin_tree, export_tree};
+use melda::melda::{BlockFilter, Cursor, FsckOptions, Melda, RetentionPolicy};
+use melda::topology::{Replicator, Topology};
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+const DEFAULT_PAGE_SIZE: usize = 100;
+
+const USAGE: &str = "Usage: melda [-r <path-or-url>] [-s <stage-file>] <command> [arguments]
+
+Options:
+  -r, --repository <path-or-url>  Repository (default: .melda)
+  -s, --stage <file>              File holding uncommitted changes (default: <path>.stage.json
+                                  for local repositories, .melda-stage.json otherwise)
+
+Commands:
+  init                   Creates the repository
+  put <file.json>        Stages the content of a JSON file
+  commit -m <message>    Commits the staged changes
+  estimate               Estimates the number of objects and bytes the next commit would write
+  read [--conflicts|--canonical] [root]
+                         Prints the state (including staged changes), embedding the
+                         alternatives of the fields in conflict with --conflicts, or as
+                         canonical JSON with --canonical
+  digest [root]          Prints the digest of the canonical JSON state
+  export-ndjson <collection> [column]...
+                         Prints the elements of a collection as NDJSON (columns select fields,
+                         or nested values with JSON Pointers)
+  export-csv <collection> [column]...
+                         Prints the elements of a collection as CSV
+  export-sqlite <file>   Writes the committed state into the tables of a SQLite database
+                         (refreshing the rows changed since the previous export)
+  log                    Prints the commit history
+  dot                    Prints the commit history as a Graphviz (DOT) graph
+  blocks [--author <a>] [--since <ms>] [--until <ms>] [--after <token>] [--limit <n>]
+                         Prints a page of blocks (as JSON), along with the token from which
+                         the listing resumes
+  packs [--after <token>] [--limit <n>]
+                         Prints a page of packs (as JSON)
+  annotate <block> <key> [value]
+                         Sets the annotation of a block to a JSON value (removes it if no
+                         value is given)
+  annotations [block]    Prints the annotations of a block (of all blocks if none is given)
+  meld <path-or-url>     Melds the committed changes of another repository
+  compare <path-or-url>  Compares the committed changes with another repository (without melding)
+  preview <path-or-url>  Previews the objects changed, the conflicts and the array orders resulting
+                         from melding another repository (as JSON)
+  sync <topology.json> <node> [--once]
+                         Synchronizes a node of a replication topology with its remotes
+                         (periodically, unless --once is given)
+  shallow-clone <path-or-url>
+                         Initializes the repository with the current state of another one
+                         (without its history)
+  backfill <path-or-url> Fetches the history missing in a shallow repository
+  attach <file>          Stores a file as an attachment and prints the reference to it
+  attachment <id> [file] Writes the content of an attachment (to stdout if no file is given)
+  changes [cursor]       Prints the changes committed after the cursor (as JSON), along with
+                         the cursor from which to resume
+  history <uuid-or-pointer>
+                         Prints the revisions which changed an object (or the value located by
+                         a JSON Pointer), with the blocks which introduced them (as JSON)
+  search <words>...      Prints the strings containing all the words (including staged changes,
+                         as JSON)
+  conflicts              Prints a summary of the conflicts (as JSON)
+  resolutions [uuid]     Prints the resolutions of conflicts found in the history (as JSON)
+  schema [file.json]     Attaches a JSON Schema to the repository (if given), then prints the
+                         violations of the committed state (as JSON)
+  stats                  Prints statistics about the objects and the storage (as JSON)
+  replica                Prints the identifier of the replica
+  replicas               Prints the replicas which created the blocks, with their latest
+                         blocks (as JSON)
+  fsck [--full]          Checks the integrity of the repository (--full also checks the
+                         pack indexes and lists orphaned items)
+  repair [path-or-url]   Repairs the damaged items (fetching them from another repository,
+                         if given)
+  compact                Merges the packs dropping the values no longer referenced
+  gc                     Deletes the blocks and packs no longer reachable from any block
+  retain [--blocks <n>] [--days <d>]
+                         Squashes the history older than the last n blocks and d days
+  backup <file>          Writes a compressed archive of the repository (with the backup feature)
+  restore <file>         Restores an archive into an empty repository, once verified (with the
+                         backup feature)
+  export-bundle [file]   Exports the committed changes as a bundle (to stdout if no file is given)
+  import-bundle <file>   Imports a bundle
+  format-patch <block> [file]
+                         Writes a block as a patch file (to stdout if no file is given)
+  apply-patch <file>     Applies a patch file (its block is applied once the parents are known)
+  export-yjs <file> <pointer>...
+                         Exports text and array fields as a Yjs update
+  checkout <dir>         Writes the state as a directory tree of JSON files
+  checkin <dir> [-m <message>]
+                         Commits the changes made to a directory tree (along with the staged
+                         changes)
+  mount <dir>            Mounts the state as a directory tree of JSON files, committing the
+                         changes made to the files (with the fuse feature)
+  import-tree <dir> [-m <message>]
+                         Imports a directory of JSON files (sub-directories become collections)
+                         in a single commit
+  shell                  Starts an interactive inspection shell";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = run(args) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(mut args: Vec<String>) -> Result<()> {
+    let mut repository = ".melda".to_string();
+    let mut stage = None;
+    while !args.is_empty() && args[0].starts_with('-') {
+        let option = args.remove(0);
+        if args.is_empty() {
+            bail!("missing_value_for_option: {}\n\n{}", option, USAGE);
+        }
+        match option.as_str() {
+            "-r" | "--repository" => repository = args.remove(0),
+            "-s" | "--stage" => stage = Some(args.remove(0)),
+            _ => bail!("unknown_option: {}\n\n{}", option, USAGE),
+        }
+    }
+    if args.is_empty() {
+        bail!("missing_command\n\n{}", USAGE);
+    }
+    let command = args.remove(0);
+    let stage = stage.unwrap_or_else(|| {
+        if repository.contains("://") {
+            ".melda-stage.json".to_string()
+        } else {
+            format!("{}.stage.json", repository.trim_end_matches('/'))
+        }
+    });
+    let stage = Path::new(&stage);
+    match (command.as_str(), args.as_slice()) {
+        ("init", []) => {
+            open(&repository)?;
+            println!("Initialized repository {}", repository_url(&repository)?);
+        }
+        ("put", [file]) => {
+            let replica = open_staged(&repository, stage)?;
+            replica.update_from_reader(BufReader::new(File::open(file)?))?;
+            save_stage(&replica, stage)?;
+        }
+        ("estimate", []) => {
+            let estimate = open_staged(&repository, stage)?.estimate_commit()?;
+            println!(
+                "{} objects, {} values, about {} bytes",
+                estimate.objects,
+                estimate.values,
+                estimate.total_bytes()
+            );
+        }
+        ("commit", [flag, message]) if flag == "-m" => {
+            let replica = open_staged(&repository, stage)?;
+            match replica.commit(Some(
+                json!({ "message": message }).as_object().unwrap().clone(),
+            ))? {
+                Some(anchors) => {
+                    for a in anchors {
+                        println!("{}", a);
+                    }
+                }
+                None => println!("Nothing to commit"),
+            }
+            if stage.exists() {
+                std::fs::remove_file(stage)?;
+            }
+        }
+        ("read", [flag, root @ ..]) if flag == "--conflicts" && root.len() <= 1 => {
+            let replica = open_staged(&repository, stage)?;
+            let content = replica.read_with_conflicts(root.first().map(|r| r.as_str()))?;
+            println!("{}", serde_json::to_string_pretty(&content)?);
+        }
+        ("read", [flag, root @ ..]) if flag == "--canonical" && root.len() <= 1 => {
+            let replica = open_staged(&repository, stage)?;
+            println!(
+                "{}",
+                replica.read_canonical(root.first().map(|r| r.as_str()))?
+            );
+        }
+        ("digest", root) if root.len() <= 1 => {
+            let replica = open_staged(&repository, stage)?;
+            println!(
+                "{}",
+                replica.canonical_digest(root.first().map(|r| r.as_str()))?
+            );
+        }
+        ("export-ndjson", [collection, columns @ ..]) => {
+            let columns: Vec<&str> = columns.iter().map(String::as_str).collect();
+            let replica = open_staged(&repository, stage)?;
+            replica.export_ndjson(collection, &columns, std::io::stdout().lock())?;
+        }
+        ("export-csv", [collection, columns @ ..]) => {
+            let columns: Vec<&str> = columns.iter().map(String::as_str).collect();
+            let replica = open_staged(&repository, stage)?;
+            replica.export_csv(collection, &columns, std::io::stdout().lock())?;
+        }
+        #[cfg(feature = "sqlitedb")]
+        ("export-sqlite", [file]) => {
+            let written = melda::sqliteexport::export_sqlite(&open(&repository)?, Path::new(file))?;
+            println!("{} rows written", written);
+        }
+        ("read", root) if root.len() <= 1 => {
+            let replica = open_staged(&repository, stage)?;
+            let content = replica.read(root.first().map(|r| r.as_str()))?;
+            println!("{}", serde_json::to_string_pretty(&content)?);
+        }
+        ("log", []) => print_log(&open(&repository)?)?,
+        ("dot", []) => print!("{}", open(&repository)?.export_dot()?),
+        ("blocks", options) if options.len() % 2 == 0 => {
+            let mut filter = BlockFilter::default();
+            let (mut token, mut limit) = (None, DEFAULT_PAGE_SIZE);
+            for option in options.chunks(2) {
+                let value = option[1].clone();
+                match option[0].as_str() {
+                    "--author" => filter.author = Some(value),
+                    "--since" => filter.since = Some(parse_number(&value)?),
+                    "--until" => filter.until = Some(parse_number(&value)?),
+                    "--after" => token = Some(value),
+                    "--limit" => limit = parse_number(&value)? as usize,
+                    other => bail!("unknown_option: {}", other),
+                }
+            }
+            let page = open(&repository)?.list_blocks(&filter, token.as_deref(), limit)?;
+            println!("{}", serde_json::to_string_pretty(&page)?);
+        }
+        ("packs", options) if options.len() % 2 == 0 => {
+            let (mut token, mut limit) = (None, DEFAULT_PAGE_SIZE);
+            for option in options.chunks(2) {
+                match option[0].as_str() {
+                    "--after" => token = Some(option[1].clone()),
+                    "--limit" => limit = parse_number(&option[1])? as usize,
+                    other => bail!("unknown_option: {}", other),
+                }
+            }
+            let page = open(&repository)?.list_packs(token.as_deref(), limit)?;
+            println!("{}", serde_json::to_string_pretty(&page)?);
+        }
+        ("annotate", [block, key, value @ ..]) if value.len() <= 1 => {
+            let value = value
+                .first()
+                .map(|v| serde_json::from_str::<Value>(v))
+                .transpose()
+                .map_err(|e| anyhow!("invalid_annotation: {}", e))?;
+            open(&repository)?.annotate(block, key, value)?;
+        }
+        ("annotations", []) => {
+            let annotations = open(&repository)?.all_annotations()?;
+            println!("{}", serde_json::to_string_pretty(&annotations)?);
+        }
+        ("annotations", [block]) => {
+            let annotations = open(&repository)?.annotations(block)?;
+            println!("{}", serde_json::to_string_pretty(&annotations)?);
+        }
+        ("meld", [other]) => {
+            let replica = open(&repository)?;
+            let melded = replica.meld(&open(other)?)?;
+            println!("Melded {} items", melded.len());
+        }
+        ("preview", [other]) => {
+            let preview = open(&repository)?.simulate_meld(&open(other)?)?;
+            println!("{}", serde_json::to_string_pretty(&preview)?);
+        }
+        ("sync", [topology, node, once @ ..]) if once.is_empty() || once == ["--once"] => {
+            let topology: Topology = serde_json::from_reader(BufReader::new(File::open(topology)?))
+                .map_err(|e| anyhow!("invalid_topology: {}", e))?;
+            let mut replicator = Replicator::new(&topology, node)?;
+            if once.is_empty() {
+                replicator.run(&std::sync::atomic::AtomicBool::new(false));
+            }
+            for outcome in replicator.sync_due(std::time::Instant::now()) {
+                match outcome.error {
+                    Some(error) => println!("{}: {}", outcome.remote, error),
+                    None => println!(
+                        "{}: pulled {} items, pushed {} items",
+                        outcome.remote,
+                        outcome.pulled.len(),
+                        outcome.pushed.len()
+                    ),
+                }
+            }
+        }
+        ("compare", [other]) => {
+            let divergence = open(&repository)?.compare(&open(other)?)?;
+            for block in &divergence.local_only {
+                println!("Local {}", block);
+            }
+            for block in &divergence.remote_only {
+                println!("Remote {}", block);
+            }
+            let status = if divergence.in_sync() {
+                "in sync"
+            } else if divergence.is_ancestor() {
+                "behind"
+            } else if divergence.is_descendant() {
+                "ahead"
+            } else {
+                "diverged"
+            };
+            println!(
+                "Repositories {} (fetch {} bytes, send {} bytes)",
+                status, divergence.fetch_bytes, divergence.send_bytes
+            );
+        }
+        ("shallow-clone", [other]) => {
+            match open(&repository)?.shallow_clone_from(&open(other)?)? {
+                Some(graft) => println!("{}", graft),
+                None => println!("Nothing to clone"),
+            }
+        }
+        ("backfill", [other]) => {
+            let melded = open(&repository)?.backfill(&open(other)?)?;
+            println!("Melded {} items", melded.len());
+        }
+        ("attach", [file]) => {
+            let reference = open(&repository)?.put_attachment(&std::fs::read(file)?)?;
+            println!("{}", reference);
+        }
+        ("attachment", [id, file @ ..]) if file.len() <= 1 => {
+            let content = open(&repository)?.get_attachment(id)?;
+            match file.first() {
+                Some(file) => std::fs::write(file, content)?,
+                None => std::io::stdout().write_all(&content)?,
+            }
+        }
+        ("schema", file) if file.len() <= 1 => {
+            let replica = open(&repository)?;
+            if let Some(file) = file.first() {
+                replica.set_schema(Some(serde_json::from_reader(BufReader::new(File::open(
+                    file,
+                )?))?))?;
+            }
+            let violations = replica.validate_schema(None)?;
+            println!("{}", serde_json::to_string_pretty(&violations)?);
+        }
+        ("changes", cursor) if cursor.len() <= 1 => {
+            let since = match cursor.first() {
+                Some(cursor) => cursor.parse()?,
+                None => Cursor::start(),
+            };
+            let feed = open(&repository)?.changes(&since)?;
+            println!("{}", serde_json::to_string_pretty(&feed)?);
+        }
+        ("history", [target]) => {
+            let history = open(&repository)?.who_changed(target)?;
+            println!("{}", serde_json::to_string_pretty(&history)?);
+        }
+        ("search", words) if !words.is_empty() => {
+            let replica = Melda::builder()
+                .url(&repository_url(&repository)?)
+                .full_text_index(true)
+                .open()?;
+            restore_stage(&replica, stage)?;
+            let hits = replica.search(&words.join(" "))?;
+            println!("{}", serde_json::to_string_pretty(&hits)?);
+        }
+        ("conflicts", []) => {
+            let report = open_staged(&repository, stage)?.conflict_report()?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+        ("resolutions", uuid) if uuid.len() <= 1 => {
+            let resolutions = open(&repository)?.resolutions(uuid.first().map(|u| u.as_str()))?;
+            println!("{}", serde_json::to_string_pretty(&resolutions)?);
+        }
+        ("stats", []) => {
+            let stats = open(&repository)?.stats()?;
+            println!("{}", serde_json::to_string_pretty(&stats)?);
+        }
+        ("replica", []) => println!("{}", open(&repository)?.replica_id()?),
+        ("replicas", []) => {
+            let replicas = open(&repository)?.replicas();
+            println!("{}", serde_json::to_string_pretty(&replicas)?);
+        }
+        ("fsck", rest) if rest.is_empty() || rest == ["--full"] => {
+            let options = if rest.is_empty() {
+                FsckOptions::default()
+            } else {
+                FsckOptions::full()
+            };
+            let report = open(&repository)?.fsck_with(&options)?;
+            println!(
+                "Checked {} blocks, {} packs and {} indexes",
+                report.checked_blocks, report.checked_packs, report.checked_indexes
+            );
+            for issue in &report.issues {
+                println!("{:?}", issue);
+            }
+            for item in &report.orphaned {
+                println!("Orphaned {}", item);
+            }
+            if !report.is_clean() {
+                bail!("repository_damaged");
+            }
+        }
+        ("repair", rest) if rest.len() <= 1 => {
+            let replica = open(&repository)?;
+            let remote = rest.first().map(|other| open(other)).transpose()?;
+            let report =
+                replica.repair(&replica.fsck_with(&FsckOptions::full())?, remote.as_ref())?;
+            for item in &report.fetched {
+                println!("Fetched {}", item);
+            }
+            for item in &report.quarantined {
+                println!("Quarantined {}", item);
+            }
+            for pack in &report.rebuilt_indexes {
+                println!("Rebuilt index of {}", pack);
+            }
+            for issue in &report.unresolved {
+                println!("{:?}", issue);
+            }
+            if !report.unresolved.is_empty() {
+                bail!("repository_damaged");
+            }
+        }
+        ("compact", []) => {
+            let report = open(&repository)?.compact()?;
+            println!(
+                "Merged {} packs, dropped {} values",
+                report.removed_packs.len(),
+                report.dropped_values
+            );
+        }
+        ("retain", options) if !options.is_empty() && options.len() % 2 == 0 => {
+            let mut policy = RetentionPolicy::default();
+            for option in options.chunks(2) {
+                let value = parse_number(&option[1])?;
+                match option[0].as_str() {
+                    "--blocks" => policy.keep_blocks = Some(value as usize),
+                    "--days" => {
+                        policy.keep_duration = Some(std::time::Duration::from_secs(value * 86400))
+                    }
+                    other => bail!("unknown_option: {}", other),
+                }
+            }
+            let report = Melda::builder()
+                .url(&repository_url(&repository)?)
+                .retention_policy(policy)
+                .open()?
+                .enforce_retention()?;
+            println!(
+                "Squashed {} blocks, removed {} blocks and {} packs, reclaimed {} bytes",
+                report.squashed_blocks.len(),
+                report.gc.removed_blocks.len(),
+                report.gc.removed_packs.len(),
+                report.gc.reclaimed_bytes
+            );
+        }
+        ("gc", []) => {
+            let report = open(&repository)?.gc()?;
+            println!(
+                "Removed {} blocks and {} packs, reclaimed {} bytes",
+                report.removed_blocks.len(),
+                report.removed_packs.len(),
+                report.reclaimed_bytes
+            );
+        }
+        ("export-bundle", file) if file.len() <= 1 => {
+            let bundle = open(&repository)?.export_bundle()?;
+            match file.first() {
+                Some(file) => BufWriter::new(File::create(file)?).write_all(&bundle)?,
+                None => std::io::stdout().write_all(&bundle)?,
+            }
+        }
+        #[cfg(feature = "backup")]
+        ("backup", [file]) => {
+            let archived = melda::backup::backup(&open(&repository)?, Path::new(file))?;
+            println!("Archived {} items", archived);
+        }
+        #[cfg(feature = "backup")]
+        ("restore", [file]) => {
+            let adapter = melda::adapter::get_adapter(&repository_url(&repository)?)?;
+            let restored = melda::backup::restore(Path::new(file), Arc::new(RwLock::new(adapter)))?;
+            println!("Restored {} items", restored);
+        }
+        ("import-bundle", [file]) => {
+            let imported = open(&repository)?.import_bundle(&std::fs::read(file)?)?;
+            println!("Imported {} items", imported.len());
+        }
+        ("format-patch", [block, file @ ..]) if file.len() <= 1 => {
+            let patch = open(&repository)?.format_patch(block)?;
+            match file.first() {
+                Some(file) => std::fs::write(file, patch)?,
+                None => print!("{}", patch),
+            }
+        }
+        ("apply-patch", [file]) => {
+            let patch = std::fs::read_to_string(file)?;
+            match open(&repository)?.apply_patch_file(&patch)? {
+                Some(block) => println!("Applied {}", block),
+                None => println!("Already applied"),
+            }
+        }
+        ("export-yjs", [file, fields @ ..]) if !fields.is_empty() => {
+            let fields: Vec<&str> = fields.iter().map(String::as_str).collect();
+            let update = open_staged(&repository, stage)?.export_yjs(&fields)?;
+            BufWriter::new(File::create(file)?).write_all(&update)?;
+        }
+        ("checkout", [dir]) => export_tree(&open_staged(&repository, stage)?, Path::new(dir))?,
+        ("checkin", [dir, rest @ ..]) => {
+            let information = match rest {
+                [] => None,
+                [flag, message] if flag == "-m" => {
+                    json!({ "message": message }).as_object().cloned()
+                }
+                _ => bail!("invalid_command: {}\n\n{}", command, USAGE),
+            };
+            let replica = open_staged(&repository, stage)?;
+            match checkin_tree(&replica, Path::new(dir), information)? {
+                Some(anchors) => {
+                    for a in anchors {
+                        println!("{}", a);
+                    }
+                }
+                None => println!("Nothing to commit"),
+            }
+            if stage.exists() {
+                std::fs::remove_file(stage)?;
+            }
+        }
+        #[cfg(feature = "fuse")]
+        ("mount", [dir]) => melda::fusefs::mount(Arc::new(open(&repository)?), Path::new(dir))?,
+        ("import-tree", [dir, rest @ ..]) => {
+            let information = match rest {
+                [] => None,
+                [flag, message] if flag == "-m" => {
+                    json!({ "message": message }).as_object().cloned()
+                }
+                _ => bail!("invalid_command: {}\n\n{}", command, USAGE),
+            };
+            match open(&repository)?.import_tree(Path::new(dir), information)? {
+                Some(anchors) => {
+                    for a in anchors {
+                        println!("{}", a);
+                    }
+                }
+                None => println!("Nothing to commit"),
+            }
+        }
+        ("shell", []) => shell(&repository)?,
+        _ => bail!("invalid_command: {}\n\n{}", command, USAGE),
+    }
+    Ok(())
+}
+
+/// Converts a path (relative or absolute) to a filesystem adapter Url, other Urls are unchanged
+fn repository_url(repository: &str) -> Result<String> {
+    if repository.contains("://") {
+        Ok(repository.to_string())
+    } else {
+        let path = std::env::current_dir()?.join(repository);
+        Ok(format!("file://{}", path.display()))
+    }
+}
+
+fn open(repository: &str) -> Result<Melda> {
+    Melda::new_from_url(&repository_url(repository)?)
+}
+
+/// Opens the repository and restores the uncommitted changes saved in the stage file
+fn open_staged(repository: &str, stage: &Path) -> Result<Melda> {
+    let replica = open(repository)?;
+    restore_stage(&replica, stage)?;
+    Ok(replica)
+}
+
+/// Restores the uncommitted changes saved in the stage file (if any)
+fn restore_stage(replica: &Melda, stage: &Path) -> Result<()> {
+    if stage.exists() {
+        let staged: Value = serde_json::from_reader(BufReader::new(File::open(stage)?))
+            .map_err(|e| anyhow!("invalid_stage_file: {}", e))?;
+        replica.replay_stage(&Some(staged))?;
+    }
+    Ok(())
+}
+
+fn save_stage(replica: &Melda, stage: &Path) -> Result<()> {
+    if let Some(staged) = replica.stage()? {
+        serde_json::to_writer(BufWriter::new(File::create(stage)?), &staged)?;
+    }
+    Ok(())
+}
+
+/// Prints the blocks reachable from the anchors, most recent first
+fn parse_number(value: &str) -> Result<u64> {
+    value
+        .parse()
+        .map_err(|_| anyhow!("invalid_value: {}", value))
+}
+
+fn print_log(replica: &Melda) -> Result<()> {
+    let mut visited = BTreeSet::new();
+    let mut queue: VecDeque<String> = replica.get_anchors().into_iter().collect();
+    while let Some(block_id) = queue.pop_front() {
+        if !visited.insert(block_id.clone()) {
+            continue;
+        }
+        if let Some(block) = replica.get_block(&block_id)? {
+            println!("block {}", block_id);
+            if let Some(parents) = &block.parents {
+                for p in parents {
+                    println!("parent {}", p);
+                    queue.push_back(p.clone());
+                }
+            }
+            if let Some(info) = &block.info {
+                for (k, v) in info {
+                    match v {
+                        Value::String(s) => println!("    {}: {}", k, s),
+                        v => println!("    {}: {}", k, v),
+                    }
+                }
+            }
+            println!();
+        }
+    }
+    Ok(())
+}
+
+const SHELL_HELP: &str = "Commands:
+  open <path-or-url>   Opens another repository
+  blocks               Lists the blocks with their parents and information
+  packs                Lists the data packs
+  delta <block>        Prints the content of a block
+  pack <pack>          Prints the content of a data pack
+  state [block]        Prints the state (at the given block)
+  conflicts            Prints the objects in conflict with their winning and conflicting revisions
+  refresh              Reloads the repository
+  help                 Prints this message
+  quit                 Exits the shell";
+
+/// Runs the interactive inspection shell
+fn shell(repository: &str) -> Result<()> {
+    let mut replica = open(repository)?;
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("melda> ");
+        std::io::stdout().flush()?;
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => {
+                println!();
+                break;
+            }
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let result = match words.as_slice() {
+            [] => Ok(()),
+            ["quit"] | ["exit"] => break,
+            ["help"] => {
+                println!("{}", SHELL_HELP);
+                Ok(())
+            }
+            ["open", repository] => open(repository).map(|r| replica = r),
+            ["refresh"] => replica.refresh(),
+            ["blocks"] => print_blocks(&replica),
+            ["packs"] => list_items(&replica, ".pack").map(|packs| {
+                for p in packs {
+                    println!("{}", p);
+                }
+            }),
+            ["delta", block] => print_item(&replica, &format!("{}.delta", block)),
+            ["pack", pack] => print_item(&replica, &format!("{}.pack", pack)),
+            ["state"] => print_state(&replica),
+            ["state", block] => Melda::new_until(
+                replica.get_adapter(),
+                &std::iter::once(block.to_string()).collect(),
+            )
+            .and_then(|r| print_state(&r)),
+            ["conflicts"] => print_conflicts(&replica),
+            _ => Err(anyhow!(
+                "invalid_command (type help for a list of commands)"
+            )),
+        };
+        if let Err(e) = result {
+            println!("error: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Lists the objects of the adapter with the given extension (without the extension)
+fn list_items(replica: &Melda, ext: &str) -> Result<Vec<String>> {
+    let adapter = replica.get_adapter();
+    let adapter = adapter.read().unwrap();
+    let mut items: Vec<String> = adapter
+        .list_objects(ext)?
+        .into_iter()
+        .map(|i| i.trim_end_matches(ext).to_string())
+        .collect();
+    items.sort();
+    Ok(items)
+}
+
+fn print_blocks(replica: &Melda) -> Result<()> {
+    let anchors = replica.get_anchors();
+    for block_id in list_items(replica, ".delta")? {
+        let marker = if anchors.contains(&block_id) {
+            " (anchor)"
+        } else {
+            ""
+        };
+        println!("{}{}", block_id, marker);
+        if let Some(block) = replica.get_block(&block_id)? {
+            for p in block.parents.iter().flatten() {
+                println!("    parent: {}", p);
+            }
+            if let Some(info) = block.info {
+                println!("    info: {}", Value::from(info));
+            }
+        } else {
+            println!("    (not loaded)");
+        }
+    }
+    Ok(())
+}
+
+/// Pretty-prints an object of the adapter if it contains JSON, otherwise prints its size
+fn print_item(replica: &Melda, key: &str) -> Result<()> {
+    let adapter = replica.get_adapter();
+    let data = adapter.read().unwrap().read_object(key, 0, 0)?;
+    match serde_json::from_slice::<Value>(&data) {
+        Ok(content) => println!("{}", serde_json::to_string_pretty(&content)?),
+        Err(_) => println!("{} bytes (binary content)", data.len()),
+    }
+    Ok(())
+}
+
+fn print_state(replica: &Melda) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(&replica.read(None)?)?);
+    Ok(())
+}
+
+fn print_conflicts(replica: &Melda) -> Result<()> {
+    for uuid in replica.in_conflict() {
+        let winner = replica.get_winner(&uuid)?;
+        println!("{}", uuid);
+        println!(
+            "    winner {}: {}",
+            winner,
+            Value::from(replica.get_value(&uuid, Some(&winner))?)
+        );
+        for revision in replica.get_conflicting(&uuid)? {
+            println!(
+                "    conflicting {}: {}",
+                revision,
+                Value::from(replica.get_value(&uuid, Some(&revision))?)
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,173 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::utils::approximate_object_size;
+use anyhow::Result;
+use serde_json::{Map, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::PathBuf;
+
+/// Cache of parsed delta blocks. Blocks are immutable (their identifier is the digest of their
+/// content), hence cached entries never need to be invalidated. The cache is optionally
+/// persisted to a file, so that reopening a repository does not require reading and verifying
+/// every block again.
+pub(crate) struct BlockCache {
+    entries: HashMap<String, Map<String, Value>>,
+    path: Option<PathBuf>,
+    dirty: bool,
+    bytes: usize,  // Estimated memory used by the entries
+    evicted: bool, // Entries have been dropped from memory (but not from the file)
+}
+
+impl BlockCache {
+    /// Creates a new cache, loading the entries persisted in the given file (if any). An
+    /// unreadable cache file is ignored (and overwritten on the next save)
+    pub fn new(path: Option<PathBuf>) -> Self {
+        let entries: HashMap<String, Map<String, Value>> = path
+            .as_ref()
+            .and_then(|p| fs::read(p).ok())
+            .and_then(|content| serde_json::from_slice(&content).ok())
+            .unwrap_or_default();
+        let bytes = entries.iter().map(|(k, v)| entry_size(k, v)).sum();
+        BlockCache {
+            entries,
+            path,
+            dirty: false,
+            bytes,
+            evicted: false,
+        }
+    }
+
+    pub fn get(&self, block_id: &str) -> Option<&Map<String, Value>> {
+        self.entries.get(block_id)
+    }
+
+    pub fn insert(&mut self, block_id: &str, raw_block: Map<String, Value>) {
+        self.bytes += entry_size(block_id, &raw_block);
+        if let Some(previous) = self.entries.insert(block_id.to_string(), raw_block) {
+            self.bytes -= entry_size(block_id, &previous);
+        }
+        self.dirty = true;
+    }
+
+    /// Drops the entries of blocks which are no longer available
+    pub fn retain(&mut self, available: &BTreeSet<String>) {
+        let count = self.entries.len();
+        let mut released = 0;
+        self.entries.retain(|k, v| {
+            let keep = available.contains(k);
+            if !keep {
+                released += entry_size(k, v);
+            }
+            keep
+        });
+        self.bytes -= released;
+        self.dirty |= count != self.entries.len();
+    }
+
+    /// Returns the estimated memory used by the entries (in bytes)
+    pub fn size(&self) -> usize {
+        self.bytes
+    }
+
+    /// Drops the entries from memory (once persisted): blocks are read again from the adapter
+    /// when needed
+    pub fn evict(&mut self) -> Result<()> {
+        self.save()?;
+        self.entries = HashMap::new();
+        self.bytes = 0;
+        self.evicted = self.path.is_some();
+        Ok(())
+    }
+
+    /// Persists the cache (if a file was given and the content changed). The file is replaced
+    /// atomically. Once entries have been evicted, the new entries are added to those of the
+    /// file (stale entries are dropped the next time the repository is opened)
+    pub fn save(&mut self) -> Result<()> {
+        if let (Some(path), true) = (&self.path, self.dirty) {
+            let content = if self.evicted {
+                let mut entries: HashMap<String, Map<String, Value>> = fs::read(path)
+                    .ok()
+                    .and_then(|content| serde_json::from_slice(&content).ok())
+                    .unwrap_or_default();
+                entries.extend(self.entries.iter().map(|(k, v)| (k.clone(), v.clone())));
+                serde_json::to_vec(&entries)?
+            } else {
+                serde_json::to_vec(&self.entries)?
+            };
+            let tmp = path.with_extension("tmp");
+            fs::write(&tmp, content)?;
+            fs::rename(&tmp, path)?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+// Estimated memory used by a cached block
+fn entry_size(block_id: &str, raw_block: &Map<String, Value>) -> usize {
+    block_id.len() + approximate_object_size(raw_block)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blockcache::BlockCache;
+    use crate::melda::Melda;
+    use serde_json::json;
+
+    #[test]
+    fn test_block_cache() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let path = dir.join("blocks.cache");
+        let mut cache = BlockCache::new(Some(path.clone()));
+        cache.insert("a", json!({"p": ["b"]}).as_object().unwrap().clone());
+        cache.insert("b", json!({}).as_object().unwrap().clone());
+        cache.save().unwrap();
+        let mut cache = BlockCache::new(Some(path.clone()));
+        assert!(cache.get("a").unwrap().contains_key("p"));
+        cache.retain(&["b".to_string()].iter().cloned().collect());
+        cache.save().unwrap();
+        let mut cache = BlockCache::new(Some(path.clone()));
+        assert!(cache.get("a").is_none() && cache.get("b").is_some());
+        // Evicted entries are kept in the file
+        assert!(cache.size() > 0);
+        cache.evict().unwrap();
+        assert!(cache.size() == 0 && cache.get("b").is_none());
+        cache.insert("c", json!({}).as_object().unwrap().clone());
+        cache.save().unwrap();
+        let cache = BlockCache::new(Some(path.clone()));
+        assert!(cache.get("b").is_some() && cache.get("c").is_some());
+        // The cache is used when reopening a repository
+        let url = format!("file://{}", dir.join("repository").display());
+        let replica = Melda::new_from_url(&url).unwrap();
+        replica
+            .update(json!({ "key" : "value" }).as_object().unwrap().clone())
+            .unwrap();
+        let block = replica.commit(None).unwrap().unwrap();
+        let cache_path = dir.join("repository.cache");
+        let open = || {
+            Melda::builder()
+                .url(&url)
+                .block_cache(&cache_path)
+                .open()
+                .unwrap()
+        };
+        assert_eq!(open().read(None).unwrap().get("key").unwrap(), "value");
+        let cache = BlockCache::new(Some(cache_path.clone()));
+        assert!(cache.get(block.first().unwrap()).is_some());
+        assert_eq!(open().read(None).unwrap().get("key").unwrap(), "value");
+    }
+}
@@ -72,6 +72,30 @@ impl Adapter for BrotliAdapter {
         self.backend.write().unwrap().write_object(&key, &buffer)
     }
 
+    /// Writes an object to the storage, replacing the existing object (if any)
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `data` - The content of the object
+    fn replace_object(&self, key: &str, data: &[u8]) -> Result<()> {
+        let key = key.to_string() + ".brotli";
+        let mut compressor = brotli::CompressorReader::new(data, 4096, 11, 22);
+        let mut buffer = vec![];
+        compressor.read_to_end(&mut buffer)?;
+        self.backend.write().unwrap().replace_object(&key, &buffer)
+    }
+
+    /// Deletes an object from the storage (deleting a missing object is not an error)
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn delete_object(&self, key: &str) -> Result<()> {
+        let key = key.to_string() + ".brotli";
+        self.backend.write().unwrap().delete_object(&key)
+    }
+
     /// Lists the keys of all objects whose key ends with ext. If ext is an empty string, all objects are returned.
     ///
     /// # Arguments
@@ -85,6 +109,32 @@ impl Adapter for BrotliAdapter {
             .map(|k| k.trim_end_matches(".brotli").to_string())
             .collect())
     }
+
+    /// Lists, in lexicographic order, at most limit keys of the objects whose key ends with ext
+    /// and follows the given key
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    /// * `after` - The key (without the extension) after which the listing starts
+    /// * `limit` - The maximum number of keys returned
+    fn list_objects_page(
+        &self,
+        ext: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        let ext = ext.to_string() + ".brotli";
+        let result = self
+            .backend
+            .read()
+            .unwrap()
+            .list_objects_page(&ext, after, limit)?;
+        Ok(result
+            .into_iter()
+            .map(|k| k.trim_end_matches(".brotli").to_string())
+            .collect())
+    }
 }
 
 mod tests {
@@ -0,0 +1,559 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::adapter::Adapter;
+use crate::commitinfo::{CommitInfo, CommitValidator};
+use crate::constants::ARRAY_CHUNK_SIZE;
+use crate::melda::{ConflictPolicy, Melda, RetentionPolicy, SquashPolicy};
+use anyhow::{anyhow, bail, Result};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// Compression applied to the data persisted by the adapter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Flate,
+    #[cfg(feature = "brotliadapter")]
+    Brotli,
+}
+
+/// Encoding of the deltas and packs written on commit. Replicas read both encodings, so
+/// replicas using different encodings can be melded
+///
+/// # Example
+/// ```
+/// use melda::{melda::Melda, builder::Encoding};
+/// use serde_json::json;
+/// let binary = Melda::builder().url("memory://").encoding(Encoding::MessagePack).open().unwrap();
+/// binary.update(json!({ "samples" : [0.5, 1.25, 2.0, 4.5] }).as_object().unwrap().clone()).unwrap();
+/// let block = binary.commit(None).unwrap().unwrap().into_iter().next().unwrap();
+/// let content = binary.get_adapter().read().unwrap().read_object(&(block + ".delta"), 0, 0).unwrap();
+/// assert!(serde_json::from_slice::<serde_json::Value>(&content).is_err());
+/// // Any replica reads binary deltas and packs
+/// let json = Melda::new_from_url("memory://").unwrap();
+/// json.meld(&binary).unwrap();
+/// json.refresh().unwrap();
+/// assert_eq!(json.read(None).unwrap(), binary.read(None).unwrap());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// Plain JSON (human readable)
+    #[default]
+    Json,
+    /// MessagePack, prefixed by a header carrying the format version
+    MessagePack,
+}
+
+/// Digest function identifying the blocks and packs of a repository. The function is recorded
+/// in the metadata of the repositories created with a function other than SHA-256 (revision
+/// digests are always computed with SHA-256)
+///
+/// # Example
+/// ```
+/// use melda::{melda::Melda, adapter::Adapter, builder::DigestAlgorithm, memoryadapter::MemoryAdapter};
+/// use std::sync::{Arc, RwLock};
+/// use serde_json::json;
+/// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+/// let adapter = Arc::new(RwLock::new(adapter));
+/// let replica = Melda::builder().adapter(adapter.clone()).digest(DigestAlgorithm::Blake3).open().unwrap();
+/// replica.update(json!({ "key" : "value" }).as_object().unwrap().clone()).unwrap();
+/// replica.commit(None).unwrap();
+/// // The algorithm is recorded in the repository metadata
+/// let reopened = Melda::builder().adapter(adapter.clone()).open().unwrap();
+/// assert_eq!(reopened.read(None).unwrap().get("key").unwrap(), "value");
+/// assert!(Melda::builder().adapter(adapter).digest(DigestAlgorithm::Sha256).open().is_err());
+/// // Replicas using different algorithms cannot be melded
+/// let other = Melda::new_from_url("memory://").unwrap();
+/// assert!(other.meld(&replica).is_err());
+/// let other = Melda::builder().url("memory://").digest(DigestAlgorithm::Blake3).open().unwrap();
+/// assert_eq!(other.meld(&replica).unwrap().len(), 2); // The block and the pack
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DigestAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+impl DigestAlgorithm {
+    /// Returns the name recorded in the repository metadata
+    pub fn name(&self) -> &'static str {
+        match self {
+            DigestAlgorithm::Sha256 => "sha256",
+            DigestAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    /// Parses the name recorded in the repository metadata
+    pub fn from_name(name: &str) -> Result<DigestAlgorithm> {
+        match name {
+            "sha256" => Ok(DigestAlgorithm::Sha256),
+            "blake3" => Ok(DigestAlgorithm::Blake3),
+            _ => bail!("unsupported_digest_algorithm: {}", name),
+        }
+    }
+}
+
+/// Order of the elements of an array changed concurrently by several replicas. All replicas
+/// sharing the data should use the same ordering, since array conflicts are resolved according
+/// to the order seen by the committing replica
+///
+/// # Example
+/// ```
+/// use melda::{melda::Melda, builder::ArrayOrdering};
+/// use serde_json::json;
+/// let open = |ordering| Melda::builder().url("memory://").ordering(ordering).open().unwrap();
+/// for ordering in [ArrayOrdering::Merged, ArrayOrdering::Winner] {
+///     let (a, b) = (open(ordering), open(ordering));
+///     a.update(json!({ "items\u{266D}" : [ { "_id" : "x" } ] }).as_object().unwrap().clone()).unwrap();
+///     a.commit(None).unwrap();
+///     b.meld(&a).unwrap();
+///     b.refresh().unwrap();
+///     a.update(json!({ "items\u{266D}" : [ { "_id" : "x" }, { "_id" : "y" } ] }).as_object().unwrap().clone()).unwrap();
+///     a.commit(None).unwrap();
+///     b.update(json!({ "items\u{266D}" : [ { "_id" : "z" }, { "_id" : "x" } ] }).as_object().unwrap().clone()).unwrap();
+///     b.commit(None).unwrap();
+///     a.meld(&b).unwrap();
+///     a.refresh().unwrap();
+///     let items = a.read(None).unwrap()["items\u{266D}"].as_array().unwrap().len();
+///     assert_eq!(items, if ordering == ArrayOrdering::Merged { 3 } else { 2 });
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArrayOrdering {
+    /// The elements inserted by the concurrent revisions are merged into the order of the
+    /// winning revision
+    #[default]
+    Merged,
+    /// Only the order of the winning revision is kept, the elements inserted by the other
+    /// revisions are dropped
+    Winner,
+}
+
+/// Configures and opens a Melda data structure
+///
+/// # Example
+/// ```
+/// use melda::{melda::Melda, builder::Compression, adapter::Adapter, memoryadapter::MemoryAdapter};
+/// use std::sync::{Arc, Mutex, RwLock};
+/// use serde_json::{Map, Value,json};
+/// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+/// let replica = Melda::builder()
+///     .adapter(Arc::new(RwLock::new(adapter)))
+///     .compression(Compression::Flate)
+///     .data_cache_capacity(64)
+///     .open()
+///     .expect("cannot_initialize_crdt");
+/// replica.update(json!({ "key" : "value" }).as_object().unwrap().clone()).unwrap();
+/// let anchors = replica.commit(None).unwrap().unwrap();
+/// let replica = Melda::builder().adapter(replica.get_adapter()).until(&anchors).open().unwrap();
+/// assert_eq!(replica.read(None).unwrap().get("key").unwrap(), "value");
+/// assert!(Melda::builder().open().is_err());
+/// let replica = Melda::builder().url("memory://").object_cache_capacity(1).open().unwrap();
+/// replica.update(json!({ "items" : [ { "_id" : "a" }, { "_id" : "b" } ] }).as_object().unwrap().clone()).unwrap();
+/// assert_eq!(replica.read(None).unwrap(), replica.read(None).unwrap());
+/// replica.update(json!({ "items" : [ { "_id" : "b" } ] }).as_object().unwrap().clone()).unwrap();
+/// assert_eq!(replica.read(None).unwrap().get("items").unwrap(), &json!([ { "_id" : "b" } ]));
+/// assert!(Melda::builder().url("memory://").object_cache_capacity(0).open().is_err());
+/// // Updates store only the changed fields
+/// let replica = Melda::builder().url("memory://").structural_deltas(true).open().unwrap();
+/// for i in 0..3 {
+///     replica.update(json!({ "text" : "x".repeat(512), "counter" : i }).as_object().unwrap().clone()).unwrap();
+///     replica.commit(None).unwrap();
+/// }
+/// let reopened = Melda::builder().adapter(replica.get_adapter()).open().unwrap();
+/// assert_eq!(reopened.read(None).unwrap().get("counter").unwrap(), 2);
+/// ```
+pub struct MeldaBuilder {
+    adapter: Option<Arc<RwLock<Box<dyn Adapter>>>>,
+    url: Option<String>,
+    compression: Compression,
+    pub(crate) encoding: Encoding,
+    pub(crate) digest: Option<DigestAlgorithm>,
+    pub(crate) structural_deltas: bool,
+    pub(crate) array_chunk_size: usize,
+    pub(crate) ordering: ArrayOrdering,
+    pub(crate) anchors: Option<BTreeSet<String>>,
+    pub(crate) array_descriptors_cache_capacity: Option<usize>,
+    pub(crate) data_cache_capacity: Option<usize>,
+    pub(crate) object_cache_capacity: Option<usize>,
+    pub(crate) block_cache: Option<PathBuf>,
+    pub(crate) location_index: Option<PathBuf>,
+    pub(crate) squash_policy: SquashPolicy,
+    pub(crate) retention_policy: RetentionPolicy,
+    pub(crate) meld_parallelism: usize,
+    pub(crate) memory_budget: Option<usize>,
+    pub(crate) trace: bool,
+    pub(crate) strict: bool,
+    pub(crate) lazy_attachments: bool,
+    pub(crate) full_text_index: bool,
+    pub(crate) conflict_policy: Option<ConflictPolicy>,
+    pub(crate) field_level_conflicts: bool,
+    pub(crate) commit_validator: Option<Arc<CommitValidator>>,
+    pub(crate) replica_id: Option<String>,
+}
+
+impl Default for MeldaBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MeldaBuilder {
+    /// Creates a new builder. Cache capacities default to the values of the
+    /// MELDA_ARRAYDESCRIPTORS_CACHE_CAP, MELDA_DATA_CACHE_CAP and MELDA_OBJECT_CACHE_CAP
+    /// environment variables (or 16), read when the data structure is opened
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, builder::MeldaBuilder};
+    /// std::env::set_var("MELDA_DATA_CACHE_CAP", "many");
+    /// assert!(MeldaBuilder::new().url("memory://").open().is_err());
+    /// // Capacities set explicitly take precedence over the environment
+    /// assert!(MeldaBuilder::new().url("memory://").data_cache_capacity(8).open().is_ok());
+    /// ```
+    pub fn new() -> Self {
+        MeldaBuilder {
+            adapter: None,
+            url: None,
+            compression: Compression::None,
+            encoding: Encoding::default(),
+            digest: None,
+            structural_deltas: false,
+            array_chunk_size: ARRAY_CHUNK_SIZE,
+            ordering: ArrayOrdering::default(),
+            anchors: None,
+            array_descriptors_cache_capacity: None,
+            data_cache_capacity: None,
+            object_cache_capacity: None,
+            block_cache: None,
+            location_index: None,
+            squash_policy: SquashPolicy::default(),
+            retention_policy: RetentionPolicy::default(),
+            meld_parallelism: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            memory_budget: None,
+            trace: false,
+            strict: false,
+            lazy_attachments: false,
+            full_text_index: false,
+            conflict_policy: None,
+            field_level_conflicts: false,
+            commit_validator: None,
+            replica_id: None,
+        }
+    }
+
+    /// Sets the backend adapter used to persist the data on commit
+    pub fn adapter(mut self, adapter: Arc<RwLock<Box<dyn Adapter>>>) -> Self {
+        self.adapter = Some(adapter);
+        self.url = None;
+        self
+    }
+
+    /// Sets the Url of the backend adapter used to persist the data on commit
+    pub fn url(mut self, url: &str) -> Self {
+        self.url = Some(url.to_string());
+        self.adapter = None;
+        self
+    }
+
+    /// Sets the compression applied on top of the adapter
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets the encoding of the deltas and packs written on commit (JSON by default)
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Sets the digest function of the repository. Existing repositories use the function
+    /// recorded in their metadata (opening fails if it differs), whereas the function is
+    /// recorded when opening an empty repository
+    pub fn digest(mut self, digest: DigestAlgorithm) -> Self {
+        self.digest = Some(digest);
+        self
+    }
+
+    /// Stores updated objects as the fields which changed with respect to their previous
+    /// revision (instead of the full object), reconstructing them on read (disabled by default)
+    pub fn structural_deltas(mut self, enabled: bool) -> Self {
+        self.structural_deltas = enabled;
+        self
+    }
+
+    /// Sets the number of elements of the chunks in which arrays are diffed on update: arrays
+    /// with more elements are diffed chunk by chunk (in linear time), producing possibly
+    /// larger deltas (2048 by default)
+    pub fn array_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.array_chunk_size = chunk_size;
+        self
+    }
+
+    /// Sets the order of the elements of arrays changed concurrently by several replicas (see
+    /// [ArrayOrdering], merged by default)
+    pub fn ordering(mut self, ordering: ArrayOrdering) -> Self {
+        self.ordering = ordering;
+        self
+    }
+
+    /// Loads the state only until the given anchors (blocks)
+    pub fn until(mut self, anchors: &BTreeSet<String>) -> Self {
+        self.anchors = Some(anchors.clone());
+        self
+    }
+
+    /// Sets the capacity of the array descriptors cache
+    pub fn array_descriptors_cache_capacity(mut self, capacity: usize) -> Self {
+        self.array_descriptors_cache_capacity = Some(capacity);
+        self
+    }
+
+    /// Sets the capacity of the objects cache of the data storage
+    pub fn data_cache_capacity(mut self, capacity: usize) -> Self {
+        self.data_cache_capacity = Some(capacity);
+        self
+    }
+
+    /// Sets the capacity of the cache of materialized objects (the values of the objects at
+    /// a given revision, as returned by read)
+    pub fn object_cache_capacity(mut self, capacity: usize) -> Self {
+        self.object_cache_capacity = Some(capacity);
+        self
+    }
+
+    /// Persists the parsed blocks to the given file, so that reopening the repository does not
+    /// require reading every block again. Blocks are always cached in memory
+    pub fn block_cache(mut self, path: &Path) -> Self {
+        self.block_cache = Some(path.to_path_buf());
+        self
+    }
+
+    /// Persists the index of the locations of the objects (see [Melda::get_location]) to the
+    /// given file, so that reopening the repository does not require walking the documents
+    /// again to rebuild it. The index is always kept in memory
+    pub fn location_index(mut self, path: &Path) -> Self {
+        self.location_index = Some(path.to_path_buf());
+        self
+    }
+
+    /// Sets the policy deciding when the blocks committed by the replica are squashed (by
+    /// default blocks are never squashed)
+    pub fn squash_policy(mut self, policy: SquashPolicy) -> Self {
+        self.squash_policy = policy;
+        self
+    }
+
+    /// Sets the policy deciding which history is squashed by [Melda::enforce_retention] (by
+    /// default the whole history is retained)
+    pub fn retention_policy(mut self, policy: RetentionPolicy) -> Self {
+        self.retention_policy = policy;
+        self
+    }
+
+    /// Sets the number of items transferred concurrently when melding (by default the number
+    /// of available CPUs, 1 transfers items sequentially)
+    pub fn meld_parallelism(mut self, parallelism: usize) -> Self {
+        self.meld_parallelism = parallelism;
+        self
+    }
+
+    /// Sets the memory budget (in bytes): when the estimated memory used by the replica exceeds
+    /// the budget, caches, cached blocks and the index of the packs are released and loaded
+    /// again on demand (by default memory is only bounded by the cache capacities). Revision
+    /// trees are never released, see [Melda::memory_usage]
+    pub fn memory_budget(mut self, bytes: usize) -> Self {
+        self.memory_budget = Some(bytes);
+        self
+    }
+
+    /// Records the staged operations and the applied blocks into a trace (see [Melda::trace]),
+    /// which reproduces the state of the replica when replayed with [Melda::replay]. Tracing
+    /// is disabled by default, as the trace holds a copy of every applied block and pack
+    pub fn trace(mut self, enabled: bool) -> Self {
+        self.trace = enabled;
+        self
+    }
+
+    /// Enables the strict mode: updates (including those applied through pointers and patches)
+    /// are rejected with a [crate::error::MeldaError::InvalidDocument] error if a flattened
+    /// array contains values which are not objects, if two objects share the same identifier
+    /// (including objects without an identifier in the same array, which would be merged) or if
+    /// an object contains a reserved field. Disabled by default
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, error::MeldaError};
+    /// use serde_json::json;
+    /// let replica = Melda::builder().url("memory://").strict(true).open().unwrap();
+    /// let document = json!({ "items\u{266D}" : [ { "_id" : "a" }, { "_id" : "a" } ] });
+    /// let error = replica.update(document.as_object().unwrap().clone()).unwrap_err();
+    /// assert!(matches!(MeldaError::of(&error), Some(MeldaError::InvalidDocument { pointer, .. }) if pointer == "/items\u{266D}/1"));
+    /// assert!(!replica.has_staging());
+    /// let document = json!({ "items\u{266D}" : [ { "_id" : "a" } ] });
+    /// replica.update(document.as_object().unwrap().clone()).unwrap();
+    /// assert!(replica.set("/items\u{266D}/1", json!("b")).is_err());
+    /// assert!(replica.set("/items\u{266D}/0/_deleted", json!(true)).is_err());
+    /// let stream = r#"{ "items\u{266D}" : [ { "_id" : "a" }, 1 ] }"#;
+    /// assert!(replica.update_from_reader(stream.as_bytes()).is_err());
+    /// // Without the strict mode duplicates are merged
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// let document = json!({ "items\u{266D}" : [ { "_id" : "a" }, { "_id" : "a" } ] });
+    /// replica.update(document.as_object().unwrap().clone()).unwrap();
+    /// ```
+    pub fn strict(mut self, enabled: bool) -> Self {
+        self.strict = enabled;
+        self
+    }
+
+    /// Does not transfer attachments when melding (see [Melda::put_attachment]): attachments
+    /// are fetched on demand with [Melda::fetch_attachments]. Disabled by default
+    pub fn lazy_attachments(mut self, enabled: bool) -> Self {
+        self.lazy_attachments = enabled;
+        self
+    }
+
+    /// Maintains a full-text index over the strings of the documents, queried with
+    /// [Melda::search]. The index is kept in memory: it is built when the replica is opened,
+    /// then updated on commit and refresh. Disabled by default
+    pub fn full_text_index(mut self, enabled: bool) -> Self {
+        self.full_text_index = enabled;
+        self
+    }
+
+    /// Resolves conflicts automatically on refresh according to the policy (see
+    /// [Melda::auto_resolution_report]). The resolutions are committed by the refresh. By
+    /// default conflicts are left to the application
+    pub fn conflict_policy(mut self, policy: ConflictPolicy) -> Self {
+        self.conflict_policy = Some(policy);
+        self
+    }
+
+    /// Detects conflicts per field rather than per object: on refresh, the concurrent
+    /// revisions of an object which changed different fields (with respect to their common
+    /// ancestor) are merged automatically, and the merge is committed. Objects remain in
+    /// conflict only if the same field diverges (see [Melda::conflicting_fields]) or if one of
+    /// the revisions deletes the object. Disabled by default
+    pub fn field_level_conflicts(mut self, enabled: bool) -> Self {
+        self.field_level_conflicts = enabled;
+        self
+    }
+
+    /// Validates the information attached to the commits (see [Melda::commit]): commits whose
+    /// information is rejected fail with a [crate::error::MeldaError::InvalidCommitInfo] error
+    /// carrying the reason returned by the validator. Commits performed automatically (such
+    /// as the resolutions committed by a refresh) are not validated
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, commitinfo::CommitInfo, error::MeldaError};
+    /// use serde_json::json;
+    /// let replica = Melda::builder()
+    ///     .url("memory://")
+    ///     .commit_validator(|info: &CommitInfo| match info.author {
+    ///         Some(_) => Ok(()),
+    ///         None => Err("missing_author".to_string()),
+    ///     })
+    ///     .open()
+    ///     .unwrap();
+    /// replica.update(json!({ "key" : "value" }).as_object().unwrap().clone()).unwrap();
+    /// let error = replica.commit(None).unwrap_err();
+    /// assert_eq!(MeldaError::of(&error), Some(&MeldaError::InvalidCommitInfo { reason: "missing_author".to_string() }));
+    /// assert!(replica.commit_with(CommitInfo::new().author("Alice")).unwrap().is_some());
+    /// ```
+    pub fn commit_validator<F>(mut self, validator: F) -> Self
+    where
+        F: Fn(&CommitInfo) -> std::result::Result<(), String> + Send + Sync + 'static,
+    {
+        self.commit_validator = Some(Arc::new(validator));
+        self
+    }
+
+    /// Sets the identifier of the replica, recorded in every block it commits (see
+    /// [Melda::replica_id]). The identifier replaces the one recorded in the replica metadata
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// let replica = Melda::builder().url("memory://").replica_id("laptop").open().unwrap();
+    /// replica.update(json!({ "key" : "value" }).as_object().unwrap().clone()).unwrap();
+    /// let block = replica.commit(None).unwrap().unwrap().into_iter().next().unwrap();
+    /// assert_eq!(replica.get_block(&block).unwrap().unwrap().replica.as_deref(), Some("laptop"));
+    /// // The identifier is recorded in the replica metadata
+    /// assert_eq!(Melda::new(replica.get_adapter()).unwrap().replica_id().unwrap(), "laptop");
+    /// ```
+    pub fn replica_id(mut self, id: &str) -> Self {
+        self.replica_id = Some(id.to_string());
+        self
+    }
+
+    /// Opens the Melda data structure with the configured options
+    pub fn open(mut self) -> Result<Melda> {
+        let adapter = match (self.adapter.take(), self.url.take()) {
+            (Some(adapter), _) => adapter,
+            (None, Some(url)) => Arc::new(RwLock::new(crate::adapter::get_adapter(&url)?)),
+            (None, None) => bail!("missing_adapter"),
+        };
+        let adapter: Arc<RwLock<Box<dyn Adapter>>> = match self.compression {
+            Compression::None => adapter,
+            Compression::Flate => Arc::new(RwLock::new(Box::new(
+                crate::flate2adapter::Flate2Adapter::new(adapter),
+            ))),
+            #[cfg(feature = "brotliadapter")]
+            Compression::Brotli => Arc::new(RwLock::new(Box::new(
+                crate::brotliadapter::BrotliAdapter::new(adapter),
+            ))),
+        };
+        for (capacity, var) in [
+            (
+                &mut self.array_descriptors_cache_capacity,
+                "MELDA_ARRAYDESCRIPTORS_CACHE_CAP",
+            ),
+            (&mut self.data_cache_capacity, "MELDA_DATA_CACHE_CAP"),
+            (&mut self.object_cache_capacity, "MELDA_OBJECT_CACHE_CAP"),
+        ] {
+            let value = match *capacity {
+                Some(value) => value,
+                None => env_capacity(var)?,
+            };
+            if value == 0 {
+                bail!("invalid_cache_capacity");
+            }
+            *capacity = Some(value);
+        }
+        if self.meld_parallelism == 0 {
+            bail!("invalid_meld_parallelism");
+        }
+        if self.array_chunk_size == 0 {
+            bail!("invalid_array_chunk_size");
+        }
+        Melda::open(adapter, &self)
+    }
+}
+
+// Reads a cache capacity from an environment variable (16 if the variable is not set)
+fn env_capacity(var: &str) -> Result<usize> {
+    match std::env::var(var) {
+        Ok(value) => value
+            .parse::<usize>()
+            .map_err(|_| anyhow!("invalid_cache_capacity: {}={}", var, value)),
+        Err(_) => Ok(16),
+    }
+}
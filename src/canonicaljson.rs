@@ -0,0 +1,181 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//! Canonical JSON serialization following the JSON Canonicalization Scheme (RFC 8785): the
+//! output has no whitespace, object keys are sorted by their UTF-16 code units, strings only
+//! escape the characters which must be escaped and numbers are written in their shortest form
+//! (as ECMAScript does). Equal values always produce the same bytes, hence replicas in the same
+//! state can be compared by hashing their canonical export.
+use serde_json::{Number, Value};
+use std::fmt::Write;
+
+/// Serializes a value as canonical JSON. Integers are written as they are (without being
+/// converted to double precision first), whereas floating point numbers with an integral value
+/// are written as integers (1.0 becomes 1).
+///
+/// # Arguments
+///
+/// * `value` - The value
+///
+/// # Example
+/// ```
+/// use melda::canonicaljson::to_canonical_string;
+/// use serde_json::json;
+/// let value = json!({ "b" : [ 1.0, 0.5, 1e21, 1e-7 ], "a" : "line\n\u{e9}" });
+/// assert_eq!(to_canonical_string(&value), r#"{"a":"line\né","b":[1,0.5,1e+21,1e-7]}"#);
+/// ```
+pub fn to_canonical_string(value: &Value) -> String {
+    let mut output = String::new();
+    write_value(&mut output, value);
+    output
+}
+
+fn write_value(output: &mut String, value: &Value) {
+    match value {
+        Value::Null => output.push_str("null"),
+        Value::Bool(b) => output.push_str(if *b { "true" } else { "false" }),
+        Value::Number(n) => write_number(output, n),
+        Value::String(s) => write_string(output, s),
+        Value::Array(a) => {
+            output.push('[');
+            for (i, v) in a.iter().enumerate() {
+                if i > 0 {
+                    output.push(',');
+                }
+                write_value(output, v);
+            }
+            output.push(']');
+        }
+        Value::Object(o) => {
+            let mut keys: Vec<&String> = o.keys().collect();
+            keys.sort_by(|a, b| a.encode_utf16().cmp(b.encode_utf16()));
+            output.push('{');
+            for (i, k) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    output.push(',');
+                }
+                write_string(output, k);
+                output.push(':');
+                write_value(output, &o[k]);
+            }
+            output.push('}');
+        }
+    }
+}
+
+fn write_string(output: &mut String, s: &str) {
+    output.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\u{8}' => output.push_str("\\b"),
+            '\u{c}' => output.push_str("\\f"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(output, "\\u{:04x}", c as u32);
+            }
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+}
+
+// Writes a number: integers as they are, other numbers as ECMAScript's Number.prototype.toString
+fn write_number(output: &mut String, n: &Number) {
+    if n.is_i64() || n.is_u64() {
+        output.push_str(&n.to_string());
+        return;
+    }
+    let f = n.as_f64().unwrap_or_default();
+    if f == 0.0 {
+        output.push('0');
+        return;
+    }
+    if f < 0.0 {
+        output.push('-');
+    }
+    // Shortest digits which read back to the same value, and the exponent of the first one
+    let scientific = format!("{:e}", f.abs());
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    let k = digits.len() as i32;
+    let n = exponent.parse::<i32>().unwrap_or_default() + 1;
+    if k <= n && n <= 21 {
+        output.push_str(&digits);
+        output.extend(std::iter::repeat_n('0', (n - k) as usize));
+    } else if 0 < n && n <= 21 {
+        output.push_str(&digits[..n as usize]);
+        output.push('.');
+        output.push_str(&digits[n as usize..]);
+    } else if -6 < n && n <= 0 {
+        output.push_str("0.");
+        output.extend(std::iter::repeat_n('0', (-n) as usize));
+        output.push_str(&digits);
+    } else {
+        output.push_str(&digits[..1]);
+        if k > 1 {
+            output.push('.');
+            output.push_str(&digits[1..]);
+        }
+        let _ = write!(
+            output,
+            "e{}{}",
+            if n > 0 { "+" } else { "-" },
+            (n - 1).abs()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::canonicaljson::to_canonical_string;
+    use serde_json::json;
+
+    #[test]
+    fn test_numbers() {
+        let value = json!([
+            0.0,
+            -0.0,
+            1.5,
+            -2.0,
+            123456789.125,
+            1e20,
+            1e21,
+            1.5e-7,
+            0.000001,
+            5e-324,
+            1.7976931348623157e308,
+            18446744073709551615u64,
+            -9007199254740993i64
+        ]);
+        assert_eq!(
+            to_canonical_string(&value),
+            "[0,0,1.5,-2,123456789.125,100000000000000000000,1e+21,1.5e-7,0.000001,5e-324,\
+             1.7976931348623157e+308,18446744073709551615,-9007199254740993]"
+        );
+    }
+
+    #[test]
+    fn test_strings_and_keys() {
+        let value = json!({ "\u{fb01}" : 1, "\u{1f600}" : 2, "b" : "\u{1}\"\\/", "a" : {} });
+        assert_eq!(
+            to_canonical_string(&value),
+            "{\"a\":{},\"b\":\"\\u0001\\\"\\\\/\",\"\u{1f600}\":2,\"\u{fb01}\":1}"
+        );
+    }
+}
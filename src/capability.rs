@@ -0,0 +1,295 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::utils::{constant_time_eq, keyed_hash, now_millis};
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// Access level granted by a capability
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    ReadOnly,
+    ReadWrite,
+}
+
+/// A capability describes what the bearer of a token is allowed to do: the access level,
+/// optionally when the capability expires and optionally the identity of the bearer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capability {
+    pub access: Access,
+    pub expires: Option<u64>, // Milliseconds since the Unix epoch
+    pub peer: Option<String>,
+}
+
+impl Capability {
+    /// Creates a capability granting read-only access to the whole document
+    pub fn read_only() -> Self {
+        Capability {
+            access: Access::ReadOnly,
+            expires: None,
+            peer: None,
+        }
+    }
+
+    /// Creates a capability granting read and write access to the whole document
+    pub fn read_write() -> Self {
+        Capability {
+            access: Access::ReadWrite,
+            expires: None,
+            peer: None,
+        }
+    }
+
+    /// Binds the capability to the identity of a peer (for example the peer whose presence is
+    /// shared through a server, see [crate::server::RepositoryServer])
+    ///
+    /// # Arguments
+    ///
+    /// * `peer` - The identity of the bearer
+    pub fn with_peer(mut self, peer: &str) -> Self {
+        self.peer = Some(peer.to_string());
+        self
+    }
+
+    /// Limits the validity of the capability: tokens granting the capability are rejected once
+    /// the given time has elapsed
+    ///
+    /// # Arguments
+    ///
+    /// * `lifetime` - The time after which the capability expires
+    ///
+    /// # Example
+    /// ```
+    /// use melda::capability::{Capability, CapabilityAuthority};
+    /// use std::time::Duration;
+    /// let authority = CapabilityAuthority::new(b"server-secret");
+    /// let token = authority.issue(&Capability::read_only().valid_for(Duration::from_secs(60))).unwrap();
+    /// assert!(authority.verify(&token).is_ok());
+    /// let token = authority.issue(&Capability::read_only().valid_for(Duration::ZERO)).unwrap();
+    /// assert!(authority.verify(&token).is_err());
+    /// ```
+    pub fn valid_for(mut self, lifetime: Duration) -> Self {
+        self.expires = Some(now_millis().unwrap_or_default() + lifetime.as_millis() as u64);
+        self
+    }
+
+    /// Returns true if the capability has expired (capabilities with an expiry are considered
+    /// expired if the current time is not available)
+    pub fn is_expired(&self) -> bool {
+        match (self.expires, now_millis()) {
+            (None, _) => false,
+            (Some(expires), Some(now)) => now >= expires,
+            (Some(_), None) => true,
+        }
+    }
+
+    /// Returns true if the capability grants write access
+    pub fn allows_write(&self) -> bool {
+        self.access == Access::ReadWrite
+    }
+
+    /// Fails with an error if write access is not granted
+    pub fn check_write(&self) -> Result<()> {
+        if !self.allows_write() {
+            bail!("write_access_denied");
+        }
+        Ok(())
+    }
+
+    fn to_json(&self) -> Value {
+        let access = match self.access {
+            Access::ReadOnly => "r",
+            Access::ReadWrite => "rw",
+        };
+        let mut v = json!({ "a": access });
+        if let Some(e) = self.expires {
+            v["e"] = Value::from(e);
+        }
+        if let Some(p) = &self.peer {
+            v["p"] = Value::from(p.clone());
+        }
+        v
+    }
+
+    fn from_json(v: &Value) -> Result<Self> {
+        let access = match v.get("a").and_then(|a| a.as_str()) {
+            Some("r") => Access::ReadOnly,
+            Some("rw") => Access::ReadWrite,
+            _ => bail!("invalid_capability_access"),
+        };
+        // Capabilities restricted to a collection are not supported: refuse them rather than
+        // granting access to the whole document
+        if v.get("c").is_some() {
+            bail!("unsupported_capability_collection");
+        }
+        let expires = match v.get("e") {
+            Some(e) => Some(
+                e.as_u64()
+                    .ok_or_else(|| anyhow!("invalid_capability_expiry"))?,
+            ),
+            None => None,
+        };
+        let peer = match v.get("p") {
+            Some(Value::String(p)) => Some(p.clone()),
+            None => None,
+            _ => bail!("invalid_capability_peer"),
+        };
+        Ok(Capability {
+            access,
+            expires,
+            peer,
+        })
+    }
+}
+
+/// Issues and verifies capability tokens. A token is the encoded capability followed by
+/// its keyed hash: tokens can therefore be shared freely, but only the holder of the
+/// secret (i.e. the server) can produce or validate them.
+pub struct CapabilityAuthority {
+    secret: Vec<u8>,
+}
+
+impl CapabilityAuthority {
+    /// Creates a new authority
+    ///
+    /// # Arguments
+    ///
+    /// * `secret` - The secret used to sign the tokens
+    pub fn new(secret: &[u8]) -> Self {
+        CapabilityAuthority {
+            secret: secret.to_vec(),
+        }
+    }
+
+    /// Derives the authority of a scope (for example a repository hosted by a server): tokens
+    /// issued by the derived authority are not valid for other scopes nor for this authority
+    ///
+    /// # Arguments
+    ///
+    /// * `scope` - The name of the scope
+    ///
+    /// # Example
+    /// ```
+    /// use melda::capability::{Capability, CapabilityAuthority};
+    /// let authority = CapabilityAuthority::new(b"server-secret");
+    /// let token = authority.derive("alice").unwrap().issue(&Capability::read_write()).unwrap();
+    /// assert!(authority.derive("alice").unwrap().verify(&token).is_ok());
+    /// assert!(authority.derive("bob").unwrap().verify(&token).is_err());
+    /// assert!(authority.verify(&token).is_err());
+    /// ```
+    pub fn derive(&self, scope: &str) -> Result<CapabilityAuthority> {
+        Ok(CapabilityAuthority {
+            secret: keyed_hash(&self.secret, scope.as_bytes())?,
+        })
+    }
+
+    /// Generates a token for the given capability
+    ///
+    /// # Arguments
+    ///
+    /// * `capability` - The capability granted by the token
+    ///
+    /// # Example
+    /// ```
+    /// use melda::capability::{Capability, CapabilityAuthority};
+    /// let authority = CapabilityAuthority::new(b"server-secret");
+    /// let token = authority.issue(&Capability::read_only()).unwrap();
+    /// let capability = authority.verify(&token).unwrap();
+    /// assert!(!capability.allows_write());
+    /// ```
+    pub fn issue(&self, capability: &Capability) -> Result<String> {
+        let payload = serde_json::to_string(&capability.to_json())?;
+        let mac = keyed_hash(&self.secret, payload.as_bytes())?;
+        Ok(hex::encode(payload) + "." + &hex::encode(mac))
+    }
+
+    /// Verifies a token and returns the capability it grants (expired tokens are rejected)
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The token presented by the client
+    pub fn verify(&self, token: &str) -> Result<Capability> {
+        let (payload, mac) = token
+            .split_once('.')
+            .ok_or_else(|| anyhow!("invalid_token"))?;
+        let payload = hex::decode(payload).map_err(|_| anyhow!("invalid_token"))?;
+        let mac = hex::decode(mac).map_err(|_| anyhow!("invalid_token"))?;
+        let expected = keyed_hash(&self.secret, &payload)?;
+        if !constant_time_eq(&expected, &mac) {
+            bail!("invalid_token_signature");
+        }
+        let capability = Capability::from_json(&serde_json::from_slice(&payload)?)?;
+        if capability.is_expired() {
+            bail!("expired_token");
+        }
+        Ok(capability)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::capability::{Access, Capability, CapabilityAuthority};
+
+    #[test]
+    fn test_issue_verify() {
+        let authority = CapabilityAuthority::new(b"secret");
+        let token = authority.issue(&Capability::read_write()).unwrap();
+        let capability = authority.verify(&token).unwrap();
+        assert!(capability.access == Access::ReadWrite);
+        assert!(capability.check_write().is_ok());
+        let token = authority.issue(&Capability::read_only()).unwrap();
+        let capability = authority.verify(&token).unwrap();
+        assert!(capability.check_write().is_err());
+        let expiring = Capability {
+            expires: Some(u64::MAX),
+            ..Capability::read_only()
+        };
+        let token = authority.issue(&expiring).unwrap();
+        assert_eq!(authority.verify(&token).unwrap(), expiring);
+        let expired = Capability {
+            expires: Some(0),
+            ..Capability::read_only()
+        };
+        assert!(expired.is_expired());
+        let token = authority.issue(&expired).unwrap();
+        assert!(authority.verify(&token).is_err());
+        let bound = Capability::read_write().with_peer("alice");
+        let token = authority.issue(&bound).unwrap();
+        assert_eq!(
+            authority.verify(&token).unwrap().peer.as_deref(),
+            Some("alice")
+        );
+    }
+
+    #[test]
+    fn test_tampered_token() {
+        let authority = CapabilityAuthority::new(b"secret");
+        let token = authority.issue(&Capability::read_only()).unwrap();
+        let forged = authority.issue(&Capability::read_write()).unwrap();
+        // Payload of the forged token with the signature of the read-only token
+        let tampered =
+            forged.split('.').next().unwrap().to_string() + "." + token.split('.').nth(1).unwrap();
+        assert!(authority.verify(&tampered).is_err());
+        assert!(authority.verify("garbage").is_err());
+        let other = CapabilityAuthority::new(b"other");
+        assert!(other.verify(&token).is_err());
+        // Signed tokens restricted to a collection are refused
+        let payload = r#"{"a":"rw","c":"items♭"}"#;
+        let mac = crate::utils::keyed_hash(b"secret", payload.as_bytes()).unwrap();
+        let scoped = hex::encode(payload) + "." + &hex::encode(mac);
+        assert!(authority.verify(&scoped).is_err());
+    }
+}
@@ -0,0 +1,207 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::constants::{
+    COMMIT_APP_VERSION_FIELD, COMMIT_AUTHOR_FIELD, COMMIT_MESSAGE_FIELD, COMMIT_TIMESTAMP_FIELD,
+};
+use crate::error::MeldaError;
+use anyhow::Result;
+use serde::{Serialize, Serializer};
+use serde_json::{Map, Value};
+
+/// Validates commit information, see [crate::builder::MeldaBuilder::commit_validator]
+pub type CommitValidator = dyn Fn(&CommitInfo) -> std::result::Result<(), String> + Send + Sync;
+
+/// Information attached to a commit. The standard fields (author, message, timestamp and
+/// version of the application) have typed accessors, any other field is kept as extra JSON.
+/// The information passed to [crate::melda::Melda::commit_with] is checked against the types of
+/// the standard fields (and against the validator of the replica, see
+/// [crate::builder::MeldaBuilder::commit_validator])
+///
+/// # Example
+/// ```
+/// use melda::{melda::Melda, commitinfo::CommitInfo};
+/// use serde_json::json;
+/// let replica = Melda::new_from_url("memory://").unwrap();
+/// replica.update(json!({ "key" : "value" }).as_object().unwrap().clone()).unwrap();
+/// let info = CommitInfo::new().author("Alice").message("First commit").app_version("1.2.0").with("ticket", json!(42));
+/// let block = replica.commit_with(info).unwrap().unwrap().into_iter().next().unwrap();
+/// let info = replica.commit_info(&block).unwrap().unwrap();
+/// assert_eq!(info.author.as_deref(), Some("Alice"));
+/// assert_eq!(info.message.as_deref(), Some("First commit"));
+/// assert_eq!(info.app_version.as_deref(), Some("1.2.0"));
+/// assert_eq!(info.extra.get("ticket"), Some(&json!(42)));
+/// assert!(info.timestamp.is_some()); // The commit time of the block
+/// // Standard fields must have the expected type
+/// replica.update(json!({ "key" : "other" }).as_object().unwrap().clone()).unwrap();
+/// assert!(replica.commit_with(CommitInfo::new().with("author", json!(1))).is_err());
+/// // ...unless committed as a map, which keeps them as other fields
+/// let block = replica.commit(Some(json!({ "author" : 1 }).as_object().unwrap().clone())).unwrap().unwrap();
+/// let info = replica.commit_info(block.iter().next().unwrap()).unwrap().unwrap();
+/// assert_eq!(info.author, None);
+/// assert_eq!(info.extra.get("author"), Some(&json!(1)));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommitInfo {
+    /// Who committed the changes
+    pub author: Option<String>,
+    /// Description of the changes
+    pub message: Option<String>,
+    /// Commit time (in milliseconds since the Unix epoch): unless given explicitly, the time
+    /// recorded by the block
+    pub timestamp: Option<u64>,
+    /// Version of the application which committed the changes
+    pub app_version: Option<String>,
+    /// Other fields
+    pub extra: Map<String, Value>,
+}
+
+impl CommitInfo {
+    /// Creates an empty commit information
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the author
+    pub fn author(mut self, author: &str) -> Self {
+        self.author = Some(author.to_string());
+        self
+    }
+
+    /// Sets the message
+    pub fn message(mut self, message: &str) -> Self {
+        self.message = Some(message.to_string());
+        self
+    }
+
+    /// Sets the version of the application
+    pub fn app_version(mut self, version: &str) -> Self {
+        self.app_version = Some(version.to_string());
+        self
+    }
+
+    /// Sets an extra field (standard fields are set through their typed accessors instead)
+    pub fn with(mut self, field: &str, value: Value) -> Self {
+        self.extra.insert(field.to_string(), value);
+        self
+    }
+
+    /// Parses the information attached to a commit, checking the types of the standard fields
+    pub fn from_map(map: Map<String, Value>) -> Result<Self> {
+        Self::parse(map, true)
+    }
+
+    /// Parses the information recorded by a block without checking the types of the standard
+    /// fields: those which do not have the expected type (for example in blocks written by
+    /// older or foreign replicas) are kept as extra fields
+    pub fn from_map_lenient(map: Map<String, Value>) -> Self {
+        Self::parse(map, false).unwrap_or_default()
+    }
+
+    // Parses the information, failing on standard fields of the wrong type if strict (otherwise
+    // they are kept as extra fields)
+    fn parse(mut map: Map<String, Value>, strict: bool) -> Result<Self> {
+        let mut mistyped = Map::new();
+        let mut string = |field: &str| match map.remove(field) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s)),
+            Some(_) if strict => Err(invalid(format!("{}_not_a_string", field))),
+            Some(v) => {
+                mistyped.insert(field.to_string(), v);
+                Ok(None)
+            }
+        };
+        let author = string(COMMIT_AUTHOR_FIELD)?;
+        let message = string(COMMIT_MESSAGE_FIELD)?;
+        let app_version = string(COMMIT_APP_VERSION_FIELD)?;
+        let timestamp = match map.remove(COMMIT_TIMESTAMP_FIELD) {
+            None | Some(Value::Null) => None,
+            Some(t) if t.is_u64() => t.as_u64(),
+            Some(_) if strict => {
+                return Err(invalid(format!(
+                    "{}_not_an_unsigned_integer",
+                    COMMIT_TIMESTAMP_FIELD
+                )))
+            }
+            Some(t) => {
+                mistyped.insert(COMMIT_TIMESTAMP_FIELD.to_string(), t);
+                None
+            }
+        };
+        map.extend(mistyped);
+        Ok(CommitInfo {
+            author,
+            message,
+            timestamp,
+            app_version,
+            extra: map,
+        })
+    }
+
+    /// Converts the information into the map attached to the commit
+    pub fn into_map(self) -> Map<String, Value> {
+        let mut map = self.extra;
+        let standard = [
+            (COMMIT_AUTHOR_FIELD, self.author.map(Value::from)),
+            (COMMIT_MESSAGE_FIELD, self.message.map(Value::from)),
+            (COMMIT_TIMESTAMP_FIELD, self.timestamp.map(Value::from)),
+            (COMMIT_APP_VERSION_FIELD, self.app_version.map(Value::from)),
+        ];
+        for (field, value) in standard {
+            if let Some(value) = value {
+                map.insert(field.to_string(), value);
+            }
+        }
+        map
+    }
+}
+
+/// Serialized as the map attached to the commit
+impl Serialize for CommitInfo {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.clone().into_map().serialize(serializer)
+    }
+}
+
+fn invalid(reason: String) -> anyhow::Error {
+    MeldaError::InvalidCommitInfo { reason }.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CommitInfo;
+    use serde_json::json;
+
+    #[test]
+    fn test_commit_info() {
+        let info = CommitInfo::new()
+            .author("Alice")
+            .message("Fix")
+            .with("ticket", json!(7));
+        let map = info.clone().into_map();
+        assert!(json!(map) == json!({ "author": "Alice", "message": "Fix", "ticket": 7 }));
+        assert!(CommitInfo::from_map(map).unwrap() == info);
+        let map = json!({ "timestamp": -1, "author": 1, "message": "Fix" })
+            .as_object()
+            .unwrap()
+            .clone();
+        assert!(CommitInfo::from_map(map.clone()).is_err());
+        // Mistyped standard fields are kept as extra fields when parsing leniently
+        let info = CommitInfo::from_map_lenient(map);
+        assert!(info.author.is_none() && info.timestamp.is_none());
+        assert_eq!(info.message.as_deref(), Some("Fix"));
+        assert!(json!(info.extra) == json!({ "timestamp": -1, "author": 1 }));
+    }
+}
@@ -34,8 +34,65 @@ pub const PACK_EXTENSION: &str = r#".pack"#;
 pub const DELTA_EXTENSION: &str = r#".delta"#;
 /// Data pack index extension
 pub const INDEX_EXTENSION: &str = r#".index"#;
+/// Extension of the records listing the data packs merged by a compaction
+pub const SUPERSEDES_EXTENSION: &str = r#".supersedes"#;
+/// Attachment extension
+pub const ATTACHMENT_EXTENSION: &str = r#".attachment"#;
+/// Block annotation extension
+pub const ANNOTATION_EXTENSION: &str = r#".annotation"#;
+/// Field of the objects referencing an attachment (holding the identifier of the attachment)
+pub const ATTACHMENT_FIELD: &str = r#"_attachment"#;
+/// Size field of the objects referencing an attachment
+pub const ATTACHMENT_SIZE_FIELD: &str = r#"size"#;
+/// Extension appended to the key of corrupt items set aside by a repair
+pub const QUARANTINE_EXTENSION: &str = r#".quarantine"#;
+/// Extension of the repository metadata object
+pub const METADATA_EXTENSION: &str = r#".metadata"#;
+/// Name of the repository metadata object
+pub const REPOSITORY_METADATA: &str = r#"repository"#;
+/// Name of the metadata object holding the identifier of the replica (see
+/// [crate::melda::Melda::replica_id])
+pub const REPLICA_METADATA: &str = r#"replica"#;
+/// Replica identifier field (inside the replica metadata)
+pub const REPLICA_ID_FIELD: &str = r#"id"#;
+/// Name of the metadata object holding the schema of the documents (see
+/// [crate::melda::Melda::set_schema])
+pub const SCHEMA_METADATA: &str = r#"schema"#;
+/// Digest algorithm field (inside the repository metadata)
+pub const DIGEST_FIELD: &str = r#"digest"#;
+/// Format version field (inside the repository metadata)
+pub const FORMAT_VERSION_FIELD: &str = r#"version"#;
+/// Version of the repository format (repositories without a version have version 1)
+pub const FORMAT_VERSION: u64 = 1;
+/// Header of the deltas and packs written with a binary encoding
+pub const BINARY_HEADER: &[u8] = b"\0MELDA";
+/// Version of the binary format (written after the header)
+pub const BINARY_FORMAT_VERSION: u8 = 1;
+/// Default number of elements of the chunks in which huge arrays are diffed
+pub const ARRAY_CHUNK_SIZE: usize = 2048;
+/// Minimum number of objects for reconstructing documents concurrently
+pub const PARALLEL_UNFLATTEN_THRESHOLD: usize = 1024;
+/// Field holding the winning value of a field in conflict (see
+/// [crate::melda::Melda::read_with_conflicts])
+pub const CONFLICT_WINNER_FIELD: &str = r#"~winner"#;
+/// Field holding the values of the conflicting revisions of a field in conflict (see
+/// [crate::melda::Melda::read_with_conflicts])
+pub const CONFLICT_ALTERNATIVES_FIELD: &str = r#"~conflicts"#;
+/// Field of the commit information listing the conflicts resolved by the block (see
+/// [crate::melda::Melda::resolutions])
+pub const RESOLUTIONS_FIELD: &str = r#"resolutions"#;
+/// Author field of the commit information (see [crate::commitinfo::CommitInfo])
+pub const COMMIT_AUTHOR_FIELD: &str = r#"author"#;
+/// Message field of the commit information
+pub const COMMIT_MESSAGE_FIELD: &str = r#"message"#;
+/// Timestamp field of the commit information
+pub const COMMIT_TIMESTAMP_FIELD: &str = r#"timestamp"#;
+/// Application version field of the commit information
+pub const COMMIT_APP_VERSION_FIELD: &str = r#"app_version"#;
 /// Default root object identifier
 pub const ROOT_ID: &str = "\u{221A}";
+/// Prefix of the root object identifiers of the named documents of a workspace
+pub const DOCUMENT_PREFIX: &str = "\u{00A7}";
 /// Parents field key (inside delta blocks)
 pub const PARENTS_FIELD: &str = r#"p"#;
 /// Changesets field key (inside delta blocks and stages)
@@ -46,13 +103,55 @@ pub const OBJECTS_FIELD: &str = r#"o"#;
 pub const INFORMATION_FIELD: &str = r#"i"#;
 /// Pack field inside delta blocks
 pub const PACK_FIELD: &str = r#"k"#;
+/// Squashed blocks field key (inside delta blocks)
+pub const SQUASHED_FIELD: &str = r#"s"#;
+/// Grafted blocks field key (inside delta blocks)
+pub const GRAFT_FIELD: &str = r#"g"#;
+/// Commit time field key (inside delta blocks and annotations, in milliseconds since the Unix
+/// epoch)
+pub const TIMESTAMP_FIELD: &str = r#"t"#;
+/// Replica field key (inside delta blocks, holding the identifier of the committing replica)
+pub const REPLICA_FIELD: &str = r#"r"#;
+/// Annotated block field key (inside annotations)
+pub const ANNOTATION_BLOCK_FIELD: &str = r#"b"#;
+/// Annotation key field key (inside annotations)
+pub const ANNOTATION_KEY_FIELD: &str = r#"k"#;
+/// Annotation value field key (inside annotations, missing if the annotation is removed)
+pub const ANNOTATION_VALUE_FIELD: &str = r#"v"#;
+/// Superseded annotations field key (inside annotations)
+pub const ANNOTATION_SUPERSEDES_FIELD: &str = r#"s"#;
+/// Identifier marking the values storing a structural delta (objects never contain an
+/// identifier field)
+pub const STRUCTURAL_DELTA_ID: &str = r#"delta"#;
+/// Base revision digest field (inside structural deltas)
+pub const DELTA_BASE_FIELD: &str = r#"b"#;
+/// Chain length field (inside structural deltas)
+pub const DELTA_DEPTH_FIELD: &str = r#"n"#;
+/// Changed fields field (inside structural deltas)
+pub const DELTA_SET_FIELD: &str = r#"s"#;
+/// Removed fields field (inside structural deltas)
+pub const DELTA_REMOVED_FIELD: &str = r#"r"#;
+/// Maximum number of structural deltas applied to reconstruct a value
+pub const MAX_DELTA_CHAIN: u64 = 16;
 /// Hash field (inside objects)
 pub const HASH_FIELD: &str = r#"#"#;
 /// Expected identifier field (inside objects)
 pub const ID_FIELD: &str = r#"_id"#;
+/// Fields marking deleted and resolved revisions (reserved inside objects)
+pub const RESERVED_FIELDS: &[&str] = &["_deleted", "_resolved"];
 /// Hash for empty objects
 pub const EMPTY_HASH: &str = r#"e"#;
 /// Hash for deleted objects
 pub const DELETED_HASH: &str = r#"d"#;
 /// Hash for resolved revisions
 pub const RESOLVED_HASH: &str = r#"r"#;
+/// Section of a patch file holding the changes as a JSON Patch
+pub const PATCH_DIFF_SECTION: &str = "--- diff";
+/// Section of a patch file holding the block
+pub const PATCH_BLOCK_SECTION: &str = "--- block";
+/// Section of a patch file holding the values referenced by the block
+pub const PATCH_VALUES_SECTION: &str = "--- values";
+/// Encoding of a block written as JSON (in a patch file)
+pub const PATCH_ENCODING_JSON: &str = "json";
+/// Encoding of a block written as MessagePack (in a patch file)
+pub const PATCH_ENCODING_MESSAGEPACK: &str = "messagepack";
@@ -14,58 +14,127 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 use crate::adapter::Adapter;
-use crate::constants::{HASH_FIELD, INDEX_EXTENSION, PACK_EXTENSION};
+use crate::builder::{DigestAlgorithm, Encoding};
+use crate::constants::{
+    DELTA_BASE_FIELD, DELTA_DEPTH_FIELD, DELTA_REMOVED_FIELD, DELTA_SET_FIELD, HASH_FIELD,
+    ID_FIELD, INDEX_EXTENSION, MAX_DELTA_CHAIN, PACK_EXTENSION, STRUCTURAL_DELTA_ID,
+    SUPERSEDES_EXTENSION,
+};
+use crate::error::MeldaError;
 use crate::revision::Revision;
-use crate::utils::digest_bytes;
+use crate::utils::{
+    approximate_object_size, binary_header, binary_header_len, decode_pack_value, digest_bytes,
+    digest_bytes_with, digest_object, digest_string,
+};
 use anyhow::{anyhow, bail, Result};
 use lru::LruCache;
 use serde_json::json;
 use serde_json::Map;
 use serde_json::Value;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::collections::{HashMap, HashSet};
 use std::num::NonZeroUsize;
 use std::sync::{Arc, Mutex, RwLock};
 
+type ObjectIndex = HashMap<String, (String, usize, usize)>;
+
+/// Stores the values referenced by revisions. Packs are indexed lazily, the first time one of
+/// their values is requested
 pub struct DataStorage {
     adapter: Arc<RwLock<Box<dyn Adapter>>>,
-    stage: HashMap<String, Value>,
-    committed_objects: HashMap<String, (String, usize, usize)>,
+    stage: BTreeMap<String, Value>, // Sorted, so that packs do not depend on the order of the writes
+    committed_objects: Mutex<ObjectIndex>,
+    pending_packs: Mutex<Vec<(String, bool)>>, // Packs not indexed yet (and whether they have an index file)
     loaded_packs: BTreeSet<String>,
+    superseded_packs: BTreeSet<String>,
     cache: Mutex<LruCache<String, Map<String, Value>>>,
+    encoding: Encoding,
+    digest: DigestAlgorithm,
+    structural_deltas: bool,
 }
 
 impl DataStorage {
-    /// Constructs a new Data storage based on the provided adapter
-    pub fn new(adapter: Arc<RwLock<Box<dyn Adapter>>>) -> DataStorage {
-        let cache_size = std::env::var("MELDA_DATA_CACHE_CAP")
-            .unwrap_or_else(|_| "16".to_string())
-            .parse::<u32>()
-            .unwrap() as usize;
+    /// Constructs a new Data storage based on the provided adapter, with the given cache capacity
+    pub fn new(adapter: Arc<RwLock<Box<dyn Adapter>>>, cache_size: usize) -> DataStorage {
         DataStorage {
             adapter,
-            stage: HashMap::<String, Value>::new(),
-            committed_objects: HashMap::<String, (String, usize, usize)>::new(),
+            stage: BTreeMap::<String, Value>::new(),
+            committed_objects: Mutex::new(ObjectIndex::new()),
+            pending_packs: Mutex::new(vec![]),
             loaded_packs: BTreeSet::new(),
+            superseded_packs: BTreeSet::new(),
             cache: Mutex::new(LruCache::<String, Map<String, Value>>::new(
                 NonZeroUsize::new(cache_size).unwrap(),
             )),
+            encoding: Encoding::Json,
+            digest: DigestAlgorithm::Sha256,
+            structural_deltas: false,
         }
     }
 
-    /// Loads a pack file (and rebuilds the index)
-    fn load_pack(&mut self, pack: &str) -> Result<()> {
+    /// Enables storing updated objects as structural deltas against their previous revision
+    pub fn set_structural_deltas(&mut self, enabled: bool) {
+        self.structural_deltas = enabled;
+    }
+
+    /// Sets the digest function identifying the packs
+    pub fn set_digest_algorithm(&mut self, digest: DigestAlgorithm) {
+        self.digest = digest;
+    }
+
+    /// Sets the encoding of the packs written by the storage
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+
+    /// Indexes a pack file (verifying its digest)
+    fn load_pack(&self, pack: &str, objects: &mut ObjectIndex) -> Result<()> {
         let object = pack.to_string() + PACK_EXTENSION;
         let data = self
             .adapter
             .read()
             .unwrap()
-            .read_object(object.as_str(), 0, 0)?;
-        self.load_pack_data(pack, &data)
+            .read_object_bytes(object.as_str(), 0, 0)?;
+        if digest_bytes_with(self.digest, &data) != pack {
+            bail!(MeldaError::CorruptPack {
+                digest: pack.to_string()
+            });
+        }
+        match binary_header_len(&data).map_err(|_| MeldaError::CorruptPack {
+            digest: pack.to_string(),
+        })? {
+            Some(start) => Self::load_binary_pack_data(pack, &data, start, objects),
+            None => {
+                Self::load_pack_data(pack, &data, objects);
+                Ok(())
+            }
+        }
+    }
+
+    /// Indexes a binary pack, decoding the values sequentially starting at the given offset.
+    /// Values are identified by the digest of their JSON serialization, as for JSON packs
+    fn load_binary_pack_data(
+        name: &str,
+        data: &[u8],
+        start: usize,
+        objects: &mut ObjectIndex,
+    ) -> Result<()> {
+        let mut reader = &data[start..];
+        while !reader.is_empty() {
+            let offset = data.len() - reader.len();
+            let value: Value =
+                rmp_serde::from_read(&mut reader).map_err(|_| MeldaError::CorruptPack {
+                    digest: name.to_string(),
+                })?;
+            let count = data.len() - reader.len() - offset;
+            let digest = digest_string(&value.to_string());
+            objects.insert(digest, (name.to_string(), offset, count));
+        }
+        Ok(())
     }
 
     /// Data is the raw string (we need to compute the offset and length of the object)
-    fn load_pack_data(&mut self, name: &str, data: &[u8]) -> Result<()> {
+    fn load_pack_data(name: &str, data: &[u8], objects: &mut ObjectIndex) {
         let mut flag = 0;
         let mut obj_start = 0;
         for (offset, c) in data.iter().enumerate() {
@@ -79,66 +148,374 @@ impl DataStorage {
                 if flag == 0 {
                     let digest = digest_bytes(&data[obj_start..offset + 1]);
                     let count = offset + 1 - obj_start;
-                    self.committed_objects
-                        .insert(digest, (name.to_string(), obj_start, count));
+                    objects.insert(digest, (name.to_string(), obj_start, count));
                 };
             }
         }
-        self.loaded_packs.insert(name.to_string());
-        Ok(())
     }
 
     /// Loads an index object
-    fn load_index_object(&mut self, index: &str, obj: &Map<String, Value>) -> Result<()> {
+    fn load_index_object(index: &str, obj: &Map<String, Value>, objects: &mut ObjectIndex) {
         for (k, v) in obj {
             let d = v.as_array().unwrap();
             let offset = d[0].as_i64().unwrap() as usize;
             let count = d[1].as_i64().unwrap() as usize;
-            self.committed_objects
-                .insert(k.clone(), (index.to_string(), offset, count));
+            objects.insert(k.clone(), (index.to_string(), offset, count));
         }
-        self.loaded_packs.insert(index.to_string());
-        Ok(())
     }
 
     /// Loads an index file
-    fn load_index(&mut self, index: &str) -> Result<()> {
+    fn load_index(&self, index: &str, objects: &mut ObjectIndex) -> Result<()> {
         let object = index.to_string() + INDEX_EXTENSION;
         let data = self
             .adapter
             .read()
             .unwrap()
             .read_object(object.as_str(), 0, 0)?;
-        let json = std::str::from_utf8(&data)?;
-        let json: Value = serde_json::from_str(json)?;
+        let corrupt = || MeldaError::CorruptPack {
+            digest: index.to_string(),
+        };
+        let json: Value = std::str::from_utf8(&data)
+            .ok()
+            .and_then(|json| serde_json::from_str(json).ok())
+            .ok_or_else(corrupt)?;
         if json.is_object() {
-            self.load_index_object(index, json.as_object().unwrap())
+            Self::load_index_object(index, json.as_object().unwrap(), objects);
+            Ok(())
         } else {
-            bail!("index_not_an_object")
+            bail!(corrupt())
+        }
+    }
+
+    /// Indexes the pending packs until the value with the given digest is found (all pending
+    /// packs are indexed if no digest is given). Packs which cannot be indexed remain pending
+    fn locate(&self, digest: Option<&str>) -> Result<Option<(String, usize, usize)>> {
+        let mut objects = self.committed_objects.lock().unwrap();
+        let mut pending = self.pending_packs.lock().unwrap();
+        let mut failed = vec![];
+        let mut error = None;
+        while digest.is_none_or(|digest| !objects.contains_key(digest)) {
+            let (pack, has_index) = match pending.pop() {
+                Some(pending) => pending,
+                None => break,
+            };
+            let result = if has_index {
+                self.load_index(&pack, &mut objects)
+            } else {
+                self.load_pack(&pack, &mut objects)
+            };
+            if let Err(e) = result {
+                failed.push((pack, has_index));
+                error.get_or_insert(e);
+            }
+        }
+        pending.extend(failed);
+        match digest.and_then(|digest| objects.get(digest)) {
+            Some(location) => Ok(Some(location.clone())),
+            None => match error {
+                Some(e) => Err(e),
+                None => Ok(None),
+            },
+        }
+    }
+
+    /// Loads the records of the packs merged by compactions: blocks referencing those packs
+    /// remain valid, since their values have been moved to another pack
+    fn load_superseded(&mut self) -> Result<()> {
+        let records = self
+            .adapter
+            .read()
+            .unwrap()
+            .list_objects(SUPERSEDES_EXTENSION)?;
+        for record in records {
+            let object = record.clone() + SUPERSEDES_EXTENSION;
+            let data = self.read_raw_item(&object, 0, 0)?;
+            let packs: Vec<String> = serde_json::from_slice(&data)
+                .map_err(|_| MeldaError::CorruptPack { digest: record })?;
+            for p in packs {
+                self.loaded_packs.insert(p.clone());
+                self.superseded_packs.insert(p);
+            }
+        }
+        Ok(())
+    }
+
+    /// Records that the values of the given packs are held by other packs (as after a
+    /// compaction), so that blocks referencing them are valid although the packs are missing
+    pub fn supersede(&mut self, packs: &[String]) -> Result<()> {
+        let record = serde_json::to_vec(packs)?;
+        let record_id = digest_bytes_with(self.digest, &record);
+        self.write_raw_item(&(record_id + SUPERSEDES_EXTENSION), &record)?;
+        for p in packs {
+            self.loaded_packs.insert(p.clone());
+            self.superseded_packs.insert(p.clone());
+        }
+        Ok(())
+    }
+
+    /// Returns true if the pack has been merged into another pack by a compaction
+    pub fn is_superseded(&self, pack: &str) -> bool {
+        self.superseded_packs.contains(pack)
+    }
+
+    /// Merges all packs into a single pack containing only the values with the given digests,
+    /// then deletes the merged packs. A record listing the merged packs is written before
+    /// deleting them, so that a failure never leaves blocks referencing unavailable values.
+    /// Returns the new pack (None if no value is retained), the deleted packs and the number
+    /// of dropped values. The storage must be reloaded afterwards
+    pub fn compact(
+        &mut self,
+        retained: &HashSet<String>,
+    ) -> Result<(Option<String>, Vec<String>, usize)> {
+        if !self.stage.is_empty() {
+            bail!(MeldaError::StageNotEmpty);
+        }
+        let packs = self.list_raw_items(PACK_EXTENSION)?;
+        let records = self.list_raw_items(SUPERSEDES_EXTENSION)?;
+        self.locate(None)?;
+        let committed = self.committed_objects.lock().unwrap().clone();
+        let mut values = BTreeMap::new();
+        for digest in retained {
+            if committed.contains_key(digest) {
+                // Structural deltas are reconstructed if their base is dropped
+                let value = self.read_raw_value(digest)?;
+                let value = match structural_delta_base(&value) {
+                    Some(base) if !retained.contains(&base) => self.read_value(digest)?,
+                    _ => value,
+                };
+                values.insert(digest.clone(), value);
+            }
+        }
+        let dropped = committed.len() - values.len();
+        if packs.len() <= 1 && dropped == 0 {
+            return Ok((None, vec![], 0));
+        }
+        self.stage = values;
+        let pack = self.pack()?;
+        let mut superseded = self.superseded_packs.clone();
+        superseded.extend(packs.iter().cloned());
+        if let Some(pack) = &pack {
+            superseded.remove(pack);
+        }
+        let record = serde_json::to_vec(&superseded)?;
+        let record_id = digest_bytes_with(self.digest, &record);
+        self.write_raw_item(&(record_id.clone() + SUPERSEDES_EXTENSION), &record)?;
+        let removed: Vec<String> = packs
+            .into_iter()
+            .filter(|p| Some(p) != pack.as_ref())
+            .collect();
+        let adapter = self.adapter.write().unwrap();
+        for p in &removed {
+            adapter.delete_object(&(p.clone() + PACK_EXTENSION))?;
+            adapter.delete_object(&(p.clone() + INDEX_EXTENSION))?;
+        }
+        for r in records.iter().filter(|r| **r != record_id) {
+            adapter.delete_object(&(r.clone() + SUPERSEDES_EXTENSION))?;
+        }
+        Ok((pack, removed, dropped))
+    }
+
+    /// Deletes the packs which are neither referenced by a block nor contain a retained value
+    /// (for example packs written by commits which failed before writing their block), along
+    /// with their indexes and the indexes of missing packs. Returns the deleted packs and the
+    /// number of reclaimed bytes. The storage must be reloaded afterwards
+    pub fn collect_garbage(
+        &mut self,
+        referenced_packs: &HashSet<String>,
+        retained: &HashSet<String>,
+    ) -> Result<(Vec<String>, usize)> {
+        if !self.stage.is_empty() {
+            bail!(MeldaError::StageNotEmpty);
+        }
+        let packs = self.list_raw_items(PACK_EXTENSION)?;
+        let indexes = self.list_raw_items(INDEX_EXTENSION)?;
+        // The bases of structural deltas are earlier revisions, which are retained as well
+        let holding = self.packs_holding(retained)?;
+        let adapter = self.adapter.write().unwrap();
+        let mut reclaimed = 0;
+        let mut delete = |key: String| -> Result<()> {
+            if let Ok(data) = adapter.read_object(&key, 0, 0) {
+                reclaimed += data.len();
+                adapter.delete_object(&key)?;
+            }
+            Ok(())
+        };
+        let mut removed = vec![];
+        for p in &packs {
+            if !referenced_packs.contains(p) && !holding.contains(p) {
+                delete(p.clone() + PACK_EXTENSION)?;
+                delete(p.clone() + INDEX_EXTENSION)?;
+                removed.push(p.clone());
+            }
+        }
+        for i in indexes.iter().filter(|i| !packs.contains(i)) {
+            delete(i.clone() + INDEX_EXTENSION)?;
+        }
+        Ok((removed, reclaimed))
+    }
+
+    /// Returns the packs holding the values with the given digests (all packs are indexed)
+    pub fn packs_holding(&self, digests: &HashSet<String>) -> Result<HashSet<String>> {
+        self.locate(None)?;
+        let objects = self.committed_objects.lock().unwrap();
+        Ok(digests
+            .iter()
+            .filter_map(|digest| objects.get(digest))
+            .map(|(pack, _, _)| pack.clone())
+            .collect())
+    }
+
+    /// Checks that the index of a pack is consistent with the pack: every entry must locate a
+    /// value whose digest matches the entry (structural deltas are only decoded). Returns the
+    /// reason of the first inconsistency found, if any
+    pub fn verify_index(&self, pack: &str) -> Result<Option<String>> {
+        let adapter = self.adapter.read().unwrap();
+        let index = adapter.read_object(&(pack.to_string() + INDEX_EXTENSION), 0, 0)?;
+        let data = adapter.read_object_bytes(&(pack.to_string() + PACK_EXTENSION), 0, 0)?;
+        drop(adapter);
+        let index: Map<String, Value> = match serde_json::from_slice(&index) {
+            Ok(index) => index,
+            Err(_) => return Ok(Some("malformed_index".to_string())),
+        };
+        for (digest, entry) in &index {
+            let location = entry.as_array().and_then(|e| match e.as_slice() {
+                [offset, count] => Some((offset.as_u64()? as usize, count.as_u64()? as usize)),
+                _ => None,
+            });
+            let (offset, count) = match location {
+                Some(location) => location,
+                None => return Ok(Some(format!("malformed_index_entry: {}", digest))),
+            };
+            let value = match data.get(offset..offset + count).map(decode_pack_value) {
+                Some(Ok(value)) => value,
+                _ => return Ok(Some(format!("invalid_index_entry: {}", digest))),
+            };
+            if structural_delta_base(&value).is_none()
+                && digest_string(&value.to_string()) != *digest
+            {
+                return Ok(Some(format!("mismatching_index_entry: {}", digest)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Rebuilds the index of a pack from its content. Values are identified by their digest
+    /// (structural deltas by the digest of the value they reconstruct)
+    pub fn rebuild_index(&self, pack: &str) -> Result<()> {
+        let mut located = ObjectIndex::new();
+        self.load_pack(pack, &mut located)?;
+        let data = self.adapter.read().unwrap().read_object_bytes(
+            &(pack.to_string() + PACK_EXTENSION),
+            0,
+            0,
+        )?;
+        let mut index = Map::new();
+        let mut objects = HashMap::new(); // Values of the pack (and bases), by digest
+        let mut deltas = vec![];
+        for (digest, (_, offset, count)) in located {
+            let value = decode_pack_value(&data[offset..offset + count])?;
+            match structural_delta_base(&value) {
+                Some(base) => deltas.push((base, value, offset, count)),
+                None => {
+                    index.insert(digest.clone(), json!([offset, count]));
+                    if let Value::Object(object) = value {
+                        objects.insert(digest, object);
+                    }
+                }
+            }
+        }
+        // The base of a delta is either in the pack or in another pack
+        while !deltas.is_empty() {
+            let pending = deltas.len();
+            let mut remaining = vec![];
+            for (base, delta, offset, count) in deltas {
+                match objects.get(&base) {
+                    Some(object) => {
+                        let mut object = object.clone();
+                        apply_structural_delta(&mut object, &delta);
+                        let digest = digest_object(&object)?;
+                        index.insert(digest.clone(), json!([offset, count]));
+                        objects.insert(digest, object);
+                    }
+                    None => remaining.push((base, delta, offset, count)),
+                }
+            }
+            if remaining.len() == pending {
+                let base = remaining[0].0.clone();
+                match self.read_value(&base)? {
+                    Value::Object(object) => objects.insert(base, object),
+                    _ => bail!("expecting_an_object"),
+                };
+            }
+            deltas = remaining;
         }
+        let key = pack.to_string() + INDEX_EXTENSION;
+        let adapter = self.adapter.read().unwrap();
+        let _ = adapter.delete_object(&key);
+        adapter.write_object(&key, serde_json::to_string(&index)?.as_bytes())
     }
 
-    /// Reloads the storage
+    /// Reloads the storage (packs are only listed, their content is indexed on demand)
     /// TODO: This can be partially replaced by a call to refresh
     pub fn reload(&mut self) -> Result<Vec<String>> {
         if !self.stage.is_empty() {
             bail!("non_empty_data_stage");
         }
         self.loaded_packs.clear();
-        self.committed_objects.clear();
-        let pack_list = self.adapter.read().unwrap().list_objects(PACK_EXTENSION)?;
-        let index_list = self.adapter.read().unwrap().list_objects(INDEX_EXTENSION)?;
-        let index_set = index_list.into_iter().collect::<HashSet<_>>();
-        if !pack_list.is_empty() {
-            for i in &pack_list {
-                if index_set.contains(i) {
-                    self.load_index(i)?;
-                } else {
-                    self.load_pack(i)?;
-                }
-            }
+        self.superseded_packs.clear();
+        self.committed_objects.lock().unwrap().clear();
+        self.pending_packs.lock().unwrap().clear();
+        self.refresh()
+    }
+
+    /// Returns the estimated memory (in bytes) used by the cache of values and by the index of
+    /// the committed values
+    pub fn memory_usage(&self) -> (usize, usize) {
+        let cache = self
+            .cache
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(k, v)| k.len() + approximate_object_size(v))
+            .sum();
+        let entry = std::mem::size_of::<(String, (String, usize, usize))>();
+        let index = self
+            .committed_objects
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(digest, (pack, _, _))| entry + digest.len() + pack.len())
+            .sum();
+        (cache, index)
+    }
+
+    /// Drops the cached values
+    pub fn clear_cache(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+
+    /// Drops the index of the committed values: the packs are indexed again on demand
+    pub fn unload_index(&self) -> Result<()> {
+        let indexes: HashSet<String> = self
+            .adapter
+            .read()
+            .unwrap()
+            .list_objects(INDEX_EXTENSION)?
+            .into_iter()
+            .collect();
+        let mut objects = self.committed_objects.lock().unwrap();
+        let mut pending = self.pending_packs.lock().unwrap();
+        let mut indexed: BTreeSet<String> =
+            objects.values().map(|(pack, _, _)| pack.clone()).collect();
+        for (pack, _) in pending.iter() {
+            indexed.remove(pack);
         }
-        Ok(pack_list)
+        for pack in indexed {
+            let has_index = indexes.contains(&pack);
+            pending.push((pack, has_index));
+        }
+        *objects = ObjectIndex::new();
+        Ok(())
     }
 
     pub fn get_loaded_packs(&self) -> &BTreeSet<String> {
@@ -146,20 +523,15 @@ impl DataStorage {
     }
 
     pub fn refresh(&mut self) -> Result<Vec<String>> {
+        self.load_superseded()?;
         let pack_list = self.adapter.read().unwrap().list_objects(PACK_EXTENSION)?;
         let index_list = self.adapter.read().unwrap().list_objects(INDEX_EXTENSION)?;
         let index_set = index_list.into_iter().collect::<HashSet<_>>();
         let mut new_packs = vec![];
-        if !pack_list.is_empty() {
-            for i in &pack_list {
-                if self.loaded_packs.contains(i) {
-                    continue;
-                }
-                if index_set.contains(i) {
-                    self.load_index(i)?;
-                } else {
-                    self.load_pack(i)?;
-                }
+        let mut pending = self.pending_packs.lock().unwrap();
+        for i in &pack_list {
+            if self.loaded_packs.insert(i.clone()) {
+                pending.push((i.clone(), index_set.contains(i)));
                 new_packs.push(i.clone());
             }
         }
@@ -171,12 +543,20 @@ impl DataStorage {
         Ok(())
     }
 
+    /// Returns true if the pack is available (its content is not read)
+    pub fn is_available_pack(&self, pack: &str) -> bool {
+        self.loaded_packs.contains(pack)
+    }
+
     /// Returns true if the pack is readable and valid (digest matches)
     pub fn is_readable_and_valid_pack(&self, pack: &str) -> Result<bool> {
+        if self.is_superseded(pack) {
+            return Ok(true);
+        }
         let pack_name = pack.to_string() + PACK_EXTENSION;
         match self.adapter.read().unwrap().read_object(&pack_name, 0, 0) {
             Ok(data) => {
-                let d = digest_bytes(data.as_slice());
+                let d = digest_bytes_with(self.digest, data.as_slice());
                 Ok(d.eq(pack))
             }
             Err(e) => Err(e),
@@ -198,6 +578,96 @@ impl DataStorage {
         }
     }
 
+    /// Writes an object updating the base revision. If structural deltas are enabled, only the
+    /// fields which differ from the base are stored (unless the delta is not smaller than the
+    /// object, or the chain of deltas to apply on read would be too long)
+    pub fn write_updated_object(
+        &mut self,
+        rev: &Revision,
+        obj: Map<String, Value>,
+        base: &Revision,
+    ) -> Result<()> {
+        let stored =
+            |r: &Revision| !(r.is_resolved() || r.is_deleted() || r.is_empty() || r.is_charcode());
+        if self.structural_deltas && stored(rev) && stored(base) {
+            if let Some(delta) = self.make_structural_delta(&obj, base.digest()) {
+                self.write_raw_value(rev.digest(), delta)?;
+                self.cache
+                    .lock()
+                    .unwrap()
+                    .put(rev.digest().to_string(), obj);
+                return Ok(());
+            }
+        }
+        self.write_object(rev, obj)
+    }
+
+    /// Computes the structural delta of an object against the value with the given digest
+    fn make_structural_delta(&self, obj: &Map<String, Value>, base: &str) -> Option<Value> {
+        let raw = self.read_raw_value(base).ok()?;
+        let depth = match structural_delta_base(&raw) {
+            Some(_) => {
+                raw.get(DELTA_DEPTH_FIELD)
+                    .and_then(Value::as_u64)
+                    .unwrap_or(MAX_DELTA_CHAIN)
+                    + 1
+            }
+            None => 1,
+        };
+        if depth > MAX_DELTA_CHAIN {
+            return None;
+        }
+        let base_obj = self.read_value(base).ok()?;
+        let base_obj = base_obj.as_object()?;
+        let set: Map<String, Value> = obj
+            .iter()
+            .filter(|(k, v)| base_obj.get(*k) != Some(*v))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        let removed: Vec<&String> = base_obj.keys().filter(|k| !obj.contains_key(*k)).collect();
+        let delta = json!({
+            ID_FIELD: STRUCTURAL_DELTA_ID,
+            DELTA_BASE_FIELD: base,
+            DELTA_DEPTH_FIELD: depth,
+            DELTA_SET_FIELD: set,
+            DELTA_REMOVED_FIELD: removed,
+        });
+        if delta.to_string().len() < serde_json::to_string(obj).ok()?.len() {
+            Some(delta)
+        } else {
+            None
+        }
+    }
+
+    fn cached_object(&self, digest: &str) -> Option<Map<String, Value>> {
+        self.cache.lock().unwrap().get(digest).cloned()
+    }
+
+    /// Reads a value given its digest, reconstructing the values stored as structural deltas
+    pub fn read_value(&self, digest: &str) -> Result<Value> {
+        self.resolve_value(self.read_raw_value(digest)?)
+    }
+
+    /// Reconstructs a value stored as a structural delta (other values are returned as is)
+    fn resolve_value(&self, mut value: Value) -> Result<Value> {
+        let mut deltas = vec![];
+        while let Some(base) = structural_delta_base(&value) {
+            let base_value = match self.cached_object(&base) {
+                Some(object) => Value::from(object),
+                None => self.read_raw_value(&base)?,
+            };
+            deltas.push(std::mem::replace(&mut value, base_value));
+        }
+        let mut object = match value {
+            Value::Object(object) => object,
+            _ => bail!("expecting_an_object"),
+        };
+        for delta in deltas.iter().rev() {
+            apply_structural_delta(&mut object, delta);
+        }
+        Ok(Value::from(object))
+    }
+
     /// Reads an object at the given revision
     pub fn read_object(&self, revision: &Revision) -> Result<Map<String, Value>> {
         if revision.is_empty() {
@@ -216,40 +686,46 @@ impl DataStorage {
                 Value::from(revision.digest().clone()),
             );
             Ok(o)
-        } else if let Some(object) = self.cache.lock().unwrap().get(revision.digest()) {
-            Ok(object.clone())
+        } else if let Some(object) = self.cached_object(revision.digest()) {
+            Ok(object)
         } else {
-            let value = self.read_raw_value(revision.digest())?;
+            let value = self.read_value(revision.digest())?;
             let object = value.as_object().expect("expecting_an_object");
             Ok(object.clone())
         }
     }
 
-    /// Writes the given (JSON) value into the temporary pack (if not already there)
+    /// Writes the given (JSON) value into the temporary pack (if not already there, packs which
+    /// have not been indexed yet are not searched)
     pub fn write_raw_value(&mut self, digest: &str, obj: Value) -> Result<()> {
-        if !self.committed_objects.contains_key(digest) && !self.stage.contains_key(digest) {
+        let committed = self.committed_objects.lock().unwrap().contains_key(digest);
+        if !committed && !self.stage.contains_key(digest) {
             self.stage.insert(digest.to_string(), obj);
         }
         Ok(())
     }
 
+    /// Returns true if a value with the given digest is available (committed or staged)
+    pub fn contains_value(&self, digest: &str) -> bool {
+        self.stage.contains_key(digest) || matches!(self.locate(Some(digest)), Ok(Some(_)))
+    }
+
     /// Reads a JSON value given its digest
     pub fn read_raw_value(&self, digest: &str) -> Result<Value> {
-        if let Some(value) = self.committed_objects.get(digest) {
-            let (pack, offset, length) = value;
-            let key = pack.clone() + PACK_EXTENSION;
+        if let Some(value) = self.stage.get(digest) {
+            Ok(value.clone())
+        } else if let Some((pack, offset, length)) = self.locate(Some(digest))? {
+            let key = pack + PACK_EXTENSION;
             let data = self
                 .adapter
                 .read()
                 .unwrap()
-                .read_object(&key, *offset, *length)?;
-            let json = std::str::from_utf8(&data)?;
-            let json: Value = serde_json::from_str(json)?;
-            Ok(json)
-        } else if let Some(value) = self.stage.get(digest) {
-            Ok(value.clone())
+                .read_object_bytes(&key, offset, length)?;
+            decode_pack_value(&data)
         } else {
-            Err(anyhow!("value_not_found"))
+            Err(anyhow!(MeldaError::MissingObject {
+                digest: digest.to_string()
+            }))
         }
     }
 
@@ -261,27 +737,46 @@ impl DataStorage {
         }
         let mut index_map = Map::<String, Value>::new();
         let mut buf = Vec::<u8>::new();
-        let mut start: usize = 1;
-        buf.push(b'[');
-        let mut remaining = self.stage.len();
-        for (digest, v) in &self.stage {
-            let content = serde_json::to_string(&v).unwrap();
-            let bytes = content.as_bytes();
-            buf.extend_from_slice(bytes);
-            index_map.insert(digest.clone(), json!([start, bytes.len()]));
-            remaining -= 1;
-            if remaining > 0 {
-                buf.push(b',');
-                start = buf.len();
+        match self.encoding {
+            Encoding::Json => {
+                let mut start: usize = 1;
+                buf.push(b'[');
+                let mut remaining = self.stage.len();
+                for (digest, v) in &self.stage {
+                    let content = serde_json::to_string(&v).unwrap();
+                    let bytes = content.as_bytes();
+                    buf.extend_from_slice(bytes);
+                    index_map.insert(digest.clone(), json!([start, bytes.len()]));
+                    remaining -= 1;
+                    if remaining > 0 {
+                        buf.push(b',');
+                        start = buf.len();
+                    }
+                }
+                buf.push(b']');
+            }
+            Encoding::MessagePack => {
+                // Values are concatenated after the header
+                buf.extend(binary_header());
+                for (digest, v) in &self.stage {
+                    let start = buf.len();
+                    rmp_serde::encode::write(&mut buf, v)?;
+                    index_map.insert(digest.clone(), json!([start, buf.len() - start]));
+                }
             }
         }
-        buf.push(b']');
-        let pack_digest = digest_bytes(buf.as_slice());
+        let pack_digest = digest_bytes_with(self.digest, buf.as_slice());
         let pack_key = pack_digest.clone() + PACK_EXTENSION;
         let adapter = self.adapter.write().unwrap();
         adapter.write_object(&pack_key, buf.as_slice())?;
         drop(adapter);
-        if buf.len() > 800 * index_map.len() {
+        let has_deltas = self
+            .stage
+            .values()
+            .any(|v| structural_delta_base(v).is_some());
+        // Structural deltas are not identified by the digest of their content: the index
+        // is always required
+        if has_deltas || buf.len() > 800 * index_map.len() {
             // 80 bytes is the estimated size of an index entry, use index only if the size is 10 times bigger
             // Only write the index if worth it
             let index_key = pack_digest.clone() + INDEX_EXTENSION;
@@ -290,8 +785,12 @@ impl DataStorage {
             adapter.write_object(&index_key, index_map_contents.as_bytes())?;
             drop(adapter);
         }
-        // load_index_object will update loaded_packs
-        self.load_index_object(&pack_digest, &index_map)?;
+        Self::load_index_object(
+            &pack_digest,
+            &index_map,
+            &mut self.committed_objects.lock().unwrap(),
+        );
+        self.loaded_packs.insert(pack_digest.clone());
         self.stage.clear();
         Ok(Some(pack_digest))
     }
@@ -308,11 +807,31 @@ impl DataStorage {
         !self.stage.is_empty()
     }
 
+    /// Returns the number of staged values and the approximate size (in bytes) of the pack
+    /// which would store them (excluding the index)
+    pub fn staged_size(&self) -> Result<(usize, usize)> {
+        if self.stage.is_empty() {
+            return Ok((0, 0));
+        }
+        let mut bytes = match self.encoding {
+            Encoding::Json => 1,
+            Encoding::MessagePack => binary_header().len(),
+        };
+        for v in self.stage.values() {
+            bytes += match self.encoding {
+                Encoding::Json => serde_json::to_vec(v)?.len() + 1,
+                Encoding::MessagePack => rmp_serde::to_vec(v)?.len(),
+            };
+        }
+        Ok((self.stage.len(), bytes))
+    }
+
     pub fn replay_stage(&mut self, s: &Value) -> Result<()> {
         if s.is_object() {
             let s = s.as_object().unwrap();
+            let committed = self.committed_objects.lock().unwrap();
             for (digest, v) in s {
-                if !self.committed_objects.contains_key(digest) {
+                if !committed.contains_key(digest) {
                     self.stage.insert(digest.clone(), v.clone());
                 }
             }
@@ -333,6 +852,10 @@ impl DataStorage {
         self.adapter.write().unwrap().write_object(key, data)
     }
 
+    pub fn delete_raw_item(&mut self, key: &str) -> Result<()> {
+        self.adapter.write().unwrap().delete_object(key)
+    }
+
     pub fn list_raw_items(&self, ext: &str) -> Result<Vec<String>> {
         self.adapter.read().unwrap().list_objects(ext)
     }
@@ -342,3 +865,150 @@ impl DataStorage {
         self.adapter.clone()
     }
 }
+
+/// Applies a structural delta to its base object
+fn apply_structural_delta(object: &mut Map<String, Value>, delta: &Value) {
+    if let Some(removed) = delta.get(DELTA_REMOVED_FIELD).and_then(Value::as_array) {
+        for k in removed.iter().filter_map(Value::as_str) {
+            object.remove(k);
+        }
+    }
+    if let Some(set) = delta.get(DELTA_SET_FIELD).and_then(Value::as_object) {
+        object.extend(set.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+}
+
+/// Returns the digest of the base value if the value is a structural delta
+fn structural_delta_base(value: &Value) -> Option<String> {
+    if value.get(ID_FIELD).and_then(Value::as_str) == Some(STRUCTURAL_DELTA_ID) {
+        value
+            .get(DELTA_BASE_FIELD)
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DataStorage;
+    use crate::adapter::Adapter;
+    use crate::builder::Encoding;
+    use crate::memoryadapter::MemoryAdapter;
+    use crate::revision::Revision;
+    use crate::utils::{digest_object, digest_string};
+    use serde_json::{json, Value};
+    use std::sync::{Arc, RwLock};
+
+    #[test]
+    fn test_lazy_pack_loading() {
+        let adapter: Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+        let adapter = Arc::new(RwLock::new(adapter));
+        let mut storage = DataStorage::new(adapter.clone(), 16);
+        let mut digests = vec![];
+        for i in 0..3 {
+            let value = json!({ "key": i });
+            let digest = digest_string(&value.to_string());
+            storage.write_raw_value(&digest, value).unwrap();
+            storage.pack().unwrap().unwrap();
+            digests.push(digest);
+        }
+        let mut storage = DataStorage::new(adapter, 16);
+        assert_eq!(storage.reload().unwrap().len(), 3);
+        assert!(storage.committed_objects.lock().unwrap().is_empty());
+        assert_eq!(storage.get_loaded_packs().len(), 3);
+        // Packs are indexed only until the requested value is found
+        let value = storage.read_raw_value(&digests[1]).unwrap();
+        assert_eq!(value, json!({ "key": 1 }));
+        assert!(storage.pending_packs.lock().unwrap().len() < 3);
+        assert!(storage.contains_value(&digests[0]) && storage.contains_value(&digests[2]));
+        assert!(storage.pending_packs.lock().unwrap().is_empty());
+        assert!(storage.read_raw_value("missing").is_err());
+    }
+
+    #[test]
+    fn test_binary_packs() {
+        let adapter: Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+        let adapter = Arc::new(RwLock::new(adapter));
+        let mut storage = DataStorage::new(adapter.clone(), 16);
+        storage.set_encoding(Encoding::MessagePack);
+        let values = vec![json!({ "key": [1.5, 2, -3] }), json!({ "other": "text" })];
+        for value in &values {
+            let digest = digest_string(&value.to_string());
+            storage.write_raw_value(&digest, value.clone()).unwrap();
+        }
+        let pack = storage.pack().unwrap().unwrap();
+        let data = adapter
+            .read()
+            .unwrap()
+            .read_object(&(pack + ".pack"), 0, 0)
+            .unwrap();
+        assert!(serde_json::from_slice::<serde_json::Value>(&data).is_err());
+        // Binary packs without an index are decoded sequentially
+        let mut storage = DataStorage::new(adapter, 16);
+        storage.reload().unwrap();
+        for value in &values {
+            let digest = digest_string(&value.to_string());
+            assert_eq!(&storage.read_raw_value(&digest).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_structural_deltas() {
+        let adapter: Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+        let adapter = Arc::new(RwLock::new(adapter));
+        let mut storage = DataStorage::new(adapter.clone(), 1);
+        storage.set_structural_deltas(true);
+        let large = "x".repeat(256);
+        let mut revisions = vec![];
+        let mut base: Option<Revision> = None;
+        for i in 0..3 {
+            let object = json!({ "large": large, "counter": i });
+            let object = object.as_object().unwrap().clone();
+            let digest = digest_object(&object).unwrap();
+            let rev = match &base {
+                Some(base) => Revision::new_updated(digest, base),
+                None => Revision::new(1, digest, None),
+            };
+            match &base {
+                Some(base) => storage.write_updated_object(&rev, object, base).unwrap(),
+                None => storage.write_object(&rev, object).unwrap(),
+            }
+            revisions.push(rev.clone());
+            base = Some(rev);
+        }
+        // Only the changed field is stored
+        let raw = storage.read_raw_value(revisions[2].digest()).unwrap();
+        assert_eq!(raw.get("s").unwrap(), &json!({ "counter": 2 }));
+        storage.pack().unwrap().unwrap();
+        let mut storage = DataStorage::new(adapter.clone(), 1);
+        storage.reload().unwrap();
+        for (i, rev) in revisions.iter().enumerate() {
+            let object = storage.read_object(rev).unwrap();
+            assert_eq!(Value::from(object), json!({ "large": large, "counter": i }));
+        }
+        // Indexes are rebuilt from the content of the pack
+        let pack = storage.get_loaded_packs().iter().next().unwrap().clone();
+        let index = pack.clone() + ".index";
+        let original = adapter.read().unwrap().read_object(&index, 0, 0).unwrap();
+        adapter.read().unwrap().delete_object(&index).unwrap();
+        adapter
+            .read()
+            .unwrap()
+            .write_object(&index, br#"{"x":[0,1]}"#)
+            .unwrap();
+        assert!(storage.verify_index(&pack).unwrap().is_some());
+        storage.rebuild_index(&pack).unwrap();
+        let rebuilt = adapter.read().unwrap().read_object(&index, 0, 0).unwrap();
+        let parse = |c: &[u8]| serde_json::from_slice::<Value>(c).unwrap();
+        assert_eq!(parse(&rebuilt), parse(&original));
+        assert!(storage.verify_index(&pack).unwrap().is_none());
+        // Deltas whose base is dropped are reconstructed by compaction
+        let retained = [revisions[2].digest().clone()].iter().cloned().collect();
+        storage.compact(&retained).unwrap();
+        storage.reload().unwrap();
+        let raw = storage.read_raw_value(revisions[2].digest()).unwrap();
+        assert_eq!(raw, json!({ "large": large, "counter": 2 }));
+    }
+}
@@ -0,0 +1,200 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use std::fmt;
+
+/// Errors which can be handled programmatically. Functions return an `anyhow::Error`, which
+/// carries a MeldaError whenever the failure falls in one of these categories: use
+/// [MeldaError::of] (or `downcast_ref`) to retrieve it.
+///
+/// # Example
+/// ```
+/// use melda::{melda::Melda, error::MeldaError};
+/// use serde_json::json;
+/// let replica = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+/// replica.update(json!({ "key" : "value" }).as_object().unwrap().clone()).unwrap();
+/// let error = replica.transaction().map(|_| ()).unwrap_err();
+/// assert_eq!(MeldaError::of(&error), Some(&MeldaError::StageNotEmpty));
+/// assert!(!MeldaError::of(&error).unwrap().is_retryable());
+/// assert_eq!(error.to_string(), "stage_not_empty");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MeldaError {
+    /// The storage backend failed to perform an operation (e.g. a network or disk error)
+    AdapterIo { message: String },
+    /// An object does not exist in the storage backend
+    NotFound { key: String },
+    /// A pack (or its index) is corrupted
+    CorruptPack { digest: String },
+    /// A block is corrupted (its content does not match its identifier or cannot be parsed)
+    CorruptBlock { id: String },
+    /// A block is not available
+    MissingBlock { id: String },
+    /// An attachment is corrupted (its content does not match its identifier)
+    CorruptAttachment { id: String },
+    /// A value is not available in any pack nor in the stage
+    MissingObject { digest: String },
+    /// The operation requires an empty stage
+    StageNotEmpty,
+    /// The state changed since the operation started
+    StaleCommit,
+    /// The repository has been written with a newer version of the format
+    FormatVersionMismatch { found: u64, expected: u64 },
+    /// A document rejected by the strict mode (the pointer locates the offending value)
+    InvalidDocument { pointer: String, reason: String },
+    /// The information attached to a commit has been rejected (see
+    /// [crate::commitinfo::CommitInfo])
+    InvalidCommitInfo { reason: String },
+    /// A pre-commit hook vetoed the commit (see [crate::melda::Melda::add_pre_commit_hook])
+    CommitRejected { reason: String },
+}
+
+impl MeldaError {
+    /// Returns the MeldaError carried by an error, if any
+    pub fn of(error: &anyhow::Error) -> Option<&MeldaError> {
+        error.downcast_ref::<MeldaError>()
+    }
+
+    /// Returns true if the operation might succeed if retried (possibly after a refresh)
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, MeldaError::AdapterIo { .. } | MeldaError::StaleCommit)
+    }
+
+    /// Returns true if the error denotes damaged or missing data
+    pub fn is_corruption(&self) -> bool {
+        matches!(
+            self,
+            MeldaError::CorruptPack { .. }
+                | MeldaError::CorruptBlock { .. }
+                | MeldaError::MissingBlock { .. }
+                | MeldaError::CorruptAttachment { .. }
+                | MeldaError::MissingObject { .. }
+        )
+    }
+
+    /// Converts an I/O error occurred while accessing an object of the storage backend
+    pub fn from_io(key: &str, error: std::io::Error) -> MeldaError {
+        if error.kind() == std::io::ErrorKind::NotFound {
+            MeldaError::NotFound {
+                key: key.to_string(),
+            }
+        } else {
+            MeldaError::AdapterIo {
+                message: error.to_string(),
+            }
+        }
+    }
+}
+
+impl fmt::Display for MeldaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeldaError::AdapterIo { message } => write!(f, "adapter_io_error: {}", message),
+            MeldaError::NotFound { key } => write!(f, "object_not_found: {}", key),
+            MeldaError::CorruptPack { digest } => write!(f, "corrupt_pack: {}", digest),
+            MeldaError::CorruptBlock { id } => write!(f, "corrupt_block: {}", id),
+            MeldaError::MissingBlock { id } => write!(f, "missing_block: {}", id),
+            MeldaError::CorruptAttachment { id } => write!(f, "corrupt_attachment: {}", id),
+            MeldaError::MissingObject { digest } => write!(f, "value_not_found: {}", digest),
+            MeldaError::StageNotEmpty => write!(f, "stage_not_empty"),
+            MeldaError::StaleCommit => write!(f, "stale_commit"),
+            MeldaError::FormatVersionMismatch { found, expected } => write!(
+                f,
+                "format_version_mismatch: {} (expected {})",
+                found, expected
+            ),
+            MeldaError::InvalidDocument { pointer, reason } => {
+                write!(f, "invalid_document: {} at '{}'", reason, pointer)
+            }
+            MeldaError::InvalidCommitInfo { reason } => {
+                write!(f, "invalid_commit_info: {}", reason)
+            }
+            MeldaError::CommitRejected { reason } => write!(f, "commit_rejected: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for MeldaError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::MeldaError;
+    use crate::melda::Melda;
+    use crate::memoryadapter::MemoryAdapter;
+    use crate::{adapter::Adapter, filesystemadapter::FilesystemAdapter};
+    use serde_json::json;
+    use std::sync::{Arc, RwLock};
+
+    #[test]
+    fn test_error_kinds() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let adapter = FilesystemAdapter::new(dir.to_str().unwrap()).unwrap();
+        let error = adapter.read_object("missing.delta", 0, 0).unwrap_err();
+        assert!(
+            MeldaError::of(&error)
+                == Some(&MeldaError::NotFound {
+                    key: "missing.delta".to_string()
+                })
+        );
+        let adapter: Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+        let adapter = Arc::new(RwLock::new(adapter));
+        let replica = Melda::new(adapter.clone()).unwrap();
+        replica
+            .update(json!({"key": "value"}).as_object().unwrap().clone())
+            .unwrap();
+        replica.commit(None).unwrap();
+        let error = Melda::new_until(adapter.clone(), &["unknown".to_string()].into())
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(
+            MeldaError::of(&error),
+            Some(&MeldaError::MissingBlock {
+                id: "unknown".to_string()
+            })
+        );
+        // Repositories written with a newer version of the format are refused
+        let newer = json!({ "digest": "sha256", "version": 2 }).to_string();
+        let adapter_w = adapter.write().unwrap();
+        adapter_w.delete_object("repository.metadata").unwrap();
+        adapter_w
+            .write_object("repository.metadata", newer.as_bytes())
+            .unwrap();
+        drop(adapter_w);
+        let error = Melda::new(adapter).map(|_| ()).unwrap_err();
+        assert_eq!(
+            MeldaError::of(&error),
+            Some(&MeldaError::FormatVersionMismatch {
+                found: 2,
+                expected: 1
+            })
+        );
+        let stale = MeldaError::StaleCommit;
+        assert!(stale.is_retryable() && !stale.is_corruption());
+        let version = MeldaError::FormatVersionMismatch {
+            found: 3,
+            expected: 2,
+        };
+        assert!(!version.is_retryable() && !version.is_corruption());
+        assert_eq!(
+            version.to_string(),
+            "format_version_mismatch: 3 (expected 2)"
+        );
+        assert!(MeldaError::CorruptPack {
+            digest: "x".to_string()
+        }
+        .is_corruption());
+    }
+}
@@ -0,0 +1,99 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use std::sync::{Condvar, Mutex};
+use std::thread::{self, ThreadId};
+
+/// Reentrant lock serializing the operations which change the loaded blocks of a replica
+/// (commits, refreshes and reloads), so that they can be invoked from several threads while
+/// reads proceed concurrently. The lock is reentrant since these operations nest (a refresh
+/// commits the resolutions of the conflict policy, a reload may be part of a larger operation).
+#[derive(Default)]
+pub(crate) struct ExclusiveLock {
+    owner: Mutex<Option<(ThreadId, usize)>>, // Owning thread and nesting depth
+    released: Condvar,
+}
+
+/// Releases the lock (or one nesting level) when dropped
+pub(crate) struct ExclusiveGuard<'a> {
+    lock: &'a ExclusiveLock,
+}
+
+impl ExclusiveLock {
+    /// Acquires the lock, waiting until other threads release it
+    pub fn acquire(&self) -> ExclusiveGuard<'_> {
+        let current = thread::current().id();
+        let mut owner = self.owner.lock().unwrap();
+        loop {
+            match *owner {
+                None => *owner = Some((current, 1)),
+                Some((thread, ref mut depth)) if thread == current => *depth += 1,
+                Some(_) => {
+                    owner = self.released.wait(owner).unwrap();
+                    continue;
+                }
+            }
+            return ExclusiveGuard { lock: self };
+        }
+    }
+}
+
+impl Drop for ExclusiveGuard<'_> {
+    fn drop(&mut self) {
+        let mut owner = self.lock.owner.lock().unwrap();
+        if let Some((_, depth)) = owner.as_mut() {
+            *depth -= 1;
+            if *depth == 0 {
+                *owner = None;
+                self.lock.released.notify_one();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_exclusive_lock() {
+        let lock = Arc::new(ExclusiveLock::default());
+        let inside = Arc::new(AtomicUsize::new(0));
+        let outer = lock.acquire();
+        // Nested acquisitions by the same thread do not wait
+        drop(lock.acquire());
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let (lock, inside) = (lock.clone(), inside.clone());
+                thread::spawn(move || {
+                    let _guard = lock.acquire();
+                    assert_eq!(inside.fetch_add(1, Ordering::SeqCst), 0);
+                    thread::yield_now();
+                    inside.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        thread::sleep(std::time::Duration::from_millis(10));
+        // Other threads wait until the outer guard is dropped
+        assert_eq!(inside.load(Ordering::SeqCst), 0);
+        drop(outer);
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert!(lock.owner.lock().unwrap().is_none());
+    }
+}
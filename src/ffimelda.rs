@@ -0,0 +1,229 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::melda::{ChangeKind, Melda};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::sync::Arc;
+
+/// Error reported across the FFI boundary
+#[derive(Debug, uniffi::Error)]
+pub enum FfiError {
+    Failed { message: String },
+}
+
+impl fmt::Display for FfiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FfiError::Failed { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for FfiError {}
+
+impl From<anyhow::Error> for FfiError {
+    fn from(error: anyhow::Error) -> Self {
+        FfiError::Failed {
+            message: error.to_string(),
+        }
+    }
+}
+
+/// Kind of change undergone by an object, see [ChangeKind]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
+pub enum FfiChangeKind {
+    Created,
+    Updated,
+    Deleted,
+    Conflicted,
+    Resolved,
+}
+
+/// Change notified to a [ChangeListener], see [crate::melda::ChangeEvent]
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct FfiChangeEvent {
+    pub uuid: String,
+    pub path: Option<String>,
+    pub kind: FfiChangeKind,
+    pub block: String,
+}
+
+/// Receives the progress of long running operations (such as melding)
+#[uniffi::export(callback_interface)]
+pub trait ProgressListener: Send + Sync {
+    fn on_progress(&self, done: u64, total: u64);
+}
+
+/// Receives the changes applied by commits and refreshes
+#[uniffi::export(callback_interface)]
+pub trait ChangeListener: Send + Sync {
+    fn on_change(&self, event: FfiChangeEvent);
+}
+
+/// Interface to a Melda data structure for Swift and Kotlin, exported by the `ffi` feature.
+/// JSON documents are exchanged as strings.
+#[derive(uniffi::Object)]
+pub struct FfiMelda {
+    inner: Melda,
+}
+
+#[uniffi::export]
+impl FfiMelda {
+    /// Opens a Melda data structure using the adapter identified by the Url
+    #[uniffi::constructor]
+    pub fn new(url: String) -> Result<Arc<Self>, FfiError> {
+        let melda = Melda::new_from_url(&url)?;
+        Ok(Arc::new(FfiMelda { inner: melda }))
+    }
+
+    /// Updates the state with a JSON object, see [Melda::update]
+    pub fn update(&self, json: String) -> Result<String, FfiError> {
+        let obj = parse_object(&json)?;
+        Ok(self.inner.update(obj)?)
+    }
+
+    /// Commits the staged changes with optional information (a JSON object), returning the
+    /// anchors (empty if there was nothing to commit), see [Melda::commit]
+    pub fn commit(&self, information: Option<String>) -> Result<Vec<String>, FfiError> {
+        let information = information.as_deref().map(parse_object).transpose()?;
+        let anchors = self.inner.commit(information)?;
+        Ok(anchors.unwrap_or_default().into_iter().collect())
+    }
+
+    /// Reads the state as JSON, starting from the given root object, see [Melda::read]
+    pub fn read(&self, root: Option<String>) -> Result<String, FfiError> {
+        let content = self.inner.read(root.as_deref())?;
+        Ok(serde_json::to_string(&content).map_err(anyhow::Error::from)?)
+    }
+
+    /// Reloads the state from the adapter, see [Melda::refresh]
+    pub fn refresh(&self) -> Result<(), FfiError> {
+        Ok(self.inner.refresh()?)
+    }
+
+    /// Melds another replica into this one reporting the progress, see
+    /// [Melda::meld_with_progress]. The state is not changed until the next refresh
+    pub fn meld(
+        &self,
+        other: Arc<FfiMelda>,
+        listener: Option<Box<dyn ProgressListener>>,
+    ) -> Result<Vec<String>, FfiError> {
+        Ok(self.inner.meld_with_progress(&other.inner, |done, total| {
+            if let Some(listener) = &listener {
+                listener.on_progress(done as u64, total as u64);
+            }
+        })?)
+    }
+
+    /// Exports the committed items as a bundle, see [Melda::export_bundle]
+    pub fn export_bundle(&self) -> Result<Vec<u8>, FfiError> {
+        Ok(self.inner.export_bundle()?)
+    }
+
+    /// Imports a bundle, see [Melda::import_bundle]. The state is not changed until the next
+    /// refresh
+    pub fn import_bundle(&self, bundle: Vec<u8>) -> Result<Vec<String>, FfiError> {
+        Ok(self.inner.import_bundle(&bundle)?)
+    }
+
+    /// Notifies the listener of the changes applied by subsequent commits and refreshes, see
+    /// [Melda::subscribe]. Notifications are delivered on a background thread, which terminates
+    /// when the replica is dropped
+    pub fn subscribe(&self, listener: Box<dyn ChangeListener>) {
+        let receiver = self.inner.subscribe();
+        std::thread::spawn(move || {
+            for event in receiver {
+                listener.on_change(FfiChangeEvent {
+                    uuid: event.uuid,
+                    path: event.path,
+                    kind: match event.kind {
+                        ChangeKind::Created => FfiChangeKind::Created,
+                        ChangeKind::Updated => FfiChangeKind::Updated,
+                        ChangeKind::Deleted => FfiChangeKind::Deleted,
+                        ChangeKind::Conflicted => FfiChangeKind::Conflicted,
+                        ChangeKind::Resolved => FfiChangeKind::Resolved,
+                    },
+                    block: event.block,
+                });
+            }
+        });
+    }
+}
+
+fn parse_object(json: &str) -> Result<Map<String, Value>, FfiError> {
+    match serde_json::from_str::<Value>(json).map_err(anyhow::Error::from)? {
+        Value::Object(obj) => Ok(obj),
+        _ => Err(FfiError::Failed {
+            message: "not_an_object".to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ffimelda::{
+        ChangeListener, FfiChangeEvent, FfiChangeKind, FfiMelda, ProgressListener,
+    };
+    use std::sync::mpsc::{channel, Sender};
+    use std::sync::{Arc, Mutex};
+
+    struct Progress(Arc<Mutex<Vec<(u64, u64)>>>);
+
+    impl ProgressListener for Progress {
+        fn on_progress(&self, done: u64, total: u64) {
+            self.0.lock().unwrap().push((done, total));
+        }
+    }
+
+    struct Changes(Mutex<Sender<FfiChangeEvent>>);
+
+    impl ChangeListener for Changes {
+        fn on_change(&self, event: FfiChangeEvent) {
+            self.0.lock().unwrap().send(event).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_ffi_melda() {
+        let alice = FfiMelda::new("memory://".to_string()).unwrap();
+        let bob = FfiMelda::new("memory://".to_string()).unwrap();
+        let (sender, receiver) = channel();
+        bob.subscribe(Box::new(Changes(Mutex::new(sender))));
+        alice.update(r#"{"key": "value"}"#.to_string()).unwrap();
+        assert!(alice.update("[]".to_string()).is_err());
+        let anchors = alice
+            .commit(Some(r#"{"author": "Alice"}"#.to_string()))
+            .unwrap();
+        assert_eq!(anchors.len(), 1);
+        let steps = Arc::new(Mutex::new(vec![]));
+        let melded = bob
+            .meld(alice.clone(), Some(Box::new(Progress(steps.clone()))))
+            .unwrap();
+        assert_eq!(
+            steps.lock().unwrap().last(),
+            Some(&(melded.len() as u64, melded.len() as u64))
+        );
+        bob.refresh().unwrap();
+        assert_eq!(
+            bob.read(None).unwrap(),
+            r#"{"_id":"√","key":"value"}"#.to_string()
+        );
+        let event = receiver.recv().unwrap();
+        assert_eq!(event.uuid, "√");
+        assert_eq!(event.kind, FfiChangeKind::Created);
+        assert_eq!(event.block, anchors[0]);
+    }
+}
@@ -13,7 +13,10 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
-use crate::adapter::Adapter;
+use crate::adapter::{Adapter, ObjectBytes};
+#[cfg(feature = "mmap")]
+use crate::constants::PACK_EXTENSION;
+use crate::error::MeldaError;
 use anyhow::{bail, Result};
 use std::{
     convert::TryInto,
@@ -22,9 +25,15 @@ use std::{
     path::{Path, PathBuf},
 };
 
+#[cfg(feature = "mmap")]
+type MappedPacks =
+    std::sync::Mutex<std::collections::HashMap<String, std::sync::Arc<memmap2::Mmap>>>;
+
 /// Implements storage in a folder on the filesystem
 pub struct FilesystemAdapter {
     path: PathBuf,
+    #[cfg(feature = "mmap")]
+    mapped: Option<MappedPacks>,
 }
 
 impl FilesystemAdapter {
@@ -43,10 +52,42 @@ impl FilesystemAdapter {
         } else {
             Ok(FilesystemAdapter {
                 path: PathBuf::from(dir),
+                #[cfg(feature = "mmap")]
+                mapped: None,
             })
         }
     }
 
+    /// Creates a new adapter to store data in the specified directory, reading data packs
+    /// through memory maps (sub-objects are sliced from the mapped pack without copies)
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The path to the directory where data is to be saved to (if the directory does not exist it will be crated)
+    #[cfg(feature = "mmap")]
+    pub fn new_mmap(dir: &str) -> Result<FilesystemAdapter, &str> {
+        let mut adapter = FilesystemAdapter::new(dir)?;
+        adapter.mapped = Some(MappedPacks::default());
+        Ok(adapter)
+    }
+
+    /// Returns the memory map of a pack (mapping it on first access)
+    #[cfg(feature = "mmap")]
+    fn map_pack(&self, mapped: &MappedPacks, key: &str) -> Result<std::sync::Arc<memmap2::Mmap>> {
+        let mut mapped = mapped.lock().unwrap();
+        if let Some(map) = mapped.get(key) {
+            return Ok(map.clone());
+        }
+        let (_, filepath) = self.get_object_path(key)?;
+        let f = File::open(&filepath).map_err(|e| MeldaError::from_io(key, e))?;
+        // SAFETY: packs are content-addressed and never modified once written (they are
+        // only deleted, which does not affect existing mappings)
+        let map = unsafe { memmap2::Mmap::map(&f) }.map_err(|e| MeldaError::from_io(key, e))?;
+        let map = std::sync::Arc::new(map);
+        mapped.insert(key.to_string(), map.clone());
+        Ok(map)
+    }
+
     fn get_object_path(&self, key: &str) -> Result<(String, PathBuf)> {
         let prefix = &key[..2];
         let subdirectory = self.path.clone().join(prefix).join(key);
@@ -82,25 +123,57 @@ impl Adapter for FilesystemAdapter {
     ///
     fn read_object(&self, key: &str, offset: usize, length: usize) -> Result<Vec<u8>> {
         let (_, filepath) = self.get_object_path(key)?;
+        let io_error = |e| MeldaError::from_io(key, e);
         if length == 0 {
-            let mut f = File::open(&filepath)?;
-            let metadata = metadata(&filepath)?;
+            let mut f = File::open(&filepath).map_err(io_error)?;
+            let metadata = metadata(&filepath).map_err(io_error)?;
             let mut data = vec![0; metadata.len() as usize];
-            f.read_exact(&mut data)?;
+            f.read_exact(&mut data).map_err(io_error)?;
             Ok(data)
         } else {
             let mut data = vec![0; length];
-            let mut f = File::open(&filepath)?;
-            let metadata = metadata(&filepath)?;
+            let mut f = File::open(&filepath).map_err(io_error)?;
+            let metadata = metadata(&filepath).map_err(io_error)?;
             if metadata.len() < (offset + length).try_into().unwrap() {
                 bail!("out_of_bounds")
             }
-            f.seek(SeekFrom::Start(offset.try_into().unwrap()))?;
-            f.read_exact(&mut data)?;
+            f.seek(SeekFrom::Start(offset.try_into().unwrap()))
+                .map_err(io_error)?;
+            f.read_exact(&mut data).map_err(io_error)?;
             Ok(data)
         }
     }
 
+    /// Reads an object or a sub-object, slicing memory-mapped packs if enabled
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `offset` - The starting position of the sub-object in the associated data pack
+    /// * `length` - The length of the sub-object (in bytes) in the associated data pack
+    ///
+    fn read_object_bytes(&self, key: &str, offset: usize, length: usize) -> Result<ObjectBytes> {
+        #[cfg(feature = "mmap")]
+        if let (Some(mapped), true) = (&self.mapped, key.ends_with(PACK_EXTENSION)) {
+            let map = self.map_pack(mapped, key)?;
+            // As with read_object, the full pack is returned when length is 0
+            let (offset, length) = if length == 0 {
+                (0, map.len())
+            } else {
+                (offset, length)
+            };
+            if map.len() < offset + length {
+                bail!("out_of_bounds")
+            }
+            return Ok(ObjectBytes::Mapped {
+                map,
+                offset,
+                length,
+            });
+        }
+        Ok(ObjectBytes::Owned(self.read_object(key, offset, length)?))
+    }
+
     /// Writes an object to the storage
     ///
     /// # Arguments
@@ -110,13 +183,69 @@ impl Adapter for FilesystemAdapter {
     fn write_object(&self, key: &str, data: &[u8]) -> Result<()> {
         let (_, filepath) = self.ensure_container_exists(key)?;
         if !filepath.exists() {
-            let mut f = File::create(filepath)?;
-            f.write_all(data)?;
-            f.flush()?;
+            let io_error = |e| MeldaError::from_io(key, e);
+            let mut f = File::create(filepath).map_err(io_error)?;
+            f.write_all(data).map_err(io_error)?;
+            f.flush().map_err(io_error)?;
+        }
+        Ok(())
+    }
+
+    /// Writes an object to the storage, replacing the existing object (if any). The content is
+    /// written to a temporary file which is then renamed, so that the existing object is only
+    /// replaced once the new content has been written
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `data` - The content of the object
+    fn replace_object(&self, key: &str, data: &[u8]) -> Result<()> {
+        let (_, filepath) = self.ensure_container_exists(key)?;
+        let mut temporary = filepath.clone().into_os_string();
+        temporary.push(".tmp");
+        let io_error = |e| MeldaError::from_io(key, e);
+        let mut f = File::create(&temporary).map_err(io_error)?;
+        f.write_all(data).map_err(io_error)?;
+        f.flush().map_err(io_error)?;
+        drop(f);
+        #[cfg(feature = "mmap")]
+        if let Some(mapped) = &self.mapped {
+            mapped.lock().unwrap().remove(key);
         }
+        std::fs::rename(&temporary, filepath).map_err(io_error)?;
         Ok(())
     }
 
+    /// Returns the size (in bytes) of an object, without reading its content
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn object_size(&self, key: &str) -> Result<usize> {
+        let (_, filepath) = self.get_object_path(key)?;
+        let metadata = metadata(&filepath).map_err(|e| MeldaError::from_io(key, e))?;
+        Ok(metadata.len() as usize)
+    }
+
+    /// Deletes an object from the storage (deleting a missing object is not an error)
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn delete_object(&self, key: &str) -> Result<()> {
+        let (_, filepath) = self.get_object_path(key)?;
+        #[cfg(feature = "mmap")]
+        if let Some(mapped) = &self.mapped {
+            mapped.lock().unwrap().remove(key);
+        }
+        match std::fs::remove_file(filepath) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                bail!(MeldaError::from_io(key, e))
+            }
+            _ => Ok(()),
+        }
+    }
+
     /// Lists the keys of all objects whose key ends with ext. If ext is an empty string, all objects are returned.
     ///
     /// # Arguments
@@ -156,6 +285,50 @@ impl Adapter for FilesystemAdapter {
             Ok(result)
         }
     }
+
+    /// Lists, in lexicographic order, at most limit keys of the objects whose key ends with ext
+    /// and follows the given key. Directories are listed one at a time, in order, and those
+    /// holding only keys which precede the starting key are skipped
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    /// * `after` - The key (without the extension) after which the listing starts
+    /// * `limit` - The maximum number of keys returned
+    fn list_objects_page(
+        &self,
+        ext: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        let mut prefixes: Vec<String> = read_dir(&self.path)?
+            .flatten()
+            .filter(|de| de.path().is_dir())
+            .filter_map(|de| de.file_name().into_string().ok())
+            .collect();
+        prefixes.sort();
+        let first = after.map(|after| after.get(..2).unwrap_or(after));
+        let mut result = vec![];
+        for prefix in prefixes {
+            if result.len() >= limit {
+                break;
+            }
+            if first.is_some_and(|first| prefix.as_str() < first) {
+                continue;
+            }
+            let mut keys: Vec<String> = read_dir(self.path.join(&prefix))?
+                .flatten()
+                .filter(|de| de.path().is_file())
+                .filter_map(|de| de.file_name().into_string().ok())
+                .filter_map(|name| name.strip_suffix(ext).map(str::to_string))
+                .filter(|key| after.is_none_or(|after| key.as_str() > after))
+                .collect();
+            keys.sort();
+            keys.truncate(limit - result.len());
+            result.extend(keys);
+        }
+        Ok(result)
+    }
 }
 
 #[cfg(test)]
@@ -290,6 +463,8 @@ mod tests {
         assert!(!ro.is_empty());
         let ro = String::from_utf8(ro).unwrap();
         assert!(ro == "om");
+        assert!(sqa.object_size("somekey.delta").unwrap() == 8);
+        assert!(sqa.object_size("missing.delta").is_err());
     }
 
     #[test]
@@ -363,4 +538,129 @@ mod tests {
         assert!(sqa.list_objects(".pack").unwrap().len() == 1);
         assert!(sqa.list_objects("").unwrap().len() == 2);
     }
+
+    #[test]
+    fn test_filesystem_list_objects_page() {
+        let temp = Temp::new_dir().unwrap();
+        let sqa = FilesystemAdapter::new(temp.to_str().unwrap()).unwrap();
+        for key in &[
+            "ad4.delta",
+            "aa1.delta",
+            "ab2.pack",
+            "ad3.delta",
+            "ac5.delta",
+        ] {
+            sqa.write_object(key, b"data").unwrap();
+        }
+        assert_eq!(
+            sqa.list_objects_page(".delta", None, 2).unwrap(),
+            vec!["aa1", "ac5"]
+        );
+        assert_eq!(
+            sqa.list_objects_page(".delta", Some("ac5"), 2).unwrap(),
+            vec!["ad3", "ad4"]
+        );
+        assert_eq!(
+            sqa.list_objects_page(".delta", Some("ad3"), 2).unwrap(),
+            vec!["ad4"]
+        );
+        assert_eq!(
+            sqa.list_objects_page(".pack", Some("aa1"), 2).unwrap(),
+            vec!["ab2"]
+        );
+        // Wrapping adapters forward the listing
+        let fa: Box<dyn Adapter> = Box::new(sqa);
+        let sqa = Flate2Adapter::new(std::sync::Arc::new(std::sync::RwLock::new(fa)));
+        sqa.write_object("ae6.delta", b"data").unwrap();
+        sqa.write_object("af7.delta", b"data").unwrap();
+        assert_eq!(
+            sqa.list_objects_page(".delta", None, 1).unwrap(),
+            vec!["ae6"]
+        );
+        assert_eq!(
+            sqa.list_objects_page(".delta", Some("ae6"), 5).unwrap(),
+            vec!["af7"]
+        );
+    }
+
+    #[test]
+    fn test_filesystem_delete_object_flate() {
+        let temp = Temp::new_dir().unwrap();
+        let path_buf = temp.to_path_buf();
+        let sa = FilesystemAdapter::new(path_buf.to_str().unwrap()).unwrap();
+        let ma: Box<dyn Adapter> = Box::new(sa);
+        let sqa = Flate2Adapter::new(std::sync::Arc::new(std::sync::RwLock::new(ma)));
+        assert!(sqa
+            .write_object("somekey.delta", "somedata".as_bytes())
+            .is_ok());
+        assert!(sqa
+            .write_object("somekey.pack", "otherdata".as_bytes())
+            .is_ok());
+        assert!(sqa.delete_object("somekey.delta").is_ok());
+        assert!(sqa.list_objects(".delta").unwrap().is_empty());
+        assert!(sqa.list_objects("").unwrap().len() == 1);
+        assert!(sqa.read_object("somekey.delta", 0, 0).is_err());
+        // Deleting a missing object is not an error
+        assert!(sqa.delete_object("somekey.delta").is_ok());
+    }
+
+    #[test]
+    fn test_filesystem_replace_object() {
+        let temp = Temp::new_dir().unwrap();
+        let path_buf = temp.to_path_buf();
+        let sa = FilesystemAdapter::new(path_buf.to_str().unwrap()).unwrap();
+        assert!(sa
+            .write_object("somekey.meta", "somedata".as_bytes())
+            .is_ok());
+        // Writing does not replace existing objects, whereas replacing does
+        assert!(sa
+            .write_object("somekey.meta", "otherdata".as_bytes())
+            .is_ok());
+        assert_eq!(sa.read_object("somekey.meta", 0, 0).unwrap(), b"somedata");
+        assert!(sa
+            .replace_object("somekey.meta", "otherdata".as_bytes())
+            .is_ok());
+        assert_eq!(sa.read_object("somekey.meta", 0, 0).unwrap(), b"otherdata");
+        assert_eq!(
+            sa.list_objects("").unwrap(),
+            vec!["somekey.meta".to_string()]
+        );
+        assert!(sa
+            .replace_object("newkey.meta", "newdata".as_bytes())
+            .is_ok());
+        assert_eq!(sa.read_object("newkey.meta", 0, 0).unwrap(), b"newdata");
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_filesystem_read_object_mmap() {
+        let temp = Temp::new_dir().unwrap();
+        let path_buf = temp.to_path_buf();
+        let sa = FilesystemAdapter::new_mmap(path_buf.to_str().unwrap()).unwrap();
+        assert!(sa
+            .write_object("somekey.pack", "somedata".as_bytes())
+            .is_ok());
+        let ro = sa.read_object_bytes("somekey.pack", 0, 0).unwrap();
+        assert!(matches!(ro, crate::adapter::ObjectBytes::Mapped { .. }));
+        assert!(&ro[..] == "somedata".as_bytes());
+        let ro = sa.read_object_bytes("somekey.pack", 1, 2).unwrap();
+        assert!(&ro[..] == "om".as_bytes());
+        assert!(sa.read_object_bytes("somekey.pack", 4, 8).is_err());
+        assert!(sa.delete_object("somekey.pack").is_ok());
+        assert!(sa.read_object_bytes("somekey.pack", 0, 0).is_err());
+        // Other objects are not mapped
+        assert!(sa
+            .write_object("somekey.delta", "somedata".as_bytes())
+            .is_ok());
+        let ro = sa.read_object_bytes("somekey.delta", 0, 0).unwrap();
+        assert!(matches!(ro, crate::adapter::ObjectBytes::Owned(_)));
+        // Values are read from the mapped packs
+        let url = format!("file+mmap://{}", path_buf.join("repository").display());
+        let replica = crate::melda::Melda::new_from_url(&url).unwrap();
+        let object = serde_json::json!({ "key" : "value" });
+        replica.update(object.as_object().unwrap().clone()).unwrap();
+        replica.commit(None).unwrap();
+        let replica = crate::melda::Melda::new_from_url(&url).unwrap();
+        assert_eq!(replica.read(None).unwrap().get("key").unwrap(), "value");
+    }
 }
@@ -0,0 +1,385 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::constants::{FLATTEN_SUFFIX, ID_FIELD};
+use crate::melda::Melda;
+use anyhow::{anyhow, bail, Result};
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File holding the fields of an object (except collections)
+pub(crate) const OBJECT_FILE: &str = "object.json";
+/// File holding the order of the elements of a collection
+pub(crate) const ORDER_FILE: &str = "order.json";
+
+/// Writes the current state as a directory tree of JSON files, which can be edited with tools
+/// that only understand files and then committed back with [checkin_tree].
+///
+/// Each object is a directory containing an *object.json* file with its fields. Each collection
+/// (a flattened array of objects) is a sub-directory named after the field, with an *order.json*
+/// file listing the identifiers of the elements and a directory for each element (named after its
+/// identifier).
+///
+/// # Arguments
+///
+/// * `melda` - The Melda data structure
+/// * `dir` - The destination directory (which must not exist or be empty)
+///
+/// # Example
+/// ```
+/// use melda::{melda::Melda, filetree::{export_tree, checkin_tree}};
+/// use serde_json::json;
+/// let dir = std::env::temp_dir().join("melda_filetree_doctest");
+/// let _ = std::fs::remove_dir_all(&dir);
+/// let replica = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+/// replica.update(json!({ "title" : "todos", "items♭" : [ { "_id" : "a", "text" : "milk" } ] }).as_object().unwrap().clone()).unwrap();
+/// replica.commit(None).unwrap();
+/// export_tree(&replica, &dir).unwrap();
+/// std::fs::write(dir.join("items♭/a/object.json"), r#"{ "_id" : "a", "text" : "eggs" }"#).unwrap();
+/// assert!(checkin_tree(&replica, &dir, None).unwrap().is_some());
+/// assert_eq!(replica.read(None).unwrap().get("items♭").unwrap(), &json!([{ "_id" : "a", "text" : "eggs" }]));
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub fn export_tree(melda: &Melda, dir: &Path) -> Result<()> {
+    if dir.exists() && fs::read_dir(dir)?.next().is_some() {
+        bail!("directory_not_empty");
+    }
+    write_object(&melda.read(None)?, dir)
+}
+
+/// Commits the changes made to a directory tree written by [export_tree] (along with the changes
+/// already staged), returning the anchors (or None if there was nothing to commit). Only the
+/// objects which differ from the current state are changed. Elements whose directory was
+/// removed are deleted, whereas new element directories are appended to their collection unless
+/// listed in the *order.json* file.
+///
+/// # Arguments
+///
+/// * `melda` - The Melda data structure
+/// * `dir` - The directory tree
+/// * `information` - Optional information attached to the block
+pub fn checkin_tree(
+    melda: &Melda,
+    dir: &Path,
+    information: Option<Map<String, Value>>,
+) -> Result<Option<BTreeSet<String>>> {
+    melda.update(read_object(dir)?)?;
+    melda.commit(information)
+}
+
+fn write_object(object: &Map<String, Value>, dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    for (k, v) in object {
+        if let Some(ids) = collection_ids(k, v) {
+            let cdir = dir.join(encode_name(k)?);
+            fs::create_dir_all(&cdir)?;
+            fs::write(cdir.join(ORDER_FILE), serde_json::to_vec_pretty(&ids)?)?;
+            for (id, element) in ids.iter().zip(v.as_array().unwrap()) {
+                write_object(element.as_object().unwrap(), &cdir.join(encode_name(id)?))?;
+            }
+        }
+    }
+    let fields = object_fields(object);
+    fs::write(dir.join(OBJECT_FILE), serde_json::to_vec_pretty(&fields)?)?;
+    Ok(())
+}
+
+/// Returns the fields of an object written to its *object.json* file (all but the collections)
+pub(crate) fn object_fields(object: &Map<String, Value>) -> Map<String, Value> {
+    object
+        .iter()
+        .filter(|(k, v)| collection_ids(k, v).is_none())
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
+}
+
+/// Orders the identifiers of the elements of a collection: the identifiers listed in the
+/// *order.json* file come first, followed by the other ones
+pub(crate) fn arrange_ids(order: Vec<String>, present: Vec<String>) -> Vec<String> {
+    let mut ids: Vec<String> = order
+        .into_iter()
+        .filter(|id| present.contains(id))
+        .collect();
+    for id in present {
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+    ids
+}
+
+fn read_object(dir: &Path) -> Result<Map<String, Value>> {
+    let path = dir.join(OBJECT_FILE);
+    let content = fs::read(&path).map_err(|e| anyhow!("cannot_read {}: {}", path.display(), e))?;
+    let mut object = match serde_json::from_slice(&content) {
+        Ok(Value::Object(object)) => object,
+        _ => bail!("invalid_object_file: {}", path.display()),
+    };
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let field = decode_name(&entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() && field.ends_with(FLATTEN_SUFFIX) {
+            object.insert(field, Value::from(read_collection(&entry.path())?));
+        }
+    }
+    Ok(object)
+}
+
+fn read_collection(dir: &Path) -> Result<Vec<Value>> {
+    let mut present = BTreeSet::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            present.insert(decode_name(&entry.file_name().to_string_lossy()));
+        }
+    }
+    let order: Vec<String> = match fs::read(dir.join(ORDER_FILE)) {
+        Ok(content) => serde_json::from_slice(&content)
+            .map_err(|_| anyhow!("invalid_order_file: {}", dir.display()))?,
+        Err(_) => vec![],
+    };
+    let mut elements = vec![];
+    for id in arrange_ids(order, present.into_iter().collect()) {
+        let mut element = read_object(&dir.join(encode_name(&id)?))?;
+        element.insert(ID_FIELD.to_string(), Value::from(id));
+        elements.push(Value::from(element));
+    }
+    Ok(elements)
+}
+
+/// Entry of a dataset directory (see [crate::melda::Melda::import_tree]), named after the JSON
+/// file (without extension) or the sub-directory
+pub(crate) enum DatasetEntry {
+    File(String, Value),
+    Directory(String, PathBuf),
+}
+
+/// Lists the JSON files and the sub-directories of a dataset directory, ordered by name (other
+/// files are ignored)
+pub(crate) fn dataset_entries(dir: &Path) -> Result<Vec<DatasetEntry>> {
+    let mut paths = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        paths.push((entry.file_type()?.is_dir(), entry.path()));
+    }
+    paths.sort_by(|a, b| a.1.cmp(&b.1));
+    let mut entries = vec![];
+    for (is_dir, path) in paths {
+        let name = |p: &Path| decode_name(&p.file_stem().unwrap_or_default().to_string_lossy());
+        if is_dir {
+            entries.push(DatasetEntry::Directory(name(&path), path));
+        } else if path.extension().is_some_and(|e| e == "json") {
+            let content =
+                fs::read(&path).map_err(|e| anyhow!("cannot_read {}: {}", path.display(), e))?;
+            let value = serde_json::from_slice(&content)
+                .map_err(|_| anyhow!("invalid_json_file: {}", path.display()))?;
+            entries.push(DatasetEntry::File(name(&path), value));
+        }
+    }
+    Ok(entries)
+}
+
+/// Reads a dataset directory as an object: each JSON file becomes a field and each
+/// sub-directory a collection of the objects held by its JSON files
+pub(crate) fn read_dataset(dir: &Path) -> Result<Map<String, Value>> {
+    let mut object = Map::new();
+    for entry in dataset_entries(dir)? {
+        match entry {
+            DatasetEntry::File(name, value) => {
+                object.insert(name, value);
+            }
+            DatasetEntry::Directory(name, path) => {
+                let field = if name.ends_with(FLATTEN_SUFFIX) {
+                    name
+                } else {
+                    name + FLATTEN_SUFFIX
+                };
+                object.insert(field, Value::from(read_elements(&path)?));
+            }
+        }
+    }
+    Ok(object)
+}
+
+// Reads the elements of a collection, identified by the names of their files
+fn read_elements(dir: &Path) -> Result<Vec<Value>> {
+    let mut elements = vec![];
+    for entry in dataset_entries(dir)? {
+        match entry {
+            DatasetEntry::File(id, Value::Object(mut element)) => {
+                match element.get(ID_FIELD) {
+                    Some(existing) if existing.as_str() != Some(&id) => {
+                        bail!("conflicting_identifier: {}", id)
+                    }
+                    _ => element.insert(ID_FIELD.to_string(), Value::from(id)),
+                };
+                elements.push(Value::from(element));
+            }
+            DatasetEntry::File(id, _) => bail!("element_not_an_object: {}", id),
+            DatasetEntry::Directory(_, path) => bail!("nested_directory: {}", path.display()),
+        }
+    }
+    Ok(elements)
+}
+
+/// Returns the identifiers of the elements if the field is a collection
+pub(crate) fn collection_ids(key: &str, value: &Value) -> Option<Vec<String>> {
+    if !key.ends_with(FLATTEN_SUFFIX) {
+        return None;
+    }
+    value
+        .as_array()?
+        .iter()
+        .map(|v| {
+            v.get(ID_FIELD)
+                .and_then(|id| id.as_str())
+                .map(|id| id.to_string())
+        })
+        .collect()
+}
+
+/// Escapes field names and identifiers which are not valid (or not safe) file names: path
+/// separators and percent signs are percent-encoded, as is a leading dot (so that names such as
+/// `..` cannot refer to another directory) and the first character of the names of the files
+/// written for objects and collections. Empty names are rejected
+pub(crate) fn encode_name(name: &str) -> Result<String> {
+    if name.is_empty() {
+        bail!("invalid_name: empty");
+    }
+    let reserved = name == OBJECT_FILE || name == ORDER_FILE;
+    let mut encoded = String::with_capacity(name.len());
+    for (i, c) in name.chars().enumerate() {
+        match c {
+            '%' | '/' | '\\' | '\0' => encoded.push_str(&format!("%{:02X}", c as u32)),
+            '.' if i == 0 => encoded.push_str("%2E"),
+            c if i == 0 && reserved => encoded.push_str(&format!("%{:02X}", c as u32)),
+            c => encoded.push(c),
+        }
+    }
+    Ok(encoded)
+}
+
+/// Decodes a name escaped by [encode_name] (invalid escape sequences are kept as they are)
+pub(crate) fn decode_name(name: &str) -> String {
+    let mut decoded = Vec::with_capacity(name.len());
+    let bytes = name.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| name.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::filetree::{checkin_tree, decode_name, encode_name, export_tree, read_dataset};
+    use crate::melda::Melda;
+    use serde_json::json;
+    use std::fs;
+
+    #[test]
+    fn test_tree_roundtrip() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let replica = Melda::new_from_url("memory://").unwrap();
+        let document = json!({ "title" : "todos", "items♭" : [
+            { "_id" : "a/1", "text" : "milk", "tags♭" : [ { "_id" : "t", "name" : "shop" } ] },
+            { "_id" : "b", "text" : "eggs" },
+            { "_id" : "c", "text" : "bread" } ] });
+        replica
+            .update(document.as_object().unwrap().clone())
+            .unwrap();
+        replica.commit(None).unwrap();
+        export_tree(&replica, &dir).unwrap();
+        assert!(export_tree(&replica, &dir).is_err());
+        assert!(checkin_tree(&replica, &dir, None).unwrap().is_none());
+        let items = dir.join("items♭");
+        fs::remove_dir_all(items.join("b")).unwrap();
+        fs::create_dir(items.join("d")).unwrap();
+        fs::write(items.join("d/object.json"), r#"{ "text" : "jam" }"#).unwrap();
+        fs::write(items.join("order.json"), r#"[ "c", "a%2F1" ]"#).unwrap();
+        assert!(checkin_tree(&replica, &dir, None).unwrap().is_some());
+        let content = replica.read(None).unwrap();
+        let ids: Vec<&str> = content["items♭"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|i| i["_id"].as_str().unwrap())
+            .collect();
+        assert_eq!(ids, vec!["c", "a/1", "d"]);
+        assert_eq!(content["items♭"][1]["tags♭"][0]["name"], "shop");
+        for name in ["..%/x", "..", ".", "a\\b", "%2E%", "%zz", "order.json"] {
+            assert_eq!(decode_name(&encode_name(name).unwrap()), name);
+        }
+        assert!(encode_name("").is_err());
+        assert_ne!(encode_name("object.json").unwrap(), "object.json");
+    }
+
+    #[test]
+    fn test_tree_escaping() {
+        let root = mktemp::Temp::new_dir().unwrap();
+        let dir = root.join("tree");
+        let replica = Melda::new_from_url("memory://").unwrap();
+        let document = json!({ "../x♭" : [ { "_id" : "..", "text" : "up" } ],
+            "a/b♭" : [ { "_id" : "../../y", "text" : "escape" } ] });
+        replica
+            .update(document.as_object().unwrap().clone())
+            .unwrap();
+        replica.commit(None).unwrap();
+        export_tree(&replica, &dir).unwrap();
+        // Nothing is written outside of the directory
+        let entries: Vec<_> = fs::read_dir(&root).unwrap().collect();
+        assert_eq!(entries.len(), 1);
+        assert!(checkin_tree(&replica, &dir, None).unwrap().is_none());
+        fs::write(
+            dir.join("%2E.%2Fx♭/%2E./object.json"),
+            r#"{ "text" : "down" }"#,
+        )
+        .unwrap();
+        assert!(checkin_tree(&replica, &dir, None).unwrap().is_some());
+        assert_eq!(replica.read(None).unwrap()["../x♭"][0]["text"], "down");
+    }
+
+    #[test]
+    fn test_read_dataset() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        fs::create_dir_all(dir.join("notes♭")).unwrap();
+        fs::write(dir.join("notes♭/a%2F1.json"), r#"{ "text" : "milk" }"#).unwrap();
+        fs::write(dir.join("count.json"), "3").unwrap();
+        assert_eq!(
+            serde_json::Value::from(read_dataset(&dir).unwrap()),
+            json!({ "count" : 3, "notes♭" : [ { "_id" : "a/1", "text" : "milk" } ] })
+        );
+        fs::write(dir.join("notes♭/b.json"), "[]").unwrap();
+        assert!(read_dataset(&dir).is_err());
+        fs::remove_file(dir.join("notes♭/b.json")).unwrap();
+        fs::create_dir(dir.join("notes♭/nested")).unwrap();
+        assert!(read_dataset(&dir).is_err());
+    }
+}
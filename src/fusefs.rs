@@ -0,0 +1,639 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::constants::{FLATTEN_SUFFIX, ID_FIELD};
+use crate::filetree::{
+    arrange_ids, collection_ids, decode_name, encode_name, object_fields, OBJECT_FILE, ORDER_FILE,
+};
+use crate::melda::Melda;
+use anyhow::Result;
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty,
+    ReplyEntry, ReplyOpen, ReplyWrite, Request, TimeOrNow,
+};
+use libc::{EINVAL, EIO, EISDIR, ENOENT, ENOTDIR, EPERM};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Time during which the kernel (and the file system) reuse the attributes and the state
+const TTL: Duration = Duration::from_secs(1);
+
+/// Step of the path of a node, from the root object
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Step {
+    Collection(String),
+    Element(String),
+    ObjectFile,
+    OrderFile,
+}
+
+/// Node of the tree, resolved against the state
+enum Node<'a> {
+    Object(&'a Map<String, Value>),
+    Collection(&'a Vec<Value>),
+    ObjectFile(&'a Map<String, Value>),
+    OrderFile(&'a Vec<Value>),
+}
+
+/// File system exposing the state of a replica as the directory tree written by
+/// [crate::filetree::export_tree]: each object is a directory with an *object.json* file,
+/// each collection a sub-directory with an *order.json* file and a directory for each element.
+///
+/// Writing an *object.json* file replaces the fields of the object, writing an *order.json*
+/// file reorders the collection, creating a directory in a collection appends an element
+/// (creating a directory whose name ends with ♭ adds a collection) and removing a directory
+/// deletes the element (or the collection). Each change is committed when the file is flushed
+/// (or when the directory is created or removed). Files cannot be created, renamed or removed,
+/// hence editors which save through a temporary file are not supported.
+pub struct MeldaFs {
+    melda: Arc<Melda>,
+    paths: Vec<Vec<Step>>,           // Path of each inode (inode 1 is the root)
+    inodes: HashMap<Vec<Step>, u64>, // Inode of each path
+    state: Option<(Instant, Value)>, // Last state read from the replica
+    buffers: HashMap<u64, Vec<u8>>,  // Content written to files, not yet committed
+    uid: u32,
+    gid: u32,
+}
+
+impl MeldaFs {
+    /// Creates a file system exposing the state of the replica
+    pub fn new(melda: Arc<Melda>) -> Self {
+        MeldaFs {
+            melda,
+            paths: vec![vec![]],
+            inodes: HashMap::from([(vec![], 1)]),
+            state: None,
+            buffers: HashMap::new(),
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+        }
+    }
+
+    // Returns the state of the replica (read again once the TTL expires)
+    fn state(&mut self) -> Result<&Value, i32> {
+        if !matches!(&self.state, Some((read, _)) if read.elapsed() < TTL) {
+            let state = self.melda.read(None).map_err(|_| EIO)?;
+            self.state = Some((Instant::now(), Value::from(state)));
+        }
+        Ok(&self.state.as_ref().unwrap().1)
+    }
+
+    // Returns the path of an inode
+    fn path(&self, ino: u64) -> Result<Vec<Step>, i32> {
+        self.paths
+            .get((ino as usize).wrapping_sub(1))
+            .cloned()
+            .ok_or(ENOENT)
+    }
+
+    // Returns the inode of a path (assigning a new one if needed)
+    fn inode(&mut self, path: Vec<Step>) -> u64 {
+        if let Some(ino) = self.inodes.get(&path) {
+            return *ino;
+        }
+        self.paths.push(path.clone());
+        let ino = self.paths.len() as u64;
+        self.inodes.insert(path, ino);
+        ino
+    }
+
+    // Returns the step corresponding to an entry of a directory
+    fn child_step(&mut self, parent: &[Step], name: &OsStr) -> Result<Step, i32> {
+        let name = name.to_str().ok_or(ENOENT)?;
+        match resolve(self.state()?, parent).ok_or(ENOENT)? {
+            Node::Object(_) if name == OBJECT_FILE => Ok(Step::ObjectFile),
+            Node::Object(_) => Ok(Step::Collection(decode_name(name))),
+            Node::Collection(_) if name == ORDER_FILE => Ok(Step::OrderFile),
+            Node::Collection(_) => Ok(Step::Element(decode_name(name))),
+            _ => Err(ENOTDIR),
+        }
+    }
+
+    // Returns the content of a file (as written, if not committed yet)
+    fn content(&mut self, ino: u64) -> Result<Vec<u8>, i32> {
+        if let Some(buffer) = self.buffers.get(&ino) {
+            return Ok(buffer.clone());
+        }
+        let path = self.path(ino)?;
+        let content = match resolve(self.state()?, &path).ok_or(ENOENT)? {
+            Node::ObjectFile(object) => serde_json::to_vec_pretty(&object_fields(object)),
+            Node::OrderFile(elements) => {
+                let ids: Vec<&Value> = elements.iter().filter_map(|e| e.get(ID_FIELD)).collect();
+                serde_json::to_vec_pretty(&ids)
+            }
+            _ => return Err(EISDIR),
+        };
+        content.map_err(|_| EIO)
+    }
+
+    // Returns the attributes of an inode
+    fn attr(&mut self, ino: u64) -> Result<FileAttr, i32> {
+        let path = self.path(ino)?;
+        let (kind, perm, size) = match resolve(self.state()?, &path).ok_or(ENOENT)? {
+            Node::Object(_) | Node::Collection(_) => (FileType::Directory, 0o755, 0),
+            _ => (
+                FileType::RegularFile,
+                0o644,
+                self.content(ino)?.len() as u64,
+            ),
+        };
+        let now = SystemTime::now();
+        Ok(FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: now,
+            mtime: now,
+            ctime: now,
+            crtime: now,
+            kind,
+            perm,
+            nlink: if kind == FileType::Directory { 2 } else { 1 },
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 512,
+            flags: 0,
+        })
+    }
+
+    // Looks up an entry of a directory
+    fn lookup_entry(&mut self, parent: u64, name: &OsStr) -> Result<FileAttr, i32> {
+        let mut path = self.path(parent)?;
+        path.push(self.child_step(&path, name)?);
+        if resolve(self.state()?, &path).is_none() {
+            return Err(ENOENT);
+        }
+        let ino = self.inode(path);
+        self.attr(ino)
+    }
+
+    // Lists the entries of a directory (with their inode and type)
+    fn entries(&mut self, ino: u64) -> Result<Vec<(u64, FileType, String)>, i32> {
+        let path = self.path(ino)?;
+        let mut children = vec![];
+        match resolve(self.state()?, &path).ok_or(ENOENT)? {
+            Node::Object(object) => {
+                children.push((Step::ObjectFile, FileType::RegularFile, OBJECT_FILE.into()));
+                for (k, v) in object {
+                    if collection_ids(k, v).is_some() {
+                        let name = encode_name(k).map_err(|_| EIO)?;
+                        children.push((Step::Collection(k.clone()), FileType::Directory, name));
+                    }
+                }
+            }
+            Node::Collection(elements) => {
+                children.push((Step::OrderFile, FileType::RegularFile, ORDER_FILE.into()));
+                for id in elements.iter().filter_map(|e| e[ID_FIELD].as_str()) {
+                    let name = encode_name(id).map_err(|_| EIO)?;
+                    children.push((Step::Element(id.to_string()), FileType::Directory, name));
+                }
+            }
+            _ => return Err(ENOTDIR),
+        }
+        let parent = match path.len() {
+            0 => 1,
+            n => self.inode(path[..n - 1].to_vec()),
+        };
+        let mut entries = vec![
+            (ino, FileType::Directory, ".".to_string()),
+            (parent, FileType::Directory, "..".to_string()),
+        ];
+        for (step, kind, name) in children {
+            let mut child = path.clone();
+            child.push(step);
+            entries.push((self.inode(child), kind, name));
+        }
+        Ok(entries)
+    }
+
+    // Writes data into the buffer of a file
+    fn write_data(&mut self, ino: u64, offset: usize, data: &[u8]) -> Result<(), i32> {
+        let mut buffer = self.content(ino)?;
+        if buffer.len() < offset + data.len() {
+            buffer.resize(offset + data.len(), 0);
+        }
+        buffer[offset..offset + data.len()].copy_from_slice(data);
+        self.buffers.insert(ino, buffer);
+        Ok(())
+    }
+
+    // Truncates (or extends) the buffer of a file
+    fn truncate(&mut self, ino: u64, size: usize) -> Result<(), i32> {
+        let mut buffer = self.content(ino)?;
+        buffer.resize(size, 0);
+        self.buffers.insert(ino, buffer);
+        Ok(())
+    }
+
+    // Commits the content written to a file (if any)
+    fn flush_file(&mut self, ino: u64) -> Result<(), i32> {
+        let Some(buffer) = self.buffers.remove(&ino) else {
+            return Ok(());
+        };
+        let mut path = self.path(ino)?;
+        let step = path.pop();
+        let content: Value = serde_json::from_slice(&buffer).map_err(|_| EINVAL)?;
+        self.change(&path, |target| match (step, target, content) {
+            (Some(Step::ObjectFile), Value::Object(object), Value::Object(mut fields)) => {
+                for (k, v) in object.iter() {
+                    if collection_ids(k, v).is_some() {
+                        fields.insert(k.clone(), v.clone());
+                    }
+                }
+                if let Some(id) = object.get(ID_FIELD) {
+                    fields.insert(ID_FIELD.to_string(), id.clone());
+                }
+                *object = fields;
+                Ok(())
+            }
+            (Some(Step::OrderFile), Value::Array(elements), Value::Array(order)) => {
+                let order = order
+                    .into_iter()
+                    .map(|id| id.as_str().map(str::to_string).ok_or(EINVAL))
+                    .collect::<Result<Vec<_>, i32>>()?;
+                let mut present: HashMap<String, Value> = HashMap::new();
+                let mut ids = vec![];
+                for element in elements.drain(..) {
+                    let id = element[ID_FIELD].as_str().unwrap_or_default().to_string();
+                    ids.push(id.clone());
+                    present.insert(id, element);
+                }
+                for id in arrange_ids(order, ids) {
+                    elements.extend(present.remove(&id));
+                }
+                Ok(())
+            }
+            _ => Err(EINVAL),
+        })
+    }
+
+    // Creates an element (in a collection) or a collection (in an object)
+    fn make_directory(&mut self, parent: u64, name: &OsStr) -> Result<FileAttr, i32> {
+        let path = self.path(parent)?;
+        let step = self.child_step(&path, name)?;
+        let mut child = path.clone();
+        child.push(step.clone());
+        if resolve(self.state()?, &child).is_some() {
+            return Err(libc::EEXIST);
+        }
+        self.change(&path, |target| match (step, target) {
+            (Step::Element(id), Value::Array(elements)) if !id.is_empty() => {
+                let mut element = Map::new();
+                element.insert(ID_FIELD.to_string(), Value::from(id));
+                elements.push(Value::from(element));
+                Ok(())
+            }
+            (Step::Collection(field), Value::Object(object)) if field.ends_with(FLATTEN_SUFFIX) => {
+                object.insert(field, Value::from(Vec::<Value>::new()));
+                Ok(())
+            }
+            _ => Err(EPERM),
+        })?;
+        let ino = self.inode(child);
+        self.attr(ino)
+    }
+
+    // Deletes an element (from a collection) or a collection (from an object)
+    fn remove_directory(&mut self, parent: u64, name: &OsStr) -> Result<(), i32> {
+        let path = self.path(parent)?;
+        let step = self.child_step(&path, name)?;
+        self.change(&path, |target| match (step, target) {
+            (Step::Element(id), Value::Array(elements)) => {
+                let count = elements.len();
+                elements.retain(|e| e[ID_FIELD].as_str() != Some(&id));
+                if elements.len() == count {
+                    return Err(ENOENT);
+                }
+                Ok(())
+            }
+            (Step::Collection(field), Value::Object(object)) => {
+                object.remove(&field).map(|_| ()).ok_or(ENOENT)
+            }
+            _ => Err(ENOTDIR),
+        })
+    }
+
+    // Applies a change to the value at the given path and commits the resulting state
+    fn change<F>(&mut self, path: &[Step], change: F) -> Result<(), i32>
+    where
+        F: FnOnce(&mut Value) -> Result<(), i32>,
+    {
+        let mut state = self.state()?.clone();
+        change(resolve_mut(&mut state, path).ok_or(ENOENT)?)?;
+        self.state = None;
+        let state = state.as_object().cloned().ok_or(EIO)?;
+        self.melda.update(state).map_err(|_| EINVAL)?;
+        self.melda.commit(None).map_err(|_| EIO)?;
+        Ok(())
+    }
+}
+
+// Resolves a path against the state
+fn resolve<'a>(state: &'a Value, path: &[Step]) -> Option<Node<'a>> {
+    let mut node = Node::Object(state.as_object()?);
+    for step in path {
+        node = match (node, step) {
+            (Node::Object(object), Step::ObjectFile) => Node::ObjectFile(object),
+            (Node::Object(object), Step::Collection(field)) => {
+                let value = object.get(field)?;
+                collection_ids(field, value)?;
+                Node::Collection(value.as_array()?)
+            }
+            (Node::Collection(elements), Step::OrderFile) => Node::OrderFile(elements),
+            (Node::Collection(elements), Step::Element(id)) => Node::Object(
+                elements
+                    .iter()
+                    .find(|e| e[ID_FIELD].as_str() == Some(id))?
+                    .as_object()?,
+            ),
+            _ => return None,
+        }
+    }
+    Some(node)
+}
+
+// Resolves the path of an object or a collection against the state, for modification
+fn resolve_mut<'a>(state: &'a mut Value, path: &[Step]) -> Option<&'a mut Value> {
+    let mut value = state;
+    for step in path {
+        value = match step {
+            Step::Collection(field) => value.as_object_mut()?.get_mut(field)?,
+            Step::Element(id) => value
+                .as_array_mut()?
+                .iter_mut()
+                .find(|e| e[ID_FIELD].as_str() == Some(id))?,
+            _ => return None,
+        }
+    }
+    Some(value)
+}
+
+impl Filesystem for MeldaFs {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match self.lookup_entry(parent, name) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        match self.attr(ino) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn setattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        let result = match size {
+            Some(size) => self.truncate(ino, size as usize),
+            None => Ok(()),
+        };
+        match result.and_then(|_| self.attr(ino)) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        let result = match flags & libc::O_TRUNC {
+            0 => self.attr(ino).map(|_| ()),
+            _ => self.truncate(ino, 0),
+        };
+        match result {
+            Ok(()) => reply.opened(0, 0),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        match self.content(ino) {
+            Ok(content) => {
+                let start = (offset as usize).min(content.len());
+                let end = (start + size as usize).min(content.len());
+                reply.data(&content[start..end]);
+            }
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        match self.write_data(ino, offset as usize, data) {
+            Ok(()) => reply.written(data.len() as u32),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn flush(&mut self, _req: &Request<'_>, ino: u64, _fh: u64, _lock: u64, reply: ReplyEmpty) {
+        match self.flush_file(ino) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        match self.flush_file(ino) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        match self.entries(ino) {
+            Ok(entries) => {
+                for (i, (ino, kind, name)) in entries.into_iter().enumerate().skip(offset as usize)
+                {
+                    if reply.add(ino, (i + 1) as i64, kind, name) {
+                        break;
+                    }
+                }
+                reply.ok();
+            }
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn mkdir(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        reply: ReplyEntry,
+    ) {
+        match self.make_directory(parent, name) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn rmdir(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        match self.remove_directory(parent, name) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+}
+
+/// Mounts the state of the replica at the given (existing, empty) directory, see [MeldaFs].
+/// Blocks until the file system is unmounted (for instance with `fusermount -u`)
+///
+/// # Arguments
+///
+/// * `melda` - The Melda data structure
+/// * `mountpoint` - The directory where the file system is mounted
+pub fn mount(melda: Arc<Melda>, mountpoint: &Path) -> Result<()> {
+    let options = [MountOption::FSName("melda".to_string())];
+    fuser::mount2(MeldaFs::new(melda), mountpoint, &options)?;
+    Ok(())
+}
+
+/// Mounts the state of the replica like [mount] in a background thread. The file system is
+/// unmounted when the returned session is dropped
+///
+/// # Arguments
+///
+/// * `melda` - The Melda data structure
+/// * `mountpoint` - The directory where the file system is mounted
+pub fn spawn_mount(melda: Arc<Melda>, mountpoint: &Path) -> Result<fuser::BackgroundSession> {
+    let options = [MountOption::FSName("melda".to_string())];
+    Ok(fuser::spawn_mount2(
+        MeldaFs::new(melda),
+        mountpoint,
+        &options,
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MeldaFs;
+    use crate::melda::Melda;
+    use serde_json::json;
+    use std::ffi::OsStr;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_fs_changes() {
+        let replica = Arc::new(Melda::new_from_url("memory://").unwrap());
+        let document = json!({ "title" : "todos", "items♭" : [
+            { "_id" : "a", "text" : "milk" },
+            { "_id" : "../b", "text" : "eggs" } ] });
+        replica
+            .update(document.as_object().unwrap().clone())
+            .unwrap();
+        replica.commit(None).unwrap();
+        let mut fs = MeldaFs::new(replica.clone());
+        let names = |fs: &mut MeldaFs, ino| -> Vec<String> {
+            fs.entries(ino).unwrap().into_iter().map(|e| e.2).collect()
+        };
+        assert_eq!(names(&mut fs, 1), vec![".", "..", "object.json", "items♭"]);
+        let items = fs.lookup_entry(1, OsStr::new("items♭")).unwrap().ino;
+        assert_eq!(
+            names(&mut fs, items),
+            vec![".", "..", "order.json", "a", "%2E.%2Fb"]
+        );
+        // Edit an element
+        let b = fs.lookup_entry(items, OsStr::new("%2E.%2Fb")).unwrap().ino;
+        let file = fs.lookup_entry(b, OsStr::new("object.json")).unwrap().ino;
+        fs.truncate(file, 0).unwrap();
+        fs.write_data(file, 0, br#"{ "text" : "bread" }"#).unwrap();
+        fs.flush_file(file).unwrap();
+        assert!(!replica.has_staging());
+        assert_eq!(
+            replica.read(None).unwrap()["items♭"][1],
+            json!({ "_id" : "../b", "text" : "bread" })
+        );
+        // Reorder, add and remove elements
+        let order = fs
+            .lookup_entry(items, OsStr::new("order.json"))
+            .unwrap()
+            .ino;
+        fs.truncate(order, 0).unwrap();
+        fs.write_data(order, 0, br#"[ "../b", "a" ]"#).unwrap();
+        fs.flush_file(order).unwrap();
+        fs.make_directory(items, OsStr::new("c")).unwrap();
+        fs.remove_directory(items, OsStr::new("a")).unwrap();
+        let content = replica.read(None).unwrap();
+        assert_eq!(
+            content["items♭"],
+            json!([ { "_id" : "../b", "text" : "bread" }, { "_id" : "c" } ])
+        );
+        assert_eq!(content["title"], "todos");
+        // Invalid content is rejected
+        let root = fs.lookup_entry(1, OsStr::new("object.json")).unwrap().ino;
+        fs.write_data(root, 0, b"[").unwrap();
+        assert!(fs.flush_file(root).is_err());
+        assert!(fs.lookup_entry(items, OsStr::new("missing")).is_err());
+    }
+}
@@ -0,0 +1,396 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//! gRPC interface of the repository server: the same operations as the HTTP interface (and the
+//! same capability tokens, carried in the `authorization` metadata as `Bearer <token>`). The
+//! schema is shipped as `proto/server.proto` (and available as [SCHEMA]); the messages and the
+//! service below mirror it.
+use crate::server::{RepositoryServer, Response};
+use anyhow::{anyhow, Result};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tonic::body::BoxBody;
+use tonic::codec::ProstCodec;
+use tonic::codegen::{http, Body, BoxFuture, Service, StdError};
+use tonic::server::{Grpc, NamedService, UnaryService};
+use tonic::Status;
+
+/// The Protocol Buffers schema of the gRPC interface
+pub const SCHEMA: &str = include_str!("../proto/server.proto");
+
+/// Lists the keys of the objects of a repository
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListObjectsRequest {
+    /// Name of the repository
+    #[prost(string, tag = "1")]
+    pub repository: String,
+    /// Extension of the listed objects (empty to list all objects)
+    #[prost(string, tag = "2")]
+    pub ext: String,
+}
+
+/// Keys of the objects of a repository
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListObjectsResponse {
+    /// Keys (without the extension, if one was requested)
+    #[prost(string, repeated, tag = "1")]
+    pub keys: Vec<String>,
+}
+
+/// Reads an object of a repository
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ReadObjectRequest {
+    /// Name of the repository
+    #[prost(string, tag = "1")]
+    pub repository: String,
+    /// Key of the object
+    #[prost(string, tag = "2")]
+    pub key: String,
+}
+
+/// An object of a repository
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Object {
+    /// Name of the repository
+    #[prost(string, tag = "1")]
+    pub repository: String,
+    /// Key of the object
+    #[prost(string, tag = "2")]
+    pub key: String,
+    /// Content of the object
+    #[prost(bytes = "vec", tag = "3")]
+    pub data: Vec<u8>,
+}
+
+/// Result of a write
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WriteObjectResponse {}
+
+// Handles a request on behalf of the bearer of the token (failing with the response of the
+// server)
+type Handler<Req, Res> = fn(&RepositoryServer, Option<&str>, Req) -> Result<Res, Response>;
+
+/// The `melda.Repository` gRPC service, which serves the repositories of a server
+#[derive(Clone)]
+pub struct RepositoryService {
+    server: Arc<RepositoryServer>,
+}
+
+impl RepositoryService {
+    /// Creates a new service
+    ///
+    /// # Arguments
+    ///
+    /// * `server` - The server hosting the repositories
+    pub fn new(server: Arc<RepositoryServer>) -> Self {
+        RepositoryService { server }
+    }
+
+    /// Serves requests on the given address. This function does not return unless the service
+    /// cannot be started
+    pub fn serve(self, address: &str) -> Result<()> {
+        let address: SocketAddr = address.parse().map_err(|_| anyhow!("invalid_address"))?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        runtime.block_on(
+            tonic::transport::Server::builder()
+                .add_service(self)
+                .serve(address),
+        )?;
+        Ok(())
+    }
+
+    // Decodes a request, handles it and encodes the response
+    fn unary<Req, Res, B>(
+        &self,
+        handler: Handler<Req, Res>,
+        request: http::Request<B>,
+    ) -> BoxFuture<http::Response<BoxBody>, Infallible>
+    where
+        Req: prost::Message + Default + Send + 'static,
+        Res: prost::Message + Send + 'static,
+        B: Body + Send + 'static,
+        B::Error: Into<StdError> + Send + 'static,
+    {
+        let method = Unary {
+            server: self.server.clone(),
+            handler,
+        };
+        Box::pin(async move {
+            let mut grpc = Grpc::new(ProstCodec::<Res, Req>::default());
+            Ok(grpc.unary(method, request).await)
+        })
+    }
+}
+
+impl<B> Service<http::Request<B>> for RepositoryService
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        match request.uri().path() {
+            "/melda.Repository/ListObjects" => self.unary(list_objects, request),
+            "/melda.Repository/ReadObject" => self.unary(read_object, request),
+            "/melda.Repository/WriteObject" => self.unary(write_object, request),
+            _ => Box::pin(async { Ok(Status::unimplemented("").into_http()) }),
+        }
+    }
+}
+
+impl NamedService for RepositoryService {
+    const NAME: &'static str = "melda.Repository";
+}
+
+// A unary method of the service (the server is invoked on a blocking thread)
+struct Unary<Req, Res> {
+    server: Arc<RepositoryServer>,
+    handler: Handler<Req, Res>,
+}
+
+impl<Req, Res> UnaryService<Req> for Unary<Req, Res>
+where
+    Req: Send + 'static,
+    Res: Send + 'static,
+{
+    type Response = Res;
+    type Future = BoxFuture<tonic::Response<Res>, Status>;
+
+    fn call(&mut self, request: tonic::Request<Req>) -> Self::Future {
+        let server = self.server.clone();
+        let handler = self.handler;
+        Box::pin(async move {
+            let token = request
+                .metadata()
+                .get("authorization")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("Bearer "))
+                .map(|t| t.trim().to_string());
+            let message = request.into_inner();
+            tokio::task::spawn_blocking(move || handler(&server, token.as_deref(), message))
+                .await
+                .map_err(|e| Status::internal(e.to_string()))?
+                .map(tonic::Response::new)
+                .map_err(status)
+        })
+    }
+}
+
+// Returns the body of a successful response of the server
+fn into_result(response: Response) -> Result<Vec<u8>, Response> {
+    match response.status {
+        200..=299 => Ok(response.body),
+        _ => Err(response),
+    }
+}
+
+// Returns the status corresponding to a failed response of the server
+fn status(response: Response) -> Status {
+    let message = String::from_utf8_lossy(&response.body).to_string();
+    match response.status {
+        400 => Status::invalid_argument(message),
+        401 => Status::unauthenticated(message),
+        403 => Status::permission_denied(message),
+        404 => Status::not_found(message),
+        405 => Status::unimplemented(message),
+        409 => Status::already_exists(message),
+        _ => Status::internal(message),
+    }
+}
+
+// Returns the URL of an object (or of the objects) of a repository
+fn objects_url(repository: &str, key: Option<&str>) -> String {
+    let encode = |s: &str| url::form_urlencoded::byte_serialize(s.as_bytes()).collect::<String>();
+    match key {
+        Some(key) => format!("/{}/objects/{}", encode(repository), encode(key)),
+        None => format!("/{}/objects", encode(repository)),
+    }
+}
+
+fn list_objects(
+    server: &RepositoryServer,
+    token: Option<&str>,
+    request: ListObjectsRequest,
+) -> Result<ListObjectsResponse, Response> {
+    let url = objects_url(&request.repository, None)
+        + "?ext="
+        + &url::form_urlencoded::byte_serialize(request.ext.as_bytes()).collect::<String>();
+    let body = into_result(server.handle("GET", &url, token, b""))?;
+    let keys = serde_json::from_slice(&body).map_err(|e| Response {
+        status: 500,
+        body: e.to_string().into_bytes(),
+    })?;
+    Ok(ListObjectsResponse { keys })
+}
+
+fn read_object(
+    server: &RepositoryServer,
+    token: Option<&str>,
+    request: ReadObjectRequest,
+) -> Result<Object, Response> {
+    let url = objects_url(&request.repository, Some(&request.key));
+    let data = into_result(server.handle("GET", &url, token, b""))?;
+    Ok(Object {
+        repository: request.repository,
+        key: request.key,
+        data,
+    })
+}
+
+fn write_object(
+    server: &RepositoryServer,
+    token: Option<&str>,
+    request: Object,
+) -> Result<WriteObjectResponse, Response> {
+    let url = objects_url(&request.repository, Some(&request.key));
+    into_result(server.handle("PUT", &url, token, &request.data))?;
+    Ok(WriteObjectResponse {})
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::capability::Capability;
+    use crate::grpc::{
+        ListObjectsRequest, ListObjectsResponse, Object, ReadObjectRequest, RepositoryService,
+        WriteObjectResponse,
+    };
+    use crate::server::RepositoryServer;
+    use std::sync::Arc;
+    use tonic::codec::ProstCodec;
+    use tonic::codegen::http::uri::PathAndQuery;
+    use tonic::transport::Channel;
+    use tonic::{Code, Status};
+
+    // Invokes a method of the service
+    async fn call<Req, Res>(
+        channel: &Channel,
+        method: &'static str,
+        token: &str,
+        message: Req,
+    ) -> Result<Res, Status>
+    where
+        Req: prost::Message + Send + Sync + 'static,
+        Res: prost::Message + Default + Send + Sync + 'static,
+    {
+        let mut client = tonic::client::Grpc::new(channel.clone());
+        client
+            .ready()
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+        let mut request = tonic::Request::new(message);
+        request.metadata_mut().insert(
+            "authorization",
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+        let path = PathAndQuery::from_static(method);
+        let response = client
+            .unary(request, path, ProstCodec::<Req, Res>::default())
+            .await?;
+        Ok(response.into_inner())
+    }
+
+    #[test]
+    fn test_grpc() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let server = RepositoryServer::new(&dir, b"secret").unwrap();
+        let rw = server.issue("alice", &Capability::read_write()).unwrap();
+        let ro = server.issue("alice", &Capability::read_only()).unwrap();
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let address = format!("127.0.0.1:{}", port);
+        let service = RepositoryService::new(Arc::new(server));
+        std::thread::spawn({
+            let address = address.clone();
+            move || service.serve(&address)
+        });
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let endpoint =
+                tonic::transport::Endpoint::from_shared(format!("http://{}", address)).unwrap();
+            let mut channel = None;
+            for _ in 0..50 {
+                if let Ok(c) = endpoint.connect().await {
+                    channel = Some(c);
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+            let channel = channel.unwrap();
+            let object = Object {
+                repository: "alice".to_string(),
+                key: "notes.txt".to_string(),
+                data: b"hello".to_vec(),
+            };
+            let write = "/melda.Repository/WriteObject";
+            let result: Result<WriteObjectResponse, _> =
+                call(&channel, write, &ro, object.clone()).await;
+            assert_eq!(result.unwrap_err().code(), Code::PermissionDenied);
+            let result: Result<WriteObjectResponse, _> =
+                call(&channel, write, &rw, object.clone()).await;
+            assert!(result.is_ok());
+            let result: Result<WriteObjectResponse, _> =
+                call(&channel, write, &rw, object.clone()).await;
+            assert_eq!(result.unwrap_err().code(), Code::AlreadyExists);
+            let request = ReadObjectRequest {
+                repository: "alice".to_string(),
+                key: "notes.txt".to_string(),
+            };
+            let read: Object = call(&channel, "/melda.Repository/ReadObject", &ro, request)
+                .await
+                .unwrap();
+            assert_eq!(read, object);
+            let request = ListObjectsRequest {
+                repository: "alice".to_string(),
+                ext: ".txt".to_string(),
+            };
+            let list: ListObjectsResponse =
+                call(&channel, "/melda.Repository/ListObjects", &ro, request)
+                    .await
+                    .unwrap();
+            assert_eq!(list.keys, vec!["notes".to_string()]);
+            let request = ReadObjectRequest {
+                repository: "alice".to_string(),
+                key: "../secret".to_string(),
+            };
+            let result: Result<Object, _> =
+                call(&channel, "/melda.Repository/ReadObject", &ro, request).await;
+            assert_eq!(result.unwrap_err().code(), Code::NotFound);
+            let request = ListObjectsRequest {
+                repository: "bob".to_string(),
+                ext: String::new(),
+            };
+            let result: Result<ListObjectsResponse, _> =
+                call(&channel, "/melda.Repository/ListObjects", &ro, request).await;
+            assert_eq!(result.unwrap_err().code(), Code::Unauthenticated);
+        });
+    }
+}
@@ -0,0 +1,217 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::adapter::Adapter;
+use crate::memoryadapter::MemoryAdapter;
+use anyhow::{anyhow, Result};
+use js_sys::{Array, Promise, Uint8Array};
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{IdbDatabase, IdbFactory, IdbRequest, IdbTransaction, IdbTransactionMode};
+
+// Name of the object store holding the objects of the adapter
+const STORE: &str = "objects";
+
+/// Implements storage in an IndexedDB database of the browser (available in WebAssembly with the
+/// **wasm** feature). Since IndexedDB can only be accessed asynchronously, the objects are loaded
+/// in memory when the adapter is opened and the changes are written to the database by
+/// [IndexedDbAdapter::flush]. Clones of the adapter share the same objects, hence a clone can be
+/// kept to flush the changes of a replica.
+#[derive(Clone)]
+pub struct IndexedDbAdapter {
+    database: String,
+    objects: Arc<MemoryAdapter>,
+    pending: Arc<Mutex<BTreeSet<String>>>, // Keys written or deleted since the last flush
+}
+
+impl IndexedDbAdapter {
+    /// Opens (or creates) an IndexedDB database and loads its objects
+    ///
+    /// # Arguments
+    ///
+    /// * `database` - The name of the database
+    pub async fn open(database: &str) -> Result<Self> {
+        let db = open_database(database).await?;
+        let transaction = db.transaction_with_str(STORE).map_err(js_error)?;
+        let store = transaction.object_store(STORE).map_err(js_error)?;
+        let keys = store.get_all_keys().map_err(js_error)?;
+        let values = store.get_all().map_err(js_error)?;
+        let keys: Array = complete(&keys).await?.unchecked_into();
+        let values: Array = complete(&values).await?.unchecked_into();
+        db.close();
+        let objects = MemoryAdapter::new();
+        for (key, value) in keys.iter().zip(values.iter()) {
+            let key = key
+                .as_string()
+                .ok_or_else(|| anyhow!("invalid_indexeddb_key"))?;
+            objects.write_object(&key, &Uint8Array::new(&value).to_vec())?;
+        }
+        Ok(IndexedDbAdapter {
+            database: database.to_string(),
+            objects: Arc::new(objects),
+            pending: Arc::new(Mutex::new(BTreeSet::new())),
+        })
+    }
+
+    /// Writes the objects written (or deleted) since the last flush to the database in a single
+    /// transaction, returning the number of changed objects
+    pub async fn flush(&self) -> Result<usize> {
+        let pending: Vec<String> = self.pending.lock().unwrap().iter().cloned().collect();
+        if pending.is_empty() {
+            return Ok(0);
+        }
+        let db = open_database(&self.database).await?;
+        let transaction = db
+            .transaction_with_str_and_mode(STORE, IdbTransactionMode::Readwrite)
+            .map_err(js_error)?;
+        let store = transaction.object_store(STORE).map_err(js_error)?;
+        for key in &pending {
+            let key_value = JsValue::from_str(key);
+            match self.objects.read_object(key, 0, 0) {
+                Ok(data) => store
+                    .put_with_key(&Uint8Array::from(data.as_slice()), &key_value)
+                    .map_err(js_error)?,
+                Err(_) => store.delete(&key_value).map_err(js_error)?,
+            };
+        }
+        let result = commit(&transaction).await;
+        db.close();
+        result?;
+        let mut current = self.pending.lock().unwrap();
+        for key in &pending {
+            current.remove(key);
+        }
+        Ok(pending.len())
+    }
+}
+
+impl Adapter for IndexedDbAdapter {
+    /// Reads an object or a sub-object from the loaded objects
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `offset` - The starting position of the sub-object in the associated data pack
+    /// * `length` - The length of the sub-object (in bytes) in the associated data pack
+    ///
+    fn read_object(&self, key: &str, offset: usize, length: usize) -> Result<Vec<u8>> {
+        self.objects.read_object(key, offset, length)
+    }
+
+    /// Writes an object (the database is only updated by [IndexedDbAdapter::flush])
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `data` - The content of the object
+    fn write_object(&self, key: &str, data: &[u8]) -> Result<()> {
+        self.objects.write_object(key, data)?;
+        self.pending.lock().unwrap().insert(key.to_string());
+        Ok(())
+    }
+
+    /// Writes an object, replacing the existing object if any (the database is only updated by
+    /// [IndexedDbAdapter::flush])
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `data` - The content of the object
+    fn replace_object(&self, key: &str, data: &[u8]) -> Result<()> {
+        self.objects.replace_object(key, data)?;
+        self.pending.lock().unwrap().insert(key.to_string());
+        Ok(())
+    }
+
+    /// Deletes an object (the database is only updated by [IndexedDbAdapter::flush])
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn delete_object(&self, key: &str) -> Result<()> {
+        self.objects.delete_object(key)?;
+        self.pending.lock().unwrap().insert(key.to_string());
+        Ok(())
+    }
+
+    /// Lists the keys of all objects whose key ends with ext. If ext is an empty string, all objects are returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    fn list_objects(&self, ext: &str) -> Result<Vec<String>> {
+        self.objects.list_objects(ext)
+    }
+}
+
+// Opens a database, creating the object store on first use. The global object is used (instead
+// of the window) so that the adapter also works in web workers
+async fn open_database(name: &str) -> Result<IdbDatabase> {
+    let factory: IdbFactory = js_sys::Reflect::get(&js_sys::global(), &"indexedDB".into())
+        .map_err(js_error)?
+        .dyn_into()
+        .map_err(|_| anyhow!("indexeddb_not_available"))?;
+    let request = factory.open_with_u32(name, 1).map_err(js_error)?;
+    let upgraded = request.clone();
+    let upgrade = Closure::once(move |_: JsValue| {
+        if let Ok(db) = upgraded.result() {
+            let _ = db
+                .unchecked_into::<IdbDatabase>()
+                .create_object_store(STORE);
+        }
+    });
+    request.set_onupgradeneeded(Some(upgrade.as_ref().unchecked_ref()));
+    let db = complete(&request).await;
+    request.set_onupgradeneeded(None);
+    Ok(db?.unchecked_into())
+}
+
+// Waits for a request to succeed, returning its result
+async fn complete(request: &IdbRequest) -> Result<JsValue> {
+    let promise = Promise::new(&mut |resolve, reject| {
+        request.set_onsuccess(Some(&resolve));
+        request.set_onerror(Some(&reject));
+    });
+    JsFuture::from(promise).await.map_err(|_| {
+        let error = request.error().ok().flatten();
+        anyhow!(
+            "indexeddb_request_failed: {}",
+            error.map(|e| e.message()).unwrap_or_default()
+        )
+    })?;
+    request.result().map_err(js_error)
+}
+
+// Waits for a transaction to be committed
+async fn commit(transaction: &IdbTransaction) -> Result<()> {
+    let promise = Promise::new(&mut |resolve, reject| {
+        transaction.set_oncomplete(Some(&resolve));
+        transaction.set_onerror(Some(&reject));
+        transaction.set_onabort(Some(&reject));
+    });
+    JsFuture::from(promise).await.map_err(|_| {
+        anyhow!(
+            "indexeddb_transaction_failed: {}",
+            transaction.error().map(|e| e.message()).unwrap_or_default()
+        )
+    })?;
+    Ok(())
+}
+
+fn js_error(error: JsValue) -> anyhow::Error {
+    anyhow!("indexeddb_error: {:?}", error)
+}
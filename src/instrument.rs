@@ -0,0 +1,83 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+#[cfg(feature = "instrument")]
+use std::time::Instant;
+
+/// Creates the span of an operation: the fields which can be recorded are `objects`, `blocks`,
+/// `items` and `bytes` (the elapsed time is recorded as `elapsed_us` when the span is dropped)
+#[cfg(feature = "instrument")]
+macro_rules! operation_span {
+    ($name:literal) => {
+        crate::instrument::OperationSpan::new(tracing::info_span!(
+            $name,
+            objects = tracing::field::Empty,
+            blocks = tracing::field::Empty,
+            items = tracing::field::Empty,
+            bytes = tracing::field::Empty,
+            elapsed_us = tracing::field::Empty,
+        ))
+    };
+}
+
+#[cfg(not(feature = "instrument"))]
+macro_rules! operation_span {
+    ($name:literal) => {
+        crate::instrument::OperationSpan::new()
+    };
+}
+
+pub(crate) use operation_span;
+
+/// The span of a core operation (update, commit, meld, refresh or reload), entered until dropped.
+/// With the `instrument` feature spans are `tracing` spans carrying structured fields (number of
+/// objects, blocks and items, bytes written or transferred, elapsed time), whereas without the
+/// feature they compile to nothing
+pub(crate) struct OperationSpan {
+    #[cfg(feature = "instrument")]
+    span: tracing::span::EnteredSpan,
+    #[cfg(feature = "instrument")]
+    start: Instant,
+}
+
+impl OperationSpan {
+    #[cfg(feature = "instrument")]
+    pub fn new(span: tracing::Span) -> Self {
+        OperationSpan {
+            span: span.entered(),
+            start: Instant::now(),
+        }
+    }
+
+    #[cfg(not(feature = "instrument"))]
+    pub fn new() -> Self {
+        OperationSpan {}
+    }
+
+    /// Records a field of the span
+    #[cfg_attr(not(feature = "instrument"), allow(unused_variables))]
+    pub fn record(&self, field: &'static str, value: usize) {
+        #[cfg(feature = "instrument")]
+        self.span.record(field, value as u64);
+    }
+}
+
+#[cfg(feature = "instrument")]
+impl Drop for OperationSpan {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed().as_micros() as u64;
+        self.span.record("elapsed_us", elapsed);
+    }
+}
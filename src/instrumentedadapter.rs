@@ -0,0 +1,218 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::adapter::{Adapter, ObjectBytes};
+use anyhow::Result;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+use tracing::field::Empty;
+
+/// Instruments another adapter: each call is wrapped in a `tracing` span (at the debug level)
+/// recording the key (or extension), the number of bytes read or written (or of listed objects),
+/// the elapsed time and the error, if any. Adapters created with
+/// [crate::adapter::get_adapter] are instrumented automatically when the `instrument` feature
+/// is enabled
+pub struct InstrumentedAdapter {
+    backend: Arc<RwLock<Box<dyn Adapter>>>,
+}
+
+impl InstrumentedAdapter {
+    /// Creates a new adapter wrapping the specified adapter
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - The adapter to be wrapped
+    pub fn new(backend: Arc<RwLock<Box<dyn Adapter>>>) -> Self {
+        InstrumentedAdapter { backend }
+    }
+}
+
+// Records the outcome of an adapter call in its span
+fn finish<T>(span: &tracing::Span, start: Instant, result: &Result<T>, size: impl Fn(&T) -> usize) {
+    span.record("elapsed_us", start.elapsed().as_micros() as u64);
+    match result {
+        Ok(value) => {
+            span.record("bytes", size(value) as u64);
+        }
+        Err(e) => {
+            span.record("error", tracing::field::display(e));
+        }
+    }
+}
+
+impl Adapter for InstrumentedAdapter {
+    fn read_object(&self, key: &str, offset: usize, length: usize) -> Result<Vec<u8>> {
+        let span = tracing::debug_span!(
+            "read_object",
+            key,
+            bytes = Empty,
+            elapsed_us = Empty,
+            error = Empty
+        );
+        let _entered = span.enter();
+        let start = Instant::now();
+        let result = self
+            .backend
+            .read()
+            .unwrap()
+            .read_object(key, offset, length);
+        finish(&span, start, &result, Vec::len);
+        result
+    }
+
+    fn read_object_bytes(&self, key: &str, offset: usize, length: usize) -> Result<ObjectBytes> {
+        let span = tracing::debug_span!(
+            "read_object",
+            key,
+            bytes = Empty,
+            elapsed_us = Empty,
+            error = Empty
+        );
+        let _entered = span.enter();
+        let start = Instant::now();
+        let result = self
+            .backend
+            .read()
+            .unwrap()
+            .read_object_bytes(key, offset, length);
+        finish(&span, start, &result, |data| data.len());
+        result
+    }
+
+    fn object_size(&self, key: &str) -> Result<usize> {
+        let span = tracing::debug_span!(
+            "object_size",
+            key,
+            bytes = Empty,
+            elapsed_us = Empty,
+            error = Empty
+        );
+        let _entered = span.enter();
+        let start = Instant::now();
+        let result = self.backend.read().unwrap().object_size(key);
+        finish(&span, start, &result, |size| *size);
+        result
+    }
+
+    fn write_object(&self, key: &str, data: &[u8]) -> Result<()> {
+        let span = tracing::debug_span!(
+            "write_object",
+            key,
+            bytes = data.len() as u64,
+            elapsed_us = Empty,
+            error = Empty
+        );
+        let _entered = span.enter();
+        let start = Instant::now();
+        let result = self.backend.write().unwrap().write_object(key, data);
+        finish(&span, start, &result, |_| data.len());
+        result
+    }
+
+    fn replace_object(&self, key: &str, data: &[u8]) -> Result<()> {
+        let span = tracing::debug_span!(
+            "replace_object",
+            key,
+            bytes = data.len() as u64,
+            elapsed_us = Empty,
+            error = Empty
+        );
+        let _entered = span.enter();
+        let start = Instant::now();
+        let result = self.backend.write().unwrap().replace_object(key, data);
+        finish(&span, start, &result, |_| data.len());
+        result
+    }
+
+    fn delete_object(&self, key: &str) -> Result<()> {
+        let span = tracing::debug_span!("delete_object", key, elapsed_us = Empty, error = Empty);
+        let _entered = span.enter();
+        let start = Instant::now();
+        let result = self.backend.write().unwrap().delete_object(key);
+        finish(&span, start, &result, |_| 0);
+        result
+    }
+
+    fn list_objects(&self, ext: &str) -> Result<Vec<String>> {
+        let span = tracing::debug_span!(
+            "list_objects",
+            ext,
+            objects = Empty,
+            elapsed_us = Empty,
+            error = Empty
+        );
+        let _entered = span.enter();
+        let start = Instant::now();
+        let result = self.backend.read().unwrap().list_objects(ext);
+        if let Ok(objects) = &result {
+            span.record("objects", objects.len() as u64);
+        }
+        finish(&span, start, &result, |_| 0);
+        result
+    }
+
+    fn list_objects_page(
+        &self,
+        ext: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        let span = tracing::debug_span!(
+            "list_objects_page",
+            ext,
+            after,
+            limit,
+            objects = Empty,
+            elapsed_us = Empty,
+            error = Empty
+        );
+        let _entered = span.enter();
+        let start = Instant::now();
+        let result = self
+            .backend
+            .read()
+            .unwrap()
+            .list_objects_page(ext, after, limit);
+        if let Ok(objects) = &result {
+            span.record("objects", objects.len() as u64);
+        }
+        finish(&span, start, &result, |_| 0);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        adapter::Adapter, instrumentedadapter::InstrumentedAdapter, memoryadapter::MemoryAdapter,
+    };
+
+    #[test]
+    fn test_instrumented_read_write_object() {
+        let ma: Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+        let ia = InstrumentedAdapter::new(std::sync::Arc::new(std::sync::RwLock::new(ma)));
+        assert!(ia.list_objects(".delta").unwrap().is_empty());
+        ia.write_object("somekey.delta", b"somedata").unwrap();
+        assert_eq!(ia.list_objects(".delta").unwrap(), vec!["somekey"]);
+        assert_eq!(ia.read_object("somekey.delta", 2, 3).unwrap(), b"med");
+        assert_eq!(
+            &*ia.read_object_bytes("somekey.delta", 0, 0).unwrap(),
+            b"somedata"
+        );
+        assert_eq!(ia.object_size("somekey.delta").unwrap(), 8);
+        ia.delete_object("somekey.delta").unwrap();
+        assert!(ia.read_object("somekey.delta", 0, 0).is_err());
+    }
+}
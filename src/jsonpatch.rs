@@ -0,0 +1,551 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::constants::ID_FIELD;
+use crate::utils::{
+    encode_json_pointer, parse_array_index, parse_json_pointer, pointer_child, pointer_child_mut,
+};
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Map, Value};
+
+/// A JSON Patch (RFC 6902) operation
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatchOp {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+impl PatchOp {
+    /// Parses an operation from its JSON representation
+    pub fn from_value(v: &Value) -> Result<PatchOp> {
+        let op = v
+            .get("op")
+            .and_then(|op| op.as_str())
+            .ok_or_else(|| anyhow!("missing_patch_op"))?;
+        let path = v
+            .get("path")
+            .and_then(|p| p.as_str())
+            .ok_or_else(|| anyhow!("missing_patch_path"))?
+            .to_string();
+        let value = || {
+            v.get("value")
+                .cloned()
+                .ok_or_else(|| anyhow!("missing_patch_value"))
+        };
+        let from = || {
+            v.get("from")
+                .and_then(|f| f.as_str())
+                .map(|f| f.to_string())
+                .ok_or_else(|| anyhow!("missing_patch_from"))
+        };
+        match op {
+            "add" => Ok(PatchOp::Add {
+                path,
+                value: value()?,
+            }),
+            "remove" => Ok(PatchOp::Remove { path }),
+            "replace" => Ok(PatchOp::Replace {
+                path,
+                value: value()?,
+            }),
+            "move" => Ok(PatchOp::Move {
+                from: from()?,
+                path,
+            }),
+            "copy" => Ok(PatchOp::Copy {
+                from: from()?,
+                path,
+            }),
+            "test" => Ok(PatchOp::Test {
+                path,
+                value: value()?,
+            }),
+            _ => bail!("invalid_patch_op"),
+        }
+    }
+
+    /// Returns the JSON representation of the operation
+    pub fn to_value(&self) -> Value {
+        match self {
+            PatchOp::Add { path, value } => json!({"op": "add", "path": path, "value": value}),
+            PatchOp::Remove { path } => json!({"op": "remove", "path": path}),
+            PatchOp::Replace { path, value } => {
+                json!({"op": "replace", "path": path, "value": value})
+            }
+            PatchOp::Move { from, path } => json!({"op": "move", "from": from, "path": path}),
+            PatchOp::Copy { from, path } => json!({"op": "copy", "from": from, "path": path}),
+            PatchOp::Test { path, value } => json!({"op": "test", "path": path, "value": value}),
+        }
+    }
+
+    /// Returns the target path of the operation
+    pub fn path(&self) -> &str {
+        match self {
+            PatchOp::Add { path, .. }
+            | PatchOp::Remove { path }
+            | PatchOp::Replace { path, .. }
+            | PatchOp::Move { path, .. }
+            | PatchOp::Copy { path, .. }
+            | PatchOp::Test { path, .. } => path,
+        }
+    }
+
+    /// Returns the source path of the operation (move and copy only)
+    pub fn from(&self) -> Option<&str> {
+        match self {
+            PatchOp::Move { from, .. } | PatchOp::Copy { from, .. } => Some(from),
+            _ => None,
+        }
+    }
+
+    /// Returns a copy of the operation with the first count reference tokens removed from all paths
+    pub(crate) fn relative_to(&self, count: usize) -> Result<PatchOp> {
+        let strip = |p: &str| -> Result<String> {
+            let tokens = parse_json_pointer(p)?;
+            Ok(encode_json_pointer(&tokens[count..]))
+        };
+        Ok(match self {
+            PatchOp::Add { path, value } => PatchOp::Add {
+                path: strip(path)?,
+                value: value.clone(),
+            },
+            PatchOp::Remove { path } => PatchOp::Remove { path: strip(path)? },
+            PatchOp::Replace { path, value } => PatchOp::Replace {
+                path: strip(path)?,
+                value: value.clone(),
+            },
+            PatchOp::Move { from, path } => PatchOp::Move {
+                from: strip(from)?,
+                path: strip(path)?,
+            },
+            PatchOp::Copy { from, path } => PatchOp::Copy {
+                from: strip(from)?,
+                path: strip(path)?,
+            },
+            PatchOp::Test { path, value } => PatchOp::Test {
+                path: strip(path)?,
+                value: value.clone(),
+            },
+        })
+    }
+}
+
+/// Parses a JSON Patch document (an array of operations)
+pub fn parse_patch(v: &Value) -> Result<Vec<PatchOp>> {
+    v.as_array()
+        .ok_or_else(|| anyhow!("patch_not_an_array"))?
+        .iter()
+        .map(PatchOp::from_value)
+        .collect()
+}
+
+/// Applies a JSON Patch to a JSON value. The operations are applied atomically: if an operation
+/// fails the value is left unchanged.
+///
+/// # Arguments
+///
+/// * `doc` - The value to be patched
+/// * `ops` - The operations
+pub fn apply_patch(doc: &mut Value, ops: &[PatchOp]) -> Result<()> {
+    let mut patched = doc.clone();
+    for op in ops {
+        apply_op(&mut patched, op)?;
+    }
+    *doc = patched;
+    Ok(())
+}
+
+/// Computes a JSON Patch transforming a value into another one. Elements of arrays of objects
+/// with an identifier (`_id` field) are matched by identifier, so that insertions, removals and
+/// moves are expressed as such instead of being replaced.
+///
+/// # Arguments
+///
+/// * `old` - The initial value
+/// * `new` - The final value
+pub fn diff(old: &Value, new: &Value) -> Vec<PatchOp> {
+    let mut ops = vec![];
+    diff_value(old, new, &mut vec![], &mut ops);
+    ops
+}
+
+fn diff_value(old: &Value, new: &Value, path: &mut Vec<String>, ops: &mut Vec<PatchOp>) {
+    if old == new {
+        return;
+    }
+    match (old, new) {
+        (Value::Object(o), Value::Object(n)) => {
+            for k in o.keys().filter(|k| !n.contains_key(*k)) {
+                path.push(k.clone());
+                ops.push(PatchOp::Remove {
+                    path: encode_json_pointer(path),
+                });
+                path.pop();
+            }
+            for (k, v) in n {
+                path.push(k.clone());
+                match o.get(k) {
+                    Some(ov) => diff_value(ov, v, path, ops),
+                    None => ops.push(PatchOp::Add {
+                        path: encode_json_pointer(path),
+                        value: v.clone(),
+                    }),
+                }
+                path.pop();
+            }
+        }
+        (Value::Array(o), Value::Array(n)) => {
+            if identifiers(o).is_some() && identifiers(n).is_some() {
+                diff_identified_array(o, n, path, ops);
+            } else {
+                diff_array(o, n, path, ops);
+            }
+        }
+        _ => ops.push(PatchOp::Replace {
+            path: encode_json_pointer(path),
+            value: new.clone(),
+        }),
+    }
+}
+
+/// Returns the identifiers of the elements of an array (if all elements are objects with a unique identifier)
+fn identifiers(a: &[Value]) -> Option<Vec<&Value>> {
+    let ids: Vec<&Value> = a
+        .iter()
+        .map(|v| v.get(ID_FIELD))
+        .collect::<Option<Vec<&Value>>>()?;
+    let mut unique = ids.clone();
+    unique.sort_by_key(|v| v.to_string());
+    unique.dedup();
+    if unique.len() == ids.len() {
+        Some(ids)
+    } else {
+        None
+    }
+}
+
+/// Compares arrays element by element
+fn diff_array(o: &[Value], n: &[Value], path: &mut Vec<String>, ops: &mut Vec<PatchOp>) {
+    for (i, (ov, nv)) in o.iter().zip(n.iter()).enumerate() {
+        path.push(i.to_string());
+        diff_value(ov, nv, path, ops);
+        path.pop();
+    }
+    for i in (n.len()..o.len()).rev() {
+        path.push(i.to_string());
+        ops.push(PatchOp::Remove {
+            path: encode_json_pointer(path),
+        });
+        path.pop();
+    }
+    for nv in n.iter().skip(o.len()) {
+        path.push("-".to_string());
+        ops.push(PatchOp::Add {
+            path: encode_json_pointer(path),
+            value: nv.clone(),
+        });
+        path.pop();
+    }
+}
+
+/// Compares arrays of identified objects
+fn diff_identified_array(o: &[Value], n: &[Value], path: &mut Vec<String>, ops: &mut Vec<PatchOp>) {
+    let new_ids = identifiers(n).unwrap();
+    // Remove elements which have disappeared
+    let mut current: Vec<&Value> = o.iter().collect();
+    for i in (0..current.len()).rev() {
+        if !new_ids.contains(&&current[i][ID_FIELD]) {
+            path.push(i.to_string());
+            ops.push(PatchOp::Remove {
+                path: encode_json_pointer(path),
+            });
+            path.pop();
+            current.remove(i);
+        }
+    }
+    // Insert, move and update elements
+    for (i, nv) in n.iter().enumerate() {
+        path.push(i.to_string());
+        match current.iter().position(|v| v[ID_FIELD] == nv[ID_FIELD]) {
+            Some(j) => {
+                if j != i {
+                    let mut from = path.clone();
+                    from.pop();
+                    from.push(j.to_string());
+                    ops.push(PatchOp::Move {
+                        from: encode_json_pointer(&from),
+                        path: encode_json_pointer(path),
+                    });
+                    let moved = current.remove(j);
+                    current.insert(i, moved);
+                }
+                diff_value(current[i], nv, path, ops);
+            }
+            None => {
+                ops.push(PatchOp::Add {
+                    path: encode_json_pointer(path),
+                    value: nv.clone(),
+                });
+                current.insert(i, nv);
+            }
+        }
+        path.pop();
+    }
+}
+
+/// Applies a JSON Merge Patch (RFC 7396) to a value: members of the patch replace those of the
+/// target (objects are merged recursively), null members are removed and arrays are replaced
+/// as a whole.
+///
+/// # Arguments
+///
+/// * `target` - The value to be patched
+/// * `patch` - The merge patch
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+    match patch {
+        Value::Object(p) => {
+            if !target.is_object() {
+                *target = Value::Object(Map::new());
+            }
+            let t = target.as_object_mut().unwrap();
+            for (k, v) in p {
+                if v.is_null() {
+                    t.remove(k);
+                } else {
+                    merge_patch(t.entry(k.clone()).or_insert(Value::Null), v);
+                }
+            }
+        }
+        _ => *target = patch.clone(),
+    }
+}
+
+/// Applies a single operation/// Applies a single operation
+fn apply_op(doc: &mut Value, op: &PatchOp) -> Result<()> {
+    match op {
+        PatchOp::Add { path, value } => add(doc, &parse_json_pointer(path)?, value.clone()),
+        PatchOp::Remove { path } => remove(doc, &parse_json_pointer(path)?).map(|_| ()),
+        PatchOp::Replace { path, value } => {
+            let tokens = parse_json_pointer(path)?;
+            let target = get_mut(doc, &tokens).ok_or_else(|| anyhow!("path_not_found"))?;
+            *target = value.clone();
+            Ok(())
+        }
+        PatchOp::Move { from, path } => {
+            let from = parse_json_pointer(from)?;
+            let path = parse_json_pointer(path)?;
+            if from == path {
+                return Ok(());
+            }
+            if path.len() > from.len() && path.starts_with(&from) {
+                bail!("cannot_move_into_child");
+            }
+            let value = remove(doc, &from)?;
+            add(doc, &path, value)
+        }
+        PatchOp::Copy { from, path } => {
+            let value = get(doc, &parse_json_pointer(from)?)
+                .ok_or_else(|| anyhow!("path_not_found"))?
+                .clone();
+            add(doc, &parse_json_pointer(path)?, value)
+        }
+        PatchOp::Test { path, value } => match get(doc, &parse_json_pointer(path)?) {
+            Some(v) if v == value => Ok(()),
+            _ => bail!("test_failed"),
+        },
+    }
+}
+
+fn get<'a>(doc: &'a Value, tokens: &[String]) -> Option<&'a Value> {
+    let mut current = doc;
+    for t in tokens {
+        current = pointer_child(current, t)?;
+    }
+    Some(current)
+}
+
+fn get_mut<'a>(doc: &'a mut Value, tokens: &[String]) -> Option<&'a mut Value> {
+    let mut current = doc;
+    for t in tokens {
+        current = pointer_child_mut(current, t)?;
+    }
+    Some(current)
+}
+
+/// Adds a value: object members are added or replaced, values are inserted into arrays
+fn add(doc: &mut Value, tokens: &[String], value: Value) -> Result<()> {
+    let (last, parents) = match tokens.split_last() {
+        Some(split) => split,
+        None => {
+            *doc = value;
+            return Ok(());
+        }
+    };
+    match get_mut(doc, parents).ok_or_else(|| anyhow!("path_not_found"))? {
+        Value::Object(o) => {
+            o.insert(last.clone(), value);
+        }
+        Value::Array(a) => {
+            let index = if last == "-" {
+                a.len()
+            } else {
+                parse_array_index(last).ok_or_else(|| anyhow!("invalid_array_index"))?
+            };
+            if index > a.len() {
+                bail!("array_index_out_of_bounds");
+            }
+            a.insert(index, value);
+        }
+        _ => bail!("path_not_found"),
+    }
+    Ok(())
+}
+
+/// Removes a value and returns it
+fn remove(doc: &mut Value, tokens: &[String]) -> Result<Value> {
+    let (last, parents) = tokens
+        .split_last()
+        .ok_or_else(|| anyhow!("cannot_remove_document"))?;
+    match get_mut(doc, parents).ok_or_else(|| anyhow!("path_not_found"))? {
+        Value::Object(o) => o.remove(last).ok_or_else(|| anyhow!("path_not_found")),
+        Value::Array(a) => {
+            let index = parse_array_index(last).ok_or_else(|| anyhow!("invalid_array_index"))?;
+            if index >= a.len() {
+                bail!("array_index_out_of_bounds");
+            }
+            Ok(a.remove(index))
+        }
+        _ => bail!("path_not_found"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::jsonpatch::{apply_patch, diff, merge_patch, parse_patch, PatchOp};
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_patch() {
+        let patch = json!([
+            {"op": "add", "path": "/a", "value": 1},
+            {"op": "remove", "path": "/b"},
+            {"op": "replace", "path": "/c", "value": [1]},
+            {"op": "move", "from": "/d", "path": "/e"},
+            {"op": "copy", "from": "/f", "path": "/g"},
+            {"op": "test", "path": "/h", "value": "x"}
+        ]);
+        let ops = parse_patch(&patch).unwrap();
+        assert!(ops.len() == 6);
+        assert!(
+            ops[3]
+                == PatchOp::Move {
+                    from: "/d".to_string(),
+                    path: "/e".to_string()
+                }
+        );
+        let serialized: Vec<_> = ops.iter().map(|o| o.to_value()).collect();
+        assert!(json!(serialized) == patch);
+        assert!(parse_patch(&json!([{"op": "add", "path": "/a"}])).is_err());
+        assert!(parse_patch(&json!([{"op": "other", "path": "/a"}])).is_err());
+    }
+
+    #[test]
+    fn test_apply_patch() {
+        let mut doc = json!({"a": {"b": [1, 2, 3]}, "c": "x"});
+        let ops = parse_patch(&json!([
+            {"op": "add", "path": "/a/b/1", "value": 9},
+            {"op": "remove", "path": "/a/b/0"},
+            {"op": "replace", "path": "/c", "value": "y"},
+            {"op": "copy", "from": "/c", "path": "/d"},
+            {"op": "move", "from": "/a/b", "path": "/e"},
+            {"op": "add", "path": "/e/-", "value": 4},
+            {"op": "test", "path": "/d", "value": "y"}
+        ]))
+        .unwrap();
+        apply_patch(&mut doc, &ops).unwrap();
+        assert!(doc == json!({"a": {}, "c": "y", "d": "y", "e": [9, 2, 3, 4]}));
+    }
+
+    #[test]
+    fn test_apply_patch_atomic() {
+        let mut doc = json!({"a": 1});
+        let ops = parse_patch(&json!([
+            {"op": "add", "path": "/b", "value": 2},
+            {"op": "test", "path": "/a", "value": 2}
+        ]))
+        .unwrap();
+        assert!(apply_patch(&mut doc, &ops).is_err());
+        assert!(doc == json!({"a": 1}));
+        let ops = parse_patch(&json!([{"op": "remove", "path": "/x"}])).unwrap();
+        assert!(apply_patch(&mut doc, &ops).is_err());
+        let ops = parse_patch(&json!([{"op": "move", "from": "/a", "path": "/a/b"}])).unwrap();
+        assert!(apply_patch(&mut doc, &ops).is_err());
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = json!({"a": 1, "b": [1, 2, 3], "c": {"d": "x"}, "e": true});
+        let new = json!({"a": 2, "b": [1, 5], "c": {"f": "y"}, "g": null});
+        let ops = diff(&old, &new);
+        let mut patched = old.clone();
+        apply_patch(&mut patched, &ops).unwrap();
+        assert!(patched == new);
+        assert!(diff(&new, &new).is_empty());
+        assert!(
+            diff(&json!({"a": 1}), &json!({"a": 2}))
+                == vec![PatchOp::Replace {
+                    path: "/a".to_string(),
+                    value: json!(2)
+                }]
+        );
+    }
+
+    #[test]
+    fn test_diff_identified_array() {
+        let old = json!({"l": [{"_id": "1", "v": 1}, {"_id": "2", "v": 2}, {"_id": "3", "v": 3}]});
+        let new = json!({"l": [{"_id": "3", "v": 3}, {"_id": "4", "v": 4}, {"_id": "1", "v": 5}]});
+        let ops = diff(&old, &new);
+        let mut patched = old.clone();
+        apply_patch(&mut patched, &ops).unwrap();
+        assert!(patched == new);
+        assert!(ops.contains(&PatchOp::Remove {
+            path: "/l/1".to_string()
+        }));
+        assert!(ops.contains(&PatchOp::Replace {
+            path: "/l/2/v".to_string(),
+            value: json!(5)
+        }));
+        assert!(!ops.iter().any(|op| op.path() == "/l"));
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        let mut doc = json!({"title": "Goodbye!", "author": {"givenName": "John", "familyName": "Doe"}, "tags": ["example", "sample"], "content": "This will be unchanged"});
+        let patch = json!({"title": "Hello!", "phoneNumber": "+01-123-456-7890", "author": {"familyName": null}, "tags": ["example"]});
+        merge_patch(&mut doc, &patch);
+        assert!(
+            doc == json!({"title": "Hello!", "author": {"givenName": "John"}, "tags": ["example"], "content": "This will be unchanged", "phoneNumber": "+01-123-456-7890"})
+        );
+        let mut doc = json!({"a": [1, 2]});
+        merge_patch(&mut doc, &json!({"a": {"b": null, "c": 1}}));
+        assert!(doc == json!({"a": {"c": 1}}));
+        merge_patch(&mut doc, &json!(["x"]));
+        assert!(doc == json!(["x"]));
+    }
+}
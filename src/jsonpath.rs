@@ -0,0 +1,600 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::constants::ID_FIELD;
+use crate::utils::encode_json_pointer;
+use anyhow::{bail, Result};
+use serde_json::Value;
+use std::cmp::Ordering;
+
+/// A value matched by a JSONPath query
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryMatch {
+    /// JSON Pointer (RFC 6901) to the matched value
+    pub path: String,
+    /// Identifier of the matched object, or of the nearest object containing the matched value
+    pub id: Option<String>,
+    /// The matched value
+    pub value: Value,
+}
+
+/// A compiled JSONPath expression. Supported syntax: root (`$`), child members (`.name`,
+/// `['name']`), wildcards (`*`), indices (negative indices count from the end), slices
+/// (`[start:end:step]`), unions (`[a,b]`), recursive descent (`..`) and filters
+/// (`[?(@.key == 'value' && @.count > 1)]`) with comparisons, existence tests, `!`, `&&` and `||`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonPath {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Segment {
+    recursive: bool,
+    selectors: Vec<Selector>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Selector {
+    Name(String),
+    Index(i64),
+    Wildcard,
+    Slice(Option<i64>, Option<i64>, i64),
+    Filter(Expr),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Current(Vec<Segment>),
+    Root(Vec<Segment>),
+    Literal(Value),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(Box<Expr>, &'static str, Box<Expr>),
+}
+
+impl JsonPath {
+    /// Parses a JSONPath expression
+    ///
+    /// # Arguments
+    ///
+    /// * `expr` - The expression (which must start with `$`)
+    pub fn parse(expr: &str) -> Result<JsonPath> {
+        let mut parser = Parser {
+            chars: expr.chars().collect(),
+            pos: 0,
+        };
+        parser.skip_whitespace();
+        if !parser.eat('$') {
+            bail!("jsonpath_must_start_with_root");
+        }
+        let segments = parser.segments()?;
+        parser.skip_whitespace();
+        if parser.pos < parser.chars.len() {
+            bail!("invalid_jsonpath_at_{}", parser.pos);
+        }
+        Ok(JsonPath { segments })
+    }
+
+    /// Returns the values (and their reference tokens) selected by the expression
+    pub fn select<'a>(&self, root: &'a Value) -> Vec<(Vec<String>, &'a Value)> {
+        select(&self.segments, root, root)
+    }
+
+    /// Evaluates the expression against a document, returning the matched values along with
+    /// their location and the identity of the object they belong to
+    ///
+    /// # Example
+    /// ```
+    /// use melda::jsonpath::JsonPath;
+    /// use serde_json::json;
+    /// let doc = json!({ "tasks": [ { "_id": "a", "priority": "high" }, { "_id": "b", "priority": "low" } ] });
+    /// let matches = JsonPath::parse("$.tasks[?(@.priority == 'high')].priority").unwrap().query(&doc);
+    /// assert_eq!(matches.len(), 1);
+    /// assert_eq!(matches[0].path, "/tasks/0/priority");
+    /// assert_eq!(matches[0].id.as_deref(), Some("a"));
+    /// assert_eq!(matches[0].value, json!("high"));
+    /// ```
+    pub fn query(&self, root: &Value) -> Vec<QueryMatch> {
+        self.select(root)
+            .into_iter()
+            .map(|(tokens, value)| QueryMatch {
+                path: encode_json_pointer(&tokens),
+                id: owner_id(root, &tokens),
+                value: value.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Returns the identifier of the nearest object along the path (including the target)
+fn owner_id(root: &Value, tokens: &[String]) -> Option<String> {
+    let mut current = root;
+    let mut id = object_id(current);
+    for token in tokens {
+        current = match current {
+            Value::Object(o) => &o[token],
+            Value::Array(a) => &a[token.parse::<usize>().ok()?],
+            _ => return id,
+        };
+        id = object_id(current).or(id);
+    }
+    id
+}
+
+fn object_id(v: &Value) -> Option<String> {
+    v.get(ID_FIELD)
+        .and_then(|id| id.as_str())
+        .map(|id| id.to_string())
+}
+
+fn select<'a>(
+    segments: &[Segment],
+    root: &'a Value,
+    current: &'a Value,
+) -> Vec<(Vec<String>, &'a Value)> {
+    let mut nodes = vec![(vec![], current)];
+    for segment in segments {
+        let mut next = vec![];
+        for (path, node) in nodes {
+            if segment.recursive {
+                let mut stack = vec![(path, node)];
+                while let Some((path, node)) = stack.pop() {
+                    apply_selectors(&segment.selectors, root, &path, node, &mut next);
+                    let mut children = children(&path, node);
+                    children.reverse();
+                    stack.extend(children);
+                }
+            } else {
+                apply_selectors(&segment.selectors, root, &path, node, &mut next);
+            }
+        }
+        nodes = next;
+    }
+    nodes
+}
+
+fn children<'a>(path: &[String], node: &'a Value) -> Vec<(Vec<String>, &'a Value)> {
+    let child = |token: String| {
+        let mut p = path.to_vec();
+        p.push(token);
+        p
+    };
+    match node {
+        Value::Object(o) => o.iter().map(|(k, v)| (child(k.clone()), v)).collect(),
+        Value::Array(a) => a
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (child(i.to_string()), v))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+fn apply_selectors<'a>(
+    selectors: &[Selector],
+    root: &'a Value,
+    path: &[String],
+    node: &'a Value,
+    out: &mut Vec<(Vec<String>, &'a Value)>,
+) {
+    let mut push = |token: String, value: &'a Value| {
+        let mut p = path.to_vec();
+        p.push(token);
+        out.push((p, value));
+    };
+    for selector in selectors {
+        match (selector, node) {
+            (Selector::Name(name), Value::Object(o)) => {
+                if let Some(v) = o.get(name) {
+                    push(name.clone(), v);
+                }
+            }
+            (Selector::Index(i), Value::Array(a)) => {
+                let i = if *i < 0 { a.len() as i64 + i } else { *i };
+                if i >= 0 && (i as usize) < a.len() {
+                    push(i.to_string(), &a[i as usize]);
+                }
+            }
+            (Selector::Slice(start, end, step), Value::Array(a)) => {
+                for i in slice_indices(a.len() as i64, *start, *end, *step) {
+                    push(i.to_string(), &a[i as usize]);
+                }
+            }
+            (Selector::Wildcard, _) => {
+                for (p, v) in children(&[], node) {
+                    push(p[0].clone(), v);
+                }
+            }
+            (Selector::Filter(expr), _) => {
+                for (p, v) in children(&[], node) {
+                    if truthy(expr, root, v) {
+                        push(p[0].clone(), v);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn slice_indices(len: i64, start: Option<i64>, end: Option<i64>, step: i64) -> Vec<i64> {
+    let normalize = |i: i64| if i < 0 { i + len } else { i };
+    let mut indices = vec![];
+    match step.cmp(&0) {
+        Ordering::Greater => {
+            let mut i = start.map(normalize).unwrap_or(0).clamp(0, len);
+            let end = end.map(normalize).unwrap_or(len).clamp(0, len);
+            while i < end {
+                indices.push(i);
+                i += step;
+            }
+        }
+        Ordering::Less => {
+            let mut i = start.map(normalize).unwrap_or(len - 1).clamp(-1, len - 1);
+            let end = end.map(normalize).unwrap_or(-1).clamp(-1, len - 1);
+            while i > end {
+                indices.push(i);
+                i += step;
+            }
+        }
+        Ordering::Equal => {}
+    }
+    indices
+}
+
+fn truthy(expr: &Expr, root: &Value, current: &Value) -> bool {
+    match expr {
+        Expr::Current(segments) => !select(segments, root, current).is_empty(),
+        Expr::Root(segments) => !select(segments, root, root).is_empty(),
+        Expr::Literal(v) => !matches!(v, Value::Null | Value::Bool(false)),
+        Expr::Not(e) => !truthy(e, root, current),
+        Expr::And(a, b) => truthy(a, root, current) && truthy(b, root, current),
+        Expr::Or(a, b) => truthy(a, root, current) || truthy(b, root, current),
+        Expr::Compare(a, op, b) => {
+            let a = evaluate(a, root, current);
+            let b = evaluate(b, root, current);
+            match *op {
+                "==" => a == b,
+                "!=" => a != b,
+                _ => {
+                    let ordering = match (&a, &b) {
+                        (Some(Value::Number(x)), Some(Value::Number(y))) => {
+                            x.as_f64().partial_cmp(&y.as_f64())
+                        }
+                        (Some(Value::String(x)), Some(Value::String(y))) => Some(x.cmp(y)),
+                        _ => None,
+                    };
+                    match ordering {
+                        Some(o) => match *op {
+                            "<" => o == Ordering::Less,
+                            "<=" => o != Ordering::Greater,
+                            ">" => o == Ordering::Greater,
+                            _ => o != Ordering::Less,
+                        },
+                        None => false,
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn evaluate(expr: &Expr, root: &Value, current: &Value) -> Option<Value> {
+    let single = |nodes: Vec<(Vec<String>, &Value)>| match nodes.as_slice() {
+        [(_, v)] => Some((*v).clone()),
+        _ => None,
+    };
+    match expr {
+        Expr::Current(segments) => single(select(segments, root, current)),
+        Expr::Root(segments) => single(select(segments, root, root)),
+        Expr::Literal(v) => Some(v.clone()),
+        _ => Some(Value::from(truthy(expr, root, current))),
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        s.chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.pos + i) == Some(&c))
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        self.skip_whitespace();
+        if !self.eat(c) {
+            bail!("expecting_{}_at_{}", c, self.pos);
+        }
+        Ok(())
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+            self.pos += 1;
+        }
+    }
+
+    fn segments(&mut self) -> Result<Vec<Segment>> {
+        let mut segments = vec![];
+        loop {
+            let recursive = self.starts_with("..");
+            if recursive {
+                self.pos += 2;
+            } else if self.peek() == Some('.') {
+                self.pos += 1;
+            } else if self.peek() != Some('[') {
+                break;
+            }
+            let selectors = if self.eat('[') {
+                self.bracket()?
+            } else if self.eat('*') {
+                vec![Selector::Wildcard]
+            } else {
+                vec![Selector::Name(self.name()?)]
+            };
+            segments.push(Segment {
+                recursive,
+                selectors,
+            });
+        }
+        Ok(segments)
+    }
+
+    fn name(&mut self) -> Result<String> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || ".[]()=!<>&|,'\"".contains(c) {
+                break;
+            }
+            self.pos += 1;
+        }
+        if start == self.pos {
+            bail!("expecting_name_at_{}", self.pos);
+        }
+        Ok(self.chars[start..self.pos].iter().collect())
+    }
+
+    fn bracket(&mut self) -> Result<Vec<Selector>> {
+        let mut selectors = vec![];
+        loop {
+            self.skip_whitespace();
+            if self.eat('?') {
+                selectors.push(Selector::Filter(self.or_expr()?));
+            } else if self.eat('*') {
+                selectors.push(Selector::Wildcard);
+            } else if matches!(self.peek(), Some('\'') | Some('"')) {
+                selectors.push(Selector::Name(self.string()?));
+            } else {
+                let start = self.integer()?;
+                self.skip_whitespace();
+                if self.eat(':') {
+                    let end = self.integer()?;
+                    self.skip_whitespace();
+                    let step = if self.eat(':') { self.integer()? } else { None };
+                    selectors.push(Selector::Slice(start, end, step.unwrap_or(1)));
+                } else {
+                    match start {
+                        Some(i) => selectors.push(Selector::Index(i)),
+                        None => bail!("invalid_selector_at_{}", self.pos),
+                    }
+                }
+            }
+            self.skip_whitespace();
+            if !self.eat(',') {
+                break;
+            }
+        }
+        self.expect(']')?;
+        Ok(selectors)
+    }
+
+    fn integer(&mut self) -> Result<Option<i64>> {
+        self.skip_whitespace();
+        let start = self.pos;
+        self.eat('-');
+        while self.peek().map(|c| c.is_ascii_digit()).unwrap_or(false) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Ok(None);
+        }
+        let s: String = self.chars[start..self.pos].iter().collect();
+        match s.parse::<i64>() {
+            Ok(i) => Ok(Some(i)),
+            Err(_) => bail!("invalid_integer_at_{}", start),
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let quote = self.peek().unwrap();
+        self.pos += 1;
+        let mut s = String::new();
+        loop {
+            match self.peek() {
+                None => bail!("unterminated_string"),
+                Some('\\') => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some(c) => s.push(c),
+                        None => bail!("unterminated_string"),
+                    }
+                }
+                Some(c) if c == quote => break,
+                Some(c) => s.push(c),
+            }
+            self.pos += 1;
+        }
+        self.pos += 1;
+        Ok(s)
+    }
+
+    fn or_expr(&mut self) -> Result<Expr> {
+        let mut left = self.and_expr()?;
+        loop {
+            self.skip_whitespace();
+            if !self.starts_with("||") {
+                return Ok(left);
+            }
+            self.pos += 2;
+            left = Expr::Or(Box::new(left), Box::new(self.and_expr()?));
+        }
+    }
+
+    fn and_expr(&mut self) -> Result<Expr> {
+        let mut left = self.unary_expr()?;
+        loop {
+            self.skip_whitespace();
+            if !self.starts_with("&&") {
+                return Ok(left);
+            }
+            self.pos += 2;
+            left = Expr::And(Box::new(left), Box::new(self.unary_expr()?));
+        }
+    }
+
+    fn unary_expr(&mut self) -> Result<Expr> {
+        self.skip_whitespace();
+        if self.peek() == Some('!') && !self.starts_with("!=") {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.unary_expr()?)));
+        }
+        let left = self.primary_expr()?;
+        self.skip_whitespace();
+        for op in ["==", "!=", "<=", ">=", "<", ">"] {
+            if self.starts_with(op) {
+                self.pos += op.len();
+                let right = self.primary_expr()?;
+                return Ok(Expr::Compare(Box::new(left), op, Box::new(right)));
+            }
+        }
+        Ok(left)
+    }
+
+    fn primary_expr(&mut self) -> Result<Expr> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                let e = self.or_expr()?;
+                self.expect(')')?;
+                Ok(e)
+            }
+            Some('@') => {
+                self.pos += 1;
+                Ok(Expr::Current(self.segments()?))
+            }
+            Some('$') => {
+                self.pos += 1;
+                Ok(Expr::Root(self.segments()?))
+            }
+            Some('\'') | Some('"') => Ok(Expr::Literal(Value::from(self.string()?))),
+            _ => {
+                let start = self.pos;
+                while self
+                    .peek()
+                    .map(|c| c.is_alphanumeric() || "+-.".contains(c))
+                    .unwrap_or(false)
+                {
+                    self.pos += 1;
+                }
+                let token: String = self.chars[start..self.pos].iter().collect();
+                match serde_json::from_str::<Value>(&token) {
+                    Ok(v) if !v.is_array() && !v.is_object() && !token.is_empty() => {
+                        Ok(Expr::Literal(v))
+                    }
+                    _ => bail!("invalid_expression_at_{}", start),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::jsonpath::JsonPath;
+    use serde_json::{json, Value};
+
+    fn values(expr: &str, doc: &Value) -> Vec<Value> {
+        JsonPath::parse(expr)
+            .unwrap()
+            .query(doc)
+            .into_iter()
+            .map(|m| m.value)
+            .collect()
+    }
+
+    #[test]
+    fn test_selectors() {
+        let doc = json!({"store": {"book": [
+            {"category": "reference", "author": "Nigel Rees", "price": 8.95},
+            {"category": "fiction", "author": "Evelyn Waugh", "price": 12.99},
+            {"category": "fiction", "author": "Herman Melville", "isbn": "0-553-21311-3", "price": 8.99},
+            {"category": "fiction", "author": "J. R. R. Tolkien", "isbn": "0-395-19395-8", "price": 22.99}
+        ], "bicycle": {"color": "red", "price": 19.95}}});
+        assert!(values("$.store.book[*].author", &doc).len() == 4);
+        assert!(values("$..author", &doc).len() == 4);
+        assert!(values("$.store.*", &doc).len() == 2);
+        assert!(values("$..price", &doc).len() == 5);
+        assert!(values("$..book[2].author", &doc) == vec![json!("Herman Melville")]);
+        assert!(values("$..book[-1].author", &doc) == vec![json!("J. R. R. Tolkien")]);
+        assert!(values("$..book[0,1].price", &doc) == vec![json!(8.95), json!(12.99)]);
+        assert!(values("$..book[:2].price", &doc) == vec![json!(8.95), json!(12.99)]);
+        assert!(values("$..book[::-2].price", &doc) == vec![json!(22.99), json!(12.99)]);
+        assert!(values("$['store']['bicycle']['color']", &doc) == vec![json!("red")]);
+        assert!(values("$..book[?(@.isbn)].price", &doc) == vec![json!(8.99), json!(22.99)]);
+        assert!(values("$..book[?(@.price < 10)].price", &doc) == vec![json!(8.95), json!(8.99)]);
+        assert!(
+            values(
+                "$..book[?(@.category == 'fiction' && !(@.price > 10))].author",
+                &doc
+            ) == vec![json!("Herman Melville")]
+        );
+        assert!(values("$..book[?(@.price > $.store.bicycle.price)]", &doc).len() == 1);
+        assert!(JsonPath::parse("store").is_err());
+        assert!(JsonPath::parse("$.store[").is_err());
+        assert!(JsonPath::parse("$.store[?(@.a == )]").is_err());
+    }
+
+    #[test]
+    fn test_query_identity() {
+        let doc = json!({"_id": "root", "items♭": [{"_id": "1", "tags": ["a", "b"]}, {"_id": "2", "tags": []}]});
+        let matches = JsonPath::parse("$['items♭'][*].tags[1]")
+            .unwrap()
+            .query(&doc);
+        assert!(matches.len() == 1);
+        assert!(matches[0].path == "/items♭/0/tags/1");
+        assert!(matches[0].id == Some("1".to_string()));
+        let matches = JsonPath::parse("$.items♭").unwrap().query(&doc);
+        assert!(matches[0].id == Some("root".to_string()));
+    }
+}
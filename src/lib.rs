@@ -13,19 +13,84 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
+#[cfg(all(feature = "openssl", feature = "rustcrypto"))]
+compile_error!(
+    "the openssl and rustcrypto features are mutually exclusive (openssl is a default feature: \
+     build with --no-default-features to enable rustcrypto or wasm)"
+);
+#[cfg(not(any(feature = "openssl", feature = "rustcrypto")))]
+compile_error!("either the openssl or the rustcrypto feature must be enabled");
+
 pub mod adapter;
+#[cfg(feature = "async")]
+pub mod asyncadapter;
+#[cfg(feature = "async")]
+pub mod asyncmelda;
+#[cfg(feature = "automergebridge")]
+pub mod automergebridge;
+#[cfg(feature = "backup")]
+pub mod backup;
+pub mod bench;
+mod blockcache;
 #[cfg(feature = "brotliadapter")]
 pub mod brotliadapter;
+pub mod builder;
+pub mod canonicaljson;
+pub mod capability;
+pub mod commitinfo;
 mod constants;
 mod datastorage;
+pub mod error;
+mod exclusivelock;
+#[cfg(feature = "ffi")]
+pub mod ffimelda;
 pub mod filesystemadapter;
+pub mod filetree;
 pub mod flate2adapter;
+#[cfg(feature = "fuse")]
+pub mod fusefs;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub mod indexeddbadapter;
+mod instrument;
+#[cfg(feature = "instrument")]
+pub mod instrumentedadapter;
+pub mod jsonpatch;
+pub mod jsonpath;
+mod locationindex;
 pub mod melda;
 pub mod memoryadapter;
+pub mod position;
+pub mod privateadapter;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+#[cfg(feature = "remote")]
+pub mod remoteadapter;
 mod revision;
 mod revisiontree;
+pub mod schema;
+mod secondaryindex;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod sharedvalue;
 #[cfg(feature = "solid")]
 pub mod solidadapter;
 #[cfg(feature = "sqlitedb")]
 pub mod sqliteadapter;
+#[cfg(feature = "sqlitedb")]
+pub mod sqliteexport;
+pub mod testing;
+mod textindex;
+pub mod topology;
+pub mod transaction;
 mod utils;
+#[cfg(feature = "wasm")]
+pub mod wasmmelda;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+pub mod workspace;
+mod yjs;
+
+#[cfg(feature = "ffi")]
+uniffi::setup_scaffolding!();
@@ -0,0 +1,222 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::melda::Location;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::PathBuf;
+
+/// Index of the location (parent container, position and JSON Pointer) of the objects
+/// reachable from the indexed documents. Entries reflect the state with the given generation
+/// (the counter of the changes of the revision trees): the index is stale as soon as the state
+/// changes, and is then rebuilt on demand. The index is optionally persisted to a file, along
+/// with the anchors of the state it reflects, so that reopening a repository does not require
+/// walking every document again.
+pub(crate) struct LocationIndex {
+    entries: HashMap<String, Location>,
+    generation: Option<u64>, // None if the index is stale
+    roots: BTreeSet<String>, // Root objects of the indexed documents
+    path: Option<PathBuf>,
+    persisted: Option<Persisted>, // Content of the file (until validated)
+}
+
+#[derive(Serialize, Deserialize)]
+struct Persisted {
+    anchors: BTreeSet<String>,
+    entries: HashMap<String, Location>,
+}
+
+impl LocationIndex {
+    /// Creates a new (stale) index. The entries persisted in the given file (if any) are used
+    /// once validated, see [LocationIndex::restore]. An unreadable file is ignored
+    pub fn new(path: Option<PathBuf>) -> Self {
+        let persisted: Option<Persisted> = path
+            .as_ref()
+            .and_then(|p| fs::read(p).ok())
+            .and_then(|content| serde_json::from_slice(&content).ok());
+        let roots = persisted
+            .iter()
+            .flat_map(|p| p.entries.iter())
+            .filter(|(_, location)| location.parent.is_none())
+            .map(|(uuid, _)| uuid.clone())
+            .collect();
+        LocationIndex {
+            entries: HashMap::new(),
+            generation: None,
+            roots,
+            path,
+            persisted,
+        }
+    }
+
+    /// Uses the persisted entries if they reflect the state with the given anchors (and no
+    /// staged changes), which has the given generation
+    pub fn restore(&mut self, anchors: &BTreeSet<String>, generation: u64) {
+        if let Some(persisted) = self.persisted.take() {
+            if persisted.anchors == *anchors {
+                self.entries = persisted.entries;
+                self.generation = Some(generation);
+            }
+        }
+    }
+
+    /// Returns the root objects of the indexed documents
+    pub fn roots(&self) -> &BTreeSet<String> {
+        &self.roots
+    }
+
+    /// Adds a document to the index (once rebuilt)
+    pub fn add_root(&mut self, root: &str) {
+        if !self.roots.contains(root) {
+            self.roots.insert(root.to_string());
+            self.generation = None;
+        }
+    }
+
+    /// Returns the entries if they reflect the state with the given generation
+    pub fn get(&self, generation: u64) -> Option<&HashMap<String, Location>> {
+        match self.generation {
+            Some(g) if g == generation => Some(&self.entries),
+            _ => None,
+        }
+    }
+
+    /// Returns the entries for updating them (the index then reflects the given generation)
+    pub fn get_mut(&mut self, generation: u64) -> Option<&mut HashMap<String, Location>> {
+        match self.generation {
+            Some(g) if g == generation => Some(&mut self.entries),
+            _ => None,
+        }
+    }
+
+    /// Replaces the entries with those reflecting the state with the given generation
+    pub fn replace(&mut self, entries: HashMap<String, Location>, generation: u64) {
+        self.entries = entries;
+        self.generation = Some(generation);
+    }
+
+    /// Marks the (up to date) index as reflecting the state with the given generation
+    pub fn advance(&mut self, from: u64, to: u64) {
+        if self.generation == Some(from) {
+            self.generation = Some(to);
+        }
+    }
+
+    /// Returns the estimated memory used by the entries (in bytes)
+    pub fn size(&self) -> usize {
+        self.entries
+            .iter()
+            .map(|(uuid, location)| {
+                uuid.len()
+                    + location.root.len()
+                    + location.parent.as_ref().map_or(0, |p| p.len())
+                    + location.position.iter().map(|t| t.len()).sum::<usize>()
+                    + location.pointer.len()
+            })
+            .sum()
+    }
+
+    /// Drops the entries from memory (the index is rebuilt when needed)
+    pub fn clear(&mut self) {
+        self.entries = HashMap::new();
+        self.generation = None;
+    }
+
+    /// Persists the entries (if a file was given and the index reflects the state with the
+    /// given generation), recording the anchors of the state. The file is replaced atomically
+    pub fn save(&self, anchors: &BTreeSet<String>, generation: u64) -> Result<()> {
+        if let (Some(path), Some(entries)) = (&self.path, self.get(generation)) {
+            let content = serde_json::to_vec(&serde_json::json!({
+                "anchors" : anchors,
+                "entries" : entries,
+            }))?;
+            let tmp = path.with_extension("tmp");
+            fs::write(&tmp, content)?;
+            fs::rename(&tmp, path)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::locationindex::LocationIndex;
+    use crate::melda::Melda;
+    use serde_json::json;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn test_location_index() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let url = format!("file://{}", dir.join("repository").display());
+        let path = dir.join("repository.locations");
+        let open = || {
+            Melda::builder()
+                .url(&url)
+                .location_index(&path)
+                .open()
+                .unwrap()
+        };
+        let replica = open();
+        let object = json!({ "items♭" : [ { "_id" : "1" }, { "_id" : "2" } ] });
+        replica.update(object.as_object().unwrap().clone()).unwrap();
+        replica.commit(None).unwrap();
+        assert_eq!(
+            replica.get_location("2").unwrap().unwrap().pointer,
+            "/items♭/1"
+        );
+        // The index is persisted along with the anchors of the state
+        let anchors = replica.get_anchors();
+        let mut index = LocationIndex::new(Some(path.clone()));
+        assert!(index.get(0).is_none());
+        index.restore(&BTreeSet::new(), 0);
+        assert!(index.get(0).is_none());
+        let mut index = LocationIndex::new(Some(path.clone()));
+        index.restore(&anchors, 7);
+        assert!(index.get(7).unwrap().contains_key("1"));
+        assert!(index.get(8).is_none());
+        // Reopening the repository reuses the index
+        let reopened = open();
+        assert_eq!(
+            reopened.get_location("1").unwrap().unwrap().pointer,
+            "/items♭/0"
+        );
+        reopened.set("/items♭/0", json!({ "_id" : "3" })).unwrap();
+        assert_eq!(
+            reopened.get_location("3").unwrap().unwrap().pointer,
+            "/items♭/0"
+        );
+        assert!(reopened.get_location("1").unwrap().is_none());
+        // Documents updated with update_document are indexed
+        reopened
+            .update_document(
+                "notes",
+                json!({ "n♭" : [ { "_id" : "4" } ] })
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+            )
+            .unwrap();
+        let location = reopened.get_location("4").unwrap().unwrap();
+        assert_eq!(
+            (location.root.as_str(), location.pointer.as_str()),
+            ("notes", "/n♭/0")
+        );
+        index.clear();
+        assert!(index.get(7).is_none() && index.size() == 0);
+    }
+}
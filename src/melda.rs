@@ -14,26 +14,74 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 use crate::adapter::Adapter;
+use crate::blockcache::BlockCache;
+use crate::builder::{ArrayOrdering, DigestAlgorithm, Encoding, MeldaBuilder};
+use crate::commitinfo::{CommitInfo, CommitValidator};
 use crate::constants::{
-    ARRAY_DESCRIPTOR_DELTA_ORDER_FIELD, ARRAY_DESCRIPTOR_ORDER_FIELD, CHANGESETS_FIELD,
-    DELTA_EXTENSION, ID_FIELD, INFORMATION_FIELD, OBJECTS_FIELD, PACK_FIELD, PARENTS_FIELD,
-    ROOT_ID,
+    ARRAY_DESCRIPTOR_DELTA_ORDER_FIELD, ARRAY_DESCRIPTOR_ORDER_FIELD, ARRAY_DESCRIPTOR_PREFIX,
+    ATTACHMENT_EXTENSION, CHANGESETS_FIELD, CONFLICT_ALTERNATIVES_FIELD, CONFLICT_WINNER_FIELD,
+    DELTA_EXTENSION, DIGEST_FIELD, FORMAT_VERSION, FORMAT_VERSION_FIELD, GRAFT_FIELD, ID_FIELD,
+    INDEX_EXTENSION, INFORMATION_FIELD, METADATA_EXTENSION, OBJECTS_FIELD, PACK_EXTENSION,
+    PACK_FIELD, PARALLEL_UNFLATTEN_THRESHOLD, PARENTS_FIELD, REPLICA_FIELD, REPLICA_ID_FIELD,
+    REPLICA_METADATA, REPOSITORY_METADATA, ROOT_ID, SCHEMA_METADATA, SQUASHED_FIELD,
+    STRING_ESCAPE_PREFIX, TIMESTAMP_FIELD,
 };
 use crate::datastorage::DataStorage;
+use crate::error::MeldaError;
+use crate::exclusivelock::{ExclusiveGuard, ExclusiveLock};
+use crate::instrument::operation_span;
+use crate::jsonpatch::PatchOp;
+use crate::locationindex::LocationIndex;
 use crate::revision::Revision;
 use crate::revisiontree::RevisionTree;
+use crate::schema::{Schema, SchemaViolation};
+use crate::secondaryindex::{IndexChanges, SecondaryIndex};
+use crate::sharedvalue::SharedValue;
+use crate::textindex::TextIndex;
+use crate::transaction::Transaction;
 use crate::utils::{
-    apply_diff_patch, digest_bytes, digest_object, digest_string, flatten, is_array_descriptor,
-    make_diff_patch, merge_arrays, unflatten,
+    apply_diff_patch, approximate_object_size, decode_value, digest_bytes_with, digest_object,
+    digest_string, encode_json_pointer, encode_value, flatten, flatten_field, generate_identifier,
+    is_array_descriptor, is_flattened_field, make_chunked_diff_patch, merge_arrays, now_millis,
+    par_unflatten, parse_json_pointer, random_bytes, unescape, unflatten, StrictValidator,
 };
 use anyhow::{anyhow, bail, Result};
+use arc_swap::ArcSwapOption;
 use lru::LruCache;
 use rayon::prelude::*;
-use serde_json::{Map, Value};
+use serde::de::{self, DeserializeOwned, DeserializeSeed, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex, RwLock};
 
+mod attachments;
+mod conflicts;
+mod export;
+mod history;
+mod integrity;
+mod maintenance;
+mod pointer;
+mod search;
+mod stats;
+mod trace;
+
+pub use self::conflicts::{
+    AutoResolutionReport, ConflictCandidate, ConflictInfo, ConflictPolicy, ConflictReport,
+    ResolutionChoice, ResolutionNote, ResolutionRecord,
+};
+pub use self::history::{
+    BlockFilter, BlockSummary, Causality, ChangeFeed, ChangeRecord, Cursor, ObjectRevision,
+    PackSummary, Page,
+};
+pub use self::integrity::{FsckIssue, FsckOptions, FsckReport, RepairReport};
+pub use self::maintenance::{CompactionReport, GcReport, RetentionPolicy, RetentionReport};
+pub use self::search::SearchHit;
+pub use self::stats::{MemoryUsage, ReplicaSummary, RepositoryStats};
+
 /// Change triple (used for storing block changesets)
 #[derive(PartialEq, Clone)]
 struct Change(String, Revision, Option<Revision>);
@@ -44,8 +92,97 @@ pub struct Melda {
     data: RwLock<DataStorage>,
     blocks: RwLock<BTreeMap<String, RwLock<Block>>>,
     array_descriptors_cache: Mutex<LruCache<Revision, ArrayDescriptor>>,
+    object_cache: Mutex<LruCache<ObjectCacheKey, Map<String, Value>>>,
+    encoding: Encoding,
+    digest: DigestAlgorithm,
+    subscribers: Mutex<Vec<Sender<ChangeEvent>>>,
+    stashed: Mutex<Vec<Value>>,
+    replica_id: Mutex<Option<String>>, // Identifier of the replica (generated when first needed)
+    block_cache: Mutex<BlockCache>,
+    squash_policy: SquashPolicy,
+    retention_policy: RetentionPolicy,
+    local_blocks: Mutex<Vec<(String, usize)>>, // Blocks committed since the last squash, never melded out (and their size)
+    meld_parallelism: usize,
+    array_chunk_size: usize,
+    ordering: ArrayOrdering, // Order of the arrays changed concurrently
+    pending_blocks: Mutex<BTreeSet<String>>, // Loaded blocks which have not been applied yet
+    memory_budget: Option<usize>,
+    clean_fields: Mutex<CleanFields>,
+    generation: AtomicU64, // Incremented whenever a revision tree changes
+    locations: Mutex<LocationIndex>,
+    shared: Mutex<HashMap<String, SharedEntry>>, // Objects materialized by the last shared read
+    trace: Option<Mutex<Vec<Value>>>, // Events recorded for replay (if tracing is enabled)
+    strict: bool,
+    lazy_attachments: bool, // Attachments are not transferred when melding
+    schema: RwLock<Option<Schema>>,
+    schema_violations: Mutex<Vec<SchemaViolation>>, // Violations of the state after the last refresh
+    indexes: Mutex<BTreeMap<(String, String), SecondaryIndex>>, // Secondary indexes (by collection and field)
+    index_changes: Mutex<IndexChanges>, // Objects changed since the secondary indexes were updated
+    text_index: Mutex<Option<TextIndex>>, // Full-text index (if enabled)
+    conflict_policy: Option<ConflictPolicy>,
+    auto_resolution: Mutex<AutoResolutionReport>, // Outcome of the last automatic resolution
+    field_level_conflicts: bool, // Concurrent changes to different fields are merged on refresh
+    commit_validator: Option<Arc<CommitValidator>>,
+    pre_commit_hooks: Mutex<Vec<Arc<PreCommitHook>>>,
+    post_commit_hooks: Mutex<Vec<Arc<PostCommitHook>>>,
+    exclusive: ExclusiveLock, // Serializes commits, refreshes, reloads and unstaging
+    published: ArcSwapOption<PublishedState>, // State materialized by the last full read
+}
+
+// Object (or flattened array) materialized by a shared read, along with the winning revision
+// and the children it was built from: the value is reused as long as neither changes
+struct SharedEntry {
+    winner: Revision,
+    children: Vec<SharedValue>,
+    value: SharedValue,
+    size: usize, // Estimated memory used by the content of the object (excluding the children)
+}
+
+// State materialized by a full read, published so that subsequent reads (as long as no
+// revision tree changed) do not acquire any lock
+struct PublishedState {
+    generation: u64, // Generation of the revision trees the state was read from
+    objects: HashMap<String, Map<String, Value>>, // Winning value of each object
+    state: Map<String, Value>,
 }
 
+// Flattened field of the root object staged by the last update, along with the objects
+// extracted from it (and their winning revision once staged)
+struct CleanField {
+    value: Value,
+    flattened: Value,
+    objects: Vec<(String, Option<Revision>)>,
+}
+
+// Fields of the root object which did not change since the last update: the next update
+// reuses them instead of flattening and hashing their subtrees again, unless they have been
+// touched in the meantime (through the pointer and patch APIs, or because the winning revision
+// of one of their objects changed)
+#[derive(Default)]
+struct CleanFields {
+    root: String,
+    fields: HashMap<String, CleanField>,
+}
+
+// Changes planned by an update: the flattened objects to be staged, the objects which
+// disappeared from the document, the fields reused from the last update and the fields
+// flattened again (along with their value, their flattened value and the extracted objects)
+struct UpdatePlan {
+    root: String,
+    objects: HashMap<String, Map<String, Value>>,
+    deleted: Vec<String>,
+    reused_fields: Vec<String>,
+    changed_fields: Vec<(String, Value, Value, Vec<String>)>,
+}
+
+// Estimated memory used by an entry of a revision tree (the revision, its parent and their
+// digests)
+const REVISION_ENTRY_SIZE: usize = 2 * (std::mem::size_of::<Revision>() + 64);
+
+// Materialized objects are identified by the object identifier, the revision and (for array
+// descriptors, whose order is merged with the conflicting revisions) the leafs
+type ObjectCacheKey = (String, Revision, Vec<Revision>);
+
 #[derive(PartialEq, Copy, Clone, Debug)]
 
 /// Status of a cblock
@@ -64,10 +201,315 @@ pub struct Block {
     pub parents: Option<BTreeSet<String>>,
     pub info: Option<Map<String, Value>>,
     pub packs: Option<BTreeSet<String>>,
+    pub squashed: Option<BTreeSet<String>>,
+    pub grafted: Option<BTreeSet<String>>,
+    pub timestamp: Option<u64>,
+    pub replica: Option<String>,
     changes: Option<Vec<Change>>,
     status: Status,
 }
 
+/// Kind of change undergone by an object
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Created,
+    Updated,
+    Deleted,
+    /// The object got in conflict after a refresh (only notified to subscribers)
+    Conflicted,
+    /// The conflict of the object has been resolved by a refresh (notified to subscribers), or
+    /// a conflicting revision has been marked as resolved (see [Melda::object_history])
+    Resolved,
+}
+
+/// Event notified to subscribers when an object changes after a commit or a refresh
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeEvent {
+    /// Identifier of the object (array descriptors represent flattened arrays)
+    pub uuid: String,
+    /// JSON Pointer to the object in the current state, None if the object is not reachable
+    pub path: Option<String>,
+    /// Kind of change
+    pub kind: ChangeKind,
+    /// Block which introduced the change
+    pub block: String,
+}
+
+/// Immutable view of the state, see [Melda::snapshot]. Clones share the same data
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    anchors: Arc<BTreeSet<String>>,
+    state: SharedValue,
+}
+
+impl Snapshot {
+    /// Returns the anchors of the replica when the snapshot was taken
+    pub fn anchors(&self) -> &BTreeSet<String> {
+        &self.anchors
+    }
+
+    /// Returns the state (from the root object)
+    pub fn state(&self) -> &SharedValue {
+        &self.state
+    }
+
+    /// Returns the value identified by a JSON Pointer (RFC 6901)
+    pub fn pointer(&self, ptr: &str) -> Option<&SharedValue> {
+        self.state.pointer(ptr)
+    }
+
+    /// Materializes the state, like [Melda::read]
+    pub fn read(&self) -> Map<String, Value> {
+        match self.state.to_value() {
+            Value::Object(o) => o,
+            _ => unreachable!("root_not_an_object"),
+        }
+    }
+}
+
+/// Location of an object (or of a flattened array) inside a document, see [Melda::get_location]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Location {
+    /// Identifier of the root object of the document
+    pub root: String,
+    /// Identifier of the object (or array descriptor) containing the object, None for the root
+    pub parent: Option<String>,
+    /// Position inside the parent: the field (or the index in the array), followed by the
+    /// indexes inside nested arrays
+    pub position: Vec<String>,
+    /// JSON Pointer to the object from the root
+    pub pointer: String,
+}
+
+impl Location {
+    fn root(root: &str) -> Location {
+        Location {
+            root: root.to_string(),
+            parent: None,
+            position: vec![],
+            pointer: String::new(),
+        }
+    }
+}
+
+/// Outcome of a meld previewed with [Melda::simulate_meld]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MergePreview {
+    /// Blocks of the other replica which would be melded
+    pub blocks: BTreeSet<String>,
+    /// Objects whose winning value would change (array descriptors are not included)
+    pub changes: BTreeMap<String, ChangeKind>,
+    /// Objects which would get in conflict
+    pub conflicts: BTreeSet<String>,
+    /// Final order (identifiers of the elements) of the arrays whose order would change, by
+    /// JSON Pointer
+    pub arrays: BTreeMap<String, Vec<String>>,
+}
+
+/// Hook run before each commit, see [Melda::add_pre_commit_hook]
+pub type PreCommitHook =
+    dyn Fn(&Melda, &mut CommitInfo) -> std::result::Result<(), String> + Send + Sync;
+
+/// Hook run after each commit, see [Melda::add_post_commit_hook]
+pub type PostCommitHook = dyn Fn(&str, &CommitInfo) + Send + Sync;
+
+/// A change staged as part of a batch, see [Melda::commit_batch]
+#[derive(Debug, Clone, PartialEq)]
+pub enum BatchChange {
+    /// Replaces a whole document, see [Melda::update_document]
+    Update {
+        root: String,
+        object: Map<String, Value>,
+    },
+    /// Sets the value identified by a JSON Pointer, see [Melda::set]
+    Set { ptr: String, value: Value },
+    /// Applies a JSON Patch, see [Melda::apply_patch]
+    Patch(Vec<PatchOp>),
+}
+
+/// Changes that an update would stage
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChangeSet {
+    /// Objects that would be created
+    pub created: BTreeSet<String>,
+    /// Objects that would be updated
+    pub updated: BTreeSet<String>,
+    /// Objects that would be deleted
+    pub deleted: BTreeSet<String>,
+    /// Flattened arrays (array descriptors) that would be created, changed or deleted
+    pub arrays: BTreeSet<String>,
+}
+
+impl ChangeSet {
+    /// Returns the number of objects that would be created, updated or deleted
+    pub fn len(&self) -> usize {
+        self.created.len() + self.updated.len() + self.deleted.len()
+    }
+
+    /// Returns true if nothing would be staged
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0 && self.arrays.is_empty()
+    }
+}
+
+/// Estimate of what the next commit would write, see [Melda::estimate_commit]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CommitEstimate {
+    /// Number of objects with staged changes (array descriptors excluded)
+    pub objects: usize,
+    /// Number of staged revisions (of objects and arrays)
+    pub revisions: usize,
+    /// Number of values written to the data pack
+    pub values: usize,
+    /// Approximate size (in bytes) of the data pack
+    pub pack_bytes: usize,
+    /// Approximate size (in bytes) of the delta block
+    pub block_bytes: usize,
+}
+
+impl CommitEstimate {
+    /// Returns the approximate number of bytes written by the commit
+    pub fn total_bytes(&self) -> usize {
+        self.pack_bytes + self.block_bytes
+    }
+}
+
+/// Difference between two replicas, see [Melda::compare]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Divergence {
+    /// Blocks which are only available in this replica
+    pub local_only: BTreeSet<String>,
+    /// Blocks which are only available in the other replica
+    pub remote_only: BTreeSet<String>,
+    /// Number of bytes melding the other replica into this one would transfer
+    pub fetch_bytes: usize,
+    /// Number of bytes melding this replica into the other one would transfer
+    pub send_bytes: usize,
+}
+
+impl Divergence {
+    /// Returns true if both replicas have the same blocks
+    pub fn in_sync(&self) -> bool {
+        self.local_only.is_empty() && self.remote_only.is_empty()
+    }
+
+    /// Returns true if the history of this replica is strictly contained in the history of the
+    /// other one (melding the other replica fast-forwards this one)
+    pub fn is_ancestor(&self) -> bool {
+        self.local_only.is_empty() && !self.remote_only.is_empty()
+    }
+
+    /// Returns true if the history of the other replica is strictly contained in the history of
+    /// this one
+    pub fn is_descendant(&self) -> bool {
+        !self.local_only.is_empty() && self.remote_only.is_empty()
+    }
+
+    /// Returns true if each replica has blocks the other one does not have
+    pub fn has_diverged(&self) -> bool {
+        !self.local_only.is_empty() && !self.remote_only.is_empty()
+    }
+}
+
+/// Policy deciding when the blocks committed by a replica are squashed: once a threshold is
+/// reached, the next commit consolidates the blocks committed since the last squash into a
+/// single block, deleting them. Blocks which have been melded into another replica (see
+/// [Melda::meld]), exported (see [Melda::export_bundle]) or referenced by other blocks are
+/// never squashed. Squashing is disabled by default: enable it only if no other replica reads
+/// the blocks directly from the storage of this replica (for instance through a server), as
+/// such transfers cannot be tracked
+///
+/// # Example
+/// ```
+/// use melda::melda::{Melda, SquashPolicy};
+/// use serde_json::json;
+/// let policy = SquashPolicy { max_blocks: Some(3), max_bytes: None };
+/// let replica = Melda::builder().url("memory://").squash_policy(policy).open().unwrap();
+/// let adapter = replica.get_adapter();
+/// let mut other = None;
+/// for i in 0..5 {
+///     replica.update(json!({ "key" : i }).as_object().unwrap().clone()).unwrap();
+///     replica.commit(None).unwrap();
+///     if i == 1 {
+///         // Another replica using the same storage
+///         other = Some(Melda::new(adapter.clone()).unwrap());
+///     }
+/// }
+/// assert_eq!(adapter.read().unwrap().list_objects(".delta").unwrap().len(), 2);
+/// assert_eq!(replica.read(None).unwrap().get("key").unwrap(), 4);
+/// assert_eq!(Melda::new(adapter.clone()).unwrap().read(None).unwrap().get("key").unwrap(), 4);
+/// // Blocks based on squashed blocks remain valid
+/// let other = other.unwrap();
+/// other.create_object("note", json!({ "text" : "hello" }).as_object().unwrap().clone()).unwrap();
+/// other.commit(None).unwrap();
+/// replica.refresh().unwrap();
+/// assert!(replica.get_all_objects().contains("note"));
+/// assert_eq!(replica.get_anchors().len(), 2);
+/// assert!(Melda::new(adapter).unwrap().get_all_objects().contains("note"));
+/// assert!(replica.fsck().unwrap().is_clean());
+/// // Blocks melded into another replica are not squashed
+/// let replica = Melda::builder().url("memory://").squash_policy(policy).open().unwrap();
+/// let other = Melda::new_from_url("memory://").unwrap();
+/// for i in 0..5 {
+///     replica.update(json!({ "key" : i }).as_object().unwrap().clone()).unwrap();
+///     replica.commit(None).unwrap();
+///     other.meld(&replica).unwrap();
+/// }
+/// assert_eq!(replica.get_adapter().read().unwrap().list_objects(".delta").unwrap().len(), 5);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SquashPolicy {
+    /// Squash when the number of local blocks reaches this value
+    pub max_blocks: Option<usize>,
+    /// Squash when the total size (in bytes) of the local blocks reaches this value
+    pub max_bytes: Option<usize>,
+}
+
+impl SquashPolicy {
+    /// Returns true if the given local blocks must be squashed
+    fn is_reached(&self, blocks: usize, bytes: usize) -> bool {
+        blocks > 0
+            && (self.max_blocks.is_some_and(|max| blocks >= max)
+                || self.max_bytes.is_some_and(|max| bytes >= max))
+    }
+}
+
+// Content of the local blocks to be squashed into the next block
+struct PendingSquash {
+    squashed: BTreeSet<String>,
+    parents: BTreeSet<String>,
+    packs: BTreeSet<String>,
+    changes: Vec<Value>,
+}
+
+/// Iterator over the elements of a collection (flattened array of objects), which are
+/// loaded and reconstructed one at a time
+pub struct CollectionIter<'a> {
+    melda: &'a Melda,
+    order: std::vec::IntoIter<String>,
+}
+
+impl Iterator for CollectionIter<'_> {
+    type Item = Result<(String, Value)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for uuid in self.order.by_ref() {
+            match self.melda.load_flattened_object(&uuid) {
+                Ok(Some(object)) => {
+                    let value =
+                        self.melda
+                            .resolve_flattened_object(&uuid, object, &mut BTreeSet::new());
+                    return Some(value.map(|v| (uuid, v)));
+                }
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        None
+    }
+}
+
 // Array descriptor represents an array descriptor. It is used to support reconstruction of delta descriptors
 #[derive(Clone)]
 struct ArrayDescriptor {
@@ -187,23 +629,10 @@ impl Melda {
     /// use std::sync::{Arc, Mutex, RwLock};
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
     /// ```
     pub fn new(adapter: Arc<RwLock<Box<dyn Adapter>>>) -> Result<Melda> {
-        let cache_size = std::env::var("MELDA_ARRAYDESCRIPTORS_CACHE_CAP")
-            .unwrap_or_else(|_| "16".to_string())
-            .parse::<u32>()
-            .unwrap() as usize;
-        let dc = Melda {
-            documents: RwLock::new(BTreeMap::<String, Mutex<RevisionTree>>::new()),
-            data: RwLock::new(DataStorage::new(adapter.clone())),
-            blocks: RwLock::new(BTreeMap::new()),
-            array_descriptors_cache: Mutex::new(LruCache::<Revision, ArrayDescriptor>::new(
-                NonZeroUsize::new(cache_size).unwrap(),
-            )),
-        };
-        dc.reload()?;
-        Ok(dc)
+        MeldaBuilder::new().adapter(adapter).open()
     }
 
     /// Initializes a new Melda data structure using the provided Url
@@ -217,24 +646,10 @@ impl Melda {
     /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
     /// use std::sync::{Arc, Mutex, RwLock};
     /// use serde_json::{Map, Value,json};
-    /// let mut replica = Melda::new_from_url("memory+flate://").expect("cannot_initialize_crdt");
+    /// let replica = Melda::new_from_url("memory+flate://").expect("cannot_initialize_crdt");
     /// ```
     pub fn new_from_url(url: &str) -> Result<Melda> {
-        let cache_size = std::env::var("MELDA_ARRAYDESCRIPTORS_CACHE_CAP")
-            .unwrap_or_else(|_| "16".to_string())
-            .parse::<u32>()
-            .unwrap() as usize;
-        let adapter = Arc::new(RwLock::new(crate::adapter::get_adapter(url).unwrap()));
-        let dc = Melda {
-            documents: RwLock::new(BTreeMap::<String, Mutex<RevisionTree>>::new()),
-            data: RwLock::new(DataStorage::new(adapter.clone())),
-            blocks: RwLock::new(BTreeMap::new()),
-            array_descriptors_cache: Mutex::new(LruCache::<Revision, ArrayDescriptor>::new(
-                NonZeroUsize::new(cache_size).unwrap(),
-            )),
-        };
-        dc.reload()?;
-        Ok(dc)
+        MeldaBuilder::new().url(url).open()
     }
 
     /// Returns the underlying storage adapter
@@ -257,7 +672,7 @@ impl Melda {
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
     /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
     /// replica.create_object("myobject", object);  
     /// assert!(replica.get_all_objects().contains("myobject"));
@@ -273,7 +688,7 @@ impl Melda {
     /// assert!(value.unwrap().contains_key("_deleted"));
     /// let info = json!({ "author" : "Some user", "date" : "2022-05-23 13:47:00CET" }).as_object().unwrap().clone();
     /// replica.commit(Some(info));
-    /// let mut replica = Melda::new_until(adapter, &committed_anchors).expect("cannot_initialize_crdt");
+    /// let replica = Melda::new_until(adapter, &committed_anchors).expect("cannot_initialize_crdt");
     /// let winner = replica.get_winner("myobject").unwrap();
     /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
     /// ```
@@ -281,20 +696,7 @@ impl Melda {
         adapter: Arc<RwLock<Box<dyn Adapter>>>,
         anchors: &BTreeSet<String>,
     ) -> Result<Melda> {
-        let cache_size = std::env::var("MELDA_ARRAYDESCRIPTORS_CACHE_CAP")
-            .unwrap_or_else(|_| "16".to_string())
-            .parse::<u32>()
-            .unwrap() as usize;
-        let dc = Melda {
-            documents: RwLock::new(BTreeMap::<String, Mutex<RevisionTree>>::new()),
-            data: RwLock::new(DataStorage::new(adapter.clone())),
-            blocks: RwLock::new(BTreeMap::new()),
-            array_descriptors_cache: Mutex::new(LruCache::<Revision, ArrayDescriptor>::new(
-                NonZeroUsize::new(cache_size).unwrap(),
-            )),
-        };
-        dc.reload_until(anchors)?;
-        Ok(dc)
+        MeldaBuilder::new().adapter(adapter).until(anchors).open()
     }
 
     /// Initializes a new Melda data structure using the provided Url and loads until the given block
@@ -306,23 +708,146 @@ impl Melda {
     ///
     /// ```
     pub fn new_from_url_until(url: &str, anchors: &BTreeSet<String>) -> Result<Melda> {
-        let cache_size = std::env::var("MELDA_ARRAYDESCRIPTORS_CACHE_CAP")
-            .unwrap_or_else(|_| "16".to_string())
-            .parse::<u32>()
-            .unwrap() as usize;
-        let adapter = Arc::new(RwLock::new(crate::adapter::get_adapter(url).unwrap()));
+        MeldaBuilder::new().url(url).until(anchors).open()
+    }
+
+    /// Returns a builder to configure and open a Melda data structure
+    pub fn builder() -> MeldaBuilder {
+        MeldaBuilder::new()
+    }
+
+    // Initializes the data structure and loads the state (until the given anchors, if any)
+    pub(crate) fn open(
+        adapter: Arc<RwLock<Box<dyn Adapter>>>,
+        options: &MeldaBuilder,
+    ) -> Result<Melda> {
+        let capacity = |capacity: Option<usize>| {
+            capacity
+                .and_then(NonZeroUsize::new)
+                .ok_or_else(|| anyhow!("invalid_cache_capacity"))
+        };
+        let digest = Self::repository_digest_algorithm(&adapter, options.digest)?;
+        let schema = read_schema(adapter.read().unwrap().as_ref())?;
+        let replica_id = match &options.replica_id {
+            Some(id) if id.is_empty() => bail!("invalid_replica_id"),
+            Some(id) => {
+                write_replica_metadata(adapter.read().unwrap().as_ref(), id)?;
+                Some(id.clone())
+            }
+            None => read_replica_metadata(adapter.read().unwrap().as_ref())?,
+        };
         let dc = Melda {
             documents: RwLock::new(BTreeMap::<String, Mutex<RevisionTree>>::new()),
-            data: RwLock::new(DataStorage::new(adapter.clone())),
+            data: RwLock::new({
+                let mut data =
+                    DataStorage::new(adapter, capacity(options.data_cache_capacity)?.get());
+                data.set_encoding(options.encoding);
+                data.set_digest_algorithm(digest);
+                data.set_structural_deltas(options.structural_deltas);
+                data
+            }),
             blocks: RwLock::new(BTreeMap::new()),
             array_descriptors_cache: Mutex::new(LruCache::<Revision, ArrayDescriptor>::new(
-                NonZeroUsize::new(cache_size).unwrap(),
+                capacity(options.array_descriptors_cache_capacity)?,
             )),
+            object_cache: Mutex::new(LruCache::new(capacity(options.object_cache_capacity)?)),
+            subscribers: Mutex::new(vec![]),
+            stashed: Mutex::new(vec![]),
+            replica_id: Mutex::new(replica_id),
+            block_cache: Mutex::new(BlockCache::new(options.block_cache.clone())),
+            encoding: options.encoding,
+            digest,
+            squash_policy: options.squash_policy,
+            retention_policy: options.retention_policy,
+            local_blocks: Mutex::new(vec![]),
+            meld_parallelism: options.meld_parallelism,
+            array_chunk_size: options.array_chunk_size,
+            ordering: options.ordering,
+            pending_blocks: Mutex::new(BTreeSet::new()),
+            memory_budget: options.memory_budget,
+            clean_fields: Mutex::new(CleanFields::default()),
+            generation: AtomicU64::new(0),
+            locations: Mutex::new(LocationIndex::new(options.location_index.clone())),
+            shared: Mutex::new(HashMap::new()),
+            trace: options.trace.then(|| Mutex::new(vec![])),
+            strict: options.strict,
+            lazy_attachments: options.lazy_attachments,
+            schema: RwLock::new(schema),
+            schema_violations: Mutex::new(vec![]),
+            indexes: Mutex::new(BTreeMap::new()),
+            index_changes: Mutex::new(IndexChanges {
+                tracking: options.full_text_index,
+                all: options.full_text_index,
+                ..IndexChanges::default()
+            }),
+            text_index: Mutex::new(options.full_text_index.then(TextIndex::default)),
+            conflict_policy: options.conflict_policy.clone(),
+            auto_resolution: Mutex::new(AutoResolutionReport::default()),
+            field_level_conflicts: options.field_level_conflicts,
+            commit_validator: options.commit_validator.clone(),
+            pre_commit_hooks: Mutex::new(vec![]),
+            post_commit_hooks: Mutex::new(vec![]),
+            exclusive: ExclusiveLock::default(),
+            published: ArcSwapOption::empty(),
         };
-        dc.reload_until(anchors)?;
+        match options.anchors.as_ref() {
+            Some(anchors) => dc.reload_until(anchors)?,
+            None => dc.reload()?,
+        }
+        if !dc.has_staging() {
+            let generation = dc.generation.load(Ordering::SeqCst);
+            let anchors = dc.get_anchors();
+            dc.locations.lock().unwrap().restore(&anchors, generation);
+        }
         Ok(dc)
     }
 
+    // Returns the digest algorithm recorded in the repository metadata (SHA-256 if there is no
+    // metadata). Repositories written with a newer version of the format are refused, whereas
+    // the metadata of an empty repository without metadata is written with the current version
+    // and the requested algorithm
+    fn repository_digest_algorithm(
+        adapter: &Arc<RwLock<Box<dyn Adapter>>>,
+        requested: Option<DigestAlgorithm>,
+    ) -> Result<DigestAlgorithm> {
+        let adapter = adapter.read().unwrap();
+        let metadata = read_repository_metadata(adapter.as_ref())?;
+        let version = format_version(metadata.as_ref());
+        if version > FORMAT_VERSION {
+            bail!(MeldaError::FormatVersionMismatch {
+                found: version,
+                expected: FORMAT_VERSION
+            });
+        }
+        let recorded = match metadata.as_ref().and_then(|m| m.get(DIGEST_FIELD)) {
+            Some(name) => Some(DigestAlgorithm::from_name(
+                name.as_str()
+                    .ok_or_else(|| anyhow!("invalid_repository_metadata"))?,
+            )?),
+            None => None,
+        };
+        if !adapter.list_objects(DELTA_EXTENSION)?.is_empty()
+            || !adapter.list_objects(PACK_EXTENSION)?.is_empty()
+        {
+            // Repositories written before the metadata was introduced use SHA-256
+            let recorded = recorded.unwrap_or(DigestAlgorithm::Sha256);
+            if requested.is_some_and(|requested| requested != recorded) {
+                bail!("digest_algorithm_mismatch")
+            }
+            return Ok(recorded);
+        }
+        if let (Some(recorded), Some(requested)) = (recorded, requested) {
+            if recorded != requested {
+                bail!("digest_algorithm_mismatch")
+            }
+        }
+        let algorithm = recorded.or(requested).unwrap_or(DigestAlgorithm::Sha256);
+        if metadata.is_none() {
+            write_repository_metadata(adapter.as_ref(), algorithm, FORMAT_VERSION)?;
+        }
+        Ok(algorithm)
+    }
+
     /// Records the creation of an object
     ///
     /// # Arguments
@@ -336,7 +861,7 @@ impl Melda {
     /// use std::sync::{Arc, Mutex, RwLock};
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey" : [ "somedata", 1, 2, 3, 4 ] }).as_object().unwrap().clone();
     /// let result = replica.create_object("myobject", object.clone());
     /// assert!(result.is_ok());
@@ -346,6 +871,16 @@ impl Melda {
     /// assert!(result.unwrap().is_none());
     /// ```
     pub fn create_object(&self, uuid: &str, obj: Map<String, Value>) -> Result<Option<String>> {
+        let traced = self.trace.as_ref().map(|_| obj.clone());
+        let result = self.stage_creation(uuid, obj);
+        if let (Ok(_), Some(obj)) = (&result, traced) {
+            self.record(|| json!({ "op": "create", "uuid": uuid, "object": obj }));
+        }
+        result
+    }
+
+    // Stages the creation of an object
+    fn stage_creation(&self, uuid: &str, obj: Map<String, Value>) -> Result<Option<String>> {
         // Create initial revision
         let rev = Revision::new(
             1u32,
@@ -367,6 +902,7 @@ impl Melda {
             .expect("cannot_acquire_revision_tree_for_writing");
         if rt_w.add(rev.clone(), None, true) {
             drop(docs_w);
+            self.changed_objects([uuid]);
             Ok(Some(rev.to_string()))
         } else {
             drop(docs_w);
@@ -387,7 +923,7 @@ impl Melda {
     /// use std::sync::{Arc, Mutex, RwLock};
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey" : [ "somedata", 1, 2, 3, 4 ] }).as_object().unwrap().clone();
     /// assert!(replica.create_object("myobject", object).is_ok());    
     /// let object = json!({ "somekey" : [ "somedata", 1, 2, 3, 4 ], "otherkey" : "otherdata" }).as_object().unwrap().clone();
@@ -400,6 +936,16 @@ impl Melda {
     /// assert_eq!(result.unwrap().unwrap(), "1-9e84b4db64036b29b7ad7def2efa95a11e1ffe93e6e5cf56e93b07ef8d3976ff");
     /// ```
     pub fn update_object(&self, uuid: &str, obj: Map<String, Value>) -> Result<Option<String>> {
+        let traced = self.trace.as_ref().map(|_| obj.clone());
+        let result = self.stage_update(uuid, obj);
+        if let (Ok(_), Some(obj)) = (&result, traced) {
+            self.record(|| json!({ "op": "update", "uuid": uuid, "object": obj }));
+        }
+        result
+    }
+
+    // Stages the update of an object
+    fn stage_update(&self, uuid: &str, obj: Map<String, Value>) -> Result<Option<String>> {
         // Obtain the revision tree (either an existing one of a new one)
         let docs_r = self
             .documents
@@ -426,16 +972,20 @@ impl Melda {
                         let rev = Revision::new_updated(digest, winning_revision);
                         let winning_revision = winning_revision.clone();
                         rt_w.add(rev.clone(), Some(winning_revision.clone()), true);
+                        self.changed_objects([uuid]);
                         let mut data_w =
                             self.data.write().expect("cannot_acquire_data_for_writing");
-                        data_w.write_object(&rev, object).unwrap();
+                        data_w
+                            .write_updated_object(&rev, object, &winning_revision)
+                            .unwrap();
                         drop(data_w);
                         Ok(Some(rev.to_string()))
                     } else {
                         Ok(None)
                     }
                 } else {
-                    Err(anyhow!("invalid_object"))
+                    // The array has not changed
+                    Ok(None)
                 }
             } else {
                 Err(anyhow!("object_has_no_winner"))
@@ -444,7 +994,7 @@ impl Melda {
             // Newly created object
             drop(docs_r);
             // No winning revision, assume that its a new object
-            self.create_object(uuid, obj)
+            self.stage_creation(uuid, obj)
         }
     }
 
@@ -454,19 +1004,35 @@ impl Melda {
         rt: &RevisionTree,
         rev: &Revision,
     ) -> Result<Map<String, Value>> {
-        if is_array_descriptor(uuid) {
-            let order = self
-                .get_merged_order_at_revision(rt, rev)
-                .expect("cannot_get_merged_order");
-            Ok(ArrayDescriptor::new_from_order(order).to_json_object())
+        // The merged order of an array depends on the conflicting revisions
+        let leafs = if is_array_descriptor(uuid) {
+            rt.get_leafs().iter().cloned().collect()
         } else {
-            Ok(self
-                .data
+            vec![]
+        };
+        let key = (uuid.to_string(), rev.clone(), leafs);
+        if let Some(object) = self
+            .object_cache
+            .lock()
+            .expect("cannot_acquire_object_cache")
+            .get(&key)
+        {
+            return Ok(object.clone());
+        }
+        let object = if is_array_descriptor(uuid) {
+            let order = self.get_merged_order_at_revision(rt, rev)?;
+            ArrayDescriptor::new_from_order(order).to_json_object()
+        } else {
+            self.data
                 .read()
                 .expect("cannot_acquire_data_for_reading")
-                .read_object(rev)
-                .expect("cannot_read_object"))
-        }
+                .read_object(rev)?
+        };
+        self.object_cache
+            .lock()
+            .expect("cannot_acquire_object_cache")
+            .put(key, object.clone());
+        Ok(object)
     }
 
     /// Records the deletion of an object
@@ -481,7 +1047,7 @@ impl Melda {
     /// use std::sync::{Arc, Mutex, RwLock};
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey" : [ "somedata", 1, 2, 3, 4 ] }).as_object().unwrap().clone();
     /// replica.create_object("myobject", object);
     /// assert!(replica.get_all_objects().contains("myobject"));    
@@ -493,7 +1059,7 @@ impl Melda {
     /// assert!(value.unwrap().contains_key("_deleted"));
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
     /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey\u{266D}" : { "_id": "1", "key" : "alpha" }}).as_object().unwrap().clone();
     /// replica.update(object.clone());
     /// let readback = replica.read(None).unwrap();
@@ -510,6 +1076,15 @@ impl Melda {
     /// assert!(result2.unwrap().is_none());
     /// ```
     pub fn delete_object(&self, uuid: &str) -> Result<Option<String>> {
+        let result = self.stage_deletion(uuid);
+        if result.is_ok() {
+            self.record(|| json!({ "op": "delete", "uuid": uuid }));
+        }
+        result
+    }
+
+    // Stages the deletion of an object
+    fn stage_deletion(&self, uuid: &str) -> Result<Option<String>> {
         let docs_r = self
             .documents
             .read()
@@ -521,6 +1096,7 @@ impl Melda {
                     let rev = Revision::new_deleted(winning_revision);
                     let winning_revision = winning_revision.clone();
                     rt_w.add(rev.clone(), Some(winning_revision.clone()), true);
+                    self.changed_objects([uuid]);
                     Ok(Some(rev.to_string()))
                 } else {
                     Ok(None)
@@ -546,7 +1122,7 @@ impl Melda {
     /// use std::sync::{Arc, Mutex, RwLock};
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey" : [ "somedata", 1, 2, 3, 4 ] }).as_object().unwrap().clone();
     /// replica.create_object("myobject", object.clone());
     /// assert!(replica.get_all_objects().contains("myobject"));    
@@ -562,6 +1138,15 @@ impl Melda {
     /// assert!(result2.unwrap().is_none());
     /// ```
     pub fn remove_object(&self, uuid: &str) -> Result<Option<String>> {
+        let result = self.stage_removal(uuid);
+        if result.is_ok() {
+            self.record(|| json!({ "op": "remove", "uuid": uuid }));
+        }
+        result
+    }
+
+    // Unstages the changes of an object, staging its deletion if it has a committed history
+    fn stage_removal(&self, uuid: &str) -> Result<Option<String>> {
         let docs_r = self
             .documents
             .read()
@@ -577,12 +1162,14 @@ impl Melda {
                     .write()
                     .expect("cannot_acquire_documents_for_writing");
                 docs_w.remove(uuid);
+                self.changed_objects([uuid]);
                 Ok(None)
             } else if let Some(winning_revision) = rt_w.get_winner() {
                 if !winning_revision.is_deleted() && !winning_revision.is_resolved() {
                     let rev = Revision::new_deleted(winning_revision);
                     let winning_revision = winning_revision.clone();
                     rt_w.add(rev.clone(), Some(winning_revision.clone()), true);
+                    self.changed_objects([uuid]);
                     Ok(Some(rev.to_string()))
                 } else {
                     Ok(None)
@@ -600,7 +1187,9 @@ impl Melda {
     // in commit we need to clear the stage afterwards and we need to write the pack and the block
 
     /// Commits changes to the backend adapter and returns the set of anchors (containing the identifier
-    /// of the committed block)
+    /// of the committed block). Standard fields of the information which do not have the
+    /// expected type are kept as other fields (see [CommitInfo::from_map_lenient]), whereas
+    /// [Melda::commit_with] rejects them
     ///
     /// # Arguments
     ///
@@ -612,7 +1201,7 @@ impl Melda {
     /// use std::sync::{Arc, Mutex, RwLock};
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
     /// replica.create_object("myobject", object);  
     /// assert!(replica.get_all_objects().contains("myobject"));  
@@ -632,39 +1221,302 @@ impl Melda {
     /// let value2 = replica.get_value("myobject", Some(&winner));
     /// assert!(value2.is_ok());
     /// assert!(value2.unwrap().contains_key("_deleted"));
+    /// // Changes staged by other threads during a commit remain staged for the next commit
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let replica = Arc::new(Melda::new(adapter.clone()).unwrap());
+    /// let writers: Vec<_> = (0..4).map(|t| {
+    ///     let replica = replica.clone();
+    ///     std::thread::spawn(move || for i in 0..25 {
+    ///         replica.create_object(&format!("{}-{}", t, i), json!({ "v" : i }).as_object().unwrap().clone()).unwrap();
+    ///         replica.commit(None).unwrap();
+    ///     })
+    /// }).collect();
+    /// writers.into_iter().for_each(|w| w.join().unwrap());
+    /// replica.commit(None).unwrap();
+    /// assert!(!replica.has_staging());
+    /// let other = Melda::new(adapter).unwrap();
+    /// assert_eq!(other.get_all_objects().len(), 100);
     /// ```
     pub fn commit(
         &self,
         information: Option<Map<String, Value>>,
     ) -> Result<Option<BTreeSet<String>>> {
+        let info = CommitInfo::from_map_lenient(information.clone().unwrap_or_default());
+        self.commit_checked(information, info)
+    }
+
+    // Commits the staged changes with the given information (parsed as info), running the
+    // hooks and the validator of the replica
+    fn commit_checked(
+        &self,
+        information: Option<Map<String, Value>>,
+        mut info: CommitInfo,
+    ) -> Result<Option<BTreeSet<String>>> {
+        let original = info.clone();
+        if self.has_staging() {
+            let hooks = self.pre_commit_hooks.lock().unwrap().clone();
+            for hook in hooks {
+                if let Err(reason) = hook(self, &mut info) {
+                    bail!(MeldaError::CommitRejected { reason });
+                }
+            }
+        }
+        if let Some(validator) = &self.commit_validator {
+            if let Err(reason) = validator(&info) {
+                bail!(MeldaError::InvalidCommitInfo { reason });
+            }
+        }
+        let information = if info == original {
+            information
+        } else {
+            Some(info.into_map())
+        };
+        let anchors = self.commit_at(information, now_millis())?;
+        let hooks = self.post_commit_hooks.lock().unwrap().clone();
+        if let Some(block) = anchors
+            .iter()
+            .flatten()
+            .next()
+            .filter(|_| !hooks.is_empty())
+        {
+            let info = self.commit_info(block)?.unwrap_or_default();
+            for hook in hooks {
+                hook(block, &info);
+            }
+        }
+        Ok(anchors)
+    }
+
+    /// Registers a hook run by [Melda::commit] before committing the staged changes (hooks
+    /// run in the order in which they are registered, and are skipped if nothing is staged).
+    /// The hook receives the replica, whose staged changes it can inspect or modify, and the
+    /// commit information, which it can modify. Returning an error vetoes the commit, which
+    /// fails with a [MeldaError::CommitRejected] error (the changes remain staged). Commits
+    /// recording automatic resolutions (see [MeldaBuilder::conflict_policy]) do not run hooks
+    ///
+    /// # Arguments
+    ///
+    /// * `hook` - The hook
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, error::MeldaError};
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// replica.add_pre_commit_hook(|replica, info| {
+    ///     if replica.read(None).map_err(|e| e.to_string())?.contains_key("secret") {
+    ///         return Err("secrets_are_not_committed".to_string());
+    ///     }
+    ///     info.app_version.get_or_insert_with(|| "1.0".to_string());
+    ///     replica.set("/revision", json!(1)).map_err(|e| e.to_string())
+    /// });
+    /// replica.update(json!({ "secret" : "42" }).as_object().unwrap().clone()).unwrap();
+    /// let error = replica.commit(None).unwrap_err();
+    /// assert_eq!(MeldaError::of(&error), Some(&MeldaError::CommitRejected { reason: "secrets_are_not_committed".to_string() }));
+    /// assert!(replica.has_staging());
+    /// replica.update(json!({ "key" : "value" }).as_object().unwrap().clone()).unwrap();
+    /// let block = replica.commit(None).unwrap().unwrap().into_iter().next().unwrap();
+    /// assert_eq!(replica.read(None).unwrap()["revision"], 1);
+    /// assert_eq!(replica.commit_info(&block).unwrap().unwrap().app_version.as_deref(), Some("1.0"));
+    /// ```
+    pub fn add_pre_commit_hook<F>(&self, hook: F)
+    where
+        F: Fn(&Melda, &mut CommitInfo) -> std::result::Result<(), String> + Send + Sync + 'static,
+    {
+        self.pre_commit_hooks.lock().unwrap().push(Arc::new(hook));
+    }
+
+    /// Registers a hook run by [Melda::commit] after committing the staged changes, for side
+    /// effects such as notifications or indexing. The hook receives the identifier of the
+    /// committed block and its information (the timestamp is the commit time unless the
+    /// information records another one)
+    ///
+    /// # Arguments
+    ///
+    /// * `hook` - The hook
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, commitinfo::CommitInfo};
+    /// use serde_json::json;
+    /// use std::sync::{Arc, Mutex};
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// let committed = Arc::new(Mutex::new(vec![]));
+    /// let log = committed.clone();
+    /// replica.add_post_commit_hook(move |block, info| {
+    ///     log.lock().unwrap().push((block.to_string(), info.author.clone()));
+    /// });
+    /// replica.update(json!({ "key" : "value" }).as_object().unwrap().clone()).unwrap();
+    /// let block = replica.commit_with(CommitInfo::new().author("Alice")).unwrap().unwrap().into_iter().next().unwrap();
+    /// // Nothing is committed
+    /// replica.commit(None).unwrap();
+    /// assert_eq!(*committed.lock().unwrap(), vec![(block, Some("Alice".to_string()))]);
+    /// ```
+    pub fn add_post_commit_hook<F>(&self, hook: F)
+    where
+        F: Fn(&str, &CommitInfo) + Send + Sync + 'static,
+    {
+        self.post_commit_hooks.lock().unwrap().push(Arc::new(hook));
+    }
+
+    /// Estimates how many objects and approximately how many bytes the next commit would write,
+    /// without committing, for example to warn users before committing a large import. The
+    /// estimate excludes the commit information and the local blocks squashed by the commit
+    /// (see [MeldaBuilder::squash_policy]), and ignores compression by the adapter
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// assert_eq!(replica.estimate_commit().unwrap().objects, 0);
+    /// let items: Vec<_> = (0..100).map(|i| json!({ "_id" : i.to_string(), "text" : format!("{}{}", i, "x".repeat(100)) })).collect();
+    /// replica.update(json!({ "items\u{266D}" : items }).as_object().unwrap().clone()).unwrap();
+    /// let estimate = replica.estimate_commit().unwrap();
+    /// assert_eq!(estimate.objects, 101);
+    /// assert!(estimate.total_bytes() > 10000);
+    /// replica.commit(None).unwrap();
+    /// let adapter = replica.get_adapter();
+    /// let pack = adapter.read().unwrap().list_objects(".pack").unwrap()[0].clone() + ".pack";
+    /// assert_eq!(estimate.pack_bytes, adapter.read().unwrap().object_size(&pack).unwrap());
+    /// ```
+    pub fn estimate_commit(&self) -> Result<CommitEstimate> {
+        let mut estimate = CommitEstimate::default();
+        let mut changes = vec![];
+        for (uuid, rt) in self.documents.read().unwrap().iter() {
+            let rt = rt.lock().unwrap();
+            if !rt.has_staging() {
+                continue;
+            }
+            if !is_array_descriptor(uuid) {
+                estimate.objects += 1;
+            }
+            for (rev, rte) in rt.get_revisions().iter().filter(|(_, e)| e.is_staging()) {
+                estimate.revisions += 1;
+                let mut record = vec![uuid.clone()];
+                record.extend(rte.get_parent().as_ref().map(|p| p.to_string()));
+                record.push(rev.digest().clone());
+                changes.push(Value::from(record));
+            }
+        }
+        if estimate.revisions > 0 {
+            let mut block = Map::<String, Value>::new();
+            block.insert(CHANGESETS_FIELD.to_string(), Value::from(changes));
+            let anchors: Vec<String> = self.get_anchors().into_iter().collect();
+            if !anchors.is_empty() {
+                block.insert(PARENTS_FIELD.to_string(), Value::from(anchors));
+            }
+            block.insert(TIMESTAMP_FIELD.to_string(), Value::from(now_millis()));
+            estimate.block_bytes = encode_value(&Value::from(block), self.encoding)?.len();
+        }
+        let (values, pack_bytes) = self.data.read().unwrap().staged_size()?;
+        estimate.values = values;
+        estimate.pack_bytes = pack_bytes;
+        Ok(estimate)
+    }
+
+    /// Commits the staged changes with the given information, see [Melda::commit] and
+    /// [CommitInfo]. Extra fields named after a standard field must have its type (see
+    /// [CommitInfo::from_map])
+    pub fn commit_with(&self, info: CommitInfo) -> Result<Option<BTreeSet<String>>> {
+        let information = info.into_map();
+        let info = CommitInfo::from_map(information.clone())?;
+        self.commit_checked(Some(information), info)
+    }
+
+    /// Returns the information attached to a block (None if the block is unknown). The
+    /// timestamp defaults to the commit time recorded by the block
+    ///
+    /// # Arguments
+    ///
+    /// * `block_id` - Block identifier
+    pub fn commit_info(&self, block_id: &str) -> Result<Option<CommitInfo>> {
+        match self.get_block(block_id)? {
+            Some(block) => {
+                let mut info = CommitInfo::from_map_lenient(block.info.unwrap_or_default());
+                info.timestamp = info.timestamp.or(block.timestamp);
+                Ok(Some(info))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the identifier of the replica, which is recorded in every block it commits. The
+    /// identifier is generated randomly the first time it is needed (or set with
+    /// [MeldaBuilder::replica_id]) and kept in the replica metadata, which is not transferred
+    /// when melding: replicas sharing the same storage share the same identifier
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// let id = replica.replica_id().unwrap();
+    /// assert_eq!(id.len(), 32);
+    /// assert_eq!(Melda::new(replica.get_adapter()).unwrap().replica_id().unwrap(), id);
+    /// assert_ne!(Melda::new_from_url("memory://").unwrap().replica_id().unwrap(), id);
+    /// ```
+    pub fn replica_id(&self) -> Result<String> {
+        let mut replica_id = self.replica_id.lock().unwrap();
+        if let Some(id) = replica_id.as_ref() {
+            return Ok(id.clone());
+        }
+        let mut bytes = [0u8; 16];
+        random_bytes(&mut bytes)?;
+        let id = hex::encode(bytes);
+        write_replica_metadata(self.get_adapter().read().unwrap().as_ref(), &id)?;
+        *replica_id = Some(id.clone());
+        Ok(id)
+    }
+
+    // Commits the staged changes recording the given commit time
+    fn commit_at(
+        &self,
+        information: Option<Map<String, Value>>,
+        timestamp: Option<u64>,
+    ) -> Result<Option<BTreeSet<String>>> {
+        let _exclusive = self.exclusive.acquire();
+        let span = operation_span!("commit");
         // If there is nothing staged, skip commit
         if !self.has_staging() {
             return Ok(None);
         }
-        // Automatically resolve conflicts in array_descriptors
+        // Automatically resolve conflicts in array_descriptors (the revision trees must be
+        // released before resolving)
+        let mut unresolved = vec![];
         for (uuid, rt) in self.documents.read().unwrap().iter() {
             if is_array_descriptor(uuid) {
                 let rt_r = rt.lock().expect("cannot_acquire_revision_tree_for_commit");
                 let w = rt_r.get_winner().ok_or_else(|| anyhow!("no_winner"))?;
-                let l = rt_r.get_leafs();
-                if l.len() > 1 {
-                    self.resolve_as(uuid, w.to_string().as_str())
-                        .expect("cannot_automatically_resolve_array_descriptor_conflict");
+                if rt_r.get_leafs().len() > 1 {
+                    unresolved.push((uuid.clone(), w.to_string()));
                 }
             }
         }
-        // Commit data packs
+        for (uuid, w) in unresolved {
+            self.resolve_as(&uuid, &w)
+                .expect("cannot_automatically_resolve_array_descriptor_conflict");
+        }
+        let replica = self.replica_id()?;
+        // Collect the local blocks to be squashed
+        let squash = self.prepare_squash()?;
+        // Process stage: the staged revisions are collected before packing the data, changes
+        // staged concurrently afterwards remain staged for the next commit (the data of a staged
+        // revision is written before the revision is added to its revision tree)
         let mut block = Map::<String, Value>::new();
-        let mut data: std::sync::RwLockWriteGuard<'_, DataStorage> =
-            self.data.write().expect("cannot_acquire_data_for_writing");
-        let _packid = data.pack()?;
-        // Process stage
-        let mut changes = Vec::<Value>::new();
+        let mut changes = squash
+            .as_ref()
+            .map(|squash| squash.changes.clone())
+            .unwrap_or_default();
+        let mut events = Vec::<(String, ChangeKind)>::new();
+        let mut staged = HashMap::<String, Vec<Revision>>::new();
         for (uuid, rt) in self.documents.read().unwrap().iter() {
             let rt_rw = rt.lock().expect("cannot_acquire_revision_tree_for_commit");
             if rt_rw.has_staging() {
                 rt_rw.get_revisions().iter().for_each(|(rev, rte)| {
                     if rte.is_staging() {
+                        staged.entry(uuid.clone()).or_default().push(rev.clone());
+                        events.push((uuid.clone(), change_kind(rev, rte.get_parent().as_ref())));
                         if rte.get_parent().is_none() {
                             // Creation record
                             let tuple = vec![uuid.clone(), rev.digest().clone()];
@@ -682,40 +1534,98 @@ impl Melda {
                 })
             }
         }
+        span.record("objects", changes.len());
         block.insert(CHANGESETS_FIELD.to_string(), Value::from(changes));
+        // Commit data packs
+        let mut data: std::sync::RwLockWriteGuard<'_, DataStorage> =
+            self.data.write().expect("cannot_acquire_data_for_writing");
+        let _packid = data.pack()?;
         // Insert information object
-        if let Some(information) = information {
-            block.insert(INFORMATION_FIELD.to_string(), Value::from(information));
+        if let Some(information) = &information {
+            block.insert(
+                INFORMATION_FIELD.to_string(),
+                Value::from(information.clone()),
+            );
         }
         // Insert anchors
-        let anchors_blocks = self.get_anchors();
+        let mut anchors_blocks = self.get_anchors();
+        if let Some(squash) = &squash {
+            anchors_blocks.retain(|bid| !squash.squashed.contains(bid));
+            anchors_blocks.extend(squash.parents.iter().cloned());
+        }
         if !anchors_blocks.is_empty() {
             let anchors_blocks: Vec<String> =
                 anchors_blocks.iter().map(|bid| bid.to_string()).collect();
             block.insert(PARENTS_FIELD.to_string(), Value::from(anchors_blocks));
         }
+        // Insert commit time
+        if let Some(timestamp) = timestamp {
+            block.insert(TIMESTAMP_FIELD.to_string(), Value::from(timestamp));
+        }
+        block.insert(REPLICA_FIELD.to_string(), Value::from(replica.clone()));
         // Insert pack indentifer
-        if _packid.is_some() {
-            let packs = vec![_packid.unwrap()];
+        let mut packs = squash
+            .as_ref()
+            .map(|squash| squash.packs.clone())
+            .unwrap_or_default();
+        packs.extend(_packid);
+        if !packs.is_empty() {
+            let packs: Vec<String> = packs.into_iter().collect();
             block.insert(PACK_FIELD.to_string(), Value::from(packs));
         }
-        let blockstr = serde_json::to_string(&block).unwrap();
-        let block_hash = digest_string(&blockstr);
+        // Insert squashed blocks
+        if let Some(squash) = &squash {
+            let squashed: Vec<String> = squash.squashed.iter().cloned().collect();
+            block.insert(SQUASHED_FIELD.to_string(), Value::from(squashed));
+        }
+        let blockdata = encode_value(&Value::from(block.clone()), self.encoding)?;
+        let block_hash = digest_bytes_with(self.digest, &blockdata);
         let blockid = block_hash.clone() + DELTA_EXTENSION;
-        data.write_raw_item(&blockid, blockstr.as_bytes())?;
+        data.write_raw_item(&blockid, &blockdata)?;
+        span.record("blocks", 1);
+        span.record("bytes", blockdata.len());
+        // Delete the squashed blocks (once the block replacing them has been written)
+        if let Some(squash) = &squash {
+            for bid in &squash.squashed {
+                data.delete_raw_item(&(bid.clone() + DELTA_EXTENSION))?;
+            }
+        }
         // Load the block
         drop(data);
         let mut b = self.parse_raw_block(block_hash.clone(), block).unwrap();
         b.status = Status::ValidAndApplied;
-        self.blocks
-            .write()
+        let mut blocks_w = self.blocks.write().unwrap();
+        if let Some(squash) = &squash {
+            for bid in &squash.squashed {
+                blocks_w.remove(bid);
+            }
+            self.local_blocks.lock().unwrap().clear();
+        }
+        blocks_w.insert(block_hash.clone(), RwLock::new(b));
+        drop(blocks_w);
+        self.local_blocks
+            .lock()
             .unwrap()
-            .insert(block_hash.clone(), RwLock::new(b));
-        // Commit changes
-        for (_, rt) in self.documents.read().unwrap().iter() {
-            let mut rt_rw = rt.lock().expect("cannot_acquire_revision_tree_for_commit");
-            rt_rw.commit();
+            .push((block_hash.clone(), blockdata.len()));
+        // Commit the changes included in the block
+        let docs_r = self.documents.read().unwrap();
+        for (uuid, revisions) in &staged {
+            if let Some(rt) = docs_r.get(uuid) {
+                let mut rt_rw = rt.lock().expect("cannot_acquire_revision_tree_for_commit");
+                rt_rw.commit_revisions(revisions);
+            }
         }
+        drop(docs_r);
+        self.notify(
+            events
+                .into_iter()
+                .map(|(uuid, kind)| (block_hash.clone(), uuid, kind))
+                .collect(),
+        )?;
+        self.update_indexes()?;
+        self.save_locations();
+        self.record(|| json!({ "op": "commit", "information": information, "timestamp": timestamp, "replica": replica, "block": block_hash }));
+        self.enforce_memory_budget()?;
         let anchors = BTreeSet::from([block_hash]);
         Ok(Some(anchors))
     }
@@ -729,7 +1639,7 @@ impl Melda {
     /// use serde_json::{Map, Value,json};
     /// use std::collections::BTreeSet;
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey" : [ "somedata", 1.0f32, 2.0f32, 3.0f32, 4.0f32 ] }).as_object().unwrap().clone();
     /// replica.create_object("myobject", object);
     /// let object = json!({ "somekey" : [ "somedata", 1.0f32, 2.0f32, 3.0f32, 4.0f32 ] }).as_object().unwrap().clone();
@@ -737,12 +1647,7 @@ impl Melda {
     /// assert_eq!(replica.get_all_objects(), BTreeSet::from(["another".to_string(),"myobject".to_string()]));
     /// ```
     pub fn get_all_objects(&self) -> BTreeSet<String> {
-        self.documents
-            .read()
-            .unwrap()
-            .iter()
-            .map(|(k, _)| k.clone())
-            .collect()
+        self.documents.read().unwrap().keys().cloned().collect()
     }
 
     /// Returns a the value associated with the given revision
@@ -759,7 +1664,7 @@ impl Melda {
     /// use serde_json::{Map, Value,json};
     /// use std::collections::BTreeSet;
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey" : [ "somedata", 1.0f32, 2.0f32, 3.0f32, 4.0f32 ] }).as_object().unwrap().clone();
     /// replica.create_object("myobject", object.clone());
     /// let winner = replica.get_winner("myobject").unwrap();
@@ -769,6 +1674,11 @@ impl Melda {
     /// assert_eq!(value, object);
     /// ```
     pub fn get_value(&self, uuid: &str, revision: Option<&str>) -> Result<Map<String, Value>> {
+        if revision.is_none() && !is_array_descriptor(uuid) {
+            if let Some(object) = self.published().and_then(|p| p.objects.get(uuid).cloned()) {
+                return Ok(object);
+            }
+        }
         match revision {
             Some(revision) => {
                 let revision = Revision::from(revision).expect("invalid_revision_string");
@@ -825,7 +1735,7 @@ impl Melda {
     /// use std::sync::{Arc, Mutex, RwLock};
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let mut replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
     /// replica.create_object("myobject", object);
     /// let anchors = replica.get_anchors();
@@ -867,7 +1777,7 @@ impl Melda {
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
     /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
     /// replica.create_object("myobject", object);  
     /// assert!(replica.get_all_objects().contains("myobject"));
@@ -882,19 +1792,23 @@ impl Melda {
     /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
     /// ```    
     pub fn reload(&self) -> Result<()> {
+        let _exclusive = self.exclusive.acquire();
+        let span = operation_span!("reload");
         // Check that stage is empty, otherwise fail (user must unstage explicity if necessary)
         if self.has_staging() {
-            bail!("stage_not_empty")
+            bail!(MeldaError::StageNotEmpty)
         }
         // Clear the documents
         self.documents
             .write()
             .expect("failed_to_acquire_documents_for_writing")
             .clear();
+        self.changed();
         // Read block list
         let data = self.data.read().expect("cannot_acquire_data_for_reading");
         let list_str = data.list_raw_items(DELTA_EXTENSION)?;
         drop(data);
+        span.record("blocks", list_str.len());
         self.blocks.write().unwrap().clear();
         // Reload data storage
         let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
@@ -915,6 +1829,8 @@ impl Melda {
                 }
             }
         }
+        self.save_block_cache(&list_str);
+        self.resolve_squashed_parents();
         // Mark valid blocks
         self.mark_valid_blocks();
         // Apply all valid blocks
@@ -931,10 +1847,16 @@ impl Melda {
                 }
             }
         });
-        Ok(())
+        self.collect_pending_blocks();
+        self.record(|| json!({ "op": "reload" }));
+        self.enforce_memory_budget()
     }
 
-    /// Loads newly available blocks
+    /// Loads newly available blocks. Only the blocks which arrived since the last refresh (and
+    /// the blocks which could not be applied yet, for example because their parents were
+    /// missing) are processed. Refreshes, commits and reloads are serialized internally, so a
+    /// replica shared between threads (for example in an `Arc`) can be refreshed in the
+    /// background while other threads keep reading
     ///
     /// # Example
     /// ```
@@ -943,7 +1865,7 @@ impl Melda {
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
     /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
     /// replica.create_object("myobject", object);  
     /// assert!(replica.get_all_objects().contains("myobject"));
@@ -956,81 +1878,196 @@ impl Melda {
     /// replica.refresh();
     /// let winner = replica.get_winner("myobject").unwrap();
     /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
-    /// ```    
-    pub fn refresh(&mut self) -> Result<()> {
+    /// // Blocks are applied once their parents become available
+    /// replica.update(json!({ "key" : "value" }).as_object().unwrap().clone()).unwrap();
+    /// let second = replica.commit(None).unwrap().unwrap();
+    /// let replica2 = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+    /// let target = replica2.get_adapter();
+    /// let copy = |keys: Vec<String>| for key in keys {
+    ///     let content = adapter.read().unwrap().read_object(&key, 0, 0).unwrap();
+    ///     target.read().unwrap().write_object(&key, &content).unwrap();
+    /// };
+    /// let first = committed_anchors.first().unwrap().clone() + ".delta";
+    /// let second = second.first().unwrap().clone() + ".delta";
+    /// copy(adapter.read().unwrap().list_objects("").unwrap().into_iter().filter(|k| *k != first).collect());
+    /// replica2.refresh().unwrap();
+    /// assert!(replica2.get_anchors().is_empty());
+    /// copy(vec![first]);
+    /// replica2.refresh().unwrap();
+    /// assert!(replica2.get_anchors().contains(&second.replace(".delta", "")));
+    /// assert_eq!(replica2.read(None).unwrap().get("key").unwrap(), "value");
+    /// // The replica can be shared: a background thread melds and refreshes while reads proceed
+    /// let shared = Arc::new(replica2);
+    /// let background = shared.clone();
+    /// let worker = std::thread::spawn(move || {
+    ///     background.meld(&replica).unwrap();
+    ///     background.refresh().unwrap();
+    /// });
+    /// while !worker.is_finished() {
+    ///     assert_eq!(shared.read(None).unwrap().get("key").unwrap(), "value");
+    /// }
+    /// worker.join().unwrap();
+    /// assert!(shared.get_all_objects().contains("myobject"));
+    /// ```
+    pub fn refresh(&self) -> Result<()> {
+        let _exclusive = self.exclusive.acquire();
+        let span = operation_span!("refresh");
         // Check that stage is empty, otherwise fail (user must unstage explicity if necessary)
         if self.has_staging() {
-            bail!("stage_not_empty")
+            bail!(MeldaError::StageNotEmpty)
         }
         // 1. Get new list of blocks
         let data_r = self.data.read().expect("cannot_acquire_data_for_writing");
         let list_str = data_r.list_raw_items(DELTA_EXTENSION)?;
         drop(data_r);
+        let new_blocks: Vec<String> = {
+            let blocks_r = self
+                .blocks
+                .read()
+                .expect("cannot_acquire_blocks_for_reading");
+            list_str
+                .iter()
+                .filter(|i| !blocks_r.contains_key(*i))
+                .cloned()
+                .collect()
+        };
+        span.record("blocks", new_blocks.len());
+        let mut pending = self.pending_blocks.lock().unwrap();
+        // Nothing to do if no block arrived and all blocks have been applied
+        if new_blocks.is_empty() && pending.is_empty() {
+            return Ok(());
+        }
         // 2. Refresh data storage
         let mut data_w = self.data.write().expect("cannot_acquire_data_for_writing");
         data_w.refresh()?;
         drop(data_w);
         // 3. Load new blocks
-        if !list_str.is_empty() {
-            for i in &list_str {
-                let is_new_block = !self
-                    .blocks
-                    .read()
-                    .expect("cannot_acquire_blocks_for_reading")
-                    .contains_key(i);
-                if is_new_block {
-                    if let Ok(block) = self.fetch_raw_block(i) {
-                        if let Ok(block) = self.parse_raw_block(i.to_string(), block) {
-                            self.blocks
-                                .write()
-                                .expect("cannot_acquire_blocks_for_writing")
-                                .insert(i.to_string(), RwLock::new(block));
-                        }
-                    }
+        let mut squashing = false;
+        let arrived: HashSet<&String> = new_blocks.iter().collect();
+        for i in &new_blocks {
+            if let Ok(block) = self.fetch_raw_block(i) {
+                if let Ok(block) = self.parse_raw_block(i.to_string(), block) {
+                    let mut blocks_w = self
+                        .blocks
+                        .write()
+                        .expect("cannot_acquire_blocks_for_writing");
+                    // References to squashed (or grafted) blocks must be redirected
+                    squashing |= block.squashed.is_some()
+                        || block.grafted.is_some()
+                        || block
+                            .parents
+                            .iter()
+                            .flatten()
+                            .any(|p| !blocks_w.contains_key(p) && !arrived.contains(p));
+                    blocks_w.insert(i.to_string(), RwLock::new(block));
+                    pending.insert(i.to_string());
                 }
             }
         }
-        // 4. Turn invalid blocks into unknown status blocks
+        self.save_block_cache(&list_str);
+        if squashing {
+            self.resolve_squashed_parents();
+        }
+        // 4. Turn invalid blocks into unknown status blocks (only blocks which have not been
+        // applied yet are considered)
         let blocks_r = self
             .blocks
             .read()
             .expect("cannot_acquire_blocks_for_reading");
-        blocks_r.par_iter().for_each(|(_, block)| {
-            let status = block
-                .read()
-                .expect("cannot_acquire_block_for_reading")
-                .status;
-            if status == Status::Invalid {
-                block
-                    .write()
-                    .expect("cannot_acquire_block_for_writing")
-                    .status = Status::Unknown;
+        pending.retain(|block_id| blocks_r.contains_key(block_id));
+        for block_id in pending.iter() {
+            let mut block_w = blocks_r
+                .get(block_id)
+                .unwrap()
+                .write()
+                .expect("cannot_acquire_block_for_writing");
+            if block_w.status == Status::Invalid {
+                block_w.status = Status::Unknown;
             }
-        });
-        drop(blocks_r);
+        }
         // 5. Mark valid blocks
-        self.mark_valid_blocks();
-        // 6. Apply all valid blocks
-        let blocks_r = self
-            .blocks
-            .read()
-            .expect("cannot_acquire_blocks_for_reading");
-        blocks_r.iter().for_each(|(_, block)| {
+        for block_id in pending.iter() {
+            self.check_block(block_id);
+        }
+        // 6. Apply all valid blocks (keeping track of the objects in conflict among those
+        // changed by the blocks)
+        let touched: BTreeSet<String> = pending
+            .iter()
+            .filter_map(|block_id| {
+                let block_r = blocks_r.get(block_id).unwrap().read().unwrap();
+                (block_r.status == Status::Valid).then(|| {
+                    block_r
+                        .changes
+                        .iter()
+                        .flatten()
+                        .map(|Change(uuid, _, _)| uuid.clone())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .flatten()
+            .collect();
+        let conflicting = self.conflicting(&touched);
+        let mut events = Vec::<(String, String, ChangeKind)>::new();
+        for block_id in pending.iter() {
+            let block = blocks_r.get(block_id).unwrap();
             let block_r = block.read().expect("cannot_acquire_block_for_reading");
-            let status = block
-                .read()
-                .expect("cannot_acquire_block_for_reading")
-                .status;
-            if status == Status::Valid && self.apply_block(&block_r).is_ok() {
+            if block_r.status == Status::Valid && self.apply_block(&block_r).is_ok() {
+                if let Some(changes) = &block_r.changes {
+                    for Change(uuid, rev, parent) in changes {
+                        events.push((
+                            block_id.clone(),
+                            uuid.clone(),
+                            change_kind(rev, parent.as_ref()),
+                        ));
+                    }
+                }
                 drop(block_r);
                 let mut block_w = block.write().expect("cannot_acquire_block_for_writing");
                 block_w.status = Status::ValidAndApplied;
                 // We can drop the changes vector
                 block_w.changes = None;
             }
+        }
+        pending.retain(|block_id| {
+            blocks_r.get(block_id).unwrap().read().unwrap().status != Status::ValidAndApplied
         });
         drop(blocks_r);
-        Ok(())
+        drop(pending);
+        if !events.is_empty() {
+            self.flag_schema_violations()?;
+        }
+        self.update_indexes()?;
+        let origins: BTreeMap<String, String> = events
+            .iter()
+            .map(|(block, uuid, _)| (uuid.clone(), block.clone()))
+            .collect();
+        let resolve = !events.is_empty();
+        self.notify(events)?;
+        self.record(|| json!({ "op": "refresh" }));
+        if resolve {
+            if self.field_level_conflicts {
+                self.merge_fields(origins.keys())?;
+            }
+            self.apply_conflict_policy(origins.keys().cloned().collect())?;
+        }
+        // Notify the conflicts which appeared or disappeared
+        let now_conflicting = self.conflicting(&touched);
+        let transitions = origins
+            .into_iter()
+            .filter_map(|(uuid, block)| {
+                match (conflicting.contains(&uuid), now_conflicting.contains(&uuid)) {
+                    (false, true) => Some((block, uuid, ChangeKind::Conflicted)),
+                    (true, false) => Some((block, uuid, ChangeKind::Resolved)),
+                    _ => None,
+                }
+            })
+            .collect();
+        self.notify(transitions)?;
+        // If the state is being read, publish the new one so that readers do not wait for it
+        if self.published.load().is_some() && self.published().is_none() {
+            self.read_with(None, false)?;
+        }
+        self.enforce_memory_budget()
     }
 
     /// Reloads the CRDT until the given block
@@ -1046,7 +2083,7 @@ impl Melda {
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
     /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
     /// replica.create_object("myobject", object);  
     /// assert!(replica.get_all_objects().contains("myobject"));
@@ -1070,9 +2107,10 @@ impl Melda {
         if anchors.is_empty() {
             return self.reload();
         }
+        let _exclusive = self.exclusive.acquire();
         // Ensure that the stage is empty
         if self.has_staging() {
-            bail!("stage_not_empty")
+            bail!(MeldaError::StageNotEmpty)
         }
         let mut documents_w = self
             .documents
@@ -1081,6 +2119,7 @@ impl Melda {
         // Clear the documents
         documents_w.clear();
         drop(documents_w);
+        self.changed();
         // Read block list
         let data_r = self.data.write().expect("cannot_acquire_data_for_writing");
         let list_str = data_r.list_raw_items(DELTA_EXTENSION)?;
@@ -1105,7 +2144,9 @@ impl Melda {
                 }
             }
         }
+        self.save_block_cache(&list_str);
         drop(blocks_w);
+        self.resolve_squashed_parents();
         // Mark valid blocks
         self.mark_valid_blocks();
         // Check if blocks are valid
@@ -1115,11 +2156,9 @@ impl Melda {
             .expect("cannot_acquire_blocks_for_reading");
         for block_id in anchors {
             if !blocks_r.contains_key(block_id) {
-                bail!(
-                    "reload_until_interrupted_block_not_found: {} {:?}",
-                    block_id,
-                    blocks_r.keys()
-                );
+                bail!(MeldaError::MissingBlock {
+                    id: block_id.clone()
+                });
             }
             if blocks_r.get(block_id).unwrap().read().unwrap().status != Status::Valid {
                 bail!("reload_until_interrupted_invalid_block: {}", block_id);
@@ -1150,6 +2189,9 @@ impl Melda {
                 block_w.changes = None;
             }
         }
+        drop(blocks_r);
+        self.collect_pending_blocks();
+        self.record(|| json!({ "op": "reload_until", "anchors": anchors }));
         Ok(())
     }
 
@@ -1162,7 +2204,7 @@ impl Melda {
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
     /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
     /// replica.create_object("myobject", object);  
     /// assert!(replica.get_all_objects().contains("myobject"));
@@ -1180,7 +2222,8 @@ impl Melda {
     /// let winner = replica.get_winner("myobject").unwrap();
     /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
     /// ```
-    pub fn unstage(&mut self) -> Result<()> {
+    pub fn unstage(&self) -> Result<()> {
+        let _exclusive = self.exclusive.acquire();
         self.data
             .write()
             .expect("cannot_acquire_data_for_writing")
@@ -1199,10 +2242,15 @@ impl Melda {
                 .expect("cannot_acquire_revision_tree_for_reading")
                 .is_empty()
         });
+        drop(docs_w);
+        self.changed();
+        self.record(|| json!({ "op": "unstage" }));
         Ok(())
     }
 
     /// Melds another Melda into this one. Only committed items (delta blocks and data packs) are melded.
+    /// Items are transferred concurrently (see [MeldaBuilder::meld_parallelism]) and the digests
+    /// of blocks and packs are verified before they are written.
     ///
     /// # Arguments
     ///
@@ -1215,7 +2263,7 @@ impl Melda {
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
     /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
     /// replica.create_object("myobject", object);  
     /// assert!(replica.get_all_objects().contains("myobject"));
@@ -1224,7 +2272,7 @@ impl Melda {
     /// let committed_anchors = replica.commit(None).unwrap().unwrap();
     /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
     /// let adapter2 = Arc::new(RwLock::new(adapter2));
-    /// let mut replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
+    /// let replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
     /// replica2.meld(&replica);
     /// replica2.refresh();
     /// assert!(replica2.get_all_objects().contains("myobject"));
@@ -1236,25 +2284,445 @@ impl Melda {
     /// assert_eq!(block_id, &block.id);
     //// assert_eq!(block_id, &block2.id);
     pub fn meld(&self, other: &Melda) -> Result<Vec<String>> {
+        self.meld_with_progress(other, |_, _| {})
+    }
+
+    /// Melds another Melda into this one (see [Melda::meld]), invoking the progress callback
+    /// with the number of processed items and the total number of items of the other replica
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - Another Melda instance
+    /// * `progress` - Progress callback
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+    /// replica.update(json!({ "key" : "value" }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let replica2 = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+    /// let mut steps = vec![];
+    /// let melded = replica2.meld_with_progress(&replica, |done, total| steps.push((done, total))).unwrap();
+    /// assert_eq!(steps.last(), Some(&(melded.len(), melded.len())));
+    /// // Items are transferred concurrently
+    /// for i in 0..8 {
+    ///     replica.update(json!({ "key" : i }).as_object().unwrap().clone()).unwrap();
+    ///     replica.commit(None).unwrap();
+    /// }
+    /// let replica3 = Melda::builder().url("memory://").meld_parallelism(3).open().unwrap();
+    /// assert_eq!(replica3.meld(&replica).unwrap().len(), 18);
+    /// replica3.refresh().unwrap();
+    /// assert_eq!(replica3.read(None).unwrap().get("key").unwrap(), 7);
+    /// // Corrupted blocks are not melded
+    /// let block = replica.get_anchors().into_iter().next().unwrap() + ".delta";
+    /// replica2.get_adapter().read().unwrap().write_object(&block, b"{}").unwrap();
+    /// assert!(Melda::new_from_url("memory://").unwrap().meld(&replica2).is_err());
+    /// ```
+    pub fn meld_with_progress<F: FnMut(usize, usize)>(
+        &self,
+        other: &Melda,
+        progress: F,
+    ) -> Result<Vec<String>> {
+        // A shallow replica does not fetch the history replaced by its grafts
+        let skipped = other.grafted_items(&self.missing_grafted_blocks());
+        // The blocks of the other replica are no longer local (and cannot be squashed)
+        other.local_blocks.lock().unwrap().clear();
+        self.transfer_items(other, &skipped, progress)
+    }
+
+    /// Previews the outcome of melding another replica into this one (and refreshing), without
+    /// modifying either replica: the committed states are melded into a scratch replica kept
+    /// in memory. Staged changes are not considered, nor are the conflict policies (see
+    /// [MeldaBuilder::conflict_policy] and [MeldaBuilder::field_level_conflicts]).
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - Another Melda instance
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::{Melda, ChangeKind};
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// replica.update(json!({ "items\u{266D}" : [ { "_id" : "1", "title" : "Buy milk" }, { "_id" : "2", "title" : "Call Bob" } ] }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let other = Melda::new_from_url("memory://").unwrap();
+    /// other.meld(&replica).unwrap();
+    /// other.refresh().unwrap();
+    /// other.update(json!({ "items\u{266D}" : [ { "_id" : "3", "title" : "Pay rent" }, { "_id" : "1", "title" : "Buy oat milk" }, { "_id" : "2", "title" : "Call Bob" } ] }).as_object().unwrap().clone()).unwrap();
+    /// other.commit(None).unwrap();
+    /// replica.set("/items\u{266D}/0/title", json!("Buy soy milk")).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let preview = replica.simulate_meld(&other).unwrap();
+    /// assert_eq!(preview.blocks, other.get_anchors());
+    /// assert_eq!(preview.changes.get("3"), Some(&ChangeKind::Created));
+    /// assert!(preview.conflicts.contains("1"));
+    /// assert_eq!(preview.arrays.get("/items\u{266D}"), Some(&vec!["3".to_string(), "1".to_string(), "2".to_string()]));
+    /// // Neither replica has changed
+    /// assert!(replica.in_conflict().is_empty());
+    /// assert_eq!(replica.read(None).unwrap()["items\u{266D}"].as_array().unwrap().len(), 2);
+    /// ```
+    pub fn simulate_meld(&self, other: &Melda) -> Result<MergePreview> {
+        let scratch = || Melda::builder().url("memory://").digest(self.digest).open();
+        let base = scratch()?;
+        base.transfer_items(self, &HashSet::new(), |_, _| {})?;
+        base.refresh()?;
+        let merged = scratch()?;
+        merged.meld(&base)?;
+        merged.transfer_items(other, &HashSet::new(), |_, _| {})?;
+        merged.refresh()?;
+        let known: BTreeSet<String> = self.blocks.read().unwrap().keys().cloned().collect();
+        let mut preview = MergePreview {
+            blocks: other
+                .blocks
+                .read()
+                .unwrap()
+                .keys()
+                .filter(|bid| !known.contains(*bid))
+                .cloned()
+                .collect(),
+            ..MergePreview::default()
+        };
+        let before = base.winning_revisions();
+        let conflicting = base.in_conflict();
+        // Returns the identifiers of the elements of an array
+        let order = |replica: &Melda, descriptor: &str| -> Result<Option<Value>> {
+            if replica.load_flattened_object(descriptor)?.is_none() {
+                return Ok(None);
+            }
+            replica.expand_flattened(&Value::from(descriptor))
+        };
+        // The merged order of an array depends on all the conflicting revisions
+        let leaves = |replica: &Melda, uuid: &str| {
+            replica
+                .documents
+                .read()
+                .unwrap()
+                .get(uuid)
+                .map(|rt| rt.lock().unwrap().get_leafs().clone())
+        };
+        for (uuid, winner) in merged.winning_revisions() {
+            let previous = before.get(&uuid).cloned().flatten();
+            if is_array_descriptor(&uuid) {
+                if leaves(&merged, &uuid) == leaves(&base, &uuid) {
+                    continue;
+                }
+                let after = order(&merged, &uuid)?;
+                if after != order(&base, &uuid)? {
+                    let path = merged.get_location(&uuid)?.map(|l| l.pointer);
+                    if let (Some(path), Some(Value::Array(after))) = (path, after) {
+                        let after = after
+                            .iter()
+                            .filter_map(Value::as_str)
+                            .map(str::to_string)
+                            .collect();
+                        preview.arrays.insert(path, after);
+                    }
+                }
+                continue;
+            }
+            if previous == winner {
+                continue;
+            }
+            let existed = previous.is_some_and(|r| !r.is_deleted());
+            let exists = winner.is_some_and(|r| !r.is_deleted());
+            let kind = match (existed, exists) {
+                (false, false) => continue,
+                (false, true) => ChangeKind::Created,
+                (true, false) => ChangeKind::Deleted,
+                (true, true) => ChangeKind::Updated,
+            };
+            preview.changes.insert(uuid, kind);
+        }
+        preview.conflicts = merged
+            .in_conflict()
+            .into_iter()
+            .filter(|uuid| !is_array_descriptor(uuid) && !conflicting.contains(uuid))
+            .collect();
+        Ok(preview)
+    }
+
+    /// Creates a shallow replica of this one into an empty target replica. A single graft block
+    /// replaces the whole history: it records the current revisions of each object (the winner
+    /// and the conflicting ones) and only carries the values needed to read them. Melding into
+    /// the shallow replica does not fetch the grafted history, which can be fetched later with
+    /// [Melda::backfill]. Returns the identifier of the graft block (None if there is nothing
+    /// to clone)
+    ///
+    /// # Arguments
+    ///
+    /// * `target` - An empty replica
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+    /// for i in 0..5 {
+    ///     replica.update(json!({ "key" : i, "items" : [ i ] }).as_object().unwrap().clone()).unwrap();
+    ///     replica.commit(None).unwrap();
+    /// }
+    /// let shallow = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+    /// let graft = shallow.shallow_clone_from(&replica).unwrap().unwrap();
+    /// assert!(shallow.is_shallow());
+    /// assert_eq!(shallow.get_anchors(), [graft.clone()].into());
+    /// assert_eq!(shallow.read(None).unwrap(), replica.read(None).unwrap());
+    /// // New changes of the other replica are melded, the grafted history is not
+    /// replica.update(json!({ "key" : 5, "items" : [ 5 ] }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// assert_eq!(shallow.meld(&replica).unwrap().len(), 2);
+    /// shallow.refresh().unwrap();
+    /// assert_eq!(shallow.read(None).unwrap(), replica.read(None).unwrap());
+    /// shallow.update(json!({ "key" : 6 }).as_object().unwrap().clone()).unwrap();
+    /// assert!(shallow.commit(None).unwrap().is_some());
+    /// assert!(shallow.fsck().unwrap().is_clean());
+    /// // The history is fetched on demand
+    /// assert!(!shallow.backfill(&replica).unwrap().is_empty());
+    /// shallow.refresh().unwrap();
+    /// assert!(!shallow.is_shallow());
+    /// assert_eq!(shallow.read(None).unwrap().get("key").unwrap(), 6);
+    /// ```
+    pub fn shallow_clone_from(&self, other: &Melda) -> Result<Option<String>> {
+        if self.has_staging() || other.has_staging() {
+            bail!(MeldaError::StageNotEmpty)
+        }
+        if self.digest != other.digest {
+            bail!("digest_algorithm_mismatch");
+        }
+        if !self.blocks.read().unwrap().is_empty() {
+            bail!("replica_not_empty");
+        }
+        // The graft replaces the applied blocks of the other replica (and the blocks grafted by
+        // them, if the other replica is shallow as well)
+        let mut grafted = BTreeSet::new();
+        for (bid, block) in other.blocks.read().unwrap().iter() {
+            let block = block.read().unwrap();
+            if block.status == Status::ValidAndApplied {
+                grafted.insert(bid.clone());
+                grafted.extend(block.grafted.iter().flatten().cloned());
+            }
+        }
+        if grafted.is_empty() {
+            return Ok(None);
+        }
+        let mut revisions = vec![];
+        for (uuid, rt) in other.documents.read().unwrap().iter() {
+            let rt_r = rt.lock().expect("cannot_acquire_revision_tree_for_reading");
+            for (rev, parent) in other.graft_revisions(uuid, &rt_r)? {
+                revisions.push((uuid.clone(), rev, parent));
+            }
+        }
+        // Copy the values of the recorded revisions
+        let other_data = other.data.read().expect("cannot_acquire_data_for_reading");
+        let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
+        let mut changes = Vec::<Value>::with_capacity(revisions.len());
+        for (uuid, rev, parent) in revisions {
+            if !(rev.is_deleted() || rev.is_resolved() || rev.is_empty() || rev.is_charcode()) {
+                data.write_raw_value(rev.digest(), other_data.read_value(rev.digest())?)?;
+            }
+            match parent {
+                // Creation record
+                None => changes.push(Value::from(vec![uuid, rev.digest().clone()])),
+                // Update record (the parent is not necessarily recorded by the graft)
+                Some(parent) => changes.push(Value::from(vec![
+                    uuid,
+                    parent.to_string(),
+                    rev.digest().clone(),
+                ])),
+            }
+        }
+        drop(other_data);
+        let mut block = Map::<String, Value>::new();
+        block.insert(CHANGESETS_FIELD.to_string(), Value::from(changes));
+        if let Some(pack) = data.pack()? {
+            block.insert(PACK_FIELD.to_string(), Value::from(vec![pack]));
+        }
+        let grafted: Vec<String> = grafted.into_iter().collect();
+        block.insert(GRAFT_FIELD.to_string(), Value::from(grafted));
+        let blockdata = encode_value(&Value::from(block), self.encoding)?;
+        let block_hash = digest_bytes_with(self.digest, &blockdata);
+        data.write_raw_item(&(block_hash.clone() + DELTA_EXTENSION), &blockdata)?;
+        drop(data);
+        self.reload()?;
+        Ok(Some(block_hash))
+    }
+
+    /// Returns true if some of the blocks replaced by a graft (see [Melda::shallow_clone_from])
+    /// have not been fetched yet
+    pub fn is_shallow(&self) -> bool {
+        !self.missing_grafted_blocks().is_empty()
+    }
+
+    /// Melds another replica including the history replaced by the grafts of this (shallow)
+    /// replica, see [Melda::shallow_clone_from]. Like [Melda::meld], the state is not changed
+    /// until the next refresh. Returns the melded items
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - Another Melda instance
+    pub fn backfill(&self, other: &Melda) -> Result<Vec<String>> {
+        other.local_blocks.lock().unwrap().clear();
+        self.transfer_items(other, &HashSet::new(), |_, _| {})
+    }
+
+    // Copies the items of the other replica which are missing in this one (except the skipped
+    // items)
+    fn transfer_items<F: FnMut(usize, usize)>(
+        &self,
+        other: &Melda,
+        skipped: &HashSet<String>,
+        mut progress: F,
+    ) -> Result<Vec<String>> {
+        let span = operation_span!("meld");
+        if self.digest != other.digest {
+            bail!("digest_algorithm_mismatch");
+        }
         let mut result = vec![];
+        let mut bytes = 0;
         let other_data = other.data.read().unwrap();
-        let other_items = other_data.list_raw_items("")?;
-        if !other_items.is_empty() {
-            let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
-            let this_items = data.list_raw_items("")?;
-            let this_items: HashSet<String> = this_items.into_iter().collect();
-            for i in &other_items {
-                if !this_items.contains(i) {
-                    data.write_raw_item(i, other_data.read_raw_item(i, 0, 0)?.as_slice())?;
-                    result.push(i.clone());
+        let data = self.data.write().expect("cannot_acquire_data_for_writing");
+        // Enumerate the items of both replicas concurrently
+        let (other_items, this_items) =
+            rayon::join(|| other_data.list_raw_items(""), || data.list_raw_items(""));
+        // The metadata of each repository is not transferred (nor are attachments, if they are
+        // fetched lazily)
+        let other_items: Vec<String> = other_items?
+            .into_iter()
+            .filter(|i| !i.ends_with(METADATA_EXTENSION))
+            .filter(|i| !(self.lazy_attachments && i.ends_with(ATTACHMENT_EXTENSION)))
+            .collect();
+        let this_items: HashSet<String> = this_items?.into_iter().collect();
+        let total = other_items.len();
+        // Packs (and other items) are transferred before the blocks referencing them
+        let (blocks, items): (Vec<&String>, Vec<&String>) = other_items
+            .iter()
+            .filter(|i| !this_items.contains(*i) && !skipped.contains(*i))
+            .partition(|i| i.ends_with(DELTA_EXTENSION));
+        let missing = blocks.len() + items.len();
+        let mut done = total - missing;
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.meld_parallelism)
+            .build()?;
+        let source = other_data.get_adapter();
+        let target = data.get_adapter();
+        for group in &[items, blocks] {
+            for batch in group.chunks(self.meld_parallelism * 4) {
+                // Adapters are Sync: concurrent reads and writes only require shared access
+                let copied: Result<Vec<(String, usize)>> = pool.install(|| {
+                    batch
+                        .par_iter()
+                        .map(|i| {
+                            let content = source.read().unwrap().read_object(i, 0, 0)?;
+                            verify_item(self.digest, i, &content)?;
+                            target.read().unwrap().write_object(i, &content)?;
+                            Ok((i.to_string(), content.len()))
+                        })
+                        .collect()
+                });
+                for (item, size) in copied? {
+                    result.push(item);
+                    bytes += size;
                 }
+                done += batch.len();
+                progress(done, total);
             }
         }
+        if missing == 0 && total > 0 {
+            progress(done, total);
+        }
+        span.record("items", result.len());
+        span.record("bytes", bytes);
         Ok(result)
     }
 
+    /// Compares this replica with another one without melding: returns the blocks available in
+    /// only one of the replicas and the number of bytes a meld would transfer in each
+    /// direction. Only committed items are compared, and the history replaced by the grafts of a
+    /// shallow replica (which is not melded, see [Melda::shallow_clone_from]) is ignored
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - Another Melda instance
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+    /// replica.update(json!({ "key" : "alpha" }).as_object().unwrap().clone()).unwrap();
+    /// let anchors = replica.commit(None).unwrap().unwrap();
+    /// let replica2 = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+    /// let divergence = replica2.compare(&replica).unwrap();
+    /// assert!(divergence.is_ancestor());
+    /// assert_eq!(divergence.remote_only, anchors);
+    /// assert!(divergence.fetch_bytes > 0 && divergence.send_bytes == 0);
+    /// replica2.meld(&replica).unwrap();
+    /// assert!(replica2.compare(&replica).unwrap().in_sync());
+    /// replica.update(json!({ "key" : "beta" }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// replica2.update(json!({ "key" : "gamma" }).as_object().unwrap().clone()).unwrap();
+    /// replica2.commit(None).unwrap();
+    /// let divergence = replica.compare(&replica2).unwrap();
+    /// assert!(divergence.has_diverged());
+    /// assert_eq!(divergence.local_only.len(), 1);
+    /// assert_eq!(divergence.remote_only.len(), 1);
+    /// ```
+    pub fn compare(&self, other: &Melda) -> Result<Divergence> {
+        if self.digest != other.digest {
+            bail!("digest_algorithm_mismatch");
+        }
+        let fetch_skipped = other.grafted_items(&self.missing_grafted_blocks());
+        let send_skipped = self.grafted_items(&other.missing_grafted_blocks());
+        let other_data = other.data.read().unwrap();
+        let data = self.data.read().unwrap();
+        let (other_items, this_items) =
+            rayon::join(|| other_data.list_raw_items(""), || data.list_raw_items(""));
+        // The metadata of each repository is not transferred
+        let committed = |items: Vec<String>| -> BTreeSet<String> {
+            items
+                .into_iter()
+                .filter(|i| !i.ends_with(METADATA_EXTENSION))
+                .collect()
+        };
+        let (other_items, this_items) = (committed(other_items?), committed(this_items?));
+        let mut divergence = Divergence::default();
+        let source = other_data.get_adapter();
+        for i in other_items.difference(&this_items) {
+            if fetch_skipped.contains(i)
+                || (self.lazy_attachments && i.ends_with(ATTACHMENT_EXTENSION))
+            {
+                continue;
+            }
+            if let Some(block) = i.strip_suffix(DELTA_EXTENSION) {
+                divergence.remote_only.insert(block.to_string());
+            }
+            divergence.fetch_bytes += source.read().unwrap().object_size(i)?;
+        }
+        let target = data.get_adapter();
+        for i in this_items.difference(&other_items) {
+            if send_skipped.contains(i)
+                || (other.lazy_attachments && i.ends_with(ATTACHMENT_EXTENSION))
+            {
+                continue;
+            }
+            if let Some(block) = i.strip_suffix(DELTA_EXTENSION) {
+                divergence.local_only.insert(block.to_string());
+            }
+            divergence.send_bytes += target.read().unwrap().object_size(i)?;
+        }
+        Ok(divergence)
+    }
+
     /// Reads the data structure and unflattens to a JSON object
     ///
+    /// The state read from the root object is published (unless a memory budget is set): until
+    /// the next change, reads of the whole state (and [Melda::get_value] of the winning
+    /// revisions) return it without acquiring any lock, hence they do not wait for concurrent
+    /// melds, commits or refreshes. A refresh publishes the new state if the previous one was
+    /// being read
+    ///
     /// # Arguments
     ///
     /// * `root` - Optional identifier of the root object (starting point)
@@ -1266,7 +2734,7 @@ impl Melda {
     /// use serde_json::{Map, Value,json,to_string};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
     /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
     /// replica.update(object.clone());
     /// let readback = replica.read(None).unwrap();
@@ -1275,7 +2743,7 @@ impl Melda {
     /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\":[\"somedata\",1,2,3,4]}", content);
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
     /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey\u{266D}" : [ { "_id": "1", "key" : "alpha" }, { "_id": "2", "key" : "beta" } ] }).as_object().unwrap().clone();
     /// replica.update(object.clone());
     /// let readback = replica.read(None).unwrap();
@@ -1287,7 +2755,7 @@ impl Melda {
     /// replica.commit(Some(info));
     /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
     /// let adapter2 = Arc::new(RwLock::new(adapter2));
-    /// let mut replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
+    /// let replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
     /// replica2.meld(&replica);
     /// replica2.refresh();
     /// // Continue editing on replica, removing one item
@@ -1325,8 +2793,105 @@ impl Melda {
     /// let readback = replica.read(None).unwrap();
     /// let content = serde_json::to_string(&readback).unwrap();
     /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\u{266D}\":[{\"_id\":\"2\",\"key\":\"beta\"},{\"_id\":\"3\",\"key\":\"gamma\"}]}", content);
+    /// // Large documents are reconstructed concurrently
+    /// let items: Vec<Value> = (0..2000).map(|i| json!({ "_id" : i.to_string(), "value" : i })).collect();
+    /// replica.update(json!({ "items\u{266D}" : items.clone() }).as_object().unwrap().clone()).unwrap();
+    /// let readback = replica.read(None).unwrap();
+    /// assert_eq!(readback.get("items\u{266D}").unwrap(), &Value::from(items));
+    /// // Readers are not blocked by background refreshes
+    /// let items = readback.get("items\u{266D}").unwrap().clone();
+    /// replica.commit(None).unwrap();
+    /// let other = Melda::new_from_url("memory://").unwrap();
+    /// other.meld(&replica).unwrap();
+    /// other.refresh().unwrap();
+    /// let replica = Arc::new(replica);
+    /// let background = replica.clone();
+    /// let worker = std::thread::spawn(move || {
+    ///     for i in 0..10 {
+    ///         other.set("/count", json!(i)).unwrap();
+    ///         other.commit(None).unwrap();
+    ///         background.meld(&other).unwrap();
+    ///         background.refresh().unwrap();
+    ///     }
+    /// });
+    /// while !worker.is_finished() {
+    ///     let readback = replica.read(None).unwrap();
+    ///     assert_eq!(readback.get("items\u{266D}").unwrap(), &items);
+    /// }
+    /// worker.join().unwrap();
+    /// assert_eq!(replica.read(None).unwrap().get("count").unwrap(), 9);
+    /// assert_eq!(replica.get_value("\u{221A}", None).unwrap().get("count").unwrap(), 9);
+    /// // Missing data is reported as an error
+    /// let adapter = replica.get_adapter();
+    /// let reopened = Melda::new(adapter.clone()).unwrap();
+    /// for pack in adapter.read().unwrap().list_objects(".pack").unwrap() {
+    ///     adapter.read().unwrap().delete_object(&(pack + ".pack")).unwrap();
+    /// }
+    /// assert!(reopened.read(None).is_err());
+    /// ```
     pub fn read(&self, root: Option<&str>) -> Result<Map<String, Value>> {
+        if root.unwrap_or(ROOT_ID) == ROOT_ID {
+            if let Some(published) = self.published() {
+                return Ok(published.state.clone());
+            }
+        }
+        self.read_with(root, false)
+    }
+
+    // Returns the state published by the last full read, if no revision tree changed since
+    fn published(&self) -> Option<Arc<PublishedState>> {
+        self.published
+            .load_full()
+            .filter(|p| p.generation == self.generation.load(Ordering::SeqCst))
+    }
+
+    /// Reads the current state like [Melda::read], embedding the unresolved alternatives of the
+    /// objects in conflict: each field whose value differs among the conflicting revisions is
+    /// replaced by an object holding the winning value (`~winner`) and the distinct values of
+    /// the other revisions (`~conflicts`, null where the field is missing). Flattened fields
+    /// are not marked (concurrent changes to collections are merged), nor are the revisions
+    /// which delete the object.
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - Optional root object identifier
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::{json, Value};
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// replica.update(json!({ "tasks\u{266D}" : [ { "_id" : "1", "title" : "Call Alice", "done" : false } ] }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let other = Melda::new_from_url("memory://").unwrap();
+    /// other.meld(&replica).unwrap();
+    /// other.refresh().unwrap();
+    /// other.set("/tasks\u{266D}/0/title", json!("Call Bob")).unwrap();
+    /// other.commit(None).unwrap();
+    /// replica.set("/tasks\u{266D}/0/title", json!("Call Carol")).unwrap();
+    /// replica.commit(None).unwrap();
+    /// replica.meld(&other).unwrap();
+    /// replica.refresh().unwrap();
+    /// let content = replica.read_with_conflicts(None).unwrap();
+    /// let title = &content["tasks\u{266D}"][0]["title"];
+    /// let winner = replica.read(None).unwrap()["tasks\u{266D}"][0]["title"].clone();
+    /// assert_eq!(title["~winner"], winner);
+    /// let loser = if winner == "Call Bob" { "Call Carol" } else { "Call Bob" };
+    /// assert_eq!(title["~conflicts"], json!([loser]));
+    /// // Fields which do not differ are left untouched
+    /// assert_eq!(content["tasks\u{266D}"][0]["done"], json!(false));
+    /// ```
+    pub fn read_with_conflicts(&self, root: Option<&str>) -> Result<Map<String, Value>> {
+        self.read_with(root, true)
+    }
+
+    // Reads the current state, embedding the alternatives of the fields in conflict if requested
+    fn read_with(&self, root: Option<&str>, conflicts: bool) -> Result<Map<String, Value>> {
         let start = root.unwrap_or(ROOT_ID);
+        // Full reads are published, unless memory is constrained (changes made while reading
+        // increment the generation afterwards, hence the published state is never served)
+        let publish = start == ROOT_ID && !conflicts && self.memory_budget.is_none();
+        let generation = self.generation.load(Ordering::SeqCst);
         if !self
             .documents
             .read()
@@ -1335,42 +2900,158 @@ impl Melda {
         {
             bail!("no_root")
         } else {
-            let c = Mutex::new(HashMap::<String, Map<String, Value>>::new());
             let docs_r = self
                 .documents
                 .read()
                 .expect("failed_to_acquire_documents_for_reading");
-            docs_r.par_iter().for_each(|(uuid, rt)| {
-                let rt_r = rt
-                    .lock()
-                    .expect("failed_to_acquire_revision_tree_for_reading");
-                if let Some(winner) = rt_r.get_winner() {
-                    if !winner.is_deleted() {
-                        let mut obj = self.read_object_at_revision(uuid, &rt_r, winner).unwrap();
-                        drop(rt_r);
-                        obj.insert(ID_FIELD.to_string(), Value::from(uuid.clone()));
-                        let mut c_w = c.lock().unwrap();
-                        c_w.insert(uuid.to_string(), obj);
-                        drop(c_w);
-                    }
-                }
+            // The winner of each object is resolved and read independently
+            let mut c: HashMap<String, Map<String, Value>> = docs_r
+                .par_iter()
+                .filter_map(|(uuid, rt)| {
+                    let rt_r = rt
+                        .lock()
+                        .expect("failed_to_acquire_revision_tree_for_reading");
+                    let winner = rt_r.get_winner().filter(|w| !w.is_deleted())?;
+                    let object =
+                        self.read_object_at_revision(uuid, &rt_r, winner)
+                            .and_then(|mut obj| {
+                                if conflicts
+                                    && !is_array_descriptor(uuid)
+                                    && rt_r.get_leafs().len() > 1
+                                {
+                                    let alternatives = rt_r
+                                        .get_leafs()
+                                        .iter()
+                                        .filter(|l| *l != winner && !l.is_deleted())
+                                        .map(|l| self.read_object_at_revision(uuid, &rt_r, l))
+                                        .collect::<Result<Vec<_>>>()?;
+                                    embed_conflicts(&mut obj, &alternatives);
+                                }
+                                Ok((uuid.clone(), obj))
+                            });
+                    drop(rt_r);
+                    Some(object)
+                })
+                .collect::<Result<_>>()?;
+            drop(docs_r);
+            let objects = publish.then(|| {
+                c.iter()
+                    .filter(|(uuid, _)| !is_array_descriptor(uuid))
+                    .map(|(uuid, obj)| (uuid.clone(), obj.clone()))
+                    .collect()
             });
-            let mut c_r: std::sync::MutexGuard<'_, HashMap<String, Map<String, Value>>> =
-                c.lock().unwrap();
-            let root = c_r.get(start).expect("root_object_not_found");
-            let root = Value::from(root.clone());
-            let result = unflatten(&mut c_r, &root)
-                .unwrap()
-                .as_object()
-                .expect("not_an_object")
-                .clone();
-            drop(c_r);
-            Ok(result)
+            for (uuid, obj) in c.iter_mut() {
+                obj.insert(ID_FIELD.to_string(), Value::from(uuid.clone()));
+            }
+            self.enforce_memory_budget()?;
+            let root = Value::from(c.get(start).ok_or_else(|| anyhow!("no_root"))?.clone());
+            // Large documents are reconstructed concurrently (array elements are independent),
+            // unless a single thread is available
+            let result =
+                if c.len() < PARALLEL_UNFLATTEN_THRESHOLD || rayon::current_num_threads() == 1 {
+                    unflatten(&mut c, &root)
+                } else {
+                    let c = c
+                        .into_iter()
+                        .map(|(uuid, obj)| (uuid, Mutex::new(Some(obj))))
+                        .collect();
+                    par_unflatten(&c, &root)
+                };
+            let state = result
+                .and_then(|state| state.as_object().cloned())
+                .ok_or_else(|| anyhow!("invalid_root"))?;
+            if let Some(objects) = objects {
+                self.published.store(Some(Arc::new(PublishedState {
+                    generation,
+                    objects,
+                    state: state.clone(),
+                })));
+            }
+            Ok(state)
         }
     }
 
+    /// Reads the current state like [Melda::read], sharing the subtrees of the previous shared
+    /// read which did not change: only changed objects (and the objects containing them) are
+    /// materialized again, other arrays and objects are reference counted instead of copied.
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - Optional root object identifier
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+    /// let object = json!({ "a\u{266D}" : [ { "_id" : "1", "key" : "alpha" } ], "b\u{266D}" : [ { "_id" : "2", "key" : "beta" } ] });
+    /// replica.update(object.as_object().unwrap().clone()).unwrap();
+    /// let first = replica.read_shared(None).unwrap();
+    /// assert_eq!(first.to_value(), serde_json::Value::from(replica.read(None).unwrap()));
+    /// replica.set("/b\u{266D}/0/key", json!("gamma")).unwrap();
+    /// let second = replica.read_shared(None).unwrap();
+    /// // The unchanged array is shared, the changed one is materialized again
+    /// assert!(second.get("a\u{266D}").unwrap().is_shared_with(first.get("a\u{266D}").unwrap()));
+    /// assert!(!second.get("b\u{266D}").unwrap().is_shared_with(first.get("b\u{266D}").unwrap()));
+    /// assert_eq!(second.pointer("/b\u{266D}/0/key").unwrap().to_value(), json!("gamma"));
+    /// assert!(replica.read_shared(None).unwrap().is_shared_with(&second));
+    /// ```
+    pub fn read_shared(&self, root: Option<&str>) -> Result<SharedValue> {
+        let start = root.unwrap_or(ROOT_ID);
+        let mut reached = HashSet::new();
+        let value = self
+            .share_object(start, &mut vec![], &mut reached)?
+            .ok_or_else(|| anyhow!("no_root"))?;
+        // Only keep the objects of the last read
+        self.shared
+            .lock()
+            .unwrap()
+            .retain(|uuid, _| reached.contains(uuid));
+        self.enforce_memory_budget()?;
+        Ok(value)
+    }
+
+    /// Takes a snapshot of the current state (including the staged changes), which remains
+    /// consistent while other threads commit, refresh or reload: the snapshot is taken while
+    /// these operations are excluded, so it never observes a partially applied refresh. Objects
+    /// which did not change since the previous shared read are shared with it (see
+    /// [Melda::read_shared]), and clones of the snapshot are cheap.
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// use std::sync::Arc;
+    /// let replica = Arc::new(Melda::new_from_url("memory://").unwrap());
+    /// replica.update(json!({ "items\u{266D}" : [ { "_id" : "1", "v" : 1 } ] }).as_object().unwrap().clone()).unwrap();
+    /// let anchors = replica.commit(None).unwrap().unwrap();
+    /// let snapshot = replica.snapshot().unwrap();
+    /// let exporter = {
+    ///     let snapshot = snapshot.clone();
+    ///     std::thread::spawn(move || serde_json::to_string(&snapshot.read()).unwrap())
+    /// };
+    /// replica.set("/items\u{266D}/0/v", json!(2)).unwrap();
+    /// replica.commit(None).unwrap();
+    /// // The snapshot is not affected by later changes
+    /// assert_eq!(exporter.join().unwrap(), "{\"_id\":\"\u{221A}\",\"items\u{266D}\":[{\"_id\":\"1\",\"v\":1}]}");
+    /// assert_eq!(snapshot.pointer("/items\u{266D}/0/v").unwrap().to_value(), json!(1));
+    /// assert_eq!(snapshot.anchors(), &anchors);
+    /// assert_eq!(replica.snapshot().unwrap().read(), replica.read(None).unwrap());
+    /// ```
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        let _exclusive = self.exclusive.acquire();
+        Ok(Snapshot {
+            anchors: Arc::new(self.get_anchors()),
+            state: self.read_shared(None)?,
+        })
+    }
+
     /// Updates the data structure by flattening the input JSON object
     ///
+    /// Flattened fields of the root object which did not change since the previous update
+    /// (and were not touched through [Melda::set] or [Melda::apply_patch]) are not flattened
+    /// nor diffed again.
+    ///
     /// # Arguments
     ///
     /// * `obj` - input JSON object
@@ -1382,7 +3063,7 @@ impl Melda {
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
     /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
     /// replica.update(object.clone());
     /// let readback = replica.read(None).unwrap();
@@ -1394,82 +3075,128 @@ impl Melda {
     /// let content = serde_json::to_string(&readback).unwrap();
     /// let check = serde_json::to_string(&object).unwrap();
     /// assert!(content == check);
+    /// let object = json!({ "items\u{266D}" : [ { "_id" : "a", "v" : 1 } ], "tags\u{266D}" : [ "x" ] });
+    /// replica.update(object.as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let object = json!({ "items\u{266D}" : [ { "_id" : "a", "v" : 1 } ], "tags\u{266D}" : [ "x", "y" ] });
+    /// replica.update(object.as_object().unwrap().clone()).unwrap();
+    /// // Only the array of tags was updated
+    /// let stage = replica.stage().unwrap().unwrap();
+    /// assert_eq!(stage["c"].as_array().unwrap().len(), 1);
+    /// replica.set("/items\u{266D}/0/v", json!(2)).unwrap();
+    /// replica.update(object.as_object().unwrap().clone()).unwrap();
+    /// assert_eq!(replica.read(None).unwrap()["items\u{266D}"][0]["v"], 1);
+    /// ```
     pub fn update(&self, obj: Map<String, Value>) -> Result<String> {
-        let mut extracted_objects = HashMap::<String, Map<String, Value>>::new();
-        let path = Vec::<String>::new();
-        let root = Value::from(obj);
-        // Flatten the structure
-        let root = flatten(&mut extracted_objects, &root, &path);
-        let root = root.as_str().expect("root_identifier_not_a_string");
-        // Check for objects that have disappeared
-        // i.e. objects that are found in the current state but are not within the extracted objects
-        let docs_r = self
-            .documents
-            .read()
-            .expect("failed_to_acquire_documents_for_reading");
-        docs_r
-            .par_iter()
-            .filter(|(uuid, _)| !extracted_objects.contains_key(*uuid))
-            .for_each(|(uuid, _)| {
-                self.delete_object(uuid).expect("unable_to_delete_object");
-            });
-        drop(docs_r);
-        // Check for newly created and updated objects
-        extracted_objects.into_par_iter().for_each(|(uuid, obj)| {
-            //for (uuid, obj) in extracted_objects {
+        let span = operation_span!("update");
+        let mut clean = self.clean_fields.lock().unwrap();
+        let plan = self.plan_update(&obj, &clean)?;
+        span.record("objects", plan.objects.len());
+        if clean.root != plan.root {
+            clean.fields.clear();
+            clean.root = plan.root.clone();
+        }
+        let mut fields: HashMap<String, CleanField> = plan
+            .reused_fields
+            .iter()
+            .filter_map(|key| clean.fields.remove_entry(key))
+            .collect();
+        plan.deleted.par_iter().for_each(|uuid| {
+            self.delete_object(uuid).expect("unable_to_delete_object");
+        });
+        plan.objects.into_par_iter().for_each(|(uuid, obj)| {
             self.update_object(&uuid, obj)
                 .expect("unable_to_update_object");
         });
-        Ok(root.to_string())
+        // Record the fields for the next update
+        for (key, value, flattened, uuids) in plan.changed_fields {
+            let objects = uuids
+                .into_iter()
+                .map(|uuid| {
+                    let winner = self.winning_revision(&uuid);
+                    (uuid, winner)
+                })
+                .collect();
+            let field = CleanField {
+                value,
+                flattened,
+                objects,
+            };
+            fields.insert(key, field);
+        }
+        clean.fields = fields;
+        Ok(plan.root)
     }
 
-    /// Returns a set of the object (identifiers) which have ongoing conflicts
-    ///
-    /// # Example
-    /// ```
-    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
-    /// use std::sync::{Arc, Mutex, RwLock};
-    /// use serde_json::{Map, Value,json};
-    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
-    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica.create_object("myobject", object);  
-    /// assert!(replica.get_all_objects().contains("myobject"));
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
-    /// let block_id = replica.commit(None).unwrap().unwrap();
-    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter2 = Arc::new(RwLock::new(adapter2));
-    /// let mut replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
-    /// let object = json!({ "another" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica2.create_object("myobject", object);
-    /// let anchors2 = replica2.commit(None).unwrap().unwrap();
-    /// replica2.meld(&replica);
-    /// replica2.refresh();
-    /// let conflicting = replica2.in_conflict();
-    /// assert!(conflicting.contains("myobject"));
-    pub fn in_conflict(&self) -> BTreeSet<String> {
-        self.documents
+    // Plans an update: validates the input object, flattens it (fields which did not change
+    // since the last update are reused) and determines the objects which disappeared from the
+    // document. Nothing is staged, see [Melda::update] and [Melda::preview_update]
+    fn plan_update(&self, obj: &Map<String, Value>, clean: &CleanFields) -> Result<UpdatePlan> {
+        if self.strict {
+            StrictValidator::default().validate_object(obj, &[], &[])?;
+        }
+        let root = generate_identifier(obj, &[])?;
+        self.check_schema(obj, &root)?;
+        let path = vec![root.clone()];
+        let previous = if clean.root == root {
+            Some(&clean.fields)
+        } else {
+            None
+        };
+        // Flatten the structure (fields which did not change since the last update are reused)
+        let mut objects = HashMap::<String, Map<String, Value>>::new();
+        let mut root_object = Map::<String, Value>::new();
+        let mut reused = HashSet::<String>::new();
+        let mut reused_fields = vec![];
+        let mut changed_fields = vec![];
+        for (key, value) in obj.iter().filter(|(k, _)| *k != ID_FIELD) {
+            if !is_flattened_field(key) {
+                root_object.insert(key.clone(), value.clone());
+                continue;
+            }
+            match previous.and_then(|fields| fields.get(key)) {
+                Some(field) if field.value == *value && self.is_unchanged(&field.objects) => {
+                    reused.extend(field.objects.iter().map(|(uuid, _)| uuid.clone()));
+                    root_object.insert(key.clone(), field.flattened.clone());
+                    reused_fields.push(key.clone());
+                }
+                _ => {
+                    let mut extracted = HashMap::new();
+                    let flattened = flatten_field(&mut extracted, key, value, &path);
+                    root_object.insert(key.clone(), flattened.clone());
+                    let uuids: Vec<String> = extracted.keys().cloned().collect();
+                    changed_fields.push((key.clone(), value.clone(), flattened, uuids));
+                    objects.extend(extracted);
+                }
+            }
+        }
+        objects.insert(root.clone(), root_object);
+        // Check for objects that have disappeared
+        // i.e. objects that are found in the current state but are not within the extracted objects
+        let deleted = self
+            .documents
             .read()
             .expect("failed_to_acquire_documents_for_reading")
-            .par_iter()
-            .filter(|(_, rt)| {
-                let rt_r = rt
-                    .lock()
-                    .expect("failed_to_acquire_revision_tree_for_reading");
-                let l = rt_r.get_leafs();
-                l.len() > 1
-            })
-            .map(|(uuid, _)| uuid.clone())
-            .collect()
+            .keys()
+            .filter(|uuid| !objects.contains_key(*uuid) && !reused.contains(*uuid))
+            .cloned()
+            .collect();
+        Ok(UpdatePlan {
+            root,
+            objects,
+            deleted,
+            reused_fields,
+            changed_fields,
+        })
     }
 
-    /// Returns the winning revision for the given object
+    /// Reads only the given fields of the root object: objects which are not referenced by the
+    /// requested subtrees are neither reconstructed nor read from the packs. Missing fields are
+    /// omitted from the result.
     ///
     /// # Arguments
     ///
-    /// * `uuid` - The uuid of the object
+    /// * `fields` - The fields of the root object to read
     ///
     /// # Example
     /// ```
@@ -1477,51 +3204,269 @@ impl Melda {
     /// use std::sync::{Arc, Mutex, RwLock};
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
-    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica.create_object("myobject", object);  
-    /// assert!(replica.get_all_objects().contains("myobject"));
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
-    /// let block_id = replica.commit(None).unwrap().unwrap();
-    /// replica.delete_object("myobject");
-    /// assert!(replica.get_all_objects().contains("myobject"));
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("2-d_e5d1d20", winner);
-    /// let value = replica.get_value("myobject", Some(&winner));
-    /// assert!(value.is_ok());
-    /// assert!(value.unwrap().contains_key("_deleted"));
-    /// replica.unstage();
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "settings" : { "theme" : "dark" }, "tasks\u{266D}" : [ { "_id" : "1", "key" : "alpha" } ], "notes\u{266D}" : [ { "_id" : "2", "key" : "beta" } ] }).as_object().unwrap().clone();
+    /// replica.update(object).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let content = replica.read_paths(&["tasks\u{266D}", "settings", "missing"]).unwrap();
+    /// assert_eq!(Value::from(content), json!({ "_id" : "\u{221A}", "settings" : { "theme" : "dark" }, "tasks\u{266D}" : [ { "_id" : "1", "key" : "alpha" } ] }));
     /// ```
-    pub fn get_winner<T>(&self, uuid: T) -> Result<String>
-    where
-        T: AsRef<str>,
-    {
-        match self
-            .documents
+    pub fn read_paths(&self, fields: &[&str]) -> Result<Map<String, Value>> {
+        let root = self
+            .load_flattened_object(ROOT_ID)?
+            .ok_or_else(|| anyhow!("no_root"))?;
+        let mut result = Map::<String, Value>::new();
+        let mut visited = BTreeSet::from([ROOT_ID.to_string()]);
+        for field in fields {
+            if let Some(value) = root.get(*field) {
+                let value = if is_flattened_field(field) {
+                    self.resolve_flattened(value, &mut visited)?
+                } else {
+                    value.clone()
+                };
+                result.insert(field.to_string(), value);
+            }
+        }
+        result.insert(ID_FIELD.to_string(), Value::from(ROOT_ID));
+        Ok(result)
+    }
+
+    /// Updates a single document of the data structure, which may hold several documents (each
+    /// one with its own root object). Unlike [Melda::update], only objects reachable from the
+    /// given root which no longer appear in the new state are deleted.
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - Identifier of the root object of the document
+    /// * `obj` - The new state of the document
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// replica.update_document("\u{221A}", json!({ "items\u{266D}" : [ { "_id" : "1" } ] }).as_object().unwrap().clone()).unwrap();
+    /// replica.update_document("notes", json!({ "items\u{266D}" : [ { "_id" : "2" } ] }).as_object().unwrap().clone()).unwrap();
+    /// replica.update_document("notes", json!({ "items\u{266D}" : [ { "_id" : "3" } ] }).as_object().unwrap().clone()).unwrap();
+    /// assert_eq!(Value::from(replica.read(None).unwrap()), json!({ "_id" : "\u{221A}", "items\u{266D}" : [ { "_id" : "1" } ] }));
+    /// assert_eq!(Value::from(replica.read(Some("notes")).unwrap()), json!({ "_id" : "notes", "items\u{266D}" : [ { "_id" : "3" } ] }));
+    /// ```
+    pub fn update_document(&self, root: &str, mut obj: Map<String, Value>) -> Result<()> {
+        match obj.get(ID_FIELD) {
+            Some(id) if id.as_str() != Some(root) => bail!("cannot_change_object_identifier"),
+            _ => {
+                obj.insert(ID_FIELD.to_string(), Value::from(root));
+            }
+        }
+        if self.strict {
+            StrictValidator::default().validate_object(&obj, &[], &[])?;
+        }
+        self.check_schema(&obj, root)?;
+        let mut extracted_objects = HashMap::<String, Map<String, Value>>::new();
+        flatten(
+            &mut extracted_objects,
+            &Value::from(obj),
+            &Vec::<String>::new(),
+        );
+        // Objects of the document which have disappeared
+        let mut locations = HashMap::new();
+        self.locate_object(root, Location::root(root), &mut vec![], &mut locations)?;
+        self.locations.lock().unwrap().add_root(root);
+        for uuid in locations.keys() {
+            if !extracted_objects.contains_key(uuid) {
+                self.delete_object(uuid)?;
+            }
+        }
+        for (uuid, obj) in extracted_objects {
+            self.update_object(&uuid, obj)?;
+        }
+        Ok(())
+    }
+
+    /// Attaches a JSON Schema to the repository (or detaches it, if None): documents passed to
+    /// [Melda::update] and [Melda::update_document] which violate the schema are rejected with
+    /// an [MeldaError::InvalidDocument] error. Concurrent changes may still produce a state
+    /// which violates the schema once melded: such violations are flagged (not rejected) by
+    /// [Melda::refresh] and returned by [Melda::schema_violations]. The schema is stored as
+    /// repository metadata, which is not transferred when melding. Changes staged with other
+    /// methods (such as [Melda::set] or [Melda::update_from_reader]) are not validated, use
+    /// [Melda::validate_schema] to check the current state
+    ///
+    /// # Arguments
+    ///
+    /// * `schema` - The schema (see [Schema] for the supported keywords)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// let schema = json!({ "properties" : { "items\u{266D}" : { "type" : "array", "maxItems" : 2 } } });
+    /// let alice = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+    /// alice.set_schema(Some(schema.clone())).unwrap();
+    /// let items = |ids: &[&str]| json!({ "items\u{266D}" : ids.iter().map(|id| json!({ "_id" : id })).collect::<Vec<_>>() }).as_object().unwrap().clone();
+    /// alice.update(items(&["1"])).unwrap();
+    /// alice.commit(None).unwrap();
+    /// // Invalid documents are rejected
+    /// let error = alice.update(items(&["1", "2", "3"])).unwrap_err();
+    /// assert_eq!(error.to_string(), "invalid_document: schema_violation: maxItems: 2 at '/items\u{266D}'");
+    /// // Concurrent changes which are valid on their own...
+    /// let bob = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+    /// bob.meld(&alice).unwrap();
+    /// bob.refresh().unwrap();
+    /// bob.update(items(&["1", "2"])).unwrap();
+    /// bob.commit(None).unwrap();
+    /// alice.update(items(&["1", "3"])).unwrap();
+    /// alice.commit(None).unwrap();
+    /// // ...may produce an invalid state once melded
+    /// alice.meld(&bob).unwrap();
+    /// alice.refresh().unwrap();
+    /// let violations = alice.schema_violations();
+    /// assert_eq!(violations[0].pointer, "/items\u{266D}");
+    /// assert_eq!(violations, alice.validate_schema(None).unwrap());
+    /// ```
+    pub fn set_schema(&self, schema: Option<Value>) -> Result<()> {
+        let schema = schema.map(Schema::new).transpose()?;
+        let key = SCHEMA_METADATA.to_string() + METADATA_EXTENSION;
+        let adapter = self.get_adapter();
+        let adapter = adapter.read().unwrap();
+        let recorded = self.schema.read().unwrap().is_some();
+        match &schema {
+            Some(schema) if recorded => {
+                adapter.replace_object(&key, schema.as_value().to_string().as_bytes())?
+            }
+            Some(schema) => adapter.write_object(&key, schema.as_value().to_string().as_bytes())?,
+            None if recorded => adapter.delete_object(&key)?,
+            None => (),
+        }
+        drop(adapter);
+        *self.schema.write().unwrap() = schema;
+        self.schema_violations.lock().unwrap().clear();
+        Ok(())
+    }
+
+    /// Returns the JSON Schema attached to the repository, if any (see [Melda::set_schema])
+    pub fn schema(&self) -> Option<Value> {
+        self.schema
             .read()
-            .expect("cannot_acquire_documents_for_reading")
-            .get(uuid.as_ref())
-        {
-            Some(rt) => {
-                let rt_r = rt.lock().expect("cannot_acquire_revision_tree_for_reading");
-                match rt_r.get_winner() {
-                    Some(r) => Ok(r.to_string()),
-                    None => Err(anyhow!("no_winner")),
-                }
+            .unwrap()
+            .as_ref()
+            .map(|s| s.as_value().clone())
+    }
+
+    /// Validates the current state of a document against the JSON Schema attached to the
+    /// repository (see [Melda::set_schema]), returning the violations (empty if there is no
+    /// schema or if the document does not exist)
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - The root identifier of the document (None for the default root)
+    pub fn validate_schema(&self, root: Option<&str>) -> Result<Vec<SchemaViolation>> {
+        let schema = self.schema.read().unwrap();
+        let schema = match schema.as_ref() {
+            Some(schema) => schema,
+            None => return Ok(vec![]),
+        };
+        let root = root.unwrap_or(ROOT_ID);
+        if !self.get_all_objects().contains(root) || self.is_deleted(root)? {
+            return Ok(vec![]);
+        }
+        Ok(schema.validate(&Value::from(self.read(Some(root))?)))
+    }
+
+    /// Returns the violations of the JSON Schema attached to the repository by the state of the
+    /// default document, as flagged by the last [Melda::refresh] which applied changes
+    pub fn schema_violations(&self) -> Vec<SchemaViolation> {
+        self.schema_violations.lock().unwrap().clone()
+    }
+
+    // Rejects a document with the given root identifier which violates the schema (if any),
+    // validating it as it would be read
+    fn check_schema(&self, obj: &Map<String, Value>, root: &str) -> Result<()> {
+        if let Some(schema) = self.schema.read().unwrap().as_ref() {
+            let mut document = obj.clone();
+            document.insert(ID_FIELD.to_string(), Value::from(root));
+            if let Some(violation) = schema.validate(&Value::from(document)).into_iter().next() {
+                bail!(violation.into_error());
             }
-            None => Err(anyhow!("unknown_document")),
         }
+        Ok(())
+    }
+
+    // Records the violations of the schema by the state of the default document
+    fn flag_schema_violations(&self) -> Result<()> {
+        let violations = self.validate_schema(None)?;
+        if !violations.is_empty() {
+            log::warn!("schema_violations: {}", violations.len());
+        }
+        *self.schema_violations.lock().unwrap() = violations;
+        Ok(())
     }
 
-    /// Returns a set of the conflicting revisions of the given object (the winning revision is not included!)
+    /// Stages a batch of changes and commits them atomically in a single block: if a change
+    /// fails nothing is committed and the stage is discarded. The stage must be empty.
     ///
     /// # Arguments
     ///
-    /// * `uuid` - The uuid of the object
+    /// * `changes` - The changes
+    /// * `information` - Optional information attached to the block
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::{Melda, BatchChange}, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let info = json!({ "author" : "Some user" }).as_object().unwrap().clone();
+    /// let anchors = replica.commit_batch(vec![
+    ///     BatchChange::Update { root: "\u{221A}".to_string(), object: json!({ "title" : "Todo" }).as_object().unwrap().clone() },
+    ///     BatchChange::Update { root: "notes".to_string(), object: json!({ "text" : "Remember" }).as_object().unwrap().clone() },
+    ///     BatchChange::Set { ptr: "/owner".to_string(), value: json!("alice") },
+    /// ], Some(info)).unwrap().unwrap();
+    /// assert_eq!(replica.get_anchors(), anchors);
+    /// assert_eq!(replica.read(Some("notes")).unwrap().get("text").unwrap(), "Remember");
+    /// assert_eq!(replica.get("/owner").unwrap().unwrap(), "alice");
+    /// // Failing batches are not committed
+    /// assert!(replica.commit_batch(vec![
+    ///     BatchChange::Set { ptr: "/owner".to_string(), value: json!("bob") },
+    ///     BatchChange::Set { ptr: "/missing/field".to_string(), value: json!(1) },
+    /// ], None).is_err());
+    /// assert!(!replica.has_staging());
+    /// assert_eq!(replica.get("/owner").unwrap().unwrap(), "alice");
+    /// ```
+    pub fn commit_batch(
+        &self,
+        changes: Vec<BatchChange>,
+        information: Option<Map<String, Value>>,
+    ) -> Result<Option<BTreeSet<String>>> {
+        if self.has_staging() {
+            bail!(MeldaError::StageNotEmpty);
+        }
+        let result = changes
+            .into_iter()
+            .try_for_each(|change| match change {
+                BatchChange::Update { root, object } => self.update_document(&root, object),
+                BatchChange::Set { ptr, value } => self.set(&ptr, value),
+                BatchChange::Patch(ops) => self.apply_patch(&ops),
+            })
+            .and_then(|_| self.commit(information));
+        if result.is_err() {
+            self.unstage()?;
+        }
+        result
+    }
+
+    /// Updates the state by reading a JSON object from a reader. The input is parsed and staged
+    /// incrementally (one field of the root object, or one element of a flattened array of the
+    /// root object, at a time), so that large inputs never need to be fully loaded in memory.
+    /// The result is the same as calling [Melda::update] with the whole object. If the input is
+    /// invalid an error is returned and the changes processed so far remain staged.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The source of the JSON input
     ///
     /// # Example
     /// ```
@@ -1529,165 +3474,511 @@ impl Melda {
     /// use std::sync::{Arc, Mutex, RwLock};
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
-    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica.create_object("myobject", object);  
-    /// assert!(replica.get_all_objects().contains("myobject"));
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
-    /// let anchors = replica.commit(None).unwrap().unwrap();
-    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter2 = Arc::new(RwLock::new(adapter2));
-    /// let mut replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
-    /// let object = json!({ "another" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica2.create_object("myobject", object);
-    /// let winner2 = replica2.get_winner("myobject").unwrap();
-    /// let anchors2 = replica2.commit(None).unwrap().unwrap();
-    /// replica2.meld(&replica);
-    /// replica2.refresh();
-    /// let conflicting = replica2.in_conflict();
-    /// assert!(conflicting.contains("myobject"));
-    /// let revs = replica2.get_conflicting("myobject").unwrap();
-    /// assert!(revs.contains(&winner2));
-    pub fn get_conflicting<T>(&self, uuid: T) -> Result<BTreeSet<String>>
-    where
-        T: AsRef<str>,
-    {
-        match self
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "title" : "Todo", "tasks\u{266D}" : [ { "_id" : "1", "key" : "alpha" }, { "_id" : "2", "key" : "beta" } ] }).as_object().unwrap().clone();
+    /// replica.update(object).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let input = "{ \"tasks\u{266D}\" : [ { \"_id\" : \"2\", \"key\" : \"beta\" }, { \"_id\" : \"3\", \"key\" : \"gamma\" } ], \"owner\u{266D}\" : { \"name\" : \"!alice\" } }";
+    /// replica.update_from_reader(input.as_bytes()).unwrap();
+    /// // The result is the same as updating with the parsed object
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let expected = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// expected.update(serde_json::from_str(input).unwrap()).unwrap();
+    /// assert_eq!(replica.read(None).unwrap(), expected.read(None).unwrap());
+    /// assert_eq!(replica.get("/owner\u{266D}/name").unwrap().unwrap(), "!alice");
+    /// assert!(replica.get("/title").unwrap().is_none());
+    /// assert!(replica.update_from_reader("[1, 2]".as_bytes()).is_err());
+    /// ```
+    pub fn update_from_reader<R: std::io::Read>(&self, reader: R) -> Result<String> {
+        let mut seen = HashSet::<String>::new();
+        let mut validator = self.strict.then(StrictValidator::default);
+        let mut root = Map::<String, Value>::new();
+        let mut deserializer = serde_json::Deserializer::from_reader(reader);
+        serde::Deserializer::deserialize_map(
+            &mut deserializer,
+            StreamingRootVisitor {
+                melda: self,
+                seen: &mut seen,
+                root: &mut root,
+                validator: &mut validator,
+            },
+        )
+        .map_err(|e| anyhow!("cannot_parse_document: {}", e))?;
+        deserializer.end()?;
+        self.update_object(ROOT_ID, root)?;
+        seen.insert(ROOT_ID.to_string());
+        // Delete the objects which have disappeared
+        let disappeared: Vec<String> = self
             .documents
             .read()
             .expect("failed_to_acquire_documents_for_reading")
-            .get(uuid.as_ref())
+            .keys()
+            .filter(|uuid| !seen.contains(*uuid))
+            .cloned()
+            .collect();
+        for uuid in disappeared {
+            self.delete_object(&uuid)?;
+        }
+        Ok(ROOT_ID.to_string())
+    }
+
+    // Stages the objects extracted while flattening a value
+    fn stage_extracted(
+        &self,
+        extracted_objects: &mut HashMap<String, Map<String, Value>>,
+        seen: &mut HashSet<String>,
+    ) -> Result<()> {
+        for (uuid, obj) in extracted_objects.drain() {
+            self.update_object(&uuid, obj)?;
+            seen.insert(uuid);
+        }
+        Ok(())
+    }
+
+    /// Computes the changes that updating the state with the given object would stage, without
+    /// staging anything. The object is validated and flattened exactly as [Melda::update] does
+    ///
+    /// # Arguments
+    ///
+    /// * `obj` - The new state
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "items\u{266D}" : [ { "_id" : "1", "key" : "alpha" }, { "_id" : "2", "key" : "beta" } ] }).as_object().unwrap().clone();
+    /// replica.update(object).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let object = json!({ "items\u{266D}" : [ { "_id" : "1", "key" : "gamma" }, { "_id" : "3", "key" : "delta" } ] }).as_object().unwrap().clone();
+    /// let changes = replica.preview_update(object).unwrap();
+    /// assert_eq!(changes.len(), 3);
+    /// assert!(changes.created.contains("3"));
+    /// assert!(changes.updated.contains("1"));
+    /// assert!(changes.deleted.contains("2"));
+    /// assert_eq!(changes.arrays.len(), 1);
+    /// assert!(!replica.has_staging());
+    /// // Documents rejected by update are rejected by the preview as well
+    /// let strict = Melda::builder().url("memory://").strict(true).open().unwrap();
+    /// let document = json!({ "items\u{266D}" : [ { "_id" : "a" }, { "_id" : "a" } ] });
+    /// assert!(strict.preview_update(document.as_object().unwrap().clone()).is_err());
+    /// ```
+    pub fn preview_update(&self, obj: Map<String, Value>) -> Result<ChangeSet> {
+        let plan = self.plan_update(&obj, &self.clean_fields.lock().unwrap())?;
+        let mut changes = ChangeSet::default();
+        let docs_r = self
+            .documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading");
+        for (uuid, rt) in plan
+            .deleted
+            .iter()
+            .filter_map(|uuid| Some((uuid, docs_r.get(uuid)?)))
         {
-            Some(rt) => {
-                let rt_r = rt
-                    .lock()
-                    .expect("failed_to_acquire_revision_tree_for_reading");
-                let w = rt_r.get_winner().ok_or_else(|| anyhow!("no_winner"))?;
-                let l = rt_r.get_leafs();
-                Ok(l.iter()
-                    .filter(|r| w.ne(r))
-                    .map(|r| r.to_string())
-                    .collect())
+            let rt_r = rt.lock().expect("cannot_acquire_revision_tree_for_reading");
+            if let Some(winner) = rt_r.get_winner() {
+                if !winner.is_deleted() && !winner.is_resolved() {
+                    if is_array_descriptor(uuid) {
+                        changes.arrays.insert(uuid.clone());
+                    } else {
+                        changes.deleted.insert(uuid.clone());
+                    }
+                }
             }
-            None => Err(anyhow!("unknown_document")),
         }
+        for (uuid, obj) in plan.objects {
+            let (created, updated) = match docs_r.get(&uuid) {
+                Some(rt) => {
+                    let rt_r = rt.lock().expect("cannot_acquire_revision_tree_for_reading");
+                    let winner = rt_r
+                        .get_winner()
+                        .ok_or_else(|| anyhow!("object_has_no_winner"))?;
+                    let object = if is_array_descriptor(&uuid) {
+                        self.create_delta_array_descriptor(obj, &rt_r)?
+                    } else {
+                        Some(obj)
+                    };
+                    match object {
+                        Some(object) if digest_object(&object)?.ne(winner.digest()) => {
+                            (winner.is_deleted(), !winner.is_deleted())
+                        }
+                        _ => (false, false),
+                    }
+                }
+                None => (true, false),
+            };
+            if is_array_descriptor(&uuid) {
+                if created || updated {
+                    changes.arrays.insert(uuid);
+                }
+            } else if created {
+                changes.created.insert(uuid);
+            } else if updated {
+                changes.updated.insert(uuid);
+            }
+        }
+        Ok(changes)
     }
 
-    /// Resolves a conflict by choosing the new winning revision. All other conflicting revisions are marked as resolved.
+    /// Reads the data structure and deserializes it into the requested type
     ///
     /// # Arguments
     ///
-    /// * `uuid` - The uuid of the object
-    /// * `winner` - The revision that is to be made the winner
+    /// * `root` - Optional identifier of the root object (starting point)
     ///
     /// # Example
     /// ```
     /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
     /// use std::sync::{Arc, Mutex, RwLock};
-    /// use serde_json::{Map, Value,json};
+    /// use serde::{Deserialize, Serialize};
+    /// #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    /// struct Item {
+    ///     _id: String,
+    ///     key: String,
+    /// }
+    /// #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    /// struct Document {
+    ///     title: String,
+    ///     #[serde(rename = "items\u{266D}")]
+    ///     items: Vec<Item>,
+    /// }
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
-    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica.create_object("myobject", object);  
-    /// assert!(replica.get_all_objects().contains("myobject"));
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
-    /// let block_id = replica.commit(None).unwrap().unwrap();
-    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter2 = Arc::new(RwLock::new(adapter2));
-    /// let mut replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
-    /// let object = json!({ "another" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica2.create_object("myobject", object);
-    /// let winner2 = replica2.get_winner("myobject").unwrap();
-    /// assert_eq!("1-255cc6219e48f526c04bc5af86439c34e4fe39fcdc611758ff833a2ff80583f0", winner2);
-    /// let anchors2 = replica2.commit(None).unwrap().unwrap();
-    /// replica2.meld(&replica);
-    /// replica2.refresh();
-    /// let conflicting = replica2.in_conflict();
-    /// assert!(conflicting.contains("myobject"));
-    /// let revs = replica2.get_conflicting("myobject").unwrap();
-    /// assert!(revs.contains(&winner2));
-    /// let winner3 = replica2.get_winner("myobject").unwrap();
-    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner3);    
-    /// replica2.resolve_as("myobject", &winner2);
-    /// let winner = replica2.get_winner("myobject").unwrap();
-    /// assert_eq!("2-255cc6219e48f526c04bc5af86439c34e4fe39fcdc611758ff833a2ff80583f0_e5d1d20", winner);
-    /// assert!(replica2.in_conflict().is_empty());
-    pub fn resolve_as(&self, uuid: &str, winner: &str) -> Result<String> {
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let document = Document { title: "Todo".to_string(), items: vec![Item { _id: "1".to_string(), key: "alpha".to_string() }] };
+    /// replica.update_from(&document).unwrap();
+    /// let readback : Document = replica.read_as(None).unwrap();
+    /// assert_eq!(readback, document);
+    /// ```
+    pub fn read_as<T: DeserializeOwned>(&self, root: Option<&str>) -> Result<T> {
+        let obj = self.read(root)?;
+        serde_json::from_value(Value::from(obj))
+            .map_err(|e| anyhow!("cannot_deserialize_document: {}", e))
+    }
+
+    /// Updates the data structure by serializing the input value (which must serialize to a JSON object)
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - input value
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use std::collections::BTreeMap;
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let mut values = BTreeMap::new();
+    /// values.insert("somekey".to_string(), vec![1u32, 2u32, 3u32]);
+    /// assert!(replica.update_from(&values).is_ok());
+    /// let readback = replica.read(None).unwrap();
+    /// assert_eq!(readback.get("somekey").unwrap(), &serde_json::json!([1, 2, 3]));
+    /// assert!(replica.update_from(&vec![1u32, 2u32]).is_err());
+    /// ```
+    pub fn update_from<T: Serialize>(&self, value: &T) -> Result<String> {
+        match serde_json::to_value(value)
+            .map_err(|e| anyhow!("cannot_serialize_document: {}", e))?
         {
-            let winner = Revision::from(winner).expect("invalid_revision_string");
-            let docs_r = self
-                .documents
-                .read()
-                .expect("failed_to_acquire_documents_for_reading");
-            let rt = docs_r
-                .get(uuid)
-                .ok_or_else(|| anyhow!("unknown_document"))?;
-            let rt_r = rt
-                .lock()
-                .expect("failed_to_acquire_revision_tree_for_reading");
-            {
-                let leafs = rt_r.get_leafs();
-                // We can only resolve to a status revision
-                if !leafs.contains(&winner) {
-                    bail!("invalid_winner_revision");
+            Value::Object(obj) => self.update(obj),
+            _ => bail!("document_not_an_object"),
+        }
+    }
+
+    /// Returns an iterator over the elements (identifier and value) of a collection, i.e. a flattened
+    /// array of objects in the root object, in merge order. Elements are reconstructed lazily,
+    /// without materializing the rest of the document. A missing collection is empty.
+    ///
+    /// # Arguments
+    ///
+    /// * `collection` - The field of the root object holding the collection
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "title" : "Todo", "tasks\u{266D}" : [ { "_id" : "1", "key" : "alpha" }, { "_id" : "2", "key" : "beta" } ] }).as_object().unwrap().clone();
+    /// replica.update(object).unwrap();
+    /// let mut tasks = replica.iter_collection("tasks\u{266D}").unwrap();
+    /// let (uuid, value) = tasks.next().unwrap().unwrap();
+    /// assert_eq!(uuid, "1");
+    /// assert_eq!(value, json!({ "_id" : "1", "key" : "alpha" }));
+    /// assert_eq!(tasks.next().unwrap().unwrap().0, "2");
+    /// assert!(tasks.next().is_none());
+    /// assert_eq!(replica.iter_collection("missing\u{266D}").unwrap().count(), 0);
+    /// assert!(replica.iter_collection("title").is_err());
+    /// ```
+    pub fn iter_collection(&self, collection: &str) -> Result<CollectionIter<'_>> {
+        let (_, order) = self.collection_order(collection)?;
+        Ok(CollectionIter {
+            melda: self,
+            order: order.into_iter(),
+        })
+    }
+
+    /// Returns the (reconstructed) value of an object given its identifier. If the object is
+    /// deleted its last value before the deletion is returned, see [Melda::is_deleted].
+    /// Returns None if the object is unknown.
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - Object identifier
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "tasks\u{266D}" : [ { "_id" : "1", "key" : "alpha" }, { "_id" : "2", "key" : "beta" } ] }).as_object().unwrap().clone();
+    /// replica.update(object).unwrap();
+    /// replica.commit(None).unwrap();
+    /// assert_eq!(replica.get_object("2").unwrap().unwrap(), json!({ "_id" : "2", "key" : "beta" }));
+    /// replica.set_object("2", json!({ "key" : "gamma" })).unwrap();
+    /// assert_eq!(replica.get("/tasks\u{266D}/1/key").unwrap().unwrap(), "gamma");
+    /// replica.set("/tasks\u{266D}", json!([ { "_id" : "1", "key" : "alpha" } ])).unwrap();
+    /// // Deleted objects can still be read
+    /// assert!(replica.is_deleted("2").unwrap());
+    /// assert_eq!(replica.get_object("2").unwrap().unwrap(), json!({ "_id" : "2", "key" : "gamma" }));
+    /// assert!(replica.set_object("2", json!({ "key" : "delta" })).is_err());
+    /// assert!(replica.get_object("unknown").unwrap().is_none());
+    /// ```
+    pub fn get_object(&self, uuid: &str) -> Result<Option<Value>> {
+        let object = match self.load_flattened_object(uuid)? {
+            Some(object) => object,
+            None => {
+                let docs_r = self
+                    .documents
+                    .read()
+                    .expect("failed_to_acquire_documents_for_reading");
+                let rt = match docs_r.get(uuid) {
+                    Some(rt) => rt,
+                    None => return Ok(None),
+                };
+                let rt_r = rt
+                    .lock()
+                    .expect("failed_to_acquire_revision_tree_for_reading");
+                // Find the last revision which is not a deletion
+                let mut revision = rt_r.get_winner();
+                while let Some(r) = revision {
+                    if !r.is_deleted() && !r.is_resolved() {
+                        break;
+                    }
+                    revision = rt_r.get_parent(r);
                 }
-                // If there is only one leaf nothing needs to be resolved
-                if leafs.len() <= 1 {
-                    bail!("not_in_conflict");
+                match revision {
+                    Some(r) => self.read_object_at_revision(uuid, &rt_r, r)?,
+                    None => return Ok(None),
                 }
             }
-            // Update the winner to ensure that we do not change the view
-            let data_r = self.data.read().expect("cannot_acquire_data_for_reading");
-            //let merged = data_r.read_object(&winner)?;
-            let merged = self.read_object_at_revision(uuid, &rt_r, &winner)?;
-            drop(winner);
-            drop(rt_r);
-            drop(data_r);
-            drop(docs_r);
-            self.update_object(uuid, merged)?;
+        };
+        Ok(Some(self.resolve_flattened_object(
+            uuid,
+            object,
+            &mut BTreeSet::new(),
+        )?))
+    }
+
+    /// Returns the location of an object (or of a flattened array) given its identifier, or None
+    /// if the object is not reachable from the root object nor from the documents updated with
+    /// [Melda::update_document]. Locations are indexed: the index is rebuilt on demand once the
+    /// state changes (changes staged with [Melda::set] or [Melda::apply_patch] only update the
+    /// entries of the modified subtree), see also [MeldaBuilder::location_index].
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - Object identifier
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::{Melda, Location};
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+    /// let object = json!({ "items\u{266D}" : [ { "_id" : "1", "tags\u{266D}" : [ { "_id" : "a" } ] }, { "_id" : "2" } ] });
+    /// replica.update(object.as_object().unwrap().clone()).unwrap();
+    /// let location = replica.get_location("a").unwrap().unwrap();
+    /// assert_eq!(location.pointer, "/items\u{266D}/0/tags\u{266D}/0");
+    /// assert_eq!(location.position, vec!["0".to_string()]);
+    /// let array = location.parent.unwrap();
+    /// assert_eq!(replica.get_location(&array).unwrap().unwrap().parent.unwrap(), "1");
+    /// replica.set("/items\u{266D}/0", json!({ "_id" : "3" })).unwrap();
+    /// assert_eq!(replica.get_location("3").unwrap().unwrap().pointer, "/items\u{266D}/0");
+    /// assert!(replica.get_location("a").unwrap().is_none());
+    /// assert_eq!(replica.get_location("\u{221A}").unwrap().unwrap(), Location {
+    ///     root: "\u{221A}".to_string(), parent: None, position: vec![], pointer: "".to_string()
+    /// });
+    /// ```
+    pub fn get_location(&self, uuid: &str) -> Result<Option<Location>> {
+        let generation = self.generation.load(Ordering::SeqCst);
+        let index = self.locations.lock().unwrap();
+        if let Some(entries) = index.get(generation) {
+            return Ok(entries.get(uuid).cloned());
         }
+        let mut roots = index.roots().clone();
+        roots.insert(ROOT_ID.to_string());
+        drop(index);
+        // Rebuild the index walking the documents
+        let mut entries = HashMap::new();
+        for root in &roots {
+            self.locate_object(root, Location::root(root), &mut vec![], &mut entries)?;
+        }
+        let location = entries.get(uuid).cloned();
+        self.locations.lock().unwrap().replace(entries, generation);
+        self.save_locations();
+        Ok(location)
+    }
+
+    /// Returns true if the object is deleted (fails if the object is unknown)
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - Object identifier
+    pub fn is_deleted(&self, uuid: &str) -> Result<bool> {
         let docs_r = self
             .documents
             .read()
             .expect("failed_to_acquire_documents_for_reading");
-        let rt = docs_r
-            .get(uuid)
-            .ok_or_else(|| anyhow!("unknown_document"))?;
+        let rt = docs_r.get(uuid).ok_or_else(|| anyhow!("unknown_object"))?;
         let rt_r = rt
             .lock()
             .expect("failed_to_acquire_revision_tree_for_reading");
         let winner = rt_r
             .get_winner()
-            .expect("revision_tree_invalid_state")
-            .clone();
-        drop(rt_r);
-        drop(docs_r);
-        // Seal all other revisions as resolved
-        let mut docs_w = self
-            .documents
-            .write()
-            .expect("failed_to_acquire_documents_for_writing");
-        let rt = docs_w
-            .get_mut(uuid)
-            .ok_or_else(|| anyhow!("unknown_document"))?;
-        let rt_r = rt
-            .get_mut()
-            .expect("failed_to_acquire_revision_tree_for_reading");
-        let leafs: Vec<Revision> = rt_r.get_leafs().iter().map(|r| (*r).clone()).collect();
-        for r in leafs {
-            if r != winner {
-                let resolved = Revision::new_resolved(&r);
-                let rt_w = rt
-                    .get_mut()
-                    .expect("failed_to_acquire_revision_tree_for_writing");
-                rt_w.add(resolved.clone(), Some(r.clone()), true);
+            .ok_or_else(|| anyhow!("object_has_no_winner"))?;
+        Ok(winner.is_deleted())
+    }
+
+    /// Starts a transaction which batches several changes and commits them as a single block,
+    /// see [Transaction]. The stage must be empty.
+    pub fn transaction(&self) -> Result<Transaction<'_>> {
+        Transaction::new(self)
+    }
+
+    // Acquires the lock serializing commits, refreshes and reloads (reentrant), so that a
+    // sequence of operations is not interleaved with those of other threads
+    pub(crate) fn exclusive(&self) -> ExclusiveGuard<'_> {
+        self.exclusive.acquire()
+    }
+
+    /// Subscribes to change events: an event is sent for each object changed by a commit or
+    /// by the blocks applied during a refresh. A refresh also sends a [ChangeKind::Conflicted]
+    /// event for each object which got in conflict, and a [ChangeKind::Resolved] event for each
+    /// object whose conflict has been resolved (by the melded blocks or by the conflict policy).
+    /// The subscription ends when the receiver is dropped.
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::{Melda, ChangeKind}, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "items\u{266D}" : [ { "_id" : "1", "key" : "alpha" } ] }).as_object().unwrap().clone();
+    /// replica.update(object).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let other = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let events = other.subscribe();
+    /// replica.set("/items\u{266D}/0/key", json!("beta")).unwrap();
+    /// let block_id = replica.commit(None).unwrap().unwrap().first().unwrap().clone();
+    /// other.refresh().unwrap();
+    /// let event = events.try_recv().unwrap();
+    /// assert_eq!(event.uuid, "1");
+    /// assert_eq!(event.path.unwrap(), "/items\u{266D}/0");
+    /// assert_eq!(event.kind, ChangeKind::Updated);
+    /// assert_eq!(event.block, block_id);
+    /// assert!(events.try_recv().is_err());
+    /// // Conflicts are notified when they appear and when they are resolved
+    /// let third = Melda::new_from_url("memory://").unwrap();
+    /// third.meld(&replica).unwrap();
+    /// third.refresh().unwrap();
+    /// third.set("/items\u{266D}/0/key", json!("gamma")).unwrap();
+    /// third.commit(None).unwrap();
+    /// replica.set("/items\u{266D}/0/key", json!("delta")).unwrap();
+    /// replica.commit(None).unwrap();
+    /// third.meld(&replica).unwrap();
+    /// let events = third.subscribe();
+    /// third.refresh().unwrap();
+    /// let events: Vec<_> = events.try_iter().map(|e| (e.uuid, e.kind)).collect();
+    /// assert_eq!(events, vec![("1".to_string(), ChangeKind::Updated), ("1".to_string(), ChangeKind::Conflicted)]);
+    /// replica.meld(&third).unwrap();
+    /// replica.refresh().unwrap();
+    /// let winner = replica.get_winner("1").unwrap();
+    /// replica.resolve_as("1", &winner).unwrap();
+    /// replica.commit(None).unwrap();
+    /// third.meld(&replica).unwrap();
+    /// let events = third.subscribe();
+    /// third.refresh().unwrap();
+    /// assert_eq!(events.try_iter().last().map(|e| (e.uuid, e.kind)), Some(("1".to_string(), ChangeKind::Resolved)));
+    /// ```
+    pub fn subscribe(&self) -> Receiver<ChangeEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers
+            .lock()
+            .expect("cannot_acquire_subscribers")
+            .push(sender);
+        receiver
+    }
+
+    /// Sets aside the staged changes (which are unstaged), so that the replica can be refreshed
+    /// or melded. Returns false if there is nothing to stash. Stashed changes are kept in memory
+    /// and can be re-applied with [Melda::stash_pop].
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "title" : "Todo", "items\u{266D}" : [ { "_id" : "1", "key" : "alpha" } ] }).as_object().unwrap().clone();
+    /// replica.update(object).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let other = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// other.set("/items\u{266D}/1", json!({ "_id" : "2", "key" : "beta" })).unwrap();
+    /// other.commit(None).unwrap();
+    /// // Local edit in progress
+    /// replica.set("/title", json!("Shopping")).unwrap();
+    /// assert!(replica.refresh().is_err());
+    /// assert!(replica.stash().unwrap());
+    /// assert!(!replica.has_staging());
+    /// replica.refresh().unwrap();
+    /// assert!(replica.stash_pop().unwrap());
+    /// assert!(!replica.stash_pop().unwrap());
+    /// assert_eq!(replica.get("/title").unwrap().unwrap(), "Shopping");
+    /// assert_eq!(replica.get("/items\u{266D}/1/key").unwrap().unwrap(), "beta");
+    /// ```
+    pub fn stash(&self) -> Result<bool> {
+        match self.stage()? {
+            Some(stage) => {
+                self.unstage()?;
+                self.stashed
+                    .lock()
+                    .expect("cannot_acquire_stash")
+                    .push(stage);
+                Ok(true)
             }
+            None => Ok(false),
+        }
+    }
+
+    /// Re-applies the most recently stashed changes (which become staged again). Returns false
+    /// if there are no stashed changes.
+    pub fn stash_pop(&self) -> Result<bool> {
+        let stage = self.stashed.lock().expect("cannot_acquire_stash").pop();
+        match stage {
+            Some(stage) => {
+                self.replay_stage(&Some(stage))?;
+                Ok(true)
+            }
+            None => Ok(false),
         }
-        Ok(winner.to_string())
     }
 
     /// Saves the current stage
@@ -1699,7 +3990,7 @@ impl Melda {
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
     /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
     /// replica.create_object("myobject", object);  
     /// assert!(replica.get_all_objects().contains("myobject"));
@@ -1770,7 +4061,7 @@ impl Melda {
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
     /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
     /// assert!(!replica.has_staging());
     /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
     /// let object2 = json!({ "somekey2" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
@@ -1810,7 +4101,7 @@ impl Melda {
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
     /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
     /// replica.create_object("myobject", object);  
     /// assert!(replica.get_all_objects().contains("myobject"));
@@ -1926,7 +4217,9 @@ impl Melda {
                             }
                         }
                     }
+                    self.changed();
                 }
+                self.record(|| json!({ "op": "replay_stage", "stage": s }));
                 Ok(())
             } else {
                 Err(anyhow!("expecting_stage_object"))
@@ -1949,7 +4242,7 @@ impl Melda {
     /// use serde_json::{Map, Value,json};
     /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
     /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
     /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
     /// replica.create_object("myobject", object);  
     /// let winner = replica.get_winner("myobject").unwrap();
@@ -1973,117 +4266,516 @@ impl Melda {
         }
     }
 
-    /// Returns the parent revision in the revision tree of the specified object, or None if there is no parent
-    ///
-    /// # Arguments
-    ///
-    /// * `uuid` - Object identifier
-    /// * `revision` - The revision
-    ///
-    /// # Example
-    /// ```
-    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
-    /// use std::sync::{Arc, Mutex, RwLock};
-    /// use serde_json::{Map, Value,json};
-    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let mut replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
-    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
-    /// replica.create_object("myobject", object);  
-    /// let winner = replica.get_winner("myobject").unwrap();
-    /// let parent = replica.get_parent_revision("myobject", &winner).unwrap();
-    /// assert!(parent.is_none());
-    /// let block_id = replica.commit(None).unwrap().unwrap();
-    /// replica.delete_object("myobject");
-    /// let newrev = replica.get_winner("myobject").unwrap();
-    /// assert_eq!("2-d_e5d1d20", newrev);
-    /// let parent = replica.get_parent_revision("myobject", &newrev).unwrap().unwrap();
-    /// assert_eq!(&parent, &winner);
-    pub fn get_parent_revision(&self, uuid: &str, revision: &str) -> Result<Option<String>> {
-        let docs = self
+    // **********************************************************************
+    // **********************************************************************
+    //
+    // LAZY RESOLUTION SUPPORT FUNCTIONS
+    //
+    // **********************************************************************
+    // **********************************************************************
+
+    // Returns the document as it was at the given anchors (an empty object if there are no anchors)
+    pub(crate) fn state_until(&self, anchors: &BTreeSet<String>) -> Result<Value> {
+        if anchors.is_empty() {
+            return Ok(Value::from(Map::new()));
+        }
+        let replica = Melda::new_until(self.get_adapter(), anchors)?;
+        Ok(replica.get("")?.unwrap_or_else(|| Value::from(Map::new())))
+    }
+
+    // Sends change events (block, object, kind) to the subscribers
+    fn notify(&self, changes: Vec<(String, String, ChangeKind)>) -> Result<()> {
+        let mut subscribers = self.subscribers.lock().expect("cannot_acquire_subscribers");
+        if subscribers.is_empty() || changes.is_empty() {
+            return Ok(());
+        }
+        for (block, uuid, kind) in changes {
+            let event = ChangeEvent {
+                path: self
+                    .get_location(&uuid)?
+                    .filter(|l| l.root == ROOT_ID)
+                    .map(|l| l.pointer),
+                uuid,
+                kind,
+                block,
+            };
+            subscribers.retain(|s| s.send(event.clone()).is_ok());
+        }
+        Ok(())
+    }
+
+    // Records the location of an object and of the objects (and arrays) it references. The path
+    // contains the tokens of the JSON Pointer of the object
+    fn locate_object(
+        &self,
+        uuid: &str,
+        location: Location,
+        path: &mut Vec<String>,
+        locations: &mut HashMap<String, Location>,
+    ) -> Result<()> {
+        if locations.contains_key(uuid) {
+            return Ok(());
+        }
+        if let Some(object) = self.load_flattened_object(uuid)? {
+            let root = location.root.clone();
+            locations.insert(uuid.to_string(), location);
+            for (k, v) in object.iter().filter(|(k, _)| is_flattened_field(k)) {
+                path.push(k.clone());
+                self.locate_value(v, &root, uuid, &mut vec![k.clone()], path, locations)?;
+                path.pop();
+            }
+        }
+        Ok(())
+    }
+
+    // Records the location of the objects (and arrays) referenced by a flattened value found at
+    // the given position of a parent object (or array)
+    fn locate_value(
+        &self,
+        value: &Value,
+        root: &str,
+        parent: &str,
+        position: &mut Vec<String>,
+        path: &mut Vec<String>,
+        locations: &mut HashMap<String, Location>,
+    ) -> Result<()> {
+        let location = |position: &[String], path: &[String]| Location {
+            root: root.to_string(),
+            parent: Some(parent.to_string()),
+            position: position.to_vec(),
+            pointer: encode_json_pointer(path),
+        };
+        match value {
+            Value::String(s) if is_array_descriptor(s) => {
+                if let Some(Value::Array(array)) = self.expand_flattened(value)? {
+                    locations.insert(s.clone(), location(position, path));
+                    for (i, uuid) in array.iter().enumerate() {
+                        path.push(i.to_string());
+                        self.locate_value(
+                            uuid,
+                            root,
+                            s,
+                            &mut vec![i.to_string()],
+                            path,
+                            locations,
+                        )?;
+                        path.pop();
+                    }
+                }
+            }
+            Value::String(s) if !s.starts_with(STRING_ESCAPE_PREFIX) => {
+                self.locate_object(s, location(position, path), path, locations)?;
+            }
+            Value::Array(a) => {
+                for (i, v) in a.iter().enumerate() {
+                    position.push(i.to_string());
+                    path.push(i.to_string());
+                    self.locate_value(v, root, parent, position, path, locations)?;
+                    path.pop();
+                    position.pop();
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Appends an event to the trace (if tracing is enabled)
+    fn record<F: FnOnce() -> Value>(&self, event: F) {
+        if let Some(trace) = &self.trace {
+            trace.lock().unwrap().push(event());
+        }
+    }
+
+    // Records an applied block along with the items it references (the packs and their index),
+    // so that the trace can be replayed without the original repository
+    fn record_block(&self, block: &Block) {
+        let data = self.data.read().expect("cannot_acquire_data_for_reading");
+        let mut keys = vec![block.id.clone() + DELTA_EXTENSION];
+        for pack in block.packs.iter().flatten() {
+            keys.push(pack.clone() + PACK_EXTENSION);
+            keys.push(pack.clone() + INDEX_EXTENSION);
+        }
+        // Small packs have no index
+        let items: Map<String, Value> = keys
+            .into_iter()
+            .filter_map(|key| {
+                let item = data.read_raw_item(&key, 0, 0).ok()?;
+                Some((key, Value::from(hex::encode(item))))
+            })
+            .collect();
+        drop(data);
+        self.record(|| json!({ "op": "block", "id": block.id, "items": items }));
+    }
+
+    // Records that revision trees changed (the index of the locations becomes stale), without
+    // knowing which ones (the secondary indexes are rebuilt)
+    fn changed(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.index_changes.lock().unwrap().record_all();
+    }
+
+    // Records that the revision trees of the given objects changed
+    fn changed_objects<'a, I: IntoIterator<Item = &'a str>>(&self, uuids: I) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.index_changes.lock().unwrap().record(uuids);
+    }
+
+    // Updates the index of the locations after the subtree of an object has been staged (if the
+    // index was up to date before the change). Visited contains the objects of the old subtree
+    fn relocate_subtree(
+        &self,
+        uuid: &str,
+        visited: &BTreeSet<String>,
+        generation: u64,
+    ) -> Result<()> {
+        let mut index = self.locations.lock().unwrap();
+        let location = match index.get(generation).and_then(|e| e.get(uuid)) {
+            Some(location) => location.clone(),
+            None => return Ok(()),
+        };
+        let mut path = parse_json_pointer(&location.pointer)?;
+        let mut relocated = HashMap::new();
+        self.locate_object(uuid, location, &mut path, &mut relocated)?;
+        if let Some(entries) = index.get_mut(generation) {
+            entries.retain(|k, _| !visited.contains(k));
+            entries.extend(relocated);
+        }
+        index.advance(generation, self.generation.load(Ordering::SeqCst));
+        Ok(())
+    }
+
+    // Persists the index of the locations (if it is up to date and there are no staged changes)
+    fn save_locations(&self) {
+        if self.has_staging() {
+            return;
+        }
+        let generation = self.generation.load(Ordering::SeqCst);
+        let anchors = self.get_anchors();
+        if let Err(e) = self.locations.lock().unwrap().save(&anchors, generation) {
+            log::warn!("cannot_save_location_index: {}", e);
+        }
+    }
+
+    // Writes the JSON serialization of a flattened object (and of the objects it references)
+    fn write_flattened_object<W: std::io::Write>(
+        &self,
+        writer: &mut W,
+        uuid: &str,
+        mut object: Map<String, Value>,
+        visited: &mut BTreeSet<String>,
+    ) -> Result<()> {
+        visited.insert(uuid.to_string());
+        object.insert(ID_FIELD.to_string(), Value::from(uuid));
+        writer.write_all(b"{")?;
+        for (i, (k, v)) in object.iter().enumerate() {
+            if i > 0 {
+                writer.write_all(b",")?;
+            }
+            serde_json::to_writer(&mut *writer, k)?;
+            writer.write_all(b":")?;
+            if is_flattened_field(k) {
+                self.write_flattened_value(writer, v, visited)?;
+            } else {
+                serde_json::to_writer(&mut *writer, v)?;
+            }
+        }
+        writer.write_all(b"}")?;
+        Ok(())
+    }
+
+    // Writes the JSON serialization of a flattened value, see resolve_flattened
+    fn write_flattened_value<W: std::io::Write>(
+        &self,
+        writer: &mut W,
+        value: &Value,
+        visited: &mut BTreeSet<String>,
+    ) -> Result<()> {
+        match value {
+            Value::String(s) if s.starts_with(STRING_ESCAPE_PREFIX) => {
+                serde_json::to_writer(&mut *writer, &unescape(s))?;
+            }
+            Value::String(s) if is_array_descriptor(s) => {
+                visited.insert(s.clone());
+                let order = match self.expand_flattened(value)? {
+                    Some(Value::Array(order)) => order,
+                    _ => bail!("unknown_descriptor_object"),
+                };
+                writer.write_all(b"[")?;
+                let mut first = true;
+                for uuid in order.iter().filter_map(|uuid| uuid.as_str()) {
+                    if visited.contains(uuid) {
+                        continue;
+                    }
+                    if let Some(object) = self.load_flattened_object(uuid)? {
+                        if !first {
+                            writer.write_all(b",")?;
+                        }
+                        first = false;
+                        self.write_flattened_object(writer, uuid, object, visited)?;
+                    }
+                }
+                writer.write_all(b"]")?;
+            }
+            Value::String(s) => match self.load_flattened_object(s)? {
+                Some(object) if !visited.contains(s) => {
+                    self.write_flattened_object(writer, s, object, visited)?;
+                }
+                _ => writer.write_all(b"null")?,
+            },
+            Value::Array(a) => {
+                writer.write_all(b"[")?;
+                for (i, v) in a.iter().enumerate() {
+                    if i > 0 {
+                        writer.write_all(b",")?;
+                    }
+                    self.write_flattened_value(writer, v, visited)?;
+                }
+                writer.write_all(b"]")?;
+            }
+            _ => serde_json::to_writer(&mut *writer, value)?,
+        }
+        Ok(())
+    }
+
+    // Returns the (flattened) winning value of an object, None if the object is unknown or deleted
+    pub(crate) fn load_flattened_object(&self, uuid: &str) -> Result<Option<Map<String, Value>>> {
+        let docs_r = self
             .documents
             .read()
             .expect("failed_to_acquire_documents_for_reading");
-        let rt = docs.get(uuid).ok_or_else(|| anyhow!("unknown_document"))?;
-        let revision = Revision::from(revision).expect("invalid_revision_string");
-        let rt_r = rt
-            .lock()
-            .expect("failed_to_acquire_revision_tree_for_reading");
-        match rt_r.get_parent(&revision) {
-            Some(r) => Ok(Some(r.to_string())),
+        match docs_r.get(uuid) {
+            Some(rt) => {
+                let rt_r = rt
+                    .lock()
+                    .expect("failed_to_acquire_revision_tree_for_reading");
+                match rt_r.get_winner() {
+                    Some(winner) if !winner.is_deleted() => {
+                        Ok(Some(self.read_object_at_revision(uuid, &rt_r, winner)?))
+                    }
+                    _ => Ok(None),
+                }
+            }
             None => Ok(None),
         }
     }
 
-    /// Stages a full snapshot for array descriptors
-    ///
-    /// # Example
-    /// ```
-    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
-    /// use std::sync::{Arc, Mutex, RwLock};
-    /// use serde_json::{Map, Value,json};
-    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
-    /// let adapter = Arc::new(RwLock::new(adapter));
-    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
-    /// let object = json!({ "somekey\u{266D}" : [ { "_id" : "somedata", "value" : 1u32 }, { "_id" : "otherdata", "value" : 2u32 } ] }).as_object().unwrap().clone();
-    /// replica.update(object).unwrap();
-    /// replica.commit(None).unwrap();
-    /// assert!(!replica.has_staging());
-    /// replica.stage_full_snapshot().unwrap();
-    /// assert!(!replica.has_staging());
-    /// let object = json!({ "somekey\u{266D}" : [ { "_id" : "somedata2", "value" : 1u32 }, { "_id" : "otherdata", "value" : 2u32 } ] }).as_object().unwrap().clone();
-    /// replica.update(object).unwrap();
-    /// replica.commit(None).unwrap();
-    /// assert!(!replica.has_staging());
-    /// replica.stage_full_snapshot().unwrap();
-    /// assert!(replica.has_staging());
-    /// let stage = replica.stage().unwrap();
-    /// let content = serde_json::to_string(&stage).unwrap();
-    /// assert_eq!(content,"{\"c\":[[\"^5dce0c82036c35bb319c8e5085004949a604475936bb5a9bb124a95fd793aa6c\",\"2-97b7a6993ee290384d32087608174bbab48de824406166f8b78c24a3bf1e1a1c_986c918\",\"bdb1432c17447b65ac69463ecbc9cde3b8945388dac19a52eb3a7c0c0d5ce7f8\"]],\"o\":{\"bdb1432c17447b65ac69463ecbc9cde3b8945388dac19a52eb3a7c0c0d5ce7f8\":{\"A\":[\"somedata2\",\"otherdata\"]}}}");
-    /// replica.commit(None).unwrap();
-    /// assert!(!replica.has_staging());
-    /// replica.stage_full_snapshot().unwrap();
-    /// assert!(!replica.has_staging());
-    /// let readback = replica.read(None).unwrap();
-    /// let content = serde_json::to_string(&readback).unwrap();
-    /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\u{266D}\":[{\"_id\":\"somedata2\",\"value\":1},{\"_id\":\"otherdata\",\"value\":2}]}", content);
-    /// let object = json!({ "somekey\u{266D}" : [ { "_id" : "somedata2", "value" : 1u32 }, { "_id" : "otherdata2", "value" : 3u32 } ] }).as_object().unwrap().clone();
-    /// replica.update(object).unwrap();
-    /// let readback = replica.read(None).unwrap();
-    /// let content = serde_json::to_string(&readback).unwrap();
-    /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\u{266D}\":[{\"_id\":\"somedata2\",\"value\":1},{\"_id\":\"otherdata2\",\"value\":3}]}", content);
-    pub fn stage_full_snapshot(&self) -> Result<()> {
-        for (uuid, rt) in self.documents.read().unwrap().iter() {
-            if is_array_descriptor(uuid) {
-                let mut rt_w = rt.lock().expect("cannot_acquire_revision_tree_for_writing");
-                let winning_revision = rt_w.get_winner().ok_or_else(|| anyhow!("no_winner"))?;
-                if winning_revision.is_deleted() {
-                    continue;
-                }
-                // If any of the leafs is a diff we need to snapshot
-                for leaf in rt_w.get_leafs() {
-                    let base_descriptor = self.read_array_descriptor(leaf)?;
-                    if base_descriptor.is_diff() {
-                        // Read the full array object
-                        let object = self
-                            .read_object_at_revision(uuid, &rt_w, winning_revision)
-                            .unwrap();
-                        let digest = digest_object(&object).unwrap(); // Digest of the current object
-                        let rev = Revision::new_updated(digest, winning_revision);
-                        let winning_revision = winning_revision.clone();
-                        rt_w.add(rev.clone(), Some(winning_revision.clone()), true);
-                        let mut data_w =
-                            self.data.write().expect("cannot_acquire_data_for_writing");
-                        data_w.write_object(&rev, object).unwrap();
-                        drop(data_w);
-                        break;
-                    }
+    // Returns the array descriptor of a collection of the root object (None if the collection
+    // does not exist) and the identifiers of its elements
+    pub(crate) fn collection_order(
+        &self,
+        collection: &str,
+    ) -> Result<(Option<String>, Vec<String>)> {
+        match self
+            .load_flattened_object(ROOT_ID)?
+            .and_then(|mut root| root.remove(collection))
+        {
+            Some(Value::String(descriptor)) if is_array_descriptor(&descriptor) => {
+                match self.expand_flattened(&Value::from(descriptor.clone()))? {
+                    Some(Value::Array(order)) => {
+                        let order = order
+                            .into_iter()
+                            .filter_map(|uuid| uuid.as_str().map(|s| s.to_string()))
+                            .collect();
+                        Ok((Some(descriptor), order))
+                    }
+                    _ => bail!("not_a_collection"),
+                }
+            }
+            Some(_) => bail!("not_a_collection"),
+            None => Ok((None, vec![])),
+        }
+    }
+
+    // Resolves a flattened value (same semantics of unflatten, but objects are loaded on demand).
+    // The identifiers of all resolved objects are added to visited.
+    fn resolve_flattened(&self, value: &Value, visited: &mut BTreeSet<String>) -> Result<Value> {
+        match value {
+            Value::String(s) => {
+                if s.starts_with(STRING_ESCAPE_PREFIX) {
+                    Ok(Value::from(unescape(s)))
+                } else if is_array_descriptor(s) {
+                    let descriptor = self
+                        .load_flattened_object(s)?
+                        .ok_or_else(|| anyhow!("unknown_descriptor_object"))?;
+                    visited.insert(s.clone());
+                    let order = descriptor
+                        .get(ARRAY_DESCRIPTOR_ORDER_FIELD)
+                        .and_then(|o| o.as_array())
+                        .ok_or_else(|| anyhow!("expecting_order_field_in_descriptor"))?;
+                    let mut array = vec![];
+                    for uuid in order {
+                        if let Some(uuid) = uuid.as_str() {
+                            if visited.contains(uuid) {
+                                continue;
+                            }
+                            if let Some(object) = self.load_flattened_object(uuid)? {
+                                array.push(self.resolve_flattened_object(uuid, object, visited)?);
+                            }
+                        }
+                    }
+                    Ok(Value::from(array))
+                } else if visited.contains(s) {
+                    Ok(Value::Null)
+                } else {
+                    match self.load_flattened_object(s)? {
+                        Some(object) => self.resolve_flattened_object(s, object, visited),
+                        None => Ok(Value::Null),
+                    }
+                }
+            }
+            Value::Array(a) => Ok(Value::from(
+                a.iter()
+                    .map(|v| self.resolve_flattened(v, visited))
+                    .collect::<Result<Vec<_>>>()?,
+            )),
+            _ => Ok(value.clone()),
+        }
+    }
+
+    // Materializes an object (or a flattened array), reusing the value of the previous shared
+    // read if neither the object nor its children changed. Path contains the objects being
+    // materialized (references forming a cycle are resolved as null). Returns None for unknown
+    // or deleted objects
+    fn share_object(
+        &self,
+        uuid: &str,
+        path: &mut Vec<String>,
+        reached: &mut HashSet<String>,
+    ) -> Result<Option<SharedValue>> {
+        let docs_r = self
+            .documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading");
+        let (winner, object) = match docs_r.get(uuid) {
+            Some(rt) => {
+                let rt_r = rt
+                    .lock()
+                    .expect("failed_to_acquire_revision_tree_for_reading");
+                match rt_r.get_winner() {
+                    Some(winner) if !winner.is_deleted() => (
+                        winner.clone(),
+                        self.read_object_at_revision(uuid, &rt_r, winner)?,
+                    ),
+                    _ => return Ok(None),
+                }
+            }
+            None => return Ok(None),
+        };
+        drop(docs_r);
+        reached.insert(uuid.to_string());
+        path.push(uuid.to_string());
+        let mut children = vec![];
+        if is_array_descriptor(uuid) {
+            let order = object
+                .get(ARRAY_DESCRIPTOR_ORDER_FIELD)
+                .and_then(|o| o.as_array())
+                .ok_or_else(|| anyhow!("expecting_order_field_in_descriptor"))?;
+            for element in order.iter().filter_map(|e| e.as_str()) {
+                if !path.iter().any(|p| p == element) {
+                    children.extend(self.share_object(element, path, reached)?);
+                }
+            }
+        } else {
+            for (_, v) in object.iter().filter(|(k, _)| is_flattened_field(k)) {
+                self.share_references(v, path, reached, &mut children)?;
+            }
+        }
+        path.pop();
+        let mut shared = self.shared.lock().unwrap();
+        if let Some(entry) = shared.get(uuid) {
+            if entry.winner == winner
+                && entry.children.len() == children.len()
+                && entry
+                    .children
+                    .iter()
+                    .zip(&children)
+                    .all(|(a, b)| a.is_shared_with(b))
+            {
+                return Ok(Some(entry.value.clone()));
+            }
+        }
+        let size = approximate_object_size(&object);
+        let value = if is_array_descriptor(uuid) {
+            SharedValue::Array(Arc::new(children.clone()))
+        } else {
+            let mut references = children.iter();
+            let mut result = BTreeMap::new();
+            for (k, v) in object {
+                let v = if is_flattened_field(&k) {
+                    share_flattened(&v, &mut references)
+                } else {
+                    SharedValue::from(v)
+                };
+                result.insert(k, v);
+            }
+            result.insert(ID_FIELD.to_string(), SharedValue::Scalar(Value::from(uuid)));
+            SharedValue::Object(Arc::new(result))
+        };
+        let entry = SharedEntry {
+            winner,
+            children,
+            value: value.clone(),
+            size,
+        };
+        shared.insert(uuid.to_string(), entry);
+        Ok(Some(value))
+    }
+
+    // Materializes the objects (and arrays) referenced by a flattened value, in order
+    fn share_references(
+        &self,
+        value: &Value,
+        path: &mut Vec<String>,
+        reached: &mut HashSet<String>,
+        children: &mut Vec<SharedValue>,
+    ) -> Result<()> {
+        match value {
+            Value::String(s) if s.starts_with(STRING_ESCAPE_PREFIX) => {}
+            Value::String(s) if is_array_descriptor(s) => {
+                let array = self
+                    .share_object(s, path, reached)?
+                    .ok_or_else(|| anyhow!("unknown_descriptor_object"))?;
+                children.push(array);
+            }
+            Value::String(s) => {
+                let object = match path.contains(s) {
+                    true => None,
+                    false => self.share_object(s, path, reached)?,
+                };
+                children.push(object.unwrap_or(SharedValue::Scalar(Value::Null)));
+            }
+            Value::Array(a) => {
+                for v in a {
+                    self.share_references(v, path, reached, children)?;
                 }
             }
+            _ => {}
         }
         Ok(())
     }
 
+    // Resolves the flattened fields of an object
+    fn resolve_flattened_object(
+        &self,
+        uuid: &str,
+        object: Map<String, Value>,
+        visited: &mut BTreeSet<String>,
+    ) -> Result<Value> {
+        visited.insert(uuid.to_string());
+        let mut result = Map::<String, Value>::new();
+        for (k, v) in object {
+            if is_flattened_field(&k) {
+                let resolved = self.resolve_flattened(&v, visited)?;
+                result.insert(k, resolved);
+            } else {
+                result.insert(k, v);
+            }
+        }
+        result.insert(ID_FIELD.to_string(), Value::from(uuid));
+        Ok(Value::from(result))
+    }
+
     // **********************************************************************
     // **********************************************************************
     //
@@ -2092,19 +4784,50 @@ impl Melda {
     // **********************************************************************
     // **********************************************************************
 
-    // Fetch a block and verify digest
+    // Fetch a block from the cache or from the storage
     fn fetch_raw_block(&self, blockid: &str) -> Result<Map<String, Value>> {
+        if let Some(block) = self.block_cache.lock().unwrap().get(blockid) {
+            return Ok(block.clone());
+        }
+        let block = self.read_raw_block(blockid)?;
+        self.block_cache
+            .lock()
+            .unwrap()
+            .insert(blockid, block.clone());
+        Ok(block)
+    }
+
+    // Persist the block cache, dropping blocks which are no longer available
+    fn save_block_cache(&self, available: &[String]) {
+        let mut cache = self.block_cache.lock().unwrap();
+        cache.retain(&available.iter().cloned().collect());
+        if let Err(e) = cache.save() {
+            log::warn!("cannot_save_block_cache: {}", e);
+        }
+    }
+
+    // Read a block from the storage and verify digest
+    fn read_raw_block(&self, blockid: &str) -> Result<Map<String, Value>> {
         let object = blockid.to_string() + DELTA_EXTENSION;
         let data = self.data.read().expect("cannot_acquire_data_for_reading");
-        let data = data.read_raw_item(object.as_str(), 0, 0)?;
-        let digest = digest_bytes(data.as_slice());
+        let data =
+            data.read_raw_item(object.as_str(), 0, 0)
+                .map_err(|e| match MeldaError::of(&e) {
+                    Some(MeldaError::NotFound { .. }) => anyhow!(MeldaError::MissingBlock {
+                        id: blockid.to_string()
+                    }),
+                    _ => e,
+                })?;
+        let corrupt = || MeldaError::CorruptBlock {
+            id: blockid.to_string(),
+        };
+        let digest = digest_bytes_with(self.digest, data.as_slice());
         if !digest.eq(blockid) {
-            bail!("mismatching_block_hash");
+            bail!(corrupt());
         }
-        let json = std::str::from_utf8(&data)?;
-        let json: Value = serde_json::from_str(json)?;
+        let json: Value = decode_value(&data).map_err(|_| corrupt())?;
         if !json.is_object() {
-            bail!("invalid_block_format");
+            bail!(corrupt());
         }
         let blockobj = json.as_object().unwrap();
         Ok(blockobj.clone())
@@ -2116,6 +4839,8 @@ impl Melda {
         let mut b_parents: Option<BTreeSet<String>> = None;
         let mut b_info: Option<Map<String, Value>> = None;
         let mut b_packs: Option<BTreeSet<String>> = None;
+        let mut b_squashed: Option<BTreeSet<String>> = None;
+        let mut b_grafted: Option<BTreeSet<String>> = None;
         let mut b_changes: Option<Vec<Change>> = None;
         // Parse raw block fields
         if raw_block.contains_key(CHANGESETS_FIELD) {
@@ -2129,8 +4854,7 @@ impl Melda {
                 if !packs.iter().all(|x| {
                     if x.is_string() {
                         let data = self.data.read().expect("cannot_acquire_data_for_reading");
-                        data.is_readable_and_valid_pack(x.as_str().unwrap())
-                            .unwrap_or(false)
+                        data.is_available_pack(x.as_str().unwrap())
                     } else {
                         false
                     }
@@ -2175,6 +4899,30 @@ impl Melda {
                     b_parents = Some(ps);
                 }
             }
+            if let Some(squashed) = raw_block.get(SQUASHED_FIELD) {
+                let squashed = squashed
+                    .as_array()
+                    .ok_or_else(|| anyhow!("squashed_not_an_array"))?;
+                let ss: BTreeSet<String> = squashed
+                    .iter()
+                    .filter_map(|b| b.as_str().map(|b| b.to_string()))
+                    .collect();
+                if !ss.is_empty() {
+                    b_squashed = Some(ss);
+                }
+            }
+            if let Some(grafted) = raw_block.get(GRAFT_FIELD) {
+                let grafted = grafted
+                    .as_array()
+                    .ok_or_else(|| anyhow!("grafted_not_an_array"))?;
+                let gs: BTreeSet<String> = grafted
+                    .iter()
+                    .filter_map(|b| b.as_str().map(|b| b.to_string()))
+                    .collect();
+                if !gs.is_empty() {
+                    b_grafted = Some(gs);
+                }
+            }
             let changes = raw_block.get(CHANGESETS_FIELD);
             if let Some(changes) = changes {
                 if changes.is_array() {
@@ -2227,6 +4975,13 @@ impl Melda {
             parents: b_parents,
             info: b_info,
             packs: b_packs,
+            squashed: b_squashed,
+            grafted: b_grafted,
+            timestamp: raw_block.get(TIMESTAMP_FIELD).and_then(Value::as_u64),
+            replica: raw_block
+                .get(REPLICA_FIELD)
+                .and_then(Value::as_str)
+                .map(|r| r.to_string()),
             changes: b_changes,
             status: Status::Unknown,
         })
@@ -2267,6 +5022,260 @@ impl Melda {
         }
     }
 
+    // Redirects the references to squashed (or grafted) blocks which are not available to the
+    // block which replaced them
+    fn resolve_squashed_parents(&self) {
+        let blocks = self.blocks.read().unwrap();
+        let mut replaced = HashMap::<String, String>::new();
+        for (bid, block) in blocks.iter() {
+            let block_r = block.read().unwrap();
+            let replaced_ids = block_r.squashed.iter().chain(block_r.grafted.iter());
+            for sid in replaced_ids.flatten() {
+                if !blocks.contains_key(sid) {
+                    replaced.insert(sid.clone(), bid.clone());
+                }
+            }
+        }
+        if replaced.is_empty() {
+            return;
+        }
+        for (bid, block) in blocks.iter() {
+            let mut block_w = block.write().unwrap();
+            if let Some(parents) = &block_w.parents {
+                if parents.iter().any(|p| replaced.contains_key(p)) {
+                    let parents = parents
+                        .iter()
+                        .map(|p| replaced.get(p).unwrap_or(p).clone())
+                        .filter(|p| p != bid)
+                        .collect();
+                    block_w.parents = Some(parents);
+                }
+            }
+        }
+    }
+
+    // Returns the winning revisions of all objects
+    fn winning_revisions(&self) -> BTreeMap<String, Option<Revision>> {
+        let docs_r = self
+            .documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading");
+        docs_r
+            .iter()
+            .map(|(uuid, rt)| {
+                let rt_r = rt.lock().expect("cannot_acquire_revision_tree");
+                (uuid.clone(), rt_r.get_winner().cloned())
+            })
+            .collect()
+    }
+
+    // Returns the winning revision of an object
+    fn winning_revision(&self, uuid: &str) -> Option<Revision> {
+        let docs_r = self
+            .documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading");
+        let rt_r = docs_r
+            .get(uuid)?
+            .lock()
+            .expect("cannot_acquire_revision_tree");
+        rt_r.get_winner().cloned()
+    }
+
+    // Returns true if the winning revisions of the objects did not change
+    fn is_unchanged(&self, objects: &[(String, Option<Revision>)]) -> bool {
+        objects
+            .par_iter()
+            .all(|(uuid, winner)| self.winning_revision(uuid) == *winner)
+    }
+
+    // Forgets the fields of the root object touched by the given JSON Pointers (they will be
+    // flattened again by the next update)
+    fn touch_fields(&self, tokens: &[Vec<String>]) {
+        let mut clean = self.clean_fields.lock().unwrap();
+        for token in tokens.iter().filter_map(|t| t.first()) {
+            clean.fields.remove(token);
+        }
+    }
+
+    // Returns the generation of each block: the length of the longest path from an origin block
+    // (parents which are not available are considered as origins)
+    fn block_generations(&self) -> HashMap<String, usize> {
+        let blocks = self.blocks.read().unwrap();
+        let mut generations = HashMap::<String, usize>::new();
+        for bid in blocks.keys() {
+            let mut stack = vec![bid.clone()];
+            while let Some(current) = stack.last().cloned() {
+                if generations.contains_key(&current) {
+                    stack.pop();
+                    continue;
+                }
+                let parents: Vec<String> = blocks[&current]
+                    .read()
+                    .unwrap()
+                    .parents
+                    .iter()
+                    .flatten()
+                    .filter(|p| blocks.contains_key(*p))
+                    .cloned()
+                    .collect();
+                let pending: Vec<String> = parents
+                    .iter()
+                    .filter(|p| !generations.contains_key(*p))
+                    .cloned()
+                    .collect();
+                if pending.is_empty() {
+                    let generation = parents.iter().map(|p| generations[p] + 1).max();
+                    generations.insert(current, generation.unwrap_or(0));
+                    stack.pop();
+                } else {
+                    stack.extend(pending);
+                }
+            }
+        }
+        generations
+    }
+
+    // Returns the blocks replaced by a graft which are not available
+    fn missing_grafted_blocks(&self) -> HashSet<String> {
+        let blocks = self.blocks.read().unwrap();
+        let mut missing = HashSet::new();
+        for block in blocks.values() {
+            for gid in block.read().unwrap().grafted.iter().flatten() {
+                if !blocks.contains_key(gid) {
+                    missing.insert(gid.clone());
+                }
+            }
+        }
+        missing
+    }
+
+    // Returns the items (blocks, and the packs only referenced by them) of the given grafted
+    // blocks
+    fn grafted_items(&self, grafted: &HashSet<String>) -> HashSet<String> {
+        let mut items = HashSet::new();
+        if grafted.is_empty() {
+            return items;
+        }
+        let mut grafted_packs = HashSet::new();
+        let mut other_packs = HashSet::new();
+        for (bid, block) in self.blocks.read().unwrap().iter() {
+            let packs = block.read().unwrap().packs.clone().unwrap_or_default();
+            if grafted.contains(bid) {
+                items.insert(bid.clone() + DELTA_EXTENSION);
+                grafted_packs.extend(packs);
+            } else {
+                other_packs.extend(packs);
+            }
+        }
+        for pack in grafted_packs.difference(&other_packs) {
+            items.insert(pack.clone() + PACK_EXTENSION);
+            items.insert(pack.clone() + INDEX_EXTENSION);
+        }
+        items
+    }
+
+    // Returns the revisions (and their parents) that a graft must record to reproduce the
+    // current revisions of an object: the winner, the leafs, the resolved revisions closing
+    // their branches and, for array descriptors, the revisions back to a full descriptor
+    fn graft_revisions(
+        &self,
+        uuid: &str,
+        rt: &RevisionTree,
+    ) -> Result<Vec<(Revision, Option<Revision>)>> {
+        let mut selected: BTreeSet<Revision> = rt.get_leafs().clone();
+        selected.extend(rt.get_winner().cloned());
+        if is_array_descriptor(uuid) {
+            for rev in selected.clone() {
+                let mut current = rev;
+                while !(current.is_deleted() || current.is_resolved() || current.is_empty())
+                    && self.read_array_descriptor(&current)?.is_diff()
+                {
+                    match rt.get_parent(&current) {
+                        Some(parent) => current = parent.clone(),
+                        None => break,
+                    }
+                    selected.insert(current.clone());
+                }
+            }
+        }
+        Ok(rt
+            .get_revisions()
+            .iter()
+            .filter(|(rev, rte)| {
+                selected.contains(*rev)
+                    || (rev.is_resolved()
+                        && rte
+                            .get_parent()
+                            .as_ref()
+                            .is_some_and(|p| selected.contains(p)))
+            })
+            .map(|(rev, rte)| (rev.clone(), rte.get_parent().clone()))
+            .collect())
+    }
+
+    // Collects the content of the local blocks to be squashed into the next block (if the
+    // squash policy requires it). Blocks referenced by other blocks have been shared and are
+    // no longer considered local
+    fn prepare_squash(&self) -> Result<Option<PendingSquash>> {
+        let mut local = self.local_blocks.lock().unwrap();
+        let blocks = self.blocks.read().unwrap();
+        local.retain(|(bid, _)| blocks.contains_key(bid));
+        let is_local = |bid: &String| local.iter().any(|(l, _)| l == bid);
+        let shared = blocks.iter().any(|(bid, block)| {
+            !is_local(bid) && block.read().unwrap().parents.iter().flatten().any(is_local)
+        });
+        drop(blocks);
+        if shared {
+            local.clear();
+            return Ok(None);
+        }
+        let bytes = local.iter().map(|(_, size)| size).sum();
+        if !self.squash_policy.is_reached(local.len(), bytes) {
+            return Ok(None);
+        }
+        let mut squash = PendingSquash {
+            squashed: BTreeSet::new(),
+            parents: BTreeSet::new(),
+            packs: BTreeSet::new(),
+            changes: vec![],
+        };
+        let strings = |raw_block: &Map<String, Value>, field: &str| -> Vec<String> {
+            raw_block
+                .get(field)
+                .and_then(|v| v.as_array())
+                .map(|v| {
+                    v.iter()
+                        .filter_map(|s| s.as_str().map(|s| s.to_string()))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        for (bid, _) in local.iter() {
+            let raw_block = self.fetch_raw_block(bid)?;
+            if let Some(changes) = raw_block.get(CHANGESETS_FIELD).and_then(|c| c.as_array()) {
+                squash.changes.extend(changes.iter().cloned());
+            }
+            squash.squashed.insert(bid.clone());
+            squash.squashed.extend(strings(&raw_block, SQUASHED_FIELD));
+            squash.parents.extend(strings(&raw_block, PARENTS_FIELD));
+            squash.packs.extend(strings(&raw_block, PACK_FIELD));
+        }
+        let squashed = &squash.squashed;
+        squash.parents.retain(|p| !squashed.contains(p));
+        Ok(Some(squash))
+    }
+
+    // Records the blocks which have not been applied (they are reconsidered on refresh)
+    fn collect_pending_blocks(&self) {
+        let blocks_r = self.blocks.read().unwrap();
+        *self.pending_blocks.lock().unwrap() = blocks_r
+            .iter()
+            .filter(|(_, block)| block.read().unwrap().status != Status::ValidAndApplied)
+            .map(|(block_id, _)| block_id.clone())
+            .collect();
+    }
+
     fn mark_valid_blocks(&self) {
         let blocks = self.blocks.read().unwrap();
         blocks.iter().for_each(|(bid, block)| {
@@ -2278,6 +5287,9 @@ impl Melda {
     }
 
     fn apply_block(&self, block: &Block) -> Result<()> {
+        if self.trace.is_some() {
+            self.record_block(block);
+        }
         if let Some(changes) = &block.changes {
             for change in changes {
                 let Change(uuid, r, prev) = change;
@@ -2292,6 +5304,7 @@ impl Melda {
                     .expect("cannot_acquire_revision_tree_for_writing");
                 rt_w.add(r.clone(), prev.clone(), false);
             }
+            self.changed_objects(changes.iter().map(|Change(uuid, _, _)| uuid.as_str()));
         };
         Ok(())
     }
@@ -2316,7 +5329,8 @@ impl Melda {
             .rebuild_array_order(rt.get_winner().expect("no_winner"), rt)
             .expect("expecting_winning_order");
         let new_order = new_descriptor.get_order().as_ref().unwrap();
-        let patch = make_diff_patch(&winning_order, new_order).expect("failed_diffing");
+        let patch = make_chunked_diff_patch(&winning_order, new_order, self.array_chunk_size)
+            .expect("failed_diffing");
         if patch.is_empty() {
             Ok(None)
         } else {
@@ -2328,9 +5342,7 @@ impl Melda {
 
     fn read_array_descriptor(&self, revision: &Revision) -> Result<ArrayDescriptor> {
         let data_r = self.data.read().expect("cannot_acquire_data_for_reading");
-        let base_object = data_r
-            .read_object(revision)
-            .expect("cannot_read_base_array_descriptor");
+        let base_object = data_r.read_object(revision)?;
         drop(data_r);
         ArrayDescriptor::new_from_object(base_object)
     }
@@ -2398,7 +5410,7 @@ impl Melda {
     ) -> Result<Vec<Value>> {
         // The base object corresponds to the revision we want to keep (winner)
         let leafs = rt.get_leafs();
-        if leafs.len() > 1 {
+        if leafs.len() > 1 && self.ordering == ArrayOrdering::Merged {
             let mut base_order = self.rebuild_array_order(base_revision, rt)?;
             for l in leafs {
                 let leaf_order = self.rebuild_array_order(l, rt)?;
@@ -2410,3 +5422,330 @@ impl Melda {
         }
     }
 }
+
+// Returns the kind of change represented by a revision
+fn change_kind(revision: &Revision, parent: Option<&Revision>) -> ChangeKind {
+    if parent.is_none() {
+        ChangeKind::Created
+    } else if revision.is_deleted() {
+        ChangeKind::Deleted
+    } else {
+        ChangeKind::Updated
+    }
+}
+
+// Replaces the (non-flattened) fields of the object whose value differs in the alternative
+// revisions by the winning value and the distinct alternative values
+fn embed_conflicts(object: &mut Map<String, Value>, alternatives: &[Map<String, Value>]) {
+    let fields: BTreeSet<String> = object
+        .keys()
+        .chain(alternatives.iter().flat_map(|a| a.keys()))
+        .filter(|k| !is_flattened_field(k) && k.as_str() != ID_FIELD)
+        .cloned()
+        .collect();
+    for field in fields {
+        let winner = object.get(&field).cloned().unwrap_or(Value::Null);
+        let mut values: Vec<Value> = vec![];
+        for alternative in alternatives {
+            let value = alternative.get(&field).cloned().unwrap_or(Value::Null);
+            if value != winner && !values.contains(&value) {
+                values.push(value);
+            }
+        }
+        if !values.is_empty() {
+            object.insert(
+                field,
+                json!({ CONFLICT_WINNER_FIELD: winner, CONFLICT_ALTERNATIVES_FIELD: values }),
+            );
+        }
+    }
+}
+
+// Materializes a flattened value, taking the referenced objects (and arrays) from the given
+// materialized references (in the order returned by Melda::share_references)
+fn share_flattened<'a, I: Iterator<Item = &'a SharedValue>>(
+    value: &Value,
+    references: &mut I,
+) -> SharedValue {
+    match value {
+        Value::String(s) if s.starts_with(STRING_ESCAPE_PREFIX) => {
+            SharedValue::Scalar(Value::from(unescape(s)))
+        }
+        Value::String(_) => references
+            .next()
+            .cloned()
+            .unwrap_or(SharedValue::Scalar(Value::Null)),
+        Value::Array(a) => SharedValue::Array(Arc::new(
+            a.iter().map(|v| share_flattened(v, references)).collect(),
+        )),
+        v => SharedValue::Scalar(v.clone()),
+    }
+}
+
+// Visits the root object of a document being streamed, see Melda::update_from_reader
+struct StreamingRootVisitor<'a> {
+    melda: &'a Melda,
+    seen: &'a mut HashSet<String>,
+    root: &'a mut Map<String, Value>,
+    validator: &'a mut Option<StrictValidator>, // Validator of the strict mode
+}
+
+impl<'de> Visitor<'de> for StreamingRootVisitor<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a JSON object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> std::result::Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            if self.validator.is_some() {
+                StrictValidator::validate_key(&key, &[]).map_err(de::Error::custom)?;
+            }
+            if key == ID_FIELD {
+                if map.next_value::<Value>()? != ROOT_ID {
+                    return Err(de::Error::custom("invalid_root_identifier"));
+                }
+            } else if is_flattened_field(&key) {
+                let path = vec![ROOT_ID.to_string(), key.clone()];
+                let value = map.next_value_seed(StreamingFieldSeed {
+                    melda: self.melda,
+                    seen: &mut *self.seen,
+                    path: &path,
+                    validator: &mut *self.validator,
+                })?;
+                self.root.insert(key, value);
+            } else {
+                let value = map.next_value::<Value>()?;
+                self.root.insert(key, value);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Flattens and stages a flattened field of the root object: arrays are processed one element at a time
+struct StreamingFieldSeed<'a> {
+    melda: &'a Melda,
+    seen: &'a mut HashSet<String>,
+    path: &'a [String],
+    validator: &'a mut Option<StrictValidator>,
+}
+
+impl StreamingFieldSeed<'_> {
+    // Validates a value (or an element, if index is given) of the field in strict mode
+    fn validate<E: de::Error>(
+        &mut self,
+        value: &Value,
+        index: Option<usize>,
+    ) -> std::result::Result<(), E> {
+        let validator = match self.validator {
+            Some(validator) => validator,
+            None => return Ok(()),
+        };
+        let mut pointer = vec![self.path[self.path.len() - 1].clone()];
+        let result = match (index, value) {
+            (Some(index), _) => {
+                pointer.push(index.to_string());
+                validator.validate_element(value, self.path, &pointer)
+            }
+            (None, Value::Object(object)) => validator.validate_object(object, self.path, &pointer),
+            (None, _) => Ok(()),
+        };
+        result.map_err(E::custom)
+    }
+
+    fn stage<E: de::Error>(mut self, value: Value) -> std::result::Result<Value, E> {
+        self.validate(&value, None)?;
+        let mut extracted_objects = HashMap::<String, Map<String, Value>>::new();
+        let flattened = flatten(&mut extracted_objects, &value, self.path);
+        self.melda
+            .stage_extracted(&mut extracted_objects, self.seen)
+            .map_err(E::custom)?;
+        Ok(flattened)
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for StreamingFieldSeed<'_> {
+    type Value = Value;
+
+    fn deserialize<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for StreamingFieldSeed<'_> {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a JSON value")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> std::result::Result<Value, A::Error> {
+        let mut order = vec![];
+        let mut extracted_objects = HashMap::<String, Map<String, Value>>::new();
+        while let Some(element) = seq.next_element::<Value>()? {
+            self.validate(&element, Some(order.len()))?;
+            order.push(flatten(&mut extracted_objects, &element, self.path));
+            self.melda
+                .stage_extracted(&mut extracted_objects, self.seen)
+                .map_err(de::Error::custom)?;
+        }
+        let mut descriptor = Map::new();
+        descriptor.insert(ARRAY_DESCRIPTOR_ORDER_FIELD.to_string(), Value::from(order));
+        let uuid = ARRAY_DESCRIPTOR_PREFIX.to_string() + &digest_string(&self.path.join(""));
+        extracted_objects.insert(uuid.clone(), descriptor);
+        self.melda
+            .stage_extracted(&mut extracted_objects, self.seen)
+            .map_err(de::Error::custom)?;
+        Ok(Value::from(uuid))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> std::result::Result<Value, A::Error> {
+        let value = Value::deserialize(de::value::MapAccessDeserializer::new(map))?;
+        self.stage(value)
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> std::result::Result<Value, E> {
+        self.stage(Value::from(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> std::result::Result<Value, E> {
+        self.stage(Value::from(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> std::result::Result<Value, E> {
+        self.stage(Value::from(v))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> std::result::Result<Value, E> {
+        self.stage(Value::from(v))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> std::result::Result<Value, E> {
+        self.stage(Value::from(v))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> std::result::Result<Value, E> {
+        self.stage(Value::Null)
+    }
+}
+
+// Verifies that the content of a block or pack matches its digest
+fn verify_item(algorithm: DigestAlgorithm, key: &str, content: &[u8]) -> Result<()> {
+    if let Some(id) = key.strip_suffix(DELTA_EXTENSION) {
+        if digest_bytes_with(algorithm, content) != id {
+            bail!(MeldaError::CorruptBlock { id: id.to_string() });
+        }
+    } else if let Some(digest) = key.strip_suffix(PACK_EXTENSION) {
+        if digest_bytes_with(algorithm, content) != digest {
+            bail!(MeldaError::CorruptPack {
+                digest: digest.to_string()
+            });
+        }
+    } else if let Some(id) = key.strip_suffix(ATTACHMENT_EXTENSION) {
+        if digest_bytes_with(algorithm, content) != id {
+            bail!(MeldaError::CorruptAttachment { id: id.to_string() });
+        }
+    }
+    Ok(())
+}
+
+// Verifies that an item uploaded to a repository matches its digest, computed with the
+// algorithm recorded in the repository metadata (SHA-256 if there is no metadata)
+#[cfg(feature = "server")]
+pub(crate) fn verify_upload(adapter: &dyn Adapter, key: &str, content: &[u8]) -> Result<()> {
+    let algorithm = match read_repository_metadata(adapter)?
+        .as_ref()
+        .and_then(|m| m.get(DIGEST_FIELD))
+    {
+        Some(name) => DigestAlgorithm::from_name(
+            name.as_str()
+                .ok_or_else(|| anyhow!("invalid_repository_metadata"))?,
+        )?,
+        None => DigestAlgorithm::Sha256,
+    };
+    verify_item(algorithm, key, content)
+}
+
+/// Reads the repository metadata, if any
+fn read_repository_metadata(adapter: &dyn Adapter) -> Result<Option<Map<String, Value>>> {
+    if !adapter
+        .list_objects(METADATA_EXTENSION)?
+        .iter()
+        .any(|m| m == REPOSITORY_METADATA)
+    {
+        return Ok(None);
+    }
+    let key = REPOSITORY_METADATA.to_string() + METADATA_EXTENSION;
+    serde_json::from_slice(&adapter.read_object(&key, 0, 0)?)
+        .map(Some)
+        .map_err(|_| anyhow!("invalid_repository_metadata"))
+}
+
+/// Reads the identifier of the replica, if any
+fn read_replica_metadata(adapter: &dyn Adapter) -> Result<Option<String>> {
+    if !adapter
+        .list_objects(METADATA_EXTENSION)?
+        .iter()
+        .any(|m| m == REPLICA_METADATA)
+    {
+        return Ok(None);
+    }
+    let key = REPLICA_METADATA.to_string() + METADATA_EXTENSION;
+    let metadata: Map<String, Value> = serde_json::from_slice(&adapter.read_object(&key, 0, 0)?)
+        .map_err(|_| anyhow!("invalid_replica_metadata"))?;
+    match metadata.get(REPLICA_ID_FIELD).and_then(Value::as_str) {
+        Some(id) => Ok(Some(id.to_string())),
+        None => bail!("invalid_replica_metadata"),
+    }
+}
+
+/// Writes the identifier of the replica (replacing the existing one)
+fn write_replica_metadata(adapter: &dyn Adapter, id: &str) -> Result<()> {
+    let key = REPLICA_METADATA.to_string() + METADATA_EXTENSION;
+    let metadata = json!({ REPLICA_ID_FIELD: id }).to_string();
+    match read_replica_metadata(adapter)? {
+        Some(recorded) if recorded == id => Ok(()),
+        Some(_) => adapter.replace_object(&key, metadata.as_bytes()),
+        None => adapter.write_object(&key, metadata.as_bytes()),
+    }
+}
+
+/// Reads the schema of the documents, if any
+fn read_schema(adapter: &dyn Adapter) -> Result<Option<Schema>> {
+    if !adapter
+        .list_objects(METADATA_EXTENSION)?
+        .iter()
+        .any(|m| m == SCHEMA_METADATA)
+    {
+        return Ok(None);
+    }
+    let key = SCHEMA_METADATA.to_string() + METADATA_EXTENSION;
+    let schema = serde_json::from_slice(&adapter.read_object(&key, 0, 0)?)
+        .map_err(|_| anyhow!("invalid_schema"))?;
+    Schema::new(schema).map(Some)
+}
+
+/// Returns the format version recorded in the repository metadata (repositories written before
+/// the format was versioned have version 1)
+fn format_version(metadata: Option<&Map<String, Value>>) -> u64 {
+    metadata
+        .and_then(|m| m.get(FORMAT_VERSION_FIELD))
+        .and_then(Value::as_u64)
+        .unwrap_or(1)
+}
+
+/// Writes the repository metadata
+fn write_repository_metadata(
+    adapter: &dyn Adapter,
+    algorithm: DigestAlgorithm,
+    version: u64,
+) -> Result<()> {
+    let key = REPOSITORY_METADATA.to_string() + METADATA_EXTENSION;
+    let metadata = json!({ DIGEST_FIELD: algorithm.name(), FORMAT_VERSION_FIELD: version });
+    adapter.write_object(&key, metadata.to_string().as_bytes())
+}
@@ -0,0 +1,153 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use super::{verify_item, Melda};
+use crate::constants::{ATTACHMENT_EXTENSION, ATTACHMENT_FIELD, ATTACHMENT_SIZE_FIELD};
+use crate::utils::digest_bytes_with;
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+
+impl Melda {
+    /// Stores a binary attachment (such as an image or a document) and returns the object
+    /// referencing it, which can be stored in any field of a document. Attachments are
+    /// identified by the digest of their content and are written to the adapter immediately
+    /// (without being staged): they are transferred when melding, unless the replica fetches
+    /// them lazily (see [crate::builder::MeldaBuilder::lazy_attachments] and [Melda::fetch_attachments])
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The content of the attachment
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+    /// let reference = replica.put_attachment(b"some binary content").unwrap();
+    /// assert_eq!(reference["size"], 19);
+    /// replica.update(json!({ "photo" : reference }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let id = Melda::attachment_id(&replica.read(None).unwrap()["photo"]).unwrap().to_string();
+    /// assert_eq!(replica.get_attachment(&id).unwrap(), b"some binary content");
+    /// assert_eq!(replica.referenced_attachments().unwrap(), [id.clone()].into());
+    /// // Attachments are transferred when melding, unless they are fetched lazily
+    /// let replica2 = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+    /// replica2.meld(&replica).unwrap();
+    /// assert!(replica2.has_attachment(&id));
+    /// let replica3 = Melda::builder().url("memory://").lazy_attachments(true).open().unwrap();
+    /// replica3.meld(&replica).unwrap();
+    /// replica3.refresh().unwrap();
+    /// assert!(!replica3.has_attachment(&id));
+    /// assert!(replica3.get_attachment(&id).is_err());
+    /// assert_eq!(replica3.fetch_attachments(&replica).unwrap(), vec![id.clone()]);
+    /// assert_eq!(replica3.get_attachment(&id).unwrap(), b"some binary content");
+    /// ```
+    pub fn put_attachment(&self, content: &[u8]) -> Result<Value> {
+        let id = digest_bytes_with(self.digest, content);
+        if !self.has_attachment(&id) {
+            self.get_adapter()
+                .write()
+                .unwrap()
+                .write_object(&(id.clone() + ATTACHMENT_EXTENSION), content)?;
+        }
+        Ok(json!({ ATTACHMENT_FIELD: id, ATTACHMENT_SIZE_FIELD: content.len() }))
+    }
+
+    /// Returns the identifier of the attachment referenced by a value (None if the value is not
+    /// a reference to an attachment), see [Melda::put_attachment]
+    ///
+    /// # Arguments
+    ///
+    /// * `reference` - The value referencing the attachment
+    pub fn attachment_id(reference: &Value) -> Option<&str> {
+        reference.get(ATTACHMENT_FIELD).and_then(Value::as_str)
+    }
+
+    /// Returns true if the attachment is available in this replica
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Identifier of the attachment
+    pub fn has_attachment(&self, id: &str) -> bool {
+        self.get_adapter()
+            .read()
+            .unwrap()
+            .object_size(&(id.to_string() + ATTACHMENT_EXTENSION))
+            .is_ok()
+    }
+
+    /// Reads the content of an attachment, verifying that it matches its identifier
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Identifier of the attachment
+    pub fn get_attachment(&self, id: &str) -> Result<Vec<u8>> {
+        let key = id.to_string() + ATTACHMENT_EXTENSION;
+        let content = self.get_adapter().read().unwrap().read_object(&key, 0, 0)?;
+        verify_item(self.digest, &key, &content)?;
+        Ok(content)
+    }
+
+    /// Returns the identifiers of the attachments referenced by the current state
+    pub fn referenced_attachments(&self) -> Result<BTreeSet<String>> {
+        let mut referenced = BTreeSet::new();
+        for uuid in self.get_all_objects() {
+            if self.is_deleted(&uuid)? {
+                continue;
+            }
+            let mut pending = vec![Value::from(self.get_value(&uuid, None)?)];
+            while let Some(value) = pending.pop() {
+                if let Some(id) = Melda::attachment_id(&value) {
+                    referenced.insert(id.to_string());
+                    continue;
+                }
+                match value {
+                    Value::Object(object) => pending.extend(object.into_iter().map(|(_, v)| v)),
+                    Value::Array(array) => pending.extend(array),
+                    _ => {}
+                }
+            }
+        }
+        Ok(referenced)
+    }
+
+    /// Copies the attachments referenced by the current state which are missing in this replica
+    /// from another replica (attachments are not transferred when melding into a replica which
+    /// fetches them lazily, see [crate::builder::MeldaBuilder::lazy_attachments]). Returns the identifiers of
+    /// the fetched attachments
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - Another Melda instance
+    pub fn fetch_attachments(&self, other: &Melda) -> Result<Vec<String>> {
+        if self.digest != other.digest {
+            bail!("digest_algorithm_mismatch");
+        }
+        let mut fetched = vec![];
+        for id in self.referenced_attachments()? {
+            if self.has_attachment(&id) || !other.has_attachment(&id) {
+                continue;
+            }
+            let content = other.get_attachment(&id)?;
+            self.get_adapter()
+                .write()
+                .unwrap()
+                .write_object(&(id.clone() + ATTACHMENT_EXTENSION), &content)?;
+            fetched.push(id);
+        }
+        Ok(fetched)
+    }
+}
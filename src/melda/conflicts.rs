@@ -0,0 +1,1216 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use super::{Block, Change, Melda, Status};
+use crate::constants::{COMMIT_AUTHOR_FIELD, RESOLUTIONS_FIELD};
+use crate::error::MeldaError;
+use crate::revision::Revision;
+use crate::utils::{
+    is_array_descriptor, is_flattened_field, merge_objects, now_millis, parse_json_pointer,
+    pointer_child_mut,
+};
+use anyhow::{anyhow, bail, Result};
+use rayon::prelude::*;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+
+// Field-level merge of the conflicting revisions of an object
+struct FieldMerge {
+    winner: String,
+    discarded: Vec<String>,
+    merged: Map<String, Value>, // Fields which diverge keep the value of the winner
+    diverging: BTreeSet<String>,
+}
+
+// Origins of the conflicting revisions (by object and revision), information and timestamp of
+// the applied blocks
+#[derive(Default)]
+struct ConflictOrigins {
+    origins: HashMap<(String, Revision), String>,
+    infos: HashMap<String, Option<Map<String, Value>>>,
+    timestamps: HashMap<String, Option<u64>>,
+}
+
+/// How a conflict is resolved, see [Melda::resolve]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResolutionChoice {
+    /// Keeps the current winning revision
+    KeepWinner,
+    /// Keeps the given conflicting revision
+    KeepRevision(String),
+    /// Replaces the object with a new value (flattened fields keep their current content)
+    Custom(Value),
+}
+
+/// Annotation recorded along with the resolution of a conflict, see [Melda::resolve_with_note]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResolutionNote {
+    /// Who resolved the conflict (recorded as the *author* of the commit)
+    pub author: Option<String>,
+    /// Why the revision was chosen
+    pub reason: Option<String>,
+}
+
+/// Resolution of a conflict found in the history, see [Melda::resolutions]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ResolutionRecord {
+    /// Identifier of the object
+    pub uuid: String,
+    /// The revision chosen as the winner
+    pub revision: String,
+    /// The conflicting revisions which were discarded
+    pub discarded: Vec<String>,
+    /// Whether the value of the chosen revision was replaced by a custom value
+    pub custom: bool,
+    /// Who resolved the conflict (the *author* of the commit)
+    pub author: Option<String>,
+    /// Why the revision was chosen
+    pub reason: Option<String>,
+    /// Block which committed the resolution
+    pub block: String,
+    /// Commit time of the block (in milliseconds since the Unix epoch)
+    pub timestamp: Option<u64>,
+}
+
+/// Revision competing in a conflict, as presented to a [ConflictPolicy]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConflictCandidate {
+    /// The revision
+    pub revision: String,
+    /// The (flattened) value of the revision, None if the revision deletes the object
+    pub value: Option<Map<String, Value>>,
+    /// Block which introduced the revision
+    pub block: Option<String>,
+    /// Author of the block (the *author* field of the commit information)
+    pub author: Option<String>,
+    /// Commit time of the block (in milliseconds since the Unix epoch)
+    pub timestamp: Option<u64>,
+}
+
+// Function choosing the winner of a conflict
+type ConflictResolver = dyn Fn(&str, &[ConflictCandidate]) -> Option<String> + Send + Sync;
+
+/// Policy resolving conflicts automatically on refresh, see [crate::builder::MeldaBuilder::conflict_policy].
+/// A conflict is resolved only if the policy designates a single revision, otherwise it is left
+/// unresolved
+#[derive(Clone)]
+pub enum ConflictPolicy {
+    /// Prefers the revision committed last
+    LatestTimestamp,
+    /// Prefers the revision of the author listed first
+    AuthorPriority(Vec<String>),
+    /// Prefers the revision whose value is the longest (once serialized)
+    LongestValue,
+    /// Prefers the revision returned by the function (invoked with the identifier of the object
+    /// and the conflicting revisions), if any
+    Custom(Arc<ConflictResolver>),
+}
+
+impl ConflictPolicy {
+    /// Creates a policy delegating the choice to a function
+    pub fn custom<F: Fn(&str, &[ConflictCandidate]) -> Option<String> + Send + Sync + 'static>(
+        f: F,
+    ) -> Self {
+        ConflictPolicy::Custom(Arc::new(f))
+    }
+
+    /// Returns the revision chosen among the candidates, if any
+    fn choose(&self, uuid: &str, candidates: &[ConflictCandidate]) -> Option<String> {
+        // Returns the candidate with the greatest key, if it is unique
+        fn best<K: Ord>(
+            candidates: &[ConflictCandidate],
+            key: impl Fn(&ConflictCandidate) -> K,
+        ) -> Option<String> {
+            let max = candidates.iter().map(&key).max()?;
+            let mut best = candidates.iter().filter(|c| key(c) == max);
+            match (best.next(), best.next()) {
+                (Some(candidate), None) => Some(candidate.revision.clone()),
+                _ => None,
+            }
+        }
+        match self {
+            ConflictPolicy::LatestTimestamp => best(candidates, |c| c.timestamp),
+            ConflictPolicy::AuthorPriority(authors) => best(candidates, |c| {
+                c.author
+                    .as_ref()
+                    .and_then(|a| authors.iter().position(|p| p == a))
+                    .map(std::cmp::Reverse)
+            }),
+            ConflictPolicy::LongestValue => best(candidates, |c| {
+                c.value
+                    .as_ref()
+                    .map_or(0, |v| Value::from(v.clone()).to_string().len())
+            }),
+            ConflictPolicy::Custom(f) => f(uuid, candidates)
+                .filter(|revision| candidates.iter().any(|c| c.revision == *revision)),
+        }
+    }
+}
+
+/// Outcome of the automatic resolution of the conflicts, see [Melda::auto_resolution_report]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AutoResolutionReport {
+    /// Objects whose conflict has been resolved (and the chosen revision)
+    pub resolved: BTreeMap<String, String>,
+    /// Objects whose conflict could not be resolved by the policy
+    pub unresolved: BTreeSet<String>,
+}
+
+/// An object in conflict, see [Melda::conflict_report]. Ages are measured in generations: the
+/// generation of a block is the length of the longest path from an origin block to the block
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConflictInfo {
+    /// Identifier of the object
+    pub uuid: String,
+    /// Field of the root object holding the collection the object belongs to, None if the object
+    /// is a root object or is not reachable
+    pub collection: Option<String>,
+    /// Number of conflicting revisions (including the winning one)
+    pub revisions: usize,
+    /// Blocks which introduced the conflicting revisions (staged revisions have no block)
+    pub blocks: BTreeSet<String>,
+    /// Authors of the blocks (taken from the "author" field of the commit information)
+    pub authors: BTreeSet<String>,
+    /// Generation of the most recent block involved in the conflict
+    pub generation: usize,
+    /// Number of generations elapsed since the conflict arose
+    pub age: usize,
+}
+
+/// Summary of the ongoing conflicts, see [Melda::conflict_report]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ConflictReport {
+    /// Objects in conflict (flattened arrays, which are merged automatically, are not included)
+    pub conflicts: Vec<ConflictInfo>,
+    /// Number of conflicts in each collection
+    pub by_collection: BTreeMap<String, usize>,
+    /// Number of conflicts involving each author
+    pub by_author: BTreeMap<String, usize>,
+    /// Number of conflicts which arose at each generation
+    pub by_generation: BTreeMap<usize, usize>,
+}
+
+impl ConflictReport {
+    /// Returns the number of objects in conflict
+    pub fn count(&self) -> usize {
+        self.conflicts.len()
+    }
+
+    /// Returns the conflict which has been ongoing for the longest time
+    pub fn oldest(&self) -> Option<&ConflictInfo> {
+        self.conflicts.iter().max_by_key(|c| c.age)
+    }
+}
+
+impl Melda {
+    /// Returns a set of the object (identifiers) which have ongoing conflicts
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);  
+    /// assert!(replica.get_all_objects().contains("myobject"));
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// let block_id = replica.commit(None).unwrap().unwrap();
+    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter2 = Arc::new(RwLock::new(adapter2));
+    /// let replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "another" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica2.create_object("myobject", object);
+    /// let anchors2 = replica2.commit(None).unwrap().unwrap();
+    /// replica2.meld(&replica);
+    /// replica2.refresh();
+    /// let conflicting = replica2.in_conflict();
+    /// assert!(conflicting.contains("myobject"));
+    pub fn in_conflict(&self) -> BTreeSet<String> {
+        self.documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading")
+            .par_iter()
+            .filter(|(_, rt)| {
+                let rt_r = rt
+                    .lock()
+                    .expect("failed_to_acquire_revision_tree_for_reading");
+                let l = rt_r.get_leafs();
+                l.len() > 1
+            })
+            .map(|(uuid, _)| uuid.clone())
+            .collect()
+    }
+
+    /// Returns the winning revision for the given object
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - The uuid of the object
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);  
+    /// assert!(replica.get_all_objects().contains("myobject"));
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// let block_id = replica.commit(None).unwrap().unwrap();
+    /// replica.delete_object("myobject");
+    /// assert!(replica.get_all_objects().contains("myobject"));
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("2-d_e5d1d20", winner);
+    /// let value = replica.get_value("myobject", Some(&winner));
+    /// assert!(value.is_ok());
+    /// assert!(value.unwrap().contains_key("_deleted"));
+    /// replica.unstage();
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// ```
+    pub fn get_winner<T>(&self, uuid: T) -> Result<String>
+    where
+        T: AsRef<str>,
+    {
+        match self
+            .documents
+            .read()
+            .expect("cannot_acquire_documents_for_reading")
+            .get(uuid.as_ref())
+        {
+            Some(rt) => {
+                let rt_r = rt.lock().expect("cannot_acquire_revision_tree_for_reading");
+                match rt_r.get_winner() {
+                    Some(r) => Ok(r.to_string()),
+                    None => Err(anyhow!("no_winner")),
+                }
+            }
+            None => Err(anyhow!("unknown_document")),
+        }
+    }
+
+    /// Returns a set of the conflicting revisions of the given object (the winning revision is not included!)
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - The uuid of the object
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);  
+    /// assert!(replica.get_all_objects().contains("myobject"));
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// let anchors = replica.commit(None).unwrap().unwrap();
+    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter2 = Arc::new(RwLock::new(adapter2));
+    /// let replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "another" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica2.create_object("myobject", object);
+    /// let winner2 = replica2.get_winner("myobject").unwrap();
+    /// let anchors2 = replica2.commit(None).unwrap().unwrap();
+    /// replica2.meld(&replica);
+    /// replica2.refresh();
+    /// let conflicting = replica2.in_conflict();
+    /// assert!(conflicting.contains("myobject"));
+    /// let revs = replica2.get_conflicting("myobject").unwrap();
+    /// assert!(revs.contains(&winner2));
+    pub fn get_conflicting<T>(&self, uuid: T) -> Result<BTreeSet<String>>
+    where
+        T: AsRef<str>,
+    {
+        match self
+            .documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading")
+            .get(uuid.as_ref())
+        {
+            Some(rt) => {
+                let rt_r = rt
+                    .lock()
+                    .expect("failed_to_acquire_revision_tree_for_reading");
+                let w = rt_r.get_winner().ok_or_else(|| anyhow!("no_winner"))?;
+                let l = rt_r.get_leafs();
+                Ok(l.iter()
+                    .filter(|r| w.ne(r))
+                    .map(|r| r.to_string())
+                    .collect())
+            }
+            None => Err(anyhow!("unknown_document")),
+        }
+    }
+
+    /// Summarizes the ongoing conflicts: for each object in conflict, the collection it belongs
+    /// to, the blocks (and their authors) which introduced the conflicting revisions and how long
+    /// the conflict has been ongoing, along with the number of conflicts per collection, per
+    /// author and per generation. The report can be serialized to JSON (for example to feed a
+    /// dashboard)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+    /// let object = json!({ "tasks\u{266D}" : [ { "_id" : "1", "title" : "Buy milk" }, { "_id" : "2", "title" : "Call Bob" } ] });
+    /// replica.update(object.as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let replica2 = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+    /// replica2.meld(&replica).unwrap();
+    /// replica.set("/tasks\u{266D}/0/title", json!("Buy bread")).unwrap();
+    /// replica.commit(Some(json!({ "author" : "Alice" }).as_object().unwrap().clone())).unwrap();
+    /// replica2.refresh().unwrap();
+    /// replica2.set("/tasks\u{266D}/0/title", json!("Buy cheese")).unwrap();
+    /// replica2.commit(Some(json!({ "author" : "Bob" }).as_object().unwrap().clone())).unwrap();
+    /// replica.meld(&replica2).unwrap();
+    /// replica.refresh().unwrap();
+    /// replica.set("/tasks\u{266D}/1/title", json!("Call Carol")).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let report = replica.conflict_report().unwrap();
+    /// assert_eq!(report.count(), 1);
+    /// let conflict = report.oldest().unwrap();
+    /// assert_eq!(conflict.uuid, "1");
+    /// assert_eq!(conflict.collection.as_deref(), Some("tasks\u{266D}"));
+    /// assert_eq!(conflict.revisions, 2);
+    /// assert_eq!(conflict.blocks.len(), 2);
+    /// assert_eq!((conflict.generation, conflict.age), (1, 1));
+    /// assert_eq!(report.by_author.get("Alice"), Some(&1));
+    /// assert_eq!(report.by_generation.get(&1), Some(&1));
+    /// assert_eq!(serde_json::to_value(&report).unwrap()["by_collection"]["tasks\u{266D}"], 1);
+    /// ```
+    pub fn conflict_report(&self) -> Result<ConflictReport> {
+        let mut leaves = HashMap::<String, BTreeSet<Revision>>::new();
+        for (uuid, rt) in self.documents.read().unwrap().iter() {
+            if is_array_descriptor(uuid) {
+                continue;
+            }
+            let rt_r = rt.lock().expect("cannot_acquire_revision_tree_for_reading");
+            if rt_r.get_leafs().len() > 1 {
+                leaves.insert(uuid.clone(), rt_r.get_leafs().clone());
+            }
+        }
+        let mut report = ConflictReport::default();
+        if leaves.is_empty() {
+            return Ok(report);
+        }
+        let ConflictOrigins { origins, infos, .. } = self.conflict_origins(&leaves)?;
+        let generations = self.block_generations();
+        let current = generations.values().copied().max().unwrap_or(0);
+        for (uuid, revisions) in leaves {
+            let blocks: BTreeSet<String> = revisions
+                .iter()
+                .filter_map(|rev| origins.get(&(uuid.clone(), rev.clone())).cloned())
+                .collect();
+            let authors: BTreeSet<String> = blocks
+                .iter()
+                .filter_map(|bid| infos.get(bid).cloned().flatten())
+                .filter_map(|info| {
+                    info.get(COMMIT_AUTHOR_FIELD)
+                        .and_then(Value::as_str)
+                        .map(str::to_string)
+                })
+                .collect();
+            let generation = blocks
+                .iter()
+                .filter_map(|bid| generations.get(bid).copied())
+                .max()
+                .unwrap_or(current);
+            let collection = self
+                .get_location(&uuid)?
+                .and_then(|l| parse_json_pointer(&l.pointer).ok())
+                .and_then(|tokens| tokens.into_iter().next());
+            if let Some(collection) = &collection {
+                *report.by_collection.entry(collection.clone()).or_default() += 1;
+            }
+            for author in &authors {
+                *report.by_author.entry(author.clone()).or_default() += 1;
+            }
+            *report.by_generation.entry(generation).or_default() += 1;
+            report.conflicts.push(ConflictInfo {
+                uuid,
+                collection,
+                revisions: revisions.len(),
+                blocks,
+                authors,
+                generation,
+                age: current - generation,
+            });
+        }
+        report.conflicts.sort_by(|a, b| a.uuid.cmp(&b.uuid));
+        Ok(report)
+    }
+
+    /// Finds the blocks which introduced the conflicting revisions (along with the information
+    /// and the timestamp of all applied blocks)
+    fn conflict_origins(
+        &self,
+        leaves: &HashMap<String, BTreeSet<Revision>>,
+    ) -> Result<ConflictOrigins> {
+        let mut result = ConflictOrigins::default();
+        let applied: Vec<String> = self
+            .blocks
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, block)| block.read().unwrap().status == Status::ValidAndApplied)
+            .map(|(bid, _)| bid.clone())
+            .collect();
+        for bid in applied {
+            let block = self.parse_raw_block(bid.clone(), self.fetch_raw_block(&bid)?)?;
+            for Change(uuid, rev, _) in block.changes.into_iter().flatten() {
+                if leaves.get(&uuid).is_some_and(|l| l.contains(&rev)) {
+                    result.origins.insert((uuid, rev), bid.clone());
+                }
+            }
+            result.timestamps.insert(bid.clone(), block.timestamp);
+            result.infos.insert(bid, block.info);
+        }
+        Ok(result)
+    }
+
+    /// Applies the conflict policy (if any) to the given objects, committing the resolutions
+    pub(super) fn apply_conflict_policy(&self, uuids: BTreeSet<String>) -> Result<()> {
+        if let Some(policy) = &self.conflict_policy {
+            let report = self.resolve_with_policy(policy, uuids)?;
+            *self.auto_resolution.lock().unwrap() = report;
+        }
+        Ok(())
+    }
+
+    // Resolves the conflicts of the given objects according to the policy, committing the
+    // resolutions
+    fn resolve_with_policy(
+        &self,
+        policy: &ConflictPolicy,
+        uuids: BTreeSet<String>,
+    ) -> Result<AutoResolutionReport> {
+        let mut leaves = HashMap::<String, BTreeSet<Revision>>::new();
+        {
+            let docs_r = self.documents.read().unwrap();
+            for uuid in uuids.iter().filter(|uuid| !is_array_descriptor(uuid)) {
+                if let Some(rt) = docs_r.get(uuid) {
+                    let rt_r = rt.lock().expect("cannot_acquire_revision_tree_for_reading");
+                    if rt_r.get_leafs().len() > 1 {
+                        leaves.insert(uuid.clone(), rt_r.get_leafs().clone());
+                    }
+                }
+            }
+        }
+        let mut report = AutoResolutionReport::default();
+        let mut entries = vec![];
+        if !leaves.is_empty() {
+            let ConflictOrigins {
+                origins,
+                infos,
+                timestamps,
+            } = self.conflict_origins(&leaves)?;
+            for (uuid, revisions) in leaves {
+                let mut candidates = vec![];
+                for rev in revisions {
+                    let block = origins.get(&(uuid.clone(), rev.clone())).cloned();
+                    let author = block
+                        .as_ref()
+                        .and_then(|bid| infos.get(bid).cloned().flatten())
+                        .and_then(|info| {
+                            info.get(COMMIT_AUTHOR_FIELD)
+                                .and_then(Value::as_str)
+                                .map(str::to_string)
+                        });
+                    let timestamp = block
+                        .as_ref()
+                        .and_then(|bid| timestamps.get(bid).copied().flatten());
+                    let value = if rev.is_deleted() {
+                        None
+                    } else {
+                        self.get_value(&uuid, Some(&rev.to_string())).ok()
+                    };
+                    candidates.push(ConflictCandidate {
+                        revision: rev.to_string(),
+                        value,
+                        block,
+                        author,
+                        timestamp,
+                    });
+                }
+                match policy.choose(&uuid, &candidates) {
+                    Some(winner) if self.resolve_as(&uuid, &winner).is_ok() => {
+                        let discarded: Vec<&String> = candidates
+                            .iter()
+                            .map(|c| &c.revision)
+                            .filter(|r| **r != winner)
+                            .collect();
+                        entries.push(json!({ "uuid": uuid, "revision": winner, "discarded": discarded, "reason": "conflict_policy" }));
+                        report.resolved.insert(uuid, winner);
+                    }
+                    _ => {
+                        report.unresolved.insert(uuid);
+                    }
+                }
+            }
+            if !report.resolved.is_empty() {
+                // The resolutions are committed, so that the stage is empty for the next refresh
+                let info = json!({ RESOLUTIONS_FIELD: entries });
+                if let Err(e) = self.commit_at(info.as_object().cloned(), now_millis()) {
+                    self.unstage()?;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Resolves all the conflicts according to the policy and commits the resolutions as a
+    /// single block (see [Melda::resolutions]). Conflicts for which the policy does not
+    /// designate a single revision are left unresolved. The stage must be empty
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The policy choosing the winners
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::{Melda, ConflictPolicy};
+    /// use serde_json::json;
+    /// let items = |title: &str| json!({ "items\u{266D}" : (0..100).map(|i| json!({ "_id" : i.to_string(), "title" : format!("{} {}", title, i) })).collect::<Vec<_>>() });
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// replica.update(items("Imported").as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let other = Melda::new_from_url("memory://").unwrap();
+    /// other.update(items("Reimported").as_object().unwrap().clone()).unwrap();
+    /// other.commit(None).unwrap();
+    /// replica.meld(&other).unwrap();
+    /// replica.refresh().unwrap();
+    /// assert_eq!(replica.in_conflict().len(), 100);
+    /// let report = replica.resolve_all(&ConflictPolicy::LongestValue).unwrap();
+    /// assert_eq!(report.resolved.len(), 100);
+    /// assert!(replica.in_conflict().is_empty());
+    /// assert_eq!(replica.get("/items\u{266D}/7/title").unwrap().unwrap(), "Reimported 7");
+    /// ```
+    pub fn resolve_all(&self, policy: &ConflictPolicy) -> Result<AutoResolutionReport> {
+        self.resolve_where(|_| true, policy)
+    }
+
+    /// Resolves the conflicts matching the predicate according to the policy, like
+    /// [Melda::resolve_all]. The predicate receives the description of each conflict (see
+    /// [Melda::conflict_report])
+    ///
+    /// # Arguments
+    ///
+    /// * `predicate` - Selects the conflicts to resolve
+    /// * `policy` - The policy choosing the winners
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::{Melda, ConflictPolicy};
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// replica.update(json!({ "tasks\u{266D}" : [ { "_id" : "t", "title" : "Call Alice" } ], "notes\u{266D}" : [ { "_id" : "n", "text" : "Hello" } ] }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(Some(json!({ "author" : "Alice" }).as_object().unwrap().clone())).unwrap();
+    /// let other = Melda::new_from_url("memory://").unwrap();
+    /// other.update(json!({ "tasks\u{266D}" : [ { "_id" : "t", "title" : "Call Bob" } ], "notes\u{266D}" : [ { "_id" : "n", "text" : "Bye" } ] }).as_object().unwrap().clone()).unwrap();
+    /// other.commit(Some(json!({ "author" : "Bob" }).as_object().unwrap().clone())).unwrap();
+    /// replica.meld(&other).unwrap();
+    /// replica.refresh().unwrap();
+    /// let policy = ConflictPolicy::AuthorPriority(vec!["Alice".to_string()]);
+    /// let report = replica.resolve_where(|c| c.collection.as_deref() == Some("tasks\u{266D}"), &policy).unwrap();
+    /// assert_eq!(report.resolved.keys().collect::<Vec<_>>(), vec!["t"]);
+    /// assert_eq!(replica.get("/tasks\u{266D}/0/title").unwrap().unwrap(), "Call Alice");
+    /// assert!(replica.in_conflict().contains("n"));
+    /// ```
+    pub fn resolve_where<P: Fn(&ConflictInfo) -> bool>(
+        &self,
+        predicate: P,
+        policy: &ConflictPolicy,
+    ) -> Result<AutoResolutionReport> {
+        if self.has_staging() {
+            bail!(MeldaError::StageNotEmpty)
+        }
+        let uuids = self
+            .conflict_report()?
+            .conflicts
+            .into_iter()
+            .filter(|conflict| predicate(conflict))
+            .map(|conflict| conflict.uuid)
+            .collect();
+        self.resolve_with_policy(policy, uuids)
+    }
+
+    // Merges the fields of the conflicting revisions of an object with respect to their common
+    // ancestor (or to an empty object, if the revisions have no common ancestor). Returns None
+    // if the object is not in conflict, if a revision deletes the object or if the ancestor is
+    // no longer available
+    fn field_merge(&self, uuid: &str) -> Result<Option<FieldMerge>> {
+        if is_array_descriptor(uuid) {
+            return Ok(None);
+        }
+        let docs_r = self.documents.read().unwrap();
+        let rt_r = match docs_r.get(uuid) {
+            Some(rt) => rt.lock().expect("cannot_acquire_revision_tree_for_reading"),
+            None => return Ok(None),
+        };
+        let leaves = rt_r.get_leafs();
+        if leaves.len() < 2 || leaves.iter().any(Revision::is_deleted) {
+            return Ok(None);
+        }
+        let ancestor = match rt_r.common_ancestor(leaves) {
+            Some(ancestor) if ancestor.is_deleted() => Map::new(),
+            Some(ancestor) => match self.read_object_at_revision(uuid, &rt_r, ancestor) {
+                Ok(ancestor) => ancestor,
+                Err(_) => return Ok(None),
+            },
+            None => Map::new(),
+        };
+        let winner = rt_r
+            .get_winner()
+            .expect("revision_tree_invalid_state")
+            .clone();
+        let current = self.read_object_at_revision(uuid, &rt_r, &winner)?;
+        let mut merged = current.clone();
+        let mut diverging = BTreeSet::new();
+        for leaf in leaves.iter().filter(|l| **l != winner) {
+            let other = self.read_object_at_revision(uuid, &rt_r, leaf)?;
+            merged = merge_objects(&ancestor, &merged, &other, &mut vec![], &mut diverging);
+        }
+        // Diverging values keep the value of the winner
+        let current = Value::from(current);
+        let mut value = Value::from(merged);
+        for pointer in &diverging {
+            let tokens = parse_json_pointer(pointer)?;
+            let (last, parents) = tokens.split_last().expect("empty_pointer");
+            let parent = parents
+                .iter()
+                .try_fold(&mut value, |v, t| pointer_child_mut(v, t));
+            if let Some(Value::Object(parent)) = parent {
+                match current.pointer(pointer) {
+                    Some(v) => parent.insert(last.clone(), v.clone()),
+                    None => parent.remove(last),
+                };
+            }
+        }
+        let merged = match value {
+            Value::Object(merged) => merged,
+            _ => unreachable!(),
+        };
+        Ok(Some(FieldMerge {
+            discarded: leaves
+                .iter()
+                .filter(|l| **l != winner)
+                .map(|l| l.to_string())
+                .collect(),
+            winner: winner.to_string(),
+            merged,
+            diverging,
+        }))
+    }
+
+    // Merges the conflicting revisions of the given objects which changed different fields,
+    // committing the merges
+    pub(super) fn merge_fields<'a, I: Iterator<Item = &'a String>>(&self, uuids: I) -> Result<()> {
+        let mut entries = vec![];
+        for uuid in uuids {
+            let FieldMerge {
+                winner,
+                discarded,
+                merged,
+                ..
+            } = match self.field_merge(uuid)? {
+                Some(merge) if merge.diverging.is_empty() => merge,
+                _ => continue,
+            };
+            let result = self
+                .resolve_as(uuid, &winner)
+                .and_then(|_| self.update_object(uuid, merged));
+            if let Err(e) = result {
+                self.unstage()?;
+                return Err(e);
+            }
+            entries.push(json!({ "uuid": uuid, "revision": winner, "discarded": discarded, "custom": true, "reason": "field_merge" }));
+        }
+        if !entries.is_empty() {
+            let info = json!({ RESOLUTIONS_FIELD: entries });
+            if let Err(e) = self.commit_at(info.as_object().cloned(), now_millis()) {
+                self.unstage()?;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the JSON Pointers (relative to the object) of the fields of an object in conflict
+    /// whose value diverges among the conflicting revisions (with respect to their common
+    /// ancestor): nested objects are compared field by field, so that only the innermost
+    /// diverging values are returned. The other fields can be merged, see
+    /// [crate::builder::MeldaBuilder::field_level_conflicts]. The set is empty if the object is not in
+    /// conflict or if one of the conflicting revisions deletes the object
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - The uuid of the object
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::{Melda, ResolutionChoice};
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// replica.update(json!({ "items\u{266D}" : [ { "_id" : "1", "title" : "Buy milk", "due" : "monday", "done" : false } ] }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let open = |replica: &Melda| {
+    ///     let other = Melda::builder().url("memory://").field_level_conflicts(true).open().unwrap();
+    ///     other.meld(replica).unwrap();
+    ///     other.refresh().unwrap();
+    ///     other
+    /// };
+    /// let (alice, bob) = (open(&replica), open(&replica));
+    /// alice.set("/items\u{266D}/0/title", json!("Buy oat milk")).unwrap();
+    /// alice.commit(None).unwrap();
+    /// bob.set("/items\u{266D}/0/due", json!("tuesday")).unwrap();
+    /// bob.commit(None).unwrap();
+    /// alice.meld(&bob).unwrap();
+    /// alice.refresh().unwrap();
+    /// // Different fields changed: both changes are kept
+    /// assert!(alice.in_conflict().is_empty());
+    /// assert_eq!(alice.get("/items\u{266D}/0").unwrap().unwrap(), json!({ "_id" : "1", "title" : "Buy oat milk", "due" : "tuesday", "done" : false }));
+    /// assert_eq!(alice.resolutions(Some("1")).unwrap()[0].reason.as_deref(), Some("field_merge"));
+    /// // The same field changed: the object is in conflict
+    /// let carol = open(&alice);
+    /// alice.set("/items\u{266D}/0/done", json!(true)).unwrap();
+    /// alice.set("/items\u{266D}/0/title", json!("Buy milk")).unwrap();
+    /// alice.commit(None).unwrap();
+    /// carol.set("/items\u{266D}/0/title", json!("Buy soy milk")).unwrap();
+    /// carol.commit(None).unwrap();
+    /// carol.meld(&alice).unwrap();
+    /// carol.refresh().unwrap();
+    /// assert!(carol.in_conflict().contains("1"));
+    /// assert_eq!(carol.conflicting_fields("1").unwrap(), vec!["/title".to_string()].into_iter().collect());
+    /// carol.resolve("1", ResolutionChoice::KeepWinner).unwrap();
+    /// // Nested objects are merged field by field
+    /// let dave = open(&carol);
+    /// dave.set("/items\u{266D}/0/settings", json!({ "theme" : { "colors" : "dark", "font" : "mono" } })).unwrap();
+    /// dave.commit(None).unwrap();
+    /// let erin = open(&dave);
+    /// dave.set("/items\u{266D}/0/settings/theme/colors", json!("light")).unwrap();
+    /// dave.commit(None).unwrap();
+    /// erin.set("/items\u{266D}/0/settings/theme/font", json!("sans")).unwrap();
+    /// erin.commit(None).unwrap();
+    /// erin.meld(&dave).unwrap();
+    /// erin.refresh().unwrap();
+    /// assert!(!erin.in_conflict().contains("1"));
+    /// assert_eq!(erin.get("/items\u{266D}/0/settings").unwrap().unwrap(), json!({ "theme" : { "colors" : "light", "font" : "sans" } }));
+    /// ```
+    pub fn conflicting_fields(&self, uuid: &str) -> Result<BTreeSet<String>> {
+        Ok(self
+            .field_merge(uuid)?
+            .map(|merge| merge.diverging)
+            .unwrap_or_default())
+    }
+
+    /// Returns the outcome of the automatic resolution of the conflicts performed by the last
+    /// refresh which applied new blocks (see [crate::builder::MeldaBuilder::conflict_policy])
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::{Melda, ConflictPolicy};
+    /// use serde_json::json;
+    /// let policy = ConflictPolicy::AuthorPriority(vec!["Bob".to_string(), "Alice".to_string()]);
+    /// let replica = Melda::builder().url("memory://").conflict_policy(policy).open().unwrap();
+    /// replica.create_object("task", json!({ "title" : "Call Alice" }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(Some(json!({ "author" : "Alice" }).as_object().unwrap().clone())).unwrap();
+    /// let other = Melda::new_from_url("memory://").unwrap();
+    /// other.create_object("task", json!({ "title" : "Call Bob" }).as_object().unwrap().clone()).unwrap();
+    /// other.commit(Some(json!({ "author" : "Bob" }).as_object().unwrap().clone())).unwrap();
+    /// replica.meld(&other).unwrap();
+    /// replica.refresh().unwrap();
+    /// let report = replica.auto_resolution_report();
+    /// assert!(report.resolved.contains_key("task"));
+    /// assert!(report.unresolved.is_empty());
+    /// assert!(replica.in_conflict().is_empty());
+    /// assert_eq!(replica.get_value("task", None).unwrap().get("title").unwrap(), "Call Bob");
+    /// assert_eq!(replica.resolutions(Some("task")).unwrap()[0].reason.as_deref(), Some("conflict_policy"));
+    /// // A custom policy leaves the conflicts it cannot decide unresolved
+    /// let policy = ConflictPolicy::custom(|_, _| None);
+    /// let replica = Melda::builder().url("memory://").conflict_policy(policy).open().unwrap();
+    /// replica.create_object("task", json!({ "title" : "Call Alice" }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// replica.meld(&other).unwrap();
+    /// replica.refresh().unwrap();
+    /// assert!(replica.auto_resolution_report().unresolved.contains("task"));
+    /// assert!(replica.in_conflict().contains("task"));
+    /// ```
+    pub fn auto_resolution_report(&self) -> AutoResolutionReport {
+        self.auto_resolution.lock().unwrap().clone()
+    }
+
+    /// Resolves a conflict by choosing the new winning revision. All other conflicting revisions are marked as resolved.
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - The uuid of the object
+    /// * `winner` - The revision that is to be made the winner
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);  
+    /// assert!(replica.get_all_objects().contains("myobject"));
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner);
+    /// let block_id = replica.commit(None).unwrap().unwrap();
+    /// let adapter2 : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter2 = Arc::new(RwLock::new(adapter2));
+    /// let replica2 = Melda::new(adapter2.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "another" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica2.create_object("myobject", object);
+    /// let winner2 = replica2.get_winner("myobject").unwrap();
+    /// assert_eq!("1-255cc6219e48f526c04bc5af86439c34e4fe39fcdc611758ff833a2ff80583f0", winner2);
+    /// let anchors2 = replica2.commit(None).unwrap().unwrap();
+    /// replica2.meld(&replica);
+    /// replica2.refresh();
+    /// let conflicting = replica2.in_conflict();
+    /// assert!(conflicting.contains("myobject"));
+    /// let revs = replica2.get_conflicting("myobject").unwrap();
+    /// assert!(revs.contains(&winner2));
+    /// let winner3 = replica2.get_winner("myobject").unwrap();
+    /// assert_eq!("1-e8e7db1ed2e2e9b7360c9216b8f21353e37ec0365c3d95c51a1302759da9e196", winner3);    
+    /// replica2.resolve_as("myobject", &winner2);
+    /// let winner = replica2.get_winner("myobject").unwrap();
+    /// assert_eq!("2-255cc6219e48f526c04bc5af86439c34e4fe39fcdc611758ff833a2ff80583f0_e5d1d20", winner);
+    /// assert!(replica2.in_conflict().is_empty());
+    pub fn resolve_as(&self, uuid: &str, winner: &str) -> Result<String> {
+        let result = self.stage_resolution(uuid, winner);
+        if result.is_ok() {
+            self.record(|| json!({ "op": "resolve", "uuid": uuid, "winner": winner }));
+        }
+        result
+    }
+
+    /// Resolves the conflicts of an object and commits the resolution as a new block: the
+    /// other replicas no longer see the conflict once they meld it. Unlike [Melda::resolve_as],
+    /// the value can be replaced by a custom value, which becomes the winner. The stage must be
+    /// empty. Returns the new winning revision
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - The uuid of the object
+    /// * `choice` - The resolution
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::{Melda, ResolutionChoice};
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// replica.update(json!({ "items\u{266D}" : [ { "_id" : "1", "title" : "Buy milk" } ] }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let other = Melda::new_from_url("memory://").unwrap();
+    /// other.meld(&replica).unwrap();
+    /// other.refresh().unwrap();
+    /// replica.set("/items\u{266D}/0/title", json!("Buy oat milk")).unwrap();
+    /// replica.commit(None).unwrap();
+    /// other.set("/items\u{266D}/0/title", json!("Buy soy milk")).unwrap();
+    /// other.commit(None).unwrap();
+    /// other.meld(&replica).unwrap();
+    /// other.refresh().unwrap();
+    /// assert!(other.in_conflict().contains("1"));
+    /// let merged = json!({ "title" : "Buy oat and soy milk" });
+    /// other.resolve("1", ResolutionChoice::Custom(merged)).unwrap();
+    /// assert!(other.in_conflict().is_empty());
+    /// assert!(!other.has_staging());
+    /// assert_eq!(other.get("/items\u{266D}/0/title").unwrap().unwrap(), "Buy oat and soy milk");
+    /// // The resolution is committed
+    /// replica.meld(&other).unwrap();
+    /// replica.refresh().unwrap();
+    /// assert!(replica.in_conflict().is_empty());
+    /// assert_eq!(replica.get("/items\u{266D}/0/title").unwrap().unwrap(), "Buy oat and soy milk");
+    /// assert!(other.resolve("1", ResolutionChoice::KeepWinner).is_err());
+    /// ```
+    pub fn resolve(&self, uuid: &str, choice: ResolutionChoice) -> Result<String> {
+        self.resolve_with_note(uuid, choice, ResolutionNote::default())
+    }
+
+    /// Resolves the conflicts of an object like [Melda::resolve], recording who resolved the
+    /// conflict and why in the history (see [Melda::resolutions])
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - The uuid of the object
+    /// * `choice` - The resolution
+    /// * `note` - The author of the resolution and the reason of the choice
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::{Melda, ResolutionChoice, ResolutionNote};
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// replica.update(json!({ "items\u{266D}" : [ { "_id" : "1", "title" : "Buy milk" } ] }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let other = Melda::new_from_url("memory://").unwrap();
+    /// other.meld(&replica).unwrap();
+    /// other.refresh().unwrap();
+    /// replica.set("/items\u{266D}/0/title", json!("Buy oat milk")).unwrap();
+    /// replica.commit(None).unwrap();
+    /// other.set("/items\u{266D}/0/title", json!("Buy soy milk")).unwrap();
+    /// other.commit(None).unwrap();
+    /// other.meld(&replica).unwrap();
+    /// other.refresh().unwrap();
+    /// let discarded = other.get_conflicting("1").unwrap();
+    /// let note = ResolutionNote { author: Some("Alice".to_string()), reason: Some("Bob is lactose intolerant".to_string()) };
+    /// other.resolve_with_note("1", ResolutionChoice::KeepWinner, note).unwrap();
+    /// // The resolution is part of the history of every replica
+    /// replica.meld(&other).unwrap();
+    /// replica.refresh().unwrap();
+    /// let resolutions = replica.resolutions(Some("1")).unwrap();
+    /// assert_eq!(resolutions.len(), 1);
+    /// assert_eq!(resolutions[0].author.as_deref(), Some("Alice"));
+    /// assert_eq!(resolutions[0].reason.as_deref(), Some("Bob is lactose intolerant"));
+    /// assert_eq!(resolutions[0].discarded, discarded.into_iter().collect::<Vec<_>>());
+    /// assert!(replica.resolutions(Some("2")).unwrap().is_empty());
+    /// ```
+    pub fn resolve_with_note(
+        &self,
+        uuid: &str,
+        choice: ResolutionChoice,
+        note: ResolutionNote,
+    ) -> Result<String> {
+        if self.has_staging() {
+            bail!(MeldaError::StageNotEmpty)
+        }
+        let winner = match &choice {
+            ResolutionChoice::KeepRevision(revision) => revision.clone(),
+            _ => self.get_winner(uuid)?,
+        };
+        if Revision::from(&winner).is_err() {
+            bail!("invalid_winner_revision");
+        }
+        let mut discarded = self.get_conflicting(uuid)?;
+        discarded.insert(self.get_winner(uuid)?);
+        discarded.remove(&winner);
+        let mut entry = json!({ "uuid": uuid, "revision": winner, "discarded": discarded });
+        if let Some(reason) = &note.reason {
+            entry["reason"] = Value::from(reason.clone());
+        }
+        if let ResolutionChoice::Custom(_) = &choice {
+            entry["custom"] = Value::from(true);
+        }
+        let mut info = json!({ RESOLUTIONS_FIELD: [entry] });
+        if let Some(author) = note.author {
+            info[COMMIT_AUTHOR_FIELD] = Value::from(author);
+        }
+        let result = self.resolve_as(uuid, &winner).and_then(|winner| {
+            if let ResolutionChoice::Custom(value) = choice {
+                let mut object = match value {
+                    Value::Object(object) => object,
+                    _ => bail!("invalid_resolution_value"),
+                };
+                // Nested collections are kept
+                object.retain(|k, _| !is_flattened_field(k));
+                let current = self
+                    .load_flattened_object(uuid)?
+                    .ok_or_else(|| anyhow!("unknown_document"))?;
+                object.extend(current.into_iter().filter(|(k, _)| is_flattened_field(k)));
+                self.update_object(uuid, object)?;
+            }
+            self.commit(info.as_object().cloned())?;
+            Ok(winner)
+        });
+        if result.is_err() {
+            self.unstage()?;
+        }
+        result?;
+        self.get_winner(uuid)
+    }
+
+    /// Returns the resolutions of conflicts committed with [Melda::resolve] (or by the conflict
+    /// policy, with the *conflict_policy* reason) found in the history of the applied blocks,
+    /// ordered by generation
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - Only returns the resolutions of the given object (if any)
+    pub fn resolutions(&self, uuid: Option<&str>) -> Result<Vec<ResolutionRecord>> {
+        let generations = self.block_generations();
+        let mut blocks: Vec<Block> = self
+            .blocks
+            .read()
+            .unwrap()
+            .values()
+            .map(|block| block.read().unwrap())
+            .filter(|block| block.status == Status::ValidAndApplied)
+            .filter(|block| {
+                block
+                    .info
+                    .as_ref()
+                    .is_some_and(|info| info.contains_key(RESOLUTIONS_FIELD))
+            })
+            .map(|block| block.clone())
+            .collect();
+        blocks.sort_by_key(|block| (generations.get(&block.id).copied(), block.id.clone()));
+        let mut records = vec![];
+        for block in blocks {
+            let info = block.info.unwrap_or_default();
+            let author = info
+                .get(COMMIT_AUTHOR_FIELD)
+                .and_then(Value::as_str)
+                .map(str::to_string);
+            let entries = info
+                .get(RESOLUTIONS_FIELD)
+                .and_then(Value::as_array)
+                .cloned()
+                .unwrap_or_default();
+            for entry in entries {
+                let string = |field: &str| entry.get(field).and_then(Value::as_str);
+                let (object, revision) = match (string("uuid"), string("revision")) {
+                    (Some(object), Some(revision)) => (object, revision),
+                    _ => continue,
+                };
+                if uuid.is_some_and(|uuid| uuid != object) {
+                    continue;
+                }
+                records.push(ResolutionRecord {
+                    uuid: object.to_string(),
+                    revision: revision.to_string(),
+                    discarded: entry
+                        .get("discarded")
+                        .and_then(Value::as_array)
+                        .map(|d| {
+                            d.iter()
+                                .filter_map(Value::as_str)
+                                .map(str::to_string)
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                    custom: entry.get("custom").and_then(Value::as_bool) == Some(true),
+                    author: author.clone(),
+                    reason: string("reason").map(str::to_string),
+                    block: block.id.clone(),
+                    timestamp: block.timestamp,
+                });
+            }
+        }
+        Ok(records)
+    }
+
+    // Stages the resolution of the conflicts of an object
+    fn stage_resolution(&self, uuid: &str, winner: &str) -> Result<String> {
+        {
+            let winner = Revision::from(winner).expect("invalid_revision_string");
+            let docs_r = self
+                .documents
+                .read()
+                .expect("failed_to_acquire_documents_for_reading");
+            let rt = docs_r
+                .get(uuid)
+                .ok_or_else(|| anyhow!("unknown_document"))?;
+            let rt_r = rt
+                .lock()
+                .expect("failed_to_acquire_revision_tree_for_reading");
+            {
+                let leafs = rt_r.get_leafs();
+                // We can only resolve to a status revision
+                if !leafs.contains(&winner) {
+                    bail!("invalid_winner_revision");
+                }
+                // If there is only one leaf nothing needs to be resolved
+                if leafs.len() <= 1 {
+                    bail!("not_in_conflict");
+                }
+            }
+            // Update the winner to ensure that we do not change the view
+            let data_r = self.data.read().expect("cannot_acquire_data_for_reading");
+            //let merged = data_r.read_object(&winner)?;
+            let merged = self.read_object_at_revision(uuid, &rt_r, &winner)?;
+            drop(winner);
+            drop(rt_r);
+            drop(data_r);
+            drop(docs_r);
+            self.update_object(uuid, merged)?;
+        }
+        let docs_r = self
+            .documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading");
+        let rt = docs_r
+            .get(uuid)
+            .ok_or_else(|| anyhow!("unknown_document"))?;
+        let rt_r = rt
+            .lock()
+            .expect("failed_to_acquire_revision_tree_for_reading");
+        let winner = rt_r
+            .get_winner()
+            .expect("revision_tree_invalid_state")
+            .clone();
+        drop(rt_r);
+        drop(docs_r);
+        // Seal all other revisions as resolved
+        let mut docs_w = self
+            .documents
+            .write()
+            .expect("failed_to_acquire_documents_for_writing");
+        let rt = docs_w
+            .get_mut(uuid)
+            .ok_or_else(|| anyhow!("unknown_document"))?;
+        let rt_r = rt
+            .get_mut()
+            .expect("failed_to_acquire_revision_tree_for_reading");
+        let leafs: Vec<Revision> = rt_r.get_leafs().iter().map(|r| (*r).clone()).collect();
+        for r in leafs {
+            if r != winner {
+                let resolved = Revision::new_resolved(&r);
+                let rt_w = rt
+                    .get_mut()
+                    .expect("failed_to_acquire_revision_tree_for_writing");
+                rt_w.add(resolved.clone(), Some(r.clone()), true);
+                self.changed_objects([uuid]);
+            }
+        }
+        Ok(winner.to_string())
+    }
+
+    // Returns the given objects which are in conflict (array descriptors are ignored, since
+    // concurrent changes to arrays are merged)
+    pub(super) fn conflicting(&self, uuids: &BTreeSet<String>) -> BTreeSet<String> {
+        let docs_r = self.documents.read().unwrap();
+        uuids
+            .iter()
+            .filter(|uuid| !is_array_descriptor(uuid))
+            .filter(|uuid| {
+                docs_r
+                    .get(*uuid)
+                    .is_some_and(|rt| rt.lock().unwrap().get_leafs().len() > 1)
+            })
+            .cloned()
+            .collect()
+    }
+}
@@ -0,0 +1,400 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use super::Melda;
+use crate::constants::ROOT_ID;
+use crate::error::MeldaError;
+use crate::utils::digest_string;
+use anyhow::{anyhow, bail, Result};
+use serde_json::{Map, Value};
+use std::collections::{BTreeSet, HashSet};
+use std::path::Path;
+
+impl Melda {
+    /// Exports all committed items (delta blocks and data packs) as a single bundle, which can be
+    /// transferred to another replica and loaded with [Melda::import_bundle]
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+    /// replica.update(json!({ "key" : "value" }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let bundle = replica.export_bundle().unwrap();
+    /// let replica2 = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+    /// assert!(!replica2.import_bundle(&bundle).unwrap().is_empty());
+    /// assert!(replica2.import_bundle(&bundle).unwrap().is_empty());
+    /// replica2.refresh().unwrap();
+    /// assert_eq!(replica2.read(None).unwrap().get("key").unwrap(), "value");
+    /// ```
+    pub fn export_bundle(&self) -> Result<Vec<u8>> {
+        // The exported blocks are no longer local (and cannot be squashed)
+        self.local_blocks.lock().unwrap().clear();
+        let data = self.data.read().unwrap();
+        let mut bundle = vec![];
+        for i in data.list_raw_items("")? {
+            let item = data.read_raw_item(&i, 0, 0)?;
+            bundle.extend_from_slice(&(i.len() as u32).to_be_bytes());
+            bundle.extend_from_slice(i.as_bytes());
+            bundle.extend_from_slice(&(item.len() as u64).to_be_bytes());
+            bundle.extend_from_slice(&item);
+        }
+        Ok(bundle)
+    }
+
+    /// Imports the items of a bundle produced by [Melda::export_bundle]. Like [Melda::meld], the
+    /// state is not changed until the next refresh. Returns the imported items
+    ///
+    /// # Arguments
+    ///
+    /// * `bundle` - The content of the bundle
+    pub fn import_bundle(&self, bundle: &[u8]) -> Result<Vec<String>> {
+        let mut items = vec![];
+        let mut rest = bundle;
+        while !rest.is_empty() {
+            let (key, tail) = split_bundle_field(rest, 4)?;
+            let key = std::str::from_utf8(key).map_err(|_| anyhow!("invalid_bundle"))?;
+            let (item, tail) = split_bundle_field(tail, 8)?;
+            items.push((key.to_string(), item));
+            rest = tail;
+        }
+        let mut result = vec![];
+        let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
+        let this_items: HashSet<String> = data.list_raw_items("")?.into_iter().collect();
+        for (key, item) in items {
+            if !this_items.contains(&key) {
+                data.write_raw_item(&key, item)?;
+                result.push(key);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Exports text and array fields as a [Yjs](https://yjs.dev) update, so that editor
+    /// frontends based on Yjs can render them: each field is identified by a JSON Pointer and
+    /// becomes a root shared type named after the pointer (without the leading slash), a
+    /// Y.Text for strings or a Y.Array (holding JSON values) for arrays. The update describes
+    /// the current state and is meant to be applied to an empty Y.Doc; its items are made by a
+    /// client derived from the identifier of the replica (see [Melda::replica_id])
+    ///
+    /// # Arguments
+    ///
+    /// * `fields` - JSON Pointers of the fields to export
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// replica.update(json!({ "title" : "Notes", "tags" : [ "work" ], "count" : 1 }).as_object().unwrap().clone()).unwrap();
+    /// let update = replica.export_yjs(&["/title", "/tags"]).unwrap();
+    /// assert_eq!(update[0], 1);
+    /// assert!(update.windows(5).any(|w| w == b"Notes"));
+    /// assert!(replica.export_yjs(&["/count"]).is_err());
+    /// assert!(replica.export_yjs(&["/missing"]).is_err());
+    /// ```
+    pub fn export_yjs(&self, fields: &[&str]) -> Result<Vec<u8>> {
+        let mut types = vec![];
+        for field in fields {
+            let value = self.get(field)?.ok_or_else(|| anyhow!("path_not_found"))?;
+            let name = field.strip_prefix('/').unwrap_or(field);
+            types.push((name.to_string(), value));
+        }
+        // Yjs clients are identified by 32-bit integers
+        let digest = blake3::hash(self.replica_id()?.as_bytes());
+        let mut client = [0u8; 4];
+        client.copy_from_slice(&digest.as_bytes()[..4]);
+        crate::yjs::encode_update(u64::from(u32::from_be_bytes(client)), &types)
+    }
+
+    /// Imports a directory of JSON files in a single commit, so that an existing dataset can be
+    /// brought under version control: each sub-directory becomes a collection named after it,
+    /// whose elements are the objects held by its JSON files (identified by the file names,
+    /// without the *.json* extension), and each JSON file directly in the directory becomes a
+    /// field named after the file. Other files are ignored. Imported fields and collections
+    /// replace the existing ones, whereas other fields are preserved. The stage must be empty.
+    /// Returns the committed block (None if nothing changed)
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory
+    /// * `information` - Optional information attached to the block
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// let dir = std::env::temp_dir().join("melda_import_tree_doctest");
+    /// let _ = std::fs::remove_dir_all(&dir);
+    /// std::fs::create_dir_all(dir.join("users")).unwrap();
+    /// std::fs::write(dir.join("users/alice.json"), r#"{ "name" : "Alice" }"#).unwrap();
+    /// std::fs::write(dir.join("users/bob.json"), r#"{ "_id" : "bob", "name" : "Bob" }"#).unwrap();
+    /// std::fs::write(dir.join("settings.json"), r#"{ "theme" : "dark" }"#).unwrap();
+    /// std::fs::write(dir.join("README.md"), "Ignored").unwrap();
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// replica.update(json!({ "title" : "Team" }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// assert!(replica.import_tree(&dir, None).unwrap().is_some());
+    /// let content = replica.read(None).unwrap();
+    /// assert_eq!(content["users\u{266D}"], json!([ { "_id" : "alice", "name" : "Alice" }, { "_id" : "bob", "name" : "Bob" } ]));
+    /// assert_eq!(content["settings"], json!({ "theme" : "dark" }));
+    /// assert_eq!(content["title"], "Team");
+    /// assert!(replica.import_tree(&dir, None).unwrap().is_none());
+    /// std::fs::write(dir.join("users/carol.json"), r#"{ "_id" : "dave" }"#).unwrap();
+    /// assert!(replica.import_tree(&dir, None).is_err());
+    /// std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn import_tree(
+        &self,
+        dir: &Path,
+        information: Option<Map<String, Value>>,
+    ) -> Result<Option<BTreeSet<String>>> {
+        if self.has_staging() {
+            bail!(MeldaError::StageNotEmpty);
+        }
+        let imported = crate::filetree::read_dataset(dir)?;
+        let mut state = match self.get("")? {
+            Some(Value::Object(state)) => state,
+            _ => Map::new(),
+        };
+        state.extend(imported);
+        self.update(state)?;
+        self.commit(information)
+    }
+
+    /// Reads the current state as canonical JSON (see [crate::canonicaljson]): replicas in the
+    /// same state produce byte-identical exports
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - Optional root object identifier
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// let alice = Melda::new_from_url("memory://").unwrap();
+    /// let bob = Melda::new_from_url("memory://").unwrap();
+    /// alice.update(json!({ "title" : "Groceries", "count" : 2.0 }).as_object().unwrap().clone()).unwrap();
+    /// alice.commit(None).unwrap();
+    /// bob.meld(&alice).unwrap();
+    /// bob.refresh().unwrap();
+    /// assert_eq!(alice.read_canonical(None).unwrap(), "{\"_id\":\"\u{221A}\",\"count\":2,\"title\":\"Groceries\"}");
+    /// assert_eq!(alice.read_canonical(None).unwrap(), bob.read_canonical(None).unwrap());
+    /// assert_eq!(alice.canonical_digest(None).unwrap(), bob.canonical_digest(None).unwrap());
+    /// ```
+    pub fn read_canonical(&self, root: Option<&str>) -> Result<String> {
+        let content = Value::from(self.read(root)?);
+        Ok(crate::canonicaljson::to_canonical_string(&content))
+    }
+
+    /// Returns the SHA-256 digest (as hexadecimal string) of the canonical JSON export of the
+    /// current state (see [Melda::read_canonical]), which can be compared to check whether
+    /// replicas converged
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - Optional root object identifier
+    pub fn canonical_digest(&self, root: Option<&str>) -> Result<String> {
+        Ok(digest_string(&self.read_canonical(root)?))
+    }
+
+    /// Serializes the current state as JSON directly into a writer: objects are reconstructed
+    /// and written one at a time, so that the whole document is never held in memory.
+    /// The output is the same as the serialization of [Melda::read].
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - The destination of the JSON output
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "title" : "Todo \"list\"", "tasks\u{266D}" : [ { "_id" : "1", "key" : "alpha", "sub\u{266D}" : { "a" : [1, 2] } }, { "_id" : "2", "key" : "!beta" } ] }).as_object().unwrap().clone();
+    /// replica.update(object).unwrap();
+    /// let mut output = vec![];
+    /// replica.read_to_writer(&mut output).unwrap();
+    /// let expected = serde_json::to_string(&replica.read(None).unwrap()).unwrap();
+    /// assert_eq!(String::from_utf8(output).unwrap(), expected);
+    /// ```
+    pub fn read_to_writer<W: std::io::Write>(&self, mut writer: W) -> Result<()> {
+        let root = self
+            .load_flattened_object(ROOT_ID)?
+            .ok_or_else(|| anyhow!("no_root"))?;
+        self.write_flattened_object(&mut writer, ROOT_ID, root, &mut BTreeSet::new())?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the elements of a collection as newline-delimited JSON (one object per line), in
+    /// merge order. Elements are reconstructed and written one at a time (see
+    /// [Melda::iter_collection]). Returns the number of written elements
+    ///
+    /// # Arguments
+    ///
+    /// * `collection` - The field of the root object holding the collection
+    /// * `columns` - The fields of the elements to write (all fields if empty): a column
+    ///   starting with a slash is a JSON Pointer to a nested value
+    /// * `writer` - The destination
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// replica.update(json!({ "tasks\u{266D}" : [
+    ///     { "_id" : "1", "title" : "Buy milk", "meta" : { "priority" : 2 } },
+    ///     { "_id" : "2", "title" : "Call Bob" }
+    /// ] }).as_object().unwrap().clone()).unwrap();
+    /// let mut output = vec![];
+    /// assert_eq!(replica.export_ndjson("tasks\u{266D}", &["title", "/meta/priority"], &mut output).unwrap(), 2);
+    /// assert_eq!(String::from_utf8(output).unwrap(), "{\"/meta/priority\":2,\"title\":\"Buy milk\"}\n{\"title\":\"Call Bob\"}\n");
+    /// ```
+    pub fn export_ndjson<W: std::io::Write>(
+        &self,
+        collection: &str,
+        columns: &[&str],
+        mut writer: W,
+    ) -> Result<usize> {
+        let mut count = 0;
+        for element in self.iter_collection(collection)? {
+            let (_, element) = element?;
+            let row = if columns.is_empty() {
+                element
+            } else {
+                Value::from(
+                    columns
+                        .iter()
+                        .filter_map(|c| {
+                            select_column(&element, c).map(|v| (c.to_string(), v.clone()))
+                        })
+                        .collect::<Map<String, Value>>(),
+                )
+            };
+            serde_json::to_writer(&mut writer, &row)?;
+            writer.write_all(b"\n")?;
+            count += 1;
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Writes the elements of a collection as CSV (RFC 4180), with a header row holding the
+    /// names of the columns. Strings are written as they are, missing values and null as empty
+    /// cells, and objects and arrays as JSON. If no columns are given, the columns are the
+    /// fields of the elements, in order of appearance (which requires reading the collection
+    /// twice). Returns the number of written elements
+    ///
+    /// # Arguments
+    ///
+    /// * `collection` - The field of the root object holding the collection
+    /// * `columns` - The fields of the elements to write (all fields if empty): a column
+    ///   starting with a slash is a JSON Pointer to a nested value
+    /// * `writer` - The destination
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// replica.update(json!({ "tasks\u{266D}" : [
+    ///     { "_id" : "1", "title" : "Buy milk, eggs", "done" : false },
+    ///     { "_id" : "2", "title" : "Call \"Bob\"", "tags" : [ "work" ] }
+    /// ] }).as_object().unwrap().clone()).unwrap();
+    /// let mut output = vec![];
+    /// replica.export_csv("tasks\u{266D}", &["_id", "title", "done"], &mut output).unwrap();
+    /// assert_eq!(String::from_utf8(output).unwrap(), "_id,title,done\r\n1,\"Buy milk, eggs\",false\r\n2,\"Call \"\"Bob\"\"\",\r\n");
+    /// let mut output = vec![];
+    /// replica.export_csv("tasks\u{266D}", &[], &mut output).unwrap();
+    /// assert!(String::from_utf8(output).unwrap().starts_with("_id,done,title,tags\r\n"));
+    /// ```
+    pub fn export_csv<W: std::io::Write>(
+        &self,
+        collection: &str,
+        columns: &[&str],
+        mut writer: W,
+    ) -> Result<usize> {
+        let columns: Vec<String> = if columns.is_empty() {
+            let mut fields = vec![];
+            for element in self.iter_collection(collection)? {
+                if let (_, Value::Object(element)) = element? {
+                    for key in element.keys() {
+                        if !fields.contains(key) {
+                            fields.push(key.clone());
+                        }
+                    }
+                }
+            }
+            fields
+        } else {
+            columns.iter().map(|c| c.to_string()).collect()
+        };
+        let header: Vec<String> = columns.iter().map(|c| csv_field(c)).collect();
+        write!(writer, "{}\r\n", header.join(","))?;
+        let mut count = 0;
+        for element in self.iter_collection(collection)? {
+            let (_, element) = element?;
+            let row: Vec<String> = columns
+                .iter()
+                .map(|c| match select_column(&element, c) {
+                    None | Some(Value::Null) => String::new(),
+                    Some(Value::String(s)) => csv_field(s),
+                    Some(v) => csv_field(&v.to_string()),
+                })
+                .collect();
+            write!(writer, "{}\r\n", row.join(","))?;
+            count += 1;
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+}
+
+/// Returns the value of a column of an exported element (a field, or a JSON Pointer if the
+/// column starts with a slash)
+fn select_column<'a>(element: &'a Value, column: &str) -> Option<&'a Value> {
+    if column.starts_with('/') {
+        element.pointer(column)
+    } else {
+        element.get(column)
+    }
+}
+
+/// Quotes a CSV field if it contains separators, quotes or line breaks
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Splits a length-prefixed field (the length is a big-endian integer of the given size) of a bundle
+fn split_bundle_field(bundle: &[u8], size: usize) -> Result<(&[u8], &[u8])> {
+    if bundle.len() < size {
+        bail!("invalid_bundle");
+    }
+    let (length, rest) = bundle.split_at(size);
+    let length = length.iter().fold(0u64, |l, b| (l << 8) | *b as u64) as usize;
+    if rest.len() < length {
+        bail!("invalid_bundle");
+    }
+    Ok(rest.split_at(length))
+}
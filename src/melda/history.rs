@@ -0,0 +1,972 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use super::{change_kind, Change, ChangeKind, Melda, Status};
+use crate::commitinfo::CommitInfo;
+use crate::constants::{
+    ANNOTATION_BLOCK_FIELD, ANNOTATION_EXTENSION, ANNOTATION_KEY_FIELD,
+    ANNOTATION_SUPERSEDES_FIELD, ANNOTATION_VALUE_FIELD, PACK_EXTENSION, ROOT_ID, TIMESTAMP_FIELD,
+};
+use crate::error::MeldaError;
+use crate::revision::Revision;
+use crate::utils::{digest_bytes_with, now_millis, parse_json_pointer, pointer_child};
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::Bound;
+
+// Annotation of a block (annotations are immutable items: changing an annotation writes a
+// new item superseding the current ones)
+struct Annotation {
+    id: String,
+    block: String,
+    key: String,
+    value: Option<Value>,
+    timestamp: u64,
+    supersedes: Vec<String>,
+}
+
+/// Position in the change feed of a replica, see [Melda::changes]: the changes already consumed
+/// are those of the blocks reachable from the anchors of the cursor. Cursors are exchanged as
+/// strings (the anchors separated by commas, the empty string denotes the start of the feed)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct Cursor {
+    anchors: BTreeSet<String>,
+}
+
+impl Cursor {
+    /// Returns the cursor preceding all changes
+    pub fn start() -> Cursor {
+        Cursor::default()
+    }
+
+    /// Returns the cursor following the changes of the given blocks (and of their ancestors)
+    pub fn at(anchors: BTreeSet<String>) -> Cursor {
+        Cursor { anchors }
+    }
+
+    /// Returns the anchors of the cursor
+    pub fn anchors(&self) -> &BTreeSet<String> {
+        &self.anchors
+    }
+}
+
+impl std::fmt::Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let anchors: Vec<&str> = self.anchors.iter().map(String::as_str).collect();
+        write!(f, "{}", anchors.join(","))
+    }
+}
+
+impl std::str::FromStr for Cursor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Cursor> {
+        if s.is_empty() {
+            return Ok(Cursor::start());
+        }
+        let anchors: BTreeSet<String> = s.split(',').map(str::to_string).collect();
+        if anchors.iter().any(String::is_empty) {
+            bail!("invalid_cursor");
+        }
+        Ok(Cursor { anchors })
+    }
+}
+
+impl From<Cursor> for String {
+    fn from(cursor: Cursor) -> String {
+        cursor.to_string()
+    }
+}
+
+impl std::convert::TryFrom<String> for Cursor {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Cursor> {
+        s.parse()
+    }
+}
+
+/// Change of an object recorded in the change feed, see [Melda::changes]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChangeRecord {
+    /// Identifier of the object (array descriptors represent flattened arrays)
+    pub uuid: String,
+    /// Kind of change
+    pub kind: ChangeKind,
+    /// Revision introduced by the change
+    pub revision: String,
+    /// Block which introduced the change
+    pub block: String,
+    /// Information attached to the block
+    pub info: Option<Map<String, Value>>,
+}
+
+/// Revision of an object, see [Melda::object_history]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ObjectRevision {
+    /// The revision
+    pub revision: String,
+    /// The parent revision (None for the revision which created the object)
+    pub parent: Option<String>,
+    /// Kind of change
+    pub kind: ChangeKind,
+    /// Block which introduced the revision
+    pub block: String,
+    /// Information attached to the block (the timestamp defaults to the commit time recorded
+    /// by the block)
+    pub info: CommitInfo,
+}
+
+/// Changes returned by [Melda::changes], along with the cursor from which the feed resumes
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChangeFeed {
+    /// Changes, ordered so that the changes of a block follow those of its ancestors
+    pub changes: Vec<ChangeRecord>,
+    /// Cursor following the returned changes
+    pub cursor: Cursor,
+}
+
+/// Filter of the blocks listed by [Melda::list_blocks]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockFilter {
+    /// Only list the blocks committed at or after this time (in milliseconds since the Unix
+    /// epoch)
+    pub since: Option<u64>,
+    /// Only list the blocks committed before this time (in milliseconds since the Unix epoch)
+    pub until: Option<u64>,
+    /// Only list the blocks of this author
+    pub author: Option<String>,
+}
+
+/// Block listed by [Melda::list_blocks]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BlockSummary {
+    /// Identifier of the block
+    pub id: String,
+    /// Parents of the block
+    pub parents: Vec<String>,
+    /// Information attached to the block (the timestamp defaults to the commit time recorded
+    /// by the block)
+    pub info: CommitInfo,
+    /// True if the changes of the block have been applied
+    pub applied: bool,
+}
+
+/// Pack listed by [Melda::list_packs]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PackSummary {
+    /// Identifier of the pack
+    pub id: String,
+    /// Size of the pack (in bytes)
+    pub size: usize,
+}
+
+/// Page of a listing, see [Melda::list_blocks] and [Melda::list_packs]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Page<T> {
+    /// Listed items
+    pub items: Vec<T>,
+    /// Continuation token from which the listing resumes (None if the listing is complete)
+    pub next: Option<String>,
+}
+
+/// Causal relation between two revisions of an object or between two blocks, see
+/// [Melda::compare_revisions] and [Melda::compare_blocks]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Causality {
+    /// Both are the same
+    Same,
+    /// The first one happened before the second one (which was made with knowledge of the first)
+    Before,
+    /// The first one happened after the second one
+    After,
+    /// Neither was made with knowledge of the other
+    Concurrent,
+}
+
+impl Melda {
+    /// Returns the changes committed (or applied by a refresh) after the given cursor, along
+    /// with the cursor from which to resume. Changes are ordered by generation, so that the
+    /// changes of a block always follow those of its ancestors: external indexers can consume
+    /// a replica incrementally by persisting the returned cursor. Staged changes are not
+    /// returned. Cursors referencing blocks which are no longer available (for example after
+    /// a squash) are rejected with a [MeldaError::MissingBlock] error: the feed must then be
+    /// consumed again from the start
+    ///
+    /// # Arguments
+    ///
+    /// * `since` - The cursor (use [Cursor::start] to obtain all changes)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::{Melda, ChangeKind, Cursor};
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+    /// replica.update(json!({ "items\u{266D}" : [ { "_id" : "1" } ] }).as_object().unwrap().clone()).unwrap();
+    /// let info = json!({ "author" : "Alice" }).as_object().unwrap().clone();
+    /// replica.commit(Some(info)).unwrap();
+    /// let feed = replica.changes(&Cursor::start()).unwrap();
+    /// assert!(feed.changes.iter().any(|c| c.uuid == "1" && c.kind == ChangeKind::Created));
+    /// assert_eq!(feed.changes[0].info.as_ref().unwrap()["author"], "Alice");
+    /// // The cursor can be persisted as a string
+    /// let cursor: Cursor = feed.cursor.to_string().parse().unwrap();
+    /// assert!(replica.changes(&cursor).unwrap().changes.is_empty());
+    /// replica.update(json!({ "items\u{266D}" : [] }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let feed = replica.changes(&cursor).unwrap();
+    /// assert!(feed.changes.iter().any(|c| c.uuid == "1" && c.kind == ChangeKind::Deleted));
+    /// assert!(feed.changes.iter().all(|c| c.info.is_none()));
+    /// ```
+    pub fn changes(&self, since: &Cursor) -> Result<ChangeFeed> {
+        let generations = self.block_generations();
+        let blocks = self.blocks.read().unwrap();
+        // Blocks reachable from the cursor have already been consumed
+        let mut consumed = HashSet::<String>::new();
+        let mut stack = vec![];
+        for anchor in &since.anchors {
+            if !blocks.contains_key(anchor) {
+                bail!(MeldaError::MissingBlock { id: anchor.clone() });
+            }
+            stack.push(anchor.clone());
+        }
+        while let Some(bid) = stack.pop() {
+            if !consumed.insert(bid.clone()) {
+                continue;
+            }
+            let block = blocks[&bid].read().unwrap();
+            stack.extend(
+                block
+                    .parents
+                    .iter()
+                    .flatten()
+                    .filter(|p| blocks.contains_key(*p))
+                    .cloned(),
+            );
+        }
+        let applied: Vec<String> = blocks
+            .iter()
+            .filter(|(_, b)| b.read().unwrap().status == Status::ValidAndApplied)
+            .map(|(bid, _)| bid.clone())
+            .collect();
+        // The new cursor covers the applied blocks (as well as the consumed ones)
+        let mut anchors: BTreeSet<String> = applied.iter().chain(&consumed).cloned().collect();
+        for bid in &applied {
+            for parent in blocks[bid].read().unwrap().parents.iter().flatten() {
+                anchors.remove(parent);
+            }
+        }
+        drop(blocks);
+        let mut pending: Vec<String> = applied
+            .into_iter()
+            .filter(|bid| !consumed.contains(bid))
+            .collect();
+        pending.sort_by(|a, b| generations[a].cmp(&generations[b]).then_with(|| a.cmp(b)));
+        let mut changes = vec![];
+        for bid in pending {
+            let block = self.parse_raw_block(bid.clone(), self.fetch_raw_block(&bid)?)?;
+            for Change(uuid, rev, parent) in block.changes.into_iter().flatten() {
+                changes.push(ChangeRecord {
+                    uuid,
+                    kind: change_kind(&rev, parent.as_ref()),
+                    revision: rev.to_string(),
+                    block: bid.clone(),
+                    info: block.info.clone(),
+                });
+            }
+        }
+        Ok(ChangeFeed {
+            changes,
+            cursor: Cursor { anchors },
+        })
+    }
+
+    /// Returns the committed revisions of an object, along with the block (and the commit
+    /// information) which introduced each revision, ordered so that the revisions of a block
+    /// follow those of its ancestors. Concurrent revisions are all returned: the parent of each
+    /// revision tells the branch it belongs to. Staged revisions are not returned
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - The uuid of the object
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::{Melda, ChangeKind}, commitinfo::CommitInfo};
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// replica.update(json!({ "tasks\u{266D}" : [ { "_id" : "1", "title" : "Call Alice" }, { "_id" : "2", "title" : "Call Bob" } ] }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit_with(CommitInfo::new().author("Alice")).unwrap();
+    /// replica.set("/tasks\u{266D}/1/title", json!("Call Carol")).unwrap();
+    /// replica.commit_with(CommitInfo::new().author("Bob").message("Bob is away")).unwrap();
+    /// replica.set("/tasks\u{266D}/0/title", json!("Call Dave")).unwrap();
+    /// replica.commit_with(CommitInfo::new().author("Alice")).unwrap();
+    /// let history = replica.object_history("2").unwrap();
+    /// assert_eq!(history.len(), 2);
+    /// assert_eq!(history[0].kind, ChangeKind::Created);
+    /// assert_eq!(history[1].kind, ChangeKind::Updated);
+    /// assert_eq!(history[1].parent.as_ref(), Some(&history[0].revision));
+    /// assert_eq!(history[1].info.message.as_deref(), Some("Bob is away"));
+    /// assert_eq!(replica.get_value("2", Some(&history[0].revision)).unwrap()["title"], "Call Bob");
+    /// assert!(replica.object_history("3").unwrap().is_empty());
+    /// ```
+    pub fn object_history(&self, uuid: &str) -> Result<Vec<ObjectRevision>> {
+        let generations = self.block_generations();
+        let mut applied: Vec<String> = self
+            .blocks
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, b)| b.read().unwrap().status == Status::ValidAndApplied)
+            .map(|(bid, _)| bid.clone())
+            .collect();
+        applied.sort_by(|a, b| generations[a].cmp(&generations[b]).then_with(|| a.cmp(b)));
+        let mut history = vec![];
+        for bid in applied {
+            let block = self.parse_raw_block(bid.clone(), self.fetch_raw_block(&bid)?)?;
+            let mut info = None;
+            for Change(object, rev, parent) in block.changes.into_iter().flatten() {
+                if object != uuid {
+                    continue;
+                }
+                let info = match &info {
+                    Some(info) => info,
+                    None => {
+                        let mut parsed =
+                            CommitInfo::from_map_lenient(block.info.clone().unwrap_or_default());
+                        parsed.timestamp = parsed.timestamp.or(block.timestamp);
+                        info.insert(parsed)
+                    }
+                };
+                history.push(ObjectRevision {
+                    kind: if rev.is_resolved() {
+                        ChangeKind::Resolved
+                    } else {
+                        change_kind(&rev, parent.as_ref())
+                    },
+                    revision: rev.to_string(),
+                    parent: parent.map(|p| p.to_string()),
+                    block: bid.clone(),
+                    info: info.clone(),
+                });
+            }
+        }
+        Ok(history)
+    }
+
+    /// Returns the committed revisions which changed an object or a value, along with the block
+    /// (and the commit information) which introduced each revision, for example to find out who
+    /// changed an unexpected value. The target is either the uuid of an object (see
+    /// [Melda::object_history]) or a JSON Pointer, which is resolved against the current state
+    /// up to the nearest tracked object: then only the revisions which changed the value located
+    /// by the rest of the pointer are returned (resolutions, which do not change values, are not
+    /// returned)
+    ///
+    /// # Arguments
+    ///
+    /// * `target` - The uuid of an object, or a JSON Pointer (starting with '/')
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::{Melda, ChangeKind}, commitinfo::CommitInfo};
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// replica.update(json!({ "tasks\u{266D}" : [ { "_id" : "1", "title" : "Call Alice", "done" : false } ] }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit_with(CommitInfo::new().author("Alice")).unwrap();
+    /// replica.set("/tasks\u{266D}/0/title", json!("Call Bob")).unwrap();
+    /// replica.commit_with(CommitInfo::new().author("Bob")).unwrap();
+    /// replica.set("/tasks\u{266D}/0/done", json!(true)).unwrap();
+    /// replica.commit_with(CommitInfo::new().author("Carol")).unwrap();
+    /// let authors = |target: &str| -> Vec<String> {
+    ///     replica.who_changed(target).unwrap().into_iter().filter_map(|r| r.info.author).collect()
+    /// };
+    /// assert_eq!(authors("/tasks\u{266D}/0/title"), vec!["Alice", "Bob"]);
+    /// assert_eq!(authors("/tasks\u{266D}/0/done"), vec!["Alice", "Carol"]);
+    /// assert_eq!(authors("/tasks\u{266D}/0"), vec!["Alice", "Bob", "Carol"]);
+    /// assert_eq!(authors("1"), authors("/tasks\u{266D}/0"));
+    /// assert!(replica.who_changed("/tasks\u{266D}/1/title").is_err());
+    /// ```
+    pub fn who_changed(&self, target: &str) -> Result<Vec<ObjectRevision>> {
+        if !target.starts_with('/') {
+            return self.object_history(target);
+        }
+        let tokens = parse_json_pointer(target)?;
+        if self.load_flattened_object(ROOT_ID)?.is_none() {
+            bail!("path_not_found");
+        }
+        let (uuid, _, index) = self.find_tracked_ancestor(&tokens, tokens.len())?;
+        let field = &tokens[index..];
+        if field.is_empty() {
+            return self.object_history(&uuid);
+        }
+        let value_at = |revision: &str| -> Result<Option<Value>> {
+            if Revision::from(revision)?.is_deleted() {
+                return Ok(None);
+            }
+            let mut current = Value::from(self.get_value(&uuid, Some(revision))?);
+            for token in field {
+                match pointer_child(&current, token) {
+                    Some(child) => current = child.clone(),
+                    None => return Ok(None),
+                }
+            }
+            Ok(Some(current))
+        };
+        let mut values = HashMap::<String, Option<Value>>::new();
+        let mut changes = vec![];
+        for r in self.object_history(&uuid)? {
+            if r.kind == ChangeKind::Resolved {
+                continue;
+            }
+            let value = value_at(&r.revision)?;
+            let previous = match &r.parent {
+                Some(parent) => match values.get(parent) {
+                    Some(previous) => previous.clone(),
+                    None => value_at(parent)?,
+                },
+                None => None,
+            };
+            let changed = value != previous;
+            values.insert(r.revision.clone(), value);
+            if changed {
+                changes.push(r);
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Lists the blocks matching a filter one page at a time, ordered by identifier. The
+    /// listing starts after the given continuation token (or from the first block), and the
+    /// returned page holds the token from which to resume
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - The filter
+    /// * `token` - The continuation token returned with the previous page (None for the first page)
+    /// * `limit` - The maximum number of blocks in the page (which must not be zero)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::{Melda, BlockFilter}, commitinfo::CommitInfo};
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// for i in 0..5 {
+    ///     replica.update(json!({ "key" : i }).as_object().unwrap().clone()).unwrap();
+    ///     let author = if i % 2 == 0 { "Alice" } else { "Bob" };
+    ///     replica.commit_with(CommitInfo::new().author(author)).unwrap();
+    /// }
+    /// let filter = BlockFilter { author: Some("Alice".to_string()), ..BlockFilter::default() };
+    /// let first = replica.list_blocks(&filter, None, 2).unwrap();
+    /// assert_eq!(first.items.len(), 2);
+    /// let second = replica.list_blocks(&filter, first.next.as_deref(), 2).unwrap();
+    /// assert_eq!(second.items.len(), 1);
+    /// assert!(second.next.is_none());
+    /// assert!(first.items.iter().chain(&second.items).all(|b| b.info.author.as_deref() == Some("Alice")));
+    /// let future = BlockFilter { since: Some(u64::MAX), ..BlockFilter::default() };
+    /// assert!(replica.list_blocks(&future, None, 10).unwrap().items.is_empty());
+    /// assert!(replica.list_blocks(&filter, None, 0).is_err());
+    /// ```
+    pub fn list_blocks(
+        &self,
+        filter: &BlockFilter,
+        token: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<BlockSummary>> {
+        if limit == 0 {
+            bail!("invalid_page_limit");
+        }
+        let start = match token {
+            Some(token) => Bound::Excluded(token),
+            None => Bound::Unbounded,
+        };
+        let blocks = self.blocks.read().unwrap();
+        let mut page = Page {
+            items: vec![],
+            next: None,
+        };
+        for (id, block) in blocks.range::<str, _>((start, Bound::Unbounded)) {
+            let block = block.read().unwrap();
+            let mut info = CommitInfo::from_map_lenient(block.info.clone().unwrap_or_default());
+            info.timestamp = info.timestamp.or(block.timestamp);
+            let matches = filter
+                .since
+                .is_none_or(|since| info.timestamp.is_some_and(|t| t >= since))
+                && filter
+                    .until
+                    .is_none_or(|until| info.timestamp.is_some_and(|t| t < until))
+                && filter
+                    .author
+                    .as_ref()
+                    .is_none_or(|author| info.author.as_ref() == Some(author));
+            if !matches {
+                continue;
+            }
+            if page.items.len() == limit {
+                page.next = page.items.last().map(|b: &BlockSummary| b.id.clone());
+                break;
+            }
+            page.items.push(BlockSummary {
+                id: id.clone(),
+                parents: block.parents.iter().flatten().cloned().collect(),
+                info,
+                applied: block.status == Status::ValidAndApplied,
+            });
+        }
+        Ok(page)
+    }
+
+    /// Lists the data packs of the storage one page at a time, ordered by identifier (see
+    /// [Melda::list_blocks])
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The continuation token returned with the previous page (None for the first page)
+    /// * `limit` - The maximum number of packs in the page (which must not be zero)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// for i in 0..3 {
+    ///     replica.update(json!({ "key" : i }).as_object().unwrap().clone()).unwrap();
+    ///     replica.commit(None).unwrap();
+    /// }
+    /// let first = replica.list_packs(None, 2).unwrap();
+    /// assert_eq!(first.items.len(), 2);
+    /// let second = replica.list_packs(first.next.as_deref(), 2).unwrap();
+    /// assert_eq!(second.items.len(), 1);
+    /// assert!(second.next.is_none() && second.items[0].size > 0);
+    /// ```
+    pub fn list_packs(&self, token: Option<&str>, limit: usize) -> Result<Page<PackSummary>> {
+        if limit == 0 {
+            bail!("invalid_page_limit");
+        }
+        let adapter = self.get_adapter();
+        let adapter = adapter.read().unwrap();
+        let mut ids = adapter.list_objects_page(PACK_EXTENSION, token, limit.saturating_add(1))?;
+        let next = (ids.len() > limit).then(|| {
+            ids.truncate(limit);
+            ids.last().cloned()
+        });
+        let mut items = vec![];
+        for id in ids {
+            let size = adapter.object_size(&(id.clone() + PACK_EXTENSION))?;
+            items.push(PackSummary { id, size });
+        }
+        Ok(Page {
+            items,
+            next: next.flatten(),
+        })
+    }
+
+    /// Sets (or removes, if the value is None) an annotation of a block, such as a label, a
+    /// review status or a comment. Blocks are immutable: annotations are stored as separate
+    /// items, which are transferred when melding. Concurrent changes to the same annotation
+    /// are resolved by keeping the most recent one
+    ///
+    /// # Arguments
+    ///
+    /// * `block_id` - Block identifier
+    /// * `key` - Key of the annotation
+    /// * `value` - Value of the annotation (None to remove it)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// replica.update(json!({ "key" : "value" }).as_object().unwrap().clone()).unwrap();
+    /// let block = replica.commit(None).unwrap().unwrap().into_iter().next().unwrap();
+    /// replica.annotate(&block, "review", Some(json!("pending"))).unwrap();
+    /// replica.annotate(&block, "labels", Some(json!(["release"]))).unwrap();
+    /// replica.annotate(&block, "review", Some(json!("approved"))).unwrap();
+    /// let other = Melda::new_from_url("memory://").unwrap();
+    /// other.meld(&replica).unwrap();
+    /// other.refresh().unwrap();
+    /// assert_eq!(other.annotations(&block).unwrap(), json!({ "review" : "approved", "labels" : ["release"] }).as_object().unwrap().clone());
+    /// other.annotate(&block, "labels", None).unwrap();
+    /// replica.meld(&other).unwrap();
+    /// assert_eq!(replica.annotations(&block).unwrap().len(), 1);
+    /// assert_eq!(replica.all_annotations().unwrap().len(), 1);
+    /// assert!(replica.annotate("unknown", "review", None).is_err());
+    /// ```
+    pub fn annotate(&self, block_id: &str, key: &str, value: Option<Value>) -> Result<()> {
+        if !self.blocks.read().unwrap().contains_key(block_id) {
+            bail!("unknown_block");
+        }
+        let mut annotation = Map::new();
+        annotation.insert(ANNOTATION_BLOCK_FIELD.to_string(), Value::from(block_id));
+        annotation.insert(ANNOTATION_KEY_FIELD.to_string(), Value::from(key));
+        if let Some(value) = value {
+            annotation.insert(ANNOTATION_VALUE_FIELD.to_string(), value);
+        }
+        if let Some(timestamp) = now_millis() {
+            annotation.insert(TIMESTAMP_FIELD.to_string(), Value::from(timestamp));
+        }
+        let supersedes: Vec<String> = self
+            .load_annotations(Some(block_id))?
+            .into_iter()
+            .filter(|a| a.key == key)
+            .map(|a| a.id)
+            .collect();
+        if !supersedes.is_empty() {
+            annotation.insert(
+                ANNOTATION_SUPERSEDES_FIELD.to_string(),
+                Value::from(supersedes),
+            );
+        }
+        let content = serde_json::to_vec(&annotation)?;
+        let id = digest_bytes_with(self.digest, &content);
+        self.get_adapter()
+            .write()
+            .unwrap()
+            .write_object(&(id + ANNOTATION_EXTENSION), &content)
+    }
+
+    /// Returns the annotations of a block (see [Melda::annotate])
+    ///
+    /// # Arguments
+    ///
+    /// * `block_id` - Block identifier
+    pub fn annotations(&self, block_id: &str) -> Result<Map<String, Value>> {
+        Ok(self
+            .load_annotations(Some(block_id))?
+            .into_iter()
+            .filter_map(|Annotation { key, value, .. }| value.map(|v| (key, v)))
+            .collect())
+    }
+
+    /// Returns the annotations of all annotated blocks (see [Melda::annotate])
+    pub fn all_annotations(&self) -> Result<BTreeMap<String, Map<String, Value>>> {
+        let mut annotations = BTreeMap::<String, Map<String, Value>>::new();
+        for a in self.load_annotations(None)? {
+            if let Some(value) = a.value {
+                annotations.entry(a.block).or_default().insert(a.key, value);
+            }
+        }
+        Ok(annotations)
+    }
+
+    /// Returns a Graphviz (DOT) description of the history: each block is a node (labelled with
+    /// its abbreviated identifier and the commit information) with an edge to each of its
+    /// parents. Anchors have a double border, whereas blocks which have not been applied and
+    /// parents which are not available (such as the blocks grafted by a shallow clone) have a
+    /// dashed border
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+    /// replica.update(json!({ "key" : "alpha" }).as_object().unwrap().clone()).unwrap();
+    /// let info = json!({ "author" : "Alice" }).as_object().unwrap().clone();
+    /// replica.commit(Some(info)).unwrap();
+    /// let replica2 = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+    /// replica2.meld(&replica).unwrap();
+    /// replica2.refresh().unwrap();
+    /// replica2.update(json!({ "key" : "beta" }).as_object().unwrap().clone()).unwrap();
+    /// let info = json!({ "author" : "Bob \"B\"" }).as_object().unwrap().clone();
+    /// replica2.commit(Some(info)).unwrap();
+    /// replica.update(json!({ "key" : "gamma" }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// replica.meld(&replica2).unwrap();
+    /// replica.refresh().unwrap();
+    /// let dot = replica.export_dot().unwrap();
+    /// assert!(dot.starts_with("digraph history {"));
+    /// assert_eq!(dot.matches(" -> ").count(), 2);
+    /// assert_eq!(dot.matches("peripheries=2").count(), 2);
+    /// assert!(dot.contains("author: Bob \\\"B\\\""));
+    /// ```
+    pub fn export_dot(&self) -> Result<String> {
+        let anchors = self.get_anchors();
+        let blocks_r = self
+            .blocks
+            .read()
+            .expect("cannot_acquire_blocks_for_reading");
+        let mut dot = "digraph history {\n    rankdir=BT;\n    node [shape=box];\n".to_string();
+        let mut unavailable = BTreeSet::new();
+        for (block_id, block) in blocks_r.iter() {
+            let block_r = block.read().expect("cannot_acquire_block_for_reading");
+            let mut label = block_id.chars().take(7).collect::<String>();
+            for (k, v) in block_r.info.iter().flatten() {
+                match v {
+                    Value::String(s) => label += &format!("\n{}: {}", k, s),
+                    v => label += &format!("\n{}: {}", k, v),
+                }
+            }
+            let mut attributes = vec![format!("label={}", dot_string(&label))];
+            if anchors.contains(block_id) {
+                attributes.push("peripheries=2".to_string());
+            }
+            if block_r.status != Status::ValidAndApplied {
+                attributes.push("style=dashed".to_string());
+            }
+            dot += &format!("    \"{}\" [{}];\n", block_id, attributes.join(", "));
+            for parent in block_r.parents.iter().flatten() {
+                if !blocks_r.contains_key(parent) {
+                    unavailable.insert(parent.clone());
+                }
+                dot += &format!("    \"{}\" -> \"{}\";\n", block_id, parent);
+            }
+        }
+        for block_id in unavailable {
+            let label = block_id.chars().take(7).collect::<String>();
+            dot += &format!(
+                "    \"{}\" [label={}, style=dashed];\n",
+                block_id,
+                dot_string(&label)
+            );
+        }
+        dot += "}\n";
+        Ok(dot)
+    }
+
+    /// Returns the parent revision in the revision tree of the specified object, or None if there is no parent
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - Object identifier
+    /// * `revision` - The revision
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica.create_object("myobject", object);  
+    /// let winner = replica.get_winner("myobject").unwrap();
+    /// let parent = replica.get_parent_revision("myobject", &winner).unwrap();
+    /// assert!(parent.is_none());
+    /// let block_id = replica.commit(None).unwrap().unwrap();
+    /// replica.delete_object("myobject");
+    /// let newrev = replica.get_winner("myobject").unwrap();
+    /// assert_eq!("2-d_e5d1d20", newrev);
+    /// let parent = replica.get_parent_revision("myobject", &newrev).unwrap().unwrap();
+    /// assert_eq!(&parent, &winner);
+    pub fn get_parent_revision(&self, uuid: &str, revision: &str) -> Result<Option<String>> {
+        let docs = self
+            .documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading");
+        let rt = docs.get(uuid).ok_or_else(|| anyhow!("unknown_document"))?;
+        let revision = Revision::from(revision).expect("invalid_revision_string");
+        let rt_r = rt
+            .lock()
+            .expect("failed_to_acquire_revision_tree_for_reading");
+        match rt_r.get_parent(&revision) {
+            Some(r) => Ok(Some(r.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the causal relation between two revisions of an object: a revision happened
+    /// before another one if it is one of its ancestors in the revision tree
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - Object identifier
+    /// * `first` - The first revision
+    /// * `second` - The second revision
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::{Melda, Causality};
+    /// use serde_json::json;
+    /// let alice = Melda::new_from_url("memory://").unwrap();
+    /// alice.create_object("1", json!({ "title" : "Call Bob" }).as_object().unwrap().clone()).unwrap();
+    /// alice.commit(None).unwrap();
+    /// let created = alice.get_winner("1").unwrap();
+    /// let bob = Melda::new_from_url("memory://").unwrap();
+    /// bob.meld(&alice).unwrap();
+    /// bob.refresh().unwrap();
+    /// alice.update_object("1", json!({ "title" : "Call Carol" }).as_object().unwrap().clone()).unwrap();
+    /// alice.commit(None).unwrap();
+    /// bob.update_object("1", json!({ "title" : "Call Dave" }).as_object().unwrap().clone()).unwrap();
+    /// bob.commit(None).unwrap();
+    /// let alices = alice.get_winner("1").unwrap();
+    /// let bobs = bob.get_winner("1").unwrap();
+    /// alice.meld(&bob).unwrap();
+    /// alice.refresh().unwrap();
+    /// assert_eq!(alice.compare_revisions("1", &created, &alices).unwrap(), Causality::Before);
+    /// assert_eq!(alice.compare_revisions("1", &bobs, &created).unwrap(), Causality::After);
+    /// assert_eq!(alice.compare_revisions("1", &alices, &bobs).unwrap(), Causality::Concurrent);
+    /// assert_eq!(alice.compare_revisions("1", &bobs, &bobs).unwrap(), Causality::Same);
+    /// assert!(alice.compare_revisions("2", &bobs, &bobs).is_err());
+    /// ```
+    pub fn compare_revisions(&self, uuid: &str, first: &str, second: &str) -> Result<Causality> {
+        let docs = self
+            .documents
+            .read()
+            .expect("failed_to_acquire_documents_for_reading");
+        let rt = docs.get(uuid).ok_or_else(|| anyhow!("unknown_document"))?;
+        let rt_r = rt
+            .lock()
+            .expect("failed_to_acquire_revision_tree_for_reading");
+        let first = Revision::from(first)?;
+        let second = Revision::from(second)?;
+        if [&first, &second]
+            .iter()
+            .any(|r| !rt_r.get_revisions().contains_key(r))
+        {
+            bail!("unknown_revision");
+        }
+        Ok(if first == second {
+            Causality::Same
+        } else if rt_r.is_ancestor(&first, &second) {
+            Causality::Before
+        } else if rt_r.is_ancestor(&second, &first) {
+            Causality::After
+        } else {
+            Causality::Concurrent
+        })
+    }
+
+    /// Returns the causal relation between two blocks: a block happened before another one if
+    /// it is one of its ancestors (blocks which are not available, such as the blocks grafted
+    /// by a shallow clone, interrupt the ancestry)
+    ///
+    /// # Arguments
+    ///
+    /// * `first` - The first block
+    /// * `second` - The second block
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::{Melda, Causality};
+    /// use serde_json::json;
+    /// let alice = Melda::new_from_url("memory://").unwrap();
+    /// alice.update(json!({ "key" : 1 }).as_object().unwrap().clone()).unwrap();
+    /// let first = alice.commit(None).unwrap().unwrap().into_iter().next().unwrap();
+    /// let bob = Melda::new_from_url("memory://").unwrap();
+    /// bob.meld(&alice).unwrap();
+    /// bob.refresh().unwrap();
+    /// bob.update(json!({ "key" : 2 }).as_object().unwrap().clone()).unwrap();
+    /// let bobs = bob.commit(None).unwrap().unwrap().into_iter().next().unwrap();
+    /// alice.update(json!({ "key" : 3 }).as_object().unwrap().clone()).unwrap();
+    /// let alices = alice.commit(None).unwrap().unwrap().into_iter().next().unwrap();
+    /// alice.meld(&bob).unwrap();
+    /// alice.refresh().unwrap();
+    /// assert_eq!(alice.compare_blocks(&first, &bobs).unwrap(), Causality::Before);
+    /// assert_eq!(alice.compare_blocks(&alices, &first).unwrap(), Causality::After);
+    /// assert_eq!(alice.compare_blocks(&alices, &bobs).unwrap(), Causality::Concurrent);
+    /// assert!(bob.compare_blocks(&alices, &bobs).is_err());
+    /// ```
+    pub fn compare_blocks(&self, first: &str, second: &str) -> Result<Causality> {
+        let blocks = self.blocks.read().unwrap();
+        if !blocks.contains_key(first) || !blocks.contains_key(second) {
+            bail!("unknown_block");
+        }
+        let is_ancestor = |ancestor: &str, block: &str| {
+            let mut visited = HashSet::new();
+            let mut stack = vec![block.to_string()];
+            while let Some(current) = stack.pop() {
+                if let Some(b) = blocks.get(&current) {
+                    for parent in b.read().unwrap().parents.iter().flatten() {
+                        if parent == ancestor {
+                            return true;
+                        }
+                        if visited.insert(parent.clone()) {
+                            stack.push(parent.clone());
+                        }
+                    }
+                }
+            }
+            false
+        };
+        Ok(if first == second {
+            Causality::Same
+        } else if is_ancestor(first, second) {
+            Causality::Before
+        } else if is_ancestor(second, first) {
+            Causality::After
+        } else {
+            Causality::Concurrent
+        })
+    }
+
+    // Returns the current annotations (of the given block, or of all blocks): for each block and
+    // key, the most recent of the annotations which have not been superseded
+    fn load_annotations(&self, block: Option<&str>) -> Result<Vec<Annotation>> {
+        let adapter = self.get_adapter();
+        let adapter = adapter.read().unwrap();
+        let mut annotations = vec![];
+        for id in adapter.list_objects(ANNOTATION_EXTENSION)? {
+            let content = adapter.read_object(&(id.clone() + ANNOTATION_EXTENSION), 0, 0)?;
+            let record: Map<String, Value> =
+                serde_json::from_slice(&content).map_err(|_| anyhow!("invalid_annotation"))?;
+            let field = |name: &str| {
+                record
+                    .get(name)
+                    .and_then(Value::as_str)
+                    .map(|s| s.to_string())
+                    .ok_or_else(|| anyhow!("invalid_annotation"))
+            };
+            let annotation = Annotation {
+                block: field(ANNOTATION_BLOCK_FIELD)?,
+                key: field(ANNOTATION_KEY_FIELD)?,
+                value: record.get(ANNOTATION_VALUE_FIELD).cloned(),
+                timestamp: record
+                    .get(TIMESTAMP_FIELD)
+                    .and_then(Value::as_u64)
+                    .unwrap_or_default(),
+                supersedes: record
+                    .get(ANNOTATION_SUPERSEDES_FIELD)
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|s| s.as_str().map(|s| s.to_string()))
+                    .collect(),
+                id,
+            };
+            if block.is_none_or(|b| b == annotation.block) {
+                annotations.push(annotation);
+            }
+        }
+        let superseded: HashSet<String> = annotations
+            .iter()
+            .flat_map(|a| a.supersedes.iter().cloned())
+            .collect();
+        let mut current = BTreeMap::<(String, String), Annotation>::new();
+        for a in annotations
+            .into_iter()
+            .filter(|a| !superseded.contains(&a.id))
+        {
+            let slot = (a.block.clone(), a.key.clone());
+            match current.get(&slot) {
+                Some(c) if (c.timestamp, &c.id) >= (a.timestamp, &a.id) => {}
+                _ => {
+                    current.insert(slot, a);
+                }
+            }
+        }
+        Ok(current.into_values().collect())
+    }
+}
+
+/// Quotes a string for a DOT description (newlines become line breaks)
+fn dot_string(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
@@ -0,0 +1,529 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use super::{verify_item, Change, Melda};
+use crate::constants::{
+    DELTA_EXTENSION, INDEX_EXTENSION, PACK_EXTENSION, PACK_FIELD, QUARANTINE_EXTENSION,
+};
+use anyhow::{bail, Result};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+/// A problem detected while checking a repository
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsckIssue {
+    /// A pack cannot be read or its content does not match its digest
+    CorruptPack { pack: String, reason: String },
+    /// A pack referenced by a block is not available
+    MissingPack { block: String, pack: String },
+    /// A block cannot be read, its content does not match its digest or it cannot be parsed
+    CorruptBlock { block: String, reason: String },
+    /// A parent referenced by a block is not available
+    MissingParent { block: String, parent: String },
+    /// An object referenced by a change in a block is not available in any pack
+    MissingObject {
+        block: String,
+        uuid: String,
+        digest: String,
+    },
+    /// The index of a pack cannot be read or does not match the content of the pack
+    CorruptIndex { pack: String, reason: String },
+}
+
+impl FsckIssue {
+    /// Returns true if the issue concerns an item which cannot be read or does not match its
+    /// digest (as opposed to an item which is referenced but not available)
+    pub fn is_corrupt(&self) -> bool {
+        matches!(
+            self,
+            FsckIssue::CorruptPack { .. }
+                | FsckIssue::CorruptBlock { .. }
+                | FsckIssue::CorruptIndex { .. }
+        )
+    }
+}
+
+/// Checks performed by [Melda::fsck_with] in addition to those of [Melda::fsck]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FsckOptions {
+    /// Check that the indexes of the packs are consistent with the packs
+    pub indexes: bool,
+    /// Look for orphaned items (items of the storage not referenced by any block)
+    pub orphans: bool,
+}
+
+impl FsckOptions {
+    /// Enables all checks
+    pub fn full() -> FsckOptions {
+        FsckOptions {
+            indexes: true,
+            orphans: true,
+        }
+    }
+}
+
+/// Report produced by a repository check, see [Melda::fsck] and [Melda::fsck_with]
+#[derive(Debug, Clone, Default)]
+pub struct FsckReport {
+    /// Number of blocks checked
+    pub checked_blocks: usize,
+    /// Number of packs checked
+    pub checked_packs: usize,
+    /// Number of pack indexes checked (see [FsckOptions::indexes])
+    pub checked_indexes: usize,
+    /// Problems found
+    pub issues: Vec<FsckIssue>,
+    /// Blocks which cannot be applied because they or one of their ancestors are damaged
+    pub damaged_blocks: BTreeSet<String>,
+    /// Items of the storage which are not referenced by any block (see [FsckOptions::orphans]).
+    /// They are harmless, and removed by [Melda::gc]
+    pub orphaned: BTreeSet<String>,
+}
+
+impl FsckReport {
+    /// Returns true if no problems were found (orphaned items are harmless)
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Returns the items which cannot be read or do not match their digest (blocks, packs
+    /// and indexes)
+    pub fn corrupt(&self) -> impl Iterator<Item = &FsckIssue> {
+        self.issues.iter().filter(|i| i.is_corrupt())
+    }
+
+    /// Returns the items referenced but not available (packs, parent blocks and values)
+    pub fn missing(&self) -> impl Iterator<Item = &FsckIssue> {
+        self.issues.iter().filter(|i| !i.is_corrupt())
+    }
+}
+
+/// Result of a repair, see [Melda::repair]
+#[derive(Debug, Clone, Default)]
+pub struct RepairReport {
+    /// Items copied from the remote replica (replacing missing or corrupt items)
+    pub fetched: Vec<String>,
+    /// Corrupt items set aside (their key is suffixed with ".quarantine")
+    pub quarantined: Vec<String>,
+    /// Packs whose index has been rebuilt
+    pub rebuilt_indexes: Vec<String>,
+    /// Problems which could not be repaired
+    pub unresolved: Vec<FsckIssue>,
+}
+
+impl Melda {
+    /// Checks the integrity of the repository: verifies the digest of every pack and every block,
+    /// that all packs, parents and objects referenced by each block are available, and
+    /// determines which blocks are affected by damaged ancestors (hash chain). The state of
+    /// the replica is not modified.
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::{Melda, FsckIssue}, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32, 3u32, 4u32 ] }).as_object().unwrap().clone();
+    /// replica.update(object).unwrap();
+    /// let first = replica.commit(None).unwrap().unwrap();
+    /// let object = json!({ "somekey" : [ "somedata", 1u32, 2u32 ] }).as_object().unwrap().clone();
+    /// replica.update(object).unwrap();
+    /// let second = replica.commit(None).unwrap().unwrap();
+    /// let report = replica.fsck().unwrap();
+    /// assert!(report.is_clean());
+    /// assert_eq!(report.checked_blocks, 2);
+    /// // Copy the repository, tampering with the first block
+    /// let first = first.first().unwrap().clone() + ".delta";
+    /// let tampered : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// for item in adapter.read().unwrap().list_objects("").unwrap() {
+    ///     let content = adapter.read().unwrap().read_object(&item, 0, 0).unwrap();
+    ///     let content = if item == first { b"{\"c\":[]}".to_vec() } else { content };
+    ///     tampered.write_object(&item, &content).unwrap();
+    /// }
+    /// let first = first.strip_suffix(".delta").unwrap();
+    /// let report = Melda::new(Arc::new(RwLock::new(tampered))).unwrap().fsck().unwrap();
+    /// assert!(!report.is_clean());
+    /// assert!(report.issues.iter().any(|i| matches!(i, FsckIssue::CorruptBlock { block, .. } if block == first)));
+    /// assert!(report.damaged_blocks.contains(first));
+    /// assert!(report.damaged_blocks.contains(second.first().unwrap()));
+    /// ```
+    pub fn fsck(&self) -> Result<FsckReport> {
+        self.fsck_with(&FsckOptions::default())
+    }
+
+    /// Checks the repository like [Melda::fsck], performing the additional checks enabled in
+    /// the options: the consistency of the indexes of the packs with the packs, and the search
+    /// for orphaned items (items of the storage not referenced by any block). Problems are
+    /// reported instead of surfacing later as failures of other operations.
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - The additional checks to perform
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::{Melda, FsckIssue, FsckOptions}, adapter::Adapter};
+    /// use serde_json::json;
+    /// let replica = Melda::builder().url("memory://").structural_deltas(true).open().unwrap();
+    /// let mut object = json!({ "key" : 0 }).as_object().unwrap().clone();
+    /// object.extend((0..32).map(|i| (format!("field{}", i), json!(i))));
+    /// for i in 0..2 {
+    ///     object.insert("key".to_string(), json!(i));
+    ///     replica.update(object.clone()).unwrap();
+    ///     replica.commit(None).unwrap();
+    /// }
+    /// let report = replica.fsck_with(&FsckOptions::full()).unwrap();
+    /// assert!(report.is_clean() && report.orphaned.is_empty());
+    /// assert_eq!((report.checked_blocks, report.checked_packs, report.checked_indexes), (2, 2, 1));
+    /// // Damage an index and leave an unreferenced pack and index around
+    /// let adapter = replica.get_adapter();
+    /// let adapter = adapter.read().unwrap();
+    /// let index = adapter.list_objects(".index").unwrap().pop().unwrap();
+    /// adapter.delete_object(&(index.clone() + ".index")).unwrap();
+    /// adapter.write_object(&(index.clone() + ".index"), b"{\"x\":[0,1]}").unwrap();
+    /// adapter.write_object("leftover.index", b"{}").unwrap();
+    /// drop(adapter);
+    /// // Indexes are only checked on request
+    /// assert!(replica.fsck().unwrap().is_clean());
+    /// let report = replica.fsck_with(&FsckOptions::full()).unwrap();
+    /// assert!(!report.is_clean());
+    /// assert!(matches!(report.corrupt().next(), Some(FsckIssue::CorruptIndex { pack, .. }) if *pack == index));
+    /// assert!(report.orphaned.contains("leftover.index"));
+    /// ```
+    pub fn fsck_with(&self, options: &FsckOptions) -> Result<FsckReport> {
+        let mut report = FsckReport::default();
+        let data = self.data.read().expect("cannot_acquire_data_for_reading");
+        let block_list = data.list_raw_items(DELTA_EXTENSION)?;
+        let pack_list = data.list_raw_items(PACK_EXTENSION)?;
+        // Verify pack digests
+        let mut valid_packs = BTreeSet::new();
+        for pack in &pack_list {
+            match data.is_readable_and_valid_pack(pack) {
+                Ok(true) => {
+                    valid_packs.insert(pack.clone());
+                }
+                Ok(false) => report.issues.push(FsckIssue::CorruptPack {
+                    pack: pack.clone(),
+                    reason: "mismatching_pack_hash".to_string(),
+                }),
+                Err(e) => report.issues.push(FsckIssue::CorruptPack {
+                    pack: pack.clone(),
+                    reason: e.to_string(),
+                }),
+            }
+        }
+        report.checked_packs = pack_list.len();
+        drop(data);
+        // Verify block digests and references
+        let available_blocks: BTreeSet<String> = block_list.iter().cloned().collect();
+        let mut damaged = BTreeSet::new();
+        let mut parents = BTreeMap::<String, BTreeSet<String>>::new();
+        let mut squashed_by = BTreeMap::<String, String>::new();
+        for bid in &block_list {
+            let raw_block = match self.read_raw_block(bid) {
+                Ok(raw_block) => raw_block,
+                Err(e) => {
+                    report.issues.push(FsckIssue::CorruptBlock {
+                        block: bid.clone(),
+                        reason: e.to_string(),
+                    });
+                    damaged.insert(bid.clone());
+                    continue;
+                }
+            };
+            // Check referenced packs
+            if let Some(packs) = raw_block.get(PACK_FIELD).and_then(|p| p.as_array()) {
+                for pack in packs.iter().filter_map(|p| p.as_str()) {
+                    let superseded = self.data.read().unwrap().is_superseded(pack);
+                    if !pack_list.iter().any(|p| p == pack) && !superseded {
+                        report.issues.push(FsckIssue::MissingPack {
+                            block: bid.clone(),
+                            pack: pack.to_string(),
+                        });
+                        damaged.insert(bid.clone());
+                    } else if !valid_packs.contains(pack) && !superseded {
+                        damaged.insert(bid.clone());
+                    }
+                }
+            }
+            if damaged.contains(bid) {
+                continue;
+            }
+            let block = match self.parse_raw_block(bid.clone(), raw_block) {
+                Ok(block) => block,
+                Err(e) => {
+                    report.issues.push(FsckIssue::CorruptBlock {
+                        block: bid.clone(),
+                        reason: e.to_string(),
+                    });
+                    damaged.insert(bid.clone());
+                    continue;
+                }
+            };
+            // Collect referenced parents (checked once all squashed blocks are known)
+            if let Some(ps) = &block.parents {
+                parents.insert(bid.clone(), ps.clone());
+            }
+            for sid in block.squashed.iter().chain(block.grafted.iter()).flatten() {
+                if !available_blocks.contains(sid) {
+                    squashed_by.insert(sid.clone(), bid.clone());
+                }
+            }
+            // Check referenced objects
+            if let Some(changes) = &block.changes {
+                let data = self.data.read().expect("cannot_acquire_data_for_reading");
+                for Change(uuid, r, _) in changes {
+                    if r.is_deleted() || r.is_resolved() || r.is_empty() || r.is_charcode() {
+                        continue;
+                    }
+                    if !data.contains_value(r.digest()) {
+                        report.issues.push(FsckIssue::MissingObject {
+                            block: bid.clone(),
+                            uuid: uuid.clone(),
+                            digest: r.digest().clone(),
+                        });
+                        damaged.insert(bid.clone());
+                    }
+                }
+            }
+        }
+        report.checked_blocks = block_list.len();
+        // Check referenced parents (squashed or grafted blocks are replaced by the block squashing
+        // or grafting them)
+        for (bid, ps) in parents.iter_mut() {
+            let mut resolved = BTreeSet::new();
+            for parent in ps.iter() {
+                if available_blocks.contains(parent) {
+                    resolved.insert(parent.clone());
+                } else if let Some(replacement) = squashed_by.get(parent) {
+                    resolved.insert(replacement.clone());
+                } else {
+                    report.issues.push(FsckIssue::MissingParent {
+                        block: bid.clone(),
+                        parent: parent.clone(),
+                    });
+                    damaged.insert(bid.clone());
+                }
+            }
+            *ps = resolved;
+        }
+        // Propagate damage along the hash chain (descendants of damaged blocks)
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (bid, ps) in &parents {
+                if !damaged.contains(bid) && ps.iter().any(|p| damaged.contains(p)) {
+                    damaged.insert(bid.clone());
+                    changed = true;
+                }
+            }
+        }
+        report.damaged_blocks = damaged;
+        if options.indexes {
+            self.check_indexes(&mut report)?;
+        }
+        if options.orphans {
+            self.find_orphans(&block_list, &pack_list, &mut report)?;
+        }
+        Ok(report)
+    }
+
+    // Checks that the indexes of the packs are consistent with the packs (indexes of missing
+    // packs are orphaned)
+    fn check_indexes(&self, report: &mut FsckReport) -> Result<()> {
+        let data = self.data.read().expect("cannot_acquire_data_for_reading");
+        let pack_list: BTreeSet<String> =
+            data.list_raw_items(PACK_EXTENSION)?.into_iter().collect();
+        for pack in &data.list_raw_items(INDEX_EXTENSION)? {
+            if !pack_list.contains(pack) {
+                report.orphaned.insert(pack.clone() + INDEX_EXTENSION);
+                continue;
+            }
+            report.checked_indexes += 1;
+            let reason = match data.verify_index(pack) {
+                Ok(reason) => reason,
+                Err(e) => Some(e.to_string()),
+            };
+            if let Some(reason) = reason {
+                report.issues.push(FsckIssue::CorruptIndex {
+                    pack: pack.clone(),
+                    reason,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Looks for squashed blocks and unreferenced packs (as removed by gc)
+    fn find_orphans(
+        &self,
+        block_list: &[String],
+        pack_list: &[String],
+        report: &mut FsckReport,
+    ) -> Result<()> {
+        let (squashed, referenced_packs, retained) = self.collect_references(block_list, false)?;
+        for bid in block_list.iter().filter(|bid| squashed.contains(*bid)) {
+            report.orphaned.insert(bid.clone() + DELTA_EXTENSION);
+        }
+        let holding = match self.data.read().unwrap().packs_holding(&retained) {
+            Ok(holding) => holding,
+            Err(_) => return Ok(()), // Packs which cannot be indexed are already reported
+        };
+        for pack in pack_list {
+            if !referenced_packs.contains(pack) && !holding.contains(pack) {
+                report.orphaned.insert(pack.clone() + PACK_EXTENSION);
+            }
+        }
+        Ok(())
+    }
+
+    /// Repairs the problems found by [Melda::fsck_with]: missing or corrupt items are fetched
+    /// from the remote replica (if given and if it holds a valid copy), corrupt blocks and packs
+    /// which cannot be fetched are quarantined (set aside so that the rest of the history can be
+    /// loaded) and inconsistent pack indexes are rebuilt. The replica is reloaded afterwards.
+    ///
+    /// # Arguments
+    ///
+    /// * `report` - The report of the check
+    /// * `remote` - Optional replica from which missing or corrupt items are fetched
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::{Melda, FsckOptions}, adapter::Adapter};
+    /// use serde_json::json;
+    /// let remote = Melda::new_from_url("memory://").unwrap();
+    /// for i in 0..2 {
+    ///     remote.update(json!({ "key" : i }).as_object().unwrap().clone()).unwrap();
+    ///     remote.commit(None).unwrap();
+    /// }
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// replica.meld(&remote).unwrap();
+    /// replica.refresh().unwrap();
+    /// // Lose a pack
+    /// let adapter = replica.get_adapter();
+    /// let pack = adapter.read().unwrap().list_objects(".pack").unwrap().pop().unwrap();
+    /// adapter.read().unwrap().delete_object(&(pack.clone() + ".pack")).unwrap();
+    /// let report = replica.fsck_with(&FsckOptions::full()).unwrap();
+    /// assert!(!report.is_clean());
+    /// // Without a remote replica the pack cannot be recovered
+    /// assert_eq!(replica.repair(&report, None).unwrap().unresolved.len(), 1);
+    /// let repaired = replica.repair(&report, Some(&remote)).unwrap();
+    /// assert_eq!(repaired.fetched, vec![pack + ".pack"]);
+    /// assert!(replica.fsck_with(&FsckOptions::full()).unwrap().is_clean());
+    /// assert_eq!(replica.read(None).unwrap().get("key").unwrap(), 1);
+    /// ```
+    pub fn repair(&self, report: &FsckReport, remote: Option<&Melda>) -> Result<RepairReport> {
+        if remote.is_some_and(|r| r.digest != self.digest) {
+            bail!("digest_algorithm_mismatch");
+        }
+        let mut result = RepairReport::default();
+        for issue in report.corrupt().chain(report.missing()) {
+            let repaired = match issue {
+                FsckIssue::CorruptPack { pack, .. } => {
+                    let key = pack.clone() + PACK_EXTENSION;
+                    self.fetch_item(&key, remote, &mut result)?
+                        || self.quarantine_item(&key, &mut result)?
+                }
+                FsckIssue::CorruptBlock { block, .. } => {
+                    let key = block.clone() + DELTA_EXTENSION;
+                    self.fetch_item(&key, remote, &mut result)?
+                        || self.quarantine_item(&key, &mut result)?
+                }
+                FsckIssue::CorruptIndex { pack, .. } => {
+                    let data = self.data.read().expect("cannot_acquire_data_for_reading");
+                    match data.rebuild_index(pack) {
+                        Ok(()) => {
+                            result.rebuilt_indexes.push(pack.clone());
+                            true
+                        }
+                        Err(_) => false,
+                    }
+                }
+                FsckIssue::MissingPack { pack, .. } => {
+                    self.fetch_item(&(pack.clone() + PACK_EXTENSION), remote, &mut result)?
+                }
+                FsckIssue::MissingParent { parent, .. } => {
+                    self.fetch_item(&(parent.clone() + DELTA_EXTENSION), remote, &mut result)?
+                }
+                FsckIssue::MissingObject { digest, .. } => match remote {
+                    Some(remote) => {
+                        let digests = HashSet::from([digest.clone()]);
+                        let packs = remote.data.read().unwrap().packs_holding(&digests)?;
+                        let mut fetched = !packs.is_empty();
+                        for pack in packs {
+                            let key = pack + PACK_EXTENSION;
+                            fetched &= self.fetch_item(&key, Some(remote), &mut result)?;
+                        }
+                        fetched
+                    }
+                    None => false,
+                },
+            };
+            if !repaired {
+                result.unresolved.push(issue.clone());
+            }
+        }
+        self.reload()?;
+        Ok(result)
+    }
+
+    // Copies an item (and the index of a pack) from the remote replica, replacing the local copy.
+    // Returns false if the remote replica does not hold a valid copy
+    fn fetch_item(
+        &self,
+        key: &str,
+        remote: Option<&Melda>,
+        result: &mut RepairReport,
+    ) -> Result<bool> {
+        if result.fetched.iter().any(|k| k == key) {
+            return Ok(true);
+        }
+        let remote = match remote {
+            Some(remote) => remote.data.read().unwrap(),
+            None => return Ok(false),
+        };
+        let content = match remote.read_raw_item(key, 0, 0) {
+            Ok(content) if verify_item(self.digest, key, &content).is_ok() => content,
+            _ => return Ok(false),
+        };
+        let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
+        let _ = data.delete_raw_item(key);
+        data.write_raw_item(key, &content)?;
+        if let Some(pack) = key.strip_suffix(PACK_EXTENSION) {
+            let index = pack.to_string() + INDEX_EXTENSION;
+            if let Ok(content) = remote.read_raw_item(&index, 0, 0) {
+                let _ = data.delete_raw_item(&index);
+                data.write_raw_item(&index, &content)?;
+            }
+        }
+        result.fetched.push(key.to_string());
+        Ok(true)
+    }
+
+    // Sets aside a corrupt item (and the index of a pack)
+    fn quarantine_item(&self, key: &str, result: &mut RepairReport) -> Result<bool> {
+        let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
+        if let Ok(content) = data.read_raw_item(key, 0, 0) {
+            data.write_raw_item(&(key.to_string() + QUARANTINE_EXTENSION), &content)?;
+        }
+        data.delete_raw_item(key)?;
+        if let Some(pack) = key.strip_suffix(PACK_EXTENSION) {
+            let _ = data.delete_raw_item(&(pack.to_string() + INDEX_EXTENSION));
+        }
+        result.quarantined.push(key.to_string());
+        Ok(true)
+    }
+}
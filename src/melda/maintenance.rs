@@ -0,0 +1,449 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use super::{Melda, Status};
+use crate::constants::{
+    CHANGESETS_FIELD, DELTA_EXTENSION, PACK_FIELD, PARENTS_FIELD, REPLICA_FIELD, SQUASHED_FIELD,
+    TIMESTAMP_FIELD,
+};
+use crate::error::MeldaError;
+use crate::utils::{digest_bytes_with, encode_value, now_millis};
+use anyhow::{bail, Result};
+use serde_json::{Map, Value};
+use std::collections::{BTreeSet, HashSet};
+use std::time::Duration;
+
+/// Result of a compaction
+#[derive(Debug, Clone, Default)]
+pub struct CompactionReport {
+    /// The pack containing all retained values (None if nothing was compacted or retained)
+    pub pack: Option<String>,
+    /// Packs merged and deleted
+    pub removed_packs: Vec<String>,
+    /// Number of values dropped because they are not referenced by any block
+    pub dropped_values: usize,
+}
+
+/// Result of a garbage collection
+#[derive(Debug, Clone, Default)]
+pub struct GcReport {
+    /// Blocks deleted because they have been squashed into another block
+    pub removed_blocks: Vec<String>,
+    /// Packs deleted (along with their indexes)
+    pub removed_packs: Vec<String>,
+    /// Number of bytes reclaimed from the storage
+    pub reclaimed_bytes: usize,
+}
+
+/// Result of the enforcement of a retention policy, see [Melda::enforce_retention]
+#[derive(Debug, Clone, Default)]
+pub struct RetentionReport {
+    /// Block replacing the blocks outside of the retention window (None if nothing was squashed)
+    pub block: Option<String>,
+    /// Blocks squashed into the new block
+    pub squashed_blocks: Vec<String>,
+    /// Garbage collected after squashing
+    pub gc: GcReport,
+}
+
+/// Policy deciding which history is retained by [Melda::enforce_retention]: the blocks which
+/// are neither among the most recent blocks nor younger than the maximum age (nor descend from
+/// such blocks) are squashed into a single block. The changes of squashed blocks are preserved,
+/// only their granularity (and information) is lost. Blocks whose commit time is unknown (such
+/// as blocks written by older versions) are considered old
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Number of most recent blocks to retain
+    pub keep_blocks: Option<usize>,
+    /// Retain the blocks committed within this duration
+    pub keep_duration: Option<Duration>,
+}
+
+impl RetentionPolicy {
+    /// Returns true if the policy retains the whole history
+    pub fn is_unbounded(&self) -> bool {
+        self.keep_blocks.is_none() && self.keep_duration.is_none()
+    }
+}
+
+impl Melda {
+    /// Merges all data packs into a single pack, dropping the values which are not referenced
+    /// by any block (for example values staged and replaced before a commit). The merged packs
+    /// are deleted from the storage, and a record listing them is kept so that blocks
+    /// referencing them remain valid. Other replicas using the same storage must be reloaded.
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+    /// for i in 0..3 {
+    ///     replica.update(json!({ "key" : i }).as_object().unwrap().clone()).unwrap();
+    ///     replica.commit(None).unwrap();
+    /// }
+    /// let report = replica.compact().unwrap();
+    /// assert_eq!(report.removed_packs.len(), 3);
+    /// assert!(report.pack.is_some());
+    /// assert_eq!(replica.get_adapter().read().unwrap().list_objects(".pack").unwrap().len(), 1);
+    /// assert_eq!(replica.read(None).unwrap().get("key").unwrap(), 2);
+    /// assert!(replica.fsck().unwrap().is_clean());
+    /// // Blocks referencing the merged packs remain valid
+    /// let reopened = Melda::new(replica.get_adapter()).unwrap();
+    /// assert_eq!(reopened.read(None).unwrap().get("key").unwrap(), 2);
+    /// let replica2 = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+    /// replica2.meld(&replica).unwrap();
+    /// replica2.refresh().unwrap();
+    /// assert_eq!(replica2.read(None).unwrap().get("key").unwrap(), 2);
+    /// ```
+    pub fn compact(&self) -> Result<CompactionReport> {
+        let _exclusive = self.exclusive.acquire();
+        if self.has_staging() {
+            bail!(MeldaError::StageNotEmpty)
+        }
+        // Collect the values referenced by the blocks
+        let block_list = self.data.read().unwrap().list_raw_items(DELTA_EXTENSION)?;
+        let mut referenced = HashSet::new();
+        for bid in &block_list {
+            let raw_block = self.fetch_raw_block(bid)?;
+            if let Some(changes) = raw_block.get(CHANGESETS_FIELD).and_then(|c| c.as_array()) {
+                for record in changes.iter().filter_map(|c| c.as_array()) {
+                    if let Some(digest) = record.last().and_then(|d| d.as_str()) {
+                        referenced.insert(digest.to_string());
+                    }
+                }
+            }
+        }
+        let (pack, removed_packs, dropped_values) = self
+            .data
+            .write()
+            .expect("cannot_acquire_data_for_writing")
+            .compact(&referenced)
+            .map_err(deletion_required("compaction"))?;
+        self.reload()?;
+        Ok(CompactionReport {
+            pack,
+            removed_packs,
+            dropped_values,
+        })
+    }
+
+    /// Deletes the items of the storage which are not reachable from any block: blocks which
+    /// have been squashed into another block (for example if they have been melded again
+    /// after squashing) and packs which are neither referenced by a block nor contain a value
+    /// referenced by a block (for example packs written by commits which failed before
+    /// writing their block). Other replicas must not commit to the same storage while garbage
+    /// is collected.
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::{Melda, SquashPolicy};
+    /// use serde_json::json;
+    /// let policy = SquashPolicy { max_blocks: Some(2), max_bytes: None };
+    /// let replica = Melda::builder().url("memory://").squash_policy(policy).open().unwrap();
+    /// replica.update(json!({ "key" : 1 }).as_object().unwrap().clone()).unwrap();
+    /// let first = replica.commit(None).unwrap().unwrap().into_iter().next().unwrap() + ".delta";
+    /// let adapter = replica.get_adapter();
+    /// let content = adapter.read().unwrap().read_object(&first, 0, 0).unwrap();
+    /// for i in 2..4 {
+    ///     replica.update(json!({ "key" : i }).as_object().unwrap().clone()).unwrap();
+    ///     replica.commit(None).unwrap();
+    /// }
+    /// // A squashed block is written again, together with a pack of another replica
+    /// adapter.read().unwrap().write_object(&first, &content).unwrap();
+    /// let other = Melda::new_from_url("memory://").unwrap();
+    /// other.update(json!({ "other" : true }).as_object().unwrap().clone()).unwrap();
+    /// other.commit(None).unwrap();
+    /// let orphan = other.get_adapter().read().unwrap().list_objects(".pack").unwrap()[0].clone();
+    /// let pack = other.get_adapter().read().unwrap().read_object(&(orphan.clone() + ".pack"), 0, 0).unwrap();
+    /// adapter.read().unwrap().write_object(&(orphan.clone() + ".pack"), &pack).unwrap();
+    /// let report = replica.gc().unwrap();
+    /// assert_eq!(report.removed_blocks, vec![first.trim_end_matches(".delta").to_string()]);
+    /// assert_eq!(report.removed_packs, vec![orphan]);
+    /// assert_eq!(report.reclaimed_bytes, content.len() + pack.len());
+    /// assert!(replica.gc().unwrap().removed_packs.is_empty());
+    /// assert_eq!(replica.read(None).unwrap().get("key").unwrap(), 3);
+    /// assert!(replica.fsck().unwrap().is_clean());
+    /// ```
+    pub fn gc(&self) -> Result<GcReport> {
+        let _exclusive = self.exclusive.acquire();
+        if self.has_staging() {
+            bail!(MeldaError::StageNotEmpty)
+        }
+        let block_list = self.data.read().unwrap().list_raw_items(DELTA_EXTENSION)?;
+        let (squashed, referenced_packs, retained) = self.collect_references(&block_list, true)?;
+        let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
+        let mut removed_blocks = vec![];
+        let mut reclaimed_bytes = 0;
+        for bid in block_list.iter().filter(|bid| squashed.contains(*bid)) {
+            let key = bid.clone() + DELTA_EXTENSION;
+            reclaimed_bytes += data.read_raw_item(&key, 0, 0)?.len();
+            data.delete_raw_item(&key)
+                .map_err(deletion_required("garbage_collection"))?;
+            removed_blocks.push(bid.clone());
+        }
+        let (removed_packs, reclaimed) = data
+            .collect_garbage(&referenced_packs, &retained)
+            .map_err(deletion_required("garbage_collection"))?;
+        drop(data);
+        self.reload()?;
+        Ok(GcReport {
+            removed_blocks,
+            removed_packs,
+            reclaimed_bytes: reclaimed_bytes + reclaimed,
+        })
+    }
+
+    /// Enforces the retention policy of the replica (see [crate::builder::MeldaBuilder::retention_policy]):
+    /// the blocks outside of the retention window are squashed into a single block, then the
+    /// storage is garbage collected (see [Melda::gc]). Blocks which have not been applied and
+    /// grafted blocks are never squashed, and the replica is restored if the squashed history
+    /// does not materialize the same state. Like [Melda::gc], this maintenance task must not
+    /// run while other replicas commit to the same storage. Blocks referencing the squashed
+    /// blocks (for example blocks of other replicas) remain valid
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::{Melda, RetentionPolicy};
+    /// use serde_json::json;
+    /// let policy = RetentionPolicy { keep_blocks: Some(2), keep_duration: None };
+    /// let replica = Melda::builder().url("memory://").retention_policy(policy).open().unwrap();
+    /// let other = Melda::new_from_url("memory://").unwrap();
+    /// for i in 0..5 {
+    ///     replica.update(json!({ "key" : i, "items" : [ i ] }).as_object().unwrap().clone()).unwrap();
+    ///     replica.commit(None).unwrap();
+    ///     if i == 0 {
+    ///         other.meld(&replica).unwrap();
+    ///         other.refresh().unwrap();
+    ///     }
+    /// }
+    /// let state = replica.read(None).unwrap();
+    /// let report = replica.enforce_retention().unwrap();
+    /// assert_eq!(report.squashed_blocks.len(), 3);
+    /// assert_eq!(replica.get_adapter().read().unwrap().list_objects(".delta").unwrap().len(), 3);
+    /// assert_eq!(replica.read(None).unwrap(), state);
+    /// assert_eq!(Melda::new(replica.get_adapter()).unwrap().read(None).unwrap(), state);
+    /// assert!(replica.fsck().unwrap().is_clean());
+    /// // Nothing else to squash
+    /// assert!(replica.enforce_retention().unwrap().block.is_none());
+    /// // Blocks based on squashed blocks remain valid
+    /// other.create_object("note", json!({ "text" : "hello" }).as_object().unwrap().clone()).unwrap();
+    /// other.commit(None).unwrap();
+    /// replica.meld(&other).unwrap();
+    /// replica.refresh().unwrap();
+    /// assert!(replica.get_all_objects().contains("note"));
+    /// assert_eq!(replica.read(None).unwrap().get("key").unwrap(), 4);
+    /// // All blocks are younger than one hour
+    /// let policy = RetentionPolicy { keep_blocks: None, keep_duration: Some(std::time::Duration::from_secs(3600)) };
+    /// let replica = Melda::builder().adapter(replica.get_adapter()).retention_policy(policy).open().unwrap();
+    /// assert!(replica.enforce_retention().unwrap().squashed_blocks.is_empty());
+    /// ```
+    pub fn enforce_retention(&self) -> Result<RetentionReport> {
+        let _exclusive = self.exclusive.acquire();
+        if self.has_staging() {
+            bail!(MeldaError::StageNotEmpty)
+        }
+        let policy = self.retention_policy;
+        if policy.is_unbounded() {
+            return Ok(RetentionReport::default());
+        }
+        // Order the applied blocks so that each block follows its ancestors
+        let generations = self.block_generations();
+        let blocks = self.blocks.read().unwrap();
+        let mut applied: Vec<(usize, String)> = blocks
+            .iter()
+            .filter(|(_, block)| block.read().unwrap().status == Status::ValidAndApplied)
+            .map(|(bid, _)| (generations[bid], bid.clone()))
+            .collect();
+        applied.sort();
+        // Retained blocks (and their descendants)
+        let cutoff = policy.keep_duration.and_then(|duration| {
+            now_millis().map(|now| now.saturating_sub(duration.as_millis() as u64))
+        });
+        let recent = applied.len() - policy.keep_blocks.unwrap_or(0).min(applied.len());
+        let mut retained = HashSet::new();
+        let mut squashed = vec![];
+        for (position, (_, bid)) in applied.iter().enumerate() {
+            let block = blocks[bid].read().unwrap();
+            let keep = (policy.keep_blocks.is_some() && position >= recent)
+                || cutoff.is_some_and(|cutoff| block.timestamp.is_some_and(|t| t >= cutoff))
+                || block.parents.iter().flatten().any(|p| retained.contains(p));
+            if keep {
+                retained.insert(bid.clone());
+            } else if block.grafted.is_none() {
+                squashed.push(bid.clone());
+            }
+        }
+        drop(blocks);
+        if squashed.len() < 2 {
+            return Ok(RetentionReport {
+                gc: self.gc()?,
+                ..Default::default()
+            });
+        }
+        // Build the block replacing the squashed blocks
+        let replica = self.replica_id()?;
+        let replaced: HashSet<&String> = squashed.iter().collect();
+        let mut changes = vec![];
+        let mut recorded = HashSet::new();
+        let mut parents = BTreeSet::new();
+        let mut packs = BTreeSet::new();
+        let mut replaced_ids = BTreeSet::new();
+        let mut timestamp = None;
+        for bid in &squashed {
+            let raw_block = self.fetch_raw_block(bid)?;
+            let strings = |field: &str| -> Vec<String> {
+                raw_block
+                    .get(field)
+                    .and_then(Value::as_array)
+                    .map(|v| {
+                        v.iter()
+                            .filter_map(|s| s.as_str().map(str::to_string))
+                            .collect()
+                    })
+                    .unwrap_or_default()
+            };
+            for change in raw_block
+                .get(CHANGESETS_FIELD)
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+            {
+                if recorded.insert(change.to_string()) {
+                    changes.push(change.clone());
+                }
+            }
+            parents.extend(
+                strings(PARENTS_FIELD)
+                    .into_iter()
+                    .filter(|p| !replaced.contains(p)),
+            );
+            packs.extend(strings(PACK_FIELD));
+            replaced_ids.insert(bid.clone());
+            replaced_ids.extend(strings(SQUASHED_FIELD));
+            let time = raw_block.get(TIMESTAMP_FIELD).and_then(Value::as_u64);
+            timestamp = timestamp.max(time);
+        }
+        let mut block = Map::<String, Value>::new();
+        block.insert(CHANGESETS_FIELD.to_string(), Value::from(changes));
+        if !parents.is_empty() {
+            let parents: Vec<String> = parents.into_iter().collect();
+            block.insert(PARENTS_FIELD.to_string(), Value::from(parents));
+        }
+        if let Some(timestamp) = timestamp {
+            block.insert(TIMESTAMP_FIELD.to_string(), Value::from(timestamp));
+        }
+        block.insert(REPLICA_FIELD.to_string(), Value::from(replica));
+        if !packs.is_empty() {
+            let packs: Vec<String> = packs.into_iter().collect();
+            block.insert(PACK_FIELD.to_string(), Value::from(packs));
+        }
+        let replaced_ids: Vec<String> = replaced_ids.into_iter().collect();
+        block.insert(SQUASHED_FIELD.to_string(), Value::from(replaced_ids));
+        let blockdata = encode_value(&Value::from(block), self.encoding)?;
+        let block_hash = digest_bytes_with(self.digest, &blockdata);
+        // Replace the squashed blocks, keeping their content until the state is checked
+        let winners = self.winning_revisions();
+        let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
+        data.write_raw_item(&(block_hash.clone() + DELTA_EXTENSION), &blockdata)?;
+        let mut removed = vec![];
+        for bid in &squashed {
+            let key = bid.clone() + DELTA_EXTENSION;
+            let content = data.read_raw_item(&key, 0, 0)?;
+            data.delete_raw_item(&key)
+                .map_err(deletion_required("retention"))?;
+            removed.push((key, content));
+        }
+        drop(data);
+        let checked = self.reload().and_then(|_| {
+            if self.winning_revisions() != winners {
+                bail!("retention_changes_state");
+            }
+            Ok(())
+        });
+        if let Err(e) = checked {
+            let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
+            for (key, content) in &removed {
+                data.write_raw_item(key, content)?;
+            }
+            data.delete_raw_item(&(block_hash + DELTA_EXTENSION))?;
+            drop(data);
+            self.reload()?;
+            return Err(e);
+        }
+        Ok(RetentionReport {
+            block: Some(block_hash),
+            squashed_blocks: squashed,
+            gc: self.gc()?,
+        })
+    }
+
+    // Returns the blocks squashed by other blocks of the list, and the packs and values referenced
+    // by the blocks which have not been squashed. Blocks which cannot be read are skipped unless
+    // strict
+    pub(super) fn collect_references(
+        &self,
+        block_list: &[String],
+        strict: bool,
+    ) -> Result<(HashSet<String>, HashSet<String>, HashSet<String>)> {
+        let mut raw_blocks = vec![];
+        let mut squashed = HashSet::new();
+        for bid in block_list {
+            let raw_block = match self.fetch_raw_block(bid) {
+                Ok(raw_block) => raw_block,
+                Err(e) if strict => return Err(e),
+                Err(_) => continue,
+            };
+            if let Some(s) = raw_block.get(SQUASHED_FIELD).and_then(|s| s.as_array()) {
+                squashed.extend(s.iter().filter_map(|b| b.as_str()).map(str::to_string));
+            }
+            raw_blocks.push((bid, raw_block));
+        }
+        // Collect the packs and values referenced by the blocks which have not been squashed
+        let mut referenced_packs = HashSet::new();
+        let mut retained = HashSet::new();
+        for (_, raw_block) in raw_blocks
+            .iter()
+            .filter(|(bid, _)| !squashed.contains(*bid))
+        {
+            if let Some(packs) = raw_block.get(PACK_FIELD).and_then(|k| k.as_array()) {
+                referenced_packs
+                    .extend(packs.iter().filter_map(|p| p.as_str()).map(str::to_string));
+            }
+            if let Some(changes) = raw_block.get(CHANGESETS_FIELD).and_then(|c| c.as_array()) {
+                for record in changes.iter().filter_map(|c| c.as_array()) {
+                    if let Some(digest) = record.last().and_then(|d| d.as_str()) {
+                        retained.insert(digest.to_string());
+                    }
+                }
+            }
+        }
+        Ok((squashed, referenced_packs, retained))
+    }
+}
+
+// Explains the failure of a maintenance operation on a backend which cannot delete objects
+// (see [crate::adapter::Adapter::delete_object])
+fn deletion_required(operation: &str) -> impl FnOnce(anyhow::Error) -> anyhow::Error + '_ {
+    move |e| {
+        if e.root_cause().to_string() == "unsupported_operation" {
+            e.context(format!(
+                "unsupported_operation: {} requires an adapter which can delete objects",
+                operation
+            ))
+        } else {
+            e
+        }
+    }
+}
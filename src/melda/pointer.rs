@@ -0,0 +1,705 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use super::{Change, Melda};
+use crate::builder::Encoding;
+use crate::commitinfo::CommitInfo;
+use crate::constants::{
+    ARRAY_DESCRIPTOR_ORDER_FIELD, DELTA_EXTENSION, ID_FIELD, PACK_FIELD, PATCH_BLOCK_SECTION,
+    PATCH_DIFF_SECTION, PATCH_ENCODING_JSON, PATCH_ENCODING_MESSAGEPACK, PATCH_VALUES_SECTION,
+    ROOT_ID, STRING_ESCAPE_PREFIX,
+};
+use crate::error::MeldaError;
+use crate::jsonpatch::PatchOp;
+use crate::jsonpath::{JsonPath, QueryMatch};
+use crate::utils::{
+    binary_header_len, decode_value, digest_bytes_with, digest_string, encode_value, flatten,
+    format_timestamp, is_array_descriptor, is_flattened_field, parse_json_pointer, pointer_child,
+    pointer_set, unescape, StrictValidator,
+};
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::atomic::Ordering;
+
+impl Melda {
+    /// Returns the value identified by a JSON Pointer (RFC 6901) in the current state, or None
+    /// if the value does not exist. Only the objects along the path and below the target
+    /// are read.
+    ///
+    /// # Arguments
+    ///
+    /// * `ptr` - The JSON Pointer (the empty string denotes the whole document)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "title" : "Todo", "items\u{266D}" : [ { "_id" : "1", "key" : "alpha" }, { "_id" : "2", "key" : { "nested" : [1, 2] } } ] }).as_object().unwrap().clone();
+    /// replica.update(object).unwrap();
+    /// assert_eq!(replica.get("/title").unwrap().unwrap(), json!("Todo"));
+    /// assert_eq!(replica.get("/items\u{266D}/0").unwrap().unwrap(), json!({ "_id" : "1", "key" : "alpha" }));
+    /// assert_eq!(replica.get("/items\u{266D}/1/key/nested/1").unwrap().unwrap(), json!(2));
+    /// assert!(replica.get("/items\u{266D}/2").unwrap().is_none());
+    /// assert!(replica.get("/missing").unwrap().is_none());
+    /// assert_eq!(replica.get("").unwrap().unwrap(), Value::from(replica.read(None).unwrap()));
+    /// ```
+    pub fn get(&self, ptr: &str) -> Result<Option<Value>> {
+        let tokens = parse_json_pointer(ptr)?;
+        let mut current = Value::from(ROOT_ID);
+        let mut flattened = true;
+        for token in &tokens {
+            if flattened {
+                match self.expand_flattened(&current)? {
+                    Some(expanded) => current = expanded,
+                    None => return Ok(None),
+                }
+            }
+            flattened = flattened && (current.is_array() || is_flattened_field(token));
+            match pointer_child(&current, token) {
+                Some(child) => current = child.clone(),
+                None => return Ok(None),
+            }
+        }
+        if flattened {
+            if !tokens.is_empty() || self.load_flattened_object(ROOT_ID)?.is_some() {
+                return Ok(Some(
+                    self.resolve_flattened(&current, &mut BTreeSet::new())?,
+                ));
+            }
+            return Ok(None);
+        }
+        Ok(Some(current))
+    }
+
+    /// Replaces the value of an object (reachable from the root) given its identifier.
+    /// Only the subtree of the object is flattened and staged.
+    ///
+    /// # Arguments
+    ///
+    /// * `uuid` - Object identifier
+    /// * `value` - The new value of the object
+    pub fn set_object(&self, uuid: &str, value: Value) -> Result<()> {
+        let mut value = match value {
+            Value::Object(o) => o,
+            _ => bail!("value_not_an_object"),
+        };
+        match value.get(ID_FIELD) {
+            Some(id) if id.as_str() != Some(uuid) => bail!("cannot_change_object_identifier"),
+            Some(_) => {}
+            None => {
+                value.insert(ID_FIELD.to_string(), Value::from(uuid));
+            }
+        }
+        let location = self
+            .get_location(uuid)?
+            .filter(|l| l.root == ROOT_ID)
+            .ok_or_else(|| anyhow!("unreachable_object"))?;
+        self.set(&location.pointer, Value::from(value))
+    }
+
+    /// Sets the value identified by a JSON Pointer (RFC 6901): object members are added or replaced,
+    /// array elements are replaced (or appended if the index is equal to the length of the array or "-").
+    /// Only the subtree of the nearest object containing the target is flattened and staged.
+    ///
+    /// # Arguments
+    ///
+    /// * `ptr` - The JSON Pointer (the empty string denotes the whole document)
+    /// * `value` - The new value
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "title" : "Todo", "items\u{266D}" : [ { "_id" : "1", "key" : "alpha" }, { "_id" : "2", "key" : "beta" } ] }).as_object().unwrap().clone();
+    /// replica.update(object).unwrap();
+    /// replica.commit(None).unwrap();
+    /// replica.set("/items\u{266D}/1/key", json!("gamma")).unwrap();
+    /// // Only the modified element is staged
+    /// let stage = replica.stage().unwrap().unwrap();
+    /// assert_eq!(stage["c"].as_array().unwrap().len(), 1);
+    /// assert_eq!(stage["c"][0][0], json!("2"));
+    /// replica.set("/items\u{266D}/-", json!({ "_id" : "3", "key" : "delta" })).unwrap();
+    /// replica.set("/title", json!("Groceries")).unwrap();
+    /// let content = serde_json::to_string(&replica.read(None).unwrap()).unwrap();
+    /// assert_eq!(content, "{\"_id\":\"\u{221A}\",\"items\u{266D}\":[{\"_id\":\"1\",\"key\":\"alpha\"},{\"_id\":\"2\",\"key\":\"gamma\"},{\"_id\":\"3\",\"key\":\"delta\"}],\"title\":\"Groceries\"}");
+    /// assert!(replica.set("/missing/key", json!(1)).is_err());
+    /// ```
+    pub fn set(&self, ptr: &str, value: Value) -> Result<()> {
+        let tokens = parse_json_pointer(ptr)?;
+        if tokens.is_empty() {
+            match value {
+                Value::Object(obj) => {
+                    self.update(obj)?;
+                    return Ok(());
+                }
+                _ => bail!("document_not_an_object"),
+            }
+        }
+        let (uuid, path, index) = self.find_tracked_ancestor(&tokens, tokens.len() - 1)?;
+        self.modify_subtree(&uuid, &path, |subtree| {
+            pointer_set(subtree, &tokens[index..], value)
+        })?;
+        self.touch_fields(&[tokens]);
+        Ok(())
+    }
+
+    /// Applies a JSON Patch (RFC 6902) to the current state. The operations are applied atomically
+    /// to the smallest subtree (rooted at a tracked object) containing all paths, which is then
+    /// flattened and staged.
+    ///
+    /// # Arguments
+    ///
+    /// * `ops` - The patch operations
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter, jsonpatch::parse_patch};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "title" : "Todo", "items\u{266D}" : [ { "_id" : "1", "key" : "alpha" }, { "_id" : "2", "key" : "beta" } ] }).as_object().unwrap().clone();
+    /// replica.update(object).unwrap();
+    /// let patch = parse_patch(&json!([
+    ///     { "op" : "add", "path" : "/items\u{266D}/0", "value" : { "_id" : "3", "key" : "gamma" } },
+    ///     { "op" : "replace", "path" : "/items\u{266D}/1/key", "value" : "delta" },
+    ///     { "op" : "move", "from" : "/items\u{266D}/2", "path" : "/items\u{266D}/1" },
+    ///     { "op" : "remove", "path" : "/title" }
+    /// ])).unwrap();
+    /// replica.apply_patch(&patch).unwrap();
+    /// let content = serde_json::to_string(&replica.read(None).unwrap()).unwrap();
+    /// assert_eq!(content, "{\"_id\":\"\u{221A}\",\"items\u{266D}\":[{\"_id\":\"3\",\"key\":\"gamma\"},{\"_id\":\"2\",\"key\":\"beta\"},{\"_id\":\"1\",\"key\":\"delta\"}]}");
+    /// // Failing operations leave the state unchanged
+    /// let patch = parse_patch(&json!([
+    ///     { "op" : "remove", "path" : "/items\u{266D}/0" },
+    ///     { "op" : "test", "path" : "/items\u{266D}/0/key", "value" : "gamma" }
+    /// ])).unwrap();
+    /// assert!(replica.apply_patch(&patch).is_err());
+    /// assert_eq!(content, serde_json::to_string(&replica.read(None).unwrap()).unwrap());
+    /// // Also on an empty replica (whose root object has not been created yet)
+    /// let empty = Melda::new_from_url("memory://").unwrap();
+    /// let patch = parse_patch(&json!([
+    ///     { "op" : "add", "path" : "/title", "value" : "Todo" },
+    ///     { "op" : "test", "path" : "/title", "value" : "Shopping" }
+    /// ])).unwrap();
+    /// assert!(empty.apply_patch(&patch).is_err());
+    /// assert!(!empty.has_staging());
+    /// empty.apply_patch(&patch[..1]).unwrap();
+    /// assert_eq!(empty.get("/title").unwrap().unwrap(), "Todo");
+    /// ```
+    pub fn apply_patch(&self, ops: &[PatchOp]) -> Result<()> {
+        let mut paths = vec![];
+        for op in ops {
+            paths.push(parse_json_pointer(op.path())?);
+            if let Some(from) = op.from() {
+                paths.push(parse_json_pointer(from)?);
+            }
+        }
+        if paths.is_empty() {
+            return Ok(());
+        }
+        if paths.iter().any(|p| p.is_empty()) {
+            // The whole document is involved
+            let mut doc = self.get("")?.unwrap_or_else(|| Value::from(Map::new()));
+            crate::jsonpatch::apply_patch(&mut doc, ops)?;
+            return match doc {
+                Value::Object(obj) => self.update(obj).map(|_| ()),
+                _ => bail!("document_not_an_object"),
+            };
+        }
+        // Determine the common prefix of all paths
+        let mut common = paths[0].clone();
+        for p in &paths[1..] {
+            let length = common.iter().zip(p).take_while(|(a, b)| a == b).count();
+            common.truncate(length);
+        }
+        let depth = paths
+            .iter()
+            .map(|p| p.len() - 1)
+            .min()
+            .unwrap()
+            .min(common.len());
+        let (uuid, path, index) = self.find_tracked_ancestor(&common, depth)?;
+        let relative = ops
+            .iter()
+            .map(|op| op.relative_to(index))
+            .collect::<Result<Vec<_>>>()?;
+        self.modify_subtree(&uuid, &path, |subtree| {
+            crate::jsonpatch::apply_patch(subtree, &relative)
+        })?;
+        self.touch_fields(&paths);
+        Ok(())
+    }
+
+    /// Applies a JSON Merge Patch (RFC 7396) to the current state: members of the patch replace
+    /// those of the document (objects are merged recursively) and null members are removed.
+    /// Arrays, including flattened ones, are replaced as a whole: elements of a flattened array
+    /// that keep their identifier (`_id` field) keep their identity, so only the order and the
+    /// objects that actually changed are staged. Since the result is staged like an update,
+    /// objects (identified by `_id`) that no longer appear in the document are deleted.
+    ///
+    /// # Arguments
+    ///
+    /// * `patch` - The merge patch, which must result in an object
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "title" : "Todo", "owner" : { "name" : "Alice", "email" : "alice@example.com" }, "items\u{266D}" : [ { "_id" : "1", "key" : "alpha" } ] }).as_object().unwrap().clone();
+    /// replica.update(object).unwrap();
+    /// replica.apply_merge_patch(&json!({ "title" : null, "owner" : { "email" : null }, "items\u{266D}" : [ { "_id" : "1", "key" : "alpha" }, { "_id" : "2", "key" : "beta" } ] })).unwrap();
+    /// let content = serde_json::to_string(&replica.read(None).unwrap()).unwrap();
+    /// assert_eq!(content, "{\"_id\":\"\u{221A}\",\"items\u{266D}\":[{\"_id\":\"1\",\"key\":\"alpha\"},{\"_id\":\"2\",\"key\":\"beta\"}],\"owner\":{\"name\":\"Alice\"}}");
+    /// assert!(replica.apply_merge_patch(&json!([1, 2, 3])).is_err());
+    /// ```
+    pub fn apply_merge_patch(&self, patch: &Value) -> Result<()> {
+        let mut doc = self.get("")?.unwrap_or_else(|| Value::from(Map::new()));
+        crate::jsonpatch::merge_patch(&mut doc, patch);
+        match doc {
+            Value::Object(obj) => self.update(obj).map(|_| ()),
+            _ => bail!("document_not_an_object"),
+        }
+    }
+
+    /// Returns the staged (but not yet committed) changes as a JSON Patch (RFC 6902), computed
+    /// by comparing the committed state with the current one
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter, jsonpatch::PatchOp};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "title" : "Todo", "items\u{266D}" : [ { "_id" : "1", "key" : "alpha" } ] }).as_object().unwrap().clone();
+    /// replica.update(object).unwrap();
+    /// replica.commit(None).unwrap();
+    /// assert!(replica.staged_patch().unwrap().is_empty());
+    /// replica.set("/items\u{266D}/0/key", json!("beta")).unwrap();
+    /// let patch = replica.staged_patch().unwrap();
+    /// assert_eq!(patch, vec![PatchOp::Replace { path: "/items\u{266D}/0/key".to_string(), value: json!("beta") }]);
+    /// ```
+    pub fn staged_patch(&self) -> Result<Vec<PatchOp>> {
+        let committed = self.state_until(&self.get_anchors())?;
+        let current = self.get("")?.unwrap_or_else(|| Value::from(Map::new()));
+        Ok(crate::jsonpatch::diff(&committed, &current))
+    }
+
+    /// Returns the changes introduced by a block as a JSON Patch (RFC 6902), computed by comparing
+    /// the state at the parents of the block with the state at the block
+    ///
+    /// # Arguments
+    ///
+    /// * `block_id` - Block identifier
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter, jsonpatch::PatchOp};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "items\u{266D}" : [ { "_id" : "1", "key" : "alpha" }, { "_id" : "2", "key" : "beta" } ] }).as_object().unwrap().clone();
+    /// replica.update(object).unwrap();
+    /// replica.commit(None).unwrap();
+    /// replica.set("/items\u{266D}/2", json!({ "_id" : "3", "key" : "gamma" })).unwrap();
+    /// let block_id = replica.commit(None).unwrap().unwrap().first().unwrap().clone();
+    /// let patch = replica.block_patch(&block_id).unwrap();
+    /// assert_eq!(patch, vec![PatchOp::Add { path: "/items\u{266D}/2".to_string(), value: json!({ "_id" : "3", "key" : "gamma" }) }]);
+    /// assert!(replica.block_patch("unknown").is_err());
+    /// ```
+    pub fn block_patch(&self, block_id: &str) -> Result<Vec<PatchOp>> {
+        let block = self
+            .get_block(block_id)?
+            .ok_or_else(|| anyhow!("unknown_block"))?;
+        let before = self.state_until(&block.parents.unwrap_or_default())?;
+        let after = self.state_until(&BTreeSet::from([block_id.to_string()]))?;
+        Ok(crate::jsonpatch::diff(&before, &after))
+    }
+
+    /// Formats a block as a self-contained, human-readable patch file which can be sent by
+    /// email and applied to another replica with [Melda::apply_patch_file]. The patch file
+    /// starts with a header (the block, its parents, the author, the date and the message),
+    /// followed by the changes introduced by the block as a JSON Patch (one operation per line,
+    /// see [Melda::block_patch]), by the block itself and by the values it references (one per
+    /// line, preceded by their digest)
+    ///
+    /// # Arguments
+    ///
+    /// * `block_id` - Block identifier
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, commitinfo::CommitInfo};
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// replica.update(json!({ "title" : "Draft" }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let other = Melda::new_from_url("memory://").unwrap();
+    /// other.meld(&replica).unwrap();
+    /// other.refresh().unwrap();
+    /// replica.set("/title", json!("Final")).unwrap();
+    /// let block = replica.commit_with(CommitInfo::new().author("Alice").message("Fix the title")).unwrap().unwrap();
+    /// let block = block.into_iter().next().unwrap();
+    /// let patch = replica.format_patch(&block).unwrap();
+    /// assert!(patch.contains("Subject: Fix the title\n"));
+    /// assert!(patch.contains(r#"{"op":"replace","path":"/title","value":"Final"}"#));
+    /// assert_eq!(other.apply_patch_file(&patch).unwrap(), Some(block));
+    /// other.refresh().unwrap();
+    /// assert_eq!(other.read(None).unwrap()["title"], "Final");
+    /// assert_eq!(other.apply_patch_file(&patch).unwrap(), None);
+    /// ```
+    pub fn format_patch(&self, block_id: &str) -> Result<String> {
+        let block = self
+            .get_block(block_id)?
+            .ok_or_else(|| anyhow!("unknown_block"))?;
+        let data = self.data.read().expect("cannot_acquire_data_for_reading");
+        let raw = data.read_raw_item(&(block_id.to_string() + DELTA_EXTENSION), 0, 0)?;
+        let encoding = match binary_header_len(&raw)? {
+            Some(_) => PATCH_ENCODING_MESSAGEPACK,
+            None => PATCH_ENCODING_JSON,
+        };
+        let raw_block = decode_value(&raw)?;
+        // The values are reconstructed, so that the patch does not depend on other values
+        let mut values = BTreeMap::<String, Value>::new();
+        let changes = raw_block
+            .as_object()
+            .map(|b| self.parse_raw_block(block_id.to_string(), b.clone()))
+            .transpose()?
+            .and_then(|b| b.changes)
+            .unwrap_or_default();
+        for Change(_, rev, _) in changes {
+            if !(rev.is_deleted() || rev.is_resolved() || rev.is_empty() || rev.is_charcode()) {
+                values.insert(rev.digest().clone(), data.read_value(rev.digest())?);
+            }
+        }
+        drop(data);
+        let mut patch = format!("Block: {}\n", block_id);
+        for parent in block.parents.iter().flatten() {
+            patch += &format!("Parent: {}\n", parent);
+        }
+        let info = block
+            .info
+            .map(CommitInfo::from_map_lenient)
+            .unwrap_or_default();
+        if let Some(author) = &info.author {
+            patch += &format!("Author: {}\n", author);
+        }
+        if let Some(timestamp) = block.timestamp.or(info.timestamp) {
+            patch += &format!("Date: {}\n", format_timestamp(timestamp));
+        }
+        if let Some(message) = &info.message {
+            // Only the first line of the message is shown (the block holds the whole message)
+            patch += &format!("Subject: {}\n", message.lines().next().unwrap_or_default());
+        }
+        patch += &format!("Encoding: {}\n\n{}\n", encoding, PATCH_DIFF_SECTION);
+        for op in self.block_patch(block_id)? {
+            patch += &format!("{}\n", op.to_value());
+        }
+        patch += &format!(
+            "{}\n{}\n{}\n",
+            PATCH_BLOCK_SECTION, raw_block, PATCH_VALUES_SECTION
+        );
+        for (digest, value) in values {
+            patch += &format!("{} {}\n", digest, value);
+        }
+        Ok(patch)
+    }
+
+    /// Applies a patch file produced by [Melda::format_patch]: the block and the values it
+    /// references are stored, so that the block is applied by the next refresh. The parents of
+    /// the block must be known (otherwise a [MeldaError::MissingBlock] error is returned) and
+    /// the stage must be empty. The diff section of the patch file is informative and ignored.
+    /// Returns the stored block, None if the block is already known
+    ///
+    /// # Arguments
+    ///
+    /// * `patch` - The content of the patch file
+    pub fn apply_patch_file(&self, patch: &str) -> Result<Option<String>> {
+        if self.has_staging() {
+            bail!(MeldaError::StageNotEmpty);
+        }
+        let invalid = || anyhow!("invalid_patch_file");
+        let mut lines = patch.lines().map(|l| l.trim_end_matches('\r'));
+        let (mut block_id, mut parents, mut encoding) = (None, vec![], Encoding::Json);
+        for line in lines.by_ref().take_while(|l| !l.is_empty()) {
+            let (key, value) = line.split_once(": ").ok_or_else(invalid)?;
+            match key {
+                "Block" => block_id = Some(value.to_string()),
+                "Parent" => parents.push(value.to_string()),
+                "Encoding" if value == PATCH_ENCODING_JSON => encoding = Encoding::Json,
+                "Encoding" if value == PATCH_ENCODING_MESSAGEPACK => {
+                    encoding = Encoding::MessagePack
+                }
+                "Encoding" => bail!("unsupported_patch_encoding: {}", value),
+                _ => {}
+            }
+        }
+        let block_id = block_id.ok_or_else(invalid)?;
+        let (mut raw_block, mut values) = (None, vec![]);
+        let mut section = "";
+        for line in lines.filter(|l| !l.is_empty()) {
+            if [
+                PATCH_DIFF_SECTION,
+                PATCH_BLOCK_SECTION,
+                PATCH_VALUES_SECTION,
+            ]
+            .contains(&line)
+            {
+                section = line;
+            } else if section == PATCH_BLOCK_SECTION && raw_block.is_none() {
+                raw_block = Some(serde_json::from_str::<Value>(line).map_err(|_| invalid())?);
+            } else if section == PATCH_VALUES_SECTION {
+                let (digest, value) = line.split_once(' ').ok_or_else(invalid)?;
+                let value: Value = serde_json::from_str(value).map_err(|_| invalid())?;
+                if digest_string(&value.to_string()) != digest {
+                    bail!("invalid_patch_value: {}", digest);
+                }
+                values.push((digest.to_string(), value));
+            }
+        }
+        let raw_block = raw_block.ok_or_else(invalid)?;
+        if self.get_block(&block_id)?.is_some() {
+            return Ok(None);
+        }
+        if let Some(parent) = parents.into_iter().find(|p| {
+            !self
+                .blocks
+                .read()
+                .expect("cannot_acquire_blocks_for_reading")
+                .contains_key(p)
+        }) {
+            bail!(MeldaError::MissingBlock { id: parent });
+        }
+        let blockdata = encode_value(&raw_block, encoding)?;
+        if digest_bytes_with(self.digest, &blockdata) != block_id {
+            bail!(MeldaError::CorruptBlock { id: block_id });
+        }
+        let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
+        for (digest, value) in values {
+            data.write_raw_value(&digest, value)?;
+        }
+        data.pack()?;
+        // The values are held by the pack just written instead of the packs of the block
+        let missing: Vec<String> = raw_block
+            .get(PACK_FIELD)
+            .and_then(|k| k.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|p| p.as_str())
+            .filter(|p| !data.is_available_pack(p))
+            .map(|p| p.to_string())
+            .collect();
+        if !missing.is_empty() {
+            data.supersede(&missing)?;
+        }
+        data.write_raw_item(&(block_id.clone() + DELTA_EXTENSION), &blockdata)?;
+        Ok(Some(block_id))
+    }
+
+    /// Evaluates a JSONPath expression against the current state, see [JsonPath]. Each match
+    /// carries its location (as a JSON Pointer) and the identifier of the object it belongs to.
+    ///
+    /// # Arguments
+    ///
+    /// * `expr` - The JSONPath expression
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+    /// let object = json!({ "tasks\u{266D}" : [ { "_id" : "1", "priority" : "high" }, { "_id" : "2", "priority" : "low" }, { "_id" : "3", "priority" : "high" } ] }).as_object().unwrap().clone();
+    /// replica.update(object).unwrap();
+    /// let block_id = replica.commit(None).unwrap().unwrap().first().unwrap().clone();
+    /// let matches = replica.query("$.tasks\u{266D}[?(@.priority=='high')]").unwrap();
+    /// let ids : Vec<String> = matches.into_iter().map(|m| m.id.unwrap()).collect();
+    /// assert_eq!(ids, vec!["1", "3"]);
+    /// replica.set("/tasks\u{266D}/1/priority", json!("high")).unwrap();
+    /// assert_eq!(replica.query("$.tasks\u{266D}[?(@.priority=='high')]").unwrap().len(), 3);
+    /// assert_eq!(replica.query_at("$.tasks\u{266D}[?(@.priority=='high')]", &block_id).unwrap().len(), 2);
+    /// ```
+    pub fn query(&self, expr: &str) -> Result<Vec<QueryMatch>> {
+        let path = JsonPath::parse(expr)?;
+        let doc = self.get("")?.unwrap_or_else(|| Value::from(Map::new()));
+        Ok(path.query(&doc))
+    }
+
+    /// Evaluates a JSONPath expression against the state at the given block, see [Melda::query]
+    ///
+    /// # Arguments
+    ///
+    /// * `expr` - The JSONPath expression
+    /// * `block_id` - Block identifier
+    pub fn query_at(&self, expr: &str, block_id: &str) -> Result<Vec<QueryMatch>> {
+        let path = JsonPath::parse(expr)?;
+        if self.get_block(block_id)?.is_none() {
+            bail!("unknown_block");
+        }
+        let doc = self.state_until(&BTreeSet::from([block_id.to_string()]))?;
+        Ok(path.query(&doc))
+    }
+
+    // Expands one level of a flattened value: references are replaced by the referenced objects
+    // (or arrays), whose flattened fields are not resolved. Returns None for unknown references.
+    pub(super) fn expand_flattened(&self, value: &Value) -> Result<Option<Value>> {
+        match value {
+            Value::String(s) => {
+                if s.starts_with(STRING_ESCAPE_PREFIX) {
+                    Ok(Some(Value::from(unescape(s))))
+                } else if is_array_descriptor(s) {
+                    let descriptor = self
+                        .load_flattened_object(s)?
+                        .ok_or_else(|| anyhow!("unknown_descriptor_object"))?;
+                    let order = descriptor
+                        .get(ARRAY_DESCRIPTOR_ORDER_FIELD)
+                        .and_then(|o| o.as_array())
+                        .ok_or_else(|| anyhow!("expecting_order_field_in_descriptor"))?;
+                    let mut array = vec![];
+                    for uuid in order {
+                        if let Some(uuid) = uuid.as_str() {
+                            if self.load_flattened_object(uuid)?.is_some() {
+                                array.push(Value::from(uuid));
+                            }
+                        }
+                    }
+                    Ok(Some(Value::from(array)))
+                } else {
+                    match self.load_flattened_object(s)? {
+                        Some(mut object) => {
+                            object.insert(ID_FIELD.to_string(), Value::from(s.clone()));
+                            Ok(Some(Value::from(object)))
+                        }
+                        None => Ok(None),
+                    }
+                }
+            }
+            _ => Ok(Some(value.clone())),
+        }
+    }
+
+    // Finds the nearest tracked object among the first depth + 1 nodes along the path (the root
+    // is always tracked, even if it has not been created yet). Returns the identifier of the
+    // object, the path that was used to flatten it and the number of tokens leading to it.
+    // Nothing is staged.
+    pub(super) fn find_tracked_ancestor(
+        &self,
+        tokens: &[String],
+        depth: usize,
+    ) -> Result<(String, Vec<String>, usize)> {
+        if self.load_flattened_object(ROOT_ID)?.is_none() {
+            return Ok((ROOT_ID.to_string(), vec![], 0));
+        }
+        let mut anchor = (ROOT_ID.to_string(), vec![], 0);
+        let mut path: Vec<String> = vec![];
+        let mut current = Value::from(ROOT_ID);
+        let mut flattened = true;
+        for index in 0..=depth.min(tokens.len()) {
+            if flattened {
+                if let Value::String(s) = &current {
+                    if !s.starts_with(STRING_ESCAPE_PREFIX) && !is_array_descriptor(s) {
+                        anchor = (s.clone(), path.clone(), index);
+                        path.push(s.clone());
+                    }
+                }
+            }
+            if index == depth || index == tokens.len() {
+                break;
+            }
+            let token = &tokens[index];
+            if flattened {
+                current = self
+                    .expand_flattened(&current)?
+                    .ok_or_else(|| anyhow!("path_not_found"))?;
+                if is_flattened_field(token) && current.is_object() {
+                    path.push(token.clone());
+                }
+            }
+            flattened = flattened && (current.is_array() || is_flattened_field(token));
+            current = pointer_child(&current, token)
+                .ok_or_else(|| anyhow!("path_not_found"))?
+                .clone();
+        }
+        Ok(anchor)
+    }
+
+    // Materializes the subtree of a tracked object (a missing root is materialized as an empty
+    // object), applies the modification and stages the flattened result (objects that have
+    // disappeared from the subtree are deleted). The result is validated before anything is
+    // staged, so that a failing modification leaves the state unchanged.
+    fn modify_subtree<F>(&self, uuid: &str, path: &[String], modify: F) -> Result<()>
+    where
+        F: FnOnce(&mut Value) -> Result<()>,
+    {
+        let generation = self.generation.load(Ordering::SeqCst);
+        let mut visited = BTreeSet::new();
+        let mut subtree = match self.load_flattened_object(uuid)? {
+            Some(object) => self.resolve_flattened_object(uuid, object, &mut visited)?,
+            None if uuid == ROOT_ID => json!({ ID_FIELD: ROOT_ID }),
+            None => bail!("path_not_found"),
+        };
+        modify(&mut subtree)?;
+        if let (true, Value::Object(object)) = (self.strict, &subtree) {
+            let pointer = match self.get_location(uuid)? {
+                Some(location) => parse_json_pointer(&location.pointer)?,
+                None => vec![],
+            };
+            StrictValidator::default().validate_object(object, path, &pointer)?;
+        }
+        let mut extracted_objects = HashMap::<String, Map<String, Value>>::new();
+        let id = flatten(&mut extracted_objects, &subtree, path);
+        if id.as_str() != Some(uuid) {
+            bail!("cannot_change_object_identifier");
+        }
+        let deleted: Vec<&String> = visited
+            .iter()
+            .filter(|old| !extracted_objects.contains_key(*old))
+            .collect();
+        // Objects whose history has been removed cannot be staged
+        {
+            let docs_r = self
+                .documents
+                .read()
+                .expect("cannot_acquire_documents_for_reading");
+            for uuid in deleted.iter().copied().chain(extracted_objects.keys()) {
+                if let Some(rt) = docs_r.get(uuid) {
+                    if rt.lock().unwrap().get_winner().is_none() {
+                        bail!("object_has_no_winner");
+                    }
+                }
+            }
+        }
+        for old in deleted {
+            self.delete_object(old)?;
+        }
+        for (uuid, obj) in extracted_objects {
+            self.update_object(&uuid, obj)?;
+        }
+        self.relocate_subtree(uuid, &visited, generation)
+    }
+}
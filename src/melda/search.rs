@@ -0,0 +1,294 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use super::Melda;
+use crate::constants::{ID_FIELD, ROOT_ID};
+use crate::secondaryindex::{IndexChanges, IndexKey, SecondaryIndex};
+use crate::textindex::{text_fields, TextIndex};
+use crate::utils::{is_array_descriptor, is_flattened_field};
+use anyhow::{anyhow, bail, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashSet;
+
+/// String matching a full-text query, see [Melda::search]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SearchHit {
+    /// Identifier of the object containing the string
+    pub uuid: String,
+    /// JSON Pointer to the string from the root of the document
+    pub path: String,
+}
+
+impl Melda {
+    /// Declares a secondary index on a field of the elements of a collection (a flattened array
+    /// of objects in the root object), see [Melda::lookup]. The index is built immediately, then
+    /// maintained incrementally: on commit and refresh (and before each lookup) only the
+    /// elements which changed are indexed again. Indexes are kept in memory and must be
+    /// declared again whenever the replica is opened. Declaring an existing index has no effect
+    ///
+    /// # Arguments
+    ///
+    /// * `collection` - The field of the root object holding the collection
+    /// * `field` - The indexed field of the elements (which cannot be a flattened field)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+    /// replica.update(json!({ "tasks\u{266D}" : [
+    ///     { "_id" : "1", "title" : "Buy milk", "priority" : 2 },
+    ///     { "_id" : "2", "title" : "Call Bob", "priority" : 1 },
+    ///     { "_id" : "3", "title" : "Pay bills", "priority" : 2 }
+    /// ] }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// replica.create_index("tasks\u{266D}", "priority").unwrap();
+    /// assert_eq!(replica.lookup("tasks\u{266D}", "priority", &json!(2)).unwrap(), ["1", "3"]);
+    /// // The index follows the changes
+    /// replica.set("/tasks\u{266D}/0/priority", json!(1)).unwrap();
+    /// replica.commit(None).unwrap();
+    /// assert_eq!(replica.lookup("tasks\u{266D}", "priority", &json!(1)).unwrap(), ["1", "2"]);
+    /// let urgent = replica.lookup_range("tasks\u{266D}", "priority", None, Some(&json!(1))).unwrap();
+    /// assert_eq!(urgent, ["1", "2"]);
+    /// assert!(replica.lookup("tasks\u{266D}", "title", &json!("Buy milk")).is_err());
+    /// ```
+    pub fn create_index(&self, collection: &str, field: &str) -> Result<()> {
+        if is_flattened_field(field) || field == ID_FIELD {
+            bail!("invalid_index_field");
+        }
+        let mut indexes = self.indexes.lock().unwrap();
+        let key = (collection.to_string(), field.to_string());
+        if indexes.contains_key(&key) {
+            return Ok(());
+        }
+        self.index_changes.lock().unwrap().tracking = true;
+        let mut index = SecondaryIndex::new(collection, field);
+        self.update_index(&mut index, true, &HashSet::new())?;
+        indexes.insert(key, index);
+        Ok(())
+    }
+
+    /// Drops a secondary index (see [Melda::create_index]), returns false if the index does
+    /// not exist
+    ///
+    /// # Arguments
+    ///
+    /// * `collection` - The field of the root object holding the collection
+    /// * `field` - The indexed field of the elements
+    pub fn drop_index(&self, collection: &str, field: &str) -> bool {
+        let mut indexes = self.indexes.lock().unwrap();
+        let dropped = indexes
+            .remove(&(collection.to_string(), field.to_string()))
+            .is_some();
+        if indexes.is_empty() && self.text_index.lock().unwrap().is_none() {
+            *self.index_changes.lock().unwrap() = IndexChanges::default();
+        }
+        dropped
+    }
+
+    /// Returns the secondary indexes (collection and field), see [Melda::create_index]
+    pub fn indexes(&self) -> Vec<(String, String)> {
+        self.indexes.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Returns the identifiers of the elements of a collection whose field has the given value
+    /// (in the current state, including staged changes), using a secondary index declared with
+    /// [Melda::create_index]. Identifiers are sorted
+    ///
+    /// # Arguments
+    ///
+    /// * `collection` - The field of the root object holding the collection
+    /// * `field` - The indexed field of the elements
+    /// * `value` - The value of the field
+    pub fn lookup(&self, collection: &str, field: &str, value: &Value) -> Result<Vec<String>> {
+        self.lookup_range(collection, field, Some(value), Some(value))
+    }
+
+    /// Returns the identifiers of the elements of a collection whose field has a value between
+    /// the given bounds (inclusive, None for no bound), ordered by value then by identifier,
+    /// using a secondary index declared with [Melda::create_index]. Values of different types
+    /// are ordered by type: null, booleans, numbers, strings, then arrays and objects
+    ///
+    /// # Arguments
+    ///
+    /// * `collection` - The field of the root object holding the collection
+    /// * `field` - The indexed field of the elements
+    /// * `from` - The lower bound
+    /// * `to` - The upper bound
+    pub fn lookup_range(
+        &self,
+        collection: &str,
+        field: &str,
+        from: Option<&Value>,
+        to: Option<&Value>,
+    ) -> Result<Vec<String>> {
+        self.update_indexes()?;
+        let indexes = self.indexes.lock().unwrap();
+        let index = indexes
+            .get(&(collection.to_string(), field.to_string()))
+            .ok_or_else(|| anyhow!("unknown_index"))?;
+        let bound = |value: Option<&Value>| match value {
+            Some(value) => std::ops::Bound::Included(IndexKey::new(value)),
+            None => std::ops::Bound::Unbounded,
+        };
+        Ok(index.range(bound(from), bound(to)))
+    }
+
+    /// Returns the strings of the documents containing all the words of the query (words are
+    /// made of alphanumeric characters and compared ignoring the case), ordered by path. Strings
+    /// of objects which are not reachable from a document are ignored. Requires the full-text
+    /// index to be enabled, see [crate::builder::MeldaBuilder::full_text_index]
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The words to search
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::{Melda, SearchHit};
+    /// use serde_json::json;
+    /// let replica = Melda::builder().url("memory://").full_text_index(true).open().unwrap();
+    /// replica.update(json!({ "title" : "Notes", "items\u{266D}" : [
+    ///     { "_id" : "1", "text" : "Melda is a JSON CRDT" },
+    ///     { "_id" : "2", "text" : "Use a database", "tags" : [ "Database", "storage" ] }
+    /// ] }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let paths: Vec<String> = replica.search("database").unwrap().into_iter().map(|h| h.path).collect();
+    /// assert_eq!(paths, ["/items\u{266D}/1/tags/0", "/items\u{266D}/1/text"]);
+    /// assert_eq!(replica.search("json crdt").unwrap(), [SearchHit {
+    ///     uuid: "1".to_string(), path: "/items\u{266D}/0/text".to_string()
+    /// }]);
+    /// replica.set("/items\u{266D}/1/text", json!("Use a file")).unwrap();
+    /// assert_eq!(replica.search("database").unwrap().len(), 1);
+    /// // The index must be enabled
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// assert!(replica.search("database").is_err());
+    /// ```
+    pub fn search(&self, query: &str) -> Result<Vec<SearchHit>> {
+        if self.text_index.lock().unwrap().is_none() {
+            bail!("full_text_index_disabled");
+        }
+        self.update_indexes()?;
+        let fields = match self.text_index.lock().unwrap().as_ref() {
+            Some(index) => index.search(query),
+            None => vec![],
+        };
+        let mut hits = vec![];
+        for (uuid, pointer) in fields {
+            if let Some(location) = self.get_location(&uuid)? {
+                hits.push(SearchHit {
+                    uuid,
+                    path: location.pointer + &pointer,
+                });
+            }
+        }
+        hits.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(hits)
+    }
+
+    // Updates the secondary indexes with the objects changed since the last update
+    pub(super) fn update_indexes(&self) -> Result<()> {
+        let mut indexes = self.indexes.lock().unwrap();
+        let mut text_index = self.text_index.lock().unwrap();
+        if indexes.is_empty() && text_index.is_none() {
+            return Ok(());
+        }
+        let (all, changed) = self.index_changes.lock().unwrap().take();
+        if !all && changed.is_empty() {
+            return Ok(());
+        }
+        let mut result = Ok(());
+        for index in indexes.values_mut() {
+            result = result.and_then(|_| self.update_index(index, all, &changed));
+        }
+        if let Some(text_index) = text_index.as_mut() {
+            result = result.and_then(|_| self.update_text_index(text_index, all, &changed));
+        }
+        if result.is_err() {
+            // The indexes are rebuilt by the next update
+            self.index_changes.lock().unwrap().record_all();
+        }
+        result
+    }
+
+    // Updates the full-text index with the changed objects (or rebuilds it)
+    fn update_text_index(
+        &self,
+        index: &mut TextIndex,
+        all: bool,
+        changed: &HashSet<String>,
+    ) -> Result<()> {
+        let uuids: Vec<String> = if all {
+            index.clear();
+            let docs_r = self
+                .documents
+                .read()
+                .expect("failed_to_acquire_documents_for_reading");
+            docs_r.keys().cloned().collect()
+        } else {
+            changed.iter().cloned().collect()
+        };
+        for uuid in uuids.iter().filter(|uuid| !is_array_descriptor(uuid)) {
+            match self.load_flattened_object(uuid)? {
+                Some(object) => index.set(uuid, text_fields(&object)),
+                None => index.remove(uuid),
+            }
+        }
+        Ok(())
+    }
+
+    // Updates a secondary index with the changed objects (or rebuilds it)
+    fn update_index(
+        &self,
+        index: &mut SecondaryIndex,
+        all: bool,
+        changed: &HashSet<String>,
+    ) -> Result<()> {
+        let field = index.field.clone();
+        let key = |uuid: &str| -> Result<Option<IndexKey>> {
+            Ok(self
+                .load_flattened_object(uuid)?
+                .and_then(|object| object.get(&field).map(IndexKey::new)))
+        };
+        let membership_changed = all
+            || changed.contains(ROOT_ID)
+            || index
+                .descriptor
+                .as_ref()
+                .is_some_and(|d| changed.contains(d));
+        if membership_changed {
+            // A field which is not a collection (anymore) has no elements
+            let (descriptor, order) = self.collection_order(&index.collection).unwrap_or_default();
+            index.descriptor = descriptor;
+            let members: HashSet<&str> = order.iter().map(String::as_str).collect();
+            index.retain(|uuid| members.contains(uuid));
+            for uuid in &order {
+                if all || changed.contains(uuid) || !index.contains(uuid) {
+                    let key = key(uuid)?;
+                    index.set(uuid, key);
+                }
+            }
+        } else {
+            for uuid in changed {
+                if index.contains(uuid) {
+                    let key = key(uuid)?;
+                    index.set(uuid, key);
+                }
+            }
+        }
+        Ok(())
+    }
+}
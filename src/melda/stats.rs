@@ -0,0 +1,326 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use super::{Block, Melda, REVISION_ENTRY_SIZE};
+use crate::constants::{DELTA_EXTENSION, PACK_EXTENSION};
+use crate::utils::{approximate_object_size, approximate_size, is_array_descriptor};
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+/// Replica which committed blocks, see [Melda::replicas]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ReplicaSummary {
+    /// Number of blocks committed by the replica
+    pub blocks: usize,
+    /// Latest blocks committed by the replica (which are not ancestors of other blocks of the
+    /// replica)
+    pub latest: BTreeSet<String>,
+    /// Commit time of the most recent block (in milliseconds since the Unix epoch)
+    pub timestamp: Option<u64>,
+}
+
+/// Statistics of a replica, see [Melda::stats]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RepositoryStats {
+    /// Number of objects whose winning revision is not deleted (array descriptors excluded)
+    pub objects: usize,
+    /// Number of deleted objects, whose revisions are still kept
+    pub tombstones: usize,
+    /// Number of objects in conflict
+    pub conflicts: usize,
+    /// Number of revisions of all objects and arrays
+    pub revisions: usize,
+    /// Number of delta blocks in the storage
+    pub blocks: usize,
+    /// Size (in bytes) of the delta blocks
+    pub block_bytes: usize,
+    /// Number of data packs in the storage
+    pub packs: usize,
+    /// Size (in bytes) of the data packs (indexes excluded)
+    pub pack_bytes: usize,
+}
+
+/// Estimated memory used by a replica (in bytes), see [Melda::memory_usage]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Revision trees of the objects (never released)
+    pub revisions: usize,
+    /// Parsed blocks and cached raw blocks
+    pub blocks: usize,
+    /// Caches of materialized objects (including shared reads), array orders, values, unchanged
+    /// fields and locations
+    pub caches: usize,
+    /// Index locating the committed values inside the packs
+    pub index: usize,
+}
+
+impl MemoryUsage {
+    /// Returns the total estimated memory
+    pub fn total(&self) -> usize {
+        self.revisions + self.blocks + self.caches + self.index
+    }
+}
+
+impl Melda {
+    /// Returns the replicas which committed the known blocks (blocks written by older versions
+    /// do not record their replica), with their latest blocks
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// let alice = Melda::builder().url("memory://").replica_id("alice").open().unwrap();
+    /// let bob = Melda::builder().url("memory://").replica_id("bob").open().unwrap();
+    /// for i in 0..2 {
+    ///     alice.update(json!({ "key" : i }).as_object().unwrap().clone()).unwrap();
+    ///     alice.commit(None).unwrap();
+    /// }
+    /// bob.update(json!({ "other" : true }).as_object().unwrap().clone()).unwrap();
+    /// let block = bob.commit(None).unwrap().unwrap().into_iter().next().unwrap();
+    /// alice.meld(&bob).unwrap();
+    /// alice.refresh().unwrap();
+    /// let replicas = alice.replicas();
+    /// assert_eq!(replicas.keys().collect::<Vec<_>>(), vec!["alice", "bob"]);
+    /// assert_eq!(replicas["alice"].blocks, 2);
+    /// assert_eq!(replicas["alice"].latest.len(), 1);
+    /// assert_eq!(replicas["bob"].latest, [block].into());
+    /// ```
+    pub fn replicas(&self) -> BTreeMap<String, ReplicaSummary> {
+        let blocks = self.blocks.read().unwrap();
+        let mut replicas = BTreeMap::<String, ReplicaSummary>::new();
+        let mut ancestors = BTreeMap::<String, HashSet<String>>::new();
+        for (id, block) in blocks.iter() {
+            let block = block.read().unwrap();
+            if let Some(replica) = &block.replica {
+                let summary = replicas.entry(replica.clone()).or_default();
+                summary.blocks += 1;
+                summary.latest.insert(id.clone());
+                summary.timestamp = summary.timestamp.max(block.timestamp);
+                // Collect the ancestors of the blocks of the replica
+                let visited = ancestors.entry(replica.clone()).or_default();
+                let mut stack: Vec<String> = block.parents.iter().flatten().cloned().collect();
+                while let Some(current) = stack.pop() {
+                    if visited.insert(current.clone()) {
+                        if let Some(b) = blocks.get(&current) {
+                            stack.extend(b.read().unwrap().parents.iter().flatten().cloned());
+                        }
+                    }
+                }
+            }
+        }
+        for (replica, summary) in replicas.iter_mut() {
+            summary.latest.retain(|id| !ancestors[replica].contains(id));
+        }
+        replicas
+    }
+
+    /// Returns statistics about the objects of the replica (including staged changes) and the
+    /// items of the storage, for example to decide when to compact (see [Melda::compact]) or
+    /// to collect garbage (see [Melda::gc])
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// replica.update(json!({ "items\u{266D}" : [ { "_id" : "a" }, { "_id" : "b" } ] }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// replica.update(json!({ "items\u{266D}" : [ { "_id" : "a" } ] }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let stats = replica.stats().unwrap();
+    /// // The root object and "a", while "b" has been deleted
+    /// assert_eq!(stats.objects, 2);
+    /// assert_eq!(stats.tombstones, 1);
+    /// assert_eq!(stats.conflicts, 0);
+    /// assert_eq!(stats.blocks, 2);
+    /// assert!(stats.pack_bytes > 0);
+    /// ```
+    pub fn stats(&self) -> Result<RepositoryStats> {
+        let mut stats = RepositoryStats::default();
+        for (uuid, rt) in self.documents.read().unwrap().iter() {
+            let rt = rt.lock().unwrap();
+            stats.revisions += rt.get_revisions().len();
+            if is_array_descriptor(uuid) {
+                continue;
+            }
+            if rt.get_winner().is_some_and(|w| w.is_deleted()) {
+                stats.tombstones += 1;
+            } else {
+                stats.objects += 1;
+            }
+            if rt.get_leafs().len() > 1 {
+                stats.conflicts += 1;
+            }
+        }
+        let adapter = self.get_adapter();
+        let adapter = adapter.read().unwrap();
+        for bid in adapter.list_objects(DELTA_EXTENSION)? {
+            stats.blocks += 1;
+            stats.block_bytes += adapter.object_size(&(bid + DELTA_EXTENSION))?;
+        }
+        for pack in adapter.list_objects(PACK_EXTENSION)? {
+            stats.packs += 1;
+            stats.pack_bytes += adapter.object_size(&(pack + PACK_EXTENSION))?;
+        }
+        Ok(stats)
+    }
+
+    /// Returns the estimated memory used by the replica. If a memory budget is set (see
+    /// [crate::builder::MeldaBuilder::memory_budget]) the caches, the cached blocks and the index of the packs
+    /// are released whenever the budget is exceeded (after a read, a commit, a reload or a
+    /// refresh), and loaded again on demand
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// let unbounded = Melda::new_from_url("memory://").unwrap();
+    /// let bounded = Melda::builder().url("memory://").memory_budget(1024).open().unwrap();
+    /// for replica in [&unbounded, &bounded] {
+    ///     for i in 0..4 {
+    ///         let items: Vec<_> = (0..32).map(|j| json!({ "_id" : j.to_string(), "value" : i * j })).collect();
+    ///         replica.update(json!({ "items\u{266D}" : items }).as_object().unwrap().clone()).unwrap();
+    ///         replica.commit(None).unwrap();
+    ///     }
+    ///     replica.read(None).unwrap();
+    /// }
+    /// assert!(unbounded.memory_usage().caches > 0);
+    /// let usage = bounded.memory_usage();
+    /// assert_eq!((usage.caches, usage.index), (0, 0));
+    /// assert!(usage.revisions > 0 && usage.total() > 1024); // Revision trees are never released
+    /// // Released data is loaded again on demand
+    /// assert_eq!(bounded.read(None).unwrap(), unbounded.read(None).unwrap());
+    /// ```
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let revisions = self
+            .documents
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(uuid, rt)| {
+                let rt_r = rt.lock().expect("cannot_acquire_revision_tree_for_reading");
+                uuid.len() + rt_r.get_revisions().len() * REVISION_ENTRY_SIZE
+            })
+            .sum();
+        let ids = |ids: &Option<BTreeSet<String>>| -> usize {
+            ids.iter().flatten().map(|id| id.len()).sum()
+        };
+        let parsed_blocks: usize = self
+            .blocks
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(bid, block)| {
+                let block = block.read().unwrap();
+                std::mem::size_of::<Block>()
+                    + bid.len()
+                    + ids(&block.parents)
+                    + ids(&block.packs)
+                    + ids(&block.squashed)
+                    + ids(&block.grafted)
+                    + block.info.as_ref().map_or(0, approximate_object_size)
+                    + block
+                        .changes
+                        .as_ref()
+                        .map_or(0, |c| c.len() * REVISION_ENTRY_SIZE)
+            })
+            .sum();
+        let blocks = parsed_blocks + self.block_cache.lock().unwrap().size();
+        let objects: usize = self
+            .object_cache
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|((uuid, _, leafs), object)| {
+                uuid.len()
+                    + (leafs.len() + 1) * REVISION_ENTRY_SIZE
+                    + approximate_object_size(object)
+            })
+            .sum();
+        let descriptors: usize = self
+            .array_descriptors_cache
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, descriptor)| {
+                let order = descriptor.get_order().iter();
+                order
+                    .chain(descriptor.get_patch())
+                    .flatten()
+                    .map(approximate_size)
+                    .sum::<usize>()
+            })
+            .sum();
+        let fields: usize = self
+            .clean_fields
+            .lock()
+            .unwrap()
+            .fields
+            .iter()
+            .map(|(key, field)| {
+                key.len()
+                    + approximate_size(&field.value)
+                    + approximate_size(&field.flattened)
+                    + field.objects.len() * REVISION_ENTRY_SIZE
+            })
+            .sum();
+        let locations = self.locations.lock().unwrap().size();
+        let shared: usize = self
+            .shared
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(uuid, entry)| uuid.len() + entry.size + REVISION_ENTRY_SIZE)
+            .sum();
+        let (values, index) = self.data.read().unwrap().memory_usage();
+        MemoryUsage {
+            revisions,
+            blocks,
+            caches: objects + descriptors + fields + locations + shared + values,
+            index,
+        }
+    }
+
+    // Releases the caches (and then the cached blocks and the index of the packs) if the
+    // estimated memory usage exceeds the budget. Released data is loaded again on demand
+    pub(super) fn enforce_memory_budget(&self) -> Result<()> {
+        let budget = match self.memory_budget {
+            Some(budget) => budget,
+            None => return Ok(()),
+        };
+        if self.memory_usage().total() <= budget {
+            return Ok(());
+        }
+        self.object_cache.lock().unwrap().clear();
+        self.array_descriptors_cache.lock().unwrap().clear();
+        self.clean_fields.lock().unwrap().fields.clear();
+        self.locations.lock().unwrap().clear();
+        self.shared.lock().unwrap().clear();
+        let data = self.data.read().expect("cannot_acquire_data_for_reading");
+        data.clear_cache();
+        drop(data);
+        if self.memory_usage().total() <= budget {
+            return Ok(());
+        }
+        if let Err(e) = self.block_cache.lock().unwrap().evict() {
+            log::warn!("cannot_save_block_cache: {}", e);
+        }
+        self.data
+            .read()
+            .expect("cannot_acquire_data_for_reading")
+            .unload_index()
+    }
+}
@@ -0,0 +1,226 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use super::Melda;
+use crate::revision::Revision;
+use crate::utils::{digest_object, is_array_descriptor};
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Map, Value};
+use std::collections::HashSet;
+
+impl Melda {
+    /// Stages a full snapshot for array descriptors
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+    /// use std::sync::{Arc, Mutex, RwLock};
+    /// use serde_json::{Map, Value,json};
+    /// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+    /// let adapter = Arc::new(RwLock::new(adapter));
+    /// let replica = Melda::new(adapter.clone()).expect("cannot_initialize_crdt");
+    /// let object = json!({ "somekey\u{266D}" : [ { "_id" : "somedata", "value" : 1u32 }, { "_id" : "otherdata", "value" : 2u32 } ] }).as_object().unwrap().clone();
+    /// replica.update(object).unwrap();
+    /// replica.commit(None).unwrap();
+    /// assert!(!replica.has_staging());
+    /// replica.stage_full_snapshot().unwrap();
+    /// assert!(!replica.has_staging());
+    /// let object = json!({ "somekey\u{266D}" : [ { "_id" : "somedata2", "value" : 1u32 }, { "_id" : "otherdata", "value" : 2u32 } ] }).as_object().unwrap().clone();
+    /// replica.update(object).unwrap();
+    /// replica.commit(None).unwrap();
+    /// assert!(!replica.has_staging());
+    /// replica.stage_full_snapshot().unwrap();
+    /// assert!(replica.has_staging());
+    /// let stage = replica.stage().unwrap();
+    /// let content = serde_json::to_string(&stage).unwrap();
+    /// assert_eq!(content,"{\"c\":[[\"^5dce0c82036c35bb319c8e5085004949a604475936bb5a9bb124a95fd793aa6c\",\"2-97b7a6993ee290384d32087608174bbab48de824406166f8b78c24a3bf1e1a1c_986c918\",\"bdb1432c17447b65ac69463ecbc9cde3b8945388dac19a52eb3a7c0c0d5ce7f8\"]],\"o\":{\"bdb1432c17447b65ac69463ecbc9cde3b8945388dac19a52eb3a7c0c0d5ce7f8\":{\"A\":[\"somedata2\",\"otherdata\"]}}}");
+    /// replica.commit(None).unwrap();
+    /// assert!(!replica.has_staging());
+    /// replica.stage_full_snapshot().unwrap();
+    /// assert!(!replica.has_staging());
+    /// let readback = replica.read(None).unwrap();
+    /// let content = serde_json::to_string(&readback).unwrap();
+    /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\u{266D}\":[{\"_id\":\"somedata2\",\"value\":1},{\"_id\":\"otherdata\",\"value\":2}]}", content);
+    /// let object = json!({ "somekey\u{266D}" : [ { "_id" : "somedata2", "value" : 1u32 }, { "_id" : "otherdata2", "value" : 3u32 } ] }).as_object().unwrap().clone();
+    /// replica.update(object).unwrap();
+    /// let readback = replica.read(None).unwrap();
+    /// let content = serde_json::to_string(&readback).unwrap();
+    /// assert_eq!("{\"_id\":\"\u{221A}\",\"somekey\u{266D}\":[{\"_id\":\"somedata2\",\"value\":1},{\"_id\":\"otherdata2\",\"value\":3}]}", content);
+    pub fn stage_full_snapshot(&self) -> Result<()> {
+        for (uuid, rt) in self.documents.read().unwrap().iter() {
+            if is_array_descriptor(uuid) {
+                let mut rt_w = rt.lock().expect("cannot_acquire_revision_tree_for_writing");
+                let winning_revision = rt_w.get_winner().ok_or_else(|| anyhow!("no_winner"))?;
+                if winning_revision.is_deleted() {
+                    continue;
+                }
+                // If any of the leafs is a diff we need to snapshot
+                for leaf in rt_w.get_leafs() {
+                    let base_descriptor = self.read_array_descriptor(leaf)?;
+                    if base_descriptor.is_diff() {
+                        // Read the full array object
+                        let object = self
+                            .read_object_at_revision(uuid, &rt_w, winning_revision)
+                            .unwrap();
+                        let digest = digest_object(&object).unwrap(); // Digest of the current object
+                        let rev = Revision::new_updated(digest, winning_revision);
+                        let winning_revision = winning_revision.clone();
+                        rt_w.add(rev.clone(), Some(winning_revision.clone()), true);
+                        self.changed_objects([uuid.as_str()]);
+                        let mut data_w =
+                            self.data.write().expect("cannot_acquire_data_for_writing");
+                        data_w.write_object(&rev, object).unwrap();
+                        drop(data_w);
+                        break;
+                    }
+                }
+            }
+        }
+        self.record(|| json!({ "op": "stage_full_snapshot" }));
+        Ok(())
+    }
+
+    /// Returns the events recorded since the replica was opened, if tracing is enabled (see
+    /// [crate::builder::MeldaBuilder::trace]). The trace is a JSON array holding the staged operations, the
+    /// commits and the applied blocks (along with the packs they reference): it can be attached
+    /// to a bug report and replayed with [Melda::replay]
+    pub fn trace(&self) -> Option<Value> {
+        self.trace
+            .as_ref()
+            .map(|trace| Value::from(trace.lock().unwrap().clone()))
+    }
+
+    /// Replays a trace recorded by [Melda::trace], reproducing the state of the traced replica.
+    /// The replica must be empty and configured like the traced one (for example with the same
+    /// squash policy): every commit is checked to produce the same block as the original one
+    /// (the replayed commits record the identifier of the traced replica)
+    ///
+    /// # Arguments
+    ///
+    /// * `trace` - The recorded trace
+    ///
+    /// # Example
+    /// ```
+    /// use melda::melda::Melda;
+    /// use serde_json::json;
+    /// let replica = Melda::builder().url("memory://").trace(true).open().unwrap();
+    /// replica.update(json!({ "items\u{266D}" : [ { "_id" : "a", "v" : 1 } ] }).as_object().unwrap().clone()).unwrap();
+    /// replica.commit(None).unwrap();
+    /// let other = Melda::new_from_url("memory://").unwrap();
+    /// other.meld(&replica).unwrap();
+    /// other.refresh().unwrap();
+    /// other.set("/items\u{266D}/1", json!({ "_id" : "b", "v" : 2 })).unwrap();
+    /// other.commit(None).unwrap();
+    /// replica.meld(&other).unwrap();
+    /// replica.refresh().unwrap();
+    /// replica.set("/items\u{266D}/0/v", json!(3)).unwrap();
+    /// let trace = replica.trace().unwrap();
+    /// let replayed = Melda::new_from_url("memory://").unwrap();
+    /// replayed.replay(&trace).unwrap();
+    /// assert_eq!(replayed.read(None).unwrap(), replica.read(None).unwrap());
+    /// assert_eq!(replayed.get_anchors(), replica.get_anchors());
+    /// assert_eq!(replayed.stage().unwrap(), replica.stage().unwrap());
+    /// assert!(replayed.replay(&trace).is_err());
+    /// ```
+    pub fn replay(&self, trace: &Value) -> Result<()> {
+        if !self.get_anchors().is_empty() || self.has_staging() {
+            bail!("replica_not_empty");
+        }
+        let events = trace.as_array().ok_or_else(|| anyhow!("invalid_trace"))?;
+        for event in events {
+            match trace_field(event, "op")? {
+                "create" => {
+                    self.create_object(trace_field(event, "uuid")?, trace_object(event)?)?;
+                }
+                "update" => {
+                    self.update_object(trace_field(event, "uuid")?, trace_object(event)?)?;
+                }
+                "delete" => {
+                    self.delete_object(trace_field(event, "uuid")?)?;
+                }
+                "remove" => {
+                    self.remove_object(trace_field(event, "uuid")?)?;
+                }
+                "resolve" => {
+                    let winner = trace_field(event, "winner")?;
+                    self.resolve_as(trace_field(event, "uuid")?, winner)?;
+                }
+                "unstage" => self.unstage()?,
+                "replay_stage" => self.replay_stage(&event.get("stage").cloned())?,
+                "stage_full_snapshot" => self.stage_full_snapshot()?,
+                "commit" => {
+                    let information = event.get("information").and_then(Value::as_object);
+                    let timestamp = event.get("timestamp").and_then(Value::as_u64);
+                    if let Some(replica) = event.get("replica").and_then(Value::as_str) {
+                        *self.replica_id.lock().unwrap() = Some(replica.to_string());
+                    }
+                    let anchors = self
+                        .commit_at(information.cloned(), timestamp)?
+                        .unwrap_or_default();
+                    let block = trace_field(event, "block")?;
+                    if !anchors.contains(block) {
+                        bail!("diverging_replay: {}", block);
+                    }
+                }
+                "block" => {
+                    let items = event
+                        .get("items")
+                        .and_then(Value::as_object)
+                        .ok_or_else(|| anyhow!("invalid_trace_event: block"))?;
+                    let mut data = self.data.write().expect("cannot_acquire_data_for_writing");
+                    let this_items: HashSet<String> =
+                        data.list_raw_items("")?.into_iter().collect();
+                    for (key, item) in items {
+                        if !this_items.contains(key) {
+                            let item = item
+                                .as_str()
+                                .and_then(|item| hex::decode(item).ok())
+                                .ok_or_else(|| anyhow!("invalid_trace_event: block"))?;
+                            data.write_raw_item(key, &item)?;
+                        }
+                    }
+                }
+                "reload" => self.reload()?,
+                "refresh" => self.refresh()?,
+                "reload_until" => {
+                    let anchors = event
+                        .get("anchors")
+                        .and_then(|anchors| serde_json::from_value(anchors.clone()).ok())
+                        .ok_or_else(|| anyhow!("invalid_trace_event: reload_until"))?;
+                    self.reload_until(&anchors)?
+                }
+                op => bail!("invalid_trace_event: {}", op),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns a string field of a trace event
+fn trace_field<'a>(event: &'a Value, name: &str) -> Result<&'a str> {
+    event
+        .get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("invalid_trace_event: {}", event))
+}
+
+/// Returns the object staged by a trace event
+fn trace_object(event: &Value) -> Result<Map<String, Value>> {
+    event
+        .get("object")
+        .and_then(Value::as_object)
+        .cloned()
+        .ok_or_else(|| anyhow!("invalid_trace_event: {}", event))
+}
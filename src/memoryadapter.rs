@@ -14,8 +14,9 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 use crate::adapter::Adapter;
+use crate::error::MeldaError;
 use anyhow::Result;
-use std::{cell::RefCell, collections::BTreeMap, sync::Mutex};
+use std::{cell::RefCell, collections::BTreeMap, ops::Bound, sync::Mutex};
 
 /// Implements in-memory storage
 pub struct MemoryAdapter {
@@ -50,7 +51,9 @@ impl Adapter for MemoryAdapter {
     fn read_object(&self, key: &str, offset: usize, length: usize) -> Result<Vec<u8>> {
         let mem = self.data.lock().unwrap();
         let d = mem.borrow();
-        let data = d.get(key).unwrap();
+        let data = d.get(key).ok_or_else(|| MeldaError::NotFound {
+            key: key.to_string(),
+        })?;
         if offset == 0 && length == 0 {
             Ok(data.clone())
         } else {
@@ -73,6 +76,42 @@ impl Adapter for MemoryAdapter {
         Ok(())
     }
 
+    /// Writes an object to the storage, replacing the existing object (if any)
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `data` - The content of the object
+    fn replace_object(&self, key: &str, data: &[u8]) -> Result<()> {
+        let mem = self.data.lock().unwrap();
+        mem.borrow_mut().insert(key.to_string(), data.to_vec());
+        Ok(())
+    }
+
+    /// Returns the size (in bytes) of an object, without copying its content
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn object_size(&self, key: &str) -> Result<usize> {
+        let mem = self.data.lock().unwrap();
+        let d = mem.borrow();
+        let data = d.get(key).ok_or_else(|| MeldaError::NotFound {
+            key: key.to_string(),
+        })?;
+        Ok(data.len())
+    }
+
+    /// Deletes an object from the storage (deleting a missing object is not an error)
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn delete_object(&self, key: &str) -> Result<()> {
+        self.data.lock().unwrap().borrow_mut().remove(key);
+        Ok(())
+    }
+
     /// Lists the keys of all objects whose key ends with ext. If ext is an empty string, all objects are returned.
     ///
     /// # Arguments
@@ -90,6 +129,34 @@ impl Adapter for MemoryAdapter {
             .collect();
         Ok(list)
     }
+
+    /// Lists, in lexicographic order, at most limit keys of the objects whose key ends with ext
+    /// and follows the given key, without enumerating the preceding keys
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    /// * `after` - The key (without the extension) after which the listing starts
+    /// * `limit` - The maximum number of keys returned
+    fn list_objects_page(
+        &self,
+        ext: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        let start = match after {
+            Some(after) => Bound::Excluded(after),
+            None => Bound::Unbounded,
+        };
+        let mem = self.data.lock().unwrap();
+        let d = mem.borrow();
+        Ok(d.range::<str, _>((start, Bound::Unbounded))
+            .filter_map(|(k, _)| k.strip_suffix(ext))
+            .filter(|k| after.is_none_or(|after| *k > after))
+            .take(limit)
+            .map(|k| k.to_string())
+            .collect())
+    }
 }
 
 #[cfg(test)]
@@ -122,6 +189,30 @@ mod tests {
         assert!(ro == "om");
     }
 
+    #[test]
+    fn test_memory_list_objects_page() {
+        let ma = MemoryAdapter::new();
+        for key in &["a.delta", "b.pack", "c.delta", "d.delta"] {
+            ma.write_object(key, b"data").unwrap();
+        }
+        assert_eq!(
+            ma.list_objects_page(".delta", None, 2).unwrap(),
+            vec!["a", "c"]
+        );
+        assert_eq!(
+            ma.list_objects_page(".delta", Some("c"), 2).unwrap(),
+            vec!["d"]
+        );
+        assert_eq!(
+            ma.list_objects_page(".pack", Some("a"), 2).unwrap(),
+            vec!["b"]
+        );
+        assert!(ma
+            .list_objects_page(".delta", Some("d"), 2)
+            .unwrap()
+            .is_empty());
+    }
+
     #[test]
     fn test_memory_write_object_flate() {
         let sa = MemoryAdapter::new();
@@ -214,6 +305,8 @@ mod tests {
         assert!(!ro.is_empty());
         let ro = String::from_utf8(ro).unwrap();
         assert!(ro == "om");
+        assert!(sqa.object_size("somekey.delta").unwrap() == 8);
+        assert!(sqa.object_size("missing.delta").is_err());
     }
 
     #[test]
@@ -283,4 +376,23 @@ mod tests {
         assert!(sqa.list_objects(".pack").unwrap().len() == 1);
         assert!(sqa.list_objects("").unwrap().len() == 2);
     }
+
+    #[test]
+    fn test_memory_delete_object_flate() {
+        let sa = MemoryAdapter::new();
+        let ma: Box<dyn Adapter> = Box::new(sa);
+        let sqa = Flate2Adapter::new(std::sync::Arc::new(std::sync::RwLock::new(ma)));
+        assert!(sqa
+            .write_object("somekey.delta", "somedata".as_bytes())
+            .is_ok());
+        assert!(sqa
+            .write_object("somekey.pack", "otherdata".as_bytes())
+            .is_ok());
+        assert!(sqa.delete_object("somekey.delta").is_ok());
+        assert!(sqa.list_objects(".delta").unwrap().is_empty());
+        assert!(sqa.list_objects("").unwrap().len() == 1);
+        assert!(sqa.read_object("somekey.delta", 0, 0).is_err());
+        // Deleting a missing object is not an error
+        assert!(sqa.delete_object("somekey.delta").is_ok());
+    }
 }
@@ -0,0 +1,255 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::constants::ID_FIELD;
+use crate::melda::Melda;
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A position (such as the caret of an editor) inside a flattened array which remains stable
+/// across concurrent edits: instead of an index, the position records the identifiers of the
+/// elements surrounding it, so that it can be resolved again after remote changes have been
+/// melded. The position sticks to the preceding element (elements inserted concurrently at
+/// the same place end up after the position), then to the following one if the preceding
+/// element has been deleted; if both have been deleted the original index is used (clamped to
+/// the length of the array). The start of the array always resolves to the start. Elements without an identifier (such as scalars) cannot be
+/// tracked. Positions can be serialized, for example to be shared with the peers of a
+/// repository as presence data.
+///
+/// # Example
+/// ```
+/// use melda::{melda::Melda, position::StablePosition};
+/// use serde_json::json;
+/// let items = |ids: &[&str]| json!({ "items\u{266D}" : ids.iter().map(|id| json!({ "_id" : id })).collect::<Vec<_>>() }).as_object().unwrap().clone();
+/// let alice = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+/// alice.update(items(&["a", "b", "c"])).unwrap();
+/// alice.commit(None).unwrap();
+/// let bob = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+/// bob.meld(&alice).unwrap();
+/// // Alice places the caret between "a" and "b", while Bob inserts two elements at the start
+/// let caret = StablePosition::at(&alice, "/items\u{266D}", 1).unwrap();
+/// assert_eq!(caret.resolve(&alice).unwrap(), Some(1));
+/// bob.refresh().unwrap();
+/// bob.update(items(&["x", "y", "a", "b", "c"])).unwrap();
+/// bob.commit(None).unwrap();
+/// alice.meld(&bob).unwrap();
+/// alice.refresh().unwrap();
+/// // The caret still follows "a"
+/// assert_eq!(caret.resolve(&alice).unwrap(), Some(3));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StablePosition {
+    /// JSON Pointer to the array
+    pub array: String,
+    /// Identifier of the element preceding the position (None at the start of the array)
+    pub before: Option<String>,
+    /// Identifier of the element following the position (None at the end of the array)
+    pub after: Option<String>,
+    /// Index of the position when it was created
+    pub index: usize,
+}
+
+impl StablePosition {
+    /// Returns the stable position corresponding to an index in the current state of a replica
+    ///
+    /// # Arguments
+    ///
+    /// * `replica` - The replica
+    /// * `array` - JSON Pointer to the array
+    /// * `index` - The index (between 0 and the length of the array)
+    pub fn at(replica: &Melda, array: &str, index: usize) -> Result<StablePosition> {
+        let elements = match read_array(replica, array)? {
+            Some(elements) => elements,
+            None => bail!("array_not_found"),
+        };
+        if index > elements.len() {
+            bail!("invalid_position");
+        }
+        Ok(StablePosition {
+            array: array.to_string(),
+            before: index.checked_sub(1).and_then(|i| element_id(&elements[i])),
+            after: elements.get(index).and_then(element_id),
+            index,
+        })
+    }
+
+    /// Returns the index of the position in the current state of a replica, or None if the
+    /// array does not exist anymore
+    ///
+    /// # Arguments
+    ///
+    /// * `replica` - The replica
+    pub fn resolve(&self, replica: &Melda) -> Result<Option<usize>> {
+        let elements = match read_array(replica, &self.array)? {
+            Some(elements) => elements,
+            None => return Ok(None),
+        };
+        let position = |id: &Option<String>| {
+            id.as_ref().and_then(|id| {
+                elements
+                    .iter()
+                    .position(|e| element_id(e).as_ref() == Some(id))
+            })
+        };
+        let index = match (position(&self.before), position(&self.after)) {
+            // The start of the array remains the start of the array
+            _ if self.index == 0 => 0,
+            (Some(before), _) => before + 1,
+            (None, Some(after)) => after,
+            // The position was at the end of the array
+            (None, None) if self.before.is_some() && self.after.is_none() => elements.len(),
+            (None, None) => self.index.min(elements.len()),
+        };
+        Ok(Some(index))
+    }
+}
+
+/// A selection inside a flattened array, delimited by two stable positions (see
+/// [StablePosition]): the anchor, where the selection started, and the head, where it ends
+/// (the caret). Collapsed selections represent a simple caret.
+///
+/// # Example
+/// ```
+/// use melda::{melda::Melda, position::Selection};
+/// use serde_json::json;
+/// let replica = Melda::new_from_url("memory://").expect("cannot_initialize_crdt");
+/// let items = |ids: &[&str]| json!({ "items\u{266D}" : ids.iter().map(|id| json!({ "_id" : id })).collect::<Vec<_>>() }).as_object().unwrap().clone();
+/// replica.update(items(&["a", "b", "c", "d"])).unwrap();
+/// let selection = Selection::new(&replica, "/items\u{266D}", 1, 3).unwrap();
+/// assert!(!selection.is_collapsed());
+/// replica.update(items(&["a", "c", "d", "e"])).unwrap();
+/// // "b" has been deleted, the selection now covers "c" only
+/// assert_eq!(selection.resolve(&replica).unwrap(), Some((1, 2)));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Selection {
+    /// Position where the selection started
+    pub anchor: StablePosition,
+    /// Position where the selection ends
+    pub head: StablePosition,
+}
+
+impl Selection {
+    /// Returns the selection between two indexes in the current state of a replica
+    ///
+    /// # Arguments
+    ///
+    /// * `replica` - The replica
+    /// * `array` - JSON Pointer to the array
+    /// * `anchor` - Index where the selection started
+    /// * `head` - Index where the selection ends
+    pub fn new(replica: &Melda, array: &str, anchor: usize, head: usize) -> Result<Selection> {
+        Ok(Selection {
+            anchor: StablePosition::at(replica, array, anchor)?,
+            head: StablePosition::at(replica, array, head)?,
+        })
+    }
+
+    /// Returns true if the anchor and the head denote the same position
+    pub fn is_collapsed(&self) -> bool {
+        self.anchor == self.head
+    }
+
+    /// Returns the indexes of the anchor and of the head in the current state of a replica, or
+    /// None if the array does not exist anymore
+    ///
+    /// # Arguments
+    ///
+    /// * `replica` - The replica
+    pub fn resolve(&self, replica: &Melda) -> Result<Option<(usize, usize)>> {
+        match (self.anchor.resolve(replica)?, self.head.resolve(replica)?) {
+            (Some(anchor), Some(head)) => Ok(Some((anchor, head))),
+            _ => Ok(None),
+        }
+    }
+}
+
+// Returns the elements of the array identified by a JSON Pointer (None if it does not exist)
+fn read_array(replica: &Melda, array: &str) -> Result<Option<Vec<Value>>> {
+    match replica.get(array)? {
+        Some(Value::Array(elements)) => Ok(Some(elements)),
+        Some(_) => bail!("not_an_array"),
+        None => Ok(None),
+    }
+}
+
+// Returns the identifier of an element of a flattened array, if any
+fn element_id(element: &Value) -> Option<String> {
+    element
+        .get(ID_FIELD)
+        .and_then(Value::as_str)
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::melda::Melda;
+    use crate::position::{Selection, StablePosition};
+    use serde_json::{json, Map, Value};
+
+    fn items(ids: &[&str]) -> Map<String, Value> {
+        let elements: Vec<Value> = ids.iter().map(|id| json!({ "_id" : id })).collect();
+        json!({ "items♭" : elements }).as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn test_stable_position() {
+        let alice = Melda::new_from_url("memory://").unwrap();
+        alice.update(items(&["a", "b", "c"])).unwrap();
+        alice.commit(None).unwrap();
+        let bob = Melda::new_from_url("memory://").unwrap();
+        bob.meld(&alice).unwrap();
+        bob.refresh().unwrap();
+        let start = StablePosition::at(&alice, "/items♭", 0).unwrap();
+        let end = StablePosition::at(&alice, "/items♭", 3).unwrap();
+        let caret = StablePosition::at(&alice, "/items♭", 2).unwrap();
+        assert_eq!(caret.before.as_deref(), Some("b"));
+        assert_eq!(caret.after.as_deref(), Some("c"));
+        assert!(StablePosition::at(&alice, "/items♭", 4).is_err());
+        assert!(StablePosition::at(&alice, "/missing♭", 0).is_err());
+        // Concurrently, Alice appends an element and Bob deletes "b" and inserts at the start
+        alice.update(items(&["a", "b", "c", "d"])).unwrap();
+        alice.commit(None).unwrap();
+        bob.update(items(&["x", "a", "c"])).unwrap();
+        bob.commit(None).unwrap();
+        alice.meld(&bob).unwrap();
+        alice.refresh().unwrap();
+        let order: Vec<Value> = alice
+            .get("/items♭")
+            .unwrap()
+            .unwrap()
+            .as_array()
+            .unwrap()
+            .clone();
+        assert_eq!(order.len(), 4);
+        // The caret falls back to the element following it
+        let c = order.iter().position(|e| e["_id"] == "c").unwrap();
+        assert_eq!(caret.resolve(&alice).unwrap(), Some(c));
+        assert_eq!(start.resolve(&alice).unwrap(), Some(0));
+        // Alice appended "d" concurrently: the end position sticks to "c"
+        assert_eq!(end.resolve(&alice).unwrap(), Some(c + 1));
+        // Positions can be exchanged as JSON
+        let selection = Selection {
+            anchor: start,
+            head: caret,
+        };
+        let shared: Selection =
+            serde_json::from_value(serde_json::to_value(&selection).unwrap()).unwrap();
+        assert_eq!(shared.resolve(&alice).unwrap(), Some((0, c)));
+        alice.update(Map::new()).unwrap();
+        assert_eq!(shared.resolve(&alice).unwrap(), None);
+    }
+}
@@ -0,0 +1,316 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{
+    adapter::Adapter,
+    utils::{constant_time_eq, keyed_hash},
+};
+use anyhow::Result;
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex, RwLock},
+};
+
+/// Length (in bytes) of the synthetic initialization vector prepended to each name
+const SIV_LENGTH: usize = 16;
+
+/// Implements private metadata storage on other adapters. The key of each object (block
+/// identifiers, pack names, indexes) is replaced by an opaque name derived through a keyed hash,
+/// so that an untrusted storage provider cannot infer the structure of the document, the number
+/// of objects or the update patterns from the names alone. The contents of the objects are
+/// not altered.
+pub struct PrivateAdapter {
+    backend: Arc<RwLock<Box<dyn Adapter>>>,
+    mac_key: Vec<u8>,
+    encryption_key: Vec<u8>,
+    /// Maps the opaque names found in the backend to the revealed keys (None for
+    /// names which were not generated with the same secret)
+    names: Mutex<HashMap<String, Option<String>>>,
+}
+
+impl PrivateAdapter {
+    /// Creates a new adapter wrapping the specified adapter
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - The adapter to be wrapped
+    /// * `secret` - The secret used to derive the names of the stored objects
+    pub fn new(backend: Arc<RwLock<Box<dyn Adapter>>>, secret: &[u8]) -> Result<Self> {
+        Ok(PrivateAdapter {
+            backend,
+            mac_key: keyed_hash(secret, b"melda-private-mac")?,
+            encryption_key: keyed_hash(secret, b"melda-private-encryption")?,
+            names: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Derives the opaque name of the given key: the name is composed of a synthetic
+    /// initialization vector (the keyed hash of the key) followed by the encrypted key
+    fn conceal(&self, key: &str) -> Result<String> {
+        let siv = keyed_hash(&self.mac_key, key.as_bytes())?;
+        let siv = &siv[..SIV_LENGTH];
+        let encrypted = encrypt(&self.encryption_key, siv, key.as_bytes())?;
+        Ok(hex::encode(siv) + &hex::encode(encrypted))
+    }
+
+    /// Recovers the original key from an opaque name. Returns None if the name was
+    /// not generated using the same secret.
+    fn reveal(&self, name: &str) -> Option<String> {
+        let data = hex::decode(name).ok()?;
+        if data.len() <= SIV_LENGTH {
+            return None;
+        }
+        let (siv, encrypted) = data.split_at(SIV_LENGTH);
+        let key = decrypt(&self.encryption_key, siv, encrypted).ok()?;
+        let expected = keyed_hash(&self.mac_key, &key).ok()?;
+        if constant_time_eq(&expected[..SIV_LENGTH], siv) {
+            String::from_utf8(key).ok()
+        } else {
+            None
+        }
+    }
+}
+
+// Encrypts the data with AES-256 in CBC mode (with PKCS#7 padding)
+#[cfg(feature = "openssl")]
+fn encrypt(key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    use openssl::symm::Cipher;
+    Ok(openssl::symm::encrypt(
+        Cipher::aes_256_cbc(),
+        key,
+        Some(iv),
+        data,
+    )?)
+}
+
+// Decrypts the data with AES-256 in CBC mode (with PKCS#7 padding)
+#[cfg(feature = "openssl")]
+fn decrypt(key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    use openssl::symm::Cipher;
+    Ok(openssl::symm::decrypt(
+        Cipher::aes_256_cbc(),
+        key,
+        Some(iv),
+        data,
+    )?)
+}
+
+// Encrypts the data with AES-256 in CBC mode (with PKCS#7 padding)
+#[cfg(feature = "rustcrypto")]
+fn encrypt(key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    use aes::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
+    let encryptor = cbc::Encryptor::<aes::Aes256>::new_from_slices(key, iv)
+        .map_err(|e| anyhow::anyhow!("invalid_encryption_key: {}", e))?;
+    Ok(encryptor.encrypt_padded_vec_mut::<Pkcs7>(data))
+}
+
+// Decrypts the data with AES-256 in CBC mode (with PKCS#7 padding)
+#[cfg(feature = "rustcrypto")]
+fn decrypt(key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+    let decryptor = cbc::Decryptor::<aes::Aes256>::new_from_slices(key, iv)
+        .map_err(|e| anyhow::anyhow!("invalid_encryption_key: {}", e))?;
+    decryptor
+        .decrypt_padded_vec_mut::<Pkcs7>(data)
+        .map_err(|e| anyhow::anyhow!("cannot_decrypt: {}", e))
+}
+
+impl Adapter for PrivateAdapter {
+    /// Reads an object or a sub-object from the backend storage. When offset and length are both 0
+    /// the full object is returned, otherwise the sub-object is returned
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `offset` - The starting position of the sub-object in the associated data pack
+    /// * `length` - The length of the sub-object (in bytes) in the associated data pack
+    ///
+    fn read_object(&self, key: &str, offset: usize, length: usize) -> Result<Vec<u8>> {
+        let name = self.conceal(key)?;
+        self.backend
+            .read()
+            .unwrap()
+            .read_object(&name, offset, length)
+    }
+
+    /// Writes an object to the storage
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `data` - The content of the object
+    fn write_object(&self, key: &str, data: &[u8]) -> Result<()> {
+        let name = self.conceal(key)?;
+        self.backend.write().unwrap().write_object(&name, data)?;
+        self.names
+            .lock()
+            .unwrap()
+            .insert(name, Some(key.to_string()));
+        Ok(())
+    }
+
+    /// Writes an object to the storage, replacing the existing object (if any)
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `data` - The content of the object
+    fn replace_object(&self, key: &str, data: &[u8]) -> Result<()> {
+        let name = self.conceal(key)?;
+        self.backend.write().unwrap().replace_object(&name, data)?;
+        self.names
+            .lock()
+            .unwrap()
+            .insert(name, Some(key.to_string()));
+        Ok(())
+    }
+
+    /// Deletes an object from the storage (deleting a missing object is not an error)
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn delete_object(&self, key: &str) -> Result<()> {
+        let name = self.conceal(key)?;
+        self.backend.write().unwrap().delete_object(&name)?;
+        self.names.lock().unwrap().remove(&name);
+        Ok(())
+    }
+
+    /// Lists the keys of all objects whose key ends with ext. If ext is an empty string, all objects are returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    fn list_objects(&self, ext: &str) -> Result<Vec<String>> {
+        // Names do not reveal the extension: all objects have to be listed, but only
+        // names which have not been seen before are revealed
+        let names = self.backend.read().unwrap().list_objects("")?;
+        let mut cache = self.names.lock().unwrap();
+        let mut seen = HashMap::with_capacity(names.len());
+        for name in names {
+            let key = match cache.remove(&name) {
+                Some(key) => key,
+                None => self.reveal(&name),
+            };
+            seen.insert(name, key);
+        }
+        // Names which disappeared from the backend are forgotten
+        *cache = seen;
+        Ok(cache
+            .values()
+            .flatten()
+            .filter(|key| key.ends_with(ext))
+            .map(|key| key.strip_suffix(ext).unwrap().to_string())
+            .collect())
+    }
+
+    /// Lists, in lexicographic order, at most limit keys of the objects whose key ends with ext
+    /// and follows the given key. Names are not ordered like the keys they conceal, hence
+    /// the backend cannot page them: all names are listed, but only the requested keys are kept
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    /// * `after` - The key (without the extension) after which the listing starts
+    /// * `limit` - The maximum number of keys returned
+    fn list_objects_page(
+        &self,
+        ext: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        let mut page = BTreeSet::new();
+        for key in self.list_objects(ext)? {
+            if after.is_some_and(|after| key.as_str() <= after) {
+                continue;
+            }
+            page.insert(key);
+            if page.len() > limit {
+                page.pop_last();
+            }
+        }
+        Ok(page.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{adapter::Adapter, memoryadapter::MemoryAdapter, privateadapter::PrivateAdapter};
+    use std::sync::{Arc, RwLock};
+
+    #[test]
+    fn test_private_read_write_object() {
+        let ma: Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+        let pa = PrivateAdapter::new(Arc::new(RwLock::new(ma)), b"secret").unwrap();
+        assert!(pa.list_objects(".delta").unwrap().is_empty());
+        assert!(pa
+            .write_object("somekey.delta", "somedata".as_bytes())
+            .is_ok());
+        assert!(pa.list_objects(".delta").unwrap().len() == 1);
+        let ro = pa.read_object("somekey.delta", 0, 0).unwrap();
+        assert!(String::from_utf8(ro).unwrap() == "somedata");
+        let ro = pa.read_object("somekey.delta", 1, 2).unwrap();
+        assert!(String::from_utf8(ro).unwrap() == "om");
+        assert!(pa
+            .write_object("somekey.pack", "otherdata".as_bytes())
+            .is_ok());
+        assert!(pa.list_objects(".delta").unwrap() == vec!["somekey".to_string()]);
+        assert!(pa.list_objects(".pack").unwrap() == vec!["somekey".to_string()]);
+        assert!(pa.list_objects("").unwrap().len() == 2);
+    }
+
+    #[test]
+    fn test_private_names_are_opaque() {
+        let ma: Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+        let backend = Arc::new(RwLock::new(ma));
+        let pa = PrivateAdapter::new(backend.clone(), b"secret").unwrap();
+        assert!(pa
+            .write_object("somekey.delta", "somedata".as_bytes())
+            .is_ok());
+        let names = backend.read().unwrap().list_objects("").unwrap();
+        assert!(names.len() == 1);
+        assert!(!names[0].contains("somekey"));
+        assert!(!names[0].contains(".delta"));
+        // Objects written with another secret are not visible
+        let other = PrivateAdapter::new(backend, b"other").unwrap();
+        assert!(other.list_objects("").unwrap().is_empty());
+        // Names do not depend on the cryptographic backend
+        let name = pa.conceal("somekey.delta").unwrap();
+        assert_eq!(
+            name,
+            "97fd597bad956eeed1f62b05c3c79cbae1771d1a04ec9ca02e3fbc451992b1f9"
+        );
+        assert_eq!(pa.reveal(&name).unwrap(), "somekey.delta");
+    }
+
+    #[test]
+    fn test_private_list_objects_cache() {
+        let ma: Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+        let backend = Arc::new(RwLock::new(ma));
+        let pa = PrivateAdapter::new(backend.clone(), b"secret").unwrap();
+        let other = PrivateAdapter::new(backend.clone(), b"secret").unwrap();
+        assert!(pa.write_object("first.delta", b"data").is_ok());
+        assert!(other.list_objects(".delta").unwrap() == vec!["first".to_string()]);
+        // Objects written or deleted by others are picked up on the next listing
+        assert!(pa.write_object("second.delta", b"data").is_ok());
+        let mut listed = other.list_objects(".delta").unwrap();
+        listed.sort();
+        assert!(listed == vec!["first".to_string(), "second".to_string()]);
+        assert!(pa.delete_object("first.delta").is_ok());
+        assert!(other.list_objects(".delta").unwrap() == vec!["second".to_string()]);
+        assert!(other.names.lock().unwrap().len() == 1);
+    }
+}
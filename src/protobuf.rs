@@ -0,0 +1,363 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//! Protocol Buffers encoding of the replication artifacts (delta blocks and packs), so that
+//! implementations in other languages and middleboxes can parse them without understanding the
+//! native encodings. The schema is shipped as `proto/melda.proto` (and available as [SCHEMA]);
+//! the messages below mirror it. Decoding yields the native JSON encoding of the artifact: for
+//! repositories using the JSON encoding the round trip is exact, so that digests (and hence item
+//! identifiers) are preserved.
+use crate::constants::{
+    CHANGESETS_FIELD, GRAFT_FIELD, INFORMATION_FIELD, PACK_FIELD, PARENTS_FIELD, REPLICA_FIELD,
+    SQUASHED_FIELD, TIMESTAMP_FIELD,
+};
+use crate::utils::{binary_header_len, decode_value, digest_bytes, digest_string};
+use anyhow::{anyhow, bail, Result};
+use prost::Message;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+/// The Protocol Buffers schema of blocks and packs
+pub const SCHEMA: &str = include_str!("../proto/melda.proto");
+
+/// A change of a single object recorded in a delta block
+#[derive(Clone, PartialEq, Message)]
+pub struct Change {
+    /// Identifier of the object
+    #[prost(string, tag = "1")]
+    pub object: String,
+    /// Revision replaced by this change (absent when the object is created)
+    #[prost(string, optional, tag = "2")]
+    pub previous: Option<String>,
+    /// Digest of the object content
+    #[prost(string, tag = "3")]
+    pub digest: String,
+}
+
+/// A delta block
+#[derive(Clone, PartialEq, Message)]
+pub struct Block {
+    /// Parent blocks
+    #[prost(string, repeated, tag = "1")]
+    pub parents: Vec<String>,
+    /// Packs holding the values referenced by the changes
+    #[prost(string, repeated, tag = "2")]
+    pub packs: Vec<String>,
+    /// Changes
+    #[prost(message, repeated, tag = "3")]
+    pub changes: Vec<Change>,
+    /// Commit information as JSON text
+    #[prost(string, optional, tag = "4")]
+    pub info: Option<String>,
+    /// Blocks replaced by this block when squashing
+    #[prost(string, repeated, tag = "5")]
+    pub squashed: Vec<String>,
+    /// Blocks grafted by this block
+    #[prost(string, repeated, tag = "6")]
+    pub grafted: Vec<String>,
+    /// Commit timestamp in milliseconds since the epoch
+    #[prost(uint64, optional, tag = "7")]
+    pub timestamp: Option<u64>,
+    /// Replica that created the block
+    #[prost(string, optional, tag = "8")]
+    pub replica: Option<String>,
+}
+
+/// A value stored in a pack
+#[derive(Clone, PartialEq, Message)]
+pub struct PackValue {
+    /// Digest identifying the value
+    #[prost(string, tag = "1")]
+    pub digest: String,
+    /// Value as JSON text
+    #[prost(string, tag = "2")]
+    pub json: String,
+}
+
+/// A pack, with its values in storage order
+#[derive(Clone, PartialEq, Message)]
+pub struct Pack {
+    #[prost(message, repeated, tag = "1")]
+    pub values: Vec<PackValue>,
+}
+
+/// Encodes a delta block (the content of a `.delta` item, in any encoding) as a Protocol
+/// Buffers `Block` message
+///
+/// # Arguments
+///
+/// * `item` - The content of the delta block
+///
+/// # Example
+/// ```
+/// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter, protobuf};
+/// use std::sync::{Arc, RwLock};
+/// use serde_json::json;
+/// let adapter: Arc<RwLock<Box<dyn Adapter>>> = Arc::new(RwLock::new(Box::new(MemoryAdapter::new())));
+/// let replica = Melda::new(adapter.clone()).unwrap();
+/// replica.update(json!({ "title": "Hello" }).as_object().unwrap().clone()).unwrap();
+/// let block = replica.commit(None).unwrap().unwrap().into_iter().next().unwrap();
+/// let key = block.clone() + ".delta";
+/// let item = adapter.read().unwrap().read_object(&key, 0, 0).unwrap();
+/// let message = protobuf::encode_block(&item).unwrap();
+/// assert_eq!(protobuf::decode_block(&message).unwrap(), item);
+/// ```
+pub fn encode_block(item: &[u8]) -> Result<Vec<u8>> {
+    let value = decode_value(item)?;
+    let block = value.as_object().ok_or_else(|| anyhow!("invalid_block"))?;
+    let strings = |field: &str| -> Result<Vec<String>> {
+        match block.get(field) {
+            Some(Value::Array(a)) => a
+                .iter()
+                .map(|v| {
+                    v.as_str()
+                        .map(|s| s.to_string())
+                        .ok_or_else(|| anyhow!("invalid_block_field: {}", field))
+                })
+                .collect(),
+            Some(_) => bail!("invalid_block_field: {}", field),
+            None => Ok(vec![]),
+        }
+    };
+    let mut changes = vec![];
+    for change in block
+        .get(CHANGESETS_FIELD)
+        .and_then(|c| c.as_array())
+        .ok_or_else(|| anyhow!("invalid_block_field: {}", CHANGESETS_FIELD))?
+    {
+        let parts: Vec<String> = change
+            .as_array()
+            .map(|c| {
+                c.iter()
+                    .filter_map(|s| s.as_str())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        changes.push(match parts.as_slice() {
+            [object, digest] => Change {
+                object: object.clone(),
+                previous: None,
+                digest: digest.clone(),
+            },
+            [object, previous, digest] => Change {
+                object: object.clone(),
+                previous: Some(previous.clone()),
+                digest: digest.clone(),
+            },
+            _ => bail!("invalid_block_field: {}", CHANGESETS_FIELD),
+        });
+    }
+    let message = Block {
+        parents: strings(PARENTS_FIELD)?,
+        packs: strings(PACK_FIELD)?,
+        changes,
+        info: block.get(INFORMATION_FIELD).map(|i| i.to_string()),
+        squashed: strings(SQUASHED_FIELD)?,
+        grafted: strings(GRAFT_FIELD)?,
+        timestamp: block.get(TIMESTAMP_FIELD).and_then(|t| t.as_u64()),
+        replica: block
+            .get(REPLICA_FIELD)
+            .and_then(|r| r.as_str())
+            .map(String::from),
+    };
+    Ok(message.encode_to_vec())
+}
+
+/// Decodes a Protocol Buffers `Block` message into the JSON encoding of the delta block (empty
+/// lists, except changes, are omitted as when the block is committed)
+///
+/// # Arguments
+///
+/// * `data` - The encoded message
+pub fn decode_block(data: &[u8]) -> Result<Vec<u8>> {
+    let message = Block::decode(data).map_err(|e| anyhow!("invalid_block_message: {}", e))?;
+    let mut block = Map::<String, Value>::new();
+    let changes: Vec<Value> = message
+        .changes
+        .into_iter()
+        .map(|c| match c.previous {
+            Some(previous) => json!([c.object, previous, c.digest]),
+            None => json!([c.object, c.digest]),
+        })
+        .collect();
+    block.insert(CHANGESETS_FIELD.to_string(), Value::from(changes));
+    if let Some(info) = message.info {
+        let info: Value =
+            serde_json::from_str(&info).map_err(|_| anyhow!("invalid_block_information"))?;
+        block.insert(INFORMATION_FIELD.to_string(), info);
+    }
+    for (field, list) in [
+        (PARENTS_FIELD, message.parents),
+        (PACK_FIELD, message.packs),
+        (SQUASHED_FIELD, message.squashed),
+        (GRAFT_FIELD, message.grafted),
+    ] {
+        if !list.is_empty() {
+            block.insert(field.to_string(), Value::from(list));
+        }
+    }
+    if let Some(timestamp) = message.timestamp {
+        block.insert(TIMESTAMP_FIELD.to_string(), Value::from(timestamp));
+    }
+    if let Some(replica) = message.replica {
+        block.insert(REPLICA_FIELD.to_string(), Value::from(replica));
+    }
+    Ok(serde_json::to_string(&block)?.into_bytes())
+}
+
+/// Encodes a pack (the content of a `.pack` item, in any encoding) as a Protocol Buffers
+/// `Pack` message. Values are identified by the digest recorded in the index of the pack, if
+/// given (the index is required for packs holding structural deltas), or else by the digest of
+/// their content
+///
+/// # Arguments
+///
+/// * `pack` - The content of the pack
+/// * `index` - The content of the index of the pack, if any
+///
+/// # Example
+/// ```
+/// use melda::protobuf;
+/// let pack = br#"[{"_id":"a","v":1},{"_id":"b","v":2}]"#;
+/// let message = protobuf::encode_pack(pack, None).unwrap();
+/// let (decoded, _index) = protobuf::decode_pack(&message).unwrap();
+/// assert_eq!(decoded, pack.to_vec());
+/// ```
+pub fn encode_pack(pack: &[u8], index: Option<&[u8]>) -> Result<Vec<u8>> {
+    let mut digests = HashMap::<(usize, usize), String>::new();
+    if let Some(index) = index {
+        let index: Map<String, Value> =
+            serde_json::from_slice(index).map_err(|_| anyhow!("invalid_pack_index"))?;
+        for (digest, location) in index {
+            let offset = location.get(0).and_then(|v| v.as_u64());
+            let count = location.get(1).and_then(|v| v.as_u64());
+            match (offset, count) {
+                (Some(offset), Some(count)) => {
+                    digests.insert((offset as usize, count as usize), digest)
+                }
+                _ => bail!("invalid_pack_index"),
+            };
+        }
+    }
+    let mut values = vec![];
+    let mut push = |offset: usize, count: usize, json: String, digest: String| {
+        let digest = digests.remove(&(offset, count)).unwrap_or(digest);
+        values.push(PackValue { digest, json });
+    };
+    match binary_header_len(pack)? {
+        Some(start) => {
+            let mut reader = &pack[start..];
+            while !reader.is_empty() {
+                let offset = pack.len() - reader.len();
+                let value: Value =
+                    rmp_serde::from_read(&mut reader).map_err(|_| anyhow!("invalid_pack"))?;
+                let json = value.to_string();
+                let digest = digest_string(&json);
+                push(offset, pack.len() - reader.len() - offset, json, digest);
+            }
+        }
+        None => {
+            // Values are objects: track their boundaries as when loading the pack
+            let mut depth = 0;
+            let mut start = 0;
+            for (offset, c) in pack.iter().enumerate() {
+                if *c == b'{' {
+                    if depth == 0 {
+                        start = offset;
+                    }
+                    depth += 1;
+                } else if *c == b'}' {
+                    depth -= 1;
+                    if depth == 0 {
+                        let bytes = &pack[start..offset + 1];
+                        let json = String::from_utf8(bytes.to_vec())?;
+                        push(start, bytes.len(), json, digest_bytes(bytes));
+                    }
+                }
+            }
+        }
+    }
+    Ok(Pack { values }.encode_to_vec())
+}
+
+/// Decodes a Protocol Buffers `Pack` message into the JSON encoding of the pack and of its
+/// index (which maps the digest of each value to its location in the pack)
+///
+/// # Arguments
+///
+/// * `data` - The encoded message
+pub fn decode_pack(data: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let message = Pack::decode(data).map_err(|e| anyhow!("invalid_pack_message: {}", e))?;
+    let mut pack = vec![b'['];
+    let mut index = Map::<String, Value>::new();
+    for (i, value) in message.values.into_iter().enumerate() {
+        if i > 0 {
+            pack.push(b',');
+        }
+        if !serde_json::from_str::<Value>(&value.json).is_ok_and(|v| v.is_object()) {
+            bail!("invalid_pack_value: {}", value.digest);
+        }
+        index.insert(value.digest, json!([pack.len(), value.json.len()]));
+        pack.extend_from_slice(value.json.as_bytes());
+    }
+    pack.push(b']');
+    Ok((pack, serde_json::to_string(&index)?.into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::Encoding;
+    use crate::utils::{binary_header, encode_value};
+
+    #[test]
+    fn test_block_round_trip() {
+        let block = br#"{"c":[["a","1-x"],["b","1-y","2-z"]],"i":{"author":"me"},"p":["p1","p2"],"r":"r1","t":1700000000000}"#;
+        let message = encode_block(block).unwrap();
+        let decoded = Block::decode(message.as_slice()).unwrap();
+        assert_eq!(decoded.parents, vec!["p1", "p2"]);
+        assert_eq!(decoded.changes[1].previous.as_deref(), Some("1-y"));
+        assert_eq!(decoded.info.as_deref(), Some(r#"{"author":"me"}"#));
+        assert_eq!(decode_block(&message).unwrap(), block.to_vec());
+        // Binary blocks decode to their JSON encoding
+        let value: Value = serde_json::from_slice(block).unwrap();
+        let binary = encode_value(&value, Encoding::MessagePack).unwrap();
+        assert_eq!(
+            decode_block(&encode_block(&binary).unwrap()).unwrap(),
+            block.to_vec()
+        );
+    }
+
+    #[test]
+    fn test_pack_round_trip() {
+        let pack = br#"[{"_id":"a","n":{"x":1}},{"_id":"b"}]"#;
+        let index = br#"{"d1":[1,23]}"#;
+        let message = Pack::decode(encode_pack(pack, Some(index)).unwrap().as_slice()).unwrap();
+        assert_eq!(message.values[0].digest, "d1");
+        assert_eq!(message.values[1].digest, digest_string(r#"{"_id":"b"}"#));
+        let (decoded, decoded_index) = decode_pack(&message.encode_to_vec()).unwrap();
+        assert_eq!(decoded, pack.to_vec());
+        let decoded_index: Value = serde_json::from_slice(&decoded_index).unwrap();
+        assert_eq!(decoded_index["d1"], json!([1, 23]));
+        // Binary packs
+        let mut binary = binary_header();
+        for v in [json!({"_id":"a","n":{"x":1}}), json!({"_id":"b"})] {
+            rmp_serde::encode::write(&mut binary, &v).unwrap();
+        }
+        let (decoded, _) = decode_pack(&encode_pack(&binary, None).unwrap()).unwrap();
+        assert_eq!(decoded, pack.to_vec());
+    }
+}
@@ -0,0 +1,223 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::adapter::Adapter;
+use crate::error::MeldaError;
+use anyhow::{anyhow, bail, Result};
+use lru::LruCache;
+use reqwest::blocking::{Client, Response};
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+/// Implements storage in a repository hosted by a melda-server (see
+/// [crate::server::RepositoryServer]), so that replicas can meld with the repositories of the
+/// server. Objects stored on the server are immutable: writing an existing key fails, and
+/// objects cannot be deleted
+pub struct RemoteAdapter {
+    url: String,
+    token: String,
+    client: Client,
+    cache: Mutex<LruCache<String, Vec<u8>>>, // Objects are immutable, hence they can be cached
+}
+
+impl RemoteAdapter {
+    /// Creates a new adapter to store data in a repository hosted by a server
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - URL of the server (for example `http://localhost:8080`)
+    /// * `repository` - Name of the repository
+    /// * `token` - Capability token issued for the repository
+    pub fn new(url: &str, repository: &str, token: &str) -> Result<Self> {
+        Ok(RemoteAdapter {
+            url: url.trim_end_matches('/').to_string() + "/" + repository.trim_matches('/'),
+            token: token.to_string(),
+            client: Client::builder().build()?,
+            cache: Mutex::new(LruCache::new(NonZeroUsize::new(64).unwrap())),
+        })
+    }
+
+    // Sends a request to the given endpoint of the repository
+    fn send(&self, method: reqwest::Method, endpoint: &str, body: &[u8]) -> Result<Response> {
+        self.client
+            .request(method, self.url.clone() + endpoint)
+            .bearer_auth(&self.token)
+            .body(body.to_vec())
+            .send()
+            .map_err(|e| {
+                anyhow!(MeldaError::AdapterIo {
+                    message: e.to_string()
+                })
+            })
+    }
+
+    // Fails with an error describing an unexpected response
+    fn failure(key: &str, response: Response) -> anyhow::Error {
+        match response.status().as_u16() {
+            404 => anyhow!(MeldaError::NotFound {
+                key: key.to_string()
+            }),
+            401 | 403 => anyhow!("access_denied"),
+            409 => anyhow!("object_exists: {}", key),
+            status => anyhow!(MeldaError::AdapterIo {
+                message: format!("{}: {}", status, response.text().unwrap_or_default())
+            }),
+        }
+    }
+}
+
+impl Adapter for RemoteAdapter {
+    /// Reads an object or a sub-object from the server (objects are always downloaded as a whole)
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `offset` - The starting position of the sub-object in the associated data pack
+    /// * `length` - The length of the sub-object (in bytes) in the associated data pack
+    ///
+    fn read_object(&self, key: &str, offset: usize, length: usize) -> Result<Vec<u8>> {
+        let cached = self.cache.lock().unwrap().get(key).cloned();
+        let data = match cached {
+            Some(data) => data,
+            None => {
+                let response =
+                    self.send(reqwest::Method::GET, &format!("/objects/{}", key), &[])?;
+                if response.status().as_u16() != 200 {
+                    return Err(Self::failure(key, response));
+                }
+                let data = response
+                    .bytes()
+                    .map_err(|e| MeldaError::AdapterIo {
+                        message: e.to_string(),
+                    })?
+                    .to_vec();
+                self.cache
+                    .lock()
+                    .unwrap()
+                    .put(key.to_string(), data.clone());
+                data
+            }
+        };
+        if offset == 0 && length == 0 {
+            Ok(data)
+        } else if offset + length <= data.len() {
+            Ok(data[offset..offset + length].to_vec())
+        } else {
+            bail!("invalid_object_range")
+        }
+    }
+
+    /// Writes an object to the server (existing objects cannot be overwritten)
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `data` - The content of the object
+    fn write_object(&self, key: &str, data: &[u8]) -> Result<()> {
+        let response = self.send(reqwest::Method::PUT, &format!("/objects/{}", key), data)?;
+        if response.status().as_u16() != 201 {
+            return Err(Self::failure(key, response));
+        }
+        Ok(())
+    }
+
+    /// Lists the keys of all objects whose key ends with ext. If ext is an empty string, all objects are returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    fn list_objects(&self, ext: &str) -> Result<Vec<String>> {
+        let mut endpoint = url::Url::parse("http://localhost/objects")?;
+        endpoint.query_pairs_mut().append_pair("ext", ext);
+        let endpoint = endpoint.path().to_string() + "?" + endpoint.query().unwrap_or_default();
+        let response = self.send(reqwest::Method::GET, &endpoint, &[])?;
+        match response.status().as_u16() {
+            200 => Ok(serde_json::from_slice(&response.bytes()?)?),
+            // Repositories are created on first write
+            404 => Ok(vec![]),
+            _ => Err(Self::failure("objects", response)),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use crate::adapter::Adapter;
+    use crate::capability::Capability;
+    use crate::melda::Melda;
+    use crate::remoteadapter::RemoteAdapter;
+    use crate::server::RepositoryServer;
+    use serde_json::json;
+    use std::sync::{Arc, RwLock};
+
+    #[test]
+    fn test_meld_with_server() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let server = RepositoryServer::new(&dir, b"secret").unwrap();
+        let rw = server.issue("alice", &Capability::read_write()).unwrap();
+        let ro = server.issue("alice", &Capability::read_only()).unwrap();
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let address = format!("127.0.0.1:{}", port);
+        std::thread::spawn({
+            let address = address.clone();
+            move || server.serve(&address, 2)
+        });
+        let url = format!("http://{}", address);
+        let remote = |token: &str| -> Arc<RwLock<Box<dyn Adapter>>> {
+            for _ in 0..50 {
+                if std::net::TcpStream::connect(&address).is_ok() {
+                    break;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(20));
+            }
+            Arc::new(RwLock::new(Box::new(
+                RemoteAdapter::new(&url, "alice", token).unwrap(),
+            )))
+        };
+        let replica = Melda::new_from_url("memory://").unwrap();
+        replica
+            .update(json!({ "key" : "value" }).as_object().unwrap().clone())
+            .unwrap();
+        replica.commit(None).unwrap();
+        // Push the changes to the server
+        let hosted = Melda::new(remote(&rw)).unwrap();
+        assert!(!hosted.meld(&replica).unwrap().is_empty());
+        assert!(hosted.meld(&replica).unwrap().is_empty());
+        // Pull the changes from the server
+        let other = Melda::new_from_url("memory://").unwrap();
+        other.meld(&Melda::new(remote(&ro)).unwrap()).unwrap();
+        other.refresh().unwrap();
+        assert_eq!(other.read(None).unwrap().get("key").unwrap(), "value");
+        let adapter = crate::adapter::get_adapter(&format!("melda://:{}@{}/alice", ro, address));
+        let hosted = Melda::new(Arc::new(RwLock::new(adapter.unwrap()))).unwrap();
+        assert_eq!(hosted.read(None).unwrap().get("key").unwrap(), "value");
+        // Read-only tokens cannot push changes
+        other
+            .update(json!({ "key" : "other" }).as_object().unwrap().clone())
+            .unwrap();
+        other.commit(None).unwrap();
+        assert!(Melda::new(remote(&ro)).unwrap().meld(&other).is_err());
+        let adapter = remote(&rw);
+        let adapter = adapter.read().unwrap();
+        assert!(adapter.delete_object("x").is_err());
+        let key = adapter.list_objects("").unwrap()[0].clone();
+        let data = adapter.read_object(&key, 0, 0).unwrap();
+        assert!(adapter.write_object(&key, &data).is_err());
+    }
+}
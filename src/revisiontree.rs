@@ -123,13 +123,15 @@ impl RevisionTree {
         self.revisions.is_empty()
     }
 
-    /// Commits staged changes (resets staging flag)
-    pub fn commit(&mut self) {
+    /// Commits the given staged revisions only (other staged revisions remain staged)
+    pub fn commit_revisions(&mut self, revisions: &[Revision]) {
         if self.staging {
-            self.revisions.iter().for_each(|rte| {
-                rte.1.commit();
-            });
-            self.staging = false;
+            for rev in revisions {
+                if let Some(rte) = self.revisions.get(rev) {
+                    rte.commit();
+                }
+            }
+            self.staging = self.revisions.values().any(|rte| rte.is_staging());
         }
     }
 
@@ -151,6 +153,40 @@ impl RevisionTree {
         &self.leafs
     }
 
+    /// Returns the nearest revision which is an ancestor of all the given revisions (a revision
+    /// is an ancestor of itself), if any
+    pub fn common_ancestor(&self, revisions: &BTreeSet<Revision>) -> Option<&Revision> {
+        let mut common: Option<BTreeSet<&Revision>> = None;
+        for revision in revisions {
+            let mut ancestors = BTreeSet::new();
+            let mut current = self.revisions.get_key_value(revision).map(|(r, _)| r);
+            while let Some(r) = current {
+                ancestors.insert(r);
+                current = self.revisions[r]
+                    .get_parent()
+                    .as_ref()
+                    .and_then(|p| self.revisions.get_key_value(p).map(|(r, _)| r));
+            }
+            common = Some(match common {
+                Some(common) => common.intersection(&ancestors).copied().collect(),
+                None => ancestors,
+            });
+        }
+        common?.into_iter().max_by_key(|r| r.index())
+    }
+
+    /// Returns true if the first revision is a (strict) ancestor of the second one
+    pub fn is_ancestor(&self, ancestor: &Revision, revision: &Revision) -> bool {
+        let mut current = self.revisions.get(revision).and_then(|e| e.parent.as_ref());
+        while let Some(r) = current {
+            if r == ancestor {
+                return true;
+            }
+            current = self.revisions.get(r).and_then(|e| e.parent.as_ref());
+        }
+        false
+    }
+
     /// Returns the parent of a revision
     pub fn get_parent(&self, revision: &Revision) -> Option<&Revision> {
         self.revisions.iter().find_map(|(rev, rte)| {
@@ -204,6 +240,24 @@ mod tests {
         assert!(w.to_string() == "3-xyz_cde");
     }
 
+    #[test]
+    fn test_is_ancestor() {
+        let mut rt = super::RevisionTree::new();
+        let r1 = crate::revision::Revision::from("1-abc").unwrap();
+        let r2 = crate::revision::Revision::from("2-abc_cde").unwrap();
+        let r3a = crate::revision::Revision::from("3-aaa_cde").unwrap();
+        let r3b = crate::revision::Revision::from("3-bbb_cde").unwrap();
+        rt.add(r1.clone(), None, false);
+        rt.add(r2.clone(), Some(r1.clone()), false);
+        rt.add(r3a.clone(), Some(r2.clone()), false);
+        rt.add(r3b.clone(), Some(r2.clone()), false);
+        assert!(rt.is_ancestor(&r1, &r3a));
+        assert!(rt.is_ancestor(&r2, &r3b));
+        assert!(!rt.is_ancestor(&r3a, &r3b));
+        assert!(!rt.is_ancestor(&r3a, &r1));
+        assert!(!rt.is_ancestor(&r2, &r2));
+    }
+
     #[test]
     fn test_leafs() {
         let mut rt = super::RevisionTree::new();
@@ -247,10 +301,42 @@ mod tests {
         assert!(l.contains(&crate::revision::Revision::from("3-abc_cde").unwrap()));
         assert!(l.contains(&crate::revision::Revision::from("4-xyz_cde").unwrap()));
         // Verify order
-        let lvec: Vec<&super::Revision> = l.into_iter().collect();
+        let lvec: Vec<&super::Revision> = l.iter().collect();
         assert!(*lvec[0] == crate::revision::Revision::from("3-abc_cde").unwrap());
         assert!(*lvec[1] == crate::revision::Revision::from("4-xyz_cde").unwrap());
         let w = rt.get_winner().unwrap();
         assert!(lvec[1] == w);
     }
+
+    #[test]
+    fn test_commit_revisions() {
+        let rev = |s: &str| crate::revision::Revision::from(s).unwrap();
+        let mut rt = super::RevisionTree::new();
+        rt.add(rev("1-abc"), None, true);
+        rt.add(rev("2-abc_cde"), Some(rev("1-abc")), true);
+        rt.commit_revisions(&[rev("1-abc")]);
+        assert!(rt.has_staging());
+        assert!(!rt.get_revisions()[&rev("1-abc")].is_staging());
+        assert!(rt.get_revisions()[&rev("2-abc_cde")].is_staging());
+        rt.commit_revisions(&[rev("2-abc_cde")]);
+        assert!(!rt.has_staging());
+    }
+
+    #[test]
+    fn test_common_ancestor() {
+        let rev = |s: &str| crate::revision::Revision::from(s).unwrap();
+        let mut rt = super::RevisionTree::new();
+        rt.add(rev("1-abc"), None, false);
+        rt.add(rev("2-abc_cde"), Some(rev("1-abc")), false);
+        rt.add(rev("3-aaa_cde"), Some(rev("2-abc_cde")), false);
+        rt.add(rev("3-xyz_cde"), Some(rev("2-abc_cde")), false);
+        rt.add(rev("4-r_cde"), Some(rev("3-aaa_cde")), false);
+        rt.add(rev("1-def"), None, false);
+        let leafs = rt.get_leafs().clone();
+        assert!(rt.common_ancestor(&leafs).is_none());
+        let revisions = vec![rev("4-r_cde"), rev("3-xyz_cde")].into_iter().collect();
+        assert!(*rt.common_ancestor(&revisions).unwrap() == rev("2-abc_cde"));
+        let revisions = vec![rev("4-r_cde"), rev("3-aaa_cde")].into_iter().collect();
+        assert!(*rt.common_ancestor(&revisions).unwrap() == rev("3-aaa_cde"));
+    }
 }
@@ -0,0 +1,390 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::error::MeldaError;
+use crate::utils::encode_json_pointer;
+use anyhow::{bail, Result};
+use regex::Regex;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// A value which does not satisfy a schema
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SchemaViolation {
+    /// JSON Pointer (RFC 6901) to the offending value
+    pub pointer: String,
+    /// The violated keyword, followed by details
+    pub reason: String,
+}
+
+impl SchemaViolation {
+    /// Converts the violation into an error (rejecting a document)
+    pub fn into_error(self) -> MeldaError {
+        MeldaError::InvalidDocument {
+            pointer: self.pointer,
+            reason: format!("schema_violation: {}", self.reason),
+        }
+    }
+}
+
+/// A JSON Schema against which documents are validated (as returned by
+/// [crate::melda::Melda::read], hence flattened fields keep their suffix and objects carry their
+/// identifier). Supported keywords: `type`, `enum`, `const`, `properties`, `required`,
+/// `additionalProperties`, `items`, `minItems`, `maxItems`, `uniqueItems`, `minimum`,
+/// `maximum`, `exclusiveMinimum`, `exclusiveMaximum`, `minLength`, `maxLength`, `pattern`,
+/// `allOf`, `anyOf`, `oneOf` and `not` (other keywords, such as `$ref`, are ignored).
+///
+/// # Example
+/// ```
+/// use melda::schema::Schema;
+/// use serde_json::json;
+/// let schema = Schema::new(json!({
+///     "type" : "object",
+///     "required" : [ "title" ],
+///     "properties" : { "title" : { "type" : "string", "maxLength" : 8 } }
+/// })).unwrap();
+/// assert!(schema.validate(&json!({ "title" : "Todo" })).is_empty());
+/// let violations = schema.validate(&json!({ "title" : "A long title" }));
+/// assert_eq!(violations[0].pointer, "/title");
+/// assert_eq!(violations[0].reason, "maxLength: 8");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Schema {
+    schema: Value,
+    patterns: HashMap<String, Regex>,
+}
+
+impl Schema {
+    /// Compiles a schema
+    ///
+    /// # Arguments
+    ///
+    /// * `schema` - The schema (an object or a boolean)
+    pub fn new(schema: Value) -> Result<Schema> {
+        let mut patterns = HashMap::new();
+        compile(&schema, &mut patterns)?;
+        Ok(Schema { schema, patterns })
+    }
+
+    /// Returns the schema
+    pub fn as_value(&self) -> &Value {
+        &self.schema
+    }
+
+    /// Returns the violations of the schema by the given value (empty if the value is valid)
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The value to validate
+    pub fn validate(&self, value: &Value) -> Vec<SchemaViolation> {
+        let mut violations = vec![];
+        self.check(&self.schema, value, &mut vec![], &mut violations);
+        violations
+    }
+
+    // Appends the violations of a (sub)schema by the value at the given location
+    fn check(
+        &self,
+        schema: &Value,
+        value: &Value,
+        pointer: &mut Vec<String>,
+        violations: &mut Vec<SchemaViolation>,
+    ) {
+        let schema = match schema {
+            Value::Bool(true) => return,
+            Value::Bool(false) => {
+                return violations.push(violation(pointer, "false_schema".to_string()))
+            }
+            Value::Object(schema) => schema,
+            _ => return,
+        };
+        let mut fail = |reason: String| violations.push(violation(pointer, reason));
+        if let Some(expected) = schema.get("type") {
+            let matches = match expected {
+                Value::Array(types) => types.iter().any(|t| has_type(value, t)),
+                t => has_type(value, t),
+            };
+            if !matches {
+                fail(format!("type: {}", expected));
+                // Other keywords would only report the same mismatch
+                return;
+            }
+        }
+        if let Some(Value::Array(values)) = schema.get("enum") {
+            if !values.contains(value) {
+                fail("enum".to_string());
+            }
+        }
+        if let Some(expected) = schema.get("const") {
+            if expected != value {
+                fail("const".to_string());
+            }
+        }
+        match value {
+            Value::Number(n) => {
+                let n = n.as_f64().unwrap_or_default();
+                let bound = |keyword: &str| schema.get(keyword).and_then(Value::as_f64);
+                if bound("minimum").is_some_and(|m| n < m) {
+                    fail(format!("minimum: {}", schema["minimum"]));
+                }
+                if bound("maximum").is_some_and(|m| n > m) {
+                    fail(format!("maximum: {}", schema["maximum"]));
+                }
+                if bound("exclusiveMinimum").is_some_and(|m| n <= m) {
+                    fail(format!("exclusiveMinimum: {}", schema["exclusiveMinimum"]));
+                }
+                if bound("exclusiveMaximum").is_some_and(|m| n >= m) {
+                    fail(format!("exclusiveMaximum: {}", schema["exclusiveMaximum"]));
+                }
+            }
+            Value::String(s) => {
+                let length = s.chars().count() as u64;
+                let bound = |keyword: &str| schema.get(keyword).and_then(Value::as_u64);
+                if let Some(min) = bound("minLength").filter(|m| length < *m) {
+                    fail(format!("minLength: {}", min));
+                }
+                if let Some(max) = bound("maxLength").filter(|m| length > *m) {
+                    fail(format!("maxLength: {}", max));
+                }
+                if let Some(Value::String(pattern)) = schema.get("pattern") {
+                    if !self.patterns[pattern].is_match(s) {
+                        fail(format!("pattern: {}", pattern));
+                    }
+                }
+            }
+            _ => {}
+        }
+        match value {
+            Value::Array(elements) => self.check_array(schema, elements, pointer, violations),
+            Value::Object(object) => self.check_object(schema, object, pointer, violations),
+            _ => {}
+        }
+        self.check_combinators(schema, value, pointer, violations);
+    }
+
+    // Appends the violations of the array keywords
+    fn check_array(
+        &self,
+        schema: &Map<String, Value>,
+        elements: &[Value],
+        pointer: &mut Vec<String>,
+        violations: &mut Vec<SchemaViolation>,
+    ) {
+        let length = elements.len() as u64;
+        let bound = |keyword: &str| schema.get(keyword).and_then(Value::as_u64);
+        if let Some(min) = bound("minItems").filter(|m| length < *m) {
+            violations.push(violation(pointer, format!("minItems: {}", min)));
+        }
+        if let Some(max) = bound("maxItems").filter(|m| length > *m) {
+            violations.push(violation(pointer, format!("maxItems: {}", max)));
+        }
+        if schema.get("uniqueItems") == Some(&Value::Bool(true))
+            && elements
+                .iter()
+                .enumerate()
+                .any(|(i, e)| elements[..i].contains(e))
+        {
+            violations.push(violation(pointer, "uniqueItems".to_string()));
+        }
+        if let Some(items) = schema.get("items") {
+            for (i, element) in elements.iter().enumerate() {
+                pointer.push(i.to_string());
+                self.check(items, element, pointer, violations);
+                pointer.pop();
+            }
+        }
+    }
+
+    // Appends the violations of the object keywords
+    fn check_object(
+        &self,
+        schema: &Map<String, Value>,
+        object: &Map<String, Value>,
+        pointer: &mut Vec<String>,
+        violations: &mut Vec<SchemaViolation>,
+    ) {
+        if let Some(Value::Array(required)) = schema.get("required") {
+            for key in required.iter().filter_map(Value::as_str) {
+                if !object.contains_key(key) {
+                    violations.push(violation(pointer, format!("required: {}", key)));
+                }
+            }
+        }
+        let properties = schema.get("properties").and_then(Value::as_object);
+        for (key, value) in object {
+            let subschema = match properties.and_then(|p| p.get(key)) {
+                Some(subschema) => subschema,
+                None => match schema.get("additionalProperties") {
+                    Some(additional) => additional,
+                    None => continue,
+                },
+            };
+            pointer.push(key.clone());
+            self.check(subschema, value, pointer, violations);
+            pointer.pop();
+        }
+    }
+
+    // Appends the violations of the combining keywords
+    fn check_combinators(
+        &self,
+        schema: &Map<String, Value>,
+        value: &Value,
+        pointer: &[String],
+        violations: &mut Vec<SchemaViolation>,
+    ) {
+        let mut valid = |subschema: &Value| {
+            let mut nested = vec![];
+            self.check(subschema, value, &mut pointer.to_vec(), &mut nested);
+            nested.is_empty()
+        };
+        let subschemas = |keyword: &str| match schema.get(keyword) {
+            Some(Value::Array(subschemas)) => subschemas.as_slice(),
+            _ => &[],
+        };
+        let mut reasons = vec![];
+        if !subschemas("allOf").iter().all(&mut valid) {
+            reasons.push("allOf");
+        }
+        if !subschemas("anyOf").is_empty() && !subschemas("anyOf").iter().any(&mut valid) {
+            reasons.push("anyOf");
+        }
+        if !subschemas("oneOf").is_empty()
+            && subschemas("oneOf").iter().filter(|s| valid(s)).count() != 1
+        {
+            reasons.push("oneOf");
+        }
+        if schema.get("not").is_some_and(&mut valid) {
+            reasons.push("not");
+        }
+        for reason in reasons {
+            violations.push(violation(pointer, reason.to_string()));
+        }
+    }
+}
+
+// Checks the structure of a (sub)schema, compiling its patterns
+fn compile(schema: &Value, patterns: &mut HashMap<String, Regex>) -> Result<()> {
+    let schema = match schema {
+        Value::Bool(_) => return Ok(()),
+        Value::Object(schema) => schema,
+        _ => bail!("invalid_schema"),
+    };
+    for (keyword, value) in schema {
+        match (keyword.as_str(), value) {
+            ("pattern", Value::String(pattern)) => {
+                let regex = Regex::new(pattern).map_err(|_| anyhow::anyhow!("invalid_pattern"))?;
+                patterns.insert(pattern.clone(), regex);
+            }
+            ("pattern", _) => bail!("invalid_schema"),
+            ("items" | "additionalProperties" | "not", subschema) => compile(subschema, patterns)?,
+            ("properties", Value::Object(properties)) => {
+                for subschema in properties.values() {
+                    compile(subschema, patterns)?;
+                }
+            }
+            ("allOf" | "anyOf" | "oneOf", Value::Array(subschemas)) => {
+                for subschema in subschemas {
+                    compile(subschema, patterns)?;
+                }
+            }
+            ("properties" | "allOf" | "anyOf" | "oneOf", _) => bail!("invalid_schema"),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// Returns true if the value has the given JSON Schema type
+fn has_type(value: &Value, t: &Value) -> bool {
+    match (t.as_str(), value) {
+        (Some("null"), Value::Null)
+        | (Some("boolean"), Value::Bool(_))
+        | (Some("number"), Value::Number(_))
+        | (Some("string"), Value::String(_))
+        | (Some("array"), Value::Array(_))
+        | (Some("object"), Value::Object(_)) => true,
+        (Some("integer"), Value::Number(n)) => {
+            n.is_i64() || n.is_u64() || n.as_f64().is_some_and(|f| f.fract() == 0.0)
+        }
+        _ => false,
+    }
+}
+
+fn violation(pointer: &[String], reason: String) -> SchemaViolation {
+    SchemaViolation {
+        pointer: encode_json_pointer(pointer),
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schema::Schema;
+    use serde_json::json;
+
+    #[test]
+    fn test_schema() {
+        let schema = Schema::new(json!({
+            "type" : "object",
+            "properties" : {
+                "items\u{266D}" : {
+                    "type" : "array",
+                    "maxItems" : 2,
+                    "items" : {
+                        "required" : [ "_id", "count" ],
+                        "properties" : {
+                            "count" : { "type" : "integer", "minimum" : 0 },
+                            "tag" : { "enum" : [ "a", "b" ] },
+                        },
+                        "additionalProperties" : { "type" : "string", "pattern" : "^[a-z]+$" }
+                    }
+                },
+                "status" : { "anyOf" : [ { "const" : "open" }, { "type" : "null" } ] }
+            }
+        }))
+        .unwrap();
+        let valid = json!({ "items\u{266D}" : [ { "_id" : "x", "count" : 1, "tag" : "a" } ], "status" : null });
+        assert!(schema.validate(&valid).is_empty());
+        let invalid = json!({
+            "items\u{266D}" : [ { "_id" : "X", "count" : -1.5 }, { "_id" : "y", "tag" : "c" }, { "_id" : "z", "count" : 0 } ],
+            "status" : "closed"
+        });
+        let violations: Vec<(String, String)> = schema
+            .validate(&invalid)
+            .into_iter()
+            .map(|v| (v.pointer, v.reason))
+            .collect();
+        assert_eq!(
+            violations,
+            [
+                ("/items\u{266D}", "maxItems: 2"),
+                ("/items\u{266D}/0/_id", "pattern: ^[a-z]+$"),
+                ("/items\u{266D}/0/count", "type: \"integer\""),
+                ("/items\u{266D}/1", "required: count"),
+                ("/items\u{266D}/1/tag", "enum"),
+                ("/status", "anyOf"),
+            ]
+            .map(|(p, r)| (p.to_string(), r.to_string()))
+        );
+        assert!(Schema::new(json!({ "pattern" : "(" })).is_err());
+        assert!(Schema::new(json!([])).is_err());
+        assert_eq!(
+            Schema::new(json!(false)).unwrap().validate(&json!(1)).len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,241 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::Bound;
+
+/// Key of a secondary index: values of different types are ordered by type (null, booleans,
+/// numbers, strings, then arrays and objects, compared by their serialization)
+#[derive(Debug, Clone)]
+pub(crate) enum IndexKey {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Other(String),
+}
+
+impl IndexKey {
+    pub fn new(value: &Value) -> Self {
+        match value {
+            Value::Null => IndexKey::Null,
+            Value::Bool(b) => IndexKey::Bool(*b),
+            Value::Number(n) => IndexKey::Number(n.as_f64().unwrap_or_default()),
+            Value::String(s) => IndexKey::String(s.clone()),
+            v => IndexKey::Other(v.to_string()),
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            IndexKey::Null => 0,
+            IndexKey::Bool(_) => 1,
+            IndexKey::Number(_) => 2,
+            IndexKey::String(_) => 3,
+            IndexKey::Other(_) => 4,
+        }
+    }
+}
+
+impl Ord for IndexKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (IndexKey::Bool(a), IndexKey::Bool(b)) => a.cmp(b),
+            (IndexKey::Number(a), IndexKey::Number(b)) => a.total_cmp(b),
+            (IndexKey::String(a), IndexKey::String(b))
+            | (IndexKey::Other(a), IndexKey::Other(b)) => a.cmp(b),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for IndexKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for IndexKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for IndexKey {}
+
+/// Index of the elements of a collection (a flattened array of objects in the root object) by
+/// the value of one of their fields. Elements lacking the field are members of the collection
+/// but are not indexed.
+pub(crate) struct SecondaryIndex {
+    pub collection: String,
+    pub field: String,
+    pub descriptor: Option<String>, // Array descriptor of the collection (if it exists)
+    members: HashMap<String, Option<IndexKey>>,
+    entries: BTreeMap<IndexKey, BTreeSet<String>>,
+}
+
+impl SecondaryIndex {
+    /// Creates a new (empty) index
+    pub fn new(collection: &str, field: &str) -> Self {
+        SecondaryIndex {
+            collection: collection.to_string(),
+            field: field.to_string(),
+            descriptor: None,
+            members: HashMap::new(),
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Returns true if the object is an element of the collection
+    pub fn contains(&self, uuid: &str) -> bool {
+        self.members.contains_key(uuid)
+    }
+
+    /// Retains the elements satisfying the predicate
+    pub fn retain<F: Fn(&str) -> bool>(&mut self, keep: F) {
+        let removed: Vec<String> = self
+            .members
+            .keys()
+            .filter(|uuid| !keep(uuid))
+            .cloned()
+            .collect();
+        for uuid in removed {
+            self.remove(&uuid);
+        }
+    }
+
+    /// Sets the key of an element (None if the element lacks the field)
+    pub fn set(&mut self, uuid: &str, key: Option<IndexKey>) {
+        self.remove(uuid);
+        if let Some(key) = &key {
+            self.entries
+                .entry(key.clone())
+                .or_default()
+                .insert(uuid.to_string());
+        }
+        self.members.insert(uuid.to_string(), key);
+    }
+
+    /// Removes an element
+    pub fn remove(&mut self, uuid: &str) {
+        if let Some(Some(key)) = self.members.remove(uuid) {
+            if let Some(uuids) = self.entries.get_mut(&key) {
+                uuids.remove(uuid);
+                if uuids.is_empty() {
+                    self.entries.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// Returns the elements whose key falls within the bounds, ordered by key (then by
+    /// identifier)
+    pub fn range(&self, from: Bound<IndexKey>, to: Bound<IndexKey>) -> Vec<String> {
+        let empty = match (&from, &to) {
+            (Bound::Included(a), Bound::Included(b)) => a > b,
+            (Bound::Included(a) | Bound::Excluded(a), Bound::Excluded(b))
+            | (Bound::Excluded(a), Bound::Included(b)) => a >= b,
+            _ => false,
+        };
+        if empty {
+            return vec![];
+        }
+        self.entries
+            .range((from, to))
+            .flat_map(|(_, uuids)| uuids.iter().cloned())
+            .collect()
+    }
+}
+
+/// Objects changed since the secondary indexes were last updated
+#[derive(Default)]
+pub(crate) struct IndexChanges {
+    pub tracking: bool,           // Changes are only recorded if there are indexes
+    pub all: bool,                // Any object may have changed
+    pub objects: HashSet<String>, // Changed objects
+}
+
+impl IndexChanges {
+    /// Records that the given objects changed
+    pub fn record<'a, I: IntoIterator<Item = &'a str>>(&mut self, uuids: I) {
+        if self.tracking && !self.all {
+            self.objects.extend(uuids.into_iter().map(str::to_string));
+        }
+    }
+
+    /// Records that any object may have changed
+    pub fn record_all(&mut self) {
+        if self.tracking {
+            self.all = true;
+            self.objects.clear();
+        }
+    }
+
+    /// Returns (and forgets) the recorded changes
+    pub fn take(&mut self) -> (bool, HashSet<String>) {
+        (
+            std::mem::take(&mut self.all),
+            std::mem::take(&mut self.objects),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::secondaryindex::{IndexKey, SecondaryIndex};
+    use serde_json::json;
+    use std::ops::Bound;
+
+    #[test]
+    fn test_secondary_index() {
+        let mut index = SecondaryIndex::new("tasks♭", "priority");
+        index.set("a", Some(IndexKey::new(&json!(2))));
+        index.set("b", Some(IndexKey::new(&json!(1.0))));
+        index.set("c", Some(IndexKey::new(&json!("high"))));
+        index.set("d", None);
+        index.set("e", Some(IndexKey::new(&json!(1))));
+        assert!(index.contains("d"));
+        let key = |v| IndexKey::new(&v);
+        assert_eq!(
+            index.range(
+                Bound::Included(key(json!(1))),
+                Bound::Included(key(json!(1)))
+            ),
+            ["b", "e"]
+        );
+        assert_eq!(
+            index.range(Bound::Unbounded, Bound::Unbounded),
+            ["b", "e", "a", "c"]
+        );
+        assert!(index
+            .range(
+                Bound::Excluded(key(json!(2))),
+                Bound::Included(key(json!(1)))
+            )
+            .is_empty());
+        index.set("a", Some(IndexKey::new(&json!(1))));
+        index.retain(|uuid| uuid != "b");
+        assert_eq!(
+            index.range(
+                Bound::Included(key(json!(1))),
+                Bound::Excluded(key(json!(2)))
+            ),
+            ["a", "e"]
+        );
+        assert!(!index.contains("b"));
+    }
+}
@@ -0,0 +1,763 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::adapter::Adapter;
+use crate::capability::{Capability, CapabilityAuthority};
+use crate::error::MeldaError;
+use crate::filesystemadapter::FilesystemAdapter;
+use crate::melda::{verify_upload, CompactionReport, Melda};
+use crate::topology::{Replicator, Topology};
+use anyhow::{anyhow, Result};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Default time after which the presence of a peer which did not publish it again expires
+pub const DEFAULT_PRESENCE_TTL: Duration = Duration::from_secs(30);
+
+// Presence of the peers of a repository, along with its expiry (presence published through the
+// sync channel lasts as long as the connection)
+type Peers = HashMap<String, (Value, Option<Instant>)>;
+
+/// Response produced by the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn new(status: u16, body: Vec<u8>) -> Self {
+        Response { status, body }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Response::new(status, message.as_bytes().to_vec())
+    }
+}
+
+/// Hosts many repositories (one directory each under a common root) and exposes the objects of
+/// their adapters over HTTP, so that clients can meld with them (replicas can use a
+/// [crate::remoteadapter::RemoteAdapter] with the `remote` feature):
+///
+/// * `GET /<repository>/objects?ext=<ext>` lists the keys of the objects (a JSON array)
+/// * `GET /<repository>/objects/<key>` returns the content of an object
+/// * `PUT /<repository>/objects/<key>` stores an object (repositories are created on first write).
+///   Objects are immutable: existing keys are never overwritten, and blocks, packs and
+///   attachments must match the digest in their key
+///
+/// Peers connected to a repository can also share ephemeral presence data (who is online, what
+/// they are selecting), which is only kept in memory and never touches the repository. The
+/// identity of a peer is the one bound to its token (see [Capability::with_peer]):
+///
+/// * `GET /<repository>/presence` returns the presence of the peers (a JSON object)
+/// * `PUT /<repository>/presence` publishes the presence of the peer (any JSON value) and
+///   returns the presence of the peers, see [RepositoryServer::with_presence_ttl]
+/// * `DELETE /<repository>/presence` withdraws the presence of the peer
+///
+/// Requests must carry a capability token (`Authorization: Bearer <token>`) issued for the
+/// repository, see [RepositoryServer::issue]: read-only tokens only allow GET requests, and
+/// expired tokens are rejected.
+///
+/// With the `grpc` feature, the same operations are available through a gRPC interface, see
+/// [RepositoryServer::with_grpc]. With the `websocket` feature, clients can instead keep a
+/// connection to the sync channel of a repository, which notifies the uploaded objects and
+/// carries the presence of the peers without polling, see [RepositoryServer::with_websocket].
+///
+/// The server can compact the hosted repositories in the background, see
+/// [RepositoryServer::with_compaction].
+///
+/// The server can also replicate the repositories it hosts with other repositories, following
+/// a replication topology, see [RepositoryServer::with_topology].
+///
+/// # Example
+/// ```
+/// use melda::{capability::Capability, melda::Melda, server::RepositoryServer};
+/// use serde_json::json;
+/// let root = std::env::temp_dir().join("melda_server_doctest");
+/// let server = RepositoryServer::new(&root, b"server-secret").unwrap();
+/// let token = server.issue("alice", &Capability::read_write()).unwrap();
+/// let replica = Melda::new_from_url("memory://").unwrap();
+/// replica.update(json!({ "key" : "value" }).as_object().unwrap().clone()).unwrap();
+/// let block = replica.commit(None).unwrap().unwrap().into_iter().next().unwrap() + ".delta";
+/// let content = replica.get_adapter().read().unwrap().read_object(&block, 0, 0).unwrap();
+/// let url = format!("/alice/objects/{}", block);
+/// assert_eq!(server.handle("PUT", &url, Some(&token), &content).status, 201);
+/// assert_eq!(server.handle("GET", &url, Some(&token), b"").body, content);
+/// // Objects cannot be overwritten and must match their digest
+/// assert_eq!(server.handle("PUT", &url, Some(&token), b"{}").status, 409);
+/// assert_eq!(server.handle("PUT", "/alice/objects/ab.pack", Some(&token), b"{}").status, 400);
+/// assert_eq!(server.handle("GET", "/bob/objects?ext=.delta", Some(&token), b"").status, 401);
+/// std::fs::remove_dir_all(&root).unwrap();
+/// ```
+pub struct RepositoryServer {
+    root: PathBuf,
+    authority: CapabilityAuthority,
+    presence: Mutex<HashMap<String, Peers>>, // Peers of each repository
+    presence_ttl: Duration,
+    subscribers: Mutex<HashMap<String, Vec<Sender<Value>>>>, // Sync channels of each repository
+    topology: Option<Topology>, // Replication topology of the hosted repositories
+    locks: Mutex<HashMap<String, Arc<RwLock<()>>>>, // Writes and compactions are exclusive
+    written: Mutex<HashMap<String, Instant>>, // Last write of the repositories to compact
+    compaction: Option<Duration>,
+    #[cfg(feature = "grpc")]
+    grpc: Option<String>, // Address of the gRPC interface
+    #[cfg(feature = "websocket")]
+    websocket: Option<String>, // Address of the sync channel
+}
+
+impl RepositoryServer {
+    /// Creates a new server
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - The directory containing the repositories
+    /// * `secret` - The secret used to sign the tokens
+    pub fn new(root: &Path, secret: &[u8]) -> Result<Self> {
+        std::fs::create_dir_all(root)?;
+        Ok(RepositoryServer {
+            root: root.to_path_buf(),
+            authority: CapabilityAuthority::new(secret),
+            presence: Mutex::new(HashMap::new()),
+            presence_ttl: DEFAULT_PRESENCE_TTL,
+            subscribers: Mutex::new(HashMap::new()),
+            topology: None,
+            locks: Mutex::new(HashMap::new()),
+            written: Mutex::new(HashMap::new()),
+            compaction: None,
+            #[cfg(feature = "grpc")]
+            grpc: None,
+            #[cfg(feature = "websocket")]
+            websocket: None,
+        })
+    }
+
+    /// Sets the time after which the presence of a peer published over HTTP expires, unless
+    /// published again (peers are expected to publish their presence periodically, or to use the
+    /// sync channel). The default is [DEFAULT_PRESENCE_TTL]
+    pub fn with_presence_ttl(mut self, ttl: Duration) -> Self {
+        self.presence_ttl = ttl;
+        self
+    }
+
+    /// Sets the replication topology: while serving, the hosted repositories which are nodes
+    /// of the topology (nodes whose URL is `file://<root>/<repository>`) are synchronized with
+    /// their remotes along the links of the topology, see [Replicator]
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{server::RepositoryServer, topology::Topology};
+    /// use std::time::Duration;
+    /// let root = std::env::temp_dir().join("melda_topology_doctest");
+    /// let topology = Topology::new()
+    ///     .node("hub", &format!("file://{}/hub", root.display()))
+    ///     .node("edge", "file:///srv/edge")
+    ///     .hub_and_spoke("hub", Duration::from_secs(60));
+    /// let server = RepositoryServer::new(&root, b"server-secret").unwrap()
+    ///     .with_topology(topology).unwrap();
+    /// // The hub is hosted, but only the edge synchronizes (with the hub)
+    /// assert_eq!(server.hosted_nodes(), ["hub"]);
+    /// std::fs::remove_dir_all(&root).unwrap();
+    /// ```
+    pub fn with_topology(mut self, topology: Topology) -> Result<Self> {
+        topology.validate()?;
+        self.topology = Some(topology);
+        Ok(self)
+    }
+
+    /// Compacts the hosted repositories in the background (see [Melda::compact]) while serving:
+    /// a repository is compacted once no object has been written to it for the given time, so
+    /// that the packs uploaded by a client are not merged away before the blocks referencing
+    /// them are uploaded
+    pub fn with_compaction(mut self, idle: Duration) -> Self {
+        self.compaction = Some(idle);
+        self
+    }
+
+    /// Also serves the gRPC interface (see [crate::grpc]) on the given address while serving
+    #[cfg(feature = "grpc")]
+    pub fn with_grpc(mut self, address: &str) -> Self {
+        self.grpc = Some(address.to_string());
+        self
+    }
+
+    /// Also serves the sync channel of the repositories over WebSocket (see [crate::websocket])
+    /// on the given address while serving
+    #[cfg(feature = "websocket")]
+    pub fn with_websocket(mut self, address: &str) -> Self {
+        self.websocket = Some(address.to_string());
+        self
+    }
+
+    /// Compacts a hosted repository, see [Melda::compact]. Requests to the repository wait
+    /// until the compaction is completed
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{capability::Capability, melda::Melda, server::RepositoryServer};
+    /// use serde_json::json;
+    /// let root = std::env::temp_dir().join("melda_compaction_doctest");
+    /// let server = RepositoryServer::new(&root, b"server-secret").unwrap();
+    /// let token = server.issue("alice", &Capability::read_write()).unwrap();
+    /// let replica = Melda::new_from_url("memory://").unwrap();
+    /// for i in 0..3 {
+    ///     replica.update(json!({ "key" : i }).as_object().unwrap().clone()).unwrap();
+    ///     replica.commit(None).unwrap();
+    /// }
+    /// let adapter = replica.get_adapter();
+    /// let adapter = adapter.read().unwrap();
+    /// for key in adapter.list_objects("").unwrap() {
+    ///     let data = adapter.read_object(&key, 0, 0).unwrap();
+    ///     server.handle("PUT", &format!("/alice/objects/{}", key), Some(&token), &data);
+    /// }
+    /// assert_eq!(server.compact("alice").unwrap().removed_packs.len(), 3);
+    /// let response = server.handle("GET", "/alice/objects?ext=.pack", Some(&token), b"");
+    /// assert_eq!(serde_json::from_slice::<Vec<String>>(&response.body).unwrap().len(), 1);
+    /// std::fs::remove_dir_all(&root).unwrap();
+    /// ```
+    pub fn compact(&self, repository: &str) -> Result<CompactionReport> {
+        if !valid_name(repository, 1) {
+            return Err(anyhow!("invalid_repository_name"));
+        }
+        let path = self.root.join(repository);
+        if !path.is_dir() {
+            return Err(anyhow!("repository_not_found"));
+        }
+        let lock = self.lock(repository);
+        let _guard = lock.write().unwrap();
+        self.written.lock().unwrap().remove(repository);
+        let adapter: Box<dyn Adapter> = Box::new(
+            FilesystemAdapter::new(&path.to_string_lossy()).map_err(|e| anyhow!("{}", e))?,
+        );
+        Melda::new(Arc::new(RwLock::new(adapter)))?.compact()
+    }
+
+    // Compacts the repositories to which no object has been written for the given time
+    fn compact_idle(&self, idle: Duration) {
+        let now = Instant::now();
+        let idle: Vec<String> = self
+            .written
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, written)| now.duration_since(**written) >= idle)
+            .map(|(repository, _)| repository.clone())
+            .collect();
+        for repository in idle {
+            match self.compact(&repository) {
+                Ok(report) => log::info!(
+                    "compacted {}: {} packs merged",
+                    repository,
+                    report.removed_packs.len()
+                ),
+                Err(e) => log::warn!("cannot_compact {}: {}", repository, e),
+            }
+        }
+    }
+
+    // Returns the lock of a repository
+    fn lock(&self, repository: &str) -> Arc<RwLock<()>> {
+        self.locks
+            .lock()
+            .unwrap()
+            .entry(repository.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Returns the nodes of the replication topology which are hosted by the server
+    pub fn hosted_nodes(&self) -> Vec<String> {
+        let topology = match &self.topology {
+            Some(topology) => topology,
+            None => return vec![],
+        };
+        topology
+            .nodes
+            .iter()
+            .filter(|(_, url)| {
+                url::Url::parse(url).is_ok_and(|url| {
+                    url.scheme() == "file"
+                        && Path::new(url.path())
+                            .strip_prefix(&self.root)
+                            .ok()
+                            .and_then(|p| p.to_str())
+                            .is_some_and(|name| valid_name(name, 1))
+                })
+            })
+            .map(|(node, _)| node.clone())
+            .collect()
+    }
+
+    /// Issues a token granting the capability on the given repository (the identity of the peer,
+    /// if any, must be a valid name)
+    ///
+    /// # Example
+    /// ```
+    /// use melda::{capability::Capability, server::RepositoryServer};
+    /// use std::time::Duration;
+    /// let root = std::env::temp_dir().join("melda_issue_doctest");
+    /// let server = RepositoryServer::new(&root, b"server-secret").unwrap();
+    /// let token = server.issue("alice", &Capability::read_only().valid_for(Duration::ZERO)).unwrap();
+    /// assert_eq!(server.handle("GET", "/alice/presence", Some(&token), b"").status, 401);
+    /// assert!(server.issue("alice", &Capability::read_only().with_peer("../x")).is_err());
+    /// std::fs::remove_dir_all(&root).unwrap();
+    /// ```
+    pub fn issue(&self, repository: &str, capability: &Capability) -> Result<String> {
+        if !valid_name(repository, 1) {
+            return Err(anyhow!("invalid_repository_name"));
+        }
+        if capability.peer.as_ref().is_some_and(|p| !valid_name(p, 1)) {
+            return Err(anyhow!("invalid_peer_name"));
+        }
+        self.authority.derive(repository)?.issue(capability)
+    }
+
+    /// Handles a request
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - The HTTP method
+    /// * `url` - The requested path (including the query string)
+    /// * `token` - The token presented by the client
+    /// * `body` - The content of the request
+    pub fn handle(&self, method: &str, url: &str, token: Option<&str>, body: &[u8]) -> Response {
+        let url = match url::Url::parse("http://localhost").and_then(|base| base.join(url)) {
+            Ok(url) => url,
+            Err(_) => return Response::error(400, "invalid_url"),
+        };
+        let segments: Vec<&str> = url.path_segments().map(|s| s.collect()).unwrap_or_default();
+        let (repository, endpoint, key) = match segments.as_slice() {
+            [repository, endpoint @ ("objects" | "presence")] if valid_name(repository, 1) => {
+                (*repository, *endpoint, None)
+            }
+            [repository, "objects", key] if valid_name(repository, 1) && valid_name(key, 2) => {
+                (*repository, "objects", Some(*key))
+            }
+            _ => return Response::error(404, "invalid_endpoint"),
+        };
+        let capability = match self.authorize(repository, token) {
+            Ok(capability) => capability,
+            Err(response) => return response,
+        };
+        if endpoint == "presence" {
+            return self.handle_presence(method, repository, &capability, body);
+        }
+        let write = method == "PUT";
+        if write && capability.check_write().is_err() {
+            return Response::error(403, "access_denied");
+        }
+        let path = self.root.join(repository);
+        if !write && !path.is_dir() {
+            return Response::error(404, "repository_not_found");
+        }
+        let lock = self.lock(repository);
+        let _read = (!write).then(|| lock.read().unwrap());
+        let _write = write.then(|| lock.write().unwrap());
+        let result = FilesystemAdapter::new(&path.to_string_lossy())
+            .map_err(|e| anyhow!("{}", e))
+            .and_then(|adapter| match (method, key) {
+                ("GET", None) => {
+                    let ext = url
+                        .query_pairs()
+                        .find(|(k, _)| k == "ext")
+                        .map(|(_, v)| v.to_string())
+                        .unwrap_or_default();
+                    let keys = adapter.list_objects(&ext)?;
+                    Ok(Response::new(200, serde_json::to_vec(&keys)?))
+                }
+                ("GET", Some(key)) => Ok(Response::new(200, adapter.read_object(key, 0, 0)?)),
+                ("PUT", Some(key)) => {
+                    match adapter.object_size(key) {
+                        Ok(_) => return Ok(Response::error(409, "object_exists")),
+                        Err(e)
+                            if !matches!(MeldaError::of(&e), Some(MeldaError::NotFound { .. })) =>
+                        {
+                            return Err(e)
+                        }
+                        Err(_) => {}
+                    }
+                    if let Err(e) = verify_upload(&adapter, key, body) {
+                        return Ok(Response::error(400, &e.to_string()));
+                    }
+                    adapter.write_object(key, body)?;
+                    self.written
+                        .lock()
+                        .unwrap()
+                        .insert(repository.to_string(), Instant::now());
+                    self.notify(repository, json!({ "objects": [key] }));
+                    Ok(Response::new(201, vec![]))
+                }
+                _ => Ok(Response::error(405, "method_not_allowed")),
+            });
+        match result {
+            Ok(response) => response,
+            Err(e) => match MeldaError::of(&e) {
+                Some(MeldaError::NotFound { .. }) => Response::error(404, "object_not_found"),
+                _ => Response::error(500, &e.to_string()),
+            },
+        }
+    }
+
+    // Verifies the token presented for a repository, returning the granted capability (or the
+    // response denying access)
+    pub(crate) fn authorize(
+        &self,
+        repository: &str,
+        token: Option<&str>,
+    ) -> std::result::Result<Capability, Response> {
+        if !valid_name(repository, 1) {
+            return Err(Response::error(404, "invalid_endpoint"));
+        }
+        match token.map(|t| self.authority.derive(repository).and_then(|a| a.verify(t))) {
+            None => Err(Response::error(401, "missing_token")),
+            Some(Err(_)) => Err(Response::error(401, "invalid_token")),
+            Some(Ok(capability)) => Ok(capability),
+        }
+    }
+
+    // Handles a request to the presence endpoint of a repository: publishing or withdrawing
+    // presence requires write access and a token bound to a peer
+    fn handle_presence(
+        &self,
+        method: &str,
+        repository: &str,
+        capability: &Capability,
+        body: &[u8],
+    ) -> Response {
+        if method == "GET" {
+            return Response::new(200, self.presence_of(repository).to_string().into_bytes());
+        }
+        if method != "PUT" && method != "DELETE" {
+            return Response::error(405, "method_not_allowed");
+        }
+        if capability.check_write().is_err() {
+            return Response::error(403, "access_denied");
+        }
+        let peer = match &capability.peer {
+            Some(peer) => peer,
+            None => return Response::error(403, "missing_peer_identity"),
+        };
+        if method == "DELETE" {
+            self.publish(repository, peer, None);
+            return Response::new(204, vec![]);
+        }
+        match serde_json::from_slice::<Value>(body) {
+            Ok(state) => {
+                let expiry = Instant::now() + self.presence_ttl;
+                let states = self.publish(repository, peer, Some((state, Some(expiry))));
+                Response::new(200, states.to_string().into_bytes())
+            }
+            Err(_) => Response::error(400, "invalid_presence"),
+        }
+    }
+
+    // Returns the presence of the peers of a repository (a JSON object), dropping the expired
+    // presence
+    pub(crate) fn presence_of(&self, repository: &str) -> Value {
+        let mut presence = self.presence.lock().unwrap();
+        let now = Instant::now();
+        let states: Map<String, Value> = match presence.get_mut(repository) {
+            Some(peers) => {
+                peers.retain(|_, (_, expiry)| expiry.is_none_or(|e| e > now));
+                peers
+                    .iter()
+                    .map(|(peer, (state, _))| (peer.clone(), state.clone()))
+                    .collect()
+            }
+            None => Map::new(),
+        };
+        if states.is_empty() {
+            presence.remove(repository);
+        }
+        Value::from(states)
+    }
+
+    // Publishes (or withdraws, if the state is None) the presence of a peer and notifies the sync
+    // channels of the repository, returning the presence of the peers
+    pub(crate) fn publish(
+        &self,
+        repository: &str,
+        peer: &str,
+        state: Option<(Value, Option<Instant>)>,
+    ) -> Value {
+        {
+            let mut presence = self.presence.lock().unwrap();
+            let peers = presence.entry(repository.to_string()).or_default();
+            match state {
+                Some(state) => peers.insert(peer.to_string(), state),
+                None => peers.remove(peer),
+            };
+        }
+        let states = self.presence_of(repository);
+        self.notify(repository, json!({ "presence": states }));
+        states
+    }
+
+    // Subscribes to the notifications sent to the sync channels of a repository
+    #[cfg(feature = "websocket")]
+    pub(crate) fn subscribe(&self, repository: &str) -> std::sync::mpsc::Receiver<Value> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        self.subscribers
+            .lock()
+            .unwrap()
+            .entry(repository.to_string())
+            .or_default()
+            .push(sender);
+        receiver
+    }
+
+    // Sends a notification to the sync channels of a repository (dropping the closed ones)
+    fn notify(&self, repository: &str, message: Value) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if let Some(senders) = subscribers.get_mut(repository) {
+            senders.retain(|s| s.send(message.clone()).is_ok());
+            if senders.is_empty() {
+                subscribers.remove(repository);
+            }
+        }
+    }
+
+    /// Serves requests on the given address using the given number of worker threads. This
+    /// function does not return unless the server cannot be started
+    pub fn serve(self, address: &str, threads: usize) -> Result<()> {
+        let http = Arc::new(tiny_http::Server::http(address).map_err(|e| anyhow!("{}", e))?);
+        // Replicate the hosted repositories which synchronize with remotes
+        if let Some(topology) = &self.topology {
+            for node in self.hosted_nodes() {
+                if topology.links_of(&node).is_empty() {
+                    continue;
+                }
+                let mut replicator = Replicator::new(topology, &node)?;
+                std::thread::spawn(move || replicator.run(&AtomicBool::new(false)));
+            }
+        }
+        let server = Arc::new(self);
+        #[cfg(feature = "grpc")]
+        if let Some(address) = server.grpc.clone() {
+            let service = crate::grpc::RepositoryService::new(server.clone());
+            std::thread::spawn(move || {
+                if let Err(e) = service.serve(&address) {
+                    log::warn!("cannot_serve_grpc: {}", e);
+                }
+            });
+        }
+        #[cfg(feature = "websocket")]
+        if let Some(address) = server.websocket.clone() {
+            let channel = crate::websocket::SyncChannel::new(server.clone());
+            std::thread::spawn(move || {
+                if let Err(e) = channel.serve(&address) {
+                    log::warn!("cannot_serve_websocket: {}", e);
+                }
+            });
+        }
+        if let Some(idle) = server.compaction {
+            let server = server.clone();
+            std::thread::spawn(move || loop {
+                std::thread::sleep(idle.min(Duration::from_secs(60)));
+                server.compact_idle(idle);
+            });
+        }
+        let workers: Vec<_> = (0..threads.max(1))
+            .map(|_| {
+                let http = http.clone();
+                let server = server.clone();
+                std::thread::spawn(move || {
+                    for mut request in http.incoming_requests() {
+                        let token = request
+                            .headers()
+                            .iter()
+                            .find(|h| h.field.equiv("Authorization"))
+                            .and_then(|h| h.value.as_str().strip_prefix("Bearer "))
+                            .map(|t| t.trim().to_string());
+                        let mut body = vec![];
+                        let response = match request.as_reader().read_to_end(&mut body) {
+                            Ok(_) => server.handle(
+                                request.method().as_str(),
+                                request.url(),
+                                token.as_deref(),
+                                &body,
+                            ),
+                            Err(_) => Response::error(400, "cannot_read_request"),
+                        };
+                        let response = tiny_http::Response::from_data(response.body)
+                            .with_status_code(response.status);
+                        if let Err(e) = request.respond(response) {
+                            log::warn!("cannot_send_response: {}", e);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for w in workers {
+            w.join().map_err(|_| anyhow!("worker_failed"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns true if the name is a valid repository name or object key
+fn valid_name(name: &str, min_length: usize) -> bool {
+    name.len() >= min_length
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::capability::Capability;
+    use crate::melda::Melda;
+    use crate::server::{RepositoryServer, Response};
+    use serde_json::{json, Value};
+    use std::time::Duration;
+
+    #[test]
+    fn test_server_access() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let server = RepositoryServer::new(&dir, b"secret").unwrap();
+        let rw = server.issue("alice", &Capability::read_write()).unwrap();
+        let ro = server.issue("alice", &Capability::read_only()).unwrap();
+        assert_eq!(
+            server
+                .handle("GET", "/alice/objects", Some(&rw), b"")
+                .status,
+            404
+        );
+        let replica = Melda::new_from_url("memory://").unwrap();
+        replica
+            .update(json!({ "key" : "value" }).as_object().unwrap().clone())
+            .unwrap();
+        let block = replica.commit(None).unwrap().unwrap();
+        let block = block.first().unwrap();
+        let adapter = replica.get_adapter();
+        let adapter = adapter.read().unwrap();
+        for key in adapter.list_objects("").unwrap() {
+            let data = adapter.read_object(&key, 0, 0).unwrap();
+            let url = format!("/alice/objects/{}", key);
+            assert_eq!(server.handle("PUT", &url, Some(&ro), &data).status, 403);
+            assert_eq!(server.handle("PUT", &url, Some(&rw), &data).status, 201);
+            assert_eq!(server.handle("PUT", &url, Some(&rw), &data).status, 409);
+            assert_eq!(server.handle("GET", &url, Some(&ro), b"").body, data);
+        }
+        // Uploads must match their digest
+        let forged = format!("/alice/objects/{}.delta", "0".repeat(64));
+        assert_eq!(server.handle("PUT", &forged, Some(&rw), b"{}").status, 400);
+        // Repositories are compacted once idle
+        server.compact_idle(Duration::from_secs(60));
+        assert!(server.written.lock().unwrap().contains_key("alice"));
+        server.compact_idle(Duration::ZERO);
+        assert!(server.written.lock().unwrap().is_empty());
+        let response = server.handle("GET", "/alice/objects?ext=.delta", Some(&ro), b"");
+        assert_eq!(response.status, 200);
+        let keys: Vec<String> = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(keys, vec![block.clone()]);
+        assert_eq!(
+            server
+                .handle("GET", "/alice/objects/missing", Some(&ro), b"")
+                .status,
+            404
+        );
+        assert_eq!(
+            server.handle("GET", "/alice/objects", None, b"").status,
+            401
+        );
+        assert_eq!(
+            server
+                .handle("GET", "/alice/objects/..%2Fx", Some(&ro), b"")
+                .status,
+            404
+        );
+        assert_eq!(
+            server
+                .handle("DELETE", "/alice/objects/ab", Some(&rw), b"")
+                .status,
+            405
+        );
+    }
+
+    #[test]
+    fn test_presence() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let server = RepositoryServer::new(&dir, b"secret")
+            .unwrap()
+            .with_presence_ttl(Duration::from_millis(200));
+        let ro = server
+            .issue("alice", &Capability::read_only().with_peer("peer1"))
+            .unwrap();
+        let peer1 = server
+            .issue("alice", &Capability::read_write().with_peer("peer1"))
+            .unwrap();
+        let peer2 = server
+            .issue("alice", &Capability::read_write().with_peer("peer2"))
+            .unwrap();
+        let anonymous = server.issue("alice", &Capability::read_write()).unwrap();
+        assert!(server
+            .issue("alice", &Capability::read_write().with_peer("../x"))
+            .is_err());
+        let presence = |response: Response| -> Value {
+            assert_eq!(response.status, 200);
+            serde_json::from_slice(&response.body).unwrap()
+        };
+        let selection = json!({ "selection" : "/items♭/0" }).to_string();
+        let response = server.handle("PUT", "/alice/presence", Some(&peer1), selection.as_bytes());
+        assert_eq!(presence(response)["peer1"]["selection"], "/items♭/0");
+        server.handle("PUT", "/alice/presence", Some(&peer2), b"\"online\"");
+        let peers = presence(server.handle("GET", "/alice/presence", Some(&ro), b""));
+        assert_eq!(
+            peers,
+            json!({ "peer1" : { "selection" : "/items♭/0" }, "peer2" : "online" })
+        );
+        // Presence never touches the repository
+        assert!(!dir.join("alice").exists());
+        // Publishing requires write access and a token bound to a peer
+        for (method, token) in [("PUT", &ro), ("PUT", &anonymous), ("DELETE", &ro)] {
+            let response = server.handle(method, "/alice/presence", Some(token), b"\"away\"");
+            assert_eq!(response.status, 403);
+        }
+        assert_eq!(
+            server
+                .handle("PUT", "/alice/presence/peer2", Some(&peer1), b"\"away\"")
+                .status,
+            404
+        );
+        assert_eq!(
+            server
+                .handle("PUT", "/alice/presence", Some(&peer1), b"{")
+                .status,
+            400
+        );
+        assert_eq!(
+            server.handle("GET", "/alice/presence", None, b"").status,
+            401
+        );
+        assert_eq!(
+            server
+                .handle("DELETE", "/alice/presence", Some(&peer2), b"")
+                .status,
+            204
+        );
+        let peers = presence(server.handle("GET", "/alice/presence", Some(&ro), b""));
+        assert_eq!(peers, json!({ "peer1" : { "selection" : "/items♭/0" } }));
+        // Presence which is not published again expires
+        std::thread::sleep(Duration::from_millis(300));
+        let peers = presence(server.handle("GET", "/alice/presence", Some(&ro), b""));
+        assert!(peers.as_object().unwrap().is_empty());
+    }
+}
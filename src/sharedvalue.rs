@@ -0,0 +1,193 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::utils::parse_json_pointer;
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Materialized JSON value whose arrays and objects are reference counted. The values returned
+/// by [crate::melda::Melda::read_shared] share the subtrees which did not change since the
+/// previous read, hence cloning a value (or reading again an unchanged document) does not copy
+/// its content.
+///
+/// # Example
+/// ```
+/// use melda::sharedvalue::SharedValue;
+/// use serde_json::json;
+/// let value = SharedValue::from(json!({ "items" : [ 1, { "key" : "alpha" } ] }));
+/// assert_eq!(value.pointer("/items/1/key").unwrap().to_value(), json!("alpha"));
+/// let copy = value.clone();
+/// assert!(copy.is_shared_with(&value));
+/// assert!(!SharedValue::from(value.to_value()).is_shared_with(&value));
+/// assert_eq!(serde_json::to_string(&copy).unwrap(), "{\"items\":[1,{\"key\":\"alpha\"}]}");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum SharedValue {
+    /// Null, boolean, number or string
+    Scalar(Value),
+    /// Array
+    Array(Arc<Vec<SharedValue>>),
+    /// Object
+    Object(Arc<BTreeMap<String, SharedValue>>),
+}
+
+impl SharedValue {
+    /// Returns the value of a field (if the value is an object)
+    pub fn get(&self, key: &str) -> Option<&SharedValue> {
+        match self {
+            SharedValue::Object(o) => o.get(key),
+            _ => None,
+        }
+    }
+
+    /// Returns an element (if the value is an array)
+    pub fn get_index(&self, index: usize) -> Option<&SharedValue> {
+        match self {
+            SharedValue::Array(a) => a.get(index),
+            _ => None,
+        }
+    }
+
+    /// Returns the value identified by a JSON Pointer (RFC 6901)
+    pub fn pointer(&self, ptr: &str) -> Option<&SharedValue> {
+        let tokens = parse_json_pointer(ptr).ok()?;
+        tokens.iter().try_fold(self, |value, token| match value {
+            SharedValue::Object(o) => o.get(token),
+            SharedValue::Array(a) => token.parse::<usize>().ok().and_then(|i| a.get(i)),
+            SharedValue::Scalar(_) => None,
+        })
+    }
+
+    /// Returns the object (if the value is an object)
+    pub fn as_object(&self) -> Option<&BTreeMap<String, SharedValue>> {
+        match self {
+            SharedValue::Object(o) => Some(o),
+            _ => None,
+        }
+    }
+
+    /// Returns the elements (if the value is an array)
+    pub fn as_array(&self) -> Option<&Vec<SharedValue>> {
+        match self {
+            SharedValue::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    /// Returns true if both values are the same array or object (not merely equal), or are
+    /// equal scalars
+    pub fn is_shared_with(&self, other: &SharedValue) -> bool {
+        match (self, other) {
+            (SharedValue::Scalar(a), SharedValue::Scalar(b)) => a == b,
+            (SharedValue::Array(a), SharedValue::Array(b)) => Arc::ptr_eq(a, b),
+            (SharedValue::Object(a), SharedValue::Object(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    /// Returns a (deep) copy of the value as a JSON value
+    pub fn to_value(&self) -> Value {
+        match self {
+            SharedValue::Scalar(v) => v.clone(),
+            SharedValue::Array(a) => {
+                Value::from(a.iter().map(|v| v.to_value()).collect::<Vec<_>>())
+            }
+            SharedValue::Object(o) => Value::from(
+                o.iter()
+                    .map(|(k, v)| (k.clone(), v.to_value()))
+                    .collect::<Map<String, Value>>(),
+            ),
+        }
+    }
+}
+
+impl From<Value> for SharedValue {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Array(a) => {
+                SharedValue::Array(Arc::new(a.into_iter().map(SharedValue::from).collect()))
+            }
+            Value::Object(o) => SharedValue::Object(Arc::new(
+                o.into_iter()
+                    .map(|(k, v)| (k, SharedValue::from(v)))
+                    .collect(),
+            )),
+            v => SharedValue::Scalar(v),
+        }
+    }
+}
+
+impl From<&SharedValue> for Value {
+    fn from(value: &SharedValue) -> Self {
+        value.to_value()
+    }
+}
+
+impl Serialize for SharedValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            SharedValue::Scalar(v) => v.serialize(serializer),
+            SharedValue::Array(a) => {
+                let mut seq = serializer.serialize_seq(Some(a.len()))?;
+                for v in a.iter() {
+                    seq.serialize_element(v)?;
+                }
+                seq.end()
+            }
+            SharedValue::Object(o) => {
+                let mut map = serializer.serialize_map(Some(o.len()))?;
+                for (k, v) in o.iter() {
+                    map.serialize_entry(k, v)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::melda::Melda;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_read_shared() {
+        let replica = Melda::new_from_url("memory://").unwrap();
+        let object = json!({
+            "title" : "!Todo",
+            "nested" : { "list" : [1, 2] },
+            "items♭" : [ { "_id" : "1", "tags♭" : [ { "_id" : "t" }, { "_id" : "u" } ] }, { "_id" : "2" } ],
+            "owner♭" : { "_id" : "o", "name" : "alice" }
+        });
+        replica.update(object.as_object().unwrap().clone()).unwrap();
+        let first = replica.read_shared(None).unwrap();
+        assert_eq!(first.to_value(), Value::from(replica.read(None).unwrap()));
+        replica.set("/items♭/1", json!({ "_id" : "3" })).unwrap();
+        replica.commit(None).unwrap();
+        let second = replica.read_shared(None).unwrap();
+        assert_eq!(second.to_value(), Value::from(replica.read(None).unwrap()));
+        let shared = |p: &str| {
+            first
+                .pointer(p)
+                .unwrap()
+                .is_shared_with(second.pointer(p).unwrap())
+        };
+        assert!(shared("/items♭/0") && shared("/items♭/0/tags♭") && shared("/owner♭"));
+        assert!(!shared("/items♭"));
+        assert!(replica.read_shared(Some("missing")).is_err());
+    }
+}
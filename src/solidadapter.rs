@@ -14,6 +14,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 use crate::adapter::Adapter;
+use crate::error::MeldaError;
 use anyhow::{bail, Result};
 use cacache;
 use lru::LruCache;
@@ -124,14 +125,28 @@ impl SolidAdapter {
                         let (_, url) = self.get_object_url(key)?;
                         let mut headers = HeaderMap::new();
                         headers.insert("Content-Type", "application/octet-stream".parse().unwrap());
-                        let response = self.client.get(url).headers(headers).send()?;
-                        if response.status().as_u16() == 200 {
-                            let data = response.bytes()?;
-                            cache.put(key.to_string(), data.to_vec());
-                            cacache::write_sync(&self.disk_cache_dir, key, &data)?;
-                            Ok(data.to_vec())
-                        } else {
-                            bail!("cannot_read_object")
+                        let io_error = |e: reqwest::Error| MeldaError::AdapterIo {
+                            message: e.to_string(),
+                        };
+                        let response = self
+                            .client
+                            .get(url)
+                            .headers(headers)
+                            .send()
+                            .map_err(io_error)?;
+                        match response.status().as_u16() {
+                            200 => {
+                                let data = response.bytes().map_err(io_error)?;
+                                cache.put(key.to_string(), data.to_vec());
+                                cacache::write_sync(&self.disk_cache_dir, key, &data)?;
+                                Ok(data.to_vec())
+                            }
+                            404 => bail!(MeldaError::NotFound {
+                                key: key.to_string()
+                            }),
+                            status => bail!(MeldaError::AdapterIo {
+                                message: format!("cannot_read_object: {}", status)
+                            }),
                         }
                     }
                 }
@@ -322,6 +337,31 @@ impl Adapter for SolidAdapter {
         Ok(())
     }
 
+    /// Deletes an object from the storage (deleting a missing object is not an error)
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn delete_object(&self, key: &str) -> Result<()> {
+        let cache = self.cache.lock().unwrap();
+        cache.borrow_mut().pop(&key.to_string());
+        let _ = cacache::remove_sync(&self.disk_cache_dir, key);
+        let (_, url) = self.get_object_url(key)?;
+        let response = self
+            .client
+            .delete(url)
+            .send()
+            .map_err(|e| MeldaError::AdapterIo {
+                message: e.to_string(),
+            })?;
+        match response.status().as_u16() {
+            200..=204 | 404 => Ok(()),
+            status => bail!(MeldaError::AdapterIo {
+                message: format!("cannot_delete_object: {}", status)
+            }),
+        }
+    }
+
     /// Lists the keys of all objects whose key ends with ext. If ext is an empty string, all objects are returned.
     ///
     /// # Arguments
@@ -362,7 +402,7 @@ mod tests {
     #[test]
     #[serial]
     fn test_solid_read_object_flate() {
-        if !std::env::var("MELDA_SOLID_URL").is_ok() {
+        if std::env::var("MELDA_SOLID_URL").is_err() {
             return;
         };
         check_env();
@@ -394,7 +434,7 @@ mod tests {
     #[test]
     #[serial]
     fn test_solid_write_object_flate() {
-        if !std::env::var("MELDA_SOLID_URL").is_ok() {
+        if std::env::var("MELDA_SOLID_URL").is_err() {
             return;
         };
         check_env();
@@ -446,7 +486,7 @@ mod tests {
     #[test]
     #[serial]
     fn test_solid_list_objects_flate() {
-        if !std::env::var("MELDA_SOLID_URL").is_ok() {
+        if std::env::var("MELDA_SOLID_URL").is_err() {
             return;
         };
         check_env();
@@ -482,7 +522,7 @@ mod tests {
     #[test]
     #[serial]
     fn test_solid_read_object() {
-        if !std::env::var("MELDA_SOLID_URL").is_ok() {
+        if std::env::var("MELDA_SOLID_URL").is_err() {
             return;
         };
         check_env();
@@ -513,7 +553,7 @@ mod tests {
     #[test]
     #[serial]
     fn test_solid_write_object() {
-        if !std::env::var("MELDA_SOLID_URL").is_ok() {
+        if std::env::var("MELDA_SOLID_URL").is_err() {
             return;
         };
         check_env();
@@ -563,7 +603,7 @@ mod tests {
     #[test]
     #[serial]
     fn test_solid_list_objects() {
-        if !std::env::var("MELDA_SOLID_URL").is_ok() {
+        if std::env::var("MELDA_SOLID_URL").is_err() {
             return;
         };
         check_env();
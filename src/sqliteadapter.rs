@@ -14,6 +14,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 use crate::adapter::Adapter;
+use crate::error::MeldaError;
 use anyhow::Result;
 use base64::{engine::general_purpose, Engine as _};
 use std::{cell::RefCell, sync::Mutex};
@@ -95,7 +96,14 @@ impl Adapter for SqliteAdapter {
         });
         match result {
             Ok(r) => Ok(r),
-            Err(_) => Err(anyhow::anyhow!("cannot_read_object")),
+            Err(rusqlite::Error::QueryReturnedNoRows) => {
+                Err(anyhow::anyhow!(MeldaError::NotFound {
+                    key: key.to_string()
+                }))
+            }
+            Err(e) => Err(anyhow::anyhow!(MeldaError::AdapterIo {
+                message: e.to_string()
+            })),
         }
     }
 
@@ -118,6 +126,41 @@ impl Adapter for SqliteAdapter {
         }
     }
 
+    /// Writes an object to the storage, replacing the existing object (if any)
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    /// * `data` - The content of the object
+    fn replace_object(&self, key: &str, data: &[u8]) -> Result<()> {
+        let mcn = self.cn.lock().unwrap();
+        let cn = mcn.borrow_mut();
+        let value = general_purpose::STANDARD.encode(data);
+        match cn.execute(
+            "INSERT OR REPLACE INTO entries (key, value) VALUES (?1,?2)",
+            [&key, &value.as_str()],
+        ) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(anyhow::anyhow!("cannot_write_object")),
+        }
+    }
+
+    /// Deletes an object from the storage (deleting a missing object is not an error)
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key associated with the object
+    fn delete_object(&self, key: &str) -> Result<()> {
+        let mcn = self.cn.lock().unwrap();
+        let cn = mcn.borrow_mut();
+        match cn.execute("DELETE FROM entries WHERE key = ?1", [&key]) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(anyhow::anyhow!(MeldaError::AdapterIo {
+                message: e.to_string()
+            })),
+        }
+    }
+
     /// Lists the keys of all objects whose key ends with ext. If ext is an empty string, all objects are returned.
     ///
     /// # Arguments
@@ -140,6 +183,53 @@ impl Adapter for SqliteAdapter {
             })
             .collect())
     }
+
+    /// Lists, in lexicographic order, at most limit keys of the objects whose key ends with ext
+    /// and follows the given key, reading only the requested rows
+    ///
+    /// # Arguments
+    ///
+    /// * `ext` - The extension (last part of the string) of the requested objects
+    /// * `after` - The key (without the extension) after which the listing starts
+    /// * `limit` - The maximum number of keys returned
+    fn list_objects_page(
+        &self,
+        ext: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        let mcn = self.cn.lock().unwrap();
+        let cn = mcn.borrow();
+        let mut stmt = cn.prepare(
+            "SELECT key FROM entries WHERE key > ?1 AND key GLOB ?2 ORDER BY key LIMIT ?3",
+        )?;
+        let pattern = "*".to_string() + &glob_escape(ext);
+        // The key equal to the starting key (followed by the extension) is skipped below
+        let rows = limit.saturating_add(1).min(i64::MAX as usize) as i64;
+        let keys = stmt.query_map(
+            rusqlite::params![after.unwrap_or_default(), pattern, rows],
+            |row| row.get::<_, String>(0),
+        )?;
+        let mut result = vec![];
+        for key in keys {
+            let key = key?;
+            let key = key.strip_suffix(ext).unwrap_or(&key);
+            if result.len() < limit && after.is_none_or(|after| key > after) {
+                result.push(key.to_string());
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// Escapes the special characters of a GLOB pattern
+fn glob_escape(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '*' | '?' | '[' => format!("[{}]", c),
+            c => c.to_string(),
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -172,6 +262,33 @@ mod tests {
         assert!(ro == "om");
     }
 
+    #[test]
+    fn test_sqlite_list_objects_page() {
+        let sa = SqliteAdapter::new_in_memory();
+        for key in &[
+            "d.delta", "a.delta", "b.pack", "c.delta", "e*.delta", "f.DELTA",
+        ] {
+            sa.write_object(key, b"data").unwrap();
+        }
+        assert_eq!(
+            sa.list_objects_page(".delta", None, 2).unwrap(),
+            vec!["a", "c"]
+        );
+        assert_eq!(
+            sa.list_objects_page(".delta", Some("c"), 5).unwrap(),
+            vec!["d", "e*"]
+        );
+        assert_eq!(
+            sa.list_objects_page(".pack", Some("a"), 2).unwrap(),
+            vec!["b"]
+        );
+        assert!(sa
+            .list_objects_page(".delta", Some("e*"), 2)
+            .unwrap()
+            .is_empty());
+        assert_eq!(sa.list_objects_page("*.delta", None, 5).unwrap(), vec!["e"]);
+    }
+
     #[test]
     fn test_solid_write_object_flate() {
         let sa = SqliteAdapter::new_in_memory();
@@ -0,0 +1,390 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//! Materialization of the state of a replica into the tables of a SQLite database, so that
+//! documents can be queried with plain SQL (for example by BI tools). Each collection of the
+//! root object becomes a table (named after the collection, without the flattening suffix)
+//! with a row per element: the `_id` column holds the identifier of the element, the
+//! `_position` column its position in the collection, and each field of the elements is stored
+//! in a column of its own (objects and arrays as JSON text). The other fields of the root
+//! object are stored in the `melda_fields` key/value table.
+use crate::constants::{FLATTEN_SUFFIX, ID_FIELD, ROOT_ID};
+use crate::error::MeldaError;
+use crate::melda::{Cursor, Melda};
+use crate::utils::{encode_json_pointer, is_flattened_field, parse_json_pointer};
+use anyhow::{bail, Result};
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params, params_from_iter, OptionalExtension, Transaction};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+/// Table holding the fields of the root object which are not collections
+const FIELDS_TABLE: &str = "melda_fields";
+/// Table holding the collections exported in the tables
+const TABLES_TABLE: &str = "melda_tables";
+/// Table holding the state of the export
+const META_TABLE: &str = "melda_meta";
+/// Key of the cursor of the change feed up to which the tables are refreshed
+const CURSOR_KEY: &str = "cursor";
+/// Column holding the position of the elements in the collection
+const POSITION_COLUMN: &str = "_position";
+
+/// Writes the (winning) committed state of the replica into the tables of a SQLite database,
+/// see the [module documentation](self). The database keeps the cursor of the change feed (see
+/// [Melda::changes]) up to which it has been refreshed: exporting again into the same database
+/// only rewrites the rows of the elements which changed in the meantime (all tables are
+/// rewritten if the cursor is no longer valid, for example after a squash). Fields no longer
+/// present in the elements are set to NULL. The stage must be empty. Returns the number of
+/// rows which have been written or deleted
+///
+/// # Arguments
+///
+/// * `melda` - The replica
+/// * `path` - The path of the SQLite database (created if it does not exist)
+///
+/// # Example
+/// ```
+/// use melda::{melda::Melda, sqliteexport::export_sqlite};
+/// use serde_json::json;
+/// let replica = Melda::new_from_url("memory://").unwrap();
+/// replica.update(json!({ "title" : "Todo", "tasks\u{266D}" : [
+///     { "_id" : "1", "title" : "Buy milk", "done" : false },
+///     { "_id" : "2", "title" : "Call Bob", "tags" : [ "phone" ] }
+/// ] }).as_object().unwrap().clone()).unwrap();
+/// replica.commit(None).unwrap();
+/// let file = mktemp::Temp::new_file().unwrap();
+/// assert_eq!(export_sqlite(&replica, &file).unwrap(), 3);
+/// replica.set("/tasks\u{266D}/0/done", json!(true)).unwrap();
+/// replica.commit(None).unwrap();
+/// // Only the changed element is written again
+/// assert_eq!(export_sqlite(&replica, &file).unwrap(), 1);
+/// let db = rusqlite::Connection::open(&*file).unwrap();
+/// let done: i64 = db.query_row("SELECT done FROM tasks WHERE _id = '1'", [], |r| r.get(0)).unwrap();
+/// assert_eq!(done, 1);
+/// let tags: String = db.query_row("SELECT tags FROM tasks WHERE _id = '2'", [], |r| r.get(0)).unwrap();
+/// assert_eq!(tags, "[\"phone\"]");
+/// let title: String = db.query_row("SELECT value FROM melda_fields WHERE key = 'title'", [], |r| r.get(0)).unwrap();
+/// assert_eq!(title, "Todo");
+/// ```
+pub fn export_sqlite(melda: &Melda, path: &Path) -> Result<usize> {
+    if melda.has_staging() {
+        bail!(MeldaError::StageNotEmpty);
+    }
+    let mut connection = rusqlite::Connection::open(path)?;
+    let tx = connection.transaction()?;
+    tx.execute_batch(&format!(
+        "CREATE TABLE IF NOT EXISTS {} (key TEXT NOT NULL PRIMARY KEY, value);
+         CREATE TABLE IF NOT EXISTS {} (collection TEXT NOT NULL PRIMARY KEY, name TEXT NOT NULL);
+         CREATE TABLE IF NOT EXISTS {} (key TEXT NOT NULL PRIMARY KEY, value TEXT NOT NULL);",
+        FIELDS_TABLE, TABLES_TABLE, META_TABLE
+    ))?;
+    let cursor: Option<String> = tx
+        .query_row(
+            &format!("SELECT value FROM {} WHERE key = ?1", META_TABLE),
+            params![CURSOR_KEY],
+            |r| r.get(0),
+        )
+        .optional()?;
+    // Elements changed since the last export (None if all tables must be rewritten)
+    let mut dirty: Option<HashSet<String>> = None;
+    let mut root_changed = true;
+    let feed = match cursor {
+        Some(cursor) => match melda.changes(&cursor.parse()?) {
+            Ok(feed) => {
+                let mut changed = HashSet::new();
+                root_changed = feed.changes.iter().any(|c| c.uuid == ROOT_ID);
+                for change in &feed.changes {
+                    if let Some(element) = changed_element(melda, &change.uuid)? {
+                        changed.insert(element);
+                    }
+                }
+                dirty = Some(changed);
+                feed
+            }
+            Err(e) if matches!(e.downcast_ref(), Some(MeldaError::MissingBlock { .. })) => {
+                melda.changes(&Cursor::start())?
+            }
+            Err(e) => return Err(e),
+        },
+        None => melda.changes(&Cursor::start())?,
+    };
+    let mut written = 0;
+    let root = melda.load_flattened_object(ROOT_ID)?.unwrap_or_default();
+    // Fields (rewritten whenever the root object changes)
+    if root_changed {
+        written += tx.execute(&format!("DELETE FROM {}", FIELDS_TABLE), [])?;
+        for (field, value) in root
+            .iter()
+            .filter(|(k, _)| *k != ID_FIELD && !is_flattened_field(k))
+        {
+            let value = match value {
+                Value::Object(_) | Value::Array(_) => melda
+                    .get(&encode_json_pointer(std::slice::from_ref(field)))?
+                    .unwrap_or(Value::Null),
+                v => v.clone(),
+            };
+            written += tx.execute(
+                &format!("INSERT INTO {} (key, value) VALUES (?1, ?2)", FIELDS_TABLE),
+                params![field, sql_value(&value)],
+            )?;
+        }
+    }
+    // Collections
+    let mut exported = BTreeMap::<String, String>::new();
+    {
+        let mut statement =
+            tx.prepare(&format!("SELECT collection, name FROM {}", TABLES_TABLE))?;
+        let rows = statement.query_map([], |r| Ok((r.get(0)?, r.get(1)?)))?;
+        for row in rows {
+            let (collection, name) = row?;
+            exported.insert(collection, name);
+        }
+    }
+    let collections: Vec<&String> = root.keys().filter(|k| is_flattened_field(k)).collect();
+    for (collection, name) in &exported {
+        if dirty.is_none() || !root.contains_key(collection) {
+            written += tx.query_row(&format!("SELECT COUNT(*) FROM {}", quote(name)), [], |r| {
+                r.get::<_, i64>(0)
+            })? as usize;
+            tx.execute_batch(&format!("DROP TABLE {}", quote(name)))?;
+            tx.execute(
+                &format!("DELETE FROM {} WHERE collection = ?1", TABLES_TABLE),
+                params![collection],
+            )?;
+        }
+    }
+    for collection in collections {
+        let name = collection.trim_end_matches(FLATTEN_SUFFIX);
+        if [FIELDS_TABLE, TABLES_TABLE, META_TABLE].contains(&name) {
+            bail!("reserved_table_name: {}", name);
+        }
+        tx.execute(
+            &format!(
+                "INSERT OR IGNORE INTO {} (collection, name) VALUES (?1, ?2)",
+                TABLES_TABLE
+            ),
+            params![collection, name],
+        )?;
+        written += export_collection(melda, &tx, collection, name, dirty.as_ref())?;
+    }
+    tx.execute(
+        &format!(
+            "INSERT OR REPLACE INTO {} (key, value) VALUES (?1, ?2)",
+            META_TABLE
+        ),
+        params![CURSOR_KEY, feed.cursor.to_string()],
+    )?;
+    tx.commit()?;
+    Ok(written)
+}
+
+/// Writes the elements of a collection into its table, skipping the elements which did not
+/// change (unless all elements are dirty). Returns the number of rows written or deleted
+fn export_collection(
+    melda: &Melda,
+    tx: &Transaction,
+    collection: &str,
+    name: &str,
+    dirty: Option<&HashSet<String>>,
+) -> Result<usize> {
+    let table = quote(name);
+    tx.execute_batch(&format!(
+        "CREATE TABLE IF NOT EXISTS {} ({} TEXT NOT NULL PRIMARY KEY, {} INTEGER NOT NULL)",
+        table,
+        quote(ID_FIELD),
+        quote(POSITION_COLUMN)
+    ))?;
+    let mut columns = HashSet::<String>::new();
+    {
+        let mut statement = tx.prepare(&format!("PRAGMA table_info({})", table))?;
+        let rows = statement.query_map([], |r| r.get::<_, String>(1))?;
+        for row in rows {
+            columns.insert(row?);
+        }
+    }
+    let mut existing = HashMap::<String, i64>::new();
+    {
+        let mut statement = tx.prepare(&format!(
+            "SELECT {}, {} FROM {}",
+            quote(ID_FIELD),
+            quote(POSITION_COLUMN),
+            table
+        ))?;
+        let rows = statement.query_map([], |r| Ok((r.get(0)?, r.get(1)?)))?;
+        for row in rows {
+            let (id, position) = row?;
+            existing.insert(id, position);
+        }
+    }
+    let (_, order) = melda.collection_order(collection)?;
+    let mut written = 0;
+    let current: HashSet<&String> = order.iter().collect();
+    for id in existing.keys().filter(|id| !current.contains(id)) {
+        written += tx.execute(
+            &format!("DELETE FROM {} WHERE {} = ?1", table, quote(ID_FIELD)),
+            params![id],
+        )?;
+    }
+    for (position, id) in order.iter().enumerate() {
+        let position = position as i64;
+        let changed = dirty.is_none_or(|d| d.contains(id));
+        match existing.get(id) {
+            Some(p) if !changed && *p == position => continue,
+            Some(_) if !changed => {
+                written += tx.execute(
+                    &format!(
+                        "UPDATE {} SET {} = ?1 WHERE {} = ?2",
+                        table,
+                        quote(POSITION_COLUMN),
+                        quote(ID_FIELD)
+                    ),
+                    params![position, id],
+                )?;
+                continue;
+            }
+            _ => {}
+        }
+        let element = match melda.get_object(id)? {
+            Some(Value::Object(element)) => element,
+            _ => continue,
+        };
+        let mut names = vec![quote(ID_FIELD), quote(POSITION_COLUMN)];
+        let mut values = vec![SqlValue::Text(id.clone()), SqlValue::Integer(position)];
+        for (field, value) in element.iter().filter(|(k, _)| *k != ID_FIELD) {
+            if field == POSITION_COLUMN {
+                bail!("reserved_column_name: {}", field);
+            }
+            if columns.insert(field.clone()) {
+                tx.execute_batch(&format!(
+                    "ALTER TABLE {} ADD COLUMN {}",
+                    table,
+                    quote(field)
+                ))?;
+            }
+            names.push(quote(field));
+            values.push(sql_value(value));
+        }
+        let placeholders: Vec<String> = (1..=values.len()).map(|i| format!("?{}", i)).collect();
+        written += tx.execute(
+            &format!(
+                "INSERT OR REPLACE INTO {} ({}) VALUES ({})",
+                table,
+                names.join(", "),
+                placeholders.join(", ")
+            ),
+            params_from_iter(values),
+        )?;
+    }
+    Ok(written)
+}
+
+/// Returns the element of a collection of the root object which contains the given object
+/// (or array), None if the object is not reachable or does not belong to an element
+fn changed_element(melda: &Melda, uuid: &str) -> Result<Option<String>> {
+    let location = match melda.get_location(uuid)? {
+        Some(location) if location.root == ROOT_ID => location,
+        _ => return Ok(None),
+    };
+    let tokens = parse_json_pointer(&location.pointer)?;
+    match tokens.as_slice() {
+        [collection, index, ..] if is_flattened_field(collection) => {
+            let (_, order) = melda.collection_order(collection)?;
+            Ok(index
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| order.get(i).cloned()))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Converts a JSON value to a SQLite value (objects and arrays become JSON text)
+fn sql_value(value: &Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::Bool(b) => SqlValue::Integer(*b as i64),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => SqlValue::Integer(i),
+            None => SqlValue::Real(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => SqlValue::Text(s.clone()),
+        v => SqlValue::Text(v.to_string()),
+    }
+}
+
+/// Quotes an SQL identifier
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_incremental_export() {
+        let replica = Melda::new_from_url("memory://").unwrap();
+        replica
+            .update(
+                json!({ "items♭" : [ { "_id" : "a", "n" : 1 }, { "_id" : "b", "n" : 2 } ], "other♭" : [ { "_id" : "c" } ] })
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+            )
+            .unwrap();
+        replica.commit(None).unwrap();
+        let file = mktemp::Temp::new_file().unwrap();
+        assert_eq!(export_sqlite(&replica, &file).unwrap(), 3);
+        assert_eq!(export_sqlite(&replica, &file).unwrap(), 0);
+        // Removing an element deletes its row and shifts the following ones, removing a
+        // collection drops its table
+        replica
+            .update(
+                json!({ "items♭" : [ { "_id" : "b", "n" : 2 }, { "_id" : "d", "m" : "x" } ] })
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+            )
+            .unwrap();
+        replica.commit(None).unwrap();
+        assert_eq!(export_sqlite(&replica, &file).unwrap(), 4);
+        let db = rusqlite::Connection::open(&*file).unwrap();
+        let mut statement = db
+            .prepare("SELECT _id, _position, n, m FROM items ORDER BY _position")
+            .unwrap();
+        let rows: Vec<(String, i64, Option<i64>, Option<String>)> = statement
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("b".to_string(), 0, Some(2), None),
+                ("d".to_string(), 1, None, Some("x".to_string()))
+            ]
+        );
+        let tables: i64 = db
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE name = 'other'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(tables, 0);
+    }
+}
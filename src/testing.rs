@@ -0,0 +1,388 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::bench::splitmix64;
+use crate::constants::{ID_FIELD, ROOT_ID};
+use crate::melda::Melda;
+use anyhow::{bail, Result};
+use serde_json::{json, Map, Value};
+
+/// Field of the root object holding the collection the operations act upon
+pub const COLLECTION: &str = "items\u{266D}";
+
+/// An operation performed by a replica. Operations act upon a collection of elements (objects
+/// with an identifier and a value) and upon the scalar fields of the root object
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    /// Appends an element to the collection (or replaces the value of an existing element)
+    Insert { id: String, value: Value },
+    /// Replaces the value of an element (nothing happens if the element does not exist)
+    Update { id: String, value: Value },
+    /// Removes an element from the collection
+    Delete { id: String },
+    /// Sets a field of the root object
+    Set { field: String, value: Value },
+    /// Commits the staged changes
+    Commit,
+    /// Commits the staged changes, melds another replica and refreshes
+    Meld { from: usize },
+}
+
+/// An operation performed by one of the replicas
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    /// Index of the replica
+    pub replica: usize,
+    /// The operation
+    pub operation: Operation,
+}
+
+/// Reference sequential model: the document a single replica is expected to hold after a
+/// sequence of operations
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Model {
+    /// The expected document (without the identifier of the root object)
+    pub document: Map<String, Value>,
+}
+
+impl Model {
+    /// Applies an operation to the model (commits and melds do not change the document)
+    pub fn apply(&mut self, operation: &Operation) {
+        apply_operation(&mut self.document, operation);
+    }
+}
+
+/// Generates random operations (the sequence only depends on the seed). Identifiers and fields
+/// are drawn from small pools, so that replicas frequently change the same elements
+#[derive(Debug, Clone)]
+pub struct Generator {
+    seed: u64,
+    counter: u64,
+    replicas: usize,
+    /// Number of distinct element identifiers
+    pub identifiers: usize,
+    /// Number of distinct fields of the root object
+    pub fields: usize,
+}
+
+impl Generator {
+    /// Creates a generator of operations for the given number of replicas
+    pub fn new(seed: u64, replicas: usize) -> Self {
+        Generator {
+            seed,
+            counter: 0,
+            replicas: replicas.max(1),
+            identifiers: 8,
+            fields: 3,
+        }
+    }
+
+    fn draw(&mut self, bound: usize) -> usize {
+        self.counter += 1;
+        (splitmix64(self.seed ^ self.counter.wrapping_mul(0x9E37_79B9)) % bound as u64) as usize
+    }
+}
+
+impl Iterator for Generator {
+    type Item = Step;
+
+    fn next(&mut self) -> Option<Step> {
+        let replica = self.draw(self.replicas);
+        let kind = self.draw(20) as u8;
+        let id = self.draw(self.identifiers);
+        let value = self.draw(100);
+        Some(Step {
+            replica,
+            operation: operation(kind, id, value, self.fields, replica, self.replicas),
+        })
+    }
+}
+
+/// Decodes a sequence of steps from arbitrary bytes (three bytes per step), for example the
+/// input of a fuzzer
+pub fn steps_from_bytes(data: &[u8], replicas: usize) -> Vec<Step> {
+    let replicas = replicas.max(1);
+    data.chunks_exact(3)
+        .map(|chunk| {
+            let replica = (chunk[0] >> 5) as usize % replicas;
+            let operation = operation(
+                chunk[0] % 20,
+                chunk[1] as usize % 8,
+                chunk[2] as usize,
+                3,
+                replica,
+                replicas,
+            );
+            Step { replica, operation }
+        })
+        .collect()
+}
+
+/// Performs an operation on a replica (the changes are staged with [Melda::update], as an
+/// application would do after modifying the document). Melds refer to the other replicas
+pub fn perform(replicas: &mut [Melda], step: &Step) -> Result<()> {
+    if step.replica >= replicas.len() {
+        bail!("unknown_replica: {}", step.replica);
+    }
+    match &step.operation {
+        Operation::Commit => {
+            replicas[step.replica].commit(None)?;
+        }
+        Operation::Meld { from } => {
+            if *from >= replicas.len() {
+                bail!("unknown_replica: {}", from);
+            }
+            let replica = &replicas[step.replica];
+            replica.commit(None)?;
+            replica.meld(&replicas[*from])?;
+            replicas[step.replica].refresh()?;
+        }
+        operation => {
+            let replica = &replicas[step.replica];
+            let mut document = read_state(replica)?;
+            apply_operation(&mut document, operation);
+            replica.update(document)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the state of a replica (without the identifier of the root object), an empty object
+/// if nothing has been stored yet
+pub fn read_state(replica: &Melda) -> Result<Map<String, Value>> {
+    if !replica.get_all_objects().contains(ROOT_ID) {
+        return Ok(Map::new());
+    }
+    let mut state = replica.read(None)?;
+    state.remove(ID_FIELD);
+    Ok(state)
+}
+
+/// Performs the steps on a single replica (the replica index of each step and melds are
+/// ignored), checking after each step that the replica holds the document of the reference
+/// model
+pub fn check_sequential(steps: &[Step], replica: Melda) -> Result<()> {
+    let mut replicas = [replica];
+    let mut model = Model::default();
+    for (n, step) in steps.iter().enumerate() {
+        if let Operation::Meld { .. } = step.operation {
+            continue;
+        }
+        let step = Step {
+            replica: 0,
+            operation: step.operation.clone(),
+        };
+        perform(&mut replicas, &step)?;
+        model.apply(&step.operation);
+        if read_state(&replicas[0])? != model.document {
+            bail!("model_mismatch: step {}", n);
+        }
+    }
+    Ok(())
+}
+
+/// Melds all replicas with each other (staged changes are committed first) and checks that
+/// they converge to the same state
+pub fn assert_convergence(replicas: &mut [Melda]) -> Result<()> {
+    if replicas.is_empty() {
+        return Ok(());
+    }
+    for replica in replicas.iter() {
+        replica.commit(None)?;
+    }
+    let (first, others) = replicas.split_first_mut().unwrap();
+    for other in others.iter() {
+        first.meld(other)?;
+    }
+    first.refresh()?;
+    for other in others.iter_mut() {
+        other.meld(first)?;
+        other.refresh()?;
+    }
+    let state = read_state(&replicas[0])?;
+    for (i, replica) in replicas.iter().enumerate().skip(1) {
+        if read_state(replica)? != state {
+            bail!("replicas_diverged: {}", i);
+        }
+    }
+    Ok(())
+}
+
+/// Performs the steps on replicas returned by the given function (invoked with the index of
+/// each replica, for example to open replicas with specific builder options), then checks that
+/// the replicas converge, see [assert_convergence]
+///
+/// # Example
+/// ```
+/// use melda::{melda::Melda, testing::{check_convergence, Generator}};
+/// let steps: Vec<_> = Generator::new(7, 3).take(200).collect();
+/// check_convergence(&steps, 3, |_| Melda::builder().url("memory://").open()).unwrap();
+/// ```
+pub fn check_convergence<F: FnMut(usize) -> Result<Melda>>(
+    steps: &[Step],
+    replicas: usize,
+    open: F,
+) -> Result<()> {
+    let mut replicas = (0..replicas).map(open).collect::<Result<Vec<Melda>>>()?;
+    for step in steps {
+        perform(&mut replicas, step)?;
+    }
+    assert_convergence(&mut replicas)
+}
+
+/// Fuzzing entry point: decodes steps from arbitrary bytes (see [steps_from_bytes]), checks them
+/// against the reference model on a single in-memory replica and checks that three in-memory
+/// replicas performing them converge. Returns an error if an invariant is violated
+pub fn fuzz(data: &[u8]) -> Result<()> {
+    let steps = steps_from_bytes(data, 3);
+    check_sequential(&steps, Melda::new_from_url("memory://")?)?;
+    check_convergence(&steps, 3, |_| Melda::new_from_url("memory://"))
+}
+
+// Builds an operation from raw draws
+fn operation(
+    kind: u8,
+    id: usize,
+    value: usize,
+    fields: usize,
+    replica: usize,
+    replicas: usize,
+) -> Operation {
+    let id = format!("e{}", id);
+    let value = if value.is_multiple_of(2) {
+        Value::from(value)
+    } else {
+        Value::from(format!("v{}", value))
+    };
+    match kind {
+        0..=5 => Operation::Insert { id, value },
+        6..=10 => Operation::Update { id, value },
+        11..=13 => Operation::Delete { id },
+        14..=15 => Operation::Set {
+            field: format!("f{}", value_index(&value) % fields.max(1)),
+            value,
+        },
+        16..=18 => Operation::Commit,
+        _ if replicas > 1 => Operation::Meld {
+            from: (replica + 1 + value_index(&value) % (replicas - 1)) % replicas,
+        },
+        _ => Operation::Commit,
+    }
+}
+
+fn value_index(value: &Value) -> usize {
+    match value {
+        Value::Number(n) => n.as_u64().unwrap_or(0) as usize,
+        Value::String(s) => s[1..].parse().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+// Applies an operation to a document
+fn apply_operation(document: &mut Map<String, Value>, operation: &Operation) {
+    let collection = || Value::from(Vec::<Value>::new());
+    match operation {
+        Operation::Insert { id, value } => {
+            let elements = document.entry(COLLECTION).or_insert_with(collection);
+            let elements = elements.as_array_mut().unwrap();
+            match elements.iter_mut().find(|e| e[ID_FIELD] == *id.as_str()) {
+                Some(element) => element["value"] = value.clone(),
+                None => elements.push(json!({ ID_FIELD: id, "value": value })),
+            }
+        }
+        Operation::Update { id, value } => {
+            if let Some(elements) = document.get_mut(COLLECTION).and_then(Value::as_array_mut) {
+                if let Some(element) = elements.iter_mut().find(|e| e[ID_FIELD] == *id.as_str()) {
+                    element["value"] = value.clone();
+                }
+            }
+        }
+        Operation::Delete { id } => {
+            if let Some(elements) = document.get_mut(COLLECTION).and_then(Value::as_array_mut) {
+                elements.retain(|e| e[ID_FIELD] != *id.as_str());
+            }
+        }
+        Operation::Set { field, value } => {
+            document.insert(field.clone(), value.clone());
+        }
+        Operation::Commit | Operation::Meld { .. } => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::melda::Melda;
+    use crate::testing::{
+        assert_convergence, check_sequential, fuzz, perform, steps_from_bytes, Generator, Model,
+        Operation, Step,
+    };
+    use serde_json::json;
+
+    #[test]
+    fn test_model() {
+        let mut model = Model::default();
+        let insert = |id: &str, value| Operation::Insert {
+            id: id.to_string(),
+            value: json!(value),
+        };
+        model.apply(&insert("a", 1));
+        model.apply(&insert("b", 2));
+        model.apply(&insert("a", 3));
+        model.apply(&Operation::Delete {
+            id: "b".to_string(),
+        });
+        model.apply(&Operation::Update {
+            id: "c".to_string(),
+            value: json!(4),
+        });
+        assert_eq!(
+            json!(model.document),
+            json!({ "items\u{266D}": [ { "_id": "a", "value": 3 } ] })
+        );
+    }
+
+    #[test]
+    fn test_generated_steps() {
+        let steps: Vec<Step> = Generator::new(3, 2).take(100).collect();
+        assert_eq!(steps, Generator::new(3, 2).take(100).collect::<Vec<Step>>());
+        assert!(steps
+            .iter()
+            .any(|s| matches!(s.operation, Operation::Meld { .. })));
+        assert!(steps.iter().all(|s| s.replica < 2));
+        check_sequential(&steps, Melda::new_from_url("memory://").unwrap()).unwrap();
+        assert_eq!(steps_from_bytes(&[0, 1, 2, 3], 2).len(), 1);
+        fuzz(&[0, 1, 2, 70, 1, 3, 140, 1, 5, 16, 0, 0, 19, 0, 0]).unwrap();
+    }
+
+    #[test]
+    fn test_convergence() {
+        let mut replicas = vec![
+            Melda::new_from_url("memory://").unwrap(),
+            Melda::new_from_url("memory://").unwrap(),
+        ];
+        let update = |replica, value| Step {
+            replica,
+            operation: Operation::Insert {
+                id: "a".to_string(),
+                value: json!(value),
+            },
+        };
+        perform(&mut replicas, &update(0, 1)).unwrap();
+        perform(&mut replicas, &update(1, 2)).unwrap();
+        assert!(perform(&mut replicas, &update(2, 3)).is_err());
+        assert_convergence(&mut replicas).unwrap();
+        assert!(!replicas[0].in_conflict().is_empty());
+    }
+}
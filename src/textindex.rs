@@ -0,0 +1,199 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::constants::STRING_ESCAPE_PREFIX;
+use crate::utils::{encode_json_pointer, is_flattened_field, unescape};
+use serde_json::{Map, Value};
+use std::collections::{BTreeSet, HashMap};
+
+/// Inverted index over the string fields of the objects: each term maps to the fields (object
+/// identifier and JSON Pointer relative to the object) whose text contains it
+#[derive(Default)]
+pub(crate) struct TextIndex {
+    fields: HashMap<String, Vec<(String, BTreeSet<String>)>>, // Indexed fields of each object (and their terms)
+    postings: HashMap<String, BTreeSet<(String, String)>>,    // Fields containing each term
+}
+
+impl TextIndex {
+    /// Replaces the indexed fields (pointer and text) of an object
+    pub fn set(&mut self, uuid: &str, fields: Vec<(String, String)>) {
+        self.remove(uuid);
+        let fields: Vec<(String, BTreeSet<String>)> = fields
+            .into_iter()
+            .map(|(pointer, text)| (pointer, tokenize(&text)))
+            .filter(|(_, terms)| !terms.is_empty())
+            .collect();
+        for (pointer, terms) in &fields {
+            for term in terms {
+                self.postings
+                    .entry(term.clone())
+                    .or_default()
+                    .insert((uuid.to_string(), pointer.clone()));
+            }
+        }
+        if !fields.is_empty() {
+            self.fields.insert(uuid.to_string(), fields);
+        }
+    }
+
+    /// Removes the fields of an object from the index
+    pub fn remove(&mut self, uuid: &str) {
+        for (pointer, terms) in self.fields.remove(uuid).unwrap_or_default() {
+            let key = (uuid.to_string(), pointer);
+            for term in terms {
+                if let Some(posting) = self.postings.get_mut(&term) {
+                    posting.remove(&key);
+                    if posting.is_empty() {
+                        self.postings.remove(&term);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Removes all the objects from the index
+    pub fn clear(&mut self) {
+        self.fields.clear();
+        self.postings.clear();
+    }
+
+    /// Returns the fields (object identifier and pointer) containing all the terms of the query
+    pub fn search(&self, query: &str) -> Vec<(String, String)> {
+        let terms = tokenize(query);
+        let mut postings: Vec<&BTreeSet<(String, String)>> = vec![];
+        for term in &terms {
+            match self.postings.get(term) {
+                Some(posting) => postings.push(posting),
+                None => return vec![],
+            }
+        }
+        // Intersect starting from the shortest list
+        postings.sort_by_key(|posting| posting.len());
+        match postings.split_first() {
+            Some((first, others)) => first
+                .iter()
+                .filter(|field| others.iter().all(|posting| posting.contains(field)))
+                .cloned()
+                .collect(),
+            None => vec![],
+        }
+    }
+}
+
+/// Returns the strings (pointer relative to the object and text) of a flattened object: the
+/// objects referenced by its flattened fields are indexed separately
+pub(crate) fn text_fields(object: &Map<String, Value>) -> Vec<(String, String)> {
+    fn walk(
+        value: &Value,
+        flattened: bool,
+        path: &mut Vec<String>,
+        fields: &mut Vec<(String, String)>,
+    ) {
+        match value {
+            Value::String(s) if !flattened => fields.push((encode_json_pointer(path), s.clone())),
+            Value::String(s) if s.starts_with(STRING_ESCAPE_PREFIX) => {
+                fields.push((encode_json_pointer(path), unescape(s)))
+            }
+            Value::Array(a) => {
+                for (i, v) in a.iter().enumerate() {
+                    path.push(i.to_string());
+                    walk(v, flattened, path, fields);
+                    path.pop();
+                }
+            }
+            Value::Object(o) if !flattened => {
+                for (k, v) in o {
+                    path.push(k.clone());
+                    walk(v, flattened, path, fields);
+                    path.pop();
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut fields = vec![];
+    for (k, v) in object {
+        walk(v, is_flattened_field(k), &mut vec![k.clone()], &mut fields);
+    }
+    fields
+}
+
+/// Splits a text into (lowercase) terms made of alphanumeric characters
+pub(crate) fn tokenize(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::textindex::{text_fields, tokenize, TextIndex};
+    use serde_json::json;
+
+    #[test]
+    fn test_text_index() {
+        assert_eq!(
+            tokenize("A CRDT-based Database, for databases!"),
+            ["a", "based", "crdt", "database", "databases", "for"]
+                .iter()
+                .map(|t| t.to_string())
+                .collect()
+        );
+        let mut index = TextIndex::default();
+        index.set(
+            "a",
+            vec![
+                ("/title".to_string(), "Distributed database".to_string()),
+                ("/notes/0".to_string(), "Eventually consistent".to_string()),
+            ],
+        );
+        index.set(
+            "b",
+            vec![("/title".to_string(), "Database design".to_string())],
+        );
+        let field = |uuid: &str, pointer: &str| (uuid.to_string(), pointer.to_string());
+        assert_eq!(
+            index.search("DATABASE"),
+            [field("a", "/title"), field("b", "/title")]
+        );
+        assert_eq!(index.search("distributed database"), [field("a", "/title")]);
+        assert!(index.search("distributed consistent").is_empty());
+        assert!(index.search("").is_empty());
+        index.set(
+            "a",
+            vec![("/title".to_string(), "Key-value store".to_string())],
+        );
+        assert_eq!(index.search("database"), [field("b", "/title")]);
+        index.remove("b");
+        assert!(index.search("database").is_empty());
+        assert_eq!(index.postings.len(), 3);
+        let object = json!({ "title" : "Notes", "tags" : [ "a", { "b" : "c" } ], "items\u{266D}" : "^1", "x\u{266D}" : [ "!y", "z" ] });
+        let mut fields = text_fields(object.as_object().unwrap());
+        fields.sort();
+        assert_eq!(
+            fields,
+            [
+                ("/tags/0", "a"),
+                ("/tags/1/b", "c"),
+                ("/title", "Notes"),
+                ("/x♭/0", "y")
+            ]
+            .iter()
+            .map(|(p, t)| (p.to_string(), t.to_string()))
+            .collect::<Vec<_>>()
+        );
+    }
+}
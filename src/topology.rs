@@ -0,0 +1,363 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::melda::Melda;
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Direction of the changes exchanged over a link
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncMode {
+    /// The node melds the changes of the remote
+    Pull,
+    /// The remote melds the changes of the node
+    Push,
+    /// Changes are exchanged in both directions
+    PushPull,
+}
+
+/// Link along which a node periodically synchronizes with a remote node
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Link {
+    /// Node performing the synchronization
+    pub from: String,
+    /// Remote node
+    pub to: String,
+    /// Direction of the changes
+    pub mode: SyncMode,
+    /// Interval between synchronizations (in milliseconds)
+    pub interval_ms: u64,
+}
+
+/// Replication topology of a deployment: the nodes (repositories, identified by a name and
+/// the URL of their adapter) and the links describing which remotes each node pushes to or
+/// pulls from, and how often. Topologies are usually stored as JSON configuration files, and
+/// consumed by a [Replicator] running on each node (or by the repository server, see
+/// `RepositoryServer::with_topology`), so that sync loops do not have to be wired by hand.
+///
+/// # Example
+/// ```
+/// use melda::topology::{SyncMode, Topology};
+/// use std::time::Duration;
+/// let topology = Topology::new()
+///     .node("hub", "file:///srv/hub")
+///     .node("paris", "file:///srv/paris")
+///     .node("tokyo", "file:///srv/tokyo")
+///     .hub_and_spoke("hub", Duration::from_secs(60));
+/// assert!(topology.validate().is_ok());
+/// let links = topology.links_of("paris");
+/// assert_eq!((links[0].to.as_str(), links[0].mode), ("hub", SyncMode::PushPull));
+/// assert!(topology.links_of("hub").is_empty());
+/// // Topologies are (de)serialized as JSON
+/// let json = serde_json::to_string(&topology).unwrap();
+/// assert_eq!(serde_json::from_str::<Topology>(&json).unwrap(), topology);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Topology {
+    /// URL of each node
+    pub nodes: BTreeMap<String, String>,
+    /// Links between the nodes
+    #[serde(default)]
+    pub links: Vec<Link>,
+}
+
+impl Topology {
+    /// Creates an empty topology
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a node
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the node
+    /// * `url` - The URL of the adapter of the node
+    pub fn node(mut self, name: &str, url: &str) -> Self {
+        self.nodes.insert(name.to_string(), url.to_string());
+        self
+    }
+
+    /// Adds a link
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - The node performing the synchronization
+    /// * `to` - The remote node
+    /// * `mode` - The direction of the changes
+    /// * `interval` - The interval between synchronizations
+    pub fn link(mut self, from: &str, to: &str, mode: SyncMode, interval: Duration) -> Self {
+        self.links.push(Link {
+            from: from.to_string(),
+            to: to.to_string(),
+            mode,
+            interval_ms: interval.as_millis() as u64,
+        });
+        self
+    }
+
+    /// Links every other node (added so far) to the hub: each node pushes its changes to the
+    /// hub and pulls the changes of the others from it
+    pub fn hub_and_spoke(self, hub: &str, interval: Duration) -> Self {
+        let spokes: Vec<String> = self.nodes.keys().filter(|n| *n != hub).cloned().collect();
+        spokes.iter().fold(self, |topology, spoke| {
+            topology.link(spoke, hub, SyncMode::PushPull, interval)
+        })
+    }
+
+    /// Links every node (added so far) to every other node: each node pulls the changes of
+    /// all the others
+    pub fn mesh(self, interval: Duration) -> Self {
+        let nodes: Vec<String> = self.nodes.keys().cloned().collect();
+        let mut topology = self;
+        for from in &nodes {
+            for to in nodes.iter().filter(|to| *to != from) {
+                topology = topology.link(from, to, SyncMode::Pull, interval);
+            }
+        }
+        topology
+    }
+
+    /// Checks that the links connect distinct known nodes with a positive interval
+    pub fn validate(&self) -> Result<()> {
+        for link in &self.links {
+            for node in [&link.from, &link.to] {
+                if !self.nodes.contains_key(node) {
+                    bail!("unknown_node: {}", node);
+                }
+            }
+            if link.from == link.to {
+                bail!("invalid_link: {}", link.from);
+            }
+            if link.interval_ms == 0 {
+                bail!("invalid_interval: {} -> {}", link.from, link.to);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the links along which the given node synchronizes
+    pub fn links_of(&self, node: &str) -> Vec<&Link> {
+        self.links.iter().filter(|l| l.from == node).collect()
+    }
+}
+
+/// Result of a synchronization along a link, see [Replicator::sync_due]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncOutcome {
+    /// Remote node
+    pub remote: String,
+    /// Items melded from the remote
+    pub pulled: Vec<String>,
+    /// Items melded into the remote
+    pub pushed: Vec<String>,
+    /// Error which interrupted the synchronization (retried at the next interval)
+    pub error: Option<String>,
+}
+
+/// Synchronizes a node with its remotes along the links of a [Topology]
+///
+/// # Example
+/// ```
+/// use melda::{melda::Melda, memoryadapter::MemoryAdapter, adapter::Adapter};
+/// use melda::topology::{Replicator, Topology};
+/// use serde_json::json;
+/// use std::{collections::HashMap, sync::{Arc, RwLock}, time::{Duration, Instant}};
+/// let topology = Topology::new()
+///     .node("hub", "memory://")
+///     .node("a", "memory://")
+///     .node("b", "memory://")
+///     .hub_and_spoke("hub", Duration::from_secs(10));
+/// // Nodes share their storage (memory adapters are not shared when opened from an URL)
+/// let adapters: HashMap<String, Arc<RwLock<Box<dyn Adapter>>>> = topology.nodes.keys()
+///     .map(|n| (n.clone(), Arc::new(RwLock::new(Box::new(MemoryAdapter::new()) as Box<dyn Adapter>))))
+///     .collect();
+/// let open = |name: &str, _: &str| Melda::new(adapters[name].clone());
+/// let mut a = Replicator::open_with(&topology, "a", open).unwrap();
+/// let mut b = Replicator::open_with(&topology, "b", open).unwrap();
+/// a.replica().update(json!({ "from" : "a" }).as_object().unwrap().clone()).unwrap();
+/// a.replica().commit(None).unwrap();
+/// let outcomes = a.sync_due(Instant::now());
+/// assert_eq!(outcomes[0].remote, "hub");
+/// assert!(!outcomes[0].pushed.is_empty());
+/// // Links are synchronized again once the interval elapsed
+/// assert!(a.sync_due(Instant::now()).is_empty());
+/// assert!(a.next_due().unwrap() > Instant::now());
+/// b.sync_due(Instant::now());
+/// assert_eq!(b.replica().read(None).unwrap().get("from").unwrap(), "a");
+/// ```
+pub struct Replicator {
+    node: String,
+    links: Vec<Link>,
+    replica: Melda,
+    remotes: HashMap<String, Melda>,
+    due: Vec<Instant>, // Next synchronization of each link
+}
+
+impl Replicator {
+    /// Opens the replicas of a node and of its remotes (from the URLs of the topology)
+    pub fn new(topology: &Topology, node: &str) -> Result<Self> {
+        Self::open_with(topology, node, |_, url| Melda::new_from_url(url))
+    }
+
+    /// Opens the replicas of a node and of its remotes with the given function (invoked with
+    /// the name and the URL of each node), for example to configure the replicas
+    pub fn open_with<F: FnMut(&str, &str) -> Result<Melda>>(
+        topology: &Topology,
+        node: &str,
+        mut open: F,
+    ) -> Result<Self> {
+        topology.validate()?;
+        let url = topology
+            .nodes
+            .get(node)
+            .ok_or_else(|| anyhow!("unknown_node: {}", node))?;
+        let replica = open(node, url)?;
+        let links: Vec<Link> = topology.links_of(node).into_iter().cloned().collect();
+        let mut remotes = HashMap::new();
+        for link in &links {
+            if !remotes.contains_key(&link.to) {
+                let remote = open(&link.to, &topology.nodes[&link.to])?;
+                remotes.insert(link.to.clone(), remote);
+            }
+        }
+        let now = Instant::now();
+        Ok(Replicator {
+            node: node.to_string(),
+            due: vec![now; links.len()],
+            links,
+            replica,
+            remotes,
+        })
+    }
+
+    /// Returns the name of the node
+    pub fn node(&self) -> &str {
+        &self.node
+    }
+
+    /// Returns the replica of the node
+    pub fn replica(&mut self) -> &mut Melda {
+        &mut self.replica
+    }
+
+    /// Returns the time of the next synchronization (None if the node has no links)
+    pub fn next_due(&self) -> Option<Instant> {
+        self.due.iter().min().copied()
+    }
+
+    /// Synchronizes the links whose interval elapsed at the given time
+    pub fn sync_due(&mut self, now: Instant) -> Vec<SyncOutcome> {
+        let mut outcomes = vec![];
+        for i in 0..self.links.len() {
+            if self.due[i] > now {
+                continue;
+            }
+            let link = self.links[i].clone();
+            self.due[i] = now + Duration::from_millis(link.interval_ms);
+            let mut outcome = SyncOutcome {
+                remote: link.to.clone(),
+                ..Default::default()
+            };
+            if let Err(e) = self.sync(&link, &mut outcome) {
+                log::warn!("sync_failed: {} -> {}: {}", self.node, link.to, e);
+                outcome.error = Some(e.to_string());
+            }
+            outcomes.push(outcome);
+        }
+        outcomes
+    }
+
+    /// Synchronizes the links as they become due, until stopped
+    pub fn run(&mut self, stop: &AtomicBool) {
+        while !stop.load(Ordering::SeqCst) {
+            self.sync_due(Instant::now());
+            // Wake up regularly to check whether the replicator has been stopped
+            let wait = self
+                .next_due()
+                .map_or(Duration::from_secs(1), |due| {
+                    due.saturating_duration_since(Instant::now())
+                })
+                .min(Duration::from_secs(1));
+            std::thread::sleep(wait);
+        }
+    }
+
+    // Exchanges the changes with the remote of a link
+    fn sync(&mut self, link: &Link, outcome: &mut SyncOutcome) -> Result<()> {
+        let remote = self.remotes.get_mut(&link.to).unwrap();
+        remote.refresh()?;
+        if link.mode != SyncMode::Push {
+            outcome.pulled = self.replica.meld(remote)?;
+            self.replica.refresh()?;
+        }
+        if link.mode != SyncMode::Pull {
+            outcome.pushed = remote.meld(&self.replica)?;
+            remote.refresh()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::topology::{SyncMode, Topology};
+    use std::time::Duration;
+
+    #[test]
+    fn test_topology() {
+        let interval = Duration::from_secs(5);
+        let topology = Topology::new()
+            .node("a", "memory://")
+            .node("b", "memory://")
+            .node("c", "memory://")
+            .mesh(interval);
+        assert_eq!(topology.links.len(), 6);
+        assert!(topology
+            .links_of("b")
+            .iter()
+            .all(|l| l.mode == SyncMode::Pull && l.interval_ms == 5000));
+        assert!(topology.validate().is_ok());
+        let config = r#"{
+            "nodes": { "hub": "file:///srv/hub", "edge": "file:///srv/edge" },
+            "links": [ { "from": "edge", "to": "hub", "mode": "push", "interval_ms": 30000 } ]
+        }"#;
+        let topology: Topology = serde_json::from_str(config).unwrap();
+        assert_eq!(topology.links_of("edge")[0].mode, SyncMode::Push);
+        let invalid = topology
+            .clone()
+            .link("edge", "cloud", SyncMode::Pull, interval);
+        assert_eq!(
+            invalid.validate().unwrap_err().to_string(),
+            "unknown_node: cloud"
+        );
+        let invalid = topology
+            .clone()
+            .link("hub", "hub", SyncMode::Pull, interval);
+        assert!(invalid.validate().is_err());
+        let invalid = topology
+            .clone()
+            .link("hub", "edge", SyncMode::Pull, Duration::ZERO);
+        assert!(invalid.validate().is_err());
+        // Sub-second intervals are preserved
+        let valid = topology.link("hub", "edge", SyncMode::Pull, Duration::from_millis(250));
+        assert!(valid.validate().is_ok());
+        assert_eq!(valid.links_of("hub")[0].interval_ms, 250);
+    }
+}
@@ -0,0 +1,167 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::error::MeldaError;
+use crate::jsonpatch::PatchOp;
+use crate::melda::Melda;
+use crate::utils::{parse_json_pointer, pointer_child, pointer_set};
+use anyhow::{bail, Result};
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+
+/// A transaction batches several changes, which are applied to a private copy of the state
+/// and are either committed as a single block or discarded. The transaction is stale (and
+/// cannot be committed) if the replica has committed or staged other changes in the meantime.
+///
+/// # Example
+/// ```
+/// use melda::{melda::Melda, adapter::Adapter, memoryadapter::MemoryAdapter};
+/// use std::sync::{Arc, Mutex, RwLock};
+/// use serde_json::{Map, Value,json};
+/// let adapter : Box<dyn Adapter> = Box::new(MemoryAdapter::new());
+/// let replica = Melda::new(Arc::new(RwLock::new(adapter))).expect("cannot_initialize_crdt");
+/// let object = json!({ "title" : "Todo", "items\u{266D}" : [ { "_id" : "1", "key" : "alpha" } ] }).as_object().unwrap().clone();
+/// replica.update(object).unwrap();
+/// replica.commit(None).unwrap();
+/// let mut tx = replica.transaction().unwrap();
+/// tx.set("/title", json!("Shopping")).unwrap();
+/// tx.set("/items\u{266D}/1", json!({ "_id" : "2", "key" : "beta" })).unwrap();
+/// assert_eq!(replica.get("/title").unwrap().unwrap(), "Todo");
+/// let anchors = tx.commit(None).unwrap().unwrap();
+/// assert_eq!(replica.get_anchors(), anchors);
+/// assert_eq!(replica.get("/title").unwrap().unwrap(), "Shopping");
+/// assert_eq!(replica.get("/items\u{266D}/1/key").unwrap().unwrap(), "beta");
+/// // A transaction becomes stale if other changes are committed
+/// let mut tx = replica.transaction().unwrap();
+/// tx.set("/title", json!("Groceries")).unwrap();
+/// replica.set("/items\u{266D}/0/key", json!("gamma")).unwrap();
+/// replica.commit(None).unwrap();
+/// assert!(tx.commit(None).is_err());
+/// assert_eq!(replica.get("/title").unwrap().unwrap(), "Shopping");
+/// // Transactions committed from several threads are either applied as a whole or stale
+/// let replica = Arc::new(replica);
+/// let workers: Vec<_> = (0..4).map(|t| {
+///     let replica = replica.clone();
+///     std::thread::spawn(move || {
+///         let mut committed = 0;
+///         for i in 0..10 {
+///             if let Ok(mut tx) = replica.transaction() {
+///                 tx.set("/title", json!(format!("{}-{}", t, i))).unwrap();
+///                 tx.set("/count", json!(i)).unwrap();
+///                 committed += tx.commit(None).map(|_| 1).unwrap_or(0);
+///             }
+///         }
+///         committed
+///     })
+/// }).collect();
+/// let committed: usize = workers.into_iter().map(|w| w.join().unwrap()).sum();
+/// assert!(committed > 0);
+/// assert!(!replica.has_staging());
+/// let title = replica.get("/title").unwrap().unwrap();
+/// let count = replica.get("/count").unwrap().unwrap();
+/// assert!(title.as_str().unwrap().ends_with(&format!("-{}", count)));
+/// ```
+pub struct Transaction<'a> {
+    melda: &'a Melda,
+    anchors: BTreeSet<String>,
+    original: Value,
+    doc: Value,
+}
+
+impl<'a> Transaction<'a> {
+    /// Starts a new transaction (the stage of the replica must be empty)
+    pub(crate) fn new(melda: &'a Melda) -> Result<Self> {
+        if melda.has_staging() {
+            bail!(MeldaError::StageNotEmpty);
+        }
+        let anchors = melda.get_anchors();
+        let original = melda.get("")?.unwrap_or_else(|| Value::from(Map::new()));
+        Ok(Transaction {
+            melda,
+            anchors,
+            doc: original.clone(),
+            original,
+        })
+    }
+
+    /// Returns the value identified by a JSON Pointer, as seen by the transaction
+    pub fn get(&self, ptr: &str) -> Result<Option<Value>> {
+        let mut current = &self.doc;
+        for token in parse_json_pointer(ptr)? {
+            match pointer_child(current, &token) {
+                Some(child) => current = child,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(current.clone()))
+    }
+
+    /// Sets the value identified by a JSON Pointer, see [Melda::set]
+    pub fn set(&mut self, ptr: &str, value: Value) -> Result<()> {
+        let tokens = parse_json_pointer(ptr)?;
+        if tokens.is_empty() && !value.is_object() {
+            bail!("document_not_an_object");
+        }
+        pointer_set(&mut self.doc, &tokens, value)
+    }
+
+    /// Replaces the whole document, see [Melda::update]
+    pub fn update(&mut self, obj: Map<String, Value>) {
+        self.doc = Value::from(obj);
+    }
+
+    /// Applies a JSON Patch (RFC 6902), see [Melda::apply_patch]
+    pub fn apply_patch(&mut self, ops: &[PatchOp]) -> Result<()> {
+        let mut doc = self.doc.clone();
+        crate::jsonpatch::apply_patch(&mut doc, ops)?;
+        if !doc.is_object() {
+            bail!("document_not_an_object");
+        }
+        self.doc = doc;
+        Ok(())
+    }
+
+    /// Stages and commits all changes as a single block. Fails (leaving the replica unchanged)
+    /// if the replica has committed or staged other changes since the transaction started.
+    /// Commits, refreshes and reloads of other threads wait until the transaction is committed.
+    ///
+    /// # Arguments
+    ///
+    /// * `information` - Optional information attached to the block
+    pub fn commit(
+        self,
+        information: Option<Map<String, Value>>,
+    ) -> Result<Option<BTreeSet<String>>> {
+        let _exclusive = self.melda.exclusive();
+        if self.melda.get_anchors() != self.anchors || self.melda.has_staging() {
+            bail!(MeldaError::StaleCommit);
+        }
+        let ops = crate::jsonpatch::diff(&self.original, &self.doc);
+        if ops.is_empty() {
+            return Ok(None);
+        }
+        let result = self
+            .melda
+            .apply_patch(&ops)
+            .and_then(|_| self.melda.commit(information));
+        if result.is_err() {
+            self.melda.unstage()?;
+        }
+        result
+    }
+
+    /// Discards all changes
+    pub fn rollback(self) {}
+}
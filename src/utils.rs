@@ -14,14 +14,19 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 use anyhow::{anyhow, bail, Result};
+use rayon::prelude::*;
 use serde_json::{json, Map, Value};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
 use yavomrs::yavom::{myers_unfilled, Move, Point};
 
+use crate::builder::{DigestAlgorithm, Encoding};
 use crate::constants::{
-    ARRAY_DESCRIPTOR_ORDER_FIELD, ARRAY_DESCRIPTOR_PREFIX, EMPTY_HASH, FLATTEN_SUFFIX, HASH_FIELD,
-    ID_FIELD, PATCH_DELETE, PATCH_INSERT, ROOT_ID, STRING_ESCAPE_PREFIX,
+    ARRAY_DESCRIPTOR_ORDER_FIELD, ARRAY_DESCRIPTOR_PREFIX, BINARY_FORMAT_VERSION, BINARY_HEADER,
+    EMPTY_HASH, FLATTEN_SUFFIX, HASH_FIELD, ID_FIELD, PATCH_DELETE, PATCH_INSERT, RESERVED_FIELDS,
+    ROOT_ID, STRING_ESCAPE_PREFIX,
 };
+use crate::error::MeldaError;
 
 /// Returns true if the key matches a flattened field
 pub fn is_flattened_field(key: &str) -> bool {
@@ -47,18 +52,122 @@ pub fn unescape(s: &str) -> String {
     }
 }
 
+/// Returns the current time in milliseconds since the Unix epoch (None if the clock is not
+/// available)
+pub fn now_millis() -> Option<u64> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()
+            .map(|d| d.as_millis() as u64)
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        None
+    }
+}
+
+/// Formats a time in milliseconds since the Unix epoch as an ISO 8601 UTC date and time
+pub fn format_timestamp(millis: u64) -> String {
+    let (days, ms) = (millis / 86_400_000, millis % 86_400_000);
+    // Civil date from the number of days since the epoch (proleptic Gregorian calendar)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
 /// Computes the digest of a string
 pub fn digest_string(content: &str) -> String {
     digest_bytes(content.as_bytes())
 }
 
 /// Computes the digest of a slice of bytes
+#[cfg(feature = "openssl")]
 pub fn digest_bytes(content: &[u8]) -> String {
     let mut hasher = openssl::sha::Sha256::new();
     hasher.update(content);
     hex::encode(hasher.finish())
 }
 
+/// Computes the digest of a slice of bytes
+#[cfg(feature = "rustcrypto")]
+pub fn digest_bytes(content: &[u8]) -> String {
+    use sha2::Digest;
+    hex::encode(sha2::Sha256::digest(content))
+}
+
+/// Computes the digest of a slice of bytes with the given algorithm
+pub fn digest_bytes_with(algorithm: DigestAlgorithm, content: &[u8]) -> String {
+    match algorithm {
+        DigestAlgorithm::Sha256 => digest_bytes(content),
+        DigestAlgorithm::Blake3 => blake3::hash(content).to_hex().to_string(),
+    }
+}
+
+/// Computes the keyed hash (HMAC-SHA256) of the given data
+#[cfg(feature = "openssl")]
+pub fn keyed_hash(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let pkey = openssl::pkey::PKey::hmac(key)?;
+    let mut signer = openssl::sign::Signer::new(openssl::hash::MessageDigest::sha256(), &pkey)?;
+    signer.update(data)?;
+    signer
+        .sign_to_vec()
+        .map_err(|e| anyhow!("cannot_compute_keyed_hash: {}", e))
+}
+
+/// Computes the keyed hash (HMAC-SHA256) of the given data
+#[cfg(feature = "rustcrypto")]
+pub fn keyed_hash(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    use hmac::Mac;
+    let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(key)
+        .map_err(|e| anyhow!("cannot_compute_keyed_hash: {}", e))?;
+    mac.update(data);
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+/// Compares two slices of bytes in constant time (slices of different lengths are different)
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    #[cfg(feature = "openssl")]
+    {
+        a.len() == b.len() && openssl::memcmp::eq(a, b)
+    }
+    #[cfg(feature = "rustcrypto")]
+    {
+        use subtle::ConstantTimeEq;
+        a.ct_eq(b).into()
+    }
+}
+
+/// Fills the buffer with cryptographically secure random bytes
+pub fn random_bytes(buffer: &mut [u8]) -> Result<()> {
+    #[cfg(feature = "openssl")]
+    {
+        openssl::rand::rand_bytes(buffer)?;
+    }
+    #[cfg(feature = "rustcrypto")]
+    {
+        getrandom::getrandom(buffer).map_err(|e| anyhow!("cannot_generate_random_bytes: {}", e))?;
+    }
+    Ok(())
+}
+
 /// Computes the digest of a JSON object
 pub fn digest_object(o: &Map<String, Value>) -> Result<String> {
     if o.is_empty() {
@@ -85,6 +194,75 @@ pub fn digest_object(o: &Map<String, Value>) -> Result<String> {
     }
 }
 
+/// Returns the header written before binary encoded data
+pub fn binary_header() -> Vec<u8> {
+    let mut header = BINARY_HEADER.to_vec();
+    header.push(BINARY_FORMAT_VERSION);
+    header
+}
+
+/// Returns the length of the header if the data is binary encoded (fails if the data has been
+/// written with an unsupported version of the binary format)
+pub fn binary_header_len(data: &[u8]) -> Result<Option<usize>> {
+    if !data.starts_with(BINARY_HEADER) {
+        return Ok(None);
+    }
+    match data.get(BINARY_HEADER.len()) {
+        Some(&BINARY_FORMAT_VERSION) => Ok(Some(BINARY_HEADER.len() + 1)),
+        Some(version) => bail!("unsupported_format_version: {}", version),
+        None => bail!("missing_format_version"),
+    }
+}
+
+/// Encodes a value (binary encodings are prefixed by a header carrying the format version)
+pub fn encode_value(value: &Value, encoding: Encoding) -> Result<Vec<u8>> {
+    match encoding {
+        Encoding::Json => Ok(serde_json::to_vec(value)?),
+        Encoding::MessagePack => {
+            let mut data = binary_header();
+            data.extend(rmp_serde::to_vec(value)?);
+            Ok(data)
+        }
+    }
+}
+
+/// Decodes a value written by encode_value (with any encoding)
+pub fn decode_value(data: &[u8]) -> Result<Value> {
+    match binary_header_len(data)? {
+        Some(start) => Ok(rmp_serde::from_slice(&data[start..])?),
+        None => Ok(serde_json::from_slice(data)?),
+    }
+}
+
+/// Decodes an object stored inside a pack: values in JSON packs are objects (starting with a
+/// brace), whereas values in binary packs start with a MessagePack map marker
+pub fn decode_pack_value(data: &[u8]) -> Result<Value> {
+    if data.first() == Some(&b'{') {
+        Ok(serde_json::from_slice(data)?)
+    } else {
+        Ok(rmp_serde::from_slice(data)?)
+    }
+}
+
+/// Returns an estimate of the memory used by a JSON value (in bytes)
+pub fn approximate_size(value: &Value) -> usize {
+    std::mem::size_of::<Value>()
+        + match value {
+            Value::String(s) => s.len(),
+            Value::Array(a) => a.iter().map(approximate_size).sum(),
+            Value::Object(o) => approximate_object_size(o),
+            _ => 0,
+        }
+}
+
+/// Returns an estimate of the memory used by the fields of a JSON object (in bytes)
+pub fn approximate_object_size(object: &Map<String, Value>) -> usize {
+    object
+        .iter()
+        .map(|(k, v)| k.len() + approximate_size(v))
+        .sum()
+}
+
 /// Returns the identifier of an object with path
 pub fn generate_identifier(value: &Map<String, Value>, path: &[String]) -> Result<String> {
     if value.contains_key(ID_FIELD) {
@@ -120,38 +298,68 @@ pub fn merge_arrays(order_m: &[Value], order_n: &mut Vec<Value>) {
     if order_m.is_empty() {
         return;
     }
-    // Find the pivot
-    let mut ins_pos_in_n = 0;
-    let mut pivot_pos_in_m: usize = 0;
-    for t in order_m {
-        let index = order_n.iter().position(|e| *e == *t);
-        match index {
-            Some(position) => {
-                ins_pos_in_n = position;
-                break;
-            }
-            None => pivot_pos_in_m += 1,
-        }
+    // Elements of N are linked in a list, so that inserting an element does not shift the
+    // following ones, and the first occurrence of each element is indexed (huge arrays are
+    // merged in linear time)
+    let key = |v: &Value| v.to_string();
+    let mut values = std::mem::take(order_n);
+    let count = values.len();
+    let mut next: Vec<Option<usize>> = (1..=count).map(|i| (i < count).then_some(i)).collect();
+    let mut prev: Vec<Option<usize>> = (0..count).map(|i| i.checked_sub(1)).collect();
+    let mut head = 0;
+    let mut first = HashMap::new();
+    for (i, v) in values.iter().enumerate() {
+        first.entry(key(v)).or_insert(i);
     }
+    // Find the pivot
+    let mut pivot_pos_in_m = order_m
+        .iter()
+        .position(|t| first.contains_key(&key(t)))
+        .unwrap_or(order_m.len());
+    let mut ins_node = order_m
+        .get(pivot_pos_in_m)
+        .and_then(|t| first.get(&key(t)).copied())
+        .unwrap_or(head);
     for (current_pos_in_m, t) in order_m.iter().enumerate() {
-        // Search t in N
-        let it = order_n.iter().position(|e| *e == *t);
-        match it {
-            // If found, update the insertion point to this new position
-            Some(position) => ins_pos_in_n = position,
+        let k = key(t);
+        match first.get(&k) {
+            // If found, update the insertion point to this element
+            Some(node) => ins_node = *node,
             None => {
+                let node = values.len();
+                values.push(t.clone());
+                first.insert(k, node);
                 // Is the current position (in M) before the pivot
                 if current_pos_in_m < pivot_pos_in_m {
-                    // Insert at insertIt
-                    order_n.insert(ins_pos_in_n, t.clone());
+                    // Insert before the insertion point
+                    let before = prev[ins_node];
+                    prev.push(before);
+                    next.push(Some(ins_node));
+                    prev[ins_node] = Some(node);
+                    match before {
+                        Some(before) => next[before] = Some(node),
+                        None => head = node,
+                    }
                     pivot_pos_in_m = current_pos_in_m
                 } else {
-                    ins_pos_in_n += 1;
-                    order_n.insert(ins_pos_in_n, t.clone());
+                    // Insert after the insertion point
+                    let after = next[ins_node];
+                    next.push(after);
+                    prev.push(Some(ins_node));
+                    next[ins_node] = Some(node);
+                    if let Some(after) = after {
+                        prev[after] = Some(node);
+                    }
                 }
+                ins_node = node;
             }
         }
     }
+    let mut cursor = Some(head);
+    while let Some(node) = cursor {
+        order_n.push(std::mem::take(&mut values[node]));
+        cursor = next[node];
+    }
 }
 
 /// Flattens a JSON value, stores promoted objects in c
@@ -170,28 +378,7 @@ pub fn flatten(
             let no: Map<String, Value> = o
                 .into_iter()
                 .filter(|(k, _)| *k != ID_FIELD)
-                .map(|(k, v)| {
-                    if is_flattened_field(k) {
-                        let mut fpath = fpath.clone();
-                        fpath.push(k.clone());
-                        let flattened = flatten(c, v, &fpath);
-                        if let Value::Array(_) = &flattened {
-                            // We assume that all arrays will be stored as deltas from
-                            // the previous version
-                            let mut array_descriptor_object = Map::new();
-                            array_descriptor_object
-                                .insert(ARRAY_DESCRIPTOR_ORDER_FIELD.to_string(), flattened);
-                            let array_descriptor_uuid = ARRAY_DESCRIPTOR_PREFIX.to_string()
-                                + &digest_string(&fpath.join(""));
-                            c.insert(array_descriptor_uuid.clone(), array_descriptor_object);
-                            (k.clone(), Value::from(array_descriptor_uuid))
-                        } else {
-                            (k.clone(), flattened)
-                        }
-                    } else {
-                        (k.clone(), v.clone())
-                    }
-                })
+                .map(|(k, v)| (k.clone(), flatten_field(c, k, v, &fpath)))
                 .collect();
             c.insert(uuid.clone(), no);
             Value::from(uuid)
@@ -200,6 +387,147 @@ pub fn flatten(
     }
 }
 
+/// Flattens the value of a field of the object with the given path (including the identifier
+/// of the object), stores promoted objects in c
+pub fn flatten_field(
+    c: &mut HashMap<String, Map<String, Value>>,
+    key: &str,
+    value: &Value,
+    path: &[String],
+) -> Value {
+    if is_flattened_field(key) {
+        let mut fpath = path.to_owned();
+        fpath.push(key.to_string());
+        let flattened = flatten(c, value, &fpath);
+        if let Value::Array(_) = &flattened {
+            // We assume that all arrays will be stored as deltas from
+            // the previous version
+            let mut array_descriptor_object = Map::new();
+            array_descriptor_object.insert(ARRAY_DESCRIPTOR_ORDER_FIELD.to_string(), flattened);
+            let array_descriptor_uuid =
+                ARRAY_DESCRIPTOR_PREFIX.to_string() + &digest_string(&fpath.join(""));
+            c.insert(array_descriptor_uuid.clone(), array_descriptor_object);
+            Value::from(array_descriptor_uuid)
+        } else {
+            flattened
+        }
+    } else {
+        value.clone()
+    }
+}
+
+/// Validates documents in strict mode (see [crate::builder::MeldaBuilder::strict]): elements of
+/// flattened arrays must be objects, object identifiers must be valid and unique within the
+/// document (objects without an identifier in the same array get the same one, see
+/// [generate_identifier]) and objects cannot contain reserved fields. Paths are those used by
+/// [flatten], pointers locate the values in the document
+#[derive(Default)]
+pub struct StrictValidator {
+    identifiers: HashMap<String, Vec<String>>, // Pointer of the object with each identifier
+}
+
+impl StrictValidator {
+    /// Validates an object with the given path
+    pub fn validate_object(
+        &mut self,
+        object: &Map<String, Value>,
+        path: &[String],
+        pointer: &[String],
+    ) -> Result<()> {
+        for key in object.keys() {
+            Self::validate_key(key, pointer)?;
+        }
+        let uuid = match object.get(ID_FIELD) {
+            Some(Value::String(id))
+                if is_array_descriptor(id) || (id == ROOT_ID && !path.is_empty()) =>
+            {
+                bail!(invalid_document(
+                    pointer,
+                    format!("invalid_identifier: {}", id)
+                ))
+            }
+            Some(Value::String(id)) => id.clone(),
+            Some(id) => bail!(invalid_document(
+                pointer,
+                format!("invalid_identifier: {}", id)
+            )),
+            None => generate_identifier(object, path)?,
+        };
+        if let Some(other) = self.identifiers.insert(uuid.clone(), pointer.to_vec()) {
+            let reason = match object.get(ID_FIELD) {
+                Some(_) => format!("duplicate_identifier: {}", uuid),
+                None => "missing_identifier".to_string(),
+            };
+            let reason = format!("{} (same as '{}')", reason, encode_json_pointer(&other));
+            bail!(invalid_document(pointer, reason));
+        }
+        let mut fpath = path.to_owned();
+        fpath.push(uuid);
+        for (key, value) in object.iter().filter(|(k, _)| is_flattened_field(k)) {
+            let mut field = pointer.to_owned();
+            field.push(key.clone());
+            self.validate_field(key, value, &fpath, &field)?;
+        }
+        Ok(())
+    }
+
+    /// Validates a flattened field of the object with the given path (including the
+    /// identifier of the object)
+    pub fn validate_field(
+        &mut self,
+        key: &str,
+        value: &Value,
+        path: &[String],
+        pointer: &[String],
+    ) -> Result<()> {
+        let mut fpath = path.to_owned();
+        fpath.push(key.to_string());
+        match value {
+            Value::Array(elements) => {
+                for (i, element) in elements.iter().enumerate() {
+                    let mut position = pointer.to_owned();
+                    position.push(i.to_string());
+                    self.validate_element(element, &fpath, &position)?;
+                }
+                Ok(())
+            }
+            Value::Object(object) => self.validate_object(object, &fpath, pointer),
+            _ => Ok(()),
+        }
+    }
+
+    /// Validates an element of a flattened array with the given path (including the key of
+    /// the field)
+    pub fn validate_element(
+        &mut self,
+        element: &Value,
+        path: &[String],
+        pointer: &[String],
+    ) -> Result<()> {
+        match element {
+            Value::Object(object) => self.validate_object(object, path, pointer),
+            _ => bail!(invalid_document(pointer, "non_object_element".to_string())),
+        }
+    }
+
+    /// Validates a key of an object
+    pub fn validate_key(key: &str, pointer: &[String]) -> Result<()> {
+        if RESERVED_FIELDS.contains(&key) {
+            let mut field = pointer.to_owned();
+            field.push(key.to_string());
+            bail!(invalid_document(&field, format!("reserved_key: {}", key)));
+        }
+        Ok(())
+    }
+}
+
+fn invalid_document(pointer: &[String], reason: String) -> MeldaError {
+    MeldaError::InvalidDocument {
+        pointer: encode_json_pointer(pointer),
+        reason,
+    }
+}
+
 /// Unflattens a collection of objects starting from an initial value
 pub fn unflatten(c: &mut HashMap<String, Map<String, Value>>, value: &Value) -> Option<Value> {
     match value {
@@ -259,6 +587,60 @@ pub fn unflatten(c: &mut HashMap<String, Map<String, Value>>, value: &Value) ->
     }
 }
 
+/// Unflattens a collection of objects starting from an initial value (like [unflatten]),
+/// reconstructing the elements of arrays concurrently. Each object is taken from the
+/// collection when it is used
+pub fn par_unflatten(
+    c: &HashMap<String, Mutex<Option<Map<String, Value>>>>,
+    value: &Value,
+) -> Option<Value> {
+    let take = |uuid: &str| c.get(uuid).and_then(|o| o.lock().unwrap().take());
+    match value {
+        Value::String(s) => {
+            if s.starts_with(STRING_ESCAPE_PREFIX) {
+                Some(Value::from(unescape(s)))
+            } else if is_array_descriptor(s) {
+                let v = take(s).expect("unknown_descriptor_object");
+                let order = v
+                    .get(ARRAY_DESCRIPTOR_ORDER_FIELD)
+                    .expect("expecting_order_field_in_descriptor")
+                    .as_array()
+                    .expect("expecting_order_field_in_descriptor_as_array");
+                let array = order
+                    .par_iter()
+                    .filter_map(|uuid| {
+                        let o = take(uuid.as_str()?)?;
+                        par_unflatten(c, &Value::from(o))
+                    })
+                    .collect::<Vec<_>>();
+                Some(Value::from(array))
+            } else {
+                match take(s) {
+                    Some(v) => par_unflatten(c, &Value::from(v)),
+                    None => Some(json!(null)),
+                }
+            }
+        }
+        Value::Array(a) => Some(Value::from(
+            a.iter()
+                .filter_map(|v| par_unflatten(c, v))
+                .collect::<Vec<_>>(),
+        )),
+        Value::Object(o) => Some(Value::from(
+            o.iter()
+                .map(|(k, v)| {
+                    if !is_flattened_field(k) {
+                        (k.clone(), v.clone())
+                    } else {
+                        (k.clone(), par_unflatten(c, v).unwrap())
+                    }
+                })
+                .collect::<Map<String, Value>>(),
+        )),
+        _ => Some(value.clone()),
+    }
+}
+
 /// Creates an array diff patch
 pub fn make_diff_patch(old: &[Value], new: &[Value]) -> Result<Vec<Value>> {
     let ops = myers_unfilled(old, new);
@@ -286,38 +668,263 @@ pub fn make_diff_patch(old: &[Value], new: &[Value]) -> Result<Vec<Value>> {
 }
 
 /// Applies a patch to the given array
+/// Computes the patch transforming old into new. Arrays longer than the chunk size are
+/// split (after removing their common prefix and suffix) into chunks which are diffed
+/// independently: the patch might not be minimal, but it is computed in linear time
+pub fn make_chunked_diff_patch(
+    old: &[Value],
+    new: &[Value],
+    chunk_size: usize,
+) -> Result<Vec<Value>> {
+    if old.len().max(new.len()) <= chunk_size {
+        return make_diff_patch(old, new);
+    }
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old = &old[prefix..old.len() - suffix];
+    let new = &new[prefix..new.len() - suffix];
+    let chunks = old.len().max(new.len()).div_ceil(chunk_size);
+    let mut patch = vec![];
+    // Position of the current chunk (the previous chunks have been patched)
+    let mut offset = prefix;
+    for i in 0..chunks {
+        let o = &old[i * old.len() / chunks..(i + 1) * old.len() / chunks];
+        let n = &new[i * new.len() / chunks..(i + 1) * new.len() / chunks];
+        if o.is_empty() && !n.is_empty() {
+            patch.push(json!([PATCH_INSERT, offset, n]));
+        } else if n.is_empty() && !o.is_empty() {
+            patch.push(json!([PATCH_DELETE, o.len(), offset]));
+        } else if !n.is_empty() {
+            for mut op in make_diff_patch(o, n)? {
+                let position = if op[0] == PATCH_INSERT { 1 } else { 2 };
+                let index = op[position].as_u64().unwrap() as usize;
+                op[position] = Value::from(index + offset);
+                patch.push(op);
+            }
+        }
+        offset += n.len();
+    }
+    Ok(patch)
+}
+
 pub fn apply_diff_patch(old: &mut Vec<Value>, patch: &[Value]) -> Result<()> {
+    // Operations are sorted by index: the result is built in a single pass, copying the
+    // elements between operations (otherwise operations are applied one by one)
+    let mut result = Vec::with_capacity(old.len());
+    let mut cursor = 0;
+    for (i, op) in patch.iter().enumerate() {
+        let (operation, index) = parse_patch_op(op, patch)?;
+        if index < result.len() {
+            result.extend_from_slice(&old[cursor..]);
+            *old = result;
+            return apply_diff_patch_ops(old, &patch[i..]);
+        }
+        let end = cursor + index - result.len();
+        if end > old.len() {
+            bail!("invalid_patch_index_out_of_bounds");
+        }
+        result.extend_from_slice(&old[cursor..end]);
+        cursor = end;
+        if operation == PATCH_DELETE {
+            let length = patch_delete_length(op)?;
+            if cursor + length > old.len() {
+                bail!("invalid_patch_length_out_of_bounds");
+            }
+            cursor += length;
+        } else {
+            result.extend(patch_insert_items(op)?.iter().cloned());
+        }
+    }
+    result.extend_from_slice(&old[cursor..]);
+    *old = result;
+    Ok(())
+}
+
+/// Applies the operations of a patch one by one
+fn apply_diff_patch_ops(old: &mut Vec<Value>, patch: &[Value]) -> Result<()> {
     for op in patch {
-        let operation = op[0]
-            .as_str()
-            .ok_or_else(|| anyhow!("invalid_patch_op_not_a_string: {:?}", patch))?;
+        let (operation, index) = parse_patch_op(op, patch)?;
         if operation == PATCH_DELETE {
-            let length = op[1]
-                .as_u64()
-                .ok_or_else(|| anyhow!("invalid_patch_length_not_a_number"))?
-                as usize;
-            let index = op[2]
-                .as_u64()
-                .ok_or_else(|| anyhow!("invalid_patch_index_not_a_number"))?
-                as usize;
+            let length = patch_delete_length(op)?;
+            if index + length > old.len() {
+                bail!("invalid_patch_length_out_of_bounds");
+            }
             old.drain(index..index + length);
-        } else if operation == PATCH_INSERT {
-            let index = op[1]
-                .as_u64()
-                .ok_or_else(|| anyhow!("invalid_patch_index_not_a_number"))?
-                as usize;
-            let items = op[2]
-                .as_array()
-                .ok_or_else(|| anyhow!("invalid_patch_items_not_an_array"))?
-                .clone();
-            old.splice(index..index, items.into_iter());
         } else {
-            return Err(anyhow!("invalid_patch_op"));
+            if index > old.len() {
+                bail!("invalid_patch_index_out_of_bounds");
+            }
+            let items = patch_insert_items(op)?.clone();
+            old.splice(index..index, items);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the operation of a patch and the index it applies to
+fn parse_patch_op<'a>(op: &'a Value, patch: &[Value]) -> Result<(&'a str, usize)> {
+    let operation = op[0]
+        .as_str()
+        .ok_or_else(|| anyhow!("invalid_patch_op_not_a_string: {:?}", patch))?;
+    let index = if operation == PATCH_DELETE {
+        &op[2]
+    } else if operation == PATCH_INSERT {
+        &op[1]
+    } else {
+        return Err(anyhow!("invalid_patch_op"));
+    };
+    let index = index
+        .as_u64()
+        .ok_or_else(|| anyhow!("invalid_patch_index_not_a_number"))? as usize;
+    Ok((operation, index))
+}
+
+fn patch_delete_length(op: &Value) -> Result<usize> {
+    Ok(op[1]
+        .as_u64()
+        .ok_or_else(|| anyhow!("invalid_patch_length_not_a_number"))? as usize)
+}
+
+fn patch_insert_items(op: &Value) -> Result<&Vec<Value>> {
+    op[2]
+        .as_array()
+        .ok_or_else(|| anyhow!("invalid_patch_items_not_an_array"))
+}
+
+/// Parses a JSON Pointer (RFC 6901) into its reference tokens
+pub fn parse_json_pointer(ptr: &str) -> Result<Vec<String>> {
+    if ptr.is_empty() {
+        return Ok(vec![]);
+    }
+    match ptr.strip_prefix('/') {
+        Some(ptr) => Ok(ptr
+            .split('/')
+            .map(|t| t.replace("~1", "/").replace("~0", "~"))
+            .collect()),
+        None => bail!("invalid_json_pointer"),
+    }
+}
+
+/// Encodes reference tokens as a JSON Pointer (RFC 6901)
+pub fn encode_json_pointer(tokens: &[String]) -> String {
+    tokens
+        .iter()
+        .map(|t| "/".to_string() + &t.replace('~', "~0").replace('/', "~1"))
+        .collect()
+}
+
+/// Parses a JSON Pointer array index (leading zeros are not allowed)
+pub fn parse_array_index(token: &str) -> Option<usize> {
+    if token.is_empty()
+        || (token.len() > 1 && token.starts_with('0'))
+        || !token.bytes().all(|b| b.is_ascii_digit())
+    {
+        None
+    } else {
+        token.parse::<usize>().ok()
+    }
+}
+
+/// Returns the child of a JSON value identified by a reference token
+pub fn pointer_child<'a>(value: &'a Value, token: &str) -> Option<&'a Value> {
+    match value {
+        Value::Object(o) => o.get(token),
+        Value::Array(a) => parse_array_index(token).and_then(|i| a.get(i)),
+        _ => None,
+    }
+}
+
+/// Returns the (mutable) child of a JSON value identified by a reference token
+pub fn pointer_child_mut<'a>(value: &'a mut Value, token: &str) -> Option<&'a mut Value> {
+    match value {
+        Value::Object(o) => o.get_mut(token),
+        Value::Array(a) => parse_array_index(token).and_then(move |i| a.get_mut(i)),
+        _ => None,
+    }
+}
+
+/// Sets the value identified by the reference tokens: object members are added or replaced,
+/// array elements are replaced (or appended if the index is equal to the length of the array or "-")
+pub fn pointer_set(target: &mut Value, tokens: &[String], value: Value) -> Result<()> {
+    let (last, parents) = match tokens.split_last() {
+        Some(split) => split,
+        None => {
+            *target = value;
+            return Ok(());
+        }
+    };
+    let mut current = target;
+    for t in parents {
+        current = pointer_child_mut(current, t).ok_or_else(|| anyhow!("path_not_found"))?;
+    }
+    match current {
+        Value::Object(o) => {
+            o.insert(last.clone(), value);
+        }
+        Value::Array(a) => {
+            let index = if last == "-" {
+                a.len()
+            } else {
+                parse_array_index(last).ok_or_else(|| anyhow!("invalid_array_index"))?
+            };
+            if index < a.len() {
+                a[index] = value;
+            } else if index == a.len() {
+                a.push(value);
+            } else {
+                bail!("array_index_out_of_bounds");
+            }
         }
+        _ => bail!("path_not_found"),
     }
     Ok(())
 }
 
+/// Three-way merge of two objects changed concurrently with respect to their common base:
+/// members changed on one side only take the changed value, nested objects changed on both
+/// sides are merged member by member. Members which diverge keep our value, their JSON Pointers
+/// (prefixed by the given path) are added to the diverging set
+pub fn merge_objects(
+    base: &Map<String, Value>,
+    ours: &Map<String, Value>,
+    theirs: &Map<String, Value>,
+    path: &mut Vec<String>,
+    diverging: &mut BTreeSet<String>,
+) -> Map<String, Value> {
+    let keys: BTreeSet<&String> = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .collect();
+    let mut merged = Map::new();
+    for key in keys {
+        let (b, o, t) = (base.get(key), ours.get(key), theirs.get(key));
+        path.push(key.clone());
+        let value = if t == b || t == o {
+            o.cloned()
+        } else if o == b {
+            t.cloned()
+        } else if let (Some(Value::Object(o)), Some(Value::Object(t))) = (o, t) {
+            let empty = Map::new();
+            let b = b.and_then(Value::as_object).unwrap_or(&empty);
+            Some(Value::from(merge_objects(b, o, t, path, diverging)))
+        } else {
+            diverging.insert(encode_json_pointer(path));
+            o.cloned()
+        };
+        path.pop();
+        if let Some(value) = value {
+            merged.insert(key.clone(), value);
+        }
+    }
+    merged
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -329,7 +936,7 @@ mod tests {
         });
     }
 
-    fn vec_equals<T: PartialEq>(a: &Vec<T>, b: &Vec<T>) -> bool {
+    fn vec_equals<T: PartialEq>(a: &[T], b: &[T]) -> bool {
         let matching = a.iter().zip(b.iter()).filter(|&(a, b)| a == b).count();
         matching == a.len() && matching == b.len()
     }
@@ -364,6 +971,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_keyed_hash() {
+        // RFC 4231, test case 2
+        let mac = keyed_hash(b"Jefe", b"what do ya want for nothing?").unwrap();
+        assert_eq!(
+            hex::encode(mac),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+    }
+
     #[test]
     fn test_digest_object() {
         assert!(
@@ -575,13 +1195,81 @@ mod tests {
                 mc.insert(k.clone(), vn);
             });
             let rootobj = mc.get(ROOT_ID).unwrap().clone();
-            let obj = unflatten(&mut mc, &serde_json::Value::from(rootobj)).unwrap();
+            let pc = mc
+                .iter()
+                .map(|(k, v)| (k.clone(), Mutex::new(Some(v.clone()))))
+                .collect();
+            let obj = unflatten(&mut mc, &serde_json::Value::from(rootobj.clone())).unwrap();
             let reconstructed = serde_json::to_string(&obj).unwrap();
             let original = serde_json::to_string(&v).unwrap();
             assert!(reconstructed == original);
+            let obj = par_unflatten(&pc, &serde_json::Value::from(rootobj)).unwrap();
+            assert!(serde_json::to_string(&obj).unwrap() == original);
         }
     }
 
+    #[test]
+    fn test_json_pointer() {
+        assert!(parse_json_pointer("").unwrap().is_empty());
+        assert!(parse_json_pointer("/a~1b/c~0d/0").unwrap() == vec!["a/b", "c~d", "0"]);
+        assert!(parse_json_pointer("a").is_err());
+        assert!(
+            encode_json_pointer(&parse_json_pointer("/a~1b/c~0d/0").unwrap()) == "/a~1b/c~0d/0"
+        );
+        assert!(encode_json_pointer(&[]).is_empty());
+        assert!(parse_array_index("0") == Some(0));
+        assert!(parse_array_index("12") == Some(12));
+        assert!(parse_array_index("01").is_none());
+        assert!(parse_array_index("-").is_none());
+        let mut v = json!({"a": {"b": [1, 2]}});
+        assert!(pointer_child(&v, "a").unwrap() == &json!({"b": [1, 2]}));
+        let tokens = parse_json_pointer("/a/b/1").unwrap();
+        pointer_set(&mut v, &tokens, json!(3)).unwrap();
+        let tokens = parse_json_pointer("/a/b/-").unwrap();
+        pointer_set(&mut v, &tokens, json!(4)).unwrap();
+        let tokens = parse_json_pointer("/a/c").unwrap();
+        pointer_set(&mut v, &tokens, json!("x")).unwrap();
+        assert!(v == json!({"a": {"b": [1, 3, 4], "c": "x"}}));
+        let tokens = parse_json_pointer("/a/b/5").unwrap();
+        assert!(pointer_set(&mut v, &tokens, json!(5)).is_err());
+        let tokens = parse_json_pointer("/x/y").unwrap();
+        assert!(pointer_set(&mut v, &tokens, json!(5)).is_err());
+    }
+
+    #[test]
+    fn test_strict_validator() {
+        let validate = |value: Value| {
+            let result =
+                StrictValidator::default().validate_object(value.as_object().unwrap(), &[], &[]);
+            result.map_err(|e| MeldaError::of(&e).unwrap().to_string())
+        };
+        assert!(
+            validate(json!({"a♭": [{"_id": "1"}, {"_id": "2", "b♭": [{"_id": "3"}]}]})).is_ok()
+        );
+        assert!(validate(json!({"a♭": {"k": 1}, "b": [1, "x", {"_deleted": true}]})).is_ok());
+        assert_eq!(
+            validate(json!({"a♭": [{"_id": "1"}, "x"]})).unwrap_err(),
+            "invalid_document: non_object_element at '/a♭/1'"
+        );
+        assert_eq!(
+            validate(json!({"a♭": [{"_id": "1"}], "b♭": [{"_id": "1"}]})).unwrap_err(),
+            "invalid_document: duplicate_identifier: 1 (same as '/a♭/0') at '/b♭/0'"
+        );
+        assert_eq!(
+            validate(json!({"a♭": [{"k": 1}, {"k": 2}]})).unwrap_err(),
+            "invalid_document: missing_identifier (same as '/a♭/0') at '/a♭/1'"
+        );
+        assert_eq!(
+            validate(json!({"a♭": [{"_id": "1", "_resolved": 1}]})).unwrap_err(),
+            "invalid_document: reserved_key: _resolved at '/a♭/0/_resolved'"
+        );
+        assert!(validate(json!({"a♭": [{"_id": "^1"}]})).is_err());
+        assert!(validate(json!({"a♭": [{"_id": 1}]})).is_err());
+        assert!(validate(json!({"a♭": [{"_id": "√"}]})).is_err());
+        assert!(validate(json!({"_id": "√", "a♭": []})).is_ok());
+        assert!(validate(json!({"_id": "doc", "a♭": []})).is_ok());
+    }
+
     #[test]
     fn test_patch() {
         {
@@ -613,4 +1301,118 @@ mod tests {
             assert!(b == a);
         }
     }
+
+    // Reference (quadratic) implementation of merge_arrays
+    fn merge_arrays_by_position(order_m: &[Value], order_n: &mut Vec<Value>) {
+        if order_n.is_empty() {
+            order_n.extend(order_m.iter().cloned());
+            return;
+        }
+        let mut ins_pos_in_n = 0;
+        let mut pivot_pos_in_m = order_m
+            .iter()
+            .position(|t| order_n.contains(t))
+            .unwrap_or(order_m.len());
+        if let Some(t) = order_m.get(pivot_pos_in_m) {
+            ins_pos_in_n = order_n.iter().position(|e| e == t).unwrap();
+        }
+        for (current_pos_in_m, t) in order_m.iter().enumerate() {
+            match order_n.iter().position(|e| e == t) {
+                Some(position) => ins_pos_in_n = position,
+                None if current_pos_in_m < pivot_pos_in_m => {
+                    order_n.insert(ins_pos_in_n, t.clone());
+                    pivot_pos_in_m = current_pos_in_m
+                }
+                None => {
+                    ins_pos_in_n += 1;
+                    order_n.insert(ins_pos_in_n, t.clone());
+                }
+            }
+        }
+    }
+
+    fn pseudo_random_array(seed: &mut u64, length: usize, range: u64) -> Vec<Value> {
+        (0..length)
+            .map(|_| {
+                *seed = seed
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                Value::from((*seed >> 33) % range)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_large_arrays() {
+        let mut seed = 42;
+        for (length, range) in &[(50, 10), (300, 1000), (2000, 100000)] {
+            let m = pseudo_random_array(&mut seed, *length, *range);
+            let mut n = pseudo_random_array(&mut seed, *length, *range);
+            let mut expected = n.clone();
+            merge_arrays_by_position(&m, &mut expected);
+            merge_arrays(&m, &mut n);
+            assert!(n == expected);
+        }
+        let old = pseudo_random_array(&mut seed, 10000, 1000);
+        let mut new = old.clone();
+        new.drain(100..300);
+        new.splice(5000..5000, pseudo_random_array(&mut seed, 500, 1000));
+        new.truncate(9000);
+        let patch = make_chunked_diff_patch(&old, &new, 256).unwrap();
+        let mut patched = old.clone();
+        apply_diff_patch(&mut patched, &patch).unwrap();
+        assert!(patched == new);
+        // Operations which are not sorted are applied one by one
+        let mut a = string_value_vec!["A", "B", "C"];
+        let patch = vec![json!(["i", 3, ["D"]]), json!(["d", 1, 0])];
+        apply_diff_patch(&mut a, &patch).unwrap();
+        assert!(a == string_value_vec!["B", "C", "D"]);
+        assert!(apply_diff_patch(&mut a, &[json!(["d", 5, 1])]).is_err());
+    }
+
+    #[test]
+    fn test_merge_objects() {
+        let base = json!({ "title": "a", "settings": { "theme": { "colors": "dark", "font": "mono" } }, "tags": [1] });
+        let ours = json!({ "title": "b", "settings": { "theme": { "colors": "light", "font": "mono" } }, "tags": [1] });
+        let theirs = json!({ "title": "a", "settings": { "theme": { "colors": "dark", "font": "sans" } }, "tags": [2] });
+        let mut diverging = BTreeSet::new();
+        let merged = merge_objects(
+            base.as_object().unwrap(),
+            ours.as_object().unwrap(),
+            theirs.as_object().unwrap(),
+            &mut vec![],
+            &mut diverging,
+        );
+        assert!(diverging.is_empty());
+        assert!(
+            Value::from(merged)
+                == json!({ "title": "b", "settings": { "theme": { "colors": "light", "font": "sans" } }, "tags": [2] })
+        );
+        let theirs =
+            json!({ "settings": { "theme": { "colors": "blue", "font": "mono" } }, "tags": [1] });
+        let merged = merge_objects(
+            base.as_object().unwrap(),
+            ours.as_object().unwrap(),
+            theirs.as_object().unwrap(),
+            &mut vec![],
+            &mut diverging,
+        );
+        assert!(
+            diverging.into_iter().collect::<Vec<_>>() == vec!["/settings/theme/colors", "/title"]
+        );
+        assert!(Value::from(merged) == ours);
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            format_timestamp(951_782_400_000),
+            "2000-02-29T00:00:00.000Z"
+        );
+        assert_eq!(
+            format_timestamp(1_700_000_000_123),
+            "2023-11-14T22:13:20.123Z"
+        );
+    }
 }
@@ -0,0 +1,144 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+#[cfg(target_arch = "wasm32")]
+use crate::indexeddbadapter::IndexedDbAdapter;
+use crate::melda::Melda;
+use gloo_utils::format::JsValueSerdeExt;
+use serde_json::{Map, Value};
+use wasm_bindgen::prelude::*;
+
+/// JavaScript interface to a Melda data structure, exported as `Melda` by the `wasm` feature.
+/// Replicas are kept in memory (or loaded from an IndexedDB database with `openIndexedDb`, in
+/// which case `persist` writes the changes back to the database): the committed items can be
+/// transferred to other replicas as a bundle (an `Uint8Array`) using `exportBundle` and
+/// `importBundle`.
+///
+/// ```js
+/// const alice = await Melda.openIndexedDb("alice");
+/// alice.update({ key: "value" });
+/// alice.commit({ author: "Alice" });
+/// await alice.persist();
+/// const bob = new Melda();
+/// bob.importBundle(alice.exportBundle());
+/// console.log(bob.read().key);
+/// ```
+#[wasm_bindgen(js_name = Melda)]
+pub struct WasmMelda {
+    inner: Melda,
+    #[cfg(target_arch = "wasm32")]
+    store: Option<IndexedDbAdapter>,
+}
+
+#[wasm_bindgen(js_class = Melda)]
+impl WasmMelda {
+    /// Creates an empty in-memory replica
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Result<WasmMelda, JsValue> {
+        let inner = Melda::new_from_url("memory://").map_err(to_js_error)?;
+        Ok(WasmMelda {
+            inner,
+            #[cfg(target_arch = "wasm32")]
+            store: None,
+        })
+    }
+
+    /// Opens a replica stored in an IndexedDB database (created if it does not exist), see
+    /// [IndexedDbAdapter]
+    #[cfg(target_arch = "wasm32")]
+    #[wasm_bindgen(js_name = openIndexedDb)]
+    pub async fn open_indexed_db(database: String) -> Result<WasmMelda, JsValue> {
+        let store = IndexedDbAdapter::open(&database)
+            .await
+            .map_err(to_js_error)?;
+        let adapter: Box<dyn crate::adapter::Adapter> = Box::new(store.clone());
+        let inner = Melda::new(std::sync::Arc::new(std::sync::RwLock::new(adapter)))
+            .map_err(to_js_error)?;
+        Ok(WasmMelda {
+            inner,
+            store: Some(store),
+        })
+    }
+
+    /// Writes the changes to the IndexedDB database of the replica (if any), resolving to the
+    /// number of written objects, see [IndexedDbAdapter::flush]
+    #[cfg(target_arch = "wasm32")]
+    pub fn persist(&self) -> js_sys::Promise {
+        let store = self.store.clone();
+        wasm_bindgen_futures::future_to_promise(async move {
+            let written = match store {
+                Some(store) => store.flush().await.map_err(to_js_error)?,
+                None => 0,
+            };
+            Ok(JsValue::from(written as u32))
+        })
+    }
+
+    /// Updates the state with the given object, see [Melda::update]
+    pub fn update(&self, obj: JsValue) -> Result<String, JsValue> {
+        self.inner.update(to_object(obj)?).map_err(to_js_error)
+    }
+
+    /// Commits the staged changes with optional information, returning the anchors (or null if
+    /// there was nothing to commit), see [Melda::commit]
+    pub fn commit(&self, information: JsValue) -> Result<JsValue, JsValue> {
+        let information = if information.is_null() || information.is_undefined() {
+            None
+        } else {
+            Some(to_object(information)?)
+        };
+        let anchors = self.inner.commit(information).map_err(to_js_error)?;
+        JsValue::from_serde(&anchors).map_err(to_js_error)
+    }
+
+    /// Reads the state starting from the given root object (or the root of the document),
+    /// see [Melda::read]
+    pub fn read(&self, root: Option<String>) -> Result<JsValue, JsValue> {
+        let content = self.inner.read(root.as_deref()).map_err(to_js_error)?;
+        JsValue::from_serde(&content).map_err(to_js_error)
+    }
+
+    /// Melds another replica into this one and refreshes the state, returning the melded items
+    pub fn meld(&mut self, other: &WasmMelda) -> Result<Vec<JsValue>, JsValue> {
+        let melded = self.inner.meld(&other.inner).map_err(to_js_error)?;
+        self.inner.refresh().map_err(to_js_error)?;
+        Ok(melded.into_iter().map(JsValue::from).collect())
+    }
+
+    /// Exports the committed items as a bundle, see [Melda::export_bundle]
+    #[wasm_bindgen(js_name = exportBundle)]
+    pub fn export_bundle(&self) -> Result<Vec<u8>, JsValue> {
+        self.inner.export_bundle().map_err(to_js_error)
+    }
+
+    /// Imports a bundle and refreshes the state, returning the imported items
+    #[wasm_bindgen(js_name = importBundle)]
+    pub fn import_bundle(&mut self, bundle: &[u8]) -> Result<Vec<JsValue>, JsValue> {
+        let imported = self.inner.import_bundle(bundle).map_err(to_js_error)?;
+        self.inner.refresh().map_err(to_js_error)?;
+        Ok(imported.into_iter().map(JsValue::from).collect())
+    }
+}
+
+fn to_object(value: JsValue) -> Result<Map<String, Value>, JsValue> {
+    match value.into_serde::<Value>().map_err(to_js_error)? {
+        Value::Object(obj) => Ok(obj),
+        _ => Err(JsValue::from_str("not_an_object")),
+    }
+}
+
+fn to_js_error<E: std::fmt::Display>(error: E) -> JsValue {
+    JsValue::from_str(&error.to_string())
+}
@@ -0,0 +1,330 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//! Sync channel of the repositories hosted by a [RepositoryServer], served over WebSocket.
+//!
+//! Clients connect to `ws://<address>/<repository>/sync`, presenting a token issued for the
+//! repository (`Authorization: Bearer <token>`, or the `token` query parameter since browsers
+//! cannot set the headers of a WebSocket). Each message is a JSON object:
+//!
+//! * the server sends `{"objects":[<key>]}` whenever an object is uploaded to the repository, so
+//!   that clients meld as soon as other peers push their changes, and `{"presence":{...}}` (the
+//!   presence of the peers) upon connection and whenever it changes
+//! * clients send `{"presence":<state>}` to publish the presence of the peer bound to their
+//!   token (or `{"presence":null}` to withdraw it), which requires write access: the presence
+//!   lasts as long as the connection
+//!
+//! Invalid messages are answered with `{"error":"<message>"}`.
+use crate::capability::Capability;
+use crate::server::{RepositoryServer, Response as Rejection};
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use std::io::ErrorKind;
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+use tungstenite::handshake::server::{Callback, ErrorResponse, Request, Response};
+use tungstenite::Message;
+
+// Maximum time to complete the handshake of a connection
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Interval at which connections forward the notifications of the repository
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Serves the sync channel of the repositories of a server
+pub struct SyncChannel {
+    server: Arc<RepositoryServer>,
+}
+
+impl SyncChannel {
+    /// Creates a new sync channel
+    ///
+    /// # Arguments
+    ///
+    /// * `server` - The server hosting the repositories
+    pub fn new(server: Arc<RepositoryServer>) -> Self {
+        SyncChannel { server }
+    }
+
+    /// Accepts connections on the given address, each served by its own thread. This function
+    /// does not return unless the address cannot be bound
+    pub fn serve(self, address: &str) -> Result<()> {
+        let listener = TcpListener::bind(address)?;
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::warn!("cannot_accept_connection: {}", e);
+                    continue;
+                }
+            };
+            let server = self.server.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = connect(&server, stream) {
+                    log::debug!("sync_connection_closed: {}", e);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+// Serves a connection until it is closed (or its token expires)
+fn connect(server: &RepositoryServer, stream: TcpStream) -> Result<()> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut authorized = None;
+    let handshake = Handshake {
+        server,
+        authorized: &mut authorized,
+    };
+    let mut socket = tungstenite::accept_hdr(stream, handshake)
+        .map_err(|e| anyhow!("handshake_failed: {}", e))?;
+    let (repository, capability) = authorized.ok_or_else(|| anyhow!("unauthorized"))?;
+    socket.get_mut().set_read_timeout(Some(POLL_INTERVAL))?;
+    let notifications = server.subscribe(&repository);
+    let presence = json!({ "presence": server.presence_of(&repository) });
+    let mut published = false;
+    let result = socket
+        .send(Message::text(presence.to_string()))
+        .map_err(|e| anyhow!(e))
+        .and_then(|_| loop {
+            if capability.is_expired() {
+                let _ = socket.close(None);
+                break Err(anyhow!("expired_token"));
+            }
+            for notification in notifications.try_iter() {
+                socket.send(Message::text(notification.to_string()))?;
+            }
+            match socket.read() {
+                Ok(Message::Text(text)) => match receive(server, &repository, &capability, &text) {
+                    Ok(publishing) => published = publishing,
+                    Err(e) => {
+                        let error = json!({ "error": e.to_string() });
+                        socket.send(Message::text(error.to_string()))?;
+                    }
+                },
+                Ok(Message::Close(_)) => break Ok(()),
+                // Pings are answered by the socket, binary messages are not used
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(e))
+                    if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => {
+                    break Ok(())
+                }
+                Err(e) => break Err(anyhow!(e)),
+            }
+        });
+    if let (true, Some(peer)) = (published, &capability.peer) {
+        server.publish(&repository, peer, None);
+    }
+    result
+}
+
+// Authorizes the handshake of a connection, recording the repository and the granted capability
+struct Handshake<'a> {
+    server: &'a RepositoryServer,
+    authorized: &'a mut Option<(String, Capability)>,
+}
+
+impl Callback for Handshake<'_> {
+    fn on_request(
+        self,
+        request: &Request,
+        response: Response,
+    ) -> std::result::Result<Response, ErrorResponse> {
+        match authorize(self.server, request) {
+            Ok(authorized) => {
+                *self.authorized = Some(authorized);
+                Ok(response)
+            }
+            Err(rejection) => {
+                let message = String::from_utf8_lossy(&rejection.body).into_owned();
+                let mut response = ErrorResponse::new(Some(message));
+                *response.status_mut() = tungstenite::http::StatusCode::from_u16(rejection.status)
+                    .unwrap_or(tungstenite::http::StatusCode::BAD_REQUEST);
+                Err(response)
+            }
+        }
+    }
+}
+
+// Verifies the token presented in the handshake request, returning the repository and the
+// granted capability
+fn authorize(
+    server: &RepositoryServer,
+    request: &Request,
+) -> std::result::Result<(String, Capability), Rejection> {
+    let rejection = |status: u16, message: &str| Rejection {
+        status,
+        body: message.as_bytes().to_vec(),
+    };
+    let url = url::Url::parse("http://localhost")
+        .and_then(|base| base.join(&request.uri().to_string()))
+        .map_err(|_| rejection(400, "invalid_url"))?;
+    let segments: Vec<&str> = url.path_segments().map(|s| s.collect()).unwrap_or_default();
+    let repository = match segments.as_slice() {
+        [repository, "sync"] => repository.to_string(),
+        _ => return Err(rejection(404, "invalid_endpoint")),
+    };
+    let token = request
+        .headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(|t| t.trim().to_string())
+        .or_else(|| {
+            url.query_pairs()
+                .find(|(k, _)| k == "token")
+                .map(|(_, v)| v.to_string())
+        });
+    let capability = server.authorize(&repository, token.as_deref())?;
+    Ok((repository, capability))
+}
+
+// Handles a message sent by a client, returning true if it published its presence
+fn receive(
+    server: &RepositoryServer,
+    repository: &str,
+    capability: &Capability,
+    text: &str,
+) -> Result<bool> {
+    let message: Value = serde_json::from_str(text).map_err(|_| anyhow!("invalid_message"))?;
+    let state = message
+        .get("presence")
+        .ok_or_else(|| anyhow!("invalid_message"))?;
+    capability
+        .check_write()
+        .map_err(|_| anyhow!("access_denied"))?;
+    let peer = capability
+        .peer
+        .as_ref()
+        .ok_or_else(|| anyhow!("missing_peer_identity"))?;
+    let state = (!state.is_null()).then(|| (state.clone(), None));
+    let publishing = state.is_some();
+    server.publish(repository, peer, state);
+    Ok(publishing)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::capability::Capability;
+    use crate::melda::Melda;
+    use crate::server::RepositoryServer;
+    use crate::websocket::SyncChannel;
+    use serde_json::{json, Value};
+    use std::net::TcpStream;
+    use std::sync::Arc;
+    use tungstenite::stream::MaybeTlsStream;
+    use tungstenite::{Message, WebSocket};
+
+    type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+    fn receive(socket: &mut Socket) -> Value {
+        loop {
+            if let Message::Text(text) = socket.read().unwrap() {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    fn send(socket: &mut Socket, message: Value) {
+        socket.send(Message::text(message.to_string())).unwrap();
+    }
+
+    #[test]
+    fn test_sync_channel() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let server = Arc::new(RepositoryServer::new(&dir, b"secret").unwrap());
+        let alice = server
+            .issue("alice", &Capability::read_write().with_peer("alice"))
+            .unwrap();
+        let bob = server
+            .issue("alice", &Capability::read_only().with_peer("bob"))
+            .unwrap();
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        std::thread::spawn({
+            let channel = SyncChannel::new(server.clone());
+            let address = address.clone();
+            move || channel.serve(&address)
+        });
+        for _ in 0..50 {
+            if TcpStream::connect(&address).is_ok() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        let connect = |repository: &str, token: &str| {
+            let url = format!("ws://{}/{}/sync?token={}", address, repository, token);
+            tungstenite::connect(url).ok().map(|(socket, _)| socket)
+        };
+        assert!(connect("alice", "garbage").is_none());
+        assert!(connect("bob", &alice).is_none());
+        let mut observer = connect("alice", &bob).unwrap();
+        assert_eq!(receive(&mut observer), json!({ "presence" : {} }));
+        let mut peer = connect("alice", &alice).unwrap();
+        assert_eq!(receive(&mut peer), json!({ "presence" : {} }));
+        // The identity of the peer is the one bound to its token
+        send(
+            &mut peer,
+            json!({ "presence" : { "selection" : "/items♭/0" } }),
+        );
+        assert_eq!(
+            receive(&mut observer),
+            json!({ "presence" : { "alice" : { "selection" : "/items♭/0" } } })
+        );
+        // Publishing presence requires write access
+        send(&mut observer, json!({ "presence" : "online" }));
+        assert_eq!(receive(&mut observer), json!({ "error" : "access_denied" }));
+        send(&mut observer, json!({ "other" : 1 }));
+        assert_eq!(
+            receive(&mut observer),
+            json!({ "error" : "invalid_message" })
+        );
+        // Uploaded objects are notified
+        let replica = Melda::new_from_url("memory://").unwrap();
+        replica
+            .update(json!({ "key" : "value" }).as_object().unwrap().clone())
+            .unwrap();
+        let block = replica
+            .commit(None)
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .next()
+            .unwrap()
+            + ".delta";
+        let content = replica
+            .get_adapter()
+            .read()
+            .unwrap()
+            .read_object(&block, 0, 0)
+            .unwrap();
+        let url = format!("/alice/objects/{}", block);
+        assert_eq!(
+            server.handle("PUT", &url, Some(&alice), &content).status,
+            201
+        );
+        assert_eq!(receive(&mut observer), json!({ "objects" : [block] }));
+        // Presence is withdrawn once the connection is closed
+        peer.close(None).unwrap();
+        while peer.read().is_ok() {}
+        assert_eq!(receive(&mut observer), json!({ "presence" : {} }));
+    }
+}
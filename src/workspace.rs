@@ -0,0 +1,286 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use crate::constants::{DOCUMENT_PREFIX, ID_FIELD};
+use crate::error::MeldaError;
+use crate::filetree::{dataset_entries, read_dataset, DatasetEntry};
+use crate::melda::Melda;
+use crate::revision::Revision;
+use anyhow::{bail, Result};
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// A workspace holds several named documents in a single replica: the documents share the
+/// adapter and the history, hence changes made to several documents are committed as a single
+/// block and a single meld exchanges the changes of all documents. Each document is stored with
+/// its own root object (identified by the name of the document prefixed with "\u{00A7}").
+/// Documents must not be changed with [Melda::update], which deletes every object that is not
+/// reachable from the default root object.
+///
+/// # Example
+/// ```
+/// use melda::workspace::Workspace;
+/// use serde_json::json;
+/// let workspace = Workspace::open("memory://").expect("cannot_initialize_workspace");
+/// let settings = workspace.open_doc("settings").unwrap();
+/// let tasks = workspace.open_doc("tasks").unwrap();
+/// settings.update(json!({ "theme" : "dark" }).as_object().unwrap().clone()).unwrap();
+/// tasks.update(json!({ "items\u{266D}" : [ { "_id" : "1", "title" : "Buy milk" } ] }).as_object().unwrap().clone()).unwrap();
+/// let anchors = workspace.commit(None).unwrap().unwrap();
+/// assert_eq!(anchors.len(), 1);
+/// assert_eq!(workspace.documents(), ["settings".to_string(), "tasks".to_string()].into());
+/// let mut other = Workspace::open("memory://").expect("cannot_initialize_workspace");
+/// other.meld(&workspace).unwrap();
+/// other.refresh().unwrap();
+/// assert_eq!(other.open_doc("settings").unwrap().read().unwrap(), json!({ "theme" : "dark" }).as_object().unwrap().clone());
+/// assert_eq!(other.open_doc("tasks").unwrap().read().unwrap().get("items\u{266D}").unwrap()[0]["title"], "Buy milk");
+/// ```
+pub struct Workspace {
+    replica: Melda,
+}
+
+impl Workspace {
+    /// Creates a workspace storing its documents in the given replica
+    ///
+    /// # Arguments
+    ///
+    /// * `replica` - The replica holding the documents
+    pub fn new(replica: Melda) -> Self {
+        Workspace { replica }
+    }
+
+    /// Opens the workspace stored by the adapter identified by the given URL, see
+    /// [crate::adapter::get_adapter]
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL of the adapter
+    pub fn open(url: &str) -> Result<Self> {
+        Ok(Workspace::new(Melda::new_from_url(url)?))
+    }
+
+    /// Returns a handle to the document with the given name (the document is created by its
+    /// first update)
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the document
+    pub fn open_doc(&self, name: &str) -> Result<Document<'_>> {
+        if name.is_empty() {
+            bail!("empty_document_name");
+        }
+        Ok(Document {
+            replica: &self.replica,
+            name: name.to_string(),
+            root: DOCUMENT_PREFIX.to_string() + name,
+        })
+    }
+
+    /// Returns the names of the documents of the workspace (documents which have been deleted
+    /// are not included)
+    pub fn documents(&self) -> BTreeSet<String> {
+        self.replica
+            .get_all_objects()
+            .into_iter()
+            .filter_map(|uuid| {
+                let name = uuid.strip_prefix(DOCUMENT_PREFIX)?.to_string();
+                Some(name).filter(|_| is_live(&self.replica, &uuid))
+            })
+            .collect()
+    }
+
+    /// Commits the changes staged in all documents as a single block, see [Melda::commit]
+    ///
+    /// # Arguments
+    ///
+    /// * `information` - Optional information attached to the block
+    pub fn commit(
+        &self,
+        information: Option<Map<String, Value>>,
+    ) -> Result<Option<BTreeSet<String>>> {
+        self.replica.commit(information)
+    }
+
+    /// Imports a directory of JSON files as documents in a single commit: each JSON file becomes
+    /// a document named after the file (without the *.json* extension) and each sub-directory a
+    /// document holding the files it contains, as described by [Melda::import_tree]. The
+    /// imported documents replace the existing ones, and the stage must be empty
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory
+    /// * `information` - Optional information attached to the block
+    ///
+    /// # Example
+    /// ```
+    /// use melda::workspace::Workspace;
+    /// use serde_json::json;
+    /// let dir = std::env::temp_dir().join("melda_workspace_import_tree_doctest");
+    /// let _ = std::fs::remove_dir_all(&dir);
+    /// std::fs::create_dir_all(dir.join("tasks/items")).unwrap();
+    /// std::fs::write(dir.join("settings.json"), r#"{ "theme" : "dark" }"#).unwrap();
+    /// std::fs::write(dir.join("tasks/items/1.json"), r#"{ "title" : "Buy milk" }"#).unwrap();
+    /// let workspace = Workspace::open("memory://").unwrap();
+    /// assert!(workspace.import_tree(&dir, None).unwrap().is_some());
+    /// assert_eq!(workspace.documents(), ["settings".to_string(), "tasks".to_string()].into());
+    /// assert_eq!(workspace.open_doc("tasks").unwrap().read().unwrap()["items\u{266D}"], json!([ { "_id" : "1", "title" : "Buy milk" } ]));
+    /// std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn import_tree(
+        &self,
+        dir: &Path,
+        information: Option<Map<String, Value>>,
+    ) -> Result<Option<BTreeSet<String>>> {
+        if self.replica.has_staging() {
+            bail!(MeldaError::StageNotEmpty);
+        }
+        for entry in dataset_entries(dir)? {
+            let (name, content) = match entry {
+                DatasetEntry::File(name, Value::Object(content)) => (name, content),
+                DatasetEntry::File(name, _) => bail!("document_not_an_object: {}", name),
+                DatasetEntry::Directory(name, path) => (name, read_dataset(&path)?),
+            };
+            self.open_doc(&name)?.update(content)?;
+        }
+        self.commit(information)
+    }
+
+    /// Melds the changes of all documents of another workspace, see [Melda::meld]
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - Another workspace
+    pub fn meld(&self, other: &Workspace) -> Result<Vec<String>> {
+        self.replica.meld(&other.replica)
+    }
+
+    /// Loads the changes melded from other workspaces, see [Melda::refresh]
+    pub fn refresh(&mut self) -> Result<()> {
+        self.replica.refresh()
+    }
+
+    /// Returns the replica holding the documents
+    pub fn replica(&self) -> &Melda {
+        &self.replica
+    }
+}
+
+/// A named document of a [Workspace]
+pub struct Document<'a> {
+    replica: &'a Melda,
+    name: String,
+    root: String,
+}
+
+impl Document<'_> {
+    /// Returns the name of the document
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the identifier of the root object of the document (which can be used with
+    /// [Melda::read] and [Melda::update_document])
+    pub fn root(&self) -> &str {
+        &self.root
+    }
+
+    /// Returns true if the document has been created (and not deleted)
+    pub fn exists(&self) -> bool {
+        is_live(self.replica, &self.root)
+    }
+
+    /// Reads the document (an empty object if the document does not exist)
+    pub fn read(&self) -> Result<Map<String, Value>> {
+        if !self.exists() {
+            return Ok(Map::new());
+        }
+        let mut content = self.replica.read(Some(&self.root))?;
+        content.remove(ID_FIELD);
+        Ok(content)
+    }
+
+    /// Stages the new state of the document, see [Melda::update_document]
+    ///
+    /// # Arguments
+    ///
+    /// * `obj` - The new state of the document
+    pub fn update(&self, mut obj: Map<String, Value>) -> Result<()> {
+        if obj.contains_key(ID_FIELD) {
+            bail!("cannot_change_object_identifier");
+        }
+        obj.insert(ID_FIELD.to_string(), Value::from(self.root.clone()));
+        self.replica.update_document(&self.root, obj)
+    }
+
+    /// Stages the deletion of the document (and of all the objects it contains)
+    pub fn delete(&self) -> Result<()> {
+        if !self.exists() {
+            bail!("unknown_document");
+        }
+        self.replica.update_document(&self.root, Map::new())?;
+        self.replica.delete_object(&self.root)?;
+        Ok(())
+    }
+}
+
+// Returns true if the object exists and its winning revision is not a deletion
+fn is_live(replica: &Melda, uuid: &str) -> bool {
+    replica
+        .get_winner(uuid)
+        .ok()
+        .and_then(|w| Revision::from(&w).ok())
+        .is_some_and(|w| !w.is_deleted())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::workspace::Workspace;
+    use serde_json::json;
+
+    #[test]
+    fn test_workspace_documents() {
+        let workspace = Workspace::open("memory://").unwrap();
+        assert!(workspace.open_doc("").is_err());
+        let notes = workspace.open_doc("notes").unwrap();
+        assert!(!notes.exists());
+        assert!(notes.read().unwrap().is_empty());
+        let content = json!({ "items\u{266D}" : [ { "_id" : "1" }, { "_id" : "2" } ] });
+        notes.update(content.as_object().unwrap().clone()).unwrap();
+        assert!(notes.exists());
+        assert_eq!(
+            workspace.replica().read(Some(notes.root())).unwrap()["_id"],
+            "\u{00A7}notes"
+        );
+        workspace
+            .replica()
+            .update_document(
+                "\u{221A}",
+                json!({ "title" : "Main" }).as_object().unwrap().clone(),
+            )
+            .unwrap();
+        workspace.commit(None).unwrap();
+        assert_eq!(workspace.documents(), ["notes".to_string()].into());
+        assert_eq!(json!(notes.read().unwrap()), content);
+        notes.delete().unwrap();
+        workspace.commit(None).unwrap();
+        assert!(!notes.exists());
+        assert!(workspace.documents().is_empty());
+        let element = workspace.replica().get_value("1", None).unwrap();
+        assert!(element.contains_key("_deleted"));
+        assert!(notes.delete().is_err());
+        assert_eq!(workspace.replica().read(None).unwrap()["title"], "Main");
+    }
+}
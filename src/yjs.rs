@@ -0,0 +1,206 @@
+// Melda - Delta State JSON CRDT
+// Copyright (C) 2021-2025 Amos Brocco <amos.brocco@supsi.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//! Encoding of [Yjs](https://yjs.dev) updates (version 1 of the update format), which editor
+//! frontends apply to a Y.Doc with `Y.applyUpdate`
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+// Content references of the items
+const CONTENT_STRING: u8 = 4;
+const CONTENT_ANY: u8 = 8;
+
+// Type markers of the values encoded by writeAny
+const ANY_NULL: u8 = 126;
+const ANY_INTEGER: u8 = 125;
+const ANY_FLOAT32: u8 = 124;
+const ANY_FLOAT64: u8 = 123;
+const ANY_TRUE: u8 = 120;
+const ANY_FALSE: u8 = 121;
+const ANY_STRING: u8 = 119;
+const ANY_OBJECT: u8 = 118;
+const ANY_ARRAY: u8 = 117;
+
+// Largest integer encoded as a variable length integer
+const MAX_INTEGER: i64 = 0x7FFFFFFF;
+
+/// Encodes an update creating the given shared types (strings become Y.Text contents and
+/// arrays Y.Array contents) with the items of a single client
+pub(crate) fn encode_update(client: u64, types: &[(String, Value)]) -> Result<Vec<u8>> {
+    let mut items = vec![];
+    let mut count = 0;
+    for (name, value) in types {
+        let mut item = vec![];
+        match value {
+            Value::String(s) if !s.is_empty() => {
+                item.push(CONTENT_STRING);
+                write_parent(&mut item, name);
+                write_string(&mut item, s);
+            }
+            Value::Array(a) if !a.is_empty() => {
+                item.push(CONTENT_ANY);
+                write_parent(&mut item, name);
+                write_uint(&mut item, a.len() as u64);
+                for v in a {
+                    write_any(&mut item, v);
+                }
+            }
+            Value::String(_) | Value::Array(_) => continue,
+            _ => bail!("unsupported_yjs_type: {}", name),
+        }
+        items.extend(item);
+        count += 1;
+    }
+    let mut update = vec![];
+    if count == 0 {
+        write_uint(&mut update, 0);
+    } else {
+        write_uint(&mut update, 1);
+        write_uint(&mut update, count);
+        write_uint(&mut update, client);
+        // Clock of the first item
+        write_uint(&mut update, 0);
+        update.extend(items);
+    }
+    // Empty delete set
+    write_uint(&mut update, 0);
+    Ok(update)
+}
+
+// Writes the parent of an item without origins: a root type identified by its name
+fn write_parent(buffer: &mut Vec<u8>, name: &str) {
+    write_uint(buffer, 1);
+    write_string(buffer, name);
+}
+
+fn write_uint(buffer: &mut Vec<u8>, mut n: u64) {
+    while n > 0x7F {
+        buffer.push(0x80 | (n & 0x7F) as u8);
+        n >>= 7;
+    }
+    buffer.push(n as u8);
+}
+
+// Writes a signed integer: the first byte holds the sign and six bits of the magnitude
+fn write_int(buffer: &mut Vec<u8>, n: i64) {
+    let mut magnitude = n.unsigned_abs();
+    let sign = if n < 0 { 0x40 } else { 0 };
+    let more = if magnitude > 0x3F { 0x80 } else { 0 };
+    buffer.push(more | sign | (magnitude & 0x3F) as u8);
+    magnitude >>= 6;
+    while magnitude > 0 {
+        let more = if magnitude > 0x7F { 0x80 } else { 0 };
+        buffer.push(more | (magnitude & 0x7F) as u8);
+        magnitude >>= 7;
+    }
+}
+
+fn write_string(buffer: &mut Vec<u8>, s: &str) {
+    write_uint(buffer, s.len() as u64);
+    buffer.extend(s.as_bytes());
+}
+
+fn write_any(buffer: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => buffer.push(ANY_NULL),
+        Value::Bool(true) => buffer.push(ANY_TRUE),
+        Value::Bool(false) => buffer.push(ANY_FALSE),
+        Value::Number(n) => match n.as_i64().filter(|i| i.abs() <= MAX_INTEGER) {
+            Some(i) => {
+                buffer.push(ANY_INTEGER);
+                write_int(buffer, i);
+            }
+            None => {
+                let f = n.as_f64().unwrap_or_default();
+                if (f as f32) as f64 == f {
+                    buffer.push(ANY_FLOAT32);
+                    buffer.extend((f as f32).to_be_bytes());
+                } else {
+                    buffer.push(ANY_FLOAT64);
+                    buffer.extend(f.to_be_bytes());
+                }
+            }
+        },
+        Value::String(s) => {
+            buffer.push(ANY_STRING);
+            write_string(buffer, s);
+        }
+        Value::Array(a) => {
+            buffer.push(ANY_ARRAY);
+            write_uint(buffer, a.len() as u64);
+            for v in a {
+                write_any(buffer, v);
+            }
+        }
+        Value::Object(o) => {
+            buffer.push(ANY_OBJECT);
+            write_uint(buffer, o.len() as u64);
+            for (k, v) in o {
+                write_string(buffer, k);
+                write_any(buffer, v);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::yjs::{encode_update, write_int};
+    use serde_json::json;
+
+    #[test]
+    fn test_encode_text() {
+        let update = encode_update(1, &[("t".to_string(), json!("hi"))]).unwrap();
+        assert_eq!(update, vec![1, 1, 1, 0, 4, 1, 1, b't', 2, b'h', b'i', 0]);
+        assert_eq!(
+            encode_update(1, &[("t".to_string(), json!(""))]).unwrap(),
+            vec![0, 0]
+        );
+        assert!(encode_update(1, &[("t".to_string(), json!(1))]).is_err());
+    }
+
+    #[test]
+    fn test_encode_array() {
+        let types = [
+            ("t".to_string(), json!("\u{e9}")),
+            (
+                "a".to_string(),
+                json!([1, -1, true, null, "x", 1.5, { "k" : [] }]),
+            ),
+        ];
+        let update = encode_update(300, &types).unwrap();
+        #[rustfmt::skip]
+        let expected = vec![
+            1, 2, 0xAC, 0x02, 0,
+            4, 1, 1, b't', 2, 0xC3, 0xA9,
+            8, 1, 1, b'a', 7,
+            125, 0x01, 125, 0x41, 120, 126, 119, 1, b'x', 124, 0x3F, 0xC0, 0, 0, 118, 1, 1, b'k', 117, 0,
+            0,
+        ];
+        assert_eq!(update, expected);
+    }
+
+    #[test]
+    fn test_write_int() {
+        let encode = |n| {
+            let mut buffer = vec![];
+            write_int(&mut buffer, n);
+            buffer
+        };
+        assert_eq!(encode(63), vec![0x3F]);
+        assert_eq!(encode(64), vec![0x80, 0x01]);
+        assert_eq!(encode(-64), vec![0xC0, 0x01]);
+    }
+}